@@ -0,0 +1,276 @@
+; ModuleID = './llvm_testfiles/golden/arithmetic.rho'
+source_filename = "./llvm_testfiles/golden/arithmetic.rho"
+
+@rho.str.0 = private unnamed_addr constant { i64, i64, [17 x i8] } { i64 -1, i64 1, [17 x i8] c"integer overflow\00" }
+@rho.str.1 = private unnamed_addr constant { i64, i64, [1 x i8] } { i64 -1, i64 1, [1 x i8] c"\00" }
+@rho.str.2 = private unnamed_addr constant { i64, i64, [39 x i8] } { i64 -1, i64 1, [39 x i8] c"./llvm_testfiles/golden/arithmetic.rho\00" }
+@rho.str.3 = private unnamed_addr constant { i64, i64, [17 x i8] } { i64 -1, i64 1, [17 x i8] c"division by zero\00" }
+@rho.str.4 = private unnamed_addr constant { i64, i64, [29 x i8] } { i64 -1, i64 1, [29 x i8] c"float out of range for `int`\00" }
+@rho.str.5 = private unnamed_addr constant { i64, i64, [32 x i8] } { i64 -1, i64 1, [32 x i8] c"NaN can't be converted to `int`\00" }
+@rho.str.6 = private unnamed_addr constant { i64, i64, [43 x i8] } { i64 -1, i64 1, [43 x i8] c"an int can't be raised to a negative power\00" }
+@rho.atom.0 = private unnamed_addr constant [3 x i8] c"ok\00"
+@rho.atom.1 = private unnamed_addr constant [6 x i8] c"error\00"
+@rho.atoms = constant [2 x i8*] [i8* getelementptr inbounds ([3 x i8], [3 x i8]* @rho.atom.0, i32 0, i32 0), i8* getelementptr inbounds ([6 x i8], [6 x i8]* @rho.atom.1, i32 0, i32 0)]
+@rho.atom_count = constant i32 2
+
+@rho.var.a = internal global i64 zeroinitializer
+@rho.var.b = internal global i64 zeroinitializer
+@rho.var.x = internal global double zeroinitializer
+@rho.var.y = internal global double zeroinitializer
+
+declare void @rho_atoms_init(i8**, i32)
+declare void @rho_fail(i8*, i8*, i8*, i64, i64) noreturn
+declare void @rho_finish()
+declare void @rho_print_bool(i1 zeroext)
+declare void @rho_print_float(double)
+declare void @rho_print_int(i64)
+declare void @rho_print_newline()
+declare { i64, i1 } @llvm.sadd.with.overflow.i64(i64, i64)
+declare { i64, i1 } @llvm.smul.with.overflow.i64(i64, i64)
+declare { i64, i1 } @llvm.ssub.with.overflow.i64(i64, i64)
+
+define i32 @main() {
+entry:
+  %_17 = alloca i1
+  %_25 = alloca i1
+  %_33 = alloca double
+  call void @rho_atoms_init(i8** getelementptr inbounds ([2 x i8*], [2 x i8*]* @rho.atoms, i64 0, i64 0), i32 2)
+  br label %bb0
+bb0:
+  store i64 7, i64* @rho.var.a
+  store i64 -3, i64* @rho.var.b
+  %t1 = load i64, i64* @rho.var.a
+  %t2 = load i64, i64* @rho.var.b
+  %t3 = call { i64, i1 } @llvm.smul.with.overflow.i64(i64 %t2, i64 2)
+  %t4 = extractvalue { i64, i1 } %t3, 1
+  br i1 %t4, label %fail5, label %ok6
+fail5:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.0, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [39 x i8] }, { i64, i64, [39 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 4, i64 15)
+  unreachable
+ok6:
+  %t7 = extractvalue { i64, i1 } %t3, 0
+  %t8 = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %t1, i64 %t7)
+  %t9 = extractvalue { i64, i1 } %t8, 1
+  br i1 %t9, label %fail10, label %ok11
+fail10:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.0, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [39 x i8] }, { i64, i64, [39 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 4, i64 11)
+  unreachable
+ok11:
+  %t12 = extractvalue { i64, i1 } %t8, 0
+  call void @rho_print_int(i64 %t12)
+  call void @rho_print_newline()
+  %t13 = load i64, i64* @rho.var.a
+  %t14 = load i64, i64* @rho.var.b
+  %t15 = icmp eq i64 %t14, 0
+  br i1 %t15, label %fail16, label %ok17
+fail16:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.3, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [39 x i8] }, { i64, i64, [39 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 5, i64 11)
+  unreachable
+ok17:
+  %t18 = icmp eq i64 %t13, -9223372036854775808
+  %t19 = icmp eq i64 %t14, -1
+  %t20 = and i1 %t18, %t19
+  br i1 %t20, label %fail21, label %ok22
+fail21:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.0, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [39 x i8] }, { i64, i64, [39 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 5, i64 11)
+  unreachable
+ok22:
+  %t23 = sdiv i64 %t13, %t14
+  call void @rho_print_int(i64 %t23)
+  call void @rho_print_newline()
+  %t24 = load i64, i64* @rho.var.a
+  %t25 = load i64, i64* @rho.var.b
+  %t26 = icmp eq i64 %t25, 0
+  br i1 %t26, label %fail27, label %ok28
+fail27:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.3, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [39 x i8] }, { i64, i64, [39 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 6, i64 11)
+  unreachable
+ok28:
+  %t29 = icmp eq i64 %t24, -9223372036854775808
+  %t30 = icmp eq i64 %t25, -1
+  %t31 = and i1 %t29, %t30
+  br i1 %t31, label %fail32, label %ok33
+fail32:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.0, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [39 x i8] }, { i64, i64, [39 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 6, i64 11)
+  unreachable
+ok33:
+  %t34 = srem i64 %t24, %t25
+  call void @rho_print_int(i64 %t34)
+  call void @rho_print_newline()
+  %t35 = call i64 @rho.ipow(i64 2, i64 10, i64 7, i64 11)
+  call void @rho_print_int(i64 %t35)
+  call void @rho_print_newline()
+  %t36 = icmp uge i64 4, 64
+  br i1 %t36, label %fail37, label %ok38
+fail37:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.0, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [39 x i8] }, { i64, i64, [39 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 8, i64 11)
+  unreachable
+ok38:
+  %t39 = shl i64 1, 4
+  %t40 = icmp uge i64 2, 64
+  br i1 %t40, label %fail41, label %ok42
+fail41:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.0, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [39 x i8] }, { i64, i64, [39 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 8, i64 16)
+  unreachable
+ok42:
+  %t43 = ashr i64 %t39, 2
+  call void @rho_print_int(i64 %t43)
+  call void @rho_print_newline()
+  store double 0x3FF8000000000000, double* @rho.var.x
+  %t44 = load double, double* @rho.var.x
+  %t45 = fmul double %t44, 0x4000000000000000
+  %t46 = fsub double %t45, 0x3FE0000000000000
+  store double %t46, double* @rho.var.y
+  %t47 = load double, double* @rho.var.y
+  %t48 = load double, double* @rho.var.x
+  %t49 = fcmp ogt double %t47, %t48
+  store i1 %t49, i1* %_17
+  br i1 %t49, label %bb2, label %bb3
+bb2:
+  %t50 = load i64, i64* @rho.var.a
+  %t51 = load i64, i64* @rho.var.b
+  %t52 = icmp eq i64 %t50, %t51
+  %t53 = xor i1 %t52, true
+  store i1 %t53, i1* %_17
+  br label %bb3
+bb3:
+  %t54 = load i1, i1* %_17
+  call void @rho_print_bool(i1 zeroext %t54)
+  call void @rho_print_newline()
+  %t55 = load i64, i64* @rho.var.a
+  %t56 = icmp sle i64 %t55, 7
+  store i1 %t56, i1* %_25
+  br i1 %t56, label %bb5, label %bb4
+bb4:
+  %t57 = icmp eq i64 0, 0
+  br i1 %t57, label %fail58, label %ok59
+fail58:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.3, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [39 x i8] }, { i64, i64, [39 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 12, i64 21)
+  unreachable
+ok59:
+  %t60 = icmp eq i64 1, -9223372036854775808
+  %t61 = icmp eq i64 0, -1
+  %t62 = and i1 %t60, %t61
+  br i1 %t62, label %fail63, label %ok64
+fail63:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.0, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [39 x i8] }, { i64, i64, [39 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 12, i64 21)
+  unreachable
+ok64:
+  %t65 = sdiv i64 1, 0
+  %t66 = icmp eq i64 %t65, 0
+  store i1 %t66, i1* %_25
+  br label %bb5
+bb5:
+  %t67 = load i1, i1* %_25
+  call void @rho_print_bool(i1 zeroext %t67)
+  call void @rho_print_newline()
+  %t68 = load i64, i64* @rho.var.a
+  %t69 = call { i64, i1 } @llvm.ssub.with.overflow.i64(i64 0, i64 %t68)
+  %t70 = extractvalue { i64, i1 } %t69, 1
+  br i1 %t70, label %fail71, label %ok72
+fail71:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.0, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [39 x i8] }, { i64, i64, [39 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 13, i64 9)
+  unreachable
+ok72:
+  %t73 = extractvalue { i64, i1 } %t69, 0
+  call void @rho_print_int(i64 %t73)
+  call void @rho_print_newline()
+  %t74 = load double, double* @rho.var.y
+  %t75 = fmul double %t74, 0xBFFE666666666666
+  store double %t75, double* %_33
+  %t76 = load double, double* %_33
+  %t77 = load double, double* %_33
+  %t78 = fcmp une double %t76, %t77
+  br i1 %t78, label %bb6, label %bb7
+bb7:
+  %t79 = load double, double* %_33
+  %t80 = fcmp olt double %t79, 0xC3E0000000000000
+  br i1 %t80, label %bb8, label %bb9
+bb9:
+  %t81 = load double, double* %_33
+  %t82 = fcmp oge double %t81, 0x43E0000000000000
+  br i1 %t82, label %bb10, label %bb11
+bb11:
+  %t83 = load double, double* %_33
+  %t84 = fptosi double %t83 to i64
+  %t85 = zext i32 97 to i64
+  %t86 = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %t84, i64 %t85)
+  %t87 = extractvalue { i64, i1 } %t86, 1
+  br i1 %t87, label %fail88, label %ok89
+fail88:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.0, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [39 x i8] }, { i64, i64, [39 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 14, i64 23)
+  unreachable
+ok89:
+  %t90 = extractvalue { i64, i1 } %t86, 0
+  %t91 = zext i1 true to i64
+  %t92 = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %t90, i64 %t91)
+  %t93 = extractvalue { i64, i1 } %t92, 1
+  br i1 %t93, label %fail94, label %ok95
+fail94:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.0, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [39 x i8] }, { i64, i64, [39 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 14, i64 34)
+  unreachable
+ok95:
+  %t96 = extractvalue { i64, i1 } %t92, 0
+  call void @rho_print_int(i64 %t96)
+  call void @rho_print_newline()
+  %t97 = load i64, i64* @rho.var.b
+  %t98 = sitofp i64 %t97 to double
+  %t99 = fdiv double %t98, 0x4000000000000000
+  call void @rho_print_float(double %t99)
+  call void @rho_print_newline()
+  br label %bb1
+bb1:
+  %t100 = trunc i64 0 to i32
+  call void @rho_finish()
+  ret i32 %t100
+bb10:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [29 x i8] }, { i64, i64, [29 x i8] }* @rho.str.4, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [39 x i8] }, { i64, i64, [39 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 14, i64 9)
+  unreachable
+bb8:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [29 x i8] }, { i64, i64, [29 x i8] }* @rho.str.4, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [39 x i8] }, { i64, i64, [39 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 14, i64 9)
+  unreachable
+bb6:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [32 x i8] }, { i64, i64, [32 x i8] }* @rho.str.5, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [39 x i8] }, { i64, i64, [39 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 14, i64 9)
+  unreachable
+}
+
+; `base ^ exp` by squaring, failing like `i64::checked_pow` does
+define internal i64 @rho.ipow(i64 %base, i64 %exp, i64 %line, i64 %col) {
+entry:
+  %negative = icmp slt i64 %exp, 0
+  br i1 %negative, label %fail.negative, label %loop
+loop:
+  %acc = phi i64 [ 1, %entry ], [ %acc.next, %square ]
+  %b = phi i64 [ %base, %entry ], [ %b.next, %square ]
+  %e = phi i64 [ %exp, %entry ], [ %e.next, %square ]
+  %finished = icmp eq i64 %e, 0
+  br i1 %finished, label %exit, label %multiply
+multiply:
+  %bit = and i64 %e, 1
+  %odd = icmp ne i64 %bit, 0
+  %product = call { i64, i1 } @llvm.smul.with.overflow.i64(i64 %acc, i64 %b)
+  %product.value = extractvalue { i64, i1 } %product, 0
+  %product.overflow = extractvalue { i64, i1 } %product, 1
+  %acc.next = select i1 %odd, i64 %product.value, i64 %acc
+  %product.failed = and i1 %odd, %product.overflow
+  br i1 %product.failed, label %fail.overflow, label %shift
+shift:
+  %e.next = lshr i64 %e, 1
+  %last = icmp eq i64 %e.next, 0
+  br i1 %last, label %done, label %square
+square:
+  %squared = call { i64, i1 } @llvm.smul.with.overflow.i64(i64 %b, i64 %b)
+  %b.next = extractvalue { i64, i1 } %squared, 0
+  %squared.overflow = extractvalue { i64, i1 } %squared, 1
+  br i1 %squared.overflow, label %fail.overflow, label %loop
+done:
+  ret i64 %acc.next
+exit:
+  ret i64 %acc
+fail.negative:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [43 x i8] }, { i64, i64, [43 x i8] }* @rho.str.6, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [39 x i8] }, { i64, i64, [39 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 %line, i64 %col)
+  unreachable
+fail.overflow:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.0, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [39 x i8] }, { i64, i64, [39 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 %line, i64 %col)
+  unreachable
+}
@@ -0,0 +1,190 @@
+; ModuleID = './llvm_testfiles/golden/functions.rho'
+source_filename = "./llvm_testfiles/golden/functions.rho"
+
+@rho.str.0 = private unnamed_addr constant { i64, i64, [17 x i8] } { i64 -1, i64 1, [17 x i8] c"integer overflow\00" }
+@rho.str.1 = private unnamed_addr constant { i64, i64, [1 x i8] } { i64 -1, i64 1, [1 x i8] c"\00" }
+@rho.str.2 = private unnamed_addr constant { i64, i64, [38 x i8] } { i64 -1, i64 1, [38 x i8] c"./llvm_testfiles/golden/functions.rho\00" }
+@rho.str.3 = private unnamed_addr constant { i64, i64, [4 x i8] } { i64 -1, i64 1, [4 x i8] c"fib\00" }
+@rho.str.4 = private unnamed_addr constant { i64, i64, [28 x i8] } { i64 -1, i64 1, [28 x i8] c"no `cond` condition is true\00" }
+@rho.str.5 = private unnamed_addr constant { i64, i64, [7 x i8] } { i64 -1, i64 1, [7 x i8] c"hello \00" }
+@rho.str.6 = private unnamed_addr constant { i64, i64, [4 x i8] } { i64 -1, i64 1, [4 x i8] c"rho\00" }
+@rho.str.7 = private unnamed_addr constant { i64, i64, [6 x i8] } { i64 -1, i64 1, [6 x i8] c"greet\00" }
+@rho.str.8 = private unnamed_addr constant { i64, i64, [5 x i8] } { i64 -1, i64 1, [5 x i8] c"sign\00" }
+@rho.str.9 = private unnamed_addr constant { i64, i64, [5 x i8] } { i64 -1, i64 1, [5 x i8] c"main\00" }
+@rho.atom.0 = private unnamed_addr constant [3 x i8] c"ok\00"
+@rho.atom.1 = private unnamed_addr constant [6 x i8] c"error\00"
+@rho.atom.2 = private unnamed_addr constant [9 x i8] c"positive\00"
+@rho.atom.3 = private unnamed_addr constant [5 x i8] c"zero\00"
+@rho.atom.4 = private unnamed_addr constant [9 x i8] c"negative\00"
+@rho.atoms = constant [5 x i8*] [i8* getelementptr inbounds ([3 x i8], [3 x i8]* @rho.atom.0, i32 0, i32 0), i8* getelementptr inbounds ([6 x i8], [6 x i8]* @rho.atom.1, i32 0, i32 0), i8* getelementptr inbounds ([9 x i8], [9 x i8]* @rho.atom.2, i32 0, i32 0), i8* getelementptr inbounds ([5 x i8], [5 x i8]* @rho.atom.3, i32 0, i32 0), i8* getelementptr inbounds ([9 x i8], [9 x i8]* @rho.atom.4, i32 0, i32 0)]
+@rho.atom_count = constant i32 5
+
+declare void @rho_atoms_init(i8**, i32)
+declare void @rho_enter(i8*, i8*, i64, i64)
+declare void @rho_fail(i8*, i8*, i8*, i64, i64) noreturn
+declare void @rho_finish()
+declare void @rho_leave()
+declare void @rho_print_atom(i32)
+declare void @rho_print_int(i64)
+declare void @rho_print_newline()
+declare void @rho_print_str(i8*)
+declare void @rho_release(i8*)
+declare void @rho_retain(i8*)
+declare { i64, i1 } @llvm.sadd.with.overflow.i64(i64, i64)
+declare { i64, i1 } @llvm.ssub.with.overflow.i64(i64, i64)
+
+define internal i64 @rho.fn.fib(i64 %n.arg) {
+entry:
+  %_0 = alloca i64
+  %n.var = alloca i64
+  store i64 %n.arg, i64* %n.var
+  br label %bb0
+bb0:
+  %t1 = load i64, i64* %n.var
+  %t2 = icmp slt i64 %t1, 2
+  br i1 %t2, label %bb3, label %bb4
+bb4:
+  br label %bb2
+bb2:
+  %t3 = load i64, i64* %n.var
+  %t4 = call { i64, i1 } @llvm.ssub.with.overflow.i64(i64 %t3, i64 1)
+  %t5 = extractvalue { i64, i1 } %t4, 1
+  br i1 %t5, label %fail6, label %ok7
+fail6:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.0, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [38 x i8] }, { i64, i64, [38 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 6, i64 18)
+  unreachable
+ok7:
+  %t8 = extractvalue { i64, i1 } %t4, 0
+  call void @rho_enter(i8* getelementptr inbounds ({ i64, i64, [4 x i8] }, { i64, i64, [4 x i8] }* @rho.str.3, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [38 x i8] }, { i64, i64, [38 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 6, i64 12)
+  %t9 = call i64 @rho.fn.fib(i64 %t8)
+  call void @rho_leave()
+  %t10 = load i64, i64* %n.var
+  %t11 = call { i64, i1 } @llvm.ssub.with.overflow.i64(i64 %t10, i64 2)
+  %t12 = extractvalue { i64, i1 } %t11, 1
+  br i1 %t12, label %fail13, label %ok14
+fail13:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.0, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [38 x i8] }, { i64, i64, [38 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 6, i64 31)
+  unreachable
+ok14:
+  %t15 = extractvalue { i64, i1 } %t11, 0
+  call void @rho_enter(i8* getelementptr inbounds ({ i64, i64, [4 x i8] }, { i64, i64, [4 x i8] }* @rho.str.3, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [38 x i8] }, { i64, i64, [38 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 6, i64 25)
+  %t16 = call i64 @rho.fn.fib(i64 %t15)
+  call void @rho_leave()
+  %t17 = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %t9, i64 %t16)
+  %t18 = extractvalue { i64, i1 } %t17, 1
+  br i1 %t18, label %fail19, label %ok20
+fail19:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.0, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [38 x i8] }, { i64, i64, [38 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 6, i64 23)
+  unreachable
+ok20:
+  %t21 = extractvalue { i64, i1 } %t17, 0
+  store i64 %t21, i64* %_0
+  br label %bb1
+bb3:
+  %t22 = load i64, i64* %n.var
+  store i64 %t22, i64* %_0
+  br label %bb1
+bb1:
+  %t23 = load i64, i64* %_0
+  ret i64 %t23
+}
+
+define internal i32 @rho.fn.sign(i64 %n.arg) {
+entry:
+  %n.var = alloca i64
+  %_4 = alloca i32
+  store i64 %n.arg, i64* %n.var
+  br label %bb0
+bb0:
+  %t1 = load i64, i64* %n.var
+  %t2 = icmp slt i64 %t1, 0
+  br i1 %t2, label %bb3, label %bb4
+bb4:
+  %t3 = load i64, i64* %n.var
+  %t4 = icmp eq i64 %t3, 0
+  br i1 %t4, label %bb5, label %bb6
+bb6:
+  br i1 true, label %bb7, label %bb8
+bb8:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [28 x i8] }, { i64, i64, [28 x i8] }* @rho.str.4, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [38 x i8] }, { i64, i64, [38 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 10, i64 12)
+  unreachable
+bb7:
+  store i32 2, i32* %_4
+  br label %bb2
+bb5:
+  store i32 3, i32* %_4
+  br label %bb2
+bb3:
+  store i32 4, i32* %_4
+  br label %bb2
+bb2:
+  %t5 = load i32, i32* %_4
+  br label %bb1
+bb1:
+  ret i32 %t5
+}
+
+define internal void @rho.fn.greet(i8* %name.arg) {
+entry:
+  %name.var = alloca i8*
+  store i8* null, i8** %name.var
+  store i8* %name.arg, i8** %name.var
+  br label %bb0
+bb0:
+  call void @rho_print_str(i8* getelementptr inbounds ({ i64, i64, [7 x i8] }, { i64, i64, [7 x i8] }* @rho.str.5, i64 0, i32 2, i64 0))
+  %t1 = load i8*, i8** %name.var
+  call void @rho_retain(i8* %t1)
+  call void @rho_print_str(i8* %t1)
+  call void @rho_print_newline()
+  call void @rho_release(i8* %t1)
+  br label %bb1
+bb1:
+  %t2 = load i8*, i8** %name.var
+  call void @rho_release(i8* %t2)
+  ret void
+}
+
+define internal i64 @rho.fn.main() {
+entry:
+  br label %bb0
+bb0:
+  call void @rho_enter(i8* getelementptr inbounds ({ i64, i64, [4 x i8] }, { i64, i64, [4 x i8] }* @rho.str.3, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [38 x i8] }, { i64, i64, [38 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 28, i64 12)
+  %t1 = call i64 @rho.fn.fib(i64 5)
+  call void @rho_leave()
+  br label %bb1
+bb1:
+  ret i64 %t1
+}
+
+define i32 @main() {
+entry:
+  call void @rho_atoms_init(i8** getelementptr inbounds ([5 x i8*], [5 x i8*]* @rho.atoms, i64 0, i64 0), i32 5)
+  br label %bb0
+bb0:
+  call void @rho_enter(i8* getelementptr inbounds ({ i64, i64, [6 x i8] }, { i64, i64, [6 x i8] }* @rho.str.7, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [38 x i8] }, { i64, i64, [38 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 22, i64 1)
+  call void @rho.fn.greet(i8* getelementptr inbounds ({ i64, i64, [4 x i8] }, { i64, i64, [4 x i8] }* @rho.str.6, i64 0, i32 2, i64 0))
+  call void @rho_leave()
+  call void @rho_enter(i8* getelementptr inbounds ({ i64, i64, [4 x i8] }, { i64, i64, [4 x i8] }* @rho.str.3, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [38 x i8] }, { i64, i64, [38 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 23, i64 9)
+  %t1 = call i64 @rho.fn.fib(i64 20)
+  call void @rho_leave()
+  call void @rho_print_int(i64 %t1)
+  call void @rho_print_newline()
+  call void @rho_enter(i8* getelementptr inbounds ({ i64, i64, [5 x i8] }, { i64, i64, [5 x i8] }* @rho.str.8, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [38 x i8] }, { i64, i64, [38 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 24, i64 9)
+  %t2 = call i32 @rho.fn.sign(i64 -4)
+  call void @rho_leave()
+  call void @rho_print_atom(i32 %t2)
+  call void @rho_print_newline()
+  call void @rho_enter(i8* getelementptr inbounds ({ i64, i64, [5 x i8] }, { i64, i64, [5 x i8] }* @rho.str.8, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [38 x i8] }, { i64, i64, [38 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 25, i64 12)
+  %t3 = call i32 @rho.fn.sign(i64 0)
+  call void @rho_leave()
+  call void @rho_print_atom(i32 %t3)
+  call void @rho_print_newline()
+  call void @rho_enter(i8* getelementptr inbounds ({ i64, i64, [5 x i8] }, { i64, i64, [5 x i8] }* @rho.str.9, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [38 x i8] }, { i64, i64, [38 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 0, i64 0)
+  %t4 = call i64 @rho.fn.main()
+  call void @rho_leave()
+  br label %bb1
+bb1:
+  %t5 = trunc i64 %t4 to i32
+  call void @rho_finish()
+  ret i32 %t5
+}
@@ -0,0 +1,223 @@
+; ModuleID = './llvm_testfiles/golden/loops.rho'
+source_filename = "./llvm_testfiles/golden/loops.rho"
+
+@rho.str.0 = private unnamed_addr constant { i64, i64, [17 x i8] } { i64 -1, i64 1, [17 x i8] c"division by zero\00" }
+@rho.str.1 = private unnamed_addr constant { i64, i64, [1 x i8] } { i64 -1, i64 1, [1 x i8] c"\00" }
+@rho.str.2 = private unnamed_addr constant { i64, i64, [34 x i8] } { i64 -1, i64 1, [34 x i8] c"./llvm_testfiles/golden/loops.rho\00" }
+@rho.str.3 = private unnamed_addr constant { i64, i64, [17 x i8] } { i64 -1, i64 1, [17 x i8] c"integer overflow\00" }
+@rho.str.4 = private unnamed_addr constant { i64, i64, [2 x i8] } { i64 -1, i64 1, [2 x i8] c" \00" }
+@rho.str.5 = private unnamed_addr constant { i64, i64, [31 x i8] } { i64 -1, i64 1, [31 x i8] c"a range can't have a step of 0\00" }
+@rho.atom.0 = private unnamed_addr constant [3 x i8] c"ok\00"
+@rho.atom.1 = private unnamed_addr constant [6 x i8] c"error\00"
+@rho.atoms = constant [2 x i8*] [i8* getelementptr inbounds ([3 x i8], [3 x i8]* @rho.atom.0, i32 0, i32 0), i8* getelementptr inbounds ([6 x i8], [6 x i8]* @rho.atom.1, i32 0, i32 0)]
+@rho.atom_count = constant i32 2
+
+@rho.var.total = internal global i64 zeroinitializer
+@rho.var.n = internal global i64 zeroinitializer
+@rho.var.steps = internal global i64 zeroinitializer
+
+declare void @rho_atoms_init(i8**, i32)
+declare void @rho_fail(i8*, i8*, i8*, i64, i64) noreturn
+declare void @rho_finish()
+declare void @rho_print_int(i64)
+declare void @rho_print_newline()
+declare void @rho_print_str(i8*)
+declare { i64, i1 } @llvm.sadd.with.overflow.i64(i64, i64)
+declare { i64, i1 } @llvm.smul.with.overflow.i64(i64, i64)
+
+define i32 @main() {
+entry:
+  %_1 = alloca i64
+  %i.var = alloca i64
+  %_15 = alloca i64
+  %i.2.var = alloca i64
+  %_30 = alloca i64
+  call void @rho_atoms_init(i8** getelementptr inbounds ([2 x i8*], [2 x i8*]* @rho.atoms, i64 0, i64 0), i32 2)
+  br label %bb0
+bb0:
+  store i64 0, i64* @rho.var.total
+  store i64 0, i64* %_1
+  br label %bb2
+bb2:
+  %t1 = load i64, i64* %_1
+  %t2 = icmp slt i64 %t1, 10
+  br i1 %t2, label %bb3, label %bb5
+bb3:
+  %t3 = load i64, i64* %_1
+  store i64 %t3, i64* %i.var
+  %t4 = load i64, i64* %i.var
+  %t5 = icmp eq i64 %t4, 7
+  br i1 %t5, label %bb7, label %bb8
+bb8:
+  %t6 = load i64, i64* %i.var
+  %t7 = icmp eq i64 2, 0
+  br i1 %t7, label %fail8, label %ok9
+fail8:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.0, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [34 x i8] }, { i64, i64, [34 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 6, i64 14)
+  unreachable
+ok9:
+  %t10 = icmp eq i64 %t6, -9223372036854775808
+  %t11 = icmp eq i64 2, -1
+  %t12 = and i1 %t10, %t11
+  br i1 %t12, label %fail13, label %ok14
+fail13:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.3, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [34 x i8] }, { i64, i64, [34 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 6, i64 14)
+  unreachable
+ok14:
+  %t15 = srem i64 %t6, 2
+  %t16 = icmp eq i64 %t15, 0
+  br i1 %t16, label %bb10, label %bb11
+bb11:
+  br label %bb6
+bb6:
+  %t17 = load i64, i64* @rho.var.total
+  %t18 = load i64, i64* %i.var
+  %t19 = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %t17, i64 %t18)
+  %t20 = extractvalue { i64, i1 } %t19, 1
+  br i1 %t20, label %fail21, label %ok22
+fail21:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.3, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [34 x i8] }, { i64, i64, [34 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 9, i64 19)
+  unreachable
+ok22:
+  %t23 = extractvalue { i64, i1 } %t19, 0
+  store i64 %t23, i64* @rho.var.total
+  br label %bb4
+bb10:
+  br label %bb4
+bb4:
+  %t24 = load i64, i64* %_1
+  %t25 = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %t24, i64 1)
+  %t26 = extractvalue { i64, i1 } %t25, 1
+  %t27 = load i64, i64* %_1
+  %t28 = add i64 %t27, 1
+  store i64 %t28, i64* %_1
+  br i1 %t26, label %bb5, label %bb2
+bb7:
+  br label %bb5
+bb5:
+  %t29 = load i64, i64* @rho.var.total
+  call void @rho_print_int(i64 %t29)
+  call void @rho_print_newline()
+  %t30 = icmp eq i64 -3, 0
+  br i1 %t30, label %bb13, label %bb14
+bb14:
+  store i64 10, i64* %_15
+  br label %bb15
+bb15:
+  %t31 = icmp sgt i64 -3, 0
+  %t32 = load i64, i64* %_15
+  %t33 = icmp slt i64 %t32, 0
+  %t34 = load i64, i64* %_15
+  %t35 = icmp sgt i64 %t34, 0
+  %t36 = select i1 %t31, i1 %t33, i1 %t35
+  br i1 %t36, label %bb16, label %bb18
+bb16:
+  %t37 = load i64, i64* %_15
+  store i64 %t37, i64* %i.2.var
+  %t38 = load i64, i64* %i.2.var
+  call void @rho_print_int(i64 %t38)
+  call void @rho_print_str(i8* getelementptr inbounds ({ i64, i64, [2 x i8] }, { i64, i64, [2 x i8] }* @rho.str.4, i64 0, i32 2, i64 0))
+  br label %bb17
+bb17:
+  %t39 = load i64, i64* %_15
+  %t40 = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %t39, i64 -3)
+  %t41 = extractvalue { i64, i1 } %t40, 1
+  %t42 = load i64, i64* %_15
+  %t43 = add i64 %t42, -3
+  store i64 %t43, i64* %_15
+  br i1 %t41, label %bb18, label %bb15
+bb18:
+  call void @rho_print_str(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0))
+  call void @rho_print_newline()
+  store i64 27, i64* @rho.var.n
+  store i64 0, i64* @rho.var.steps
+  br label %bb19
+bb19:
+  %t44 = load i64, i64* @rho.var.n
+  %t45 = icmp ne i64 %t44, 1
+  br i1 %t45, label %bb20, label %bb21
+bb21:
+  %t46 = load i64, i64* @rho.var.steps
+  call void @rho_print_int(i64 %t46)
+  call void @rho_print_newline()
+  br label %bb1
+bb1:
+  %t47 = trunc i64 0 to i32
+  call void @rho_finish()
+  ret i32 %t47
+bb20:
+  %t48 = load i64, i64* @rho.var.n
+  %t49 = icmp eq i64 2, 0
+  br i1 %t49, label %fail50, label %ok51
+fail50:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.0, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [34 x i8] }, { i64, i64, [34 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 20, i64 14)
+  unreachable
+ok51:
+  %t52 = icmp eq i64 %t48, -9223372036854775808
+  %t53 = icmp eq i64 2, -1
+  %t54 = and i1 %t52, %t53
+  br i1 %t54, label %fail55, label %ok56
+fail55:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.3, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [34 x i8] }, { i64, i64, [34 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 20, i64 14)
+  unreachable
+ok56:
+  %t57 = srem i64 %t48, 2
+  %t58 = icmp eq i64 %t57, 0
+  br i1 %t58, label %bb23, label %bb24
+bb24:
+  %t59 = load i64, i64* @rho.var.n
+  %t60 = call { i64, i1 } @llvm.smul.with.overflow.i64(i64 3, i64 %t59)
+  %t61 = extractvalue { i64, i1 } %t60, 1
+  br i1 %t61, label %fail62, label %ok63
+fail62:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.3, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [34 x i8] }, { i64, i64, [34 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 20, i64 42)
+  unreachable
+ok63:
+  %t64 = extractvalue { i64, i1 } %t60, 0
+  %t65 = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %t64, i64 1)
+  %t66 = extractvalue { i64, i1 } %t65, 1
+  br i1 %t66, label %fail67, label %ok68
+fail67:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.3, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [34 x i8] }, { i64, i64, [34 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 20, i64 46)
+  unreachable
+ok68:
+  %t69 = extractvalue { i64, i1 } %t65, 0
+  store i64 %t69, i64* %_30
+  br label %bb22
+bb23:
+  %t70 = load i64, i64* @rho.var.n
+  %t71 = icmp eq i64 2, 0
+  br i1 %t71, label %fail72, label %ok73
+fail72:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.0, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [34 x i8] }, { i64, i64, [34 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 20, i64 27)
+  unreachable
+ok73:
+  %t74 = icmp eq i64 %t70, -9223372036854775808
+  %t75 = icmp eq i64 2, -1
+  %t76 = and i1 %t74, %t75
+  br i1 %t76, label %fail77, label %ok78
+fail77:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.3, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [34 x i8] }, { i64, i64, [34 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 20, i64 27)
+  unreachable
+ok78:
+  %t79 = sdiv i64 %t70, 2
+  store i64 %t79, i64* %_30
+  br label %bb22
+bb22:
+  %t80 = load i64, i64* %_30
+  store i64 %t80, i64* @rho.var.n
+  %t81 = load i64, i64* @rho.var.steps
+  %t82 = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %t81, i64 1)
+  %t83 = extractvalue { i64, i1 } %t82, 1
+  br i1 %t83, label %fail84, label %ok85
+fail84:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.3, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [34 x i8] }, { i64, i64, [34 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 21, i64 19)
+  unreachable
+ok85:
+  %t86 = extractvalue { i64, i1 } %t82, 0
+  store i64 %t86, i64* @rho.var.steps
+  br label %bb19
+bb13:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [31 x i8] }, { i64, i64, [31 x i8] }* @rho.str.5, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [34 x i8] }, { i64, i64, [34 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 12, i64 12)
+  unreachable
+}
@@ -0,0 +1,294 @@
+; ModuleID = './llvm_testfiles/golden/memory.rho'
+source_filename = "./llvm_testfiles/golden/memory.rho"
+
+@rho.str.0 = private unnamed_addr constant { i64, i64, [7 x i8] } { i64 -1, i64 1, [7 x i8] c"hello \00" }
+@rho.str.1 = private unnamed_addr constant { i64, i64, [2 x i8] } { i64 -1, i64 1, [2 x i8] c".\00" }
+@rho.str.2 = private unnamed_addr constant { i64, i64, [2 x i8] } { i64 -1, i64 1, [2 x i8] c"!\00" }
+@rho.str.3 = private unnamed_addr constant { i64, i64, [1 x i8] } { i64 -1, i64 1, [1 x i8] c"\00" }
+@rho.str.4 = private unnamed_addr constant { i64, i64, [5 x i8] } { i64 -1, i64 1, [5 x i8] c"main\00" }
+@rho.str.5 = private unnamed_addr constant { i64, i64, [6 x i8] } { i64 -1, i64 1, [6 x i8] c"greet\00" }
+@rho.str.6 = private unnamed_addr constant { i64, i64, [35 x i8] } { i64 -1, i64 1, [35 x i8] c"./llvm_testfiles/golden/memory.rho\00" }
+@rho.str.7 = private unnamed_addr constant { i64, i64, [3 x i8] } { i64 -1, i64 1, [3 x i8] c"ab\00" }
+@rho.str.8 = private unnamed_addr constant { i64, i64, [4 x i8] } { i64 -1, i64 1, [4 x i8] c"rho\00" }
+@rho.str.9 = private unnamed_addr constant { i64, i64, [6 x i8] } { i64 -1, i64 1, [6 x i8] c"world\00" }
+@rho.str.10 = private unnamed_addr constant { i64, i64, [2 x i8] } { i64 -1, i64 1, [2 x i8] c"s\00" }
+@rho.str.11 = private unnamed_addr constant { i64, i64, [2 x i8] } { i64 -1, i64 1, [2 x i8] c"z\00" }
+@rho.str.12 = private unnamed_addr constant { i64, i64, [8 x i8] } { i64 -1, i64 1, [8 x i8] c"longest\00" }
+@rho.str.13 = private unnamed_addr constant { i64, i64, [7 x i8] } { i64 -1, i64 1, [7 x i8] c"nobody\00" }
+@rho.str.14 = private unnamed_addr constant { i64, i64, [2 x i8] } { i64 -1, i64 1, [2 x i8] c" \00" }
+@rho.str.15 = private unnamed_addr constant { i64, i64, [10 x i8] } { i64 -1, i64 1, [10 x i8] c"discarded\00" }
+@rho.str.16 = private unnamed_addr constant { i64, i64, [17 x i8] } { i64 -1, i64 1, [17 x i8] c"integer overflow\00" }
+@rho.atom.0 = private unnamed_addr constant [3 x i8] c"ok\00"
+@rho.atom.1 = private unnamed_addr constant [6 x i8] c"error\00"
+@rho.atoms = constant [2 x i8*] [i8* getelementptr inbounds ([3 x i8], [3 x i8]* @rho.atom.0, i32 0, i32 0), i8* getelementptr inbounds ([6 x i8], [6 x i8]* @rho.atom.1, i32 0, i32 0)]
+@rho.atom_count = constant i32 2
+
+@rho.var.line = internal global i8* zeroinitializer
+@rho.var.count = internal global i64 zeroinitializer
+
+declare i32 @rho_str_compare(i8*, i8*)
+declare i8* @rho_str_concat(i8*, i8*)
+declare void @rho_atoms_init(i8**, i32)
+declare void @rho_enter(i8*, i8*, i64, i64)
+declare void @rho_fail(i8*, i8*, i8*, i64, i64) noreturn
+declare void @rho_finish()
+declare void @rho_inspect_str(i8*)
+declare void @rho_leave()
+declare void @rho_print_newline()
+declare void @rho_print_str(i8*)
+declare void @rho_release(i8*)
+declare void @rho_retain(i8*)
+declare { i64, i1 } @llvm.sadd.with.overflow.i64(i64, i64)
+
+define internal i8* @rho.fn.greet(i8* %name.arg, i1 %loud.arg) {
+entry:
+  %_0 = alloca i8*
+  store i8* null, i8** %_0
+  %name.var = alloca i8*
+  store i8* null, i8** %name.var
+  %loud.var = alloca i1
+  %greeting.var = alloca i8*
+  store i8* null, i8** %greeting.var
+  store i8* %name.arg, i8** %name.var
+  store i1 %loud.arg, i1* %loud.var
+  br label %bb0
+bb0:
+  %t1 = load i8*, i8** %name.var
+  call void @rho_retain(i8* %t1)
+  %t2 = call i8* @rho_str_concat(i8* getelementptr inbounds ({ i64, i64, [7 x i8] }, { i64, i64, [7 x i8] }* @rho.str.0, i64 0, i32 2, i64 0), i8* %t1)
+  call void @rho_release(i8* %t1)
+  %t3 = load i8*, i8** %greeting.var
+  call void @rho_release(i8* %t3)
+  store i8* %t2, i8** %greeting.var
+  %t4 = load i1, i1* %loud.var
+  br i1 %t4, label %bb3, label %bb4
+bb4:
+  br label %bb2
+bb2:
+  %t5 = load i8*, i8** %greeting.var
+  call void @rho_retain(i8* %t5)
+  %t6 = call i8* @rho_str_concat(i8* %t5, i8* getelementptr inbounds ({ i64, i64, [2 x i8] }, { i64, i64, [2 x i8] }* @rho.str.1, i64 0, i32 2, i64 0))
+  call void @rho_release(i8* %t5)
+  %t7 = load i8*, i8** %greeting.var
+  call void @rho_release(i8* %t7)
+  store i8* %t6, i8** %greeting.var
+  %t8 = load i8*, i8** %greeting.var
+  call void @rho_retain(i8* %t8)
+  store i8* %t8, i8** %_0
+  br label %bb1
+bb3:
+  %t9 = load i8*, i8** %greeting.var
+  call void @rho_retain(i8* %t9)
+  %t10 = call i8* @rho_str_concat(i8* %t9, i8* getelementptr inbounds ({ i64, i64, [2 x i8] }, { i64, i64, [2 x i8] }* @rho.str.2, i64 0, i32 2, i64 0))
+  call void @rho_release(i8* %t9)
+  store i8* %t10, i8** %_0
+  br label %bb1
+bb1:
+  %t11 = load i8*, i8** %name.var
+  call void @rho_release(i8* %t11)
+  %t12 = load i8*, i8** %greeting.var
+  call void @rho_release(i8* %t12)
+  %t13 = load i8*, i8** %_0
+  ret i8* %t13
+}
+
+define internal i8* @rho.fn.longest(i8* %a.arg, i8* %b.arg) {
+entry:
+  %a.var = alloca i8*
+  store i8* null, i8** %a.var
+  %b.var = alloca i8*
+  store i8* null, i8** %b.var
+  %_7 = alloca i8*
+  store i8* null, i8** %_7
+  store i8* %a.arg, i8** %a.var
+  store i8* %b.arg, i8** %b.var
+  br label %bb0
+bb0:
+  %t1 = load i8*, i8** %a.var
+  call void @rho_retain(i8* %t1)
+  %t2 = load i8*, i8** %b.var
+  call void @rho_retain(i8* %t2)
+  %t3 = call i32 @rho_str_compare(i8* %t1, i8* %t2)
+  %t4 = icmp sgt i32 %t3, 0
+  call void @rho_release(i8* %t1)
+  call void @rho_release(i8* %t2)
+  br i1 %t4, label %bb3, label %bb4
+bb4:
+  %t5 = load i8*, i8** %b.var
+  call void @rho_retain(i8* %t5)
+  %t6 = call i8* @rho_str_concat(i8* %t5, i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.3, i64 0, i32 2, i64 0))
+  call void @rho_release(i8* %t5)
+  store i8* %t6, i8** %_7
+  br label %bb2
+bb3:
+  %t7 = load i8*, i8** %a.var
+  call void @rho_retain(i8* %t7)
+  store i8* %t7, i8** %_7
+  br label %bb2
+bb2:
+  %t8 = load i8*, i8** %_7
+  br label %bb1
+bb1:
+  %t9 = load i8*, i8** %a.var
+  call void @rho_release(i8* %t9)
+  %t10 = load i8*, i8** %b.var
+  call void @rho_release(i8* %t10)
+  ret i8* %t8
+}
+
+define internal i64 @rho.fn.main() {
+entry:
+  %local.var = alloca i8*
+  store i8* null, i8** %local.var
+  br label %bb0
+bb0:
+  call void @rho_enter(i8* getelementptr inbounds ({ i64, i64, [6 x i8] }, { i64, i64, [6 x i8] }* @rho.str.5, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [35 x i8] }, { i64, i64, [35 x i8] }* @rho.str.6, i64 0, i32 2, i64 0), i64 38, i64 17)
+  %t1 = call i8* @rho.fn.greet(i8* getelementptr inbounds ({ i64, i64, [5 x i8] }, { i64, i64, [5 x i8] }* @rho.str.4, i64 0, i32 2, i64 0), i1 true)
+  call void @rho_leave()
+  %t2 = load i8*, i8** %local.var
+  call void @rho_release(i8* %t2)
+  store i8* %t1, i8** %local.var
+  %t3 = load i8*, i8** %local.var
+  call void @rho_retain(i8* %t3)
+  call void @rho_print_str(i8* %t3)
+  call void @rho_print_newline()
+  call void @rho_release(i8* %t3)
+  br label %bb1
+bb1:
+  %t4 = load i8*, i8** %local.var
+  call void @rho_release(i8* %t4)
+  ret i64 0
+}
+
+define i32 @main() {
+entry:
+  %_1 = alloca i64
+  %i.var = alloca i64
+  %copy.var = alloca i8*
+  store i8* null, i8** %copy.var
+  %_25 = alloca i8*
+  store i8* null, i8** %_25
+  call void @rho_atoms_init(i8** getelementptr inbounds ([2 x i8*], [2 x i8*]* @rho.atoms, i64 0, i64 0), i32 2)
+  br label %bb0
+bb0:
+  %t1 = load i8*, i8** @rho.var.line
+  call void @rho_release(i8* %t1)
+  store i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.3, i64 0, i32 2, i64 0), i8** @rho.var.line
+  store i64 0, i64* %_1
+  br label %bb2
+bb2:
+  %t2 = load i64, i64* %_1
+  %t3 = icmp slt i64 %t2, 5
+  br i1 %t3, label %bb3, label %bb5
+bb3:
+  %t4 = load i64, i64* %_1
+  store i64 %t4, i64* %i.var
+  %t5 = load i8*, i8** @rho.var.line
+  call void @rho_retain(i8* %t5)
+  %t6 = call i8* @rho_str_concat(i8* %t5, i8* getelementptr inbounds ({ i64, i64, [3 x i8] }, { i64, i64, [3 x i8] }* @rho.str.7, i64 0, i32 2, i64 0))
+  call void @rho_release(i8* %t5)
+  %t7 = load i8*, i8** @rho.var.line
+  call void @rho_release(i8* %t7)
+  store i8* %t6, i8** @rho.var.line
+  br label %bb4
+bb4:
+  %t8 = load i64, i64* %_1
+  %t9 = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %t8, i64 1)
+  %t10 = extractvalue { i64, i1 } %t9, 1
+  %t11 = load i64, i64* %_1
+  %t12 = add i64 %t11, 1
+  store i64 %t12, i64* %_1
+  br i1 %t10, label %bb5, label %bb2
+bb5:
+  %t13 = load i8*, i8** @rho.var.line
+  call void @rho_retain(i8* %t13)
+  call void @rho_print_str(i8* %t13)
+  call void @rho_print_newline()
+  call void @rho_release(i8* %t13)
+  call void @rho_enter(i8* getelementptr inbounds ({ i64, i64, [6 x i8] }, { i64, i64, [6 x i8] }* @rho.str.5, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [35 x i8] }, { i64, i64, [35 x i8] }* @rho.str.6, i64 0, i32 2, i64 0), i64 21, i64 9)
+  %t14 = call i8* @rho.fn.greet(i8* getelementptr inbounds ({ i64, i64, [4 x i8] }, { i64, i64, [4 x i8] }* @rho.str.8, i64 0, i32 2, i64 0), i1 true)
+  call void @rho_leave()
+  call void @rho_print_str(i8* %t14)
+  call void @rho_print_newline()
+  call void @rho_release(i8* %t14)
+  %t15 = call i8* @rho_str_concat(i8* getelementptr inbounds ({ i64, i64, [6 x i8] }, { i64, i64, [6 x i8] }* @rho.str.9, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [2 x i8] }, { i64, i64, [2 x i8] }* @rho.str.10, i64 0, i32 2, i64 0))
+  call void @rho_enter(i8* getelementptr inbounds ({ i64, i64, [6 x i8] }, { i64, i64, [6 x i8] }* @rho.str.5, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [35 x i8] }, { i64, i64, [35 x i8] }* @rho.str.6, i64 0, i32 2, i64 0), i64 22, i64 9)
+  %t16 = call i8* @rho.fn.greet(i8* %t15, i1 false)
+  call void @rho_leave()
+  call void @rho_print_str(i8* %t16)
+  call void @rho_print_newline()
+  call void @rho_release(i8* %t16)
+  %t17 = load i8*, i8** @rho.var.line
+  call void @rho_retain(i8* %t17)
+  %t18 = call i8* @rho_str_concat(i8* getelementptr inbounds ({ i64, i64, [2 x i8] }, { i64, i64, [2 x i8] }* @rho.str.11, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [2 x i8] }, { i64, i64, [2 x i8] }* @rho.str.11, i64 0, i32 2, i64 0))
+  call void @rho_enter(i8* getelementptr inbounds ({ i64, i64, [8 x i8] }, { i64, i64, [8 x i8] }* @rho.str.12, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [35 x i8] }, { i64, i64, [35 x i8] }* @rho.str.6, i64 0, i32 2, i64 0), i64 23, i64 12)
+  %t19 = call i8* @rho.fn.longest(i8* %t17, i8* %t18)
+  call void @rho_leave()
+  call void @rho_inspect_str(i8* %t19)
+  call void @rho_print_newline()
+  call void @rho_release(i8* %t19)
+  call void @rho_enter(i8* getelementptr inbounds ({ i64, i64, [6 x i8] }, { i64, i64, [6 x i8] }* @rho.str.5, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [35 x i8] }, { i64, i64, [35 x i8] }* @rho.str.6, i64 0, i32 2, i64 0), i64 24, i64 1)
+  %t20 = call i8* @rho.fn.greet(i8* getelementptr inbounds ({ i64, i64, [7 x i8] }, { i64, i64, [7 x i8] }* @rho.str.13, i64 0, i32 2, i64 0), i1 false)
+  call void @rho_leave()
+  call void @rho_release(i8* %t20)
+  store i64 0, i64* @rho.var.count
+  br label %bb6
+bb6:
+  %t21 = load i64, i64* @rho.var.count
+  %t22 = icmp slt i64 %t21, 3
+  br i1 %t22, label %bb7, label %bb8
+bb8:
+  call void @rho_print_str(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.3, i64 0, i32 2, i64 0))
+  call void @rho_print_newline()
+  call void @rho_enter(i8* getelementptr inbounds ({ i64, i64, [5 x i8] }, { i64, i64, [5 x i8] }* @rho.str.4, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [35 x i8] }, { i64, i64, [35 x i8] }* @rho.str.6, i64 0, i32 2, i64 0), i64 0, i64 0)
+  %t23 = call i64 @rho.fn.main()
+  call void @rho_leave()
+  br label %bb1
+bb1:
+  %t24 = load i8*, i8** %copy.var
+  call void @rho_release(i8* %t24)
+  %t25 = load i8*, i8** @rho.var.line
+  call void @rho_release(i8* %t25)
+  %t26 = trunc i64 %t23 to i32
+  call void @rho_finish()
+  ret i32 %t26
+bb7:
+  %t27 = load i8*, i8** @rho.var.line
+  call void @rho_retain(i8* %t27)
+  %t28 = load i8*, i8** %copy.var
+  call void @rho_release(i8* %t28)
+  store i8* %t27, i8** %copy.var
+  %t29 = load i8*, i8** %copy.var
+  call void @rho_retain(i8* %t29)
+  %t30 = call i8* @rho_str_concat(i8* %t29, i8* getelementptr inbounds ({ i64, i64, [2 x i8] }, { i64, i64, [2 x i8] }* @rho.str.14, i64 0, i32 2, i64 0))
+  call void @rho_release(i8* %t29)
+  call void @rho_print_str(i8* %t30)
+  call void @rho_release(i8* %t30)
+  %t31 = load i64, i64* @rho.var.count
+  %t32 = icmp eq i64 %t31, 1
+  br i1 %t32, label %bb10, label %bb11
+bb11:
+  br label %bb9
+bb10:
+  %t33 = load i8*, i8** @rho.var.line
+  call void @rho_retain(i8* %t33)
+  %t34 = call i8* @rho_str_concat(i8* %t33, i8* getelementptr inbounds ({ i64, i64, [10 x i8] }, { i64, i64, [10 x i8] }* @rho.str.15, i64 0, i32 2, i64 0))
+  call void @rho_release(i8* %t33)
+  store i8* %t34, i8** %_25
+  br label %bb9
+bb9:
+  %t35 = load i8*, i8** %_25
+  call void @rho_release(i8* %t35)
+  store i8* null, i8** %_25
+  %t36 = load i64, i64* @rho.var.count
+  %t37 = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %t36, i64 1)
+  %t38 = extractvalue { i64, i1 } %t37, 1
+  br i1 %t38, label %fail39, label %ok40
+fail39:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.3, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.16, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [35 x i8] }, { i64, i64, [35 x i8] }* @rho.str.6, i64 0, i32 2, i64 0), i64 33, i64 19)
+  unreachable
+ok40:
+  %t41 = extractvalue { i64, i1 } %t37, 0
+  store i64 %t41, i64* @rho.var.count
+  br label %bb6
+}
@@ -0,0 +1,285 @@
+; ModuleID = './llvm_testfiles/golden/strings.rho'
+source_filename = "./llvm_testfiles/golden/strings.rho"
+
+@rho.str.0 = private unnamed_addr constant { i64, i64, [8 x i8] } { i64 -1, i64 1, [8 x i8] c"too big\00" }
+@rho.str.1 = private unnamed_addr constant { i64, i64, [8 x i8] } { i64 -1, i64 1, [8 x i8] c"panic: \00" }
+@rho.str.2 = private unnamed_addr constant { i64, i64, [36 x i8] } { i64 -1, i64 1, [36 x i8] c"./llvm_testfiles/golden/strings.rho\00" }
+@rho.str.3 = private unnamed_addr constant { i64, i64, [12 x i8] } { i64 -1, i64 1, [12 x i8] c"rho \22lang\22\0A\00" }
+@rho.str.4 = private unnamed_addr constant { i64, i64, [2 x i8] } { i64 -1, i64 1, [2 x i8] c"!\00" }
+@rho.str.5 = private unnamed_addr constant { i64, i64, [2 x i8] } { i64 -1, i64 1, [2 x i8] c"a\00" }
+@rho.str.6 = private unnamed_addr constant { i64, i64, [2 x i8] } { i64 -1, i64 1, [2 x i8] c"b\00" }
+@rho.str.7 = private unnamed_addr constant { i64, i64, [4 x i8] } { i64 -1, i64 1, [4 x i8] c"rho\00" }
+@rho.str.8 = private unnamed_addr constant { i64, i64, [10 x i8] } { i64 -1, i64 1, [10 x i8] c"  Ren\C3\A9e \00" }
+@rho.str.9 = private unnamed_addr constant { i64, i64, [2 x i8] } { i64 -1, i64 1, [2 x i8] c" \00" }
+@rho.str.10 = private unnamed_addr constant { i64, i64, [17 x i8] } { i64 -1, i64 1, [17 x i8] c"integer overflow\00" }
+@rho.str.11 = private unnamed_addr constant { i64, i64, [1 x i8] } { i64 -1, i64 1, [1 x i8] c"\00" }
+@rho.str.12 = private unnamed_addr constant { i64, i64, [4 x i8] } { i64 -1, i64 1, [4 x i8] c"1-2\00" }
+@rho.str.13 = private unnamed_addr constant { i64, i64, [2 x i8] } { i64 -1, i64 1, [2 x i8] c"-\00" }
+@rho.str.14 = private unnamed_addr constant { i64, i64, [2 x i8] } { i64 -1, i64 1, [2 x i8] c".\00" }
+@rho.str.15 = private unnamed_addr constant { i64, i64, [2 x i8] } { i64 -1, i64 1, [2 x i8] c"0\00" }
+@rho.str.16 = private unnamed_addr constant { i64, i64, [3 x i8] } { i64 -1, i64 1, [3 x i8] c"Re\00" }
+@rho.str.17 = private unnamed_addr constant { i64, i64, [2 x i8] } { i64 -1, i64 1, [2 x i8] c"x\00" }
+@rho.str.18 = private unnamed_addr constant { i64, i64, [2 x i8] } { i64 -1, i64 1, [2 x i8] c"`\00" }
+@rho.str.19 = private unnamed_addr constant { i64, i64, [5 x i8] } { i64 -1, i64 1, [5 x i8] c" == \00" }
+@rho.str.20 = private unnamed_addr constant { i64, i64, [15 x i8] } { i64 -1, i64 1, [15 x i8] c"math is broken\00" }
+@rho.str.21 = private unnamed_addr constant { i64, i64, [3 x i8] } { i64 -1, i64 1, [3 x i8] c", \00" }
+@rho.str.22 = private unnamed_addr constant { i64, i64, [19 x i8] } { i64 -1, i64 1, [19 x i8] c"assertion failed: \00" }
+@rho.str.23 = private unnamed_addr constant { i64, i64, [6 x i8] } { i64 -1, i64 1, [6 x i8] c"check\00" }
+@rho.str.24 = private unnamed_addr constant { i64, i64, [3 x i8] } { i64 -1, i64 1, [3 x i8] c"ab\00" }
+@rho.str.25 = private unnamed_addr constant { i64, i64, [2 x i8] } { i64 -1, i64 1, [2 x i8] c"c\00" }
+@rho.str.26 = private unnamed_addr constant { i64, i64, [4 x i8] } { i64 -1, i64 1, [4 x i8] c"abc\00" }
+@rho.str.27 = private unnamed_addr constant { i64, i64, [7 x i8] } { i64 -1, i64 1, [7 x i8] c"concat\00" }
+@rho.atom.0 = private unnamed_addr constant [3 x i8] c"ok\00"
+@rho.atom.1 = private unnamed_addr constant [6 x i8] c"error\00"
+@rho.atoms = constant [2 x i8*] [i8* getelementptr inbounds ([3 x i8], [3 x i8]* @rho.atom.0, i32 0, i32 0), i8* getelementptr inbounds ([6 x i8], [6 x i8]* @rho.atom.1, i32 0, i32 0)]
+@rho.atom_count = constant i32 2
+
+@rho.var.name = internal global i8* zeroinitializer
+@rho.var.trimmed = internal global i8* zeroinitializer
+@rho.var.s = internal global i8* zeroinitializer
+
+declare i32 @rho_str_compare(i8*, i8*)
+declare i64 @rho_string_byte_size(i8*)
+declare i64 @rho_string_length(i8*)
+declare i8* @rho_str_concat(i8*, i8*)
+declare i8* @rho_str_from_int(i64)
+declare i8* @rho_str_inspect_str(i8*)
+declare i8* @rho_string_pad_leading(i8*, i64, i8*, i8*, i64, i64)
+declare i8* @rho_string_replace(i8*, i8*, i8*)
+declare i8* @rho_string_slice(i8*, i64, i64)
+declare i8* @rho_string_trim(i8*)
+declare i8* @rho_string_upcase(i8*)
+declare void @rho_atoms_init(i8**, i32)
+declare void @rho_enter(i8*, i8*, i64, i64)
+declare void @rho_fail(i8*, i8*, i8*, i64, i64) noreturn
+declare void @rho_finish()
+declare void @rho_inspect_char(i32)
+declare void @rho_inspect_str(i8*)
+declare void @rho_leave()
+declare void @rho_panic(i8*, i8*, i8*, i64, i64) noreturn
+declare void @rho_print_bool(i1 zeroext)
+declare void @rho_print_char(i32)
+declare void @rho_print_float(double)
+declare void @rho_print_int(i64)
+declare void @rho_print_newline()
+declare void @rho_print_str(i8*)
+declare void @rho_release(i8*)
+declare void @rho_retain(i8*)
+declare zeroext i1 @rho_string_contains(i8*, i8*)
+declare zeroext i1 @rho_string_starts_with(i8*, i8*)
+declare { i64, i1 } @llvm.sadd.with.overflow.i64(i64, i64)
+
+define internal i64 @rho.fn.check(i64 %n.arg) {
+entry:
+  %n.var = alloca i64
+  store i64 %n.arg, i64* %n.var
+  br label %bb0
+bb0:
+  %t1 = load i64, i64* %n.var
+  %t2 = icmp sgt i64 %t1, 3
+  br i1 %t2, label %bb3, label %bb4
+bb4:
+  br label %bb2
+bb2:
+  %t3 = load i64, i64* %n.var
+  br label %bb1
+bb1:
+  ret i64 %t3
+bb3:
+  call void @rho_panic(i8* getelementptr inbounds ({ i64, i64, [8 x i8] }, { i64, i64, [8 x i8] }* @rho.str.1, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [8 x i8] }, { i64, i64, [8 x i8] }* @rho.str.0, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [36 x i8] }, { i64, i64, [36 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 19, i64 9)
+  unreachable
+}
+
+define i32 @main() {
+entry:
+  %_23 = alloca i1
+  %_29 = alloca i64
+  %_42 = alloca i8*
+  store i8* null, i8** %_42
+  call void @rho_atoms_init(i8** getelementptr inbounds ([2 x i8*], [2 x i8*]* @rho.atoms, i64 0, i64 0), i32 2)
+  br label %bb0
+bb0:
+  %t1 = load i8*, i8** @rho.var.name
+  call void @rho_release(i8* %t1)
+  store i8* getelementptr inbounds ({ i64, i64, [12 x i8] }, { i64, i64, [12 x i8] }* @rho.str.3, i64 0, i32 2, i64 0), i8** @rho.var.name
+  %t2 = load i8*, i8** @rho.var.name
+  call void @rho_retain(i8* %t2)
+  call void @rho_print_str(i8* %t2)
+  call void @rho_release(i8* %t2)
+  %t3 = load i8*, i8** @rho.var.name
+  call void @rho_retain(i8* %t3)
+  %t4 = call i8* @rho_str_concat(i8* %t3, i8* getelementptr inbounds ({ i64, i64, [2 x i8] }, { i64, i64, [2 x i8] }* @rho.str.4, i64 0, i32 2, i64 0))
+  call void @rho_release(i8* %t3)
+  call void @rho_inspect_str(i8* %t4)
+  call void @rho_print_newline()
+  call void @rho_release(i8* %t4)
+  call void @rho_print_char(i32 955)
+  call void @rho_print_newline()
+  call void @rho_inspect_char(i32 9)
+  call void @rho_print_newline()
+  %t5 = fmul double 0x3FF8000000000000, 0x4000000000000000
+  call void @rho_print_float(double %t5)
+  call void @rho_print_newline()
+  %t6 = fadd double 0x3FB999999999999A, 0x3FC999999999999A
+  call void @rho_print_float(double %t6)
+  call void @rho_print_newline()
+  %t7 = call i32 @rho_str_compare(i8* getelementptr inbounds ({ i64, i64, [2 x i8] }, { i64, i64, [2 x i8] }* @rho.str.5, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [2 x i8] }, { i64, i64, [2 x i8] }* @rho.str.6, i64 0, i32 2, i64 0))
+  %t8 = icmp slt i32 %t7, 0
+  call void @rho_print_bool(i1 zeroext %t8)
+  call void @rho_print_newline()
+  %t9 = load i8*, i8** @rho.var.name
+  call void @rho_retain(i8* %t9)
+  %t10 = call i32 @rho_str_compare(i8* %t9, i8* getelementptr inbounds ({ i64, i64, [4 x i8] }, { i64, i64, [4 x i8] }* @rho.str.7, i64 0, i32 2, i64 0))
+  %t11 = icmp eq i32 %t10, 0
+  call void @rho_release(i8* %t9)
+  call void @rho_print_bool(i1 zeroext %t11)
+  call void @rho_print_newline()
+  %t12 = call i8* @rho_string_trim(i8* getelementptr inbounds ({ i64, i64, [10 x i8] }, { i64, i64, [10 x i8] }* @rho.str.8, i64 0, i32 2, i64 0))
+  %t13 = load i8*, i8** @rho.var.trimmed
+  call void @rho_release(i8* %t13)
+  store i8* %t12, i8** @rho.var.trimmed
+  %t14 = load i8*, i8** @rho.var.trimmed
+  call void @rho_retain(i8* %t14)
+  %t15 = call i8* @rho_string_upcase(i8* %t14)
+  call void @rho_release(i8* %t14)
+  %t16 = call i8* @rho_str_concat(i8* %t15, i8* getelementptr inbounds ({ i64, i64, [2 x i8] }, { i64, i64, [2 x i8] }* @rho.str.9, i64 0, i32 2, i64 0))
+  call void @rho_release(i8* %t15)
+  %t17 = load i8*, i8** @rho.var.trimmed
+  call void @rho_retain(i8* %t17)
+  %t18 = call i8* @rho_string_slice(i8* %t17, i64 -3, i64 2)
+  call void @rho_release(i8* %t17)
+  %t19 = call i8* @rho_str_concat(i8* %t16, i8* %t18)
+  call void @rho_release(i8* %t16)
+  call void @rho_release(i8* %t18)
+  call void @rho_print_str(i8* %t19)
+  call void @rho_print_newline()
+  call void @rho_release(i8* %t19)
+  %t20 = load i8*, i8** @rho.var.trimmed
+  call void @rho_retain(i8* %t20)
+  %t21 = call i64 @rho_string_length(i8* %t20)
+  call void @rho_release(i8* %t20)
+  %t22 = load i8*, i8** @rho.var.trimmed
+  call void @rho_retain(i8* %t22)
+  %t23 = call i64 @rho_string_byte_size(i8* %t22)
+  call void @rho_release(i8* %t22)
+  %t24 = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %t21, i64 %t23)
+  %t25 = extractvalue { i64, i1 } %t24, 1
+  br i1 %t25, label %fail26, label %ok27
+fail26:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.11, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.10, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [36 x i8] }, { i64, i64, [36 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 13, i64 32)
+  unreachable
+ok27:
+  %t28 = extractvalue { i64, i1 } %t24, 0
+  call void @rho_print_int(i64 %t28)
+  call void @rho_print_newline()
+  %t29 = call i8* @rho_string_replace(i8* getelementptr inbounds ({ i64, i64, [4 x i8] }, { i64, i64, [4 x i8] }* @rho.str.12, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [2 x i8] }, { i64, i64, [2 x i8] }* @rho.str.13, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [2 x i8] }, { i64, i64, [2 x i8] }* @rho.str.14, i64 0, i32 2, i64 0))
+  %t30 = call i8* @rho_string_pad_leading(i8* %t29, i64 5, i8* getelementptr inbounds ({ i64, i64, [2 x i8] }, { i64, i64, [2 x i8] }* @rho.str.15, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [36 x i8] }, { i64, i64, [36 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 14, i64 9)
+  call void @rho_release(i8* %t29)
+  call void @rho_print_str(i8* %t30)
+  call void @rho_print_newline()
+  call void @rho_release(i8* %t30)
+  %t31 = load i8*, i8** @rho.var.trimmed
+  call void @rho_retain(i8* %t31)
+  %t32 = call zeroext i1 @rho_string_starts_with(i8* %t31, i8* getelementptr inbounds ({ i64, i64, [3 x i8] }, { i64, i64, [3 x i8] }* @rho.str.16, i64 0, i32 2, i64 0))
+  call void @rho_release(i8* %t31)
+  store i1 %t32, i1* %_23
+  br i1 %t32, label %bb2, label %bb3
+bb2:
+  %t33 = load i8*, i8** @rho.var.name
+  call void @rho_retain(i8* %t33)
+  %t34 = call zeroext i1 @rho_string_contains(i8* %t33, i8* getelementptr inbounds ({ i64, i64, [2 x i8] }, { i64, i64, [2 x i8] }* @rho.str.17, i64 0, i32 2, i64 0))
+  call void @rho_release(i8* %t33)
+  %t35 = xor i1 %t34, true
+  store i1 %t35, i1* %_23
+  br label %bb3
+bb3:
+  %t36 = load i1, i1* %_23
+  call void @rho_print_bool(i1 zeroext %t36)
+  call void @rho_print_newline()
+  %t37 = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 1, i64 1)
+  %t38 = extractvalue { i64, i1 } %t37, 1
+  br i1 %t38, label %fail39, label %ok40
+fail39:
+  call void @rho_fail(i8* getelementptr inbounds ({ i64, i64, [1 x i8] }, { i64, i64, [1 x i8] }* @rho.str.11, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [17 x i8] }, { i64, i64, [17 x i8] }* @rho.str.10, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [36 x i8] }, { i64, i64, [36 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 16, i64 10)
+  unreachable
+ok40:
+  %t41 = extractvalue { i64, i1 } %t37, 0
+  store i64 %t41, i64* %_29
+  %t42 = load i64, i64* %_29
+  %t43 = icmp eq i64 %t42, 2
+  br i1 %t43, label %bb5, label %bb4
+bb4:
+  %t44 = load i64, i64* %_29
+  %t45 = call i8* @rho_str_from_int(i64 %t44)
+  %t46 = call i8* @rho_str_concat(i8* getelementptr inbounds ({ i64, i64, [2 x i8] }, { i64, i64, [2 x i8] }* @rho.str.18, i64 0, i32 2, i64 0), i8* %t45)
+  call void @rho_release(i8* %t45)
+  %t47 = call i8* @rho_str_concat(i8* %t46, i8* getelementptr inbounds ({ i64, i64, [5 x i8] }, { i64, i64, [5 x i8] }* @rho.str.19, i64 0, i32 2, i64 0))
+  call void @rho_release(i8* %t46)
+  %t48 = call i8* @rho_str_from_int(i64 2)
+  %t49 = call i8* @rho_str_concat(i8* %t47, i8* %t48)
+  call void @rho_release(i8* %t47)
+  call void @rho_release(i8* %t48)
+  %t50 = call i8* @rho_str_concat(i8* %t49, i8* getelementptr inbounds ({ i64, i64, [2 x i8] }, { i64, i64, [2 x i8] }* @rho.str.18, i64 0, i32 2, i64 0))
+  call void @rho_release(i8* %t49)
+  %t51 = call i8* @rho_str_concat(i8* getelementptr inbounds ({ i64, i64, [15 x i8] }, { i64, i64, [15 x i8] }* @rho.str.20, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [3 x i8] }, { i64, i64, [3 x i8] }* @rho.str.21, i64 0, i32 2, i64 0))
+  %t52 = call i8* @rho_str_concat(i8* %t51, i8* %t50)
+  call void @rho_release(i8* %t51)
+  call void @rho_release(i8* %t50)
+  call void @rho_panic(i8* getelementptr inbounds ({ i64, i64, [19 x i8] }, { i64, i64, [19 x i8] }* @rho.str.22, i64 0, i32 2, i64 0), i8* %t52, i8* getelementptr inbounds ({ i64, i64, [36 x i8] }, { i64, i64, [36 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 16, i64 1)
+  unreachable
+bb5:
+  call void @rho_enter(i8* getelementptr inbounds ({ i64, i64, [6 x i8] }, { i64, i64, [6 x i8] }* @rho.str.23, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [36 x i8] }, { i64, i64, [36 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 23, i64 9)
+  %t53 = call i64 @rho.fn.check(i64 3)
+  call void @rho_leave()
+  call void @rho_print_int(i64 %t53)
+  call void @rho_print_newline()
+  call void @rho_enter(i8* getelementptr inbounds ({ i64, i64, [6 x i8] }, { i64, i64, [6 x i8] }* @rho.str.23, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [36 x i8] }, { i64, i64, [36 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 24, i64 1)
+  %t54 = call i64 @rho.fn.check(i64 4)
+  call void @rho_leave()
+  %t55 = load i8*, i8** @rho.var.s
+  call void @rho_release(i8* %t55)
+  store i8* getelementptr inbounds ({ i64, i64, [3 x i8] }, { i64, i64, [3 x i8] }* @rho.str.24, i64 0, i32 2, i64 0), i8** @rho.var.s
+  %t56 = load i8*, i8** @rho.var.s
+  call void @rho_retain(i8* %t56)
+  %t57 = call i8* @rho_str_concat(i8* %t56, i8* getelementptr inbounds ({ i64, i64, [2 x i8] }, { i64, i64, [2 x i8] }* @rho.str.25, i64 0, i32 2, i64 0))
+  store i8* %t57, i8** %_42
+  call void @rho_release(i8* %t56)
+  %t58 = load i8*, i8** %_42
+  %t59 = call i32 @rho_str_compare(i8* %t58, i8* getelementptr inbounds ({ i64, i64, [4 x i8] }, { i64, i64, [4 x i8] }* @rho.str.26, i64 0, i32 2, i64 0))
+  %t60 = icmp eq i32 %t59, 0
+  br i1 %t60, label %bb7, label %bb6
+bb6:
+  %t61 = load i8*, i8** %_42
+  %t62 = call i8* @rho_str_inspect_str(i8* %t61)
+  %t63 = call i8* @rho_str_concat(i8* getelementptr inbounds ({ i64, i64, [2 x i8] }, { i64, i64, [2 x i8] }* @rho.str.18, i64 0, i32 2, i64 0), i8* %t62)
+  call void @rho_release(i8* %t62)
+  %t64 = call i8* @rho_str_concat(i8* %t63, i8* getelementptr inbounds ({ i64, i64, [5 x i8] }, { i64, i64, [5 x i8] }* @rho.str.19, i64 0, i32 2, i64 0))
+  call void @rho_release(i8* %t63)
+  %t65 = call i8* @rho_str_inspect_str(i8* getelementptr inbounds ({ i64, i64, [4 x i8] }, { i64, i64, [4 x i8] }* @rho.str.26, i64 0, i32 2, i64 0))
+  %t66 = call i8* @rho_str_concat(i8* %t64, i8* %t65)
+  call void @rho_release(i8* %t64)
+  call void @rho_release(i8* %t65)
+  %t67 = call i8* @rho_str_concat(i8* %t66, i8* getelementptr inbounds ({ i64, i64, [2 x i8] }, { i64, i64, [2 x i8] }* @rho.str.18, i64 0, i32 2, i64 0))
+  call void @rho_release(i8* %t66)
+  %t68 = call i8* @rho_str_concat(i8* getelementptr inbounds ({ i64, i64, [7 x i8] }, { i64, i64, [7 x i8] }* @rho.str.27, i64 0, i32 2, i64 0), i8* getelementptr inbounds ({ i64, i64, [3 x i8] }, { i64, i64, [3 x i8] }* @rho.str.21, i64 0, i32 2, i64 0))
+  %t69 = call i8* @rho_str_concat(i8* %t68, i8* %t67)
+  call void @rho_release(i8* %t68)
+  call void @rho_release(i8* %t67)
+  call void @rho_panic(i8* getelementptr inbounds ({ i64, i64, [19 x i8] }, { i64, i64, [19 x i8] }* @rho.str.22, i64 0, i32 2, i64 0), i8* %t69, i8* getelementptr inbounds ({ i64, i64, [36 x i8] }, { i64, i64, [36 x i8] }* @rho.str.2, i64 0, i32 2, i64 0), i64 26, i64 1)
+  unreachable
+bb7:
+  %t70 = load i8*, i8** %_42
+  call void @rho_release(i8* %t70)
+  store i8* null, i8** %_42
+  br label %bb1
+bb1:
+  %t71 = load i8*, i8** @rho.var.name
+  call void @rho_release(i8* %t71)
+  %t72 = load i8*, i8** @rho.var.trimmed
+  call void @rho_release(i8* %t72)
+  %t73 = load i8*, i8** @rho.var.s
+  call void @rho_release(i8* %t73)
+  %t74 = trunc i64 0 to i32
+  call void @rho_finish()
+  ret i32 %t74
+}
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::ffi::{c_char, CString};
+use std::sync::Mutex;
+
+use crate::string;
+
+/* The atoms of a program by their ids. The generated code starts with the table the compiler
+ * numbered, `@rho.atoms`, so the ids in the code and here agree, and atoms made at runtime are
+ * added after them.
+ */
+struct Atoms {
+    names: Vec<CString>, // their buffers don't move when the vector grows
+    ids: HashMap<String, u32>,
+}
+
+static ATOMS: Mutex<Option<Atoms>> = Mutex::new(None);
+
+fn with_atoms<T>(f: impl FnOnce(&mut Atoms) -> T) -> T {
+    let mut atoms = ATOMS.lock().unwrap_or_else(|e| e.into_inner());
+    let atoms = atoms.get_or_insert_with(|| Atoms {
+        names: vec![],
+        ids: HashMap::new(),
+    });
+    f(atoms)
+}
+
+fn intern(atoms: &mut Atoms, name: &str) -> u32 {
+    if let Some(id) = atoms.ids.get(name) {
+        return *id;
+    }
+    let id = atoms.names.len() as u32;
+    let end = name.find('\0').unwrap_or(name.len());
+    atoms
+        .names
+        .push(CString::new(&name[..end]).expect("The name should have no null left"));
+    atoms.ids.insert(name.to_string(), id);
+    id
+}
+
+// Interns the program's atoms in their order, called first thing in `main`
+#[no_mangle]
+pub unsafe extern "C" fn rho_atoms_init(names: *const *const c_char, count: i32) {
+    let names: Vec<String> = (0..count as usize).map(|i| string(*names.add(i))).collect();
+    with_atoms(|atoms| {
+        for name in names.iter() {
+            intern(atoms, name);
+        }
+    });
+}
+
+// The id of the atom of that name, ie. `:ok` for a result the runtime makes
+pub fn atom(name: &str) -> u32 {
+    with_atoms(|atoms| intern(atoms, name))
+}
+
+// `string_to_atom`, the id of an atom by its name, which is added to the table when it's new
+#[no_mangle]
+pub unsafe extern "C" fn rho_atom_intern(name: *const c_char) -> u32 {
+    let name = string(name);
+    with_atoms(|atoms| intern(atoms, &name))
+}
+
+// The name of an atom without its colon, null for an id that was never handed out. It belongs
+// to the atom table, it isn't a str object to retain or release
+#[no_mangle]
+pub extern "C" fn rho_atom_name(id: u32) -> *const c_char {
+    with_atoms(|atoms| match atoms.names.get(id as usize) {
+        Some(name) => name.as_ptr(),
+        None => std::ptr::null(),
+    })
+}
+
+#[test]
+fn test_atoms() {
+    let ok = CString::new("ok").unwrap();
+    let error = CString::new("error").unwrap();
+    let names = [ok.as_ptr(), error.as_ptr()];
+    unsafe {
+        rho_atoms_init(names.as_ptr(), 2);
+        assert_eq!(rho_atom_intern(error.as_ptr()), 1);
+        let id = rho_atom_intern(CString::new("made_at_runtime").unwrap().as_ptr());
+        assert!(id >= 2);
+        assert_eq!(string(rho_atom_name(id)), "made_at_runtime");
+        assert_eq!(string(rho_atom_name(0)), "ok");
+    }
+    assert!(rho_atom_name(u32::MAX).is_null());
+}
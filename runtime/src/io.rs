@@ -0,0 +1,134 @@
+use std::cell::RefCell;
+use std::ffi::c_char;
+use std::io::{BufRead, Write};
+
+use crate::atoms::rho_atom_name;
+use crate::text::{escape, inspect_float};
+use crate::values::{show, Shape};
+use crate::{new_string, string};
+
+/* `IO.print` and `IO.puts` write values like the interpreter's `Display` does, strs and chars as
+ * they are and everything else like `IO.inspect`, which quotes strs and chars. `IO.puts` is a
+ * print followed by `rho_print_newline`.
+ */
+
+thread_local! {
+    // what the tests print, see `capture`
+    static CAPTURED: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+}
+
+fn write(text: &str) {
+    let captured = CAPTURED.with(|c| match c.borrow_mut().as_mut() {
+        Some(output) => {
+            output.extend_from_slice(text.as_bytes());
+            true
+        }
+        None => false,
+    });
+    if !captured {
+        // a closed stdout isn't an error, like in the interpreter
+        let _ = std::io::stdout().write_all(text.as_bytes());
+    }
+}
+
+pub fn flush() {
+    let _ = std::io::stdout().flush();
+}
+
+#[no_mangle]
+pub extern "C" fn rho_print_int(i: i64) {
+    write(&i.to_string());
+}
+
+#[no_mangle]
+pub extern "C" fn rho_print_float(f: f64) {
+    write(&inspect_float(f));
+}
+
+#[no_mangle]
+pub extern "C" fn rho_print_bool(b: bool) {
+    write(if b { "true" } else { "false" });
+}
+
+#[no_mangle]
+pub extern "C" fn rho_print_char(c: u32) {
+    write(&char::from_u32(c).unwrap_or('\u{fffd}').to_string());
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_print_str(s: *const c_char) {
+    write(&string(s));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_print_atom(id: u32) {
+    write(&format!(":{}", string(rho_atom_name(id))));
+}
+
+#[no_mangle]
+pub extern "C" fn rho_print_newline() {
+    write("\n");
+}
+
+// `IO.inspect` of a char, ie. '\n'
+#[no_mangle]
+pub extern "C" fn rho_inspect_char(c: u32) {
+    let c = char::from_u32(c).unwrap_or('\u{fffd}');
+    write(&format!("'{}'", escape(&c.to_string(), '\'')));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_inspect_str(s: *const c_char) {
+    write(&format!("\"{}\"", escape(&string(s), '"')));
+}
+
+// A list, map or record, `descriptor` gives its type, see `values`. Its elements are shown like
+// `IO.inspect` does whether or not `inspect` is set
+#[no_mangle]
+pub unsafe extern "C" fn rho_print_value(value: u64, descriptor: *const c_char, inspect: bool) {
+    write(&show(value, &Shape::parse(&string(descriptor)), inspect));
+}
+
+// `IO.read_line`, a line of stdin without its line ending, empty at the end of stdin
+#[no_mangle]
+pub extern "C" fn rho_read_line() -> *mut c_char {
+    // a prompt written with `IO.print` shows before the program waits
+    flush();
+    let mut line = String::new();
+    let _ = std::io::stdin().lock().read_line(&mut line);
+    let line = line.strip_suffix('\n').unwrap_or(&line);
+    new_string(line.strip_suffix('\r').unwrap_or(line))
+}
+
+// What `f` prints
+#[cfg(test)]
+fn capture(f: impl FnOnce()) -> String {
+    CAPTURED.with(|c| *c.borrow_mut() = Some(vec![]));
+    f();
+    let output = CAPTURED.with(|c| c.borrow_mut().take()).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn test_print() {
+    let output = capture(|| unsafe {
+        rho_print_int(-7);
+        rho_print_newline();
+        rho_print_float(2.0);
+        rho_print_float(0.1 + 0.2);
+        rho_print_bool(true);
+        rho_print_char('é' as u32);
+        rho_print_str(c"tab\there".as_ptr());
+        rho_print_newline();
+        rho_inspect_char('\'' as u32);
+        rho_inspect_str(c"say \"hi\"\n".as_ptr());
+        let list = crate::lists::rho_list_new(1, false);
+        crate::lists::rho_list_push(list, 'x' as u64);
+        rho_print_value(list as u64, c"lc".as_ptr(), false);
+        crate::memory::rho_release(list as *mut u8);
+    });
+    assert_eq!(
+        output,
+        "-7\n2.00.30000000000000004trueétab\there\n'\\''\"say \\\"hi\\\"\\n\"['x']"
+    );
+}
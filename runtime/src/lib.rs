@@ -0,0 +1,244 @@
+#![allow(clippy::missing_safety_doc)]
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr};
+use std::io::Write;
+
+pub mod atoms;
+pub mod io;
+pub mod lists;
+pub mod maps;
+pub mod memory;
+pub mod streams;
+pub mod strings;
+pub mod text;
+pub mod values;
+
+/* The runtime of compiled rho programs, a static library `rho build` links into every executable.
+ * Its functions are `extern "C"` and named `rho_*`, the IR `codegen` generates declares and calls
+ * them. Which values they take is part of that contract and changes with the code generator:
+ *
+ *     int    i64           float  double        bool   i1 (zeroext)
+ *     char   i32 (u32)     atom   i32 (its id)  str    i8*, a null terminated UTF-8 string
+ *     list   RhoList*      map    RhoMap*       tuple, struct and result   a record, u8*
+ *
+ * The elements of lists, maps and records are u64 bit patterns, an object's is its pointer, and
+ * the functions that look inside them are told their types by a descriptor, see `values`.
+ *
+ * Their pointers come from the generated code, which only passes ones the runtime handed out or
+ * string constants, so none of them checks what it's given. strs, lists, maps and records are
+ * reference counted objects, see `memory`, and a function's object arguments are only borrowed
+ * unless it says it takes them over.
+ *
+ * The text functions are plain Rust, the compiler's interpreter uses them too.
+ */
+
+// Deep enough for any sane recursion, the interpreter stops at the same depth
+pub const MAX_CALL_DEPTH: usize = 10_000;
+// The frames a stack trace shows, the rest of a deep recursion is summed up
+pub const TRACE_FRAMES: usize = 20;
+
+thread_local! {
+    // the rho functions being called and the line each was called from, outermost first
+    static FRAMES: RefCell<Vec<(*const c_char, i64)>> = const { RefCell::new(vec![]) };
+}
+
+/* The generated code enters a frame before each call to a rho function, with where the call is,
+ * and leaves it after, so a failure can show the calls it happened in. A call that would go
+ * deeper than `MAX_CALL_DEPTH` fails there, like in the interpreter.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn rho_enter(
+    function: *const c_char,
+    file: *const c_char,
+    line: i64,
+    col: i64,
+) {
+    if FRAMES.with(|f| f.borrow().len()) >= MAX_CALL_DEPTH {
+        let message = format!("stack overflow, more than {} nested calls", MAX_CALL_DEPTH);
+        fail("", &message, &string(file), line, col);
+    }
+    FRAMES.with(|f| f.borrow_mut().push((function, line)));
+}
+
+#[no_mangle]
+pub extern "C" fn rho_leave() {
+    FRAMES.with(|f| f.borrow_mut().pop());
+}
+
+/* Ends the program with a runtime error, written like the interpreter writes them:
+ *
+ *     error: panic: too big
+ *      --> main.rho:9:9
+ *     stack trace:
+ *         at check (main.rho:9)
+ *         at the top level (main.rho:12)
+ *
+ * `prefix` comes before the message, ie. "panic: " or "assertion failed: ". The stack trace is
+ * only there when the failure is inside a call, see `rho_panic` for the ones that always have it.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn rho_fail(
+    prefix: *const c_char,
+    message: *const c_char,
+    file: *const c_char,
+    line: i64,
+    col: i64,
+) -> ! {
+    fail(&string(prefix), &string(message), &string(file), line, col)
+}
+
+// `rho_fail` for `panic` and `assert`, which show the stack trace at the top level too
+#[no_mangle]
+pub unsafe extern "C" fn rho_panic(
+    prefix: *const c_char,
+    message: *const c_char,
+    file: *const c_char,
+    line: i64,
+    col: i64,
+) -> ! {
+    let file = string(file);
+    let trace = stack_trace(&frames(), line, true);
+    exit_with(&failure(
+        &string(prefix),
+        &string(message),
+        &file,
+        line,
+        col,
+        &trace,
+    ))
+}
+
+// `rho_fail` for the runtime's own errors
+fn fail(prefix: &str, message: &str, file: &str, line: i64, col: i64) -> ! {
+    let trace = stack_trace(&frames(), line, false);
+    exit_with(&failure(prefix, message, file, line, col, &trace))
+}
+
+fn exit_with(error: &str) -> ! {
+    io::flush();
+    let _ = std::io::stderr().write_all(error.as_bytes());
+    std::process::exit(1)
+}
+
+fn frames() -> Vec<(String, i64)> {
+    FRAMES.with(|f| {
+        (f.borrow().iter())
+            .map(|(function, line)| (unsafe { string(*function) }, *line))
+            .collect()
+    })
+}
+
+/* The functions being called, innermost first, with the line each one is at, like
+ * `interpreter::stack_trace`. `main` is called from line 0, there's no top level below it then.
+ * The frames left out of a deep trace are one entry at line 0.
+ */
+fn stack_trace(frames: &[(String, i64)], at: i64, always: bool) -> Vec<(String, i64)> {
+    if frames.is_empty() && !always {
+        return vec![];
+    }
+    let mut trace = vec![];
+    let mut line = at;
+    for (function, call) in frames.iter().rev() {
+        trace.push((function.to_owned(), line));
+        line = *call;
+    }
+    if line != 0 {
+        trace.push(("the top level".to_string(), line));
+    }
+    if trace.len() > TRACE_FRAMES {
+        let hidden = trace.len() - TRACE_FRAMES;
+        trace.truncate(TRACE_FRAMES);
+        trace.push((format!("{} more calls", hidden), 0));
+    }
+    trace
+}
+
+fn failure(
+    prefix: &str,
+    message: &str,
+    file: &str,
+    line: i64,
+    col: i64,
+    trace: &[(String, i64)],
+) -> String {
+    let gutter = " ".repeat(line.to_string().len());
+    let mut out = format!(
+        "error: {}{}\n{} --> {}:{}:{}\n",
+        prefix, message, gutter, file, line, col
+    );
+    if !trace.is_empty() {
+        out += "stack trace:\n";
+    }
+    for (function, line) in trace.iter() {
+        match line {
+            0 => out += &format!("    ... {}\n", function),
+            line => out += &format!("    at {} ({}:{})\n", function, file, line),
+        }
+    }
+    out
+}
+
+/* Called when `main` returns, what the program printed without a newline is still buffered. By
+ * then the generated code released everything, with RHO_LEAK_CHECK set what it didn't is reported.
+ */
+#[no_mangle]
+pub extern "C" fn rho_finish() {
+    io::flush();
+    if std::env::var_os("RHO_LEAK_CHECK").is_some() {
+        unsafe { memory::rho_collect_cycles() };
+        if let Some(report) = leaks(memory::rho_live_objects()) {
+            let _ = std::io::stderr().write_all(report.as_bytes());
+        }
+    }
+}
+
+fn leaks(live: i64) -> Option<String> {
+    (live != 0).then(|| format!("rho: {} objects still live at exit\n", live))
+}
+
+// A string argument, the generated code only passes valid UTF-8
+unsafe fn string(s: *const c_char) -> String {
+    if s.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(s).to_string_lossy().into_owned()
+}
+
+// A str object for the generated code, a rho string with a null in it ends there
+fn new_string(s: &str) -> *mut c_char {
+    let end = s.find('\0').unwrap_or(s.len());
+    // zeroed, so it's null terminated
+    let object = memory::allocate(memory::RHO_KIND_STR, end + 1);
+    unsafe { std::ptr::copy_nonoverlapping(s.as_ptr(), object, end) };
+    object as *mut c_char
+}
+
+#[test]
+fn test_failure() {
+    assert_eq!(
+        failure("panic: ", "too big", "main.rho", 9, 5, &[]),
+        "error: panic: too big\n  --> main.rho:9:5\n"
+    );
+    let frames = [("main".to_string(), 0), ("f".to_string(), 12)];
+    let trace = stack_trace(&frames, 14, false);
+    assert_eq!(
+        failure("", "integer overflow", "a.rho", 14, 3, &trace),
+        "error: integer overflow\n   --> a.rho:14:3\nstack trace:\n    at f (a.rho:14)\n    at main (a.rho:12)\n"
+    );
+    assert_eq!(stack_trace(&[], 3, false), []);
+    assert_eq!(
+        stack_trace(&[], 3, true),
+        [("the top level".to_string(), 3)]
+    );
+    let deep = vec![("f".to_string(), 2); 30];
+    let trace = stack_trace(&deep, 2, false);
+    assert_eq!(trace.len(), TRACE_FRAMES + 1);
+    assert_eq!(trace[TRACE_FRAMES], ("11 more calls".to_string(), 0));
+    assert_eq!(leaks(0), None);
+    assert_eq!(leaks(2).unwrap(), "rho: 2 objects still live at exit\n");
+    let s = new_string("a\0b");
+    assert_eq!(unsafe { string(s) }, "a");
+    unsafe { memory::rho_release(s as *mut u8) };
+    assert_eq!(unsafe { string(std::ptr::null()) }, "");
+}
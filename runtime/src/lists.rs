@@ -0,0 +1,191 @@
+use std::ffi::c_char;
+
+use crate::memory::{self, rho_release, rho_retain, rho_unique, RHO_KIND_LIST};
+use crate::rho_fail;
+
+/* A rho list, its elements are the bits of their values, ie. an int as is, a float by
+ * `f64::to_bits` and a str by its pointer. `objects` is set when they're objects, which the
+ * list holds a reference to.
+ */
+pub struct RhoList {
+    objects: bool,
+    items: Vec<u64>,
+}
+
+fn new_list(objects: bool, items: Vec<u64>) -> *mut RhoList {
+    let list = memory::allocate(RHO_KIND_LIST, std::mem::size_of::<RhoList>()) as *mut RhoList;
+    unsafe { std::ptr::write(list, RhoList { objects, items }) };
+    list
+}
+
+// The objects a list holds, for `memory`
+pub unsafe fn objects(list: *const RhoList) -> Vec<u64> {
+    match (*list).objects {
+        true => (*list).items.clone(),
+        false => vec![],
+    }
+}
+
+// The elements of a list, ie. to show it
+pub unsafe fn items<'a>(list: *const RhoList) -> &'a [u64] {
+    &(*list).items
+}
+
+// The element at `i` as a new reference, None past the end, ie. for a stream going through it
+pub unsafe fn element(list: *const RhoList, i: usize) -> Option<u64> {
+    let item = *items(list).get(i)?;
+    if (*list).objects {
+        rho_retain(item as *mut u8);
+    }
+    Some(item)
+}
+
+// A list with the same elements, for `rho_unique`
+pub unsafe fn copy(list: *const RhoList) -> *mut RhoList {
+    new_list((*list).objects, (*list).items.clone())
+}
+
+// An empty list with room for `capacity` elements, ie. a list literal's length
+#[no_mangle]
+pub extern "C" fn rho_list_new(capacity: i64, objects: bool) -> *mut RhoList {
+    new_list(objects, Vec::with_capacity(capacity.max(0) as usize))
+}
+
+// The list takes over the reference to `value`
+#[no_mangle]
+pub unsafe extern "C" fn rho_list_push(list: *mut RhoList, value: u64) {
+    (*list).items.push(value);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_list_len(list: *const RhoList) -> i64 {
+    (*list).items.len() as i64
+}
+
+// `xs[i]`, failing at `line` and `col` of `file` when it's out of bounds. The list lends its
+// reference to an object, retain it to keep it
+#[no_mangle]
+pub unsafe extern "C" fn rho_list_get(
+    list: *const RhoList,
+    index: i64,
+    file: *const c_char,
+    line: i64,
+    col: i64,
+) -> u64 {
+    let items = &(*list).items;
+    let i = check_index(index, items.len(), file, line, col);
+    items[i]
+}
+
+// `xs[i] = value`, the list takes over the reference to `value` and releases the old one
+#[no_mangle]
+pub unsafe extern "C" fn rho_list_set(
+    list: *mut RhoList,
+    index: i64,
+    value: u64,
+    file: *const c_char,
+    line: i64,
+    col: i64,
+) {
+    let items = &mut (*list).items;
+    let i = check_index(index, items.len(), file, line, col);
+    let old = std::mem::replace(&mut items[i], value);
+    if (*list).objects {
+        rho_release(old as *mut u8);
+    }
+}
+
+/* The element of a list of lists, maps or records that an assignment to `xs[i][j]` or `xs[i].x`
+ * changes, made unshared first like `rho_unique` does. The list lends its reference to it.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn rho_list_unique_at(
+    list: *mut RhoList,
+    index: i64,
+    file: *const c_char,
+    line: i64,
+    col: i64,
+) -> u64 {
+    let items = &mut (*list).items;
+    let i = check_index(index, items.len(), file, line, col);
+    items[i] = rho_unique(items[i] as *mut u8) as u64;
+    items[i]
+}
+
+// `a ++ b`, a new list. One of them can be an empty list whose elements had no type to tell
+// whether they're objects
+#[no_mangle]
+pub unsafe extern "C" fn rho_list_concat(a: *const RhoList, b: *const RhoList) -> *mut RhoList {
+    let mut items = (*a).items.clone();
+    items.extend_from_slice(&(*b).items);
+    let objects = (*a).objects || (*b).objects;
+    if objects {
+        for item in items.iter() {
+            rho_retain(*item as *mut u8);
+        }
+    }
+    new_list(objects, items)
+}
+
+unsafe fn check_index(index: i64, len: usize, file: *const c_char, line: i64, col: i64) -> usize {
+    if index >= 0 && (index as usize) < len {
+        return index as usize;
+    }
+    // the interpreter's error for it
+    let message = format!("index {} is out of bounds for a length of {}", index, len);
+    let message = crate::new_string(&message);
+    rho_fail(c"".as_ptr(), message, file, line, col)
+}
+
+#[test]
+fn test_lists() {
+    unsafe {
+        let a = rho_list_new(2, false);
+        rho_list_push(a, 1);
+        rho_list_push(a, 2.5f64.to_bits());
+        let b = rho_list_new(0, false);
+        rho_list_push(b, 3);
+        let ab = rho_list_concat(a, b);
+        assert_eq!(rho_list_len(ab), 3);
+        assert_eq!(rho_list_len(a), 2);
+        let file = c"a.rho".as_ptr();
+        assert_eq!(f64::from_bits(rho_list_get(ab, 1, file, 1, 1)), 2.5);
+        rho_list_set(ab, 2, 4, file, 1, 1);
+        assert_eq!(rho_list_get(ab, 2, file, 1, 1), 4);
+        assert_eq!(rho_list_get(b, 0, file, 1, 1), 3);
+
+        // both lists hold the strs after a concatenation
+        let before = memory::rho_live_objects();
+        let strs = rho_list_new(1, true);
+        rho_list_push(strs, crate::new_string("a") as u64);
+        let twice = rho_list_concat(strs, strs);
+        rho_list_set(twice, 0, crate::new_string("b") as u64, file, 1, 1);
+        rho_release(strs as *mut u8);
+        assert_eq!(
+            crate::string(rho_list_get(twice, 1, file, 1, 1) as *const c_char),
+            "a"
+        );
+        rho_release(twice as *mut u8);
+        assert_eq!(memory::rho_live_objects(), before);
+
+        // an assignment to an element of a shared one changes a copy of it
+        let inner = rho_list_new(1, false);
+        rho_list_push(inner, 1);
+        let outer = rho_list_new(1, true);
+        rho_list_push(outer, inner as u64);
+        rho_retain(inner as *mut u8);
+        let unshared = rho_list_unique_at(outer, 0, file, 1, 1) as *mut RhoList;
+        assert_ne!(unshared, inner);
+        rho_list_set(unshared, 0, 2, file, 1, 1);
+        assert_eq!(rho_list_get(inner, 0, file, 1, 1), 1);
+        assert_eq!(rho_list_unique_at(outer, 0, file, 1, 1), unshared as u64);
+        rho_release(inner as *mut u8);
+        let empty = rho_list_new(0, false);
+        let both = rho_list_concat(empty, outer);
+        rho_release(outer as *mut u8);
+        rho_release(empty as *mut u8);
+        rho_release(both as *mut u8);
+        memory::rho_collect_cycles();
+        assert_eq!(memory::rho_live_objects(), before);
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+use std::ffi::c_char;
+
+use crate::lists::{rho_list_new, rho_list_push, RhoList};
+use crate::memory::{self, rho_record_new, rho_release, rho_retain, rho_unique, RHO_KIND_MAP};
+use crate::values::{show, Shape};
+use crate::{new_string, rho_fail, string};
+
+// How a map compares its keys, by their bits or, for strs, by the strings they point to
+pub const RHO_KEYS_BITS: i32 = 0;
+pub const RHO_KEYS_STR: i32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Bits(u64),
+    Str(String),
+}
+
+/* A rho map, like `RhoList` its keys and values are bits. It iterates in insertion order like
+ * the interpreter's maps do, `index` finds an entry by its key. It holds a reference to its str
+ * keys, and to its values when `objects` is set.
+ */
+pub struct RhoMap {
+    str_keys: bool,
+    objects: bool,
+    entries: Vec<(u64, u64)>,
+    index: HashMap<Key, usize>,
+}
+
+impl RhoMap {
+    unsafe fn key(&self, key: u64) -> Key {
+        match self.str_keys {
+            true => Key::Str(string(key as *const c_char)),
+            false => Key::Bits(key),
+        }
+    }
+}
+
+// The objects a map holds, for `memory`
+pub unsafe fn objects(map: *const RhoMap) -> Vec<u64> {
+    let map = &*map;
+    let keys = map.entries.iter().filter(|_| map.str_keys).map(|e| e.0);
+    let values = map.entries.iter().filter(|_| map.objects).map(|e| e.1);
+    keys.chain(values).collect()
+}
+
+fn new_map(contents: RhoMap) -> *mut RhoMap {
+    let map = memory::allocate(RHO_KIND_MAP, std::mem::size_of::<RhoMap>()) as *mut RhoMap;
+    unsafe { std::ptr::write(map, contents) };
+    map
+}
+
+// A map with the same entries, for `rho_unique`
+pub unsafe fn copy(map: *const RhoMap) -> *mut RhoMap {
+    let map = &*map;
+    new_map(RhoMap {
+        str_keys: map.str_keys,
+        objects: map.objects,
+        entries: map.entries.clone(),
+        index: map.index.clone(),
+    })
+}
+
+// An empty map, `keys` is `RHO_KEYS_BITS` or `RHO_KEYS_STR`
+#[no_mangle]
+pub extern "C" fn rho_map_new(keys: i32, objects: bool) -> *mut RhoMap {
+    new_map(RhoMap {
+        str_keys: keys == RHO_KEYS_STR,
+        objects,
+        entries: vec![],
+        index: HashMap::new(),
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_map_len(map: *const RhoMap) -> i64 {
+    (*map).entries.len() as i64
+}
+
+/* Adds an entry or changes the value of the key's one, which keeps its place. The map takes over
+ * the references to `key` and `value`, and releases the value it had.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn rho_map_put(map: *mut RhoMap, key: u64, value: u64) {
+    let map = &mut *map;
+    let k = map.key(key);
+    match map.index.get(&k) {
+        Some(i) => {
+            let old = std::mem::replace(&mut map.entries[*i].1, value);
+            if map.objects {
+                rho_release(old as *mut u8);
+            }
+            // it keeps the equal key it has
+            if map.str_keys {
+                rho_release(key as *mut u8);
+            }
+        }
+        None => {
+            map.index.insert(k, map.entries.len());
+            map.entries.push((key, value));
+        }
+    }
+}
+
+// Whether the map has the key, its value is lent to `value` when it does
+#[no_mangle]
+pub unsafe extern "C" fn rho_map_get(map: *const RhoMap, key: u64, value: *mut u64) -> bool {
+    let map = &*map;
+    match map.index.get(&map.key(key)) {
+        Some(i) => {
+            *value = map.entries[*i].1;
+            true
+        }
+        None => false,
+    }
+}
+
+/* `map[key]`, failing at `line` and `col` of `file` like the interpreter when it doesn't have
+ * the key, which `descriptor` is the type of, see `values`. The map lends its reference.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn rho_map_at(
+    map: *const RhoMap,
+    key: u64,
+    descriptor: *const c_char,
+    file: *const c_char,
+    line: i64,
+    col: i64,
+) -> u64 {
+    let mut value = 0;
+    if !rho_map_get(map, key, &mut value) {
+        let key = show(key, &Shape::parse(&string(descriptor)), false);
+        let message = new_string(&format!("key `{}` is not in the map", key));
+        rho_fail(c"".as_ptr(), message, file, line, col);
+    }
+    value
+}
+
+/* The value an assignment to `map[key][i]` or `map[key].x` changes, made unshared first like
+ * `rho_unique` does. The interpreter makes a missing one unit, which can't be assigned to, so
+ * this fails with its `message` then. The map lends its reference.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn rho_map_unique_at(
+    map: *mut RhoMap,
+    key: u64,
+    message: *const c_char,
+    file: *const c_char,
+    line: i64,
+    col: i64,
+) -> u64 {
+    let map = &mut *map;
+    let Some(i) = map.index.get(&map.key(key)).copied() else {
+        rho_fail(c"".as_ptr(), message, file, line, col);
+    };
+    map.entries[i].1 = rho_unique(map.entries[i].1 as *mut u8) as u64;
+    map.entries[i].1
+}
+
+// `Map.keys`, a new list
+#[no_mangle]
+pub unsafe extern "C" fn rho_map_keys(map: *const RhoMap) -> *mut RhoList {
+    let map = &*map;
+    let keys = map.entries.iter().map(|e| e.0);
+    elements(keys, map.str_keys)
+}
+
+// `Map.values`, a new list
+#[no_mangle]
+pub unsafe extern "C" fn rho_map_values(map: *const RhoMap) -> *mut RhoList {
+    let map = &*map;
+    let values = map.entries.iter().map(|e| e.1);
+    elements(values, map.objects)
+}
+
+// `Map.to_list`, a new list of `{key, value}` tuples, which `for` iterates over a map with
+#[no_mangle]
+pub unsafe extern "C" fn rho_map_to_list(map: *const RhoMap) -> *mut RhoList {
+    let map = &*map;
+    let list = rho_list_new(map.entries.len() as i64, true);
+    for (key, value) in map.entries.iter() {
+        let tuple = rho_record_new(2, map.str_keys as u64 | (map.objects as u64) << 1);
+        *(tuple as *mut u64).add(1) = retained(*key, map.str_keys);
+        *(tuple as *mut u64).add(2) = retained(*value, map.objects);
+        rho_list_push(list, tuple as u64);
+    }
+    list
+}
+
+// `Map.merge`, a new map where the second map's values win and its new keys go after the first's
+#[no_mangle]
+pub unsafe extern "C" fn rho_map_merge(a: *const RhoMap, b: *const RhoMap) -> *mut RhoMap {
+    let merged = copy(a);
+    for (key, value) in (*a).entries.iter() {
+        retained(*key, (*a).str_keys);
+        retained(*value, (*a).objects);
+    }
+    for (key, value) in (*b).entries.iter() {
+        let key = retained(*key, (*b).str_keys);
+        rho_map_put(merged, key, retained(*value, (*b).objects));
+    }
+    merged
+}
+
+unsafe fn elements(bits: impl Iterator<Item = u64>, objects: bool) -> *mut RhoList {
+    let list = rho_list_new(0, objects);
+    for b in bits {
+        rho_list_push(list, retained(b, objects));
+    }
+    list
+}
+
+unsafe fn retained(bits: u64, object: bool) -> u64 {
+    if object {
+        rho_retain(bits as *mut u8);
+    }
+    bits
+}
+
+// Whether the map had the key, the entries after it move up one place
+#[no_mangle]
+pub unsafe extern "C" fn rho_map_remove(map: *mut RhoMap, key: u64) -> bool {
+    let map = &mut *map;
+    let Some(i) = map.index.remove(&map.key(key)) else {
+        return false;
+    };
+    let (key, value) = map.entries.remove(i);
+    if map.str_keys {
+        rho_release(key as *mut u8);
+    }
+    if map.objects {
+        rho_release(value as *mut u8);
+    }
+    for position in map.index.values_mut() {
+        if *position > i {
+            *position -= 1;
+        }
+    }
+    true
+}
+
+// The key of the `i`th entry, for iterating over a map with `rho_map_len`
+#[no_mangle]
+pub unsafe extern "C" fn rho_map_key_at(map: *const RhoMap, i: i64) -> u64 {
+    (&(*map).entries)[i as usize].0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_map_value_at(map: *const RhoMap, i: i64) -> u64 {
+    (&(*map).entries)[i as usize].1
+}
+
+#[test]
+fn test_maps() {
+    unsafe {
+        let before = memory::rho_live_objects();
+        let map = rho_map_new(RHO_KEYS_STR, false);
+        // equal strings at different addresses are the same key
+        let a = crate::new_string("a") as u64;
+        let b = crate::new_string("b") as u64;
+        memory::rho_retain(b as *mut u8);
+        rho_map_put(map, b, 1);
+        rho_map_put(map, a, 2);
+        rho_map_put(map, crate::new_string("b") as u64, 3);
+        assert_eq!(rho_map_len(map), 2);
+        let mut value = 0;
+        assert!(rho_map_get(map, b, &mut value));
+        assert_eq!(value, 3);
+        assert_eq!(string(rho_map_key_at(map, 0) as *const c_char), "b");
+        assert!(rho_map_remove(map, b));
+        assert!(!rho_map_remove(map, b));
+        assert_eq!(rho_map_value_at(map, 0), 2);
+        assert!(!rho_map_get(map, b, &mut value));
+        rho_release(b as *mut u8);
+        rho_release(map as *mut u8);
+        assert_eq!(memory::rho_live_objects(), before);
+
+        let ints = rho_map_new(RHO_KEYS_BITS, true);
+        rho_map_put(ints, 7, crate::new_string("seven") as u64);
+        assert!(rho_map_get(ints, 7, &mut value));
+        assert_eq!(string(value as *const c_char), "seven");
+        rho_map_put(ints, 7, crate::new_string("7") as u64);
+        let file = c"a.rho".as_ptr();
+        assert!(rho_map_get(ints, 7, &mut value));
+        assert_eq!(rho_map_at(ints, 7, c"i".as_ptr(), file, 1, 1), value);
+        let entries = rho_map_to_list(ints);
+        let values = rho_map_values(ints);
+        let keys = rho_map_keys(ints);
+        assert_eq!(
+            show(entries as u64, &Shape::parse("lt2;is"), true),
+            "[{7, \"7\"}]"
+        );
+        assert_eq!(show(values as u64, &Shape::parse("ls"), true), "[\"7\"]");
+        assert_eq!(show(keys as u64, &Shape::parse("li"), true), "[7]");
+        let other = rho_map_new(RHO_KEYS_BITS, true);
+        rho_map_put(other, 8, crate::new_string("8") as u64);
+        rho_map_put(other, 7, crate::new_string("seven") as u64);
+        let merged = rho_map_merge(ints, other);
+        rho_release(other as *mut u8);
+        assert_eq!(
+            show(merged as u64, &Shape::parse("mis"), true),
+            "{7: \"seven\", 8: \"8\"}"
+        );
+        rho_release(merged as *mut u8);
+        for list in [entries, values, keys] {
+            rho_release(list as *mut u8);
+        }
+        rho_release(ints as *mut u8);
+        assert_eq!(memory::rho_live_objects(), before);
+    }
+}
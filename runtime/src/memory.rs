@@ -0,0 +1,423 @@
+use std::alloc::Layout;
+use std::cell::{Cell, RefCell};
+
+use crate::lists::{self, RhoList};
+use crate::maps::{self, RhoMap};
+use crate::streams::RhoCursor;
+
+/* The runtime's objects, strs, lists, maps, records and cursors, are reference counted. Each one starts
+ * with a header the generated code never sees, the pointers it's handed point just past it:
+ *
+ *     count  i64   the references to it, -1 for a constant that's never freed
+ *     info   u64   its kind, bits 0-7, its color, 8-15, whether it's buffered, 16, and the
+ *                  size of what follows, 32-63
+ *
+ * `rho_retain` and `rho_release` are inserted by the code generator, a function that makes an
+ * object gives its caller the reference to it. A count can't free a cycle, ie. a closure whose
+ * record holds itself, so the containers whose count drops without reaching 0 are buffered as
+ * the possible roots of one, and `rho_collect_cycles` frees the garbage among them by trial
+ * deletion, the synchronous collector of Bacon and Rajan's "Concurrent Cycle Collection in
+ * Reference Counted Systems". A str can't hold anything and a stream's cursor is only held by its
+ * loop, so neither is ever buffered, what a cursor holds just looks in use until it's freed. A
+ * collection runs whenever enough of them are buffered, like the interpreter's `gc` does for its
+ * scopes.
+ */
+
+pub const RHO_KIND_STR: u64 = 1;
+pub const RHO_KIND_LIST: u64 = 2;
+pub const RHO_KIND_MAP: u64 = 3;
+pub const RHO_KIND_RECORD: u64 = 4;
+// a `for` loop's pass over a stream, see `streams::RhoCursor`
+pub const RHO_KIND_CURSOR: u64 = 5;
+
+const HEADER: usize = std::mem::size_of::<Header>();
+
+// The possible roots buffered before `rho_release` collects them, so short programs never pay for it
+const FIRST_COLLECTION: usize = 4096;
+
+#[repr(C)]
+struct Header {
+    count: i64,
+    info: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Color {
+    Black,  // in use, or free
+    Gray,   // possibly part of a cycle
+    White,  // garbage
+    Purple, // a possible root of a cycle
+}
+
+impl Header {
+    fn kind(&self) -> u64 {
+        self.info & 0xff
+    }
+
+    fn size(&self) -> usize {
+        (self.info >> 32) as usize
+    }
+
+    fn color(&self) -> Color {
+        match (self.info >> 8) & 0xff {
+            0 => Color::Black,
+            1 => Color::Gray,
+            2 => Color::White,
+            _ => Color::Purple,
+        }
+    }
+
+    fn set_color(&mut self, color: Color) {
+        self.info = (self.info & !0xff00) | (color as u64) << 8;
+    }
+
+    fn buffered(&self) -> bool {
+        self.info & 1 << 16 != 0
+    }
+
+    fn set_buffered(&mut self, buffered: bool) {
+        self.info = (self.info & !(1 << 16)) | (buffered as u64) << 16;
+    }
+}
+
+thread_local! {
+    static LIVE: Cell<i64> = const { Cell::new(0) };
+    static ROOTS: RefCell<Vec<*mut Header>> = const { RefCell::new(vec![]) };
+}
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(HEADER + size, 8).expect("A rho object should have a valid size")
+}
+
+// A zeroed object of `size` bytes with a count of 1
+pub fn allocate(kind: u64, size: usize) -> *mut u8 {
+    let size = u32::try_from(size).expect("A rho object should be smaller than 4GB") as usize;
+    let layout = layout(size);
+    let memory = unsafe { std::alloc::alloc_zeroed(layout) };
+    if memory.is_null() {
+        std::alloc::handle_alloc_error(layout);
+    }
+    let header = memory as *mut Header;
+    unsafe {
+        (*header).count = 1;
+        (*header).info = kind | (size as u64) << 32;
+    }
+    LIVE.with(|live| live.set(live.get() + 1));
+    unsafe { memory.add(HEADER) }
+}
+
+unsafe fn header(object: *const u8) -> *mut Header {
+    object.sub(HEADER) as *mut Header
+}
+
+// The objects `h` holds a reference to, constants left out
+unsafe fn children(h: *mut Header) -> Vec<*mut Header> {
+    let object = (h as *mut u8).add(HEADER);
+    let pointers = match (*h).kind() {
+        RHO_KIND_LIST => lists::objects(object as *const RhoList),
+        RHO_KIND_MAP => maps::objects(object as *const RhoMap),
+        RHO_KIND_RECORD => record_objects(object as *const u64, (*h).size()),
+        _ => vec![],
+    };
+    pointers
+        .into_iter()
+        .filter(|p| *p != 0)
+        .map(|p| header(p as *const u8))
+        .filter(|child| (**child).count >= 0)
+        .collect()
+}
+
+// Gives back the memory of `h` without releasing what it holds
+unsafe fn free(h: *mut Header) {
+    let object = (h as *mut u8).add(HEADER);
+    match (*h).kind() {
+        RHO_KIND_LIST => std::ptr::drop_in_place(object as *mut RhoList),
+        RHO_KIND_MAP => std::ptr::drop_in_place(object as *mut RhoMap),
+        RHO_KIND_CURSOR => std::ptr::drop_in_place(object as *mut RhoCursor),
+        _ => {}
+    }
+    std::alloc::dealloc(h as *mut u8, layout((*h).size()));
+    LIVE.with(|live| live.set(live.get() - 1));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_retain(object: *mut u8) {
+    if object.is_null() {
+        return;
+    }
+    let h = header(object);
+    if (*h).count < 0 {
+        return;
+    }
+    (*h).count += 1;
+    (*h).set_color(Color::Black);
+}
+
+// Frees the object when that was its last reference, null and constants are left alone
+#[no_mangle]
+pub unsafe extern "C" fn rho_release(object: *mut u8) {
+    if object.is_null() || (*header(object)).count < 0 {
+        return;
+    }
+    release(header(object));
+    if ROOTS.with(|roots| roots.borrow().len()) >= FIRST_COLLECTION {
+        rho_collect_cycles();
+    }
+}
+
+unsafe fn release(h: *mut Header) {
+    (*h).count -= 1;
+    if (*h).count == 0 {
+        for child in children(h) {
+            release(child);
+        }
+        (*h).set_color(Color::Black);
+        // a buffered one is freed by the next collection
+        if !(*h).buffered() {
+            free(h);
+        }
+    } else if !matches!((*h).kind(), RHO_KIND_STR | RHO_KIND_CURSOR)
+        && (*h).color() != Color::Purple
+    {
+        (*h).set_color(Color::Purple);
+        if !(*h).buffered() {
+            (*h).set_buffered(true);
+            ROOTS.with(|roots| roots.borrow_mut().push(h));
+        }
+    }
+}
+
+// Frees the cycles nothing outside them refers to anymore
+#[no_mangle]
+pub unsafe extern "C" fn rho_collect_cycles() {
+    let roots = ROOTS.with(|roots| std::mem::take(&mut *roots.borrow_mut()));
+    let mut candidates = vec![];
+    for h in roots {
+        if (*h).color() == Color::Purple {
+            mark_gray(h);
+            candidates.push(h);
+        } else {
+            (*h).set_buffered(false);
+            if (*h).color() == Color::Black && (*h).count == 0 {
+                free(h);
+            }
+        }
+    }
+    for h in candidates.iter() {
+        scan(*h);
+    }
+    for h in candidates {
+        (*h).set_buffered(false);
+        collect_white(h);
+    }
+}
+
+// Takes away the references the objects `h` reaches make to each other
+unsafe fn mark_gray(h: *mut Header) {
+    if (*h).color() == Color::Gray {
+        return;
+    }
+    (*h).set_color(Color::Gray);
+    for child in children(h) {
+        (*child).count -= 1;
+        mark_gray(child);
+    }
+}
+
+// What's still referred to is in use, with what it reaches, the rest is garbage
+unsafe fn scan(h: *mut Header) {
+    if (*h).color() != Color::Gray {
+        return;
+    }
+    if (*h).count > 0 {
+        scan_black(h);
+        return;
+    }
+    (*h).set_color(Color::White);
+    for child in children(h) {
+        scan(child);
+    }
+}
+
+unsafe fn scan_black(h: *mut Header) {
+    (*h).set_color(Color::Black);
+    for child in children(h) {
+        (*child).count += 1;
+        if (*child).color() != Color::Black {
+            scan_black(child);
+        }
+    }
+}
+
+unsafe fn collect_white(h: *mut Header) {
+    if (*h).color() != Color::White || (*h).buffered() {
+        return;
+    }
+    (*h).set_color(Color::Black);
+    for child in children(h) {
+        collect_white(child);
+    }
+    free(h);
+}
+
+/* Copy on write for the generated code's assignments to `xs[i]`, `map[k]` and `p.x`, which
+ * change a list, map or record in place while rho's values are never shared. It takes over the
+ * reference to `object` and gives back one to an object nothing else refers to, `object` itself
+ * when that was its only reference, or else a copy that refers to the same elements.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn rho_unique(object: *mut u8) -> *mut u8 {
+    let h = header(object);
+    if (*h).count == 1 {
+        return object;
+    }
+    let copy = match (*h).kind() {
+        RHO_KIND_LIST => lists::copy(object as *const RhoList) as *mut u8,
+        RHO_KIND_MAP => maps::copy(object as *const RhoMap) as *mut u8,
+        kind => {
+            let copy = allocate(kind, (*h).size());
+            std::ptr::copy_nonoverlapping(object, copy, (*h).size());
+            copy
+        }
+    };
+    for child in children(header(copy)) {
+        (*child).count += 1;
+        (*child).set_color(Color::Black);
+    }
+    rho_release(object);
+    copy
+}
+
+// How many objects haven't been freed, `rho_finish` checks for 0 when RHO_LEAK_CHECK is set
+#[no_mangle]
+pub extern "C" fn rho_live_objects() -> i64 {
+    LIVE.with(|live| live.get())
+}
+
+/* A record of `fields` u64s, ie. the environment of a closure, bit `i` of `objects` is set when
+ * field `i` holds an object. It's stored before the fields, so a record has at most 64 of them.
+ */
+#[no_mangle]
+pub extern "C" fn rho_record_new(fields: i64, objects: u64) -> *mut u8 {
+    let fields = fields.clamp(0, 64) as usize;
+    let record = allocate(RHO_KIND_RECORD, 8 * (fields + 1));
+    unsafe { *(record as *mut u64) = objects };
+    record
+}
+
+// The field lends its reference, retain it to keep it
+#[no_mangle]
+pub unsafe extern "C" fn rho_record_get(record: *const u8, i: i64) -> u64 {
+    *(record as *const u64).add(1 + i as usize)
+}
+
+// The record takes over the reference to `value` and releases the one it had
+#[no_mangle]
+pub unsafe extern "C" fn rho_record_set(record: *mut u8, i: i64, value: u64) {
+    let field = (record as *mut u64).add(1 + i as usize);
+    if *(record as *const u64) & 1 << i != 0 {
+        rho_release(*field as *mut u8);
+    }
+    *field = value;
+}
+
+// The field of a record an assignment to `r.x.y` or `r.x[i]` changes, see `rho_list_unique_at`
+#[no_mangle]
+pub unsafe extern "C" fn rho_record_unique_at(record: *mut u8, i: i64) -> u64 {
+    let field = (record as *mut u64).add(1 + i as usize);
+    *field = rho_unique(*field as *mut u8) as u64;
+    *field
+}
+
+unsafe fn record_objects(record: *const u64, size: usize) -> Vec<u64> {
+    let objects = *record;
+    (0..size / 8 - 1)
+        .filter(|i| objects & 1 << i != 0)
+        .map(|i| *record.add(1 + i))
+        .collect()
+}
+
+#[test]
+fn test_collect_cycles() {
+    unsafe {
+        let before = rho_live_objects();
+        // a <-> b, and b holds a list of a str
+        let a = rho_record_new(1, 1);
+        let b = rho_record_new(2, 0b11);
+        let list = lists::rho_list_new(1, true);
+        lists::rho_list_push(list, crate::new_string("in a cycle") as u64);
+        rho_retain(a);
+        rho_record_set(b, 0, a as u64);
+        rho_retain(b);
+        rho_record_set(a, 0, b as u64);
+        rho_record_set(b, 1, list as u64);
+        assert_eq!(rho_live_objects(), before + 4);
+
+        // still referred to from here
+        rho_release(b);
+        rho_collect_cycles();
+        assert_eq!(rho_live_objects(), before + 4);
+        assert_eq!(rho_record_get(a, 0), b as u64);
+
+        rho_release(a);
+        assert_eq!(rho_live_objects(), before + 4);
+        rho_collect_cycles();
+        assert_eq!(rho_live_objects(), before);
+
+        // without a cycle the count alone frees it, constants are never freed
+        let s = crate::new_string("s");
+        let list = lists::rho_list_new(2, true);
+        rho_retain(s as *mut u8);
+        lists::rho_list_push(list, s as u64);
+        lists::rho_list_push(list, 0);
+        rho_release(s as *mut u8);
+        rho_release(list as *mut u8);
+        rho_release(std::ptr::null_mut());
+        let mut constant = [u64::MAX, RHO_KIND_STR, 0];
+        let c = constant.as_mut_ptr().add(2) as *mut u8;
+        rho_retain(c);
+        rho_release(c);
+        assert_eq!(constant[0], u64::MAX);
+        rho_collect_cycles();
+        assert_eq!(rho_live_objects(), before);
+
+        // enough garbage cycles collect themselves
+        for _ in 0..FIRST_COLLECTION {
+            let a = rho_record_new(1, 1);
+            rho_retain(a);
+            rho_record_set(a, 0, a as u64);
+            rho_release(a);
+        }
+        assert_eq!(rho_live_objects(), before);
+    }
+}
+
+#[test]
+fn test_unique() {
+    unsafe {
+        let before = rho_live_objects();
+        let s = crate::new_string("s") as u64;
+        let record = rho_record_new(2, 0b10);
+        rho_record_set(record, 1, s);
+        assert_eq!(rho_unique(record), record);
+
+        // a shared one is copied, and the copy holds its own references
+        rho_retain(record);
+        let copy = rho_unique(record);
+        assert_ne!(copy, record);
+        assert_eq!(rho_record_get(copy, 1), s);
+        rho_record_set(copy, 0, 5);
+        assert_eq!(rho_record_get(record, 0), 0);
+        rho_release(record);
+        assert_eq!(crate::string(rho_record_get(copy, 1) as *const _), "s");
+
+        let outer = rho_record_new(1, 1);
+        rho_record_set(outer, 0, copy as u64);
+        rho_retain(copy);
+        assert_ne!(rho_record_unique_at(outer, 0), copy as u64);
+        rho_release(copy);
+        rho_release(outer);
+        rho_collect_cycles();
+        assert_eq!(rho_live_objects(), before);
+    }
+}
@@ -0,0 +1,602 @@
+use std::ffi::c_char;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use crate::lists::{self, rho_list_len, rho_list_new, rho_list_push, RhoList};
+use crate::memory::{self, rho_record_get, rho_record_new, rho_release, rho_retain};
+use crate::{fail, new_string, rho_enter, rho_leave, string};
+
+/* A stream is a record that only describes where its elements come from, like the interpreter's
+ * `streams::Stream`. Its first field is its kind and the others are:
+ *
+ *     range                                start, end, step
+ *     values                               the list
+ *     iterate                              objects, the first element, the closure, its adapter
+ *     lines                                the path
+ *     map, filter, take_while, flat_map    objects, the stream, the closure, its adapter
+ *     take, chunk                          objects, the stream, the count
+ *
+ * `objects` is set when the elements it pulls, or an iterate's own, are objects. Nothing runs
+ * until a `Cursor` pulls from it, one element at a time and each pass from the start.
+ *
+ * A closure's function takes its parameters as the types they are, which the runtime can't call,
+ * so the generated code hands it an adapter for the closure's type along with it. An adapter
+ * takes the record and the bits of up to two arguments, whose references it takes over, and
+ * gives the bits of the result as a new reference.
+ */
+
+const RANGE: u64 = 0;
+const VALUES: u64 = 1;
+const ITERATE: u64 = 2;
+const LINES: u64 = 3;
+const MAP: u64 = 4;
+const FILTER: u64 = 5;
+const TAKE_WHILE: u64 = 6;
+const FLAT_MAP: u64 = 7;
+const TAKE: u64 = 8;
+const CHUNK: u64 = 9;
+
+pub type Adapter = unsafe extern "C" fn(*mut u8, u64, u64) -> u64;
+
+// Where the operation pulling from a stream is, the closures it calls are called from there
+#[derive(Clone, Copy)]
+struct At {
+    file: *const c_char,
+    line: i64,
+    col: i64,
+}
+
+impl At {
+    unsafe fn fail(&self, message: &str) -> ! {
+        fail("", message, &string(self.file), self.line, self.col)
+    }
+}
+
+// A new stream of `kind`, holding a reference to the fields `objects` has the bits of
+unsafe fn new_stream(kind: u64, fields: &[u64], objects: u64) -> *mut u8 {
+    let stream = rho_record_new(fields.len() as i64 + 1, objects << 1);
+    let values = stream as *mut u64;
+    *values.add(1) = kind;
+    for (i, field) in fields.iter().enumerate() {
+        if objects & 1 << i != 0 {
+            rho_retain(*field as *mut u8);
+        }
+        *values.add(2 + i) = *field;
+    }
+    stream
+}
+
+unsafe fn field(stream: *const u8, i: i64) -> u64 {
+    rho_record_get(stream, i)
+}
+
+// A call of the closure, in its frame like the generated code's calls
+unsafe fn call(closure: u64, adapter: u64, a: u64, b: u64, at: At) -> u64 {
+    let adapter: Adapter = std::mem::transmute(adapter as usize);
+    let name = rho_record_get(closure as *const u8, 1) as *const c_char;
+    rho_enter(name, at.file, at.line, at.col);
+    let result = adapter(closure as *mut u8, a, b);
+    rho_leave();
+    result
+}
+
+unsafe fn count(n: i64, at: At) -> u64 {
+    if n < 0 {
+        at.fail(&format!("expected a count, found `{}`", n));
+    }
+    n as u64
+}
+
+// `Stream.from` of `start..end..step`, the generated code checked the step isn't 0
+#[no_mangle]
+pub unsafe extern "C" fn rho_stream_range(start: i64, end: i64, step: i64) -> *mut u8 {
+    new_stream(RANGE, &[start as u64, end as u64, step as u64], 0)
+}
+
+// `Stream.from` of a list
+#[no_mangle]
+pub unsafe extern "C" fn rho_stream_from(list: *mut RhoList) -> *mut u8 {
+    new_stream(VALUES, &[list as u64], 0b1)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_stream_iterate(
+    first: u64,
+    objects: bool,
+    closure: *mut u8,
+    adapter: Adapter,
+) -> *mut u8 {
+    let fields = [
+        objects as u64,
+        first,
+        closure as u64,
+        adapter as usize as u64,
+    ];
+    new_stream(ITERATE, &fields, (objects as u64) << 1 | 0b100)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_stream_lines(path: *mut c_char) -> *mut u8 {
+    new_stream(LINES, &[path as u64], 0b1)
+}
+
+unsafe fn apply(
+    kind: u64,
+    stream: *mut u8,
+    closure: *mut u8,
+    adapter: Adapter,
+    objects: bool,
+) -> *mut u8 {
+    let fields = [
+        objects as u64,
+        stream as u64,
+        closure as u64,
+        adapter as usize as u64,
+    ];
+    new_stream(kind, &fields, 0b110)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_stream_map(
+    stream: *mut u8,
+    closure: *mut u8,
+    adapter: Adapter,
+    objects: bool,
+) -> *mut u8 {
+    apply(MAP, stream, closure, adapter, objects)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_stream_filter(
+    stream: *mut u8,
+    closure: *mut u8,
+    adapter: Adapter,
+    objects: bool,
+) -> *mut u8 {
+    apply(FILTER, stream, closure, adapter, objects)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_stream_take_while(
+    stream: *mut u8,
+    closure: *mut u8,
+    adapter: Adapter,
+    objects: bool,
+) -> *mut u8 {
+    apply(TAKE_WHILE, stream, closure, adapter, objects)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_stream_flat_map(
+    stream: *mut u8,
+    closure: *mut u8,
+    adapter: Adapter,
+    objects: bool,
+) -> *mut u8 {
+    apply(FLAT_MAP, stream, closure, adapter, objects)
+}
+
+// `Stream.take`, failing at `line` and `col` of `file` for a negative count like the interpreter
+#[no_mangle]
+pub unsafe extern "C" fn rho_stream_take(
+    stream: *mut u8,
+    n: i64,
+    objects: bool,
+    file: *const c_char,
+    line: i64,
+    col: i64,
+) -> *mut u8 {
+    let n = count(n, At { file, line, col });
+    new_stream(TAKE, &[objects as u64, stream as u64, n], 0b10)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_stream_chunk(
+    stream: *mut u8,
+    size: i64,
+    objects: bool,
+    file: *const c_char,
+    line: i64,
+    col: i64,
+) -> *mut u8 {
+    let at = At { file, line, col };
+    let size = count(size, at);
+    if size == 0 {
+        at.fail("a chunk can't be empty");
+    }
+    new_stream(CHUNK, &[objects as u64, stream as u64, size], 0b10)
+}
+
+// Where one pass over a stream is, the runtime's `streams::Cursor`
+pub struct Cursor {
+    stream: *mut u8,
+    inner: Option<Box<Cursor>>, // the pass over the stream it pulls from
+    state: State,
+    at: At,
+}
+
+enum State {
+    Range(Option<i64>),
+    Values(usize),
+    Iterate(Option<u64>), // the last element, which the next one is made from
+    Lines(Option<BufReader<File>>),
+    Take(u64),                              // how many elements are left
+    TakeWhile(bool),                        // whether it ended
+    FlatMap(Option<(*mut RhoList, usize)>), // the elements of the last result come first
+    Pulls, // a map, filter or chunk only pulls from the stream inside
+}
+
+impl Cursor {
+    unsafe fn new(stream: *mut u8, at: At) -> Cursor {
+        rho_retain(stream);
+        let kind = field(stream, 0);
+        let inner = (kind >= MAP).then(|| Box::new(Cursor::new(field(stream, 2) as *mut u8, at)));
+        let state = match kind {
+            RANGE => State::Range(Some(field(stream, 1) as i64)),
+            VALUES => State::Values(0),
+            ITERATE => State::Iterate(None),
+            LINES => State::Lines(None),
+            TAKE => State::Take(field(stream, 3)),
+            TAKE_WHILE => State::TakeWhile(false),
+            FLAT_MAP => State::FlatMap(None),
+            MAP | FILTER | CHUNK => State::Pulls,
+            kind => unreachable!("there's no stream of kind {}", kind),
+        };
+        Cursor {
+            stream,
+            inner,
+            state,
+            at,
+        }
+    }
+
+    // The next element as a new reference, None when the pass ended
+    unsafe fn next(&mut self) -> Option<u64> {
+        let Cursor {
+            stream,
+            inner,
+            state,
+            at,
+        } = self;
+        let (stream, at) = (*stream, *at);
+        let objects = field(stream, 1) != 0;
+        let mut pull = || match inner {
+            Some(inner) => inner.next(),
+            None => None,
+        };
+        // the closure of a stream that has one, on an element it takes over
+        let apply = |v: u64| call(field(stream, 3), field(stream, 4), v, 0, at);
+        match state {
+            State::Range(next) => {
+                let current = (*next)?;
+                let (end, step) = (field(stream, 2) as i64, field(stream, 3) as i64);
+                let in_range = if step > 0 {
+                    current < end
+                } else {
+                    current > end
+                };
+                if !in_range {
+                    return None;
+                }
+                *next = current.checked_add(step);
+                Some(current as u64)
+            }
+            State::Values(i) => {
+                let v = lists::element(field(stream, 1) as *const RhoList, *i)?;
+                *i += 1;
+                Some(v)
+            }
+            State::Iterate(last) => {
+                let v = match last.take() {
+                    None => {
+                        let first = field(stream, 2);
+                        if objects {
+                            rho_retain(first as *mut u8);
+                        }
+                        first
+                    }
+                    Some(last) => apply(last),
+                };
+                // one reference stays for the next one
+                if objects {
+                    rho_retain(v as *mut u8);
+                }
+                *last = Some(v);
+                Some(v)
+            }
+            State::Lines(reader) => {
+                let path = string(field(stream, 1) as *const c_char);
+                if reader.is_none() {
+                    let file = File::open(&path)
+                        .unwrap_or_else(|e| at.fail(&format!("can't read `{}`: {}", path, e)));
+                    *reader = Some(BufReader::new(file));
+                }
+                let mut line = String::new();
+                match reader.as_mut().unwrap().read_line(&mut line) {
+                    Ok(0) => None,
+                    Ok(_) => {
+                        let line = line.strip_suffix('\n').unwrap_or(&line);
+                        let line = line.strip_suffix('\r').unwrap_or(line);
+                        Some(new_string(line) as u64)
+                    }
+                    Err(e) => at.fail(&format!("can't read `{}`: {}", path, e)),
+                }
+            }
+            State::Take(left) => {
+                if *left == 0 {
+                    return None;
+                }
+                *left -= 1;
+                pull()
+            }
+            State::TakeWhile(done) => {
+                if *done {
+                    return None;
+                }
+                if let Some(v) = pull() {
+                    if objects {
+                        rho_retain(v as *mut u8);
+                    }
+                    if apply(v) != 0 {
+                        return Some(v);
+                    }
+                    if objects {
+                        rho_release(v as *mut u8);
+                    }
+                }
+                *done = true;
+                None
+            }
+            State::FlatMap(current) => loop {
+                if let Some((list, i)) = current {
+                    if let Some(v) = lists::element(*list, *i) {
+                        *i += 1;
+                        return Some(v);
+                    }
+                    rho_release(*list as *mut u8);
+                    *current = None;
+                }
+                let v = pull()?;
+                *current = Some((apply(v) as *mut RhoList, 0));
+            },
+            State::Pulls if field(stream, 0) == MAP => pull().map(apply),
+            State::Pulls if field(stream, 0) == FILTER => {
+                while let Some(v) = pull() {
+                    if objects {
+                        rho_retain(v as *mut u8);
+                    }
+                    if apply(v) != 0 {
+                        return Some(v);
+                    }
+                    if objects {
+                        rho_release(v as *mut u8);
+                    }
+                }
+                None
+            }
+            // a chunk
+            State::Pulls => {
+                let size = field(stream, 3) as i64;
+                let chunk = rho_list_new(size, objects);
+                while rho_list_len(chunk) < size {
+                    match pull() {
+                        Some(v) => rho_list_push(chunk, v),
+                        None => break,
+                    }
+                }
+                if rho_list_len(chunk) == 0 {
+                    rho_release(chunk as *mut u8);
+                    return None;
+                }
+                Some(chunk as u64)
+            }
+        }
+    }
+}
+
+impl Drop for Cursor {
+    fn drop(&mut self) {
+        unsafe {
+            match self.state {
+                State::Iterate(Some(last)) if field(self.stream, 1) != 0 => {
+                    rho_release(last as *mut u8)
+                }
+                State::FlatMap(Some((list, _))) => rho_release(list as *mut u8),
+                _ => {}
+            }
+            rho_release(self.stream);
+        }
+    }
+}
+
+// Every element of the stream pulled into a new list
+#[no_mangle]
+pub unsafe extern "C" fn rho_stream_to_list(
+    stream: *mut u8,
+    objects: bool,
+    file: *const c_char,
+    line: i64,
+    col: i64,
+) -> *mut RhoList {
+    let mut cursor = Cursor::new(stream, At { file, line, col });
+    let list = rho_list_new(0, objects);
+    while let Some(v) = cursor.next() {
+        rho_list_push(list, v);
+    }
+    list
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_stream_count(
+    stream: *mut u8,
+    objects: bool,
+    file: *const c_char,
+    line: i64,
+    col: i64,
+) -> i64 {
+    let mut cursor = Cursor::new(stream, At { file, line, col });
+    let mut count = 0;
+    while let Some(v) = cursor.next() {
+        if objects {
+            rho_release(v as *mut u8);
+        }
+        count += 1;
+    }
+    count
+}
+
+// `Stream.reduce`, which takes over the reference to `acc` and gives one to the result
+#[no_mangle]
+pub unsafe extern "C" fn rho_stream_reduce(
+    stream: *mut u8,
+    acc: u64,
+    closure: *mut u8,
+    adapter: Adapter,
+    file: *const c_char,
+    line: i64,
+    col: i64,
+) -> u64 {
+    let at = At { file, line, col };
+    let mut cursor = Cursor::new(stream, at);
+    let mut acc = acc;
+    while let Some(v) = cursor.next() {
+        acc = call(closure as u64, adapter as usize as u64, acc, v, at);
+    }
+    acc
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_stream_each(
+    stream: *mut u8,
+    closure: *mut u8,
+    adapter: Adapter,
+    file: *const c_char,
+    line: i64,
+    col: i64,
+) {
+    let at = At { file, line, col };
+    let mut cursor = Cursor::new(stream, at);
+    while let Some(v) = cursor.next() {
+        call(closure as u64, adapter as usize as u64, v, 0, at);
+    }
+}
+
+/* A `for` loop's pass over a stream, an object the loop holds so a `return` or `break` out of it
+ * lets go of it like of any other. `rho_stream_next` pulls the next element, which
+ * `rho_stream_current` then hands over, and one it didn't is released by the next pull.
+ */
+pub struct RhoCursor {
+    cursor: Cursor,
+    objects: bool,
+    current: Option<u64>,
+}
+
+impl Drop for RhoCursor {
+    fn drop(&mut self) {
+        if let (Some(v), true) = (self.current.take(), self.objects) {
+            unsafe { rho_release(v as *mut u8) };
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_stream_cursor(
+    stream: *mut u8,
+    objects: bool,
+    file: *const c_char,
+    line: i64,
+    col: i64,
+) -> *mut RhoCursor {
+    let size = std::mem::size_of::<RhoCursor>();
+    let object = memory::allocate(memory::RHO_KIND_CURSOR, size) as *mut RhoCursor;
+    let cursor = RhoCursor {
+        cursor: Cursor::new(stream, At { file, line, col }),
+        objects,
+        current: None,
+    };
+    std::ptr::write(object, cursor);
+    object
+}
+
+// Whether there's a next element
+#[no_mangle]
+pub unsafe extern "C" fn rho_stream_next(cursor: *mut RhoCursor) -> bool {
+    let cursor = &mut *cursor;
+    if let (Some(v), true) = (cursor.current.take(), cursor.objects) {
+        rho_release(v as *mut u8);
+    }
+    cursor.current = cursor.cursor.next();
+    cursor.current.is_some()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_stream_current(cursor: *mut RhoCursor) -> u64 {
+    (*cursor).current.take().unwrap_or(0)
+}
+
+#[cfg(test)]
+unsafe extern "C" fn double(_: *mut u8, v: u64, _: u64) -> u64 {
+    v * 2
+}
+
+#[cfg(test)]
+unsafe extern "C" fn below_8(_: *mut u8, v: u64, _: u64) -> u64 {
+    (v < 8) as u64
+}
+
+#[cfg(test)]
+unsafe extern "C" fn add(_: *mut u8, acc: u64, v: u64) -> u64 {
+    acc + v
+}
+
+#[test]
+fn test_streams() {
+    unsafe {
+        let before = memory::rho_live_objects();
+        let file = c"a.rho".as_ptr();
+        // a closure's record is its function and its name
+        let closure = rho_record_new(2, 0);
+        memory::rho_record_set(closure, 1, c"fn".as_ptr() as u64);
+        let powers = rho_stream_iterate(1, false, closure, double);
+        let small = rho_stream_take_while(powers, closure, below_8, false);
+        let chunks = rho_stream_chunk(small, 2, false, file, 1, 1);
+        let list = rho_stream_to_list(chunks, true, file, 1, 1);
+        let shown = crate::values::show(list as u64, &crate::values::Shape::parse("lli"), true);
+        assert_eq!(shown, "[[1, 2], [4]]");
+        rho_release(list as *mut u8);
+        assert_eq!(rho_stream_count(small, false, file, 1, 1), 3);
+        assert_eq!(rho_stream_reduce(small, 10, closure, add, file, 1, 1), 17);
+
+        // a `for` loop's pass, which can end before the stream does
+        let range = rho_stream_range(0, 10, 3);
+        let doubled = rho_stream_map(range, closure, double, false);
+        let cursor = rho_stream_cursor(doubled, false, file, 1, 1);
+        assert!(rho_stream_next(cursor));
+        assert_eq!(rho_stream_current(cursor), 0);
+        assert!(rho_stream_next(cursor));
+        assert_eq!(rho_stream_current(cursor), 6);
+        rho_release(cursor as *mut u8);
+
+        // the strs of a list are handed out, and the stream holds the list
+        let strs = rho_list_new(2, true);
+        rho_list_push(strs, new_string("a") as u64);
+        rho_list_push(strs, new_string("b") as u64);
+        let from = rho_stream_from(strs);
+        rho_release(strs as *mut u8);
+        let cursor = rho_stream_cursor(from, true, file, 1, 1);
+        assert!(rho_stream_next(cursor));
+        assert!(rho_stream_next(cursor));
+        let b = rho_stream_current(cursor) as *mut c_char;
+        assert_eq!(string(b), "b");
+        rho_release(b as *mut u8);
+        assert!(!rho_stream_next(cursor));
+        rho_release(cursor as *mut u8);
+
+        for stream in [powers, small, chunks, range, doubled, from] {
+            rho_release(stream);
+        }
+        rho_release(closure);
+        memory::rho_collect_cycles();
+        assert_eq!(memory::rho_live_objects(), before);
+    }
+}
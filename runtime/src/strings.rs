@@ -0,0 +1,303 @@
+use std::ffi::c_char;
+
+use crate::atoms::{atom, rho_atom_name};
+use crate::lists::{self, rho_list_new, rho_list_push, RhoList};
+use crate::memory::{rho_record_new, rho_record_set};
+use crate::text::{
+    escape, graphemes, inspect_float, pad, parse_float, parse_int, replace, slice, split,
+};
+use crate::{fail, new_string, string};
+
+// A str of `len` bytes, ie. one read from a file
+#[no_mangle]
+pub unsafe extern "C" fn rho_str_new(bytes: *const u8, len: i64) -> *mut c_char {
+    let bytes = std::slice::from_raw_parts(bytes, len as usize);
+    new_string(&String::from_utf8_lossy(bytes))
+}
+
+// `a <> b`
+#[no_mangle]
+pub unsafe extern "C" fn rho_str_concat(a: *const c_char, b: *const c_char) -> *mut c_char {
+    new_string(&(string(a) + &string(b)))
+}
+
+// -1, 0 or 1 as `a` is before, equal to or after `b`, byte by byte like rho compares strs
+#[no_mangle]
+pub unsafe extern "C" fn rho_str_compare(a: *const c_char, b: *const c_char) -> i32 {
+    string(a).cmp(&string(b)) as i32
+}
+
+// `str(i)`
+#[no_mangle]
+pub extern "C" fn rho_str_from_int(i: i64) -> *mut c_char {
+    new_string(&i.to_string())
+}
+
+// `str(f)`, ie. "2.0"
+#[no_mangle]
+pub extern "C" fn rho_str_from_float(f: f64) -> *mut c_char {
+    new_string(&inspect_float(f))
+}
+
+/* `IO.inspect`'s text of a value as a str, ie. for the operands of a failed `assert`. An int or
+ * float is `rho_str_from_int` or `rho_str_from_float`.
+ */
+#[no_mangle]
+pub extern "C" fn rho_str_inspect_bool(b: bool) -> *mut c_char {
+    new_string(if b { "true" } else { "false" })
+}
+
+#[no_mangle]
+pub extern "C" fn rho_str_inspect_char(c: u32) -> *mut c_char {
+    let c = char::from_u32(c).unwrap_or('\u{fffd}');
+    new_string(&format!("'{}'", escape(&c.to_string(), '\'')))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_str_inspect_str(s: *const c_char) -> *mut c_char {
+    new_string(&format!("\"{}\"", escape(&string(s), '"')))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_str_inspect_atom(id: u32) -> *mut c_char {
+    new_string(&format!(":{}", string(rho_atom_name(id))))
+}
+
+/* The `String` module for compiled code, `rho_string_upcase` is `String.upcase`. They work like
+ * the interpreter's natives in `strings.rs` through the same functions of `text`, and the ones
+ * that fail take the location to fail at after their arguments, like `rho_fail` does.
+ */
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_string_length(s: *const c_char) -> i64 {
+    graphemes(&string(s)).len() as i64
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_string_char_count(s: *const c_char) -> i64 {
+    string(s).chars().count() as i64
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_string_byte_size(s: *const c_char) -> i64 {
+    string(s).len() as i64
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_string_slice(
+    s: *const c_char,
+    start: i64,
+    length: i64,
+) -> *mut c_char {
+    new_string(&slice(&string(s), start, length))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_string_trim(s: *const c_char) -> *mut c_char {
+    new_string(string(s).trim())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_string_trim_leading(s: *const c_char) -> *mut c_char {
+    new_string(string(s).trim_start())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_string_trim_trailing(s: *const c_char) -> *mut c_char {
+    new_string(string(s).trim_end())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_string_replace(
+    s: *const c_char,
+    pattern: *const c_char,
+    with: *const c_char,
+) -> *mut c_char {
+    new_string(&replace(&string(s), &string(pattern), &string(with)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_string_contains(s: *const c_char, part: *const c_char) -> bool {
+    string(s).contains(&string(part))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_string_starts_with(s: *const c_char, part: *const c_char) -> bool {
+    string(s).starts_with(&string(part))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_string_ends_with(s: *const c_char, part: *const c_char) -> bool {
+    string(s).ends_with(&string(part))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_string_upcase(s: *const c_char) -> *mut c_char {
+    new_string(&string(s).to_uppercase())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_string_downcase(s: *const c_char) -> *mut c_char {
+    new_string(&string(s).to_lowercase())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_string_pad_leading(
+    s: *const c_char,
+    width: i64,
+    padding: *const c_char,
+    file: *const c_char,
+    line: i64,
+    col: i64,
+) -> *mut c_char {
+    match pad(&string(s), width, &string(padding), true) {
+        Ok(padded) => new_string(&padded),
+        Err(message) => fail("", &message, &string(file), line, col),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_string_pad_trailing(
+    s: *const c_char,
+    width: i64,
+    padding: *const c_char,
+    file: *const c_char,
+    line: i64,
+    col: i64,
+) -> *mut c_char {
+    match pad(&string(s), width, &string(padding), false) {
+        Ok(padded) => new_string(&padded),
+        Err(message) => fail("", &message, &string(file), line, col),
+    }
+}
+
+// A new list of new strs
+fn strs(parts: Vec<&str>) -> *mut RhoList {
+    let list = rho_list_new(parts.len() as i64, true);
+    for part in parts {
+        unsafe { rho_list_push(list, new_string(part) as u64) };
+    }
+    list
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_string_graphemes(s: *const c_char) -> *mut RhoList {
+    strs(graphemes(&string(s)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_string_split(
+    s: *const c_char,
+    separator: *const c_char,
+) -> *mut RhoList {
+    strs(split(&string(s), &string(separator)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_string_join(
+    parts: *const RhoList,
+    separator: *const c_char,
+) -> *mut c_char {
+    let parts: Vec<String> = (lists::items(parts).iter())
+        .map(|part| string(*part as *const c_char))
+        .collect();
+    new_string(&parts.join(&string(separator)))
+}
+
+// `{:ok, bits}` of a number, or `{:error, reason}` with the reason as a new str
+fn parsed(number: Result<u64, String>) -> *mut u8 {
+    let (tag, value, objects) = match number {
+        Ok(bits) => (atom("ok"), bits, 0),
+        Err(reason) => (atom("error"), new_string(&reason) as u64, 0b10),
+    };
+    let result = rho_record_new(2, objects);
+    unsafe {
+        rho_record_set(result, 0, tag as u64);
+        rho_record_set(result, 1, value);
+    }
+    result
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_string_to_int(s: *const c_char) -> *mut u8 {
+    parsed(parse_int(&string(s)).map(|i| i as u64))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rho_string_to_float(s: *const c_char) -> *mut u8 {
+    parsed(parse_float(&string(s)).map(f64::to_bits))
+}
+
+#[test]
+fn test_strings() {
+    unsafe {
+        let a = rho_str_new("héllo ".as_ptr(), 7);
+        let b = rho_str_from_int(-42);
+        let ab = rho_str_concat(a, b);
+        assert_eq!(string(ab), "héllo -42");
+        assert_eq!(rho_string_length(ab), 9);
+        assert_eq!(rho_str_compare(a, b), 1);
+        assert_eq!(rho_str_compare(b, a), -1);
+        assert_eq!(rho_str_compare(ab, ab), 0);
+        assert_eq!(string(rho_str_from_float(2.0)), "2.0");
+        assert_eq!(string(rho_str_inspect_str(c"a\"b".as_ptr())), "\"a\\\"b\"");
+        assert_eq!(string(rho_str_inspect_char('\n' as u32)), "'\\n'");
+        assert_eq!(string(rho_str_inspect_bool(false)), "false");
+    }
+}
+
+#[test]
+fn test_string_module() {
+    unsafe {
+        let s = new_string(" Ren\u{e9}e ");
+        let trimmed = rho_string_trim(s);
+        assert_eq!(string(trimmed), "Ren\u{e9}e");
+        assert_eq!(rho_string_char_count(trimmed), 5);
+        assert_eq!(rho_string_byte_size(trimmed), 6);
+        assert_eq!(string(rho_string_upcase(trimmed)), "REN\u{c9}E");
+        assert_eq!(string(rho_string_slice(trimmed, -3, 2)), "n\u{e9}");
+        let e = c"e".as_ptr();
+        assert!(rho_string_ends_with(trimmed, e));
+        assert!(!rho_string_starts_with(trimmed, e));
+        assert_eq!(
+            string(rho_string_replace(trimmed, e, c"a".as_ptr())),
+            "Ran\u{e9}a"
+        );
+        let padded = rho_string_pad_leading(c"7".as_ptr(), 3, c"0".as_ptr(), e, 1, 1);
+        assert_eq!(string(padded), "007");
+
+        let before = crate::memory::rho_live_objects();
+        let strs = crate::values::Shape::parse("ls");
+        let parts = rho_string_split(c"a,b,,c".as_ptr(), c",".as_ptr());
+        let show = |v: u64, shape| crate::values::show(v, shape, true);
+        assert_eq!(show(parts as u64, &strs), "[\"a\", \"b\", \"\", \"c\"]");
+        let joined = rho_string_join(parts, c"+".as_ptr());
+        assert_eq!(string(joined), "a+b++c");
+        let clusters = rho_string_graphemes(c"e\u{301}x".as_ptr());
+        assert_eq!(show(clusters as u64, &strs), "[\"e\u{301}\", \"x\"]");
+        let result = crate::values::Shape::parse("eis");
+        let int = rho_string_to_int(c"-12".as_ptr());
+        assert_eq!(show(int as u64, &result), "{:ok, -12}");
+        let not_int = rho_string_to_int(c"1.5".as_ptr());
+        assert_eq!(
+            show(not_int as u64, &result),
+            "{:error, \"`1.5` is not an int\"}"
+        );
+        let float = rho_string_to_float(c"1.5".as_ptr());
+        assert_eq!(
+            show(float as u64, &crate::values::Shape::parse("efs")),
+            "{:ok, 1.5}"
+        );
+        for object in [
+            parts as *mut u8,
+            joined as *mut u8,
+            clusters as *mut u8,
+            int,
+            not_int,
+            float,
+        ] {
+            crate::memory::rho_release(object);
+        }
+        assert_eq!(crate::memory::rho_live_objects(), before);
+    }
+}
@@ -0,0 +1,117 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/* The pure functions on text that the interpreter and compiled programs share, so a program
+ * prints and measures strings the same way whichever backend runs it.
+ */
+
+// Rust writes floats without an exponent and with the fewest digits that read back the same
+pub fn inspect_float(f: f64) -> String {
+    if f.is_nan() {
+        return "0.0 / 0.0".to_string();
+    }
+    if f.is_infinite() {
+        return if f > 0.0 { "1.0 / 0.0" } else { "-1.0 / 0.0" }.to_string();
+    }
+    let s = f.to_string();
+    if s.contains('.') {
+        s
+    } else {
+        s + ".0"
+    }
+}
+
+// The reverse of the lexer's `tokens::unescape`, for strings and chars written as literals
+pub fn escape(string: &str, quote: char) -> String {
+    let mut out = String::new();
+    for c in string.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\0"),
+            '\\' => out.push_str("\\\\"),
+            c if c == quote => {
+                out.push('\\');
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// Splits a string into the extended grapheme clusters of Unicode's segmentation rules (UAX #29)
+pub fn graphemes(s: &str) -> Vec<&str> {
+    s.graphemes(true).collect()
+}
+
+// `length` graphemes from `start`, a negative start counts from the end, ie. -1 is the last
+pub fn slice(s: &str, start: i64, length: i64) -> String {
+    let clusters = graphemes(s);
+    let count = clusters.len() as i64;
+    let start = if start < 0 { count + start } else { start }.clamp(0, count);
+    let end = start.saturating_add(length.max(0)).min(count);
+    clusters[start as usize..end as usize].concat()
+}
+
+// Every `pattern` in `s` replaced, an empty pattern replaces nothing
+pub fn replace(s: &str, pattern: &str, with: &str) -> String {
+    if pattern.is_empty() {
+        return s.to_owned();
+    }
+    s.replace(pattern, with)
+}
+
+// An empty separator splits the string into its graphemes
+pub fn split<'s>(s: &'s str, separator: &str) -> Vec<&'s str> {
+    if separator.is_empty() {
+        return graphemes(s);
+    }
+    s.split(separator).collect()
+}
+
+pub fn parse_int(s: &str) -> Result<i64, String> {
+    s.parse::<i64>()
+        .map_err(|_| format!("`{}` is not an int", s))
+}
+
+// Decimal numbers, with a sign, fraction or exponent, ie. `-2` or `1.5e300`, not `inf` or `NaN`
+pub fn parse_float(s: &str) -> Result<f64, String> {
+    let is_number = s.chars().any(|c| c.is_ascii_digit())
+        && s.chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E'));
+    match s.parse::<f64>() {
+        Ok(f) if is_number => Ok(f),
+        _ => Err(format!("`{}` is not a float", s)),
+    }
+}
+
+// The widest `pad` goes, past it a typo like an extra digit would build a string the size of memory
+pub const MAX_PAD_WIDTH: i64 = 1 << 24;
+
+// Repeats `padding` on one side until the string is `width` graphemes long
+pub fn pad(s: &str, width: i64, padding: &str, leading: bool) -> Result<String, String> {
+    let missing = width - graphemes(s).len() as i64;
+    if missing <= 0 {
+        return Ok(s.to_owned());
+    }
+    if width > MAX_PAD_WIDTH {
+        return Err(format!(
+            "can't pad to {} graphemes, the most is {}",
+            width, MAX_PAD_WIDTH
+        ));
+    }
+    if padding.is_empty() {
+        return Err("the padding can't be an empty str".to_string());
+    }
+    let fill: String = graphemes(padding)
+        .into_iter()
+        .cycle()
+        .take(missing as usize)
+        .collect();
+    Ok(if leading {
+        fill + s
+    } else {
+        s.to_owned() + &fill
+    })
+}
@@ -0,0 +1,259 @@
+use std::ffi::c_char;
+
+use crate::atoms::{atom, rho_atom_name};
+use crate::lists::{self, RhoList};
+use crate::maps::{rho_map_key_at, rho_map_len, rho_map_value_at, RhoMap};
+use crate::memory::rho_record_get;
+use crate::text::{escape, inspect_float};
+use crate::{new_string, string};
+
+/* The type of a value, which the code generator describes to the functions that look inside a
+ * list, map or record since their elements are only bits:
+ *
+ *     i int   f float   b bool   c char   a atom   s str
+ *     l<T>                       a list of T
+ *     m<K><V>                    a map from K to V
+ *     t<n>;<T>...                a tuple of n elements
+ *     r<name>;<n>;<field>;<T>... a struct, its fields in the order they're declared
+ *     R<name>;                   the struct of that name around it, inside itself
+ *     e<T><E>                    a result, `{:ok, T}` or `{:error, E}` as its tag says
+ *     F<n>;                      a function of n parameters, a closure's record
+ *     S                          a stream, which only shows as `#stream`
+ *
+ * ie. `lms` is a `list[map[str, int]]` and `rPoint;2;x;iy;i` a `Point` of two ints. Anything
+ * else is `?`, like the elements of an empty list nothing gives a type to.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Int,
+    Float,
+    Bool,
+    Char,
+    Atom,
+    Str,
+    Unknown,
+    List(Box<Shape>),
+    Map(Box<Shape>, Box<Shape>),
+    Tuple(Vec<Shape>),
+    Struct(String, Vec<(String, Shape)>),
+    Inside(String),
+    Result(Box<Shape>, Box<Shape>),
+    Function(usize),
+    Stream,
+}
+
+impl Shape {
+    pub fn parse(descriptor: &str) -> Shape {
+        parse(&mut descriptor.chars())
+    }
+}
+
+fn parse(d: &mut std::str::Chars) -> Shape {
+    match d.next() {
+        Some('i') => Shape::Int,
+        Some('f') => Shape::Float,
+        Some('b') => Shape::Bool,
+        Some('c') => Shape::Char,
+        Some('a') => Shape::Atom,
+        Some('s') => Shape::Str,
+        Some('l') => Shape::List(Box::new(parse(d))),
+        Some('m') => {
+            let key = parse(d);
+            Shape::Map(Box::new(key), Box::new(parse(d)))
+        }
+        Some('t') => {
+            let n = count(d);
+            Shape::Tuple((0..n).map(|_| parse(d)).collect())
+        }
+        Some('r') => {
+            let name = word(d);
+            let n = count(d);
+            let fields = (0..n).map(|_| (word(d), parse(d))).collect();
+            Shape::Struct(name, fields)
+        }
+        Some('R') => Shape::Inside(word(d)),
+        Some('e') => {
+            let ok = parse(d);
+            Shape::Result(Box::new(ok), Box::new(parse(d)))
+        }
+        Some('F') => Shape::Function(count(d)),
+        Some('S') => Shape::Stream,
+        _ => Shape::Unknown,
+    }
+}
+
+fn word(d: &mut std::str::Chars) -> String {
+    d.take_while(|c| *c != ';').collect()
+}
+
+fn count(d: &mut std::str::Chars) -> usize {
+    word(d).parse().unwrap_or(0)
+}
+
+// The text of a value like the interpreter's `Display` writes it, or `IO.inspect` when `inspect`
+pub unsafe fn show(value: u64, shape: &Shape, inspect: bool) -> String {
+    show_in(value, shape, inspect, &mut vec![])
+}
+
+// `show` inside the structs in `around`, innermost last
+unsafe fn show_in<'a>(
+    value: u64,
+    shape: &'a Shape,
+    inspect: bool,
+    around: &mut Vec<&'a Shape>,
+) -> String {
+    match shape {
+        Shape::Int => (value as i64).to_string(),
+        Shape::Float => inspect_float(f64::from_bits(value)),
+        Shape::Bool => (value != 0).to_string(),
+        Shape::Char => {
+            let c = char::from_u32(value as u32)
+                .unwrap_or('\u{fffd}')
+                .to_string();
+            match inspect {
+                true => format!("'{}'", escape(&c, '\'')),
+                false => c,
+            }
+        }
+        Shape::Atom => format!(":{}", string(rho_atom_name(value as u32))),
+        Shape::Str => {
+            let s = string(value as *const c_char);
+            match inspect {
+                true => format!("\"{}\"", escape(&s, '"')),
+                false => s,
+            }
+        }
+        Shape::Unknown => "?".to_string(),
+        Shape::Stream => "#stream".to_string(),
+        // the record has the name after the function
+        Shape::Function(arity) => {
+            let name = rho_record_get(value as *const u8, 1) as *const c_char;
+            format!("#fn<{}/{}>", string(name), arity)
+        }
+        Shape::List(element) => {
+            let items = lists::items(value as *const RhoList);
+            let items = items.iter().map(|i| show_in(*i, element, true, around));
+            let items: Vec<String> = items.collect();
+            format!("[{}]", items.join(", "))
+        }
+        Shape::Map(k, v) => {
+            let map = value as *const RhoMap;
+            let entries: Vec<String> = (0..rho_map_len(map))
+                .map(|i| {
+                    let key = show_in(rho_map_key_at(map, i), k, true, around);
+                    let value = show_in(rho_map_value_at(map, i), v, true, around);
+                    format!("{}: {}", key, value)
+                })
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Shape::Tuple(elements) => {
+            let elements: Vec<String> = (elements.iter().enumerate())
+                .map(|(i, e)| {
+                    let v = rho_record_get(value as *const u8, i as i64);
+                    show_in(v, e, true, around)
+                })
+                .collect();
+            format!("{{{}}}", elements.join(", "))
+        }
+        Shape::Struct(name, fields) => {
+            around.push(shape);
+            let fields: Vec<String> = (fields.iter().enumerate())
+                .map(|(i, (field, t))| {
+                    let v = rho_record_get(value as *const u8, i as i64);
+                    format!("{}: {}", field, show_in(v, t, true, around))
+                })
+                .collect();
+            around.pop();
+            format!("{}{{{}}}", name, fields.join(", "))
+        }
+        Shape::Result(ok, error) => {
+            let tag = rho_record_get(value as *const u8, 0);
+            let v = rho_record_get(value as *const u8, 1);
+            let shape = match tag as u32 == atom("ok") {
+                true => ok,
+                false => error,
+            };
+            let tag = show_in(tag, &Shape::Atom, true, around);
+            format!("{{{}, {}}}", tag, show_in(v, shape, true, around))
+        }
+        Shape::Inside(name) => {
+            let outer = (around.iter().rev())
+                .find(|s| matches!(s, Shape::Struct(n, _) if n == name))
+                .copied();
+            match outer {
+                Some(outer) => show_in(value, outer, true, around),
+                None => "?".to_string(),
+            }
+        }
+    }
+}
+
+// `str(x)` or, when `inspect`, `IO.inspect`'s text of a value `descriptor` gives the type of
+#[no_mangle]
+pub unsafe extern "C" fn rho_str_show(
+    value: u64,
+    descriptor: *const c_char,
+    inspect: bool,
+) -> *mut c_char {
+    new_string(&show(value, &Shape::parse(&string(descriptor)), inspect))
+}
+
+#[test]
+fn test_show() {
+    unsafe {
+        let before = crate::memory::rho_live_objects();
+        assert_eq!(
+            Shape::parse("lrPoint;2;x;iy;t2;fs"),
+            Shape::List(Box::new(Shape::Struct(
+                "Point".to_string(),
+                vec![
+                    ("x".to_string(), Shape::Int),
+                    (
+                        "y".to_string(),
+                        Shape::Tuple(vec![Shape::Float, Shape::Str])
+                    ),
+                ]
+            )))
+        );
+        assert_eq!(Shape::parse("mcl"), {
+            let unknown = Box::new(Shape::List(Box::new(Shape::Unknown)));
+            Shape::Map(Box::new(Shape::Char), unknown)
+        });
+
+        let list = lists::rho_list_new(2, true);
+        lists::rho_list_push(list, new_string("a\"b") as u64);
+        lists::rho_list_push(list, new_string("c") as u64);
+        let map = crate::maps::rho_map_new(crate::maps::RHO_KEYS_BITS, true);
+        crate::maps::rho_map_put(map, 'k' as u64, list as u64);
+        let record = crate::memory::rho_record_new(2, 0b10);
+        crate::memory::rho_record_set(record, 0, (-1i64) as u64);
+        crate::memory::rho_record_set(record, 1, map as u64);
+        let point = Shape::parse("rP;2;x;iy;mcls");
+        assert_eq!(
+            show(record as u64, &point, false),
+            "P{x: -1, y: {'k': [\"a\\\"b\", \"c\"]}}"
+        );
+        assert_eq!(show('k' as u64, &Shape::Char, false), "k");
+        let tree = Shape::parse("rTree;2;value;ichildren;lRTree;");
+        let leaf = crate::memory::rho_record_new(2, 0b10);
+        crate::memory::rho_record_set(leaf, 0, 2);
+        crate::memory::rho_record_set(leaf, 1, lists::rho_list_new(0, true) as u64);
+        let children = lists::rho_list_new(1, true);
+        lists::rho_list_push(children, leaf as u64);
+        let root = crate::memory::rho_record_new(2, 0b10);
+        crate::memory::rho_record_set(root, 1, children as u64);
+        assert_eq!(
+            show(root as u64, &tree, false),
+            "Tree{value: 0, children: [Tree{value: 2, children: []}]}"
+        );
+        crate::memory::rho_release(root);
+        assert_eq!(show(2.0f64.to_bits(), &Shape::Float, false), "2.0");
+        let pair = crate::memory::rho_record_new(2, 0);
+        crate::memory::rho_record_set(pair, 1, 1);
+        assert_eq!(show(pair as u64, &Shape::parse("t2;ib"), true), "{0, true}");
+        crate::memory::rho_release(pair);
+        crate::memory::rho_release(record);
+        assert_eq!(crate::memory::rho_live_objects(), before);
+    }
+}
@@ -0,0 +1,248 @@
+use core::fmt;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use crate::parsers::*;
+use crate::tokens::*;
+
+// An interned atom, two atoms are the same atom exactly when their ids are equal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Atom(pub u32);
+
+// The atoms the runtime produces itself, they are interned first so their ids never change
+pub const OK: Atom = Atom(0);
+pub const ERROR: Atom = Atom(1);
+const WELL_KNOWN: [&str; 2] = ["ok", "error"];
+
+/* Gives every atom name a small integer id, in the order the names are first seen. A table built
+ * from a program with `AtomTable::for_program` numbers its atoms the same way every time, which
+ * is what lets compiled code and the interpreter agree on the ids.
+ */
+#[derive(Debug, Clone)]
+pub struct AtomTable {
+    names: Vec<String>,
+    ids: HashMap<String, Atom>,
+}
+
+impl Default for AtomTable {
+    fn default() -> AtomTable {
+        let mut table = AtomTable {
+            names: vec![],
+            ids: HashMap::new(),
+        };
+        for name in WELL_KNOWN {
+            table.intern(name);
+        }
+        table
+    }
+}
+
+impl AtomTable {
+    pub fn new() -> AtomTable {
+        AtomTable::default()
+    }
+
+    // The well known atoms followed by every atom literal of the program, in source order
+    pub fn for_program(program: &[Expression]) -> AtomTable {
+        let mut table = AtomTable::new();
+        table.intern_program(program);
+        table
+    }
+
+    pub fn intern(&mut self, name: &str) -> Atom {
+        if let Some(atom) = self.ids.get(name) {
+            return *atom;
+        }
+        let atom = Atom(self.names.len() as u32);
+        self.names.push(name.to_owned());
+        self.ids.insert(name.to_owned(), atom);
+        atom
+    }
+
+    pub fn intern_program(&mut self, program: &[Expression]) {
+        for e in program.iter() {
+            self.intern_expression(e);
+        }
+    }
+
+    fn intern_expression(&mut self, expression: &Expression) {
+        if let Expression::Literal {
+            value: TokenValue::Atom(name),
+            ..
+        } = expression
+        {
+            self.intern(name);
+        }
+        for child in expression.children() {
+            self.intern_expression(child);
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Atom> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn name(&self, atom: Atom) -> &str {
+        &self.names[atom.0 as usize]
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+// The table every running program shares, atoms made at runtime by `string_to_atom` go here too
+fn table() -> MutexGuard<'static, AtomTable> {
+    static TABLE: OnceLock<Mutex<AtomTable>> = OnceLock::new();
+    TABLE
+        .get_or_init(|| Mutex::new(AtomTable::new()))
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn intern(name: &str) -> Atom {
+    table().intern(name)
+}
+
+/* Interns a program's atoms before it runs. The ids are the ones `AtomTable::for_program` gives
+ * only for the first program of the process, the REPL and `rho test` run more on the same table,
+ * whose atoms get the ids after those already interned. Nothing relies on them matching, the ids
+ * of a running program never leave the process.
+ */
+pub fn intern_program(program: &[Expression]) {
+    table().intern_program(program)
+}
+
+pub fn atom_to_string(atom: Atom) -> String {
+    table().name(atom).to_owned()
+}
+
+pub fn string_to_atom(name: &str) -> Atom {
+    intern(name)
+}
+
+// The name, without the leading `:`
+impl fmt::Display for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", atom_to_string(*self))
+    }
+}
+
+/* The table as LLVM constants, a null terminated string per atom and an array of them indexed by
+ * the atom's id:
+ *
+ *   @rho.atom.0 = private unnamed_addr constant [3 x i8] c"ok\00"
+ *   @rho.atoms = constant [1 x i8*] [i8* getelementptr inbounds ([3 x i8], [3 x i8]* @rho.atom.0, i32 0, i32 0)]
+ *   @rho.atom_count = constant i32 1
+ */
+pub fn llvm_constants(table: &AtomTable) -> String {
+    let mut out = String::new();
+    let mut entries = vec![];
+    for (id, name) in table.names().iter().enumerate() {
+        let array = format!("[{} x i8]", name.len() + 1);
+        out += &format!(
+            "@rho.atom.{} = private unnamed_addr constant {} c\"{}\\00\"\n",
+            id,
+            array,
+            llvm_escape(name)
+        );
+        entries.push(format!(
+            "i8* getelementptr inbounds ({0}, {0}* @rho.atom.{1}, i32 0, i32 0)",
+            array, id
+        ));
+    }
+    out += &format!(
+        "@rho.atoms = constant [{} x i8*] [{}]\n",
+        table.len(),
+        entries.join(", ")
+    );
+    out += &format!("@rho.atom_count = constant i32 {}\n", table.len());
+    out
+}
+
+// LLVM strings escape anything unprintable, and `"` and `\`, as two hex digits
+pub fn llvm_escape(name: &str) -> String {
+    name.bytes()
+        .map(|b| match b {
+            b'"' | b'\\' => format!("\\{:02X}", b),
+            b' '..=b'~' => (b as char).to_string(),
+            _ => format!("\\{:02X}", b),
+        })
+        .collect()
+}
+
+#[test]
+fn test_atom_table() {
+    let program =
+        parse("x = {:error, :found}\nif x == {:ok, :found} {\n    y = :missing\n}\n").unwrap();
+    let table = AtomTable::for_program(&program);
+    assert_eq!(table.names(), ["ok", "error", "found", "missing"]);
+    assert_eq!(table.get("error"), Some(ERROR));
+    assert_eq!(table.get("nothing"), None);
+    // the same program always numbers its atoms the same way
+    assert_eq!(AtomTable::for_program(&program).names(), table.names());
+
+    let atom = string_to_atom("interned at runtime");
+    assert_eq!(string_to_atom("interned at runtime"), atom);
+    assert_eq!(atom_to_string(atom), "interned at runtime");
+    assert_eq!(intern("ok"), OK);
+}
+
+#[test]
+fn test_llvm_constants() {
+    let mut table = AtomTable::new();
+    table.intern("a\"b");
+    assert_eq!(
+        llvm_constants(&table),
+        "@rho.atom.0 = private unnamed_addr constant [3 x i8] c\"ok\\00\"
+@rho.atom.1 = private unnamed_addr constant [6 x i8] c\"error\\00\"
+@rho.atom.2 = private unnamed_addr constant [4 x i8] c\"a\\22b\\00\"
+@rho.atoms = constant [3 x i8*] [\
+i8* getelementptr inbounds ([3 x i8], [3 x i8]* @rho.atom.0, i32 0, i32 0), \
+i8* getelementptr inbounds ([6 x i8], [6 x i8]* @rho.atom.1, i32 0, i32 0), \
+i8* getelementptr inbounds ([4 x i8], [4 x i8]* @rho.atom.2, i32 0, i32 0)]
+@rho.atom_count = constant i32 3
+"
+    );
+}
+
+#[test]
+fn test_atom_builtins() {
+    let (result, output) = crate::interpreter::run_source(
+        "a = string_to_atom(\"ok\")
+IO.inspect(a == :ok)
+IO.puts(atom_to_string(:error) <> \"!\")
+return {:error, string_to_atom(IO.read_line())}
+",
+        "reason\n",
+    );
+    assert_eq!(result, Ok(1));
+    assert_eq!(output, "true\nerror!\n");
+}
+
+#[test]
+fn test_atom_builtins_native() {
+    let source = "a = string_to_atom(\"ok\")
+IO.inspect(a == :ok)
+IO.puts(atom_to_string(:error) <> \"!\")
+b = string_to_atom(\"made\" <> \"_later\")
+IO.inspect(b)
+IO.inspect(b == string_to_atom(atom_to_string(b)))
+";
+    let Some((code, stdout, stderr)) = crate::codegen::run_native(source, "") else {
+        return;
+    };
+    let (result, output) = crate::interpreter::run_source(source, "");
+    assert_eq!(stdout, output);
+    assert_eq!(output, "true\nerror!\n:made_later\ntrue\n");
+    assert_eq!(code, Some(result.unwrap()), "{}", stderr);
+    assert!(!stderr.contains("still live"), "{}", stderr);
+}
@@ -0,0 +1,1201 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::atoms;
+use crate::diagnostics::Diagnostic;
+use crate::mir::{
+    self, BinOp, BlockId, Callee, Constant, LocalId, Operand, Pattern, Place, Rvalue, Statement,
+    Step, Terminator, UnOp,
+};
+use crate::rho_core::{self, NativeFn};
+use crate::tokens::*;
+use crate::value::Value;
+
+/* The bytecode `vm` runs, compiled from the `mir` of a program. Each function is a `Chunk` of
+ * code, a byte for the op and then its operands, most of them 2 byte indexes into the chunk's
+ * constants, its nested functions or its local slots. Every local of the MIR gets a slot of its
+ * own, the parameters first, and a local a closure captures lives in a cell instead, a scope of
+ * its own that the closures made share:
+ *
+ *     func counter() -> fn() -> int { var n = 0; return fn -> { n = n + 1; return n } }
+ *
+ * here `n` is a cell of `counter` and an upvalue of the lambda. The globals of the MIR, and then
+ * the top level functions, are globals every function reaches by their index. The blocks are laid
+ * out in order and a jump goes to the address its block starts at.
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Op {
+    Constant, // constant index
+    Unit,
+    True,
+    False,
+    Pop,
+    GetLocal, // slot
+    SetLocal,
+    GetCell, // cell of the running function
+    SetCell,
+    FreshCell, // a new cell, still unset, for every time a captured local's declaration runs
+    GetUpvalue, // cell of the running closure
+    SetUpvalue,
+    GetGlobal, // global index
+    SetGlobal,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Power,
+    ShiftLeft,
+    ShiftRight,
+    Concat,
+    Append, // `++`
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    Negate,
+    Not,
+    Jump, // to the address of the operand
+    JumpIfFalse,
+    JumpIfTrue,
+    Call,       // argument count, the function is below the arguments
+    CallNative, // native index, argument count
+    CallMethod, // method name constant, argument count, by the type of the first argument
+    Return,
+    Closure, // function index
+    List,    // element count
+    Tuple,
+    Map,     // entry count
+    Struct,  // constant `{name, field...}` with the fields in declaration order
+    Variant, // constant `{enum, variant}`, value count
+    Range,
+    SteppedRange,
+    GetField, // field name constant
+    GetIndex,
+    SetPath, // path constant, target kind, target slot, see `Assembler::statement`
+    Iterate, // starts a `for` over the value on top
+    Next,    // pushes the next element, or ends the `for` and jumps
+    EndIterate,
+    Match, // pattern index, pops the subject and pushes whether it matched
+    Show,  // the str `IO.inspect` prints for the value on top
+    Enter, // function name constant, the frame of an inlined call for stack traces
+    Leave,
+    Fail, // prefix constant, whether to show the stack trace, pops the message
+}
+
+const OPS: [Op; 58] = [
+    Op::Constant,
+    Op::Unit,
+    Op::True,
+    Op::False,
+    Op::Pop,
+    Op::GetLocal,
+    Op::SetLocal,
+    Op::GetCell,
+    Op::SetCell,
+    Op::FreshCell,
+    Op::GetUpvalue,
+    Op::SetUpvalue,
+    Op::GetGlobal,
+    Op::SetGlobal,
+    Op::Add,
+    Op::Subtract,
+    Op::Multiply,
+    Op::Divide,
+    Op::Remainder,
+    Op::Power,
+    Op::ShiftLeft,
+    Op::ShiftRight,
+    Op::Concat,
+    Op::Append,
+    Op::Equal,
+    Op::NotEqual,
+    Op::Less,
+    Op::Greater,
+    Op::LessEqual,
+    Op::GreaterEqual,
+    Op::Negate,
+    Op::Not,
+    Op::Jump,
+    Op::JumpIfFalse,
+    Op::JumpIfTrue,
+    Op::Call,
+    Op::CallNative,
+    Op::CallMethod,
+    Op::Return,
+    Op::Closure,
+    Op::List,
+    Op::Tuple,
+    Op::Map,
+    Op::Struct,
+    Op::Variant,
+    Op::Range,
+    Op::SteppedRange,
+    Op::GetField,
+    Op::GetIndex,
+    Op::SetPath,
+    Op::Iterate,
+    Op::Next,
+    Op::EndIterate,
+    Op::Match,
+    Op::Show,
+    Op::Enter,
+    Op::Leave,
+    Op::Fail,
+];
+
+impl Op {
+    pub fn decode(byte: u8) -> Op {
+        OPS[byte as usize]
+    }
+
+    // The sizes of the operands that follow the op, in bytes
+    pub fn operands(self) -> &'static [usize] {
+        match self {
+            Op::Call => &[1],
+            Op::CallNative | Op::CallMethod | Op::Variant | Op::Fail => &[2, 1],
+            Op::SetPath => &[2, 1, 2],
+            Op::Constant
+            | Op::GetLocal
+            | Op::SetLocal
+            | Op::GetCell
+            | Op::SetCell
+            | Op::FreshCell
+            | Op::GetUpvalue
+            | Op::SetUpvalue
+            | Op::GetGlobal
+            | Op::SetGlobal
+            | Op::Jump
+            | Op::JumpIfFalse
+            | Op::JumpIfTrue
+            | Op::Closure
+            | Op::List
+            | Op::Tuple
+            | Op::Map
+            | Op::Struct
+            | Op::GetField
+            | Op::Next
+            | Op::Match
+            | Op::Enter => &[2],
+            _ => &[],
+        }
+    }
+
+    pub fn size(self) -> usize {
+        1 + self.operands().iter().sum::<usize>()
+    }
+
+    // The operator of a binary op, for the errors and for `interpreter::binary`
+    pub fn operator(self) -> Option<Operators> {
+        Some(match self {
+            Op::Add => Operators::Add,
+            Op::Subtract => Operators::Subtract,
+            Op::Multiply => Operators::Mult,
+            Op::Divide => Operators::Div,
+            Op::Remainder => Operators::Modulo,
+            Op::Power => Operators::Exp,
+            Op::ShiftLeft => Operators::Lshift,
+            Op::ShiftRight => Operators::Rshift,
+            Op::Concat => Operators::Concat,
+            Op::Append => Operators::EnumConcat,
+            Op::Equal => Operators::BEq,
+            Op::NotEqual => Operators::BNEq,
+            Op::Less => Operators::LessThan,
+            Op::Greater => Operators::GreaterThan,
+            Op::LessEqual => Operators::LEq,
+            Op::GreaterEqual => Operators::GEq,
+            _ => return None,
+        })
+    }
+
+    fn binary(op: BinOp) -> Op {
+        match op {
+            BinOp::Add => Op::Add,
+            BinOp::Sub => Op::Subtract,
+            BinOp::Mul => Op::Multiply,
+            BinOp::Div => Op::Divide,
+            BinOp::Rem => Op::Remainder,
+            BinOp::Pow => Op::Power,
+            BinOp::Shl => Op::ShiftLeft,
+            BinOp::Shr => Op::ShiftRight,
+            BinOp::Concat => Op::Concat,
+            BinOp::Append => Op::Append,
+            BinOp::Eq => Op::Equal,
+            BinOp::Ne => Op::NotEqual,
+            BinOp::Lt => Op::Less,
+            BinOp::Gt => Op::Greater,
+            BinOp::Le => Op::LessEqual,
+            BinOp::Ge => Op::GreaterEqual,
+            BinOp::AddOverflows | BinOp::WrappingAdd => {
+                unreachable!("Only the native backend's MIR checks for overflow itself")
+            }
+        }
+    }
+
+    // ie. "jump_if_false"
+    pub fn name(self) -> String {
+        let mut name = String::new();
+        for (i, c) in format!("{:?}", self).chars().enumerate() {
+            if c.is_uppercase() && i > 0 {
+                name.push('_');
+            }
+            name.push(c.to_ascii_lowercase());
+        }
+        name
+    }
+}
+
+// Where a binding lives, `SetPath` and `Match` take one as a kind and a slot
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Local(u16),
+    Cell(u16),
+    Upvalue(u16),
+    Global(u16),
+}
+
+impl Target {
+    pub fn kind(self) -> (u8, u16) {
+        match self {
+            Target::Local(slot) => (0, slot),
+            Target::Cell(slot) => (1, slot),
+            Target::Upvalue(slot) => (2, slot),
+            Target::Global(slot) => (3, slot),
+        }
+    }
+
+    pub fn from_kind(kind: u8, slot: u16) -> Target {
+        match kind {
+            0 => Target::Local(slot),
+            1 => Target::Cell(slot),
+            2 => Target::Upvalue(slot),
+            _ => Target::Global(slot),
+        }
+    }
+}
+
+// Where a closure being made finds a variable it captures, in the function making it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capture {
+    Cell(u16),
+    Upvalue(u16),
+}
+
+// A `match` pattern, with its bindings turned into the slots they are declared in
+#[derive(Debug, Clone, PartialEq)]
+pub enum Matcher {
+    Wildcard,
+    Bind(Target),
+    Literal(Value),
+    Tuple(Vec<Matcher>),
+    List(Vec<Matcher>),
+    Variant(String, Option<String>, Vec<Matcher>),
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Value>,
+    pub functions: Vec<Rc<Function>>,
+    pub natives: Vec<(&'static str, NativeFn)>,
+    pub patterns: Vec<Matcher>,
+    spans: Vec<(usize, Span)>, // where the code of each statement starts
+}
+
+impl Chunk {
+    // The statement the op at `offset` is part of, for the errors it reports
+    pub fn span(&self, offset: usize) -> Span {
+        match self.spans.partition_point(|(start, _)| *start <= offset) {
+            0 => Span::default(),
+            i => self.spans[i - 1].1,
+        }
+    }
+
+    pub fn read_u16(&self, offset: usize) -> usize {
+        u16::from_le_bytes([self.code[offset], self.code[offset + 1]]) as usize
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Function {
+    pub name: String, // "fn" for lambdas
+    pub arity: usize,
+    pub locals: Vec<String>, // the names of the slots, parameters first
+    pub cells: Vec<String>,
+    pub upvalues: Vec<String>,
+    pub captures: Vec<Capture>, // one for each upvalue
+    pub chunk: Chunk,
+}
+
+pub struct Program {
+    pub script: Rc<Function>, // the top level statements, which end by calling `main`
+    pub globals: Vec<String>,
+    pub functions: HashMap<String, u16>, // the globals of the top level functions, by path
+}
+
+/* Compiles the MIR `mir::lower_for_vm` gives. The only errors are the limits of the format, ie. a
+ * function with more than 65535 constants.
+ */
+pub fn compile(program: &mir::Program) -> Result<Program, Diagnostic> {
+    let mut globals: Vec<String> = program.globals.iter().map(|g| g.name.clone()).collect();
+    let mut functions = HashMap::new();
+    for f in program.functions.iter() {
+        functions.insert(f.name.clone(), globals.len() as u16);
+        globals.push(f.name.clone());
+    }
+    if globals.len() > u16::MAX as usize {
+        let message = "the VM doesn't support more than 65535 globals yet";
+        return Err(Diagnostic::error(message, Span::default()));
+    }
+    // a closure only makes closures compiled before it, which have smaller ids
+    let mut closures: Vec<Rc<Function>> = vec![];
+    for (f, captures) in program.closures.iter().zip(captures(program)) {
+        let mut function = assemble(&functions, &closures, f, &[])?;
+        function.captures = captures;
+        closures.push(Rc::new(function));
+    }
+    let mut made = vec![];
+    for f in program.functions.iter() {
+        made.push(Rc::new(assemble(&functions, &closures, f, &[])?));
+    }
+    let script = assemble(&functions, &closures, &program.entry, &made)?;
+    Ok(Program {
+        script: Rc::new(script),
+        globals,
+        functions,
+    })
+}
+
+// Where each local of a function lives, and the names of its slots and cells
+fn layout(f: &mir::Function) -> (Vec<Target>, Vec<String>, Vec<String>) {
+    let name = |id: LocalId| f.locals[id].name.clone().unwrap_or(format!("_{}", id));
+    let mut targets = vec![Target::Local(0); f.locals.len()];
+    let (mut locals, mut cells) = (vec![], vec![]);
+    // the arguments are the first slots, a captured parameter is copied into its cell
+    for &id in f.params.iter() {
+        targets[id] = Target::Local(locals.len() as u16);
+        locals.push(name(id));
+    }
+    for (id, local) in f.locals.iter().enumerate() {
+        if local.captured {
+            targets[id] = Target::Cell(cells.len() as u16);
+            cells.push(name(id));
+        } else if !f.params.contains(&id) {
+            targets[id] = Target::Local(locals.len() as u16);
+            locals.push(name(id));
+        }
+    }
+    (targets, locals, cells)
+}
+
+// What each closure captures, the cells and upvalues the function making it gives it
+fn captures(program: &mir::Program) -> Vec<Vec<Capture>> {
+    let mut captures = vec![vec![]; program.closures.len()];
+    let makers = (program.closures.iter())
+        .chain(program.functions.iter())
+        .chain([&program.entry]);
+    for f in makers {
+        let (targets, _, _) = layout(f);
+        for statement in f.blocks.iter().flat_map(|b| b.statements.iter()) {
+            let Statement::Assign {
+                value: Rvalue::Closure(id, places),
+                ..
+            } = statement
+            else {
+                continue;
+            };
+            captures[*id] = (places.iter())
+                .map(|place| match (*place, place_target(&targets, *place)) {
+                    (_, Target::Cell(cell)) => Capture::Cell(cell),
+                    (Place::Upvalue(upvalue), _) => Capture::Upvalue(upvalue as u16),
+                    _ => unreachable!("A closure should only capture cells and upvalues"),
+                })
+                .collect();
+        }
+    }
+    captures
+}
+
+// The blocks control can reach from the first, in order, only those are laid out
+fn reachable(f: &mir::Function) -> Vec<BlockId> {
+    let mut seen = vec![false; f.blocks.len()];
+    let mut stack = vec![0];
+    while let Some(block) = stack.pop() {
+        if !std::mem::replace(&mut seen[block], true) {
+            stack.extend(f.blocks[block].terminator.successors());
+        }
+    }
+    (0..f.blocks.len()).filter(|b| seen[*b]).collect()
+}
+
+fn place_target(targets: &[Target], place: Place) -> Target {
+    match place {
+        Place::Local(id) => targets[id],
+        Place::Upvalue(id) => Target::Upvalue(id as u16),
+        Place::Global(id) => Target::Global(id as u16),
+    }
+}
+
+fn value(constant: &Constant) -> Value {
+    match constant {
+        Constant::Int(i) => Value::Int(*i),
+        Constant::Float(f) => Value::Float(*f),
+        Constant::Bool(b) => Value::Bool(*b),
+        Constant::Char(c) => Value::Char(*c),
+        Constant::Atom(name) => Value::Atom(atoms::intern(name)),
+        Constant::Str(s) => Value::string(s),
+        Constant::Unit => Value::Unit,
+    }
+}
+
+// A function being compiled
+struct Assembler<'a> {
+    functions: &'a HashMap<String, u16>,
+    closures: &'a [Rc<Function>],
+    function: Function,
+    targets: Vec<Target>,
+    starts: Vec<usize>,           // the address of each block laid out
+    jumps: Vec<(usize, BlockId)>, // the jump operands to point at their blocks
+    span: Span,                   // of the statement being compiled, the ops emitted for it get it
+    error: Option<Diagnostic>,
+}
+
+/* Compiles one function, `made` are the top level functions the script makes before anything else
+ * runs, so any of them can call any other.
+ */
+fn assemble(
+    functions: &HashMap<String, u16>,
+    closures: &[Rc<Function>],
+    f: &mir::Function,
+    made: &[Rc<Function>],
+) -> Result<Function, Diagnostic> {
+    let (targets, locals, cells) = layout(f);
+    let upvalues = (f.upvalues.iter())
+        .map(|u| u.name.clone().unwrap_or_default())
+        .collect();
+    let mut a = Assembler {
+        functions,
+        closures,
+        function: Function {
+            name: f.name.clone(),
+            arity: f.params.len(),
+            locals,
+            cells,
+            upvalues,
+            ..Function::default()
+        },
+        targets,
+        starts: vec![0; f.blocks.len()],
+        jumps: vec![],
+        span: f.span,
+        error: None,
+    };
+    a.index(a.function.locals.len(), "locals in a function");
+    a.index(a.function.cells.len(), "captured locals in a function");
+    for (k, &id) in f.params.iter().enumerate() {
+        if let Target::Cell(cell) = a.targets[id] {
+            a.emit(Op::GetLocal, &[k]);
+            a.emit(Op::FreshCell, &[cell as usize]);
+            a.emit(Op::SetCell, &[cell as usize]);
+        }
+    }
+    for function in made.iter() {
+        let i = a.nested(function);
+        a.emit(Op::Closure, &[i]);
+        a.emit(Op::SetGlobal, &[functions[&function.name] as usize]);
+    }
+    let order = reachable(f);
+    for (i, &block) in order.iter().enumerate() {
+        a.starts[block] = a.function.chunk.code.len();
+        for statement in f.blocks[block].statements.iter() {
+            a.statement(statement);
+        }
+        a.terminator(&f.blocks[block].terminator, order.get(i + 1).copied());
+    }
+    for (at, block) in std::mem::take(&mut a.jumps) {
+        let address = a.index(a.starts[block], "bytes in a function") as u16;
+        a.function.chunk.code[at..at + 2].copy_from_slice(&address.to_le_bytes());
+    }
+    match a.error {
+        Some(d) => Err(d),
+        None => Ok(a.function),
+    }
+}
+
+impl Assembler<'_> {
+    fn fail(&mut self, message: &str) {
+        if self.error.is_none() {
+            self.error = Some(Diagnostic::error(message, self.span));
+        }
+    }
+
+    fn index(&mut self, i: usize, what: &str) -> usize {
+        if i > u16::MAX as usize {
+            self.fail(&format!(
+                "the VM doesn't support more than 65535 {} yet",
+                what
+            ));
+        }
+        i
+    }
+
+    fn emit(&mut self, op: Op, operands: &[usize]) -> usize {
+        let span = self.span;
+        let chunk = &mut self.function.chunk;
+        let offset = chunk.code.len();
+        if chunk.spans.last().map(|(_, s)| *s) != Some(span) {
+            chunk.spans.push((offset, span));
+        }
+        chunk.code.push(op as u8);
+        for (operand, size) in operands.iter().zip(op.operands()) {
+            let bytes = (*operand as u16).to_le_bytes();
+            chunk.code.extend_from_slice(&bytes[..*size]);
+        }
+        offset
+    }
+
+    fn constant(&mut self, value: Value) -> usize {
+        let constants = &mut self.function.chunk.constants;
+        let i = match constants.iter().position(|c| *c == value) {
+            Some(i) => i,
+            None => {
+                constants.push(value);
+                constants.len() - 1
+            }
+        };
+        self.index(i, "constants in a function")
+    }
+
+    // The index of a function this one makes in its chunk
+    fn nested(&mut self, function: &Rc<Function>) -> usize {
+        let functions = &mut self.function.chunk.functions;
+        let i = match functions.iter().position(|f| Rc::ptr_eq(f, function)) {
+            Some(i) => i,
+            None => {
+                functions.push(function.clone());
+                functions.len() - 1
+            }
+        };
+        self.index(i, "functions in a function")
+    }
+
+    // A jump to a block, pointed at it once every block is laid out
+    fn jump(&mut self, op: Op, block: BlockId) {
+        let at = self.emit(op, &[u16::MAX as usize]) + 1;
+        self.jumps.push((at, block));
+    }
+
+    // Nothing when the block is laid out next
+    fn goto(&mut self, block: BlockId, next: Option<BlockId>) {
+        if next != Some(block) {
+            self.jump(Op::Jump, block);
+        }
+    }
+
+    fn target(&self, place: Place) -> Target {
+        place_target(&self.targets, place)
+    }
+
+    fn get(&mut self, place: Place) {
+        let (op, slot) = match self.target(place) {
+            Target::Local(slot) => (Op::GetLocal, slot),
+            Target::Cell(slot) => (Op::GetCell, slot),
+            Target::Upvalue(slot) => (Op::GetUpvalue, slot),
+            Target::Global(slot) => (Op::GetGlobal, slot),
+        };
+        self.emit(op, &[slot as usize]);
+    }
+
+    fn set(&mut self, place: Place) {
+        let (op, slot) = match self.target(place) {
+            Target::Local(slot) => (Op::SetLocal, slot),
+            Target::Cell(slot) => (Op::SetCell, slot),
+            Target::Upvalue(slot) => (Op::SetUpvalue, slot),
+            Target::Global(slot) => (Op::SetGlobal, slot),
+        };
+        self.emit(op, &[slot as usize]);
+    }
+
+    fn operand(&mut self, operand: &Operand) {
+        match operand {
+            Operand::Copy(place) | Operand::Move(place) => self.get(*place),
+            Operand::Constant(Constant::Bool(true)) => {
+                self.emit(Op::True, &[]);
+            }
+            Operand::Constant(Constant::Bool(false)) => {
+                self.emit(Op::False, &[]);
+            }
+            Operand::Constant(Constant::Unit) => {
+                self.emit(Op::Unit, &[]);
+            }
+            Operand::Constant(c) => {
+                let i = self.constant(value(c));
+                self.emit(Op::Constant, &[i]);
+            }
+        }
+    }
+
+    // Pushes the arguments of a call, and how many there are
+    fn arguments(&mut self, args: &[Operand]) -> usize {
+        for arg in args.iter() {
+            self.operand(arg);
+        }
+        if args.len() > u8::MAX as usize {
+            self.fail("the VM doesn't support more than 255 arguments in a call yet");
+        }
+        args.len()
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Assign { place, value, span } => {
+                self.span = *span;
+                self.rvalue(value);
+                self.set(*place);
+            }
+            Statement::Call {
+                result,
+                callee,
+                args,
+                span,
+            } => {
+                self.span = *span;
+                self.call(callee, args);
+                match (result, callee) {
+                    (_, Callee::Enter(_) | Callee::Leave) => {}
+                    (Some(place), _) => self.set(*place),
+                    (None, _) => {
+                        self.emit(Op::Pop, &[]);
+                    }
+                }
+            }
+            Statement::Match {
+                result,
+                subject,
+                pattern,
+                span,
+            } => {
+                self.span = *span;
+                self.operand(subject);
+                let matcher = self.matcher(pattern);
+                self.function.chunk.patterns.push(matcher);
+                let i = self.function.chunk.patterns.len() - 1;
+                let i = self.index(i, "patterns in a function");
+                self.emit(Op::Match, &[i]);
+                self.set(*result);
+            }
+            // the value, then the indexes in the order the path goes from the place in
+            Statement::SetPath {
+                place,
+                path,
+                value,
+                span,
+            } => {
+                self.span = *span;
+                self.operand(value);
+                let mut steps = vec![];
+                for step in path.iter() {
+                    match step {
+                        Step::Field(name) => steps.push(Value::string(name)),
+                        Step::Index(i) => {
+                            self.operand(i);
+                            steps.push(Value::Unit);
+                        }
+                    }
+                }
+                let steps = self.constant(Value::list(steps));
+                let (kind, slot) = self.target(*place).kind();
+                self.emit(Op::SetPath, &[steps, kind as usize, slot as usize]);
+            }
+            Statement::Fresh(place) => {
+                if let Target::Cell(cell) = self.target(*place) {
+                    self.emit(Op::FreshCell, &[cell as usize]);
+                }
+            }
+            Statement::Iterate { iterable, span } => {
+                self.span = *span;
+                self.operand(iterable);
+                self.emit(Op::Iterate, &[]);
+            }
+            Statement::EndIterate => {
+                self.emit(Op::EndIterate, &[]);
+            }
+            // the VM's values are counted by `Rc`
+            Statement::Drop(_) => {}
+        }
+    }
+
+    fn rvalue(&mut self, value: &Rvalue) {
+        match value {
+            Rvalue::Use(a) => self.operand(a),
+            Rvalue::Binary(op, a, b) => {
+                self.operand(a);
+                self.operand(b);
+                self.emit(Op::binary(*op), &[]);
+            }
+            Rvalue::Unary(op, a) => {
+                self.operand(a);
+                let op = match op {
+                    UnOp::Neg => Op::Negate,
+                    UnOp::Not => Op::Not,
+                };
+                self.emit(op, &[]);
+            }
+            Rvalue::List(values) | Rvalue::Tuple(values) => {
+                for v in values.iter() {
+                    self.operand(v);
+                }
+                let n = self.index(values.len(), "elements in a literal");
+                let op = match value {
+                    Rvalue::List(_) => Op::List,
+                    _ => Op::Tuple,
+                };
+                self.emit(op, &[n]);
+            }
+            Rvalue::Map(entries) => {
+                for (k, v) in entries.iter() {
+                    self.operand(k);
+                    self.operand(v);
+                }
+                let n = self.index(entries.len(), "entries in a literal");
+                self.emit(Op::Map, &[n]);
+            }
+            Rvalue::Struct(name, fields) => {
+                let mut names = vec![Value::string(name)];
+                for (field, v) in fields.iter() {
+                    self.operand(v);
+                    names.push(Value::string(field));
+                }
+                let names = self.constant(Value::tuple(names));
+                self.emit(Op::Struct, &[names]);
+            }
+            Rvalue::Variant(name, variant, values) => {
+                let n = self.arguments(values);
+                let names = self.constant(Value::tuple(vec![
+                    Value::string(name),
+                    Value::string(variant),
+                ]));
+                self.emit(Op::Variant, &[names, n]);
+            }
+            Rvalue::Range(start, end, step) => {
+                self.operand(start);
+                self.operand(end);
+                match step {
+                    Some(step) => {
+                        self.operand(step);
+                        self.emit(Op::SteppedRange, &[]);
+                    }
+                    None => {
+                        self.emit(Op::Range, &[]);
+                    }
+                }
+            }
+            Rvalue::Field(a, name) => {
+                self.operand(a);
+                let name = self.constant(Value::string(name));
+                self.emit(Op::GetField, &[name]);
+            }
+            Rvalue::Index(a, i) => {
+                self.operand(a);
+                self.operand(i);
+                self.emit(Op::GetIndex, &[]);
+            }
+            Rvalue::Closure(id, _) => {
+                let function = self.closures[*id].clone();
+                let i = self.nested(&function);
+                self.emit(Op::Closure, &[i]);
+            }
+            Rvalue::Function(name) => {
+                let slot = self.functions[name];
+                self.emit(Op::GetGlobal, &[slot as usize]);
+            }
+            Rvalue::Builtin(name) => match rho_core::builtin(name) {
+                Some(builtin) => {
+                    let i = self.constant(Value::Builtin(builtin.name));
+                    self.emit(Op::Constant, &[i]);
+                }
+                None => self.fail(&format!("unknown builtin `{}`", name)),
+            },
+            Rvalue::Cast(..) | Rvalue::Select(..) => {
+                unreachable!("Only the native backend's MIR has casts and selects")
+            }
+        }
+    }
+
+    fn call(&mut self, callee: &Callee, args: &[Operand]) {
+        match callee {
+            Callee::Function(name) => {
+                let slot = self.functions[name];
+                self.emit(Op::GetGlobal, &[slot as usize]);
+                let n = self.arguments(args);
+                self.emit(Op::Call, &[n]);
+            }
+            // the function is the first argument
+            Callee::Value => {
+                let n = self.arguments(args);
+                self.emit(Op::Call, &[n - 1]);
+            }
+            Callee::Method(method) => {
+                let n = self.arguments(args);
+                let method = self.constant(Value::string(method));
+                self.emit(Op::CallMethod, &[method, n]);
+            }
+            Callee::Show => {
+                self.arguments(args);
+                self.emit(Op::Show, &[]);
+            }
+            Callee::Enter(name) => {
+                let name = self.constant(Value::string(name));
+                self.emit(Op::Enter, &[name]);
+            }
+            Callee::Leave => {
+                self.emit(Op::Leave, &[]);
+            }
+            Callee::Print | Callee::Puts | Callee::Inspect | Callee::Builtin(_) => {
+                let n = self.arguments(args);
+                let name = callee.to_string();
+                let Some(builtin) = rho_core::builtin(&name) else {
+                    return self.fail(&format!("unknown builtin `{}`", name));
+                };
+                let natives = &mut self.function.chunk.natives;
+                let i = match natives.iter().position(|(name, _)| *name == builtin.name) {
+                    Some(i) => i,
+                    None => {
+                        natives.push((builtin.name, builtin.function));
+                        natives.len() - 1
+                    }
+                };
+                let i = self.index(i, "builtins in a function");
+                self.emit(Op::CallNative, &[i, n]);
+            }
+        }
+    }
+
+    fn terminator(&mut self, terminator: &Terminator, next: Option<BlockId>) {
+        match terminator {
+            Terminator::Goto(block) => self.goto(*block, next),
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.operand(condition);
+                if next == Some(*otherwise) {
+                    self.jump(Op::JumpIfTrue, *then);
+                } else {
+                    self.jump(Op::JumpIfFalse, *otherwise);
+                    self.goto(*then, next);
+                }
+            }
+            Terminator::Return => {
+                self.get(Place::Local(0));
+                self.emit(Op::Return, &[]);
+            }
+            Terminator::Fail {
+                prefix,
+                message,
+                span,
+                traced,
+            } => {
+                self.span = *span;
+                self.operand(message);
+                let prefix = self.constant(Value::string(prefix));
+                self.emit(Op::Fail, &[prefix, *traced as usize]);
+            }
+            Terminator::Unreachable => {
+                let message = self.constant(Value::string("unreachable code was reached"));
+                self.emit(Op::Constant, &[message]);
+                let prefix = self.constant(Value::string(""));
+                self.emit(Op::Fail, &[prefix, 0]);
+            }
+            // the element is on top when the pass goes on
+            Terminator::Next {
+                place,
+                body,
+                done,
+                span,
+            } => {
+                self.span = *span;
+                self.jump(Op::Next, *done);
+                match place {
+                    Some(place) => self.set(*place),
+                    None => {
+                        self.emit(Op::Pop, &[]);
+                    }
+                }
+                self.goto(*body, next);
+            }
+        }
+    }
+
+    fn matcher(&self, pattern: &Pattern) -> Matcher {
+        let all = |patterns: &[Pattern]| patterns.iter().map(|p| self.matcher(p)).collect();
+        match pattern {
+            Pattern::Wildcard => Matcher::Wildcard,
+            Pattern::Bind(place) => Matcher::Bind(self.target(*place)),
+            Pattern::Literal(c) => Matcher::Literal(value(c)),
+            Pattern::Tuple(patterns) => Matcher::Tuple(all(patterns)),
+            Pattern::List(patterns) => Matcher::List(all(patterns)),
+            Pattern::Variant(name, variant, patterns) => {
+                Matcher::Variant(name.clone(), variant.clone(), all(patterns))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Matcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let all = |matchers: &[Matcher]| {
+            matchers
+                .iter()
+                .map(|m| m.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        match self {
+            Matcher::Wildcard => write!(f, "_"),
+            Matcher::Bind(target) => {
+                let (kind, slot) = target.kind();
+                write!(
+                    f,
+                    "{} {}",
+                    ["local", "cell", "upvalue", "global"][kind as usize],
+                    slot
+                )
+            }
+            Matcher::Literal(value) => write!(f, "{}", rho_core::inspect(value)),
+            Matcher::Tuple(matchers) => write!(f, "{{{}}}", all(matchers)),
+            Matcher::List(matchers) => write!(f, "[{}]", all(matchers)),
+            Matcher::Variant(name, variant, matchers) => {
+                write!(f, "{}.{}", name, variant.as_deref().unwrap_or("?"))?;
+                if !matchers.is_empty() {
+                    write!(f, "({})", all(matchers))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/* What `rho disasm` prints, ie.
+ *
+ *     fn fib/1 {
+ *         locals: n
+ *         0000  2:8    get_local 0        // n
+ *         0003         constant 0         // 2
+ *
+ * with the source position where a statement starts and what an operand stands for. The
+ * functions and lambdas a function makes come after it.
+ */
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.globals.is_empty() {
+            writeln!(f, "globals: {}\n", self.globals.join(", "))?;
+        }
+        disassemble(&self.script, &self.globals, f)
+    }
+}
+
+fn disassemble(function: &Function, globals: &[String], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(f, "fn {}/{} {{", function.name, function.arity)?;
+    for (what, names) in [
+        ("locals", &function.locals),
+        ("cells", &function.cells),
+        ("upvalues", &function.upvalues),
+    ] {
+        if !names.is_empty() {
+            writeln!(f, "    {}: {}", what, names.join(", "))?;
+        }
+    }
+    let chunk = &function.chunk;
+    let mut offset = 0;
+    let mut last_span = None;
+    while offset < chunk.code.len() {
+        let op = Op::decode(chunk.code[offset]);
+        let mut operands = vec![];
+        let mut at = offset + 1;
+        for size in op.operands() {
+            operands.push(match size {
+                1 => chunk.code[at] as usize,
+                _ => chunk.read_u16(at),
+            });
+            at += size;
+        }
+        let span = chunk.span(offset);
+        let position = match last_span {
+            Some(last) if last == span => String::new(),
+            _ if span == Span::default() => String::new(),
+            _ => format!("{}:{}", span.line, span.col),
+        };
+        last_span = Some(span);
+        let mut text = op.name();
+        for operand in operands.iter() {
+            text += &format!(" {}", operand);
+        }
+        let name = |names: &[String], i: usize| names.get(i).cloned().unwrap_or_default();
+        let comment = match op {
+            Op::Constant
+            | Op::Struct
+            | Op::Variant
+            | Op::GetField
+            | Op::CallMethod
+            | Op::Enter
+            | Op::Fail => rho_core::inspect(&chunk.constants[operands[0]]),
+            Op::GetLocal | Op::SetLocal => name(&function.locals, operands[0]),
+            Op::GetCell | Op::SetCell | Op::FreshCell => name(&function.cells, operands[0]),
+            Op::GetUpvalue | Op::SetUpvalue => name(&function.upvalues, operands[0]),
+            Op::GetGlobal | Op::SetGlobal => name(globals, operands[0]),
+            Op::CallNative => chunk.natives[operands[0]].0.to_string(),
+            Op::Closure => {
+                let nested = &chunk.functions[operands[0]];
+                format!("{}/{}", nested.name, nested.arity)
+            }
+            Op::Match => chunk.patterns[operands[0]].to_string(),
+            Op::SetPath => {
+                let names = [&function.locals, &function.cells, &function.upvalues];
+                let root = match operands[1] {
+                    3 => name(globals, operands[2]),
+                    kind => name(names[kind], operands[2]),
+                };
+                let mut path = root;
+                if let Value::List(steps) = &chunk.constants[operands[0]] {
+                    for step in steps.iter() {
+                        match step {
+                            Value::String(field) => path += &format!(".{}", field),
+                            _ => path += "[]",
+                        }
+                    }
+                }
+                path
+            }
+            _ => String::new(),
+        };
+        let line = format!("    {:04}  {:<7}{}", offset, position, text);
+        match comment.as_str() {
+            "" => writeln!(f, "{}", line)?,
+            comment => writeln!(f, "{:<40}// {}", line, comment)?,
+        }
+        offset = at;
+    }
+    writeln!(f, "}}")?;
+    for nested in chunk.functions.iter() {
+        writeln!(f)?;
+        disassemble(nested, globals, f)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub fn compile_source(source: &str, passes: &[crate::passes::Pass]) -> Program {
+    let (compiled, diagnostics) = crate::compile(source);
+    let Some((mut program, symbols)) = compiled else {
+        panic!("{:?}", diagnostics);
+    };
+    crate::testing::strip_tests(&mut program);
+    let mut mir = mir::lower_for_vm(&program, &symbols).unwrap();
+    crate::passes::optimize(&mut mir, passes);
+    compile(&mir).unwrap()
+}
+
+#[test]
+fn test_ops() {
+    for (i, op) in OPS.iter().enumerate() {
+        assert_eq!(*op as usize, i);
+    }
+    assert_eq!(Op::decode(Op::Fail as u8), Op::Fail);
+    assert_eq!(Op::JumpIfFalse.name(), "jump_if_false");
+    assert_eq!(Op::SetPath.size(), 6);
+    assert_eq!(Op::binary(BinOp::Le), Op::LessEqual);
+    assert_eq!(Op::LessEqual.operator(), Some(Operators::LEq));
+}
+
+#[test]
+fn test_disassemble() {
+    let program = compile_source(
+        "func add(int a, int b) -> int {
+    return a + b
+}
+var total = 0
+for i in 0..3 {
+    total = add(total, i)
+}
+IO.puts(total)
+",
+        &crate::passes::Pass::ALL,
+    );
+    assert_eq!(
+        program.to_string(),
+        "globals: total
+
+fn <top level>/0 {
+    locals: _0, _1, i, _3, a, b
+    0000  4:1    constant 0             // 0
+    0003         set_global 0           // total
+    0006  5:11   constant 0             // 0
+    0009         constant 1             // 3
+    0012         range
+    0013         set_local 1            // _1
+    0016  5:1    get_local 1            // _1
+    0019         iterate
+    0020         next 55
+    0023         set_local 2            // i
+    0026  6:17   get_global 0           // total
+    0029         set_local 4            // a
+    0032  6:13   enter 2                // \"add\"
+    0035         get_local 2            // i
+    0038         set_local 5            // b
+    0041  2:14   get_local 4            // a
+    0044         get_local 5            // b
+    0047         add
+    0048         set_global 0           // total
+    0051  6:13   leave
+    0052         jump 20
+    0055  8:9    get_global 0           // total
+    0058         set_local 3            // _3
+    0061  8:1    get_local 3            // _3
+    0064         call_native 0 1        // IO.puts
+    0068         pop
+    0069         unit
+    0070         set_local 0            // _0
+    0073         get_local 0            // _0
+    0076         return
+}
+"
+    );
+}
+
+#[test]
+fn test_captures() {
+    let program = compile_source(
+        "func counter() -> fn() -> int {
+    var int count = 0
+    func next() -> int {
+        count = count + 1
+        return count
+    }
+    return next
+}
+",
+        &[],
+    );
+    let counter = &program.script.chunk.functions[0];
+    assert_eq!(counter.cells, ["count"]);
+    assert_eq!(counter.locals, ["_0", "next", "_3", "_4"]);
+    let next = &counter.chunk.functions[0];
+    assert_eq!(next.upvalues, ["count"]);
+    assert_eq!(next.captures, [Capture::Cell(0)]);
+    // `next` is made before `count` is declared, so the declaration fills the cell it shares
+    let ops: Vec<Op> = (0..2)
+        .scan(0, |offset, _| {
+            let op = Op::decode(counter.chunk.code[*offset]);
+            *offset += op.size();
+            Some(op)
+        })
+        .collect();
+    assert_eq!(ops, [Op::FreshCell, Op::Closure]);
+}
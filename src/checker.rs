@@ -0,0 +1,2090 @@
+use std::collections::HashMap;
+
+use crate::diagnostics::Diagnostic;
+use crate::parsers::*;
+use crate::resolver::{SymbolKind, SymbolTable};
+use crate::rho_core;
+use crate::tokens::*;
+use crate::types::Type;
+
+// The type of a function, its `Type::Param`s are the ones named in `type_params`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub type_params: Vec<String>,
+    pub params: Vec<Option<Type>>, // None for an untyped parameter
+    pub param_names: Vec<Ident>,
+    pub return_type: Type, // a type variable for a func without a return type
+    pub iterables: bool,   // a builtin takes any iterable where it declares a list, ie. a range
+}
+
+impl Signature {
+    fn of(decl: &FunctionDecl, return_type: Type) -> Signature {
+        Signature {
+            type_params: decl.type_params.iter().map(|p| p.name.to_owned()).collect(),
+            params: decl.params.iter().map(|p| p.param_type.clone()).collect(),
+            param_names: decl.params.iter().map(|p| p.name.clone()).collect(),
+            return_type,
+            iterables: false,
+        }
+    }
+
+    fn builtin(type_params: &[&str], params: Vec<Type>, return_type: Type) -> Signature {
+        Signature {
+            type_params: type_params.iter().map(|p| p.to_string()).collect(),
+            param_names: params
+                .iter()
+                .map(|_| Ident::new("x", Span::default()))
+                .collect(),
+            params: params.into_iter().map(Some).collect(),
+            return_type,
+            iterables: true,
+        }
+    }
+
+    // The function type, if everything about it is known
+    fn as_type(&self) -> Option<Type> {
+        let params: Option<Vec<Type>> = self.params.iter().cloned().collect();
+        Some(Type::function(params?, self.return_type.clone()))
+    }
+}
+
+// The signature a native function was registered with, see `rho_core::register`
+pub fn builtin_signature(name: &str) -> Option<Signature> {
+    let builtin = rho_core::builtin(name)?;
+    Some(Signature::builtin(
+        &builtin.type_params,
+        builtin.params,
+        builtin.return_type,
+    ))
+}
+
+/* The types the elements of a tuple pattern match against. A result matches `{:ok, value}` and
+ * `{:error, reason}`, the second element's type depends on which atom the first one is.
+ */
+pub fn tuple_pattern_types(subject: &Type, elements: &[Pattern]) -> Option<Vec<Option<Type>>> {
+    match subject {
+        Type::BuiltIn(BuiltinType::Tuple, args) if args.len() == elements.len() => {
+            Some(args.iter().cloned().map(Some).collect())
+        }
+        Type::BuiltIn(BuiltinType::Result, args) if elements.len() == 2 => {
+            let payload = match &elements[0] {
+                Pattern::Literal(TokenValue::Atom(a), _) if a == "ok" => args.first().cloned(),
+                Pattern::Literal(TokenValue::Atom(a), _) if a == "error" => args.get(1).cloned(),
+                _ => None,
+            };
+            Some(vec![Some(Type::atom()), payload])
+        }
+        _ => None,
+    }
+}
+
+// The payload of an `{:ok, value}` or `{:error, reason}` literal, and which of a result's type
+// arguments it is
+fn result_payload(elements: &[Expression]) -> Option<(usize, &Expression)> {
+    match elements {
+        [Expression::Literal {
+            value: TokenValue::Atom(tag),
+            ..
+        }, payload] => match tag.as_str() {
+            "ok" => Some((0, payload)),
+            "error" => Some((1, payload)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+const CONVERSIONS: [&str; 5] = ["int", "float", "str", "bool", "char"];
+
+// Numbers are never converted implicitly, `int(1.5)` and `float(1)` are
+fn can_convert(from: &Type, to: &str) -> bool {
+    match to {
+        "int" => from.is_numeric() || *from == Type::char() || *from == Type::bool(),
+        "float" => from.is_numeric(),
+        "char" => *from == Type::int() || *from == Type::char(),
+        "bool" => *from == Type::bool(),
+        "str" => true,
+        _ => false,
+    }
+}
+
+pub fn literal_type(value: &TokenValue) -> Type {
+    match value {
+        TokenValue::Int(_) => Type::int(),
+        TokenValue::Float(_) => Type::float(),
+        TokenValue::Bool(_) => Type::bool(),
+        TokenValue::Char(_) => Type::char(),
+        TokenValue::String(_) => Type::string(),
+        TokenValue::Atom(_) => Type::atom(),
+    }
+}
+
+/* Whether a value of type `actual` can be used where `expected` is wanted. A collection type
+ * without arguments is the type of an empty literal ie. `[]`, it fits any collection of its kind.
+ */
+pub fn fits(expected: &Type, actual: &Type) -> bool {
+    match (expected, actual) {
+        (Type::BuiltIn(a, a_args), Type::BuiltIn(b, b_args)) if a == b => {
+            a_args.is_empty()
+                || b_args.is_empty()
+                || (a_args.len() == b_args.len()
+                    && a_args.iter().zip(b_args.iter()).all(|(x, y)| fits(x, y)))
+        }
+        (Type::Named(a, a_args), Type::Named(b, b_args)) => {
+            a == b
+                && a_args.len() == b_args.len()
+                && a_args.iter().zip(b_args.iter()).all(|(x, y)| fits(x, y))
+        }
+        (Type::Function(a_params, a_ret), Type::Function(b_params, b_ret)) => {
+            a_params.len() == b_params.len()
+                && a_params
+                    .iter()
+                    .zip(b_params.iter())
+                    .all(|(x, y)| fits(x, y))
+                && fits(a_ret, b_ret)
+        }
+        _ => expected == actual,
+    }
+}
+
+/* Whether every way through `e` ends in a `return`, or doesn't end, ie. in a `panic`, a failed
+ * `match` or `cond` or a `while true` without a `break`. Whether a condition holds isn't worked
+ * out, but for a literal `true`.
+ */
+fn always_returns(e: &Expression) -> bool {
+    match e {
+        Expression::Return { .. } | Expression::Panic { .. } => true,
+        Expression::Block { expressions, .. } => expressions.iter().any(always_returns),
+        Expression::If {
+            branches,
+            else_branch: Some(else_branch),
+            ..
+        } => branches.iter().all(|(_, b)| always_returns(b)) && always_returns(else_branch),
+        Expression::Match { arms, .. } => arms.iter().all(|a| always_returns(&a.body)),
+        Expression::Cond { arms, .. } => arms.iter().all(|(_, b)| always_returns(b)),
+        Expression::While {
+            condition, body, ..
+        } => {
+            let forever = matches!(
+                condition.as_ref(),
+                Expression::Literal {
+                    value: TokenValue::Bool(true),
+                    ..
+                }
+            );
+            forever && !breaks(body)
+        }
+        _ => false,
+    }
+}
+
+// Whether `e` has a `break` out of the loop it's the body of
+fn breaks(e: &Expression) -> bool {
+    match e {
+        Expression::Break { .. } => true,
+        // their `break`s are their own
+        Expression::While { .. }
+        | Expression::For { .. }
+        | Expression::Lambda { .. }
+        | Expression::Function(_) => false,
+        e => e.children().into_iter().any(breaks),
+    }
+}
+
+#[test]
+fn test_fits() {
+    assert!(fits(
+        &Type::list(Type::int()),
+        &Type::BuiltIn(BuiltinType::List, vec![])
+    ));
+    assert!(!fits(&Type::list(Type::int()), &Type::list(Type::float())));
+    assert!(!fits(&Type::int(), &Type::float()));
+}
+
+fn mismatch(expected: &Type, actual: &Type, span: Span) -> Diagnostic {
+    Diagnostic::error(
+        format!(
+            "mismatched types: expected `{}`, found `{}`",
+            expected, actual
+        ),
+        span,
+    )
+}
+
+// The result of a binary operator, `None` for either side means it couldn't be typed
+fn binary_type(
+    operator: Operators,
+    left: Option<&Type>,
+    right: Option<&Type>,
+) -> Result<Option<Type>, String> {
+    let op = operator_to_string(operator);
+    let (l, r) = match (left, right) {
+        (Some(l), Some(r)) => (l, r),
+        (Some(known), None) | (None, Some(known)) => {
+            return Ok(match operator {
+                Operators::LessThan
+                | Operators::GreaterThan
+                | Operators::LEq
+                | Operators::GEq
+                | Operators::BEq
+                | Operators::BNEq
+                | Operators::And
+                | Operators::Or => Some(Type::bool()),
+                Operators::Concat => Some(Type::string()),
+                _ if known.is_numeric() => Some(known.clone()),
+                _ => None,
+            })
+        }
+        (None, None) => return Ok(None),
+    };
+    let mixed_numbers = || {
+        format!(
+            "`{}` can't mix `{}` and `{}`, convert one side with `int(..)` or `float(..)`",
+            op, l, r
+        )
+    };
+    match operator {
+        Operators::Add
+        | Operators::Subtract
+        | Operators::Mult
+        | Operators::Div
+        | Operators::Exp
+        | Operators::Modulo => {
+            if l.is_numeric() && r.is_numeric() {
+                if l != r {
+                    return Err(mixed_numbers());
+                }
+                return Ok(Some(l.clone()));
+            }
+            let mut message = format!("`{}` needs numbers, found `{}` and `{}`", op, l, r);
+            if operator == Operators::Add && (*l == Type::string() || *r == Type::string()) {
+                message += ", use `<>` to join strings";
+            }
+            Err(message)
+        }
+        Operators::Concat => {
+            if *l == Type::string() && *r == Type::string() {
+                Ok(Some(Type::string()))
+            } else {
+                Err(format!("`<>` joins strings, found `{}` and `{}`", l, r))
+            }
+        }
+        Operators::EnumConcat => match (l, r) {
+            (Type::BuiltIn(BuiltinType::List, l_args), Type::BuiltIn(BuiltinType::List, _))
+                if fits(l, r) =>
+            {
+                Ok(Some(if l_args.is_empty() {
+                    r.clone()
+                } else {
+                    l.clone()
+                }))
+            }
+            _ => Err(format!(
+                "`++` joins lists of one type, found `{}` and `{}`",
+                l, r
+            )),
+        },
+        Operators::LessThan | Operators::GreaterThan | Operators::LEq | Operators::GEq => {
+            if l.is_numeric() && r.is_numeric() && l != r {
+                return Err(mixed_numbers());
+            }
+            let ordered = l.is_numeric() || *l == Type::string() || *l == Type::char();
+            if l == r && ordered {
+                Ok(Some(Type::bool()))
+            } else {
+                Err(format!("`{}` can't compare `{}` and `{}`", op, l, r))
+            }
+        }
+        Operators::BEq | Operators::BNEq => {
+            if fits(l, r) || fits(r, l) {
+                Ok(Some(Type::bool()))
+            } else {
+                Err(format!("`{}` can't compare `{}` and `{}`", op, l, r))
+            }
+        }
+        Operators::Lshift | Operators::Rshift => {
+            if *l == Type::int() && *r == Type::int() {
+                Ok(Some(Type::int()))
+            } else {
+                Err(format!("`{}` needs ints, found `{}` and `{}`", op, l, r))
+            }
+        }
+        Operators::And | Operators::Or => {
+            if *l == Type::bool() && *r == Type::bool() {
+                Ok(Some(Type::bool()))
+            } else {
+                Err(format!("`{}` needs bools, found `{}` and `{}`", op, l, r))
+            }
+        }
+        _ => Err(format!("`{}` is not a binary operator", op)),
+    }
+}
+
+#[test]
+fn test_binary_type() {
+    let int = Type::int();
+    let float = Type::float();
+    let string = Type::string();
+    assert_eq!(
+        binary_type(Operators::Add, Some(&int), Some(&int)),
+        Ok(Some(int.clone()))
+    );
+    assert!(binary_type(Operators::Add, Some(&int), Some(&float))
+        .unwrap_err()
+        .contains("can't mix `int` and `float`"));
+    assert!(binary_type(Operators::Concat, Some(&string), Some(&int)).is_err());
+    assert!(binary_type(Operators::Modulo, Some(&string), Some(&string)).is_err());
+    assert_eq!(
+        binary_type(Operators::LessThan, Some(&string), Some(&string)),
+        Ok(Some(Type::bool()))
+    );
+}
+
+// Where the value of a `return` goes
+struct ReturnSlot {
+    name: String,
+    expected: Type,
+    returns: bool,
+}
+
+// A type variable, `bound` is what it was solved to and where
+struct Var {
+    bound: Option<(Type, Span)>,
+}
+
+struct Checker<'a> {
+    symbols: &'a mut SymbolTable,
+    signatures: HashMap<SymbolId, Signature>,
+    structs: HashMap<String, StructDecl>,
+    enums: HashMap<String, EnumDecl>,
+    interfaces: HashMap<String, InterfaceDecl>,
+    // the type parameters, and their bounds, of the generic functions being checked
+    type_params: Vec<TypeParam>,
+    returns: Vec<ReturnSlot>,
+    vars: Vec<Var>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/* Checks that every value is used as the type it was declared with, that operators get operands
+ * they work on and that calls get the arguments their function takes.
+ *
+ * Types that aren't written down, of untyped bindings, lambda parameters, the elements of empty
+ * collections and the type arguments of generic calls, start as type variables and are solved by
+ * unifying them with the types they are used as. What each binding turned out to be is recorded
+ * in the symbol table, bindings whose type is still unknown at the end are reported.
+ */
+pub fn check(program: &[Expression], symbols: &mut SymbolTable) -> Vec<Diagnostic> {
+    let mut c = Checker {
+        symbols,
+        signatures: HashMap::new(),
+        structs: HashMap::new(),
+        enums: HashMap::new(),
+        interfaces: HashMap::new(),
+        type_params: vec![],
+        returns: vec![],
+        vars: vec![],
+        diagnostics: vec![],
+    };
+    let builtins: Vec<(String, SymbolId)> = c
+        .symbols
+        .globals
+        .iter()
+        .map(|(name, id)| (name.to_owned(), *id))
+        .collect();
+    for (name, id) in builtins {
+        if let Some(signature) = builtin_signature(&name) {
+            c.signatures.insert(id, signature);
+        }
+    }
+    for e in program.iter() {
+        c.declare_item(e);
+    }
+    for e in program.iter() {
+        if !matches!(e, Expression::Function(_)) {
+            c.check_statement(e);
+        }
+    }
+    for e in program.iter() {
+        if let Expression::Function(decl) = e {
+            c.check_function(decl);
+            c.record_function_type(decl);
+        }
+    }
+    c.report_unsolved();
+    c.diagnostics
+        .sort_by_key(|d| (d.span.line, d.span.col, d.is_error()));
+    c.diagnostics
+}
+
+/* Writes the types found for untyped lambda parameters into the program, so the passes after
+ * the checker don't need to infer them again.
+ */
+pub fn annotate(program: &mut [Expression], symbols: &SymbolTable) {
+    for e in program.iter_mut() {
+        annotate_expression(e, symbols);
+    }
+}
+
+fn annotate_expression(expression: &mut Expression, symbols: &SymbolTable) {
+    if let Expression::Lambda { params, .. } = expression {
+        for p in params.iter_mut().filter(|p| p.param_type.is_none()) {
+            p.param_type = p
+                .name
+                .symbol
+                .and_then(|id| symbols.get(id).symbol_type.clone())
+                .filter(|t| !t.contains_vars());
+        }
+    }
+    for child in expression.children_mut() {
+        annotate_expression(child, symbols);
+    }
+}
+
+impl Checker<'_> {
+    fn declare_item(&mut self, e: &Expression) {
+        match e {
+            Expression::Function(decl) => {
+                let return_type = match &decl.return_type {
+                    Some(t) => t.clone(),
+                    None => self.new_var(),
+                };
+                if let Some(id) = decl.name.idents.last().and_then(|i| i.symbol) {
+                    self.signatures.insert(id, Signature::of(decl, return_type));
+                }
+            }
+            Expression::Struct(decl) => {
+                self.structs.insert(decl.name.name.to_owned(), decl.clone());
+            }
+            Expression::Enum(decl) => {
+                self.enums.insert(decl.name.name.to_owned(), decl.clone());
+            }
+            Expression::Interface(decl) => {
+                self.interfaces
+                    .insert(decl.name.name.to_owned(), decl.clone());
+            }
+            _ => {}
+        }
+    }
+
+    fn new_var(&mut self) -> Type {
+        self.vars.push(Var { bound: None });
+        Type::Var(self.vars.len() - 1)
+    }
+
+    // Follows solved type variables to the type they stand for, and where the last was solved
+    fn shallow(&self, t: &Type) -> (Type, Option<Span>) {
+        let mut t = t.clone();
+        let mut origin = None;
+        while let Type::Var(v) = t {
+            match &self.vars[v].bound {
+                Some((bound, span)) => {
+                    origin = Some(*span);
+                    t = bound.clone();
+                }
+                None => break,
+            }
+        }
+        (t, origin)
+    }
+
+    // `t` with every solved type variable replaced
+    fn resolve(&self, t: &Type) -> Type {
+        match self.shallow(t).0 {
+            Type::Named(name, args) => {
+                Type::Named(name, args.iter().map(|a| self.resolve(a)).collect())
+            }
+            Type::BuiltIn(b, args) => {
+                Type::BuiltIn(b, args.iter().map(|a| self.resolve(a)).collect())
+            }
+            Type::Function(params, ret) => Type::function(
+                params.iter().map(|p| self.resolve(p)).collect(),
+                self.resolve(&ret),
+            ),
+            t => t,
+        }
+    }
+
+    fn occurs(&self, v: usize, t: &Type) -> bool {
+        match self.shallow(t).0 {
+            Type::Var(w) => v == w,
+            Type::Named(_, args) | Type::BuiltIn(_, args) => args.iter().any(|a| self.occurs(v, a)),
+            Type::Function(params, ret) => {
+                params.iter().any(|p| self.occurs(v, p)) || self.occurs(v, &ret)
+            }
+            _ => false,
+        }
+    }
+
+    /* Makes `expected` and `actual` the same type by solving the type variables in them, `span`
+     * is the use that solves them. When they can't be the same, the error carries the span where
+     * one of the type variables involved was solved, if any.
+     */
+    fn unify(&mut self, expected: &Type, actual: &Type, span: Span) -> Result<(), Option<Span>> {
+        let (e, e_origin) = self.shallow(expected);
+        let (a, a_origin) = self.shallow(actual);
+        let origin = e_origin.or(a_origin);
+        let all = |this: &mut Self, xs: &[Type], ys: &[Type]| -> Result<(), Option<Span>> {
+            if xs.len() != ys.len() {
+                return Err(origin);
+            }
+            for (x, y) in xs.iter().zip(ys.iter()) {
+                this.unify(x, y, span).map_err(|o| o.or(origin))?;
+            }
+            Ok(())
+        };
+        match (&e, &a) {
+            (Type::Var(x), Type::Var(y)) if x == y => Ok(()),
+            (Type::Var(v), t) | (t, Type::Var(v)) => {
+                if self.occurs(*v, t) {
+                    return Err(origin);
+                }
+                self.vars[*v].bound = Some((t.clone(), span));
+                Ok(())
+            }
+            (Type::BuiltIn(x, x_args), Type::BuiltIn(y, y_args)) if x == y => {
+                // an empty literal's type doesn't constrain its elements
+                if x_args.is_empty() || y_args.is_empty() {
+                    return Ok(());
+                }
+                all(self, x_args, y_args)
+            }
+            (Type::Named(x, x_args), Type::Named(y, y_args)) if x == y => all(self, x_args, y_args),
+            (Type::Function(x_params, x_ret), Type::Function(y_params, y_ret)) => {
+                all(self, x_params, y_params)?;
+                self.unify(x_ret, y_ret, span).map_err(|o| o.or(origin))
+            }
+            _ if e == a => Ok(()),
+            _ => Err(origin),
+        }
+    }
+
+    // Unifies, reporting a mismatch at `span` along with where a conflicting type was inferred
+    fn expect(&mut self, expected: &Type, actual: &Type, span: Span) -> Option<Diagnostic> {
+        let origin = self.unify(expected, actual, span).err()?;
+        let mut d = mismatch(&self.resolve(expected), &self.resolve(actual), span);
+        if let Some(origin) = origin.filter(|o| *o != span) {
+            d = d.with_note("the expected type was inferred from this use", origin);
+        }
+        Some(d)
+    }
+
+    fn symbol_type(&self, ident: &Ident) -> Option<Type> {
+        ident
+            .symbol
+            .and_then(|id| self.symbols.get(id).symbol_type.clone())
+    }
+
+    fn set_symbol_type(&mut self, ident: &Ident, t: Type) {
+        if let Some(id) = ident.symbol {
+            self.symbols.symbols[id].symbol_type = Some(t);
+        }
+    }
+
+    fn symbol_kind(&self, ident: &Ident) -> Option<SymbolKind> {
+        ident.symbol.map(|id| self.symbols.get(id).kind)
+    }
+
+    // Records what the type variables of each binding were solved to, reporting the unsolved
+    fn report_unsolved(&mut self) {
+        let mut reported = vec![];
+        for id in 0..self.symbols.symbols.len() {
+            let Some(t) = self.symbols.symbols[id].symbol_type.clone() else {
+                continue;
+            };
+            let t = self.resolve(&t);
+            let symbol = &self.symbols.symbols[id];
+            let binding = matches!(symbol.kind, SymbolKind::Variable | SymbolKind::Parameter);
+            let vars = Self::vars_of(&t);
+            // once per unknown, ie. not again for `id` after `x` in `id = fn x -> x`
+            if binding && !vars.iter().all(|v| reported.contains(v)) {
+                reported.extend(vars);
+                self.diagnostics.push(Diagnostic::error(
+                    format!(
+                        "cannot infer the type of `{}`, it is `{}` so far, add a type annotation",
+                        symbol.name, t
+                    ),
+                    symbol.span,
+                ));
+            }
+            self.symbols.symbols[id].symbol_type = Some(t);
+        }
+    }
+
+    fn vars_of(t: &Type) -> Vec<usize> {
+        match t {
+            Type::Var(v) => vec![*v],
+            Type::Named(_, args) | Type::BuiltIn(_, args) => {
+                args.iter().flat_map(Self::vars_of).collect()
+            }
+            Type::Function(params, ret) => params
+                .iter()
+                .chain(std::iter::once(ret.as_ref()))
+                .flat_map(Self::vars_of)
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    // Checks `expression` and reports it if its type doesn't fit `expected`
+    fn check_against(&mut self, expression: &Expression, expected: &Type) -> Option<Type> {
+        let actual = self.check(expression, Some(expected))?;
+        if let Some(d) = self.expect(expected, &actual, expression.span()) {
+            self.diagnostics.push(d);
+        }
+        Some(actual)
+    }
+
+    fn check_function(&mut self, decl: &FunctionDecl) {
+        let id = decl.name.idents.last().and_then(|i| i.symbol);
+        let return_type = id
+            .and_then(|id| self.signatures.get(&id))
+            .map(|s| s.return_type.clone())
+            .or(decl.return_type.clone())
+            .unwrap_or(Type::Unit);
+        let outer_type_params = self.type_params.len();
+        self.type_params.extend(decl.type_params.iter().cloned());
+        self.returns.push(ReturnSlot {
+            name: format!("`{}`", decl.name.path()),
+            expected: return_type.clone(),
+            returns: false,
+        });
+        self.check_body(&decl.body);
+        let slot = self.returns.pop().unwrap();
+        self.type_params.truncate(outer_type_params);
+
+        match &decl.return_type {
+            Some(t) if *t != Type::Unit && !slot.returns => {
+                self.diagnostics.push(Diagnostic::error(
+                    format!(
+                        "`{}` is declared to return `{}` but never returns a value",
+                        decl.name.path(),
+                        t
+                    ),
+                    decl.name.idents.last().map(|i| i.span).unwrap_or(decl.span),
+                ));
+            }
+            None if !slot.returns => {
+                let _ = self.unify(&return_type, &Type::Unit, decl.span);
+            }
+            _ if self.resolve(&return_type) != Type::Unit && !always_returns(&decl.body) => {
+                self.diagnostics.push(Diagnostic::error(
+                    format!(
+                        "`{}` returns `{}` but can reach the end of its body without a `return`",
+                        decl.name.path(),
+                        self.resolve(&return_type)
+                    ),
+                    decl.name.idents.last().map(|i| i.span).unwrap_or(decl.span),
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    // A function's type as it turned out, ie. with its return type inferred, for the backends
+    fn record_function_type(&mut self, decl: &FunctionDecl) {
+        let Some(id) = decl.name.idents.last().and_then(|i| i.symbol) else {
+            return;
+        };
+        let params: Option<Vec<Type>> = decl
+            .params
+            .iter()
+            .map(|p| p.param_type.clone().or_else(|| self.symbol_type(&p.name)))
+            .collect();
+        let Some(signature) = self.signatures.get(&id) else {
+            return;
+        };
+        if let Some(params) = params {
+            let t = Type::function(params, signature.return_type.clone());
+            self.symbols.symbols[id].symbol_type = Some(t);
+        }
+    }
+
+    fn check_return(&mut self, value: Option<&Expression>, span: Span) {
+        let Some(slot) = self.returns.last_mut() else {
+            // a `return` at the top level ends the program
+            if let Some(v) = value {
+                self.check(v, None);
+            }
+            return;
+        };
+        slot.returns = true;
+        let expected = slot.expected.clone();
+        let name = slot.name.to_owned();
+        match value {
+            Some(v) => {
+                self.check_against(v, &expected);
+            }
+            None => {
+                if self.unify(&expected, &Type::Unit, span).is_err() {
+                    self.diagnostics.push(Diagnostic::error(
+                        format!(
+                            "{} returns `{}`, this `return` needs a value",
+                            name,
+                            self.resolve(&expected)
+                        ),
+                        span,
+                    ));
+                }
+            }
+        }
+    }
+
+    /* `value?` is the ok value of a result, an error is returned from the function it's in. That
+     * function has to return a result with the same error type, a top level `?` ends the program
+     * like a top level `return` does.
+     */
+    fn check_try(&mut self, value: &Expression, span: Span) -> Option<Type> {
+        let t = self.check(value, None)?;
+        let (ok, error) = match self.resolve(&t) {
+            Type::BuiltIn(BuiltinType::Result, args) if args.len() == 2 => {
+                (args[0].clone(), args[1].clone())
+            }
+            Type::Var(_) => return None,
+            t => {
+                self.diagnostics.push(Diagnostic::error(
+                    format!("`?` needs a `result`, found `{}`", t),
+                    span,
+                ));
+                return None;
+            }
+        };
+        if let Some(slot) = self.returns.last() {
+            let (expected, name) = (slot.expected.clone(), slot.name.clone());
+            let returned = Type::result(self.new_var(), error.clone());
+            if self.unify(&expected, &returned, span).is_err() {
+                self.diagnostics.push(Diagnostic::error(
+                    format!(
+                        "`?` returns a `result[_, {}]` from {}, which returns `{}`",
+                        error,
+                        name,
+                        self.resolve(&expected)
+                    ),
+                    span,
+                ));
+            }
+        }
+        Some(ok)
+    }
+
+    // A statement's value is dropped, an error it might hold would go unnoticed
+    fn check_statement(&mut self, expression: &Expression) {
+        let Some(t) = self.check(expression, None) else {
+            return;
+        };
+        if self.passes_through(expression) {
+            return;
+        }
+        if let t @ Type::BuiltIn(BuiltinType::Result, _) = self.resolve(&t) {
+            self.diagnostics.push(Diagnostic::warning(
+                format!("unused `{}`, handle its error with `match` or `?`", t),
+                expression.span(),
+            ));
+        }
+    }
+
+    // A call to a function that returns one of its arguments, ie. `IO.inspect(result)`
+    fn passes_through(&self, expression: &Expression) -> bool {
+        let Expression::FunctionCall { function_name, .. } = expression else {
+            return false;
+        };
+        let id = function_name.idents.last().and_then(|i| i.symbol);
+        id.and_then(|id| self.signatures.get(&id))
+            .is_some_and(|s| matches!(s.return_type, Type::Param(_)))
+    }
+
+    // The body of a function or loop, whose last expression is a statement too
+    fn check_body(&mut self, body: &Expression) {
+        match body {
+            Expression::Block { expressions, .. } => {
+                self.check_block(expressions, None, false);
+            }
+            _ => {
+                self.check(body, None);
+            }
+        }
+    }
+
+    fn check_block(
+        &mut self,
+        expressions: &[Expression],
+        expected: Option<&Type>,
+        has_value: bool,
+    ) -> Option<Type> {
+        for e in expressions.iter() {
+            if matches!(e, Expression::Function(_)) {
+                self.declare_item(e);
+            }
+        }
+        let mut last = Some(Type::Unit);
+        for (i, e) in expressions.iter().enumerate() {
+            if has_value && i + 1 == expressions.len() {
+                last = self.check(e, expected);
+            } else {
+                self.check_statement(e);
+            }
+        }
+        last
+    }
+
+    // The type of every branch when they all agree, otherwise the branches are statements
+    fn common_type(&self, types: Vec<Option<Type>>) -> Type {
+        let types: Vec<Option<Type>> = types
+            .into_iter()
+            .map(|t| t.map(|t| self.resolve(&t)))
+            .collect();
+        match types.first().cloned().flatten() {
+            Some(t) if types.iter().all(|b| b.as_ref() == Some(&t)) => t,
+            _ => Type::Unit,
+        }
+    }
+
+    fn check(&mut self, expression: &Expression, expected: Option<&Type>) -> Option<Type> {
+        let expected = expected.map(|t| self.resolve(t));
+        let expected = expected.as_ref();
+        match expression {
+            Expression::Literal { value, .. } => Some(literal_type(value)),
+            Expression::Definition {
+                definition_type,
+                identifier,
+                value,
+                ..
+            } => {
+                if let Some(t) = definition_type {
+                    self.check_against(value, t);
+                } else if self.symbols.declares(identifier) {
+                    if let Some(t) = self.check(value, None) {
+                        if self.resolve(&t) == Type::Unit {
+                            self.diagnostics.push(Diagnostic::error(
+                                format!(
+                                    "`{}` can't be bound to a value of type `unit`",
+                                    identifier.name
+                                ),
+                                value.span(),
+                            ));
+                        }
+                        self.set_symbol_type(identifier, t);
+                    }
+                } else {
+                    match self.symbol_type(identifier) {
+                        Some(t) => {
+                            self.check_against(value, &t);
+                        }
+                        None => {
+                            if let Some(t) = self.check(value, None) {
+                                self.set_symbol_type(identifier, t);
+                            }
+                        }
+                    }
+                }
+                Some(Type::Unit)
+            }
+            Expression::Assignment { target, value, .. } => {
+                match self.check(target, None) {
+                    Some(t) => self.check_against(value, &t),
+                    None => self.check(value, None),
+                };
+                Some(Type::Unit)
+            }
+            Expression::Identifier(ident) => self.check_identifier(ident, expected),
+            Expression::Calculation {
+                left,
+                operator,
+                right,
+                span,
+            } => self.check_calculation(left, *operator, right, *span),
+            Expression::Unary {
+                operator,
+                operand,
+                span,
+            } => {
+                let t = self.check(operand, None)?;
+                let t = self.resolve(&t);
+                let ok = match (operator, &t) {
+                    (Operators::Not, _) => self.unify(&Type::bool(), &t, *span).is_ok(),
+                    (_, Type::Var(_)) => true,
+                    _ => t.is_numeric(),
+                };
+                if !ok {
+                    self.diagnostics.push(Diagnostic::error(
+                        format!(
+                            "`{}` can't be applied to `{}`",
+                            operator_to_string(*operator),
+                            t
+                        ),
+                        *span,
+                    ));
+                    return None;
+                }
+                Some(self.resolve(&t))
+            }
+            Expression::FunctionCall {
+                function_name,
+                type_args,
+                parameters,
+                span,
+            } => self.check_call(function_name, type_args, parameters, *span, expected),
+            Expression::FieldAccess { object, field, .. } => {
+                if let Expression::Identifier(ident) = object.as_ref() {
+                    if self.symbol_kind(ident) == Some(SymbolKind::Enum) {
+                        return self.check_unit_variant(ident, field, expected);
+                    }
+                }
+                let t = self.check(object, None)?;
+                self.field_type(&t, field)
+            }
+            Expression::Index {
+                object,
+                index,
+                span,
+            } => {
+                let t = self.check(object, None)?;
+                match self.resolve(&t) {
+                    Type::BuiltIn(BuiltinType::List, args) => {
+                        self.check_against(index, &Type::int());
+                        args.first().cloned()
+                    }
+                    Type::BuiltIn(BuiltinType::String, _) => {
+                        self.check_against(index, &Type::int());
+                        Some(Type::char())
+                    }
+                    Type::BuiltIn(BuiltinType::Map, args) if args.len() == 2 => {
+                        self.check_against(index, &args[0]);
+                        Some(args[1].clone())
+                    }
+                    Type::Var(_) => {
+                        self.check(index, None);
+                        None
+                    }
+                    t => {
+                        self.check(index, None);
+                        self.diagnostics.push(Diagnostic::error(
+                            format!("`{}` can't be indexed", t),
+                            *span,
+                        ));
+                        None
+                    }
+                }
+            }
+            Expression::List { elements, .. } => {
+                let element = match expected {
+                    Some(Type::BuiltIn(BuiltinType::List, args)) if args.len() == 1 => {
+                        args[0].clone()
+                    }
+                    _ => self.new_var(),
+                };
+                for e in elements.iter() {
+                    self.check_against(e, &element);
+                }
+                Some(Type::list(self.resolve(&element)))
+            }
+            Expression::Tuple { elements, .. } => {
+                // `{:ok, value}` and `{:error, reason}` are results where one is expected
+                if let (Some(Type::BuiltIn(BuiltinType::Result, args)), Some((i, payload))) =
+                    (expected, result_payload(elements))
+                {
+                    if args.len() == 2 {
+                        self.check_against(payload, &args[i]);
+                        return Some(self.resolve(&Type::result(args[0].clone(), args[1].clone())));
+                    }
+                }
+                let hints = match expected {
+                    Some(Type::BuiltIn(BuiltinType::Tuple, args))
+                        if args.len() == elements.len() =>
+                    {
+                        args.clone()
+                    }
+                    _ => vec![],
+                };
+                let types: Vec<Option<Type>> = elements
+                    .iter()
+                    .enumerate()
+                    .map(|(i, e)| self.check(e, hints.get(i)))
+                    .collect();
+                let types: Option<Vec<Type>> = types.into_iter().collect();
+                Some(Type::tuple(types?))
+            }
+            Expression::Map { entries, .. } => {
+                let (key, value) = match expected {
+                    Some(Type::BuiltIn(BuiltinType::Map, args)) if args.len() == 2 => {
+                        (args[0].clone(), args[1].clone())
+                    }
+                    _ => (self.new_var(), self.new_var()),
+                };
+                for (k, v) in entries.iter() {
+                    self.check_against(k, &key);
+                    self.check_against(v, &value);
+                }
+                let key = self.resolve(&key);
+                if !key.is_hashable() {
+                    if let Some((k, _)) = entries.first() {
+                        self.diagnostics.push(Diagnostic::error(
+                            format!("can't use a `{}` as a map key", key),
+                            k.span(),
+                        ));
+                    }
+                }
+                Some(Type::map(key, self.resolve(&value)))
+            }
+            Expression::Range {
+                start, end, step, ..
+            } => {
+                self.check_against(start, &Type::int());
+                self.check_against(end, &Type::int());
+                if let Some(s) = step {
+                    self.check_against(s, &Type::int());
+                }
+                Some(Type::range())
+            }
+            Expression::StructLiteral {
+                name,
+                type_args,
+                fields,
+                span,
+            } => self.check_struct_literal(name, type_args, fields, *span, expected),
+            Expression::Lambda { params, body, span } => {
+                self.check_lambda(params, body, *span, expected)
+            }
+            Expression::Block { expressions, .. } => self.check_block(expressions, expected, true),
+            Expression::If {
+                branches,
+                else_branch,
+                ..
+            } => {
+                let mut types = vec![];
+                for (condition, body) in branches.iter() {
+                    self.check_against(condition, &Type::bool());
+                    types.push(self.check(body, expected));
+                }
+                match else_branch {
+                    Some(e) => types.push(self.check(e, expected)),
+                    None => types.push(Some(Type::Unit)),
+                }
+                Some(self.common_type(types))
+            }
+            Expression::While {
+                condition, body, ..
+            } => {
+                self.check_against(condition, &Type::bool());
+                self.check_body(body);
+                Some(Type::Unit)
+            }
+            Expression::For {
+                variable,
+                iterable,
+                body,
+                span,
+            } => {
+                if let Some(t) = self.check(iterable, None) {
+                    let element = match self.resolve(&t) {
+                        Type::Var(_) => None,
+                        t if t.element_type().is_some() => t.element_type(),
+                        t => {
+                            self.diagnostics.push(Diagnostic::error(
+                                format!("can't iterate over `{}`", t),
+                                iterable.span(),
+                            ));
+                            None
+                        }
+                    };
+                    if let Some(element) = element {
+                        self.set_symbol_type(variable, element);
+                    }
+                }
+                self.check_body(body);
+                Some(Type::Unit)
+            }
+            Expression::Match { subject, arms, .. } => {
+                let subject_type = self.check(subject, None);
+                let mut types = vec![];
+                for arm in arms.iter() {
+                    self.check_pattern(&arm.pattern, subject_type.as_ref());
+                    if let Some(g) = &arm.guard {
+                        self.check_against(g, &Type::bool());
+                    }
+                    types.push(self.check(&arm.body, expected));
+                }
+                Some(self.common_type(types))
+            }
+            Expression::Cond { arms, .. } => {
+                let mut types = vec![];
+                for (condition, body) in arms.iter() {
+                    self.check_against(condition, &Type::bool());
+                    types.push(self.check(body, expected));
+                }
+                Some(self.common_type(types))
+            }
+            Expression::Return { value, span } => {
+                self.check_return(value.as_deref(), *span);
+                Some(Type::Unit)
+            }
+            Expression::Try { value, span } => self.check_try(value, *span),
+            Expression::Panic { message, .. } => {
+                self.check(message, None);
+                // a function that panics doesn't have to return
+                if let Some(slot) = self.returns.last_mut() {
+                    slot.returns = true;
+                }
+                Some(Type::Unit)
+            }
+            Expression::Test { body, span, .. } => {
+                if !self.returns.is_empty() {
+                    self.diagnostics.push(Diagnostic::error(
+                        "a `test` goes at the top level of a file",
+                        *span,
+                    ));
+                }
+                self.check_body(body);
+                Some(Type::Unit)
+            }
+            Expression::Assert {
+                condition, message, ..
+            } => {
+                self.check_against(condition, &Type::bool());
+                if let Some(m) = message {
+                    self.check_against(m, &Type::string());
+                }
+                Some(Type::Unit)
+            }
+            Expression::Function(decl) => {
+                self.check_function(decl);
+                self.record_function_type(decl);
+                Some(Type::Unit)
+            }
+            Expression::Break { .. }
+            | Expression::Continue { .. }
+            | Expression::Import { .. }
+            | Expression::Struct(_)
+            | Expression::Enum(_)
+            | Expression::Interface(_) => Some(Type::Unit),
+        }
+    }
+
+    fn check_calculation(
+        &mut self,
+        left: &Expression,
+        operator: Operators,
+        right: &Expression,
+        span: Span,
+    ) -> Option<Type> {
+        let l = self.check(left, None);
+        let r = self.check(
+            right,
+            l.as_ref().filter(|_| operator != Operators::EnumConcat),
+        );
+        // what the operator says about its operands solves type variables in them
+        let mut origin = None;
+        let operand = match operator {
+            Operators::Concat => Some(Type::string()),
+            Operators::And | Operators::Or => Some(Type::bool()),
+            Operators::Lshift | Operators::Rshift => Some(Type::int()),
+            _ => None,
+        };
+        match (&operand, &l, &r) {
+            (Some(t), _, _) => {
+                for side in [&l, &r].into_iter().flatten() {
+                    if matches!(self.resolve(side), Type::Var(_)) {
+                        let _ = self.unify(t, side, span);
+                    }
+                }
+            }
+            (None, Some(l), Some(r)) => {
+                origin = self.unify(l, r, span).err().flatten();
+            }
+            _ => {}
+        }
+        let known = |this: &Self, t: &Option<Type>| {
+            t.as_ref()
+                .map(|t| this.resolve(t))
+                .filter(|t| !matches!(t, Type::Var(_)))
+        };
+        let (l_known, r_known) = (known(self, &l), known(self, &r));
+        match binary_type(operator, l_known.as_ref(), r_known.as_ref()) {
+            // two operands of one unknown type, ie. `a + b` in `fn a, b -> a + b`
+            Ok(None) if l_known.is_none() && r_known.is_none() && operand.is_none() => {
+                l.map(|t| self.resolve(&t))
+            }
+            Ok(t) => t,
+            Err(message) => {
+                let mut d = Diagnostic::error(message, span);
+                if let Some(origin) = origin {
+                    d = d.with_note("the type of an operand was inferred from this use", origin);
+                }
+                self.diagnostics.push(d);
+                None
+            }
+        }
+    }
+
+    // Fresh type variables for the type parameters of a generic item being used
+    fn instantiate(&mut self, type_params: &[String]) -> HashMap<String, Type> {
+        type_params
+            .iter()
+            .map(|p| (p.to_owned(), self.new_var()))
+            .collect()
+    }
+
+    fn check_identifier(&mut self, ident: &Ident, expected: Option<&Type>) -> Option<Type> {
+        let id = ident.symbol?;
+        match self.symbols.get(id).kind {
+            SymbolKind::Variable | SymbolKind::Parameter => self.symbol_type(ident),
+            SymbolKind::Function | SymbolKind::Builtin => {
+                let signature = self.signatures.get(&id)?.clone();
+                // a generic function passed as a value takes its type arguments from the context
+                let s = self.instantiate(&signature.type_params);
+                let t = signature.as_type()?.substitute(&s);
+                if let Some(expected) = expected {
+                    let _ = self.unify(expected, &t, ident.span);
+                }
+                Some(self.resolve(&t))
+            }
+            _ => {
+                self.diagnostics.push(Diagnostic::error(
+                    format!("`{}` is a type, not a value", ident.name),
+                    ident.span,
+                ));
+                None
+            }
+        }
+    }
+
+    fn field_type(&mut self, t: &Type, field: &Ident) -> Option<Type> {
+        let t = self.resolve(t);
+        if let Type::Named(name, args) = &t {
+            if let Some(decl) = self.structs.get(name) {
+                let s: HashMap<String, Type> = decl
+                    .type_params
+                    .iter()
+                    .map(|p| p.name.to_owned())
+                    .zip(args.iter().cloned())
+                    .collect();
+                if let Some((field_type, _)) =
+                    decl.fields.iter().find(|(_, f)| f.name == field.name)
+                {
+                    return Some(field_type.substitute(&s));
+                }
+            }
+        }
+        if !matches!(t, Type::Var(_)) {
+            self.diagnostics.push(Diagnostic::error(
+                format!("`{}` has no field `{}`", t, field.name),
+                field.span,
+            ));
+        }
+        None
+    }
+
+    /* Instantiates the generic struct or enum `name`, taking any type arguments the context or the
+     * program gives, and returns the type variables standing for its type parameters
+     */
+    fn instantiate_type(
+        &mut self,
+        name: &str,
+        type_params: &[TypeParam],
+        type_args: &[Type],
+        expected: Option<&Type>,
+        span: Span,
+    ) -> (HashMap<String, Type>, Type) {
+        let names: Vec<String> = type_params.iter().map(|p| p.name.to_owned()).collect();
+        let s = self.instantiate(&names);
+        let t = Type::Named(
+            name.to_string(),
+            names.iter().map(|n| s[n].clone()).collect(),
+        );
+        if !type_args.is_empty() {
+            let given = Type::Named(name.to_string(), type_args.to_vec());
+            if self.expect(&t, &given, span).is_some() {
+                self.diagnostics.push(Diagnostic::error(
+                    format!(
+                        "`{}` takes {} type arguments but {} were given",
+                        name,
+                        names.len(),
+                        type_args.len()
+                    ),
+                    span,
+                ));
+            }
+        }
+        if let Some(expected) = expected {
+            let _ = self.unify(expected, &t, span);
+        }
+        (s, t)
+    }
+
+    fn check_unit_variant(
+        &mut self,
+        enum_ident: &Ident,
+        variant: &Ident,
+        expected: Option<&Type>,
+    ) -> Option<Type> {
+        let decl = self.enums.get(&enum_ident.name)?.clone();
+        let v = decl.variants.iter().find(|v| v.name.name == variant.name)?;
+        if !v.fields.is_empty() {
+            self.diagnostics.push(Diagnostic::error(
+                format!(
+                    "`{}.{}` takes {} values, call it with them",
+                    enum_ident.name,
+                    variant.name,
+                    v.fields.len()
+                ),
+                variant.span,
+            ));
+            return None;
+        }
+        let (_, t) = self.instantiate_type(
+            &enum_ident.name,
+            &decl.type_params,
+            &[],
+            expected,
+            variant.span,
+        );
+        Some(self.resolve(&t))
+    }
+
+    /* Checks `values` against `params`, which may mention type variables of the callee. Lambdas
+     * and functions passed as values are checked last, once the types of their parameters can be
+     * known from the other arguments.
+     */
+    fn check_arguments(
+        &mut self,
+        callee: &str,
+        params: &[Option<Type>],
+        param_names: &[Ident],
+        values: &[Expression],
+        iterables: bool,
+    ) {
+        let deferred =
+            |e: &Expression| matches!(e, Expression::Lambda { .. } | Expression::Identifier(_));
+        for pass in [false, true] {
+            for (i, value) in values.iter().enumerate() {
+                if deferred(value) != pass {
+                    continue;
+                }
+                let Some(param) = params.get(i).cloned().flatten() else {
+                    self.check(value, None);
+                    continue;
+                };
+                let Some(mut actual) = self.check(value, Some(&param)) else {
+                    continue;
+                };
+                if iterables {
+                    actual = self.iterable_as_list(&param, actual);
+                }
+                if let Some(mut d) = self.expect(&param, &actual, value.span()) {
+                    if let Some(name) = param_names.get(i).filter(|n| n.span != Span::default()) {
+                        d = d.with_note(
+                            format!("parameter `{}` of `{}` is declared here", name.name, callee),
+                            name.span,
+                        );
+                    }
+                    self.diagnostics.push(d);
+                }
+            }
+        }
+    }
+
+    // A range, str or map passed for a list is a list of its elements
+    fn iterable_as_list(&mut self, param: &Type, actual: Type) -> Type {
+        let resolved = self.resolve(&actual);
+        match (self.resolve(param), resolved.element_type()) {
+            (Type::BuiltIn(BuiltinType::List, _), Some(element))
+                if !matches!(resolved, Type::BuiltIn(BuiltinType::List, _)) =>
+            {
+                Type::list(element)
+            }
+            _ => actual,
+        }
+    }
+
+    fn check_arity(
+        &mut self,
+        callee: &str,
+        wanted: usize,
+        values: &[Expression],
+        span: Span,
+    ) -> bool {
+        if wanted == values.len() {
+            return true;
+        }
+        self.diagnostics.push(Diagnostic::error(
+            format!(
+                "`{}` takes {} arguments but {} were given",
+                callee,
+                wanted,
+                values.len()
+            ),
+            span,
+        ));
+        for v in values.iter() {
+            self.check(v, None);
+        }
+        false
+    }
+
+    fn check_call(
+        &mut self,
+        function_name: &FunctionName,
+        type_args: &[Type],
+        parameters: &[Expression],
+        span: Span,
+        expected: Option<&Type>,
+    ) -> Option<Type> {
+        let path = function_name.path();
+        let first = &function_name.idents[0];
+        match self.symbol_kind(first) {
+            // a closure held by a variable, or by a field of one ie. `handlers.on_click(e)`
+            Some(SymbolKind::Variable) | Some(SymbolKind::Parameter) => {
+                // its parameters stay type variables, a mismatch then points at where they were solved
+                let mut t = self.symbol_type(first).map(|t| self.shallow(&t).0);
+                for field in function_name.idents[1..].iter() {
+                    t = match t {
+                        Some(t) => self.field_type(&self.resolve(&t), field),
+                        None => None,
+                    };
+                }
+                // calling a value of unknown type tells us it is a function
+                if let Some(Type::Var(_)) = t {
+                    let params = parameters.iter().map(|_| self.new_var()).collect();
+                    let f = Type::function(params, self.new_var());
+                    let _ = self.unify(t.as_ref().unwrap(), &f, span);
+                    t = Some(f);
+                }
+                match t {
+                    Some(Type::Function(params, ret)) => {
+                        if self.check_arity(&path, params.len(), parameters, span) {
+                            let params: Vec<Option<Type>> = params.into_iter().map(Some).collect();
+                            self.check_arguments(&path, &params, &[], parameters, false);
+                        }
+                        Some(self.resolve(&ret))
+                    }
+                    Some(t) => {
+                        self.diagnostics.push(Diagnostic::error(
+                            format!("`{}` is `{}`, not a function", path, t),
+                            function_name.span(),
+                        ));
+                        None
+                    }
+                    None => {
+                        for v in parameters.iter() {
+                            self.check(v, None);
+                        }
+                        None
+                    }
+                }
+            }
+            Some(SymbolKind::TypeParam) => self.check_method_call(function_name, parameters, span),
+            Some(SymbolKind::Enum) => {
+                self.check_variant_call(function_name, type_args, parameters, span, expected)
+            }
+            _ => {
+                let id = function_name.idents.last().and_then(|i| i.symbol);
+                let Some(signature) = id.and_then(|id| self.signatures.get(&id)).cloned() else {
+                    for v in parameters.iter() {
+                        self.check(v, None);
+                    }
+                    return None;
+                };
+                if CONVERSIONS.contains(&path.as_str()) && signature.type_params.len() == 1 {
+                    return self.check_conversion(&path, &signature, parameters, span);
+                }
+                self.check_signature_call(&path, &signature, type_args, parameters, span, expected)
+            }
+        }
+    }
+
+    fn check_conversion(
+        &mut self,
+        path: &str,
+        signature: &Signature,
+        parameters: &[Expression],
+        span: Span,
+    ) -> Option<Type> {
+        if self.check_arity(path, 1, parameters, span) {
+            if let Some(from) = self.check(&parameters[0], None) {
+                let from = self.resolve(&from);
+                if !matches!(from, Type::Var(_)) && !can_convert(&from, path) {
+                    self.diagnostics.push(Diagnostic::error(
+                        format!("`{}` can't be converted to `{}`", from, path),
+                        parameters[0].span(),
+                    ));
+                }
+            }
+        }
+        Some(signature.return_type.clone())
+    }
+
+    fn check_signature_call(
+        &mut self,
+        path: &str,
+        signature: &Signature,
+        type_args: &[Type],
+        parameters: &[Expression],
+        span: Span,
+        expected: Option<&Type>,
+    ) -> Option<Type> {
+        let s = self.instantiate(&signature.type_params);
+        let ret = signature.return_type.substitute(&s);
+        if !self.check_arity(path, signature.params.len(), parameters, span) {
+            return Some(self.resolve(&ret));
+        }
+        if !type_args.is_empty() {
+            if type_args.len() != signature.type_params.len() {
+                self.diagnostics.push(Diagnostic::error(
+                    format!(
+                        "`{}` takes {} type arguments but {} were given",
+                        path,
+                        signature.type_params.len(),
+                        type_args.len()
+                    ),
+                    span,
+                ));
+            }
+            for (p, a) in signature.type_params.iter().zip(type_args.iter()) {
+                let _ = self.unify(&s[p], a, span);
+            }
+        }
+        // the context's expectation guides the arguments, ie. the parameter types of a lambda
+        if let Some(expected) = expected {
+            let _ = self.unify(expected, &ret, span);
+        }
+        let params: Vec<Option<Type>> = signature
+            .params
+            .iter()
+            .map(|p| p.as_ref().map(|t| t.substitute(&s)))
+            .collect();
+        self.check_arguments(
+            path,
+            &params,
+            &signature.param_names,
+            parameters,
+            signature.iterables,
+        );
+        Some(self.resolve(&ret))
+    }
+
+    fn check_variant_call(
+        &mut self,
+        function_name: &FunctionName,
+        type_args: &[Type],
+        parameters: &[Expression],
+        span: Span,
+        expected: Option<&Type>,
+    ) -> Option<Type> {
+        let enum_name = function_name.idents[0].name.to_owned();
+        let decl = self.enums.get(&enum_name)?.clone();
+        let variant = function_name
+            .idents
+            .get(1)
+            .and_then(|v| decl.variants.iter().find(|d| d.name.name == v.name))?;
+        let path = function_name.path();
+        if !self.check_arity(&path, variant.fields.len(), parameters, span) {
+            return None;
+        }
+        let (s, t) =
+            self.instantiate_type(&enum_name, &decl.type_params, type_args, expected, span);
+        let fields: Vec<Option<Type>> = variant
+            .fields
+            .iter()
+            .map(|f| Some(f.substitute(&s)))
+            .collect();
+        self.check_arguments(&path, &fields, &[], parameters, false);
+        Some(self.resolve(&t))
+    }
+
+    // `T.show(x)` in a generic function calls the method an interface bound of T requires
+    fn check_method_call(
+        &mut self,
+        function_name: &FunctionName,
+        parameters: &[Expression],
+        span: Span,
+    ) -> Option<Type> {
+        let param = function_name.idents[0].name.to_owned();
+        let method = function_name.idents.get(1)?.clone();
+        let bounds = self
+            .type_params
+            .iter()
+            .rev()
+            .find(|p| p.name == param)
+            .map(|p| p.bounds.clone())
+            .unwrap_or_default();
+        let found = bounds.iter().find_map(|b| {
+            let interface = self.interfaces.get(&b.base_name())?;
+            let signature = (interface.methods.iter()).find(|m| m.name.name == method.name)?;
+            Some((b, interface.type_params.clone(), signature.clone()))
+        });
+        let Some((bound, type_params, signature)) = found else {
+            self.diagnostics.push(Diagnostic::error(
+                format!(
+                    "no interface bound of `{}` has a method `{}`",
+                    param, method.name
+                ),
+                method.span,
+            ));
+            for v in parameters.iter() {
+                self.check(v, None);
+            }
+            return None;
+        };
+        // `Self` is the type parameter, and the interface's own parameters the bound's arguments
+        let bound_args = match bound {
+            Type::Named(_, args) => args.clone(),
+            _ => vec![],
+        };
+        let mut names = vec!["Self".to_string()];
+        names.extend(type_params.iter().map(|p| p.name.to_owned()));
+        let mut s: HashMap<String, Type> = names[1..].iter().cloned().zip(bound_args).collect();
+        s.insert("Self".to_string(), Type::Param(param.to_owned()));
+        let this = |t: &Type| t.bind_params(&names).substitute(&s);
+        let path = function_name.path();
+        if self.check_arity(&path, signature.params.len(), parameters, span) {
+            let params: Vec<Option<Type>> = signature
+                .params
+                .iter()
+                .map(|p| p.param_type.as_ref().map(this))
+                .collect();
+            let names: Vec<Ident> = signature.params.iter().map(|p| p.name.clone()).collect();
+            self.check_arguments(&path, &params, &names, parameters, false);
+        }
+        Some(
+            signature
+                .return_type
+                .as_ref()
+                .map(this)
+                .unwrap_or(Type::Unit),
+        )
+    }
+
+    fn check_struct_literal(
+        &mut self,
+        name: &Ident,
+        type_args: &[Type],
+        fields: &[(Ident, Expression)],
+        span: Span,
+        expected: Option<&Type>,
+    ) -> Option<Type> {
+        let Some(decl) = self.structs.get(&name.name).cloned() else {
+            for (_, v) in fields.iter() {
+                self.check(v, None);
+            }
+            return None;
+        };
+        let (s, t) =
+            self.instantiate_type(&name.name, &decl.type_params, type_args, expected, span);
+        let mut params = vec![];
+        let mut names = vec![];
+        let mut values = vec![];
+        for (field, value) in fields.iter() {
+            let declared = decl.fields.iter().find(|(_, f)| f.name == field.name);
+            if let Some((t, f)) = declared {
+                params.push(Some(t.substitute(&s)));
+                names.push(f.clone());
+                values.push(value.clone());
+            } else {
+                self.check(value, None);
+            }
+        }
+        self.check_arguments(&name.name, &params, &names, &values, false);
+        Some(self.resolve(&t))
+    }
+
+    fn check_lambda(
+        &mut self,
+        params: &[Param],
+        body: &Expression,
+        span: Span,
+        expected: Option<&Type>,
+    ) -> Option<Type> {
+        let (hints, expected_return) = match expected {
+            Some(Type::Function(ps, ret)) if ps.len() == params.len() => {
+                (ps.clone(), Some(*ret.clone()))
+            }
+            _ => (vec![], None),
+        };
+        let mut types = vec![];
+        for (i, p) in params.iter().enumerate() {
+            let t = match (&p.param_type, hints.get(i)) {
+                (Some(t), _) => t.clone(),
+                (None, Some(hint)) => hint.clone(),
+                (None, None) => self.new_var(),
+            };
+            self.set_symbol_type(&p.name, t.clone());
+            types.push(t);
+        }
+        let ret = expected_return.unwrap_or_else(|| self.new_var());
+        self.returns.push(ReturnSlot {
+            name: "the lambda".to_string(),
+            expected: ret.clone(),
+            returns: false,
+        });
+        match body {
+            Expression::Block { .. } => {
+                self.check_body(body);
+            }
+            _ => {
+                self.check_against(body, &ret);
+            }
+        }
+        let slot = self.returns.pop().unwrap();
+        if matches!(body, Expression::Block { .. }) && !slot.returns {
+            if let Some(d) = self.expect(&ret, &Type::Unit, span) {
+                self.diagnostics.push(d);
+            }
+        }
+        // unresolved, a call with the wrong argument then points at the use its parameter was solved by
+        Some(Type::function(types, ret))
+    }
+
+    fn check_pattern(&mut self, pattern: &Pattern, subject: Option<&Type>) {
+        let subject = subject.map(|t| self.resolve(t));
+        let subject = subject.as_ref();
+        match pattern {
+            Pattern::Wildcard(_) => {}
+            Pattern::Binding(ident) => {
+                if let Some(t) = subject {
+                    self.set_symbol_type(ident, t.clone());
+                }
+            }
+            Pattern::Literal(value, span) => {
+                let t = literal_type(value);
+                if let Some(subject) = subject {
+                    if let Some(d) = self.expect(subject, &t, *span) {
+                        self.diagnostics.push(d);
+                    }
+                }
+            }
+            Pattern::Tuple(elements, span) => match subject {
+                Some(t) if tuple_pattern_types(t, elements).is_some() => {
+                    let types = tuple_pattern_types(t, elements).unwrap();
+                    for (p, t) in elements.iter().zip(types.iter()) {
+                        self.check_pattern(p, t.as_ref());
+                    }
+                }
+                Some(t) if !matches!(t, Type::Var(_)) => {
+                    self.diagnostics.push(Diagnostic::error(
+                        format!("a tuple of {} can't match `{}`", elements.len(), t),
+                        *span,
+                    ));
+                }
+                _ => {
+                    for p in elements.iter() {
+                        self.check_pattern(p, None);
+                    }
+                }
+            },
+            Pattern::List(elements, span) => {
+                let element = match subject {
+                    Some(Type::BuiltIn(BuiltinType::List, args)) => args.first().cloned(),
+                    Some(t) if !matches!(t, Type::Var(_)) => {
+                        self.diagnostics.push(Diagnostic::error(
+                            format!("a list pattern can't match `{}`", t),
+                            *span,
+                        ));
+                        None
+                    }
+                    _ => None,
+                };
+                for p in elements.iter() {
+                    self.check_pattern(p, element.as_ref());
+                }
+            }
+            Pattern::Variant(name, fields, span) => {
+                let enum_name = &name.idents[0].name;
+                let args = match subject {
+                    Some(Type::Named(n, args)) if n == enum_name => args.clone(),
+                    Some(t) if !matches!(t, Type::Var(_)) => {
+                        self.diagnostics.push(Diagnostic::error(
+                            format!("a `{}` pattern can't match `{}`", enum_name, t),
+                            *span,
+                        ));
+                        vec![]
+                    }
+                    _ => vec![],
+                };
+                let mut field_types = vec![];
+                if let Some(decl) = self.enums.get(enum_name) {
+                    let s: HashMap<String, Type> = decl
+                        .type_params
+                        .iter()
+                        .map(|p| p.name.to_owned())
+                        .zip(args.iter().cloned())
+                        .collect();
+                    let variant = name
+                        .idents
+                        .get(1)
+                        .and_then(|v| decl.variants.iter().find(|d| d.name.name == v.name));
+                    if let Some(variant) = variant {
+                        field_types = variant
+                            .fields
+                            .iter()
+                            .map(|t| t.substitute(&s))
+                            .filter(|t| {
+                                args.len() == decl.type_params.len() || !t.contains_params()
+                            })
+                            .collect();
+                        if variant.fields.len() != fields.len() {
+                            self.diagnostics.push(Diagnostic::error(
+                                format!(
+                                    "`{}` has {} values but the pattern has {}",
+                                    name.path(),
+                                    variant.fields.len(),
+                                    fields.len()
+                                ),
+                                *span,
+                            ));
+                        }
+                    }
+                }
+                for (i, p) in fields.iter().enumerate() {
+                    self.check_pattern(p, field_types.get(i));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn check_source(source: &str) -> Vec<String> {
+    let mut program = parse(source).unwrap();
+    let (mut symbols, diagnostics) = crate::resolver::resolve(&mut program);
+    assert!(
+        diagnostics.iter().all(|d| !d.is_error()),
+        "{:?}",
+        diagnostics
+    );
+    check(&program, &mut symbols)
+        .iter()
+        .map(|d| d.to_string())
+        .collect()
+}
+
+#[test]
+fn test_check_definitions() {
+    assert_eq!(
+        check_source(
+            "int i = \"hello\"\nstr s = \"a\" <> 1\nfloat f = 1.0\nint j = int(f) % 2\nm = {f: 1}\n"
+        ),
+        vec![
+            "error: mismatched types: expected `int`, found `str` at 1:9",
+            "error: `<>` joins strings, found `str` and `int` at 2:13",
+            "error: can't use a `float` as a map key at 5:6",
+        ]
+    );
+}
+
+#[test]
+fn test_check_calls() {
+    assert_eq!(
+        check_source(
+            "func add(int a, int b) -> int {
+    return a + b
+}
+x = add(1, \"two\")
+y = add(1)
+str z = add(1, 2)
+bool b = bool(1)
+"
+        ),
+        vec![
+            "error: mismatched types: expected `int`, found `str` at 4:12",
+            "error: `add` takes 2 arguments but 1 were given at 5:5",
+            "error: mismatched types: expected `str`, found `int` at 6:9",
+            "error: `int` can't be converted to `bool` at 7:15",
+        ]
+    );
+}
+
+#[test]
+fn test_check_returns_on_every_path() {
+    assert_eq!(
+        check_source(
+            "func sign(int n) -> int {
+    if n > 0 {
+        return 1
+    } elif n < 0 {
+        return -1
+    }
+}
+func half(int n) {
+    if n % 2 == 0 {
+        return n / 2
+    }
+}
+func never() -> int {
+}
+"
+        ),
+        vec![
+            "error: `sign` returns `int` but can reach the end of its body without a `return` at 1:6",
+            "error: `half` returns `int` but can reach the end of its body without a `return` at 8:6",
+            "error: `never` is declared to return `int` but never returns a value at 13:6",
+        ]
+    );
+    let paths = "func a(int n) -> int {
+    if n > 0 {
+        return 1
+    } else {
+        panic \"no\"
+    }
+}
+func b(int n) -> int {
+    match n {
+        0 -> return 1
+        _ -> return 2
+    }
+}
+func c(int n) -> int {
+    while true {
+        for i in 0..n {
+            break
+        }
+        return n
+    }
+}
+func d(int n) -> int {
+    cond {
+        n > 0 -> { return 1 }
+        true -> { return 0 }
+    }
+}
+";
+    assert_eq!(check_source(paths), Vec::<String>::new());
+    assert_eq!(
+        check_source(
+            "func e(int n) -> int {\n    while true {\n        if n > 0 {\n            break\n        }\n        return n\n    }\n}\n"
+        ),
+        vec!["error: `e` returns `int` but can reach the end of its body without a `return` at 1:6"]
+    );
+}
+
+#[test]
+fn test_infer_lambdas_and_bindings() {
+    let source = "l = fn x -> {
+    return x + 1
+}
+twice = fn f, v -> f(f(v))
+var xs = []
+xs = xs ++ [\"a\"]
+n = twice(l, 2)
+";
+    let mut program = parse(source).unwrap();
+    let (mut symbols, _) = crate::resolver::resolve(&mut program);
+    assert!(check(&program, &mut symbols).is_empty());
+    let type_of = |name: &str| {
+        let symbol = symbols.symbols.iter().find(|s| s.name == name).unwrap();
+        symbol.symbol_type.as_ref().unwrap().to_string()
+    };
+    assert_eq!(type_of("l"), "fn(int) -> int");
+    assert_eq!(type_of("twice"), "fn(fn(int) -> int, int) -> int");
+    assert_eq!(type_of("xs"), "list[str]");
+    assert_eq!(type_of("n"), "int");
+
+    annotate(&mut program, &symbols);
+    let Expression::Definition { value, .. } = &program[0] else {
+        panic!("expected a definition");
+    };
+    let Expression::Lambda { params, .. } = value.as_ref() else {
+        panic!("expected a lambda");
+    };
+    assert_eq!(params[0].param_type, Some(Type::int()));
+}
+
+#[test]
+fn test_infer_conflicts() {
+    let source = "var xs = []
+xs = xs ++ [1]
+xs = xs ++ [\"a\"]
+id = fn x -> x
+ys = []
+l = fn x -> {
+    return x + 1
+}
+y = l(2.0)
+";
+    let mut program = parse(source).unwrap();
+    let (mut symbols, _) = crate::resolver::resolve(&mut program);
+    let diagnostics = check(&program, &mut symbols);
+    let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "error: `++` joins lists of one type, found `list[int]` and `list[str]` at 3:9",
+            "error: cannot infer the type of `x`, it is `_` so far, add a type annotation at 4:9",
+            "error: cannot infer the type of `ys`, it is `list[_]` so far, add a type annotation at 5:1",
+            "error: mismatched types: expected `int`, found `float` at 9:7",
+        ]
+    );
+    // the conflicting use points back at the use the type was inferred from
+    assert_eq!(diagnostics[0].notes[0].1.line, 2);
+    assert_eq!(
+        diagnostics[3].notes[0].0,
+        "the expected type was inferred from this use"
+    );
+    assert_eq!(diagnostics[3].notes[0].1.line, 7);
+}
+
+#[test]
+fn test_check_results() {
+    assert_eq!(
+        check_source(
+            "func f(str s) -> int {
+    n = String.to_int(s)?
+    return n
+}
+String.to_int(\"1\")
+x = 1?
+IO.inspect(String.to_int(\"2\"))
+func g(str s) -> result[int, str] {
+    String.to_int(s)
+    n = String.to_int(s)?
+    return {:ok, n}
+}
+func h() -> result[int, str] {
+    return {:error, 1}
+}
+"
+        ),
+        vec![
+            "error: `?` returns a `result[_, str]` from `f`, which returns `int` at 2:9",
+            "warning: unused `result[int, str]`, handle its error with `match` or `?` at 5:1",
+            "error: `?` needs a `result`, found `int` at 6:5",
+            "warning: unused `result[int, str]`, handle its error with `match` or `?` at 9:5",
+            "error: mismatched types: expected `str`, found `int` at 14:21",
+        ]
+    );
+}
+
+#[test]
+fn test_check_generic_interfaces() {
+    // a bound's arguments are what the interface's own parameters stand for in its methods
+    let source = "interface Into[T] {
+    func into(Self x) -> T
+}
+func int.into(int x) -> str {
+    return str(x)
+}
+func conv[A: Into[str]](A a) -> str {
+    return A.into(a)
+}
+func count[A: Into[str]](A a) -> int {
+    return A.into(a)
+}
+s = conv(5)
+";
+    assert_eq!(
+        check_source(source),
+        vec!["error: mismatched types: expected `int`, found `str` at 11:12"]
+    );
+}
+
+#[test]
+fn test_check_testfiles() {
+    let source = std::fs::read_to_string("./rho_testfiles/generics.rho").unwrap();
+    assert_eq!(check_source(&source), Vec::<String>::new());
+
+    // scratch_typed.rho is scratch.rho converting its ints before mixing them with floats
+    let source = std::fs::read_to_string("./rho_testfiles/scratch_typed.rho").unwrap();
+    assert_eq!(check_source(&source), Vec::<String>::new());
+    assert_eq!(
+        check_source("float k = 1.0\nint j = 1\nfloat f = k + j\n"),
+        vec!["error: `+` can't mix `float` and `int`, convert one side with `int(..)` or `float(..)` at 3:13"]
+    );
+}
@@ -0,0 +1,615 @@
+use std::fs;
+use std::io::Write;
+
+use crate::bytecode;
+use crate::codegen;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::gc;
+use crate::interpreter;
+use crate::json::{self, Json};
+use crate::mir;
+use crate::native::{self, BuildOptions, Emit};
+use crate::parsers::{expressionize, Expression};
+use crate::passes::{self, Pass};
+use crate::repl;
+use crate::resolver::SymbolTable;
+use crate::testing;
+use crate::tokens::{tokenize, Token};
+use crate::vm;
+
+const USAGE: &str = "usage: rho <command> [options]
+
+commands:
+    run <file>              interprets a program, `rho <file>` does the same
+    build <file>            compiles a program to a native executable with LLVM
+    check <file>...         reports a program's errors and warnings without running it
+    tokens <file>           prints the tokens of a file
+    ast <file>              prints the syntax tree of a file
+    disasm <file>           prints the bytecode `rho run --vm` runs
+    repl                    starts an interactive session, see `:help` in it
+    test [path]             runs the `test` blocks under path, see `rho test --help`
+
+options:
+    --json                  check, tokens and ast print JSON instead of text
+    --vm                    run compiles the program to bytecode and runs that instead of
+                            interpreting it
+    -o, --output <file>     where build writes its output, ie. `hello` for hello.rho, `-`
+                            writes LLVM IR or MIR to stdout
+    --emit=ll|bc|obj|exe|mir
+                            what build writes: LLVM IR, bitcode, an object file, an
+                            executable, the default, or the mid-level IR
+    --target <triple>       the target build compiles for, ie. aarch64-linux-gnu
+    --passes=<list>         the MIR passes build, run --vm and disasm run, any of inline,
+                            fold, copies and dce, or none, or all, the default
+    -O0, -O1, -O2, -O3      build's optimization level, -O is -O2 and -O0 the default
+    -h, --help              prints this message
+    -V, --version           prints the version
+
+exit codes:
+    0    success, `rho run` exits with the program's own exit code
+    1    the program has errors, failed at runtime or a test failed
+    2    bad usage, ie. an unknown command or flag, or a file that can't be read
+";
+
+/* The command line driver, `main` hands it the arguments after the executable and exits with
+ * what it returns. See `USAGE` for the commands and exit codes.
+ */
+pub fn main(args: &[String]) -> i32 {
+    let command = match Command::parse(args) {
+        Ok(command) => command,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            eprint!("{}", USAGE);
+            return 2;
+        }
+    };
+    match command.name.as_str() {
+        "help" => {
+            print!("{}", USAGE);
+            0
+        }
+        "version" => {
+            println!("rho {}", env!("CARGO_PKG_VERSION"));
+            0
+        }
+        "test" => on_big_stack(move || testing::main(&command.files)),
+        "repl" => on_big_stack(repl::main),
+        "run" => run(&command.files[0], command.vm, command.build.passes),
+        "build" => build(&command.files[0], &command.build),
+        "check" => check(&command.files, command.json),
+        "tokens" => dump_tokens(&command.files[0], command.json),
+        "ast" => dump_ast(&command.files[0], command.json),
+        "disasm" => disassemble(&command.files[0], &command.build.passes),
+        _ => unreachable!("Command::parse only returns known commands"),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Command {
+    name: String,
+    files: Vec<String>, // for `test`, its own arguments
+    json: bool,
+    vm: bool,
+    build: BuildOptions,
+}
+
+impl Command {
+    fn parse(args: &[String]) -> Result<Command, String> {
+        let mut command = Command {
+            name: String::new(),
+            files: vec![],
+            json: false,
+            vm: false,
+            build: BuildOptions::default(),
+        };
+        let Some(first) = args.first() else {
+            return Err("no command given".to_string());
+        };
+        command.name = match first.as_str() {
+            "-h" | "--help" | "help" => "help".to_string(),
+            "-V" | "--version" | "version" => "version".to_string(),
+            "run" | "build" | "check" | "tokens" | "ast" | "disasm" | "repl" => first.clone(),
+            "test" | "t" => {
+                // the test runner has its own flags
+                command.name = "test".to_string();
+                command.files = args[1..].to_vec();
+                return Ok(command);
+            }
+            // `rho file.rho` runs the file
+            file if file.ends_with(".rho") => {
+                command.files.push(file.to_string());
+                "run".to_string()
+            }
+            other => return Err(format!("unknown command `{}`", other)),
+        };
+        let mut build_flag = None; // the first flag only `rho build` takes
+        let mut passes = false;
+        let mut args = args[1..].iter();
+        while let Some(arg) = args.next() {
+            // `--emit=ll` or `--emit ll`
+            let (flag, value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = |what: &str| match value.clone() {
+                Some(value) => Ok(value),
+                None => args
+                    .next()
+                    .cloned()
+                    .ok_or(format!("`{}` needs {}", flag, what)),
+            };
+            match flag {
+                "-h" | "--help" => command.name = "help".to_string(),
+                "--json" => command.json = true,
+                "--vm" => command.vm = true,
+                "-o" | "--output" => command.build.output = Some(value("a file")?),
+                "--emit" => {
+                    let emit = value("one of ll, bc, obj, exe and mir")?;
+                    command.build.emit = Emit::parse(&emit).ok_or(format!(
+                        "unknown `--emit` `{}`, it's one of ll, bc, obj, exe and mir",
+                        emit
+                    ))?;
+                    build_flag.get_or_insert(flag);
+                }
+                "--target" => {
+                    command.build.target = Some(value("a target triple")?);
+                    build_flag.get_or_insert(flag);
+                }
+                "--passes" => {
+                    let list = value("a list of passes")?;
+                    command.build.passes = Pass::parse_list(&list).ok_or(format!(
+                        "unknown `--passes` `{}`, they're any of inline, fold, copies and dce, or none or all",
+                        list
+                    ))?;
+                    passes = true;
+                }
+                "-O" | "-O0" | "-O1" | "-O2" | "-O3" => {
+                    command.build.opt_level = flag[2..].parse().unwrap_or(2);
+                    build_flag.get_or_insert(flag);
+                }
+                flag if flag.starts_with('-') => return Err(format!("unknown flag `{}`", flag)),
+                file => command.files.push(file.to_string()),
+            }
+        }
+        if matches!(command.name.as_str(), "help" | "version") {
+            return Ok(command);
+        }
+        if command.json && !matches!(command.name.as_str(), "check" | "tokens" | "ast") {
+            return Err(format!("`rho {}` has no JSON output", command.name));
+        }
+        if command.build.output.is_some() && command.name != "build" {
+            return Err(format!("`rho {}` doesn't write a file", command.name));
+        }
+        // stdout only takes text
+        if command.build.output.as_deref() == Some("-")
+            && !matches!(command.build.emit, Emit::Ll | Emit::Mir)
+        {
+            return Err(
+                "`-o -` writes to stdout, it's only for `--emit=ll` and `--emit=mir`".to_string(),
+            );
+        }
+        if command.vm && command.name != "run" {
+            return Err("`--vm` is only for `rho run`".to_string());
+        }
+        // the interpreter runs the syntax tree, everything else compiles the MIR
+        if passes && !(matches!(command.name.as_str(), "build" | "disasm") || command.vm) {
+            return Err(
+                "`--passes` is only for `rho build`, `rho run --vm` and `rho disasm`".to_string(),
+            );
+        }
+        if let (Some(flag), false) = (build_flag, command.name == "build") {
+            return Err(format!("`{}` is only for `rho build`", flag));
+        }
+        match (command.name.as_str(), command.files.len()) {
+            ("repl", 0) => Ok(command),
+            ("repl", _) => Err("`rho repl` takes no file".to_string()),
+            (_, 0) => Err(format!("`rho {}` needs a file", command.name)),
+            ("check", _) | (_, 1) => Ok(command),
+            (name, _) => Err(format!("`rho {}` takes one file", name)),
+        }
+    }
+}
+
+// The interpreter recurses once per rho call, the test runner too, give them room for deep recursion
+fn on_big_stack(f: impl FnOnce() -> i32 + Send + 'static) -> i32 {
+    std::thread::Builder::new()
+        .stack_size(interpreter::STACK_SIZE)
+        .spawn(f)
+        .expect("Should have been able to start the interpreter")
+        .join()
+        .expect("The interpreter panicked")
+}
+
+fn read(file: &str) -> Option<String> {
+    match fs::read_to_string(file) {
+        Ok(source) => Some(source),
+        Err(e) => {
+            eprintln!("error: can't read {}: {}", file, e);
+            None
+        }
+    }
+}
+
+fn run(file: &str, on_vm: bool, passes: Vec<Pass>) -> i32 {
+    let Some(source) = read(file) else {
+        return 2;
+    };
+    let (compiled, diagnostics) = crate::compile(&source);
+    for d in diagnostics.iter() {
+        eprint!("{}", d.render(file, &source));
+    }
+    let Some((mut program, symbols)) = compiled else {
+        return 1;
+    };
+    testing::strip_tests(&mut program);
+    let file = file.to_string();
+    on_big_stack(move || {
+        let result = match on_vm {
+            true => compile_bytecode(&program, &symbols, &passes)
+                .and_then(|b| vm::run(&b).map_err(|e| vec![e])),
+            false => interpreter::run(&program, &symbols).map_err(|e| vec![e]),
+        };
+        let code = match result {
+            Ok(code) => code,
+            Err(errors) => {
+                for e in errors.iter() {
+                    eprint!("{}", e.render(&file, &source));
+                }
+                1
+            }
+        };
+        // the same check compiled programs make in `rho_finish`
+        if std::env::var_os("RHO_LEAK_CHECK").is_some() {
+            gc::collect();
+            let live = gc::live_scopes();
+            if live > 0 {
+                eprintln!("rho: {} scopes still live at exit", live);
+            }
+        }
+        code
+    })
+}
+
+fn build(file: &str, options: &BuildOptions) -> i32 {
+    let Some(source) = read(file) else {
+        return 2;
+    };
+    let (compiled, diagnostics) = crate::compile(&source);
+    for d in diagnostics.iter() {
+        eprint!("{}", d.render(file, &source));
+    }
+    let Some((mut program, symbols)) = compiled else {
+        return 1;
+    };
+    testing::strip_tests(&mut program);
+    let mut mir = match mir::lower(&program, &symbols) {
+        Ok(mir) => mir,
+        Err(errors) => {
+            for e in errors.iter() {
+                eprint!("{}", e.render(file, &source));
+            }
+            return 1;
+        }
+    };
+    passes::optimize(&mut mir, &options.passes);
+    let ir = || codegen::generate(&mir, file);
+    if options.output.as_deref() == Some("-") {
+        let text = match options.emit {
+            Emit::Mir => Ok(mir.to_string()),
+            _ => native::build_text(file, &ir(), options),
+        };
+        return match text {
+            Ok(text) => {
+                emit(text);
+                0
+            }
+            Err(message) => {
+                eprintln!("error: {}", message);
+                1
+            }
+        };
+    }
+    // the MIR is written as is, the LLVM tools aren't needed for it
+    if options.emit == Emit::Mir {
+        let output = match &options.output {
+            Some(output) => output.into(),
+            None => native::default_output(file, Emit::Mir),
+        };
+        return match fs::write(&output, mir.to_string()) {
+            Ok(_) => 0,
+            Err(e) => {
+                eprintln!("error: can't write {}: {}", output.display(), e);
+                1
+            }
+        };
+    }
+    match native::build(file, &ir(), options) {
+        Ok(_) => 0,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            1
+        }
+    }
+}
+
+fn disassemble(file: &str, passes: &[Pass]) -> i32 {
+    let Some(source) = read(file) else {
+        return 2;
+    };
+    let (compiled, diagnostics) = crate::compile(&source);
+    for d in diagnostics.iter() {
+        eprint!("{}", d.render(file, &source));
+    }
+    let Some((mut program, symbols)) = compiled else {
+        return 1;
+    };
+    testing::strip_tests(&mut program);
+    match compile_bytecode(&program, &symbols, passes) {
+        Ok(bytecode) => {
+            emit(bytecode.to_string());
+            0
+        }
+        Err(errors) => {
+            for e in errors.iter() {
+                eprint!("{}", e.render(file, &source));
+            }
+            1
+        }
+    }
+}
+
+// The bytecode `rho run --vm` runs, compiled from the program's MIR after `passes`
+fn compile_bytecode(
+    program: &[Expression],
+    symbols: &SymbolTable,
+    passes: &[Pass],
+) -> Result<bytecode::Program, Vec<Diagnostic>> {
+    let mut mir = mir::lower_for_vm(program, symbols)?;
+    passes::optimize(&mut mir, passes);
+    bytecode::compile(&mir).map_err(|e| vec![e])
+}
+
+fn check(files: &[String], as_json: bool) -> i32 {
+    let mut code = 0;
+    let mut found = vec![];
+    for file in files {
+        let Some(source) = read(file) else {
+            code = 2;
+            continue;
+        };
+        let (_, diagnostics) = crate::compile(&source);
+        if diagnostics.iter().any(|d| d.is_error()) && code == 0 {
+            code = 1;
+        }
+        for d in diagnostics.iter() {
+            if as_json {
+                found.push(diagnostic_json(file, d));
+            } else {
+                eprint!("{}", d.render(file, &source));
+            }
+        }
+    }
+    if as_json {
+        println!("{}", Json::Array(found));
+    }
+    code
+}
+
+fn dump_tokens(file: &str, as_json: bool) -> i32 {
+    let Some(source) = read(file) else {
+        return 2;
+    };
+    match tokenize(&source) {
+        Ok(tokens) if as_json => {
+            let tokens = tokens.iter().map(json::token).collect();
+            emit(format!("{}\n", Json::Array(tokens)));
+            0
+        }
+        Ok(tokens) => {
+            emit(format_tokens(&tokens));
+            0
+        }
+        Err(e) => report(file, &source, e, as_json),
+    }
+}
+
+fn dump_ast(file: &str, as_json: bool) -> i32 {
+    let Some(source) = read(file) else {
+        return 2;
+    };
+    match tokenize(&source).and_then(|tokens| expressionize(&tokens)) {
+        Ok(expressions) if as_json => {
+            let json = expressions.iter().map(json::expression).collect();
+            emit(format!("{}\n", Json::Array(json)));
+            0
+        }
+        Ok(expressions) => {
+            let lines = expressions.iter().map(|e| format!("{:#?}\n", e));
+            emit(lines.collect());
+            0
+        }
+        Err(e) => report(file, &source, e, as_json),
+    }
+}
+
+// A token a line, ie. `1:5 Type(BuiltIn(String)) "str"`
+pub fn format_tokens(tokens: &[Token]) -> String {
+    let lines = tokens.iter().map(|token| {
+        format!(
+            "{}\t{:?}\t{:?}\n",
+            token.span, token.token_type, token.string
+        )
+    });
+    lines.collect()
+}
+
+// Dumps are often piped into `head`, a closed stdout isn't an error
+fn emit(text: String) {
+    let _ = std::io::stdout().write_all(text.as_bytes());
+}
+
+// A syntax error from `rho tokens` or `rho ast`
+fn report(file: &str, source: &str, e: Diagnostic, as_json: bool) -> i32 {
+    if as_json {
+        println!("{}", Json::Array(vec![diagnostic_json(file, &e)]));
+    } else {
+        eprint!("{}", e.render(file, source));
+    }
+    1
+}
+
+fn diagnostic_json(file: &str, d: &Diagnostic) -> Json {
+    let severity = match d.severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+    };
+    let mut fields = vec![
+        ("severity", Json::string(severity)),
+        ("message", Json::string(&d.message)),
+        ("file", Json::string(file)),
+    ];
+    fields.extend(json::span(d.span));
+    let notes = d.notes.iter().map(|(message, span)| {
+        let mut note = vec![("message", Json::string(message))];
+        note.extend(json::span(*span));
+        Json::object(note)
+    });
+    fields.push(("notes", Json::Array(notes.collect())));
+    Json::object(fields)
+}
+
+#[cfg(test)]
+fn arguments(args: &str) -> Vec<String> {
+    args.split_whitespace().map(|a| a.to_string()).collect()
+}
+
+#[test]
+fn test_command_parse() {
+    let command = Command::parse(&arguments("check a.rho b.rho --json")).unwrap();
+    assert_eq!(
+        command,
+        Command {
+            name: "check".to_string(),
+            files: arguments("a.rho b.rho"),
+            json: true,
+            vm: false,
+            build: BuildOptions::default(),
+        }
+    );
+    let command = Command::parse(&arguments("build main.rho -o main")).unwrap();
+    assert_eq!(command.build.output.as_deref(), Some("main"));
+    let command = Command::parse(&arguments(
+        "build a.rho --emit=obj -O --target x86_64-linux-gnu",
+    ));
+    assert_eq!(
+        command.unwrap().build,
+        BuildOptions {
+            emit: Emit::Obj,
+            output: None,
+            target: Some("x86_64-linux-gnu".to_string()),
+            opt_level: 2,
+            passes: Pass::ALL.to_vec(),
+        }
+    );
+    let command = Command::parse(&arguments("build a.rho -O3 --emit ll")).unwrap();
+    assert_eq!((command.build.emit, command.build.opt_level), (Emit::Ll, 3));
+    let command = Command::parse(&arguments("build a.rho --emit=mir")).unwrap();
+    assert_eq!(command.build.emit, Emit::Mir);
+    let command = Command::parse(&arguments("build a.rho -o - --emit=ll")).unwrap();
+    assert_eq!(command.build.output.as_deref(), Some("-"));
+    let command = Command::parse(&arguments("build a.rho --passes=fold,dce")).unwrap();
+    assert_eq!(command.build.passes, [Pass::Fold, Pass::DeadCode]);
+    let command = Command::parse(&arguments("build a.rho --passes none")).unwrap();
+    assert_eq!(command.build.passes, []);
+    assert_eq!(Command::parse(&arguments("hello.rho")).unwrap().name, "run");
+    assert!(Command::parse(&arguments("hello.rho --vm")).unwrap().vm);
+    let command = Command::parse(&arguments("run a.rho --vm --passes=fold")).unwrap();
+    assert_eq!(command.build.passes, [Pass::Fold]);
+    let command = Command::parse(&arguments("disasm a.rho --passes none")).unwrap();
+    assert_eq!(command.build.passes, []);
+    let command = Command::parse(&arguments("build a.rho --passes=all")).unwrap();
+    assert_eq!(command.build.passes, Pass::ALL);
+    let command = Command::parse(&arguments("disasm a.rho")).unwrap();
+    assert_eq!(
+        (command.name.as_str(), command.files),
+        ("disasm", arguments("a.rho"))
+    );
+    assert_eq!(
+        Command::parse(&arguments("ast --help")).unwrap().name,
+        "help"
+    );
+    let test = Command::parse(&arguments("t src --filter x")).unwrap();
+    assert_eq!(
+        (test.name.as_str(), test.files),
+        ("test", arguments("src --filter x"))
+    );
+
+    let errors: Vec<String> = [
+        "",
+        "compile a.rho",
+        "run",
+        "run a.rho b.rho",
+        "run a.rho --json",
+        "check a.rho -o out",
+        "build a.rho -o",
+        "tokens a.rho --pretty",
+        "build a.rho --emit=asm",
+        "run a.rho -O1",
+        "build a.rho --target",
+        "build a.rho --passes=fold,cse",
+        "run a.rho --passes=none",
+        "build a.rho --vm",
+        "disasm",
+        "build a.rho -o - --emit=obj",
+        "build a.rho -o -",
+    ]
+    .iter()
+    .map(|args| Command::parse(&arguments(args)).unwrap_err())
+    .collect();
+    assert_eq!(
+        errors,
+        vec![
+            "no command given",
+            "unknown command `compile`",
+            "`rho run` needs a file",
+            "`rho run` takes one file",
+            "`rho run` has no JSON output",
+            "`rho check` doesn't write a file",
+            "`-o` needs a file",
+            "unknown flag `--pretty`",
+            "unknown `--emit` `asm`, it's one of ll, bc, obj, exe and mir",
+            "`-O1` is only for `rho build`",
+            "`--target` needs a target triple",
+            "unknown `--passes` `fold,cse`, they're any of inline, fold, copies and dce, or none or all",
+            "`--passes` is only for `rho build`, `rho run --vm` and `rho disasm`",
+            "`--vm` is only for `rho run`",
+            "`rho disasm` needs a file",
+            "`-o -` writes to stdout, it's only for `--emit=ll` and `--emit=mir`",
+            "`-o -` writes to stdout, it's only for `--emit=ll` and `--emit=mir`",
+        ]
+    );
+}
+
+#[test]
+fn test_json_output() {
+    let source = "x = \"hi\"\n";
+    let tokens = tokenize(source).unwrap();
+    assert_eq!(
+        json::token(&tokens[0]).to_string(),
+        r#"{"type":"Identifier","text":"x","line":1,"col":1,"len":1}"#
+    );
+    let (_, diagnostics) = crate::compile("x = 1\nx = 2\n");
+    let json = Json::Array(
+        diagnostics
+            .iter()
+            .map(|d| diagnostic_json("a.rho", d))
+            .collect(),
+    );
+    assert!(json
+        .to_string()
+        .starts_with(r#"[{"severity":"error","message":"#));
+    let expressions = expressionize(&tokens).unwrap();
+    assert!(json::expression(&expressions[0])
+        .to_string()
+        .starts_with(r#"{"node":"Definition","mutability":"Immutable","#));
+}
@@ -0,0 +1,2045 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::atoms::{self, AtomTable};
+#[cfg(test)]
+use crate::diagnostics::Diagnostic;
+use crate::mir::{self, BinOp, Callee, Constant, LocalId, Operand, Place, Rvalue, Statement};
+use crate::mir::{Terminator, UnOp};
+use crate::rho_core;
+use crate::tokens::*;
+use crate::types::Type;
+
+/* Compiles a program's MIR to textual LLVM IR, in the typed pointer syntax of LLVM 14 that
+ * `llvm-as` reads. The top level becomes the C `main`, which exits with the code `<top level>`
+ * returns, and each function a function of its own.
+ *
+ * A temporary the MIR assigns once is an LLVM register, the bindings and the other temporaries
+ * live in `alloca`s, or in globals for top level bindings since every function can see those. An
+ * int is an i64, a float a double, a bool an i1, a char and an atom an i32 and a str a null
+ * terminated i8*. Lists, maps, tuples and structs are i8* pointers to the runtime's objects, a
+ * tuple or struct is a record, and the elements they hold are the u64 bits of their values.
+ * Overflow, division by zero and the other runtime errors of the interpreter are checked for and
+ * end the program through `rho_fail` with the location of the statement.
+ *
+ * `rho_fail`, printing and the work on objects are calls to the runtime, `rho_runtime`, which
+ * executables link. The MIR's copies of an object are `rho_retain` calls and its drops
+ * `rho_release` ones, string constants have a count the runtime never changes so no calls are
+ * made for them. Assigning inside a list, map or record changes it in place after `rho_unique`
+ * made sure no other value has it.
+ *
+ * A closure is a record too, of its function, its name and the cells of the locals it shares with
+ * the function that made it, and it's called with the record before its arguments. A captured
+ * local's `alloca` holds its cell, a record of one field. A stream is the runtime's record
+ * of where its elements come from, the closures it calls are passed with an adapter, ie.
+ * `@rho.adapter.0`, that calls one with the bits of its arguments and returns the bits of its
+ * result.
+ */
+pub fn generate(program: &mir::Program, file: &str) -> String {
+    let mut g = Codegen {
+        program,
+        file: file.to_string(),
+        atoms: AtomTable::new(),
+        strings: HashMap::new(),
+        constants: String::new(),
+        globals: vec![],
+        declarations: BTreeSet::new(),
+        helpers: BTreeSet::new(),
+        values: BTreeSet::new(),
+        adapters: vec![],
+        f: Function::default(),
+    };
+    let mut globals = String::new();
+    let mut names = HashMap::new();
+    for global in program.globals.iter() {
+        let name = format!("@rho.var.{}", unique(&mut names, &global.name));
+        let t = llvm_type(&global.ty);
+        globals += &format!("{} = internal global {} zeroinitializer\n", name, t);
+        g.globals.push(name);
+    }
+
+    let mut functions = vec![];
+    for f in program.functions.iter() {
+        functions.push(g.function(f, Kind::Function));
+    }
+    for (id, f) in program.closures.iter().enumerate() {
+        functions.push(g.function(f, Kind::Closure(id)));
+    }
+    functions.push(g.function(&program.entry, Kind::Main));
+    for name in std::mem::take(&mut g.values) {
+        functions.push(g.function_value(&name));
+    }
+    for (id, t) in std::mem::take(&mut g.adapters).iter().enumerate() {
+        functions.push(g.adapter(id, t));
+    }
+    // a helper can need another one
+    let mut generated = BTreeSet::new();
+    while let Some(helper) = g.helpers.difference(&generated).next().copied() {
+        generated.insert(helper);
+        functions.push(g.helper(helper));
+    }
+
+    let mut out = format!(
+        "; ModuleID = '{0}'\nsource_filename = \"{0}\"\n\n",
+        atoms::llvm_escape(file)
+    );
+    out += &g.constants;
+    out += &atoms::llvm_constants(&g.atoms);
+    if !globals.is_empty() {
+        out += &format!("\n{}", globals);
+    }
+    out += "\n";
+    for declaration in g.declarations.iter() {
+        out += &format!("{}\n", declaration);
+    }
+    for text in functions {
+        out += &format!("\n{}", text);
+    }
+    out
+}
+
+// The LLVM type of a value of type `t`, the MIR only has the types it has one for and unit
+fn llvm_type(t: &Type) -> &'static str {
+    match t {
+        Type::Primitive(PrimitiveType::Int) => "i64",
+        Type::Primitive(PrimitiveType::Float) => "double",
+        Type::Primitive(PrimitiveType::Bool) => "i1",
+        Type::Primitive(PrimitiveType::Char) | Type::Primitive(PrimitiveType::Atom) => "i32",
+        Type::BuiltIn(
+            BuiltinType::String
+            | BuiltinType::List
+            | BuiltinType::Map
+            | BuiltinType::Tuple
+            | BuiltinType::Result
+            | BuiltinType::Stream,
+            _,
+        )
+        | Type::Named(..)
+        | Type::Function(..) => "i8*",
+        _ => "void",
+    }
+}
+
+/* The runtime's descriptor of a type, see `rho_runtime::values`, for the functions that look
+ * inside a list, map or record, ie. `lms` for a `list[map[str, int]]`. A struct that holds
+ * itself refers to the one around it by its name.
+ */
+fn descriptor(program: &mir::Program, t: &Type, around: &mut Vec<String>) -> String {
+    match t {
+        Type::Primitive(PrimitiveType::Int) => "i".to_string(),
+        Type::Primitive(PrimitiveType::Float) => "f".to_string(),
+        Type::Primitive(PrimitiveType::Bool) => "b".to_string(),
+        Type::Primitive(PrimitiveType::Char) => "c".to_string(),
+        Type::Primitive(PrimitiveType::Atom) => "a".to_string(),
+        Type::BuiltIn(BuiltinType::String, _) => "s".to_string(),
+        Type::BuiltIn(BuiltinType::List, args) if args.len() == 1 => {
+            format!("l{}", descriptor(program, &args[0], around))
+        }
+        Type::BuiltIn(BuiltinType::Map, args) if args.len() == 2 => {
+            let key = descriptor(program, &args[0], around);
+            format!("m{}{}", key, descriptor(program, &args[1], around))
+        }
+        Type::BuiltIn(BuiltinType::Tuple, args) => {
+            let elements: Vec<String> = (args.iter())
+                .map(|a| descriptor(program, a, around))
+                .collect();
+            format!("t{};{}", args.len(), elements.concat())
+        }
+        Type::BuiltIn(BuiltinType::Result, args) if args.len() == 2 => {
+            let ok = descriptor(program, &args[0], around);
+            format!("e{}{}", ok, descriptor(program, &args[1], around))
+        }
+        Type::Named(name, _) if around.contains(name) => format!("R{};", name),
+        Type::Named(name, _) => {
+            let fields = program.fields(t).unwrap_or_default();
+            around.push(name.clone());
+            let fields: Vec<String> = (fields.iter())
+                .map(|(f, t)| format!("{};{}", f, descriptor(program, t, around)))
+                .collect();
+            around.pop();
+            let name = crate::generics::generic_name(name);
+            format!("r{};{};{}", name, fields.len(), fields.concat())
+        }
+        Type::Function(params, _) => format!("F{};", params.len()),
+        Type::BuiltIn(BuiltinType::Stream, _) => "S".to_string(),
+        _ => "?".to_string(),
+    }
+}
+
+// The bit of each field of a record that holds an object is set, ie. for `rho_record_new`
+fn objects_mask(types: &[Type]) -> u64 {
+    (types.iter().enumerate())
+        .filter(|(_, t)| mir::owns(t))
+        .fold(0, |mask, (i, _)| mask | 1 << i)
+}
+
+// The types of the elements of a tuple or the fields of a struct, in the order its record has them
+fn record_types(program: &mir::Program, t: &Type) -> Vec<Type> {
+    match t {
+        Type::BuiltIn(BuiltinType::Tuple, args) => args.clone(),
+        t => (program.fields(t).unwrap_or_default().into_iter())
+            .map(|(_, t)| t)
+            .collect(),
+    }
+}
+
+// The type parameter `i` of a list or map type is, ie. 0 for a list's elements and a map's keys
+fn type_arg(t: &Type, i: usize) -> Type {
+    t.type_args().get(i).cloned().unwrap_or(Type::Var(0))
+}
+
+// Rho names can hold any letter, LLVM names unquoted only ascii ones
+fn llvm_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/* A name for a binding that no other one in its function, or the module for a global, has. A
+ * binding can be shadowed or declared in two blocks, the second `x` is `x.2`.
+ */
+fn unique(names: &mut HashMap<String, usize>, name: &str) -> String {
+    let name = llvm_name(name);
+    let count = names.entry(name.clone()).or_insert(0);
+    *count += 1;
+    match *count {
+        1 => name,
+        n => format!("{}.{}", name, n),
+    }
+}
+
+/* The blocks a function reaches from `bb0`, in reverse postorder, so a block comes after the
+ * ones it's only reached through. Those assign the registers it uses, and a `then` comes before
+ * its `else`.
+ */
+fn block_order(function: &mir::Function) -> Vec<usize> {
+    let mut visited = vec![false; function.blocks.len()];
+    let mut order = vec![];
+    // the blocks being visited with the successors still to visit
+    let mut stack = vec![(0, successors(&function.blocks[0].terminator))];
+    visited[0] = true;
+    while let Some((block, pending)) = stack.last_mut() {
+        match pending.pop() {
+            Some(next) if !visited[next] => {
+                visited[next] = true;
+                stack.push((next, successors(&function.blocks[next].terminator)));
+            }
+            Some(_) => {}
+            None => {
+                order.push(*block);
+                stack.pop();
+            }
+        }
+    }
+    order.reverse();
+    order
+}
+
+// In the order they're visited, which is the reverse of the one the last is popped in
+fn successors(terminator: &Terminator) -> Vec<usize> {
+    match terminator {
+        Terminator::Goto(block) => vec![*block],
+        Terminator::Branch {
+            then, otherwise, ..
+        } => vec![*otherwise, *then],
+        _ => vec![],
+    }
+}
+
+/* The LLVM type of a pointer to the function of a closure taking `params` and giving `ret`, its
+ * record comes before them
+ */
+fn closure_type(params: &[Type], ret: &Type) -> String {
+    let params: Vec<String> = params
+        .iter()
+        .map(|t| format!(", {}", llvm_type(t)))
+        .collect();
+    format!("{} (i8*{})*", llvm_type(ret), params.concat())
+}
+
+// What a function of the MIR is generated as
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Main,           // the C `main`, of the top level
+    Function,       // `@rho.fn.<name>`
+    Closure(usize), // `@rho.closure.<id>`, which is given its record as `%env`
+}
+
+// The state of the function being generated
+#[derive(Default)]
+struct Function {
+    locals: Vec<Type>,
+    captured: Vec<bool>, // the locals in cells, their slots hold the cells
+    upvalues: Vec<Type>,
+    slots: Vec<Option<String>>, // the `alloca` of each local, None for the registers
+    values: HashMap<LocalId, String>, // the registers' values, ie. `%t3` or a constant
+    allocas: String, // in the entry block, so they run once however often their block does
+    body: String,
+    next: usize,      // numbers the registers and labels
+    terminated: bool, // whether the block ended with a `br`, `ret` or `unreachable`
+}
+
+struct Codegen<'a> {
+    program: &'a mir::Program,
+    file: String,
+    atoms: AtomTable,
+    strings: HashMap<String, String>, // string constants by their contents
+    constants: String,
+    globals: Vec<String>,
+    declarations: BTreeSet<String>,
+    helpers: BTreeSet<&'static str>,
+    values: BTreeSet<String>, // the top level functions used as values
+    adapters: Vec<Type>,      // the types of the closures the runtime calls
+    f: Function,
+}
+
+impl Codegen<'_> {
+    fn declare(&mut self, declaration: &str) {
+        self.declarations.insert(declaration.to_string());
+    }
+
+    fn temp(&mut self) -> String {
+        self.f.next += 1;
+        format!("%t{}", self.f.next)
+    }
+
+    fn label(&mut self, name: &str) -> String {
+        self.f.next += 1;
+        format!("{}{}", name, self.f.next)
+    }
+
+    fn emit(&mut self, instruction: &str) {
+        self.f.body += &format!("  {}\n", instruction);
+    }
+
+    fn terminate(&mut self, instruction: &str) {
+        self.emit(instruction);
+        self.f.terminated = true;
+    }
+
+    fn branch_if(&mut self, condition: &str, then: &str, otherwise: &str) {
+        self.terminate(&format!(
+            "br i1 {}, label %{}, label %{}",
+            condition, then, otherwise
+        ));
+    }
+
+    // Starts a block, the block before falls through to it unless it already ended
+    fn start_block(&mut self, label: &str) {
+        if !self.f.terminated {
+            self.f.body += &format!("  br label %{}\n", label);
+        }
+        self.f.body += &format!("{}:\n", label);
+        self.f.terminated = false;
+    }
+
+    // A register holding the result of `instruction`
+    fn assign(&mut self, instruction: &str) -> String {
+        let register = self.temp();
+        self.emit(&format!("{} = {}", register, instruction));
+        register
+    }
+
+    fn alloca(&mut self, name: &str, t: &str) -> String {
+        let slot = format!("%{}", name);
+        self.f.allocas += &format!("  {} = alloca {}\n", slot, t);
+        slot
+    }
+
+    /* A pointer to the first byte of a constant string. It has the header of a runtime object
+     * before it, with a count of -1 so it's never freed, and is a str like any other.
+     */
+    fn string_constant(&mut self, s: &str) -> String {
+        if let Some(pointer) = self.strings.get(s) {
+            return pointer.to_owned();
+        }
+        let name = format!("@rho.str.{}", self.strings.len());
+        let object = format!("{{ i64, i64, [{} x i8] }}", s.len() + 1);
+        self.constants += &format!(
+            "{} = private unnamed_addr constant {} {{ i64 -1, i64 {}, [{} x i8] c\"{}\\00\" }}\n",
+            name,
+            object,
+            rho_runtime::memory::RHO_KIND_STR,
+            s.len() + 1,
+            atoms::llvm_escape(s)
+        );
+        let pointer = format!(
+            "getelementptr inbounds ({0}, {0}* {1}, i64 0, i32 2, i64 0)",
+            object, name
+        );
+        self.strings.insert(s.to_string(), pointer.clone());
+        pointer
+    }
+
+    /* Ends the program with a runtime error at `span`, `message` is an i8* after `prefix`. A
+     * `traced` one, of `panic` or `assert`, is `rho_panic`'s, which always shows the stack trace.
+     */
+    fn fail_with(&mut self, prefix: &str, message: &str, span: Span, traced: bool) {
+        let function = if traced { "rho_panic" } else { "rho_fail" };
+        self.declare(&format!(
+            "declare void @{}(i8*, i8*, i8*, i64, i64) noreturn",
+            function
+        ));
+        let prefix = self.string_constant(prefix);
+        let file = self.string_constant(&self.file.clone());
+        self.emit(&format!(
+            "call void @{}(i8* {}, i8* {}, i8* {}, i64 {}, i64 {})",
+            function, prefix, message, file, span.line, span.col
+        ));
+        self.terminate("unreachable");
+    }
+
+    // Fails with `message` when the i1 `condition` holds, the code after runs when it doesn't
+    fn fail_if(&mut self, condition: &str, message: &str, span: Span) {
+        let failed = self.label("fail");
+        let ok = self.label("ok");
+        self.branch_if(condition, &failed, &ok);
+        self.start_block(&failed);
+        let message = self.string_constant(message);
+        self.fail_with("", &message, span, false);
+        self.start_block(&ok);
+    }
+
+    fn place_type(&self, place: Place) -> Type {
+        match place {
+            Place::Local(id) => self.f.locals[id].clone(),
+            Place::Global(id) => self.program.globals[id].ty.clone(),
+            Place::Upvalue(id) => self.f.upvalues[id].clone(),
+        }
+    }
+
+    fn operand_type(&self, operand: &Operand) -> Type {
+        match operand {
+            Operand::Copy(place) | Operand::Move(place) => self.place_type(*place),
+            Operand::Constant(c) => c.ty(),
+        }
+    }
+
+    // Where a place is stored, None for a register
+    fn slot(&self, place: Place) -> Option<String> {
+        match place {
+            Place::Local(id) => self.f.slots[id].clone(),
+            Place::Global(id) => Some(self.globals[id].clone()),
+            Place::Upvalue(_) => unreachable!("an upvalue is in a cell of the closure's record"),
+        }
+    }
+
+    // Whether the place is in a cell, a captured local or an upvalue
+    fn in_cell(&self, place: Place) -> bool {
+        match place {
+            Place::Local(id) => self.f.captured[id],
+            Place::Upvalue(_) => true,
+            Place::Global(_) => false,
+        }
+    }
+
+    /* The cell a place is in, a record of one field. A captured local's slot holds its cell, the
+     * closure's record holds an upvalue's after the function and its name.
+     */
+    fn cell(&mut self, place: Place) -> Option<String> {
+        match place {
+            Place::Local(id) if self.f.captured[id] => {
+                let slot = self.f.slots[id].clone().expect("A cell should be stored");
+                Some(self.assign(&format!("load i8*, i8** {}", slot)))
+            }
+            Place::Upvalue(id) => {
+                self.declare("declare i64 @rho_record_get(i8*, i64)");
+                let bits = self.assign(&format!(
+                    "call i64 @rho_record_get(i8* %env, i64 {})",
+                    id + 2
+                ));
+                Some(self.assign(&format!("inttoptr i64 {} to i8*", bits)))
+            }
+            _ => None,
+        }
+    }
+
+    // A new cell for the captured local, which lets go of the one it had
+    fn fresh(&mut self, id: LocalId) {
+        let slot = self.f.slots[id].clone().expect("A cell should be stored");
+        self.release_cell(&slot);
+        self.declare("declare i8* @rho_record_new(i64, i64)");
+        let objects = mir::owns(&self.f.locals[id]) as u64;
+        let cell = self.assign(&format!("call i8* @rho_record_new(i64 1, i64 {})", objects));
+        self.emit(&format!("store i8* {}, i8** {}", cell, slot));
+    }
+
+    fn release_cell(&mut self, slot: &str) {
+        self.declare("declare void @rho_release(i8*)");
+        let cell = self.assign(&format!("load i8*, i8** {}", slot));
+        self.emit(&format!("call void @rho_release(i8* {})", cell));
+    }
+
+    fn load(&mut self, place: Place) -> String {
+        if let Some(cell) = self.cell(place) {
+            // the cell lends it
+            self.declare("declare i64 @rho_record_get(i8*, i64)");
+            let bits = self.assign(&format!("call i64 @rho_record_get(i8* {}, i64 0)", cell));
+            return self.value_of(&bits, &self.place_type(place));
+        }
+        let Some(slot) = self.slot(place) else {
+            let Place::Local(id) = place else {
+                unreachable!("globals are stored")
+            };
+            return (self.f.values.get(&id))
+                .expect("The MIR should assign a temporary before using it")
+                .clone();
+        };
+        let lt = llvm_type(&self.place_type(place));
+        self.assign(&format!("load {0}, {0}* {1}", lt, slot))
+    }
+
+    fn store(&mut self, place: Place, value: String) {
+        if let Some(cell) = self.cell(place) {
+            // which releases the value it had
+            self.declare("declare void @rho_record_set(i8*, i64, i64)");
+            let bits = self.bits_of(&value, &self.place_type(place));
+            self.emit(&format!(
+                "call void @rho_record_set(i8* {}, i64 0, i64 {})",
+                cell, bits
+            ));
+            return;
+        }
+        match self.slot(place) {
+            Some(slot) => {
+                let lt = llvm_type(&self.place_type(place));
+                self.emit(&format!("store {0} {1}, {0}* {2}", lt, value, slot));
+            }
+            None => {
+                let Place::Local(id) = place else {
+                    unreachable!("globals are stored")
+                };
+                self.f.values.insert(id, value);
+            }
+        }
+    }
+
+    fn constant(&mut self, c: &Constant) -> String {
+        match c {
+            Constant::Int(i) => i.to_string(),
+            // the bits, a decimal double has to be exact in LLVM
+            Constant::Float(f) => format!("0x{:016X}", f.to_bits()),
+            Constant::Bool(b) => b.to_string(),
+            Constant::Char(c) => (*c as u32).to_string(),
+            Constant::Atom(name) => self.atoms.intern(name).0.to_string(),
+            Constant::Str(s) => self.string_constant(s),
+            Constant::Unit => unreachable!("the native backend has no unit values"),
+        }
+    }
+
+    // The value of an operand the code only looks at
+    fn operand(&mut self, operand: &Operand) -> String {
+        match operand {
+            Operand::Copy(place) | Operand::Move(place) => self.load(*place),
+            Operand::Constant(c) => self.constant(c),
+        }
+    }
+
+    /* The value of an operand that is stored or passed on, a copy of an object is a new reference,
+     * and so is a move out of a cell, which keeps its own
+     */
+    fn consume(&mut self, operand: &Operand) -> String {
+        let value = self.operand(operand);
+        let copied = match operand {
+            Operand::Copy(place) => Some(*place),
+            Operand::Move(place) if self.in_cell(*place) => Some(*place),
+            _ => None,
+        };
+        if let Some(place) = copied {
+            let t = self.place_type(place);
+            self.retain(&value, &t);
+        }
+        value
+    }
+
+    fn retain(&mut self, value: &str, t: &Type) {
+        if mir::owns(t) && !value.starts_with("getelementptr") {
+            self.declare("declare void @rho_retain(i8*)");
+            self.emit(&format!("call void @rho_retain(i8* {})", value));
+        }
+    }
+
+    fn release(&mut self, value: &str, t: &Type) {
+        if mir::owns(t) && !value.starts_with("getelementptr") {
+            self.declare("declare void @rho_release(i8*)");
+            self.emit(&format!("call void @rho_release(i8* {})", value));
+        }
+    }
+
+    // The u64 bits of a value, which is how lists, maps and records hold them
+    fn bits_of(&mut self, v: &str, t: &Type) -> String {
+        match llvm_type(t) {
+            "i64" => v.to_string(),
+            "double" => self.assign(&format!("bitcast double {} to i64", v)),
+            "i8*" => self.assign(&format!("ptrtoint i8* {} to i64", v)),
+            lt => self.assign(&format!("zext {} {} to i64", lt, v)),
+        }
+    }
+
+    fn value_of(&mut self, bits: &str, t: &Type) -> String {
+        match llvm_type(t) {
+            "i64" => bits.to_string(),
+            "double" => self.assign(&format!("bitcast i64 {} to double", bits)),
+            "i8*" => self.assign(&format!("inttoptr i64 {} to i8*", bits)),
+            lt => self.assign(&format!("trunc i64 {} to {}", bits, lt)),
+        }
+    }
+
+    // A value to hand over to a list, map or record, as its bits
+    fn consume_bits(&mut self, operand: &Operand) -> String {
+        let t = self.operand_type(operand);
+        let v = self.consume(operand);
+        self.bits_of(&v, &t)
+    }
+
+    fn descriptor(&mut self, t: &Type) -> String {
+        let descriptor = descriptor(self.program, t, &mut vec![]);
+        self.string_constant(&descriptor)
+    }
+
+    // Where a struct's record has the field
+    fn field_index(&self, t: &Type, field: &str) -> usize {
+        (self.program.fields(t).unwrap_or_default().iter())
+            .position(|(f, _)| f == field)
+            .expect("The MIR should only read the fields a struct has")
+    }
+
+    // A new list, map or record of type `t` holding the values
+    fn aggregate(&mut self, value: &Rvalue, t: &Type) -> String {
+        match value {
+            Rvalue::List(values) => {
+                self.declare("declare i8* @rho_list_new(i64, i1 zeroext)");
+                self.declare("declare void @rho_list_push(i8*, i64)");
+                let objects = mir::owns(&type_arg(t, 0));
+                let list = self.assign(&format!(
+                    "call i8* @rho_list_new(i64 {}, i1 zeroext {})",
+                    values.len(),
+                    objects
+                ));
+                for v in values.iter() {
+                    let bits = self.consume_bits(v);
+                    self.emit(&format!(
+                        "call void @rho_list_push(i8* {}, i64 {})",
+                        list, bits
+                    ));
+                }
+                list
+            }
+            Rvalue::Map(entries) => {
+                self.declare("declare i8* @rho_map_new(i32, i1 zeroext)");
+                self.declare("declare void @rho_map_put(i8*, i64, i64)");
+                let keys = match type_arg(t, 0) == Type::string() {
+                    true => rho_runtime::maps::RHO_KEYS_STR,
+                    false => rho_runtime::maps::RHO_KEYS_BITS,
+                };
+                let objects = mir::owns(&type_arg(t, 1));
+                let map = self.assign(&format!(
+                    "call i8* @rho_map_new(i32 {}, i1 zeroext {})",
+                    keys, objects
+                ));
+                for (k, v) in entries.iter() {
+                    let (k, v) = (self.consume_bits(k), self.consume_bits(v));
+                    self.emit(&format!(
+                        "call void @rho_map_put(i8* {}, i64 {}, i64 {})",
+                        map, k, v
+                    ));
+                }
+                map
+            }
+            Rvalue::Tuple(_) | Rvalue::Struct(..) => {
+                self.declare("declare i8* @rho_record_new(i64, i64)");
+                self.declare("declare void @rho_record_set(i8*, i64, i64)");
+                let types = record_types(self.program, t);
+                let record = self.assign(&format!(
+                    "call i8* @rho_record_new(i64 {}, i64 {})",
+                    types.len(),
+                    objects_mask(&types)
+                ));
+                let fields: Vec<(usize, &Operand)> = match value {
+                    Rvalue::Struct(_, fields) => (fields.iter())
+                        .map(|(f, v)| (self.field_index(t, f), v))
+                        .collect(),
+                    _ => value.operands().into_iter().enumerate().collect(),
+                };
+                for (i, v) in fields {
+                    let bits = self.consume_bits(v);
+                    self.emit(&format!(
+                        "call void @rho_record_set(i8* {}, i64 {}, i64 {})",
+                        record, i, bits
+                    ));
+                }
+                record
+            }
+            value => unreachable!("`{}` isn't a new object", value),
+        }
+    }
+
+    /* An element of a list, map or record as a new reference, of type `t`. An index out of a
+     * list's bounds and a key a map doesn't have fail like in the interpreter.
+     */
+    fn element(&mut self, value: &Rvalue, t: &Type, span: Span) -> String {
+        let bits = match value {
+            Rvalue::Field(o, field) => {
+                let i = self.field_index(&self.operand_type(o), field);
+                let record = self.operand(o);
+                self.declare("declare i64 @rho_record_get(i8*, i64)");
+                self.assign(&format!(
+                    "call i64 @rho_record_get(i8* {}, i64 {})",
+                    record, i
+                ))
+            }
+            Rvalue::Index(o, i) => {
+                let ot = self.operand_type(o);
+                let it = self.operand_type(i);
+                let (object, index) = (self.operand(o), self.operand(i));
+                let file = self.string_constant(&self.file.clone());
+                let location = format!("i8* {}, i64 {}, i64 {}", file, span.line, span.col);
+                match ot {
+                    Type::BuiltIn(BuiltinType::Map, _) => {
+                        self.declare("declare i64 @rho_map_at(i8*, i64, i8*, i8*, i64, i64)");
+                        let key = self.bits_of(&index, &it);
+                        let descriptor = self.descriptor(&it);
+                        self.assign(&format!(
+                            "call i64 @rho_map_at(i8* {}, i64 {}, i8* {}, {})",
+                            object, key, descriptor, location
+                        ))
+                    }
+                    _ => {
+                        self.declare("declare i64 @rho_list_get(i8*, i64, i8*, i64, i64)");
+                        self.assign(&format!(
+                            "call i64 @rho_list_get(i8* {}, i64 {}, {})",
+                            object, index, location
+                        ))
+                    }
+                }
+            }
+            value => unreachable!("`{}` isn't an element", value),
+        };
+        let v = self.value_of(&bits, t);
+        self.retain(&v, t);
+        v
+    }
+
+    /* `p.x = v` and `xs[i][j] = v`, each object on the path is made unshared before it's changed
+     * so the values that had it before still see what they saw
+     */
+    fn set_path(&mut self, place: Place, path: &[mir::Step], value: &Operand, span: Span) {
+        let mut t = self.place_type(place);
+        let mut object = match self.cell(place) {
+            // the cell keeps its reference to the value
+            Some(cell) => {
+                self.declare("declare i64 @rho_record_unique_at(i8*, i64)");
+                let bits = self.assign(&format!(
+                    "call i64 @rho_record_unique_at(i8* {}, i64 0)",
+                    cell
+                ));
+                self.value_of(&bits, &t)
+            }
+            None => {
+                self.declare("declare i8* @rho_unique(i8*)");
+                let root = self.load(place);
+                let object = self.assign(&format!("call i8* @rho_unique(i8* {})", root));
+                self.store(place, object.clone());
+                object
+            }
+        };
+        let file = self.string_constant(&self.file.clone());
+        let location = format!("i8* {}, i64 {}, i64 {}", file, span.line, span.col);
+        for (n, step) in path.iter().enumerate() {
+            let last = n + 1 == path.len();
+            let (call, element) = match (step, &t) {
+                (mir::Step::Field(field), _) => {
+                    let i = self.field_index(&t, field);
+                    let element = record_types(self.program, &t)[i].clone();
+                    let call = match last {
+                        true => {
+                            self.declare("declare void @rho_record_set(i8*, i64, i64)");
+                            let bits = self.consume_bits(value);
+                            format!(
+                                "call void @rho_record_set(i8* {}, i64 {}, i64 {})",
+                                object, i, bits
+                            )
+                        }
+                        false => {
+                            self.declare("declare i64 @rho_record_unique_at(i8*, i64)");
+                            format!("call i64 @rho_record_unique_at(i8* {}, i64 {})", object, i)
+                        }
+                    };
+                    (call, element)
+                }
+                (mir::Step::Index(key), Type::BuiltIn(BuiltinType::Map, _)) => {
+                    let kt = self.operand_type(key);
+                    let k = self.operand(key);
+                    let call = match last {
+                        true => {
+                            self.declare("declare void @rho_map_put(i8*, i64, i64)");
+                            // the map takes over the key when it's new
+                            self.retain(&k, &kt);
+                            let k = self.bits_of(&k, &kt);
+                            let bits = self.consume_bits(value);
+                            format!(
+                                "call void @rho_map_put(i8* {}, i64 {}, i64 {})",
+                                object, k, bits
+                            )
+                        }
+                        false => {
+                            self.declare(
+                                "declare i64 @rho_map_unique_at(i8*, i64, i8*, i8*, i64, i64)",
+                            );
+                            // the interpreter sets a missing key to unit, which has no insides
+                            let message = match &path[n + 1] {
+                                mir::Step::Field(f) => format!("`unit` has no field `{}`", f),
+                                mir::Step::Index(_) => "`unit` can't be assigned by index".into(),
+                            };
+                            let message = self.string_constant(&message);
+                            let k = self.bits_of(&k, &kt);
+                            format!(
+                                "call i64 @rho_map_unique_at(i8* {}, i64 {}, i8* {}, {})",
+                                object, k, message, location
+                            )
+                        }
+                    };
+                    (call, type_arg(&t, 1))
+                }
+                (mir::Step::Index(i), _) => {
+                    let i = self.operand(i);
+                    let call = match last {
+                        true => {
+                            self.declare(
+                                "declare void @rho_list_set(i8*, i64, i64, i8*, i64, i64)",
+                            );
+                            let bits = self.consume_bits(value);
+                            format!(
+                                "call void @rho_list_set(i8* {}, i64 {}, i64 {}, {})",
+                                object, i, bits, location
+                            )
+                        }
+                        false => {
+                            self.declare(
+                                "declare i64 @rho_list_unique_at(i8*, i64, i8*, i64, i64)",
+                            );
+                            format!(
+                                "call i64 @rho_list_unique_at(i8* {}, i64 {}, {})",
+                                object, i, location
+                            )
+                        }
+                    };
+                    (call, type_arg(&t, 0))
+                }
+            };
+            if last {
+                self.emit(&call);
+            } else {
+                let bits = self.assign(&call);
+                object = self.value_of(&bits, &element);
+            }
+            t = element;
+        }
+    }
+
+    fn function(&mut self, function: &mir::Function, kind: Kind) -> String {
+        self.f = Function {
+            locals: function.locals.iter().map(|l| l.ty.clone()).collect(),
+            captured: function.locals.iter().map(|l| l.captured).collect(),
+            upvalues: function.upvalues.iter().map(|u| u.ty.clone()).collect(),
+            slots: vec![None; function.locals.len()],
+            ..Function::default()
+        };
+        /* A temporary assigned once and only used in its block is a register, the others and the
+         * bindings are stored, ie. the value of an `if` that each branch assigns. `_0` is read by
+         * the `return`, which every assignment to it goes to, so once is enough for it too.
+         */
+        let mut assigned = vec![vec![]; function.locals.len()];
+        let mut used = vec![BTreeSet::new(); function.locals.len()];
+        for (b, block) in function.blocks.iter().enumerate() {
+            for statement in block.statements.iter() {
+                match statement {
+                    Statement::Assign {
+                        place: Place::Local(id),
+                        ..
+                    }
+                    | Statement::Call {
+                        result: Some(Place::Local(id)),
+                        ..
+                    } => assigned[*id].push(b),
+                    Statement::Drop(Place::Local(id)) => {
+                        used[*id].insert(b);
+                    }
+                    _ => {}
+                }
+            }
+            let operands = block.statements.iter().flat_map(|s| s.operands());
+            for operand in operands.chain(block.terminator.operands()) {
+                if let Operand::Copy(Place::Local(id)) | Operand::Move(Place::Local(id)) = operand {
+                    used[*id].insert(b);
+                }
+            }
+        }
+        let register = |id: LocalId| match assigned[id].as_slice() {
+            [b] => used[id].iter().all(|u| u == b),
+            _ => false,
+        };
+        let mut names = HashMap::new();
+        for (id, local) in function.locals.iter().enumerate() {
+            if local.ty == Type::Unit || (local.name.is_none() && register(id)) {
+                continue;
+            }
+            // the `.var` keeps them apart from the registers and labels, which have no dot
+            let name = match &local.name {
+                Some(name) => format!("{}.var", unique(&mut names, name)),
+                None => format!("_{}", id),
+            };
+            let lt = match local.captured {
+                true => "i8*",
+                false => llvm_type(&local.ty),
+            };
+            let slot = self.alloca(&name, lt);
+            if mir::owns(&local.ty) || local.captured {
+                // null until it's set, releasing null does nothing
+                self.f.allocas += &format!("  store {0} null, {0}* {1}\n", lt, slot);
+            }
+            self.f.slots[id] = Some(slot);
+        }
+        let mut signature = vec![];
+        if let Kind::Closure(_) = kind {
+            signature.push("i8* %env".to_string());
+        }
+        for id in function.params.iter() {
+            let local = &function.locals[*id];
+            let name = local.name.as_deref().unwrap_or("_");
+            let arg = format!("%{}.arg", llvm_name(name));
+            signature.push(format!("{} {}", llvm_type(&local.ty), arg));
+            // a closure can share a parameter too
+            if local.captured {
+                self.fresh(*id);
+            }
+            self.store(Place::Local(*id), arg);
+        }
+
+        for id in block_order(function) {
+            self.start_block(&format!("bb{}", id));
+            let block = &function.blocks[id];
+            for (i, statement) in block.statements.iter().enumerate() {
+                let rest = &block.statements[i + 1..];
+                self.statement(statement, rest, &block.terminator);
+            }
+            self.terminator(&block.terminator, kind == Kind::Main);
+        }
+
+        if kind == Kind::Main {
+            // the runtime numbers atoms like the code does
+            self.declare("declare void @rho_atoms_init(i8**, i32)");
+            self.f.allocas += &format!(
+                "  call void @rho_atoms_init(i8** getelementptr inbounds ([{0} x i8*], [{0} x i8*]* @rho.atoms, i64 0, i64 0), i32 {0})\n",
+                self.atoms.len()
+            );
+            return self.finish("define i32 @main()");
+        }
+        let name = match kind {
+            Kind::Closure(id) => format!("closure.{}", id),
+            _ => format!("fn.{}", llvm_name(&function.name)),
+        };
+        let header = format!(
+            "define internal {} @rho.{}({})",
+            llvm_type(function.return_type()),
+            name,
+            signature.join(", ")
+        );
+        self.finish(&header)
+    }
+
+    // A top level function as a closure's function, which has no cells to look at in its record
+    fn function_value(&mut self, name: &str) -> String {
+        let function = (self.program.functions.iter())
+            .find(|f| f.name == name)
+            .expect("The MIR should only make values of the functions it has");
+        let ret = llvm_type(function.return_type());
+        let mut params = vec![];
+        for (i, id) in function.params.iter().enumerate() {
+            params.push(format!("{} %a{}", llvm_type(&function.locals[*id].ty), i));
+        }
+        let call = format!(
+            "call {} @rho.fn.{}({})",
+            ret,
+            llvm_name(name),
+            params.join(", ")
+        );
+        let body = match ret {
+            "void" => format!("  {}\n  ret void\n", call),
+            _ => format!("  %result = {}\n  ret {} %result\n", call, ret),
+        };
+        let params: Vec<String> = params.iter().map(|p| format!(", {}", p)).collect();
+        format!(
+            "define internal {} @rho.value.{}(i8* %env{}) {{\nentry:\n{}}}\n",
+            ret,
+            llvm_name(name),
+            params.concat(),
+            body
+        )
+    }
+
+    /* The adapter the runtime calls a closure of type `t` through, see `rho_runtime::streams`. It
+     * takes the bits of the arguments and gives the bits of the result, unit is 0.
+     */
+    fn adapter(&mut self, id: usize, t: &Type) -> String {
+        let Type::Function(params, ret) = t else {
+            unreachable!("only closures have adapters")
+        };
+        self.declare("declare i64 @rho_record_get(i8*, i64)");
+        let mut body = format!(
+            "  %code.bits = call i64 @rho_record_get(i8* %closure, i64 0)\n  \
+             %code = inttoptr i64 %code.bits to {}\n",
+            closure_type(params, ret)
+        );
+        let mut args = vec!["i8* %closure".to_string()];
+        for (i, t) in params.iter().enumerate() {
+            let lt = llvm_type(t);
+            let conversion = match lt {
+                "i64" => {
+                    args.push(format!("i64 %a{}", i));
+                    continue;
+                }
+                "double" => "bitcast",
+                "i8*" => "inttoptr",
+                _ => "trunc",
+            };
+            body += &format!("  %p{0} = {1} i64 %a{0} to {2}\n", i, conversion, lt);
+            args.push(format!("{} %p{}", lt, i));
+        }
+        let call = format!("call {} %code({})", llvm_type(ret), args.join(", "));
+        body += &match llvm_type(ret) {
+            "void" => format!("  {}\n  ret i64 0\n", call),
+            "i64" => format!("  %result = {}\n  ret i64 %result\n", call),
+            lt => {
+                let conversion = match lt {
+                    "double" => "bitcast",
+                    "i8*" => "ptrtoint",
+                    _ => "zext",
+                };
+                format!(
+                    "  %result = {}\n  %bits = {} {} %result to i64\n  ret i64 %bits\n",
+                    call, conversion, lt
+                )
+            }
+        };
+        format!(
+            "define internal i64 @rho.adapter.{}(i8* %closure, i64 %a0, i64 %a1) {{\nentry:\n{}}}\n",
+            id, body
+        )
+    }
+
+    fn finish(&mut self, header: &str) -> String {
+        let f = std::mem::take(&mut self.f);
+        format!("{} {{\nentry:\n{}{}}}\n", header, f.allocas, f.body)
+    }
+
+    // `rest` are the statements after it in its block, which ends with `terminator`
+    fn statement(&mut self, statement: &Statement, rest: &[Statement], terminator: &Terminator) {
+        match statement {
+            Statement::Assign { place, value, span } => {
+                let t = self.place_type(*place);
+                let value = self.rvalue(value, &t, *span);
+                self.store(*place, value);
+            }
+            Statement::Call {
+                result,
+                callee,
+                args,
+                span,
+            } => self.call(*result, callee, args, *span),
+            Statement::SetPath {
+                place,
+                path,
+                value,
+                span,
+            } => self.set_path(*place, path, value, *span),
+            Statement::Drop(place) => {
+                let returning = *terminator == Terminator::Return
+                    && rest.iter().all(|s| matches!(s, Statement::Drop(_)));
+                // a captured local lets go of its cell, which the closures made with it share
+                if let Place::Local(id) = *place {
+                    if self.f.captured[id] {
+                        let slot = self.f.slots[id].clone().expect("A cell should be stored");
+                        self.release_cell(&slot);
+                        if !returning {
+                            self.emit(&format!("store i8* null, i8** {}", slot));
+                        }
+                        return;
+                    }
+                }
+                let t = self.place_type(*place);
+                let value = self.load(*place);
+                self.release(&value, &t);
+                // it's empty after, unless it's set right away or the function is returning
+                let set = match rest.first() {
+                    Some(Statement::Assign { place: p, .. }) => p == place,
+                    Some(Statement::Call { result, .. }) => *result == Some(*place),
+                    _ => false,
+                };
+                if let (Some(slot), false) = (self.slot(*place), set || returning) {
+                    let lt = llvm_type(&t);
+                    self.emit(&format!("store {0} null, {0}* {1}", lt, slot));
+                }
+            }
+            Statement::Fresh(Place::Local(id)) => self.fresh(*id),
+            statement => unreachable!("only the VM's MIR has `{}`", statement),
+        }
+    }
+
+    fn rvalue(&mut self, value: &Rvalue, t: &Type, span: Span) -> String {
+        match value {
+            Rvalue::Use(operand) => self.consume(operand),
+            Rvalue::Binary(op, a, b) => self.binary(*op, a, b, span),
+            Rvalue::Unary(op, a) => {
+                let v = self.operand(a);
+                match op {
+                    UnOp::Not => self.assign(&format!("xor i1 {}, true", v)),
+                    UnOp::Neg if *t == Type::int() => self.checked("ssub", "0", &v, span),
+                    UnOp::Neg => self.assign(&format!("fneg double {}", v)),
+                }
+            }
+            Rvalue::Cast(a, to) => {
+                let from = self.operand_type(a);
+                let v = self.operand(a);
+                match from {
+                    from if from == Type::bool() => self.assign(&format!("zext i1 {} to i64", v)),
+                    from if from == Type::char() => self.assign(&format!("zext i32 {} to i64", v)),
+                    // the MIR checked it's in range
+                    from if from == Type::float() => {
+                        self.assign(&format!("fptosi double {} to i64", v))
+                    }
+                    _ => self.assign(&format!("sitofp i64 {} to {}", v, llvm_type(to))),
+                }
+            }
+            Rvalue::Select(c, a, b) => {
+                let lt = llvm_type(t);
+                let (c, a, b) = (self.operand(c), self.operand(a), self.operand(b));
+                self.assign(&format!("select i1 {0}, {3} {1}, {3} {2}", c, a, b, lt))
+            }
+            Rvalue::List(_) | Rvalue::Map(_) | Rvalue::Tuple(_) | Rvalue::Struct(..) => {
+                self.aggregate(value, t)
+            }
+            Rvalue::Field(..) | Rvalue::Index(..) => self.element(value, t, span),
+            Rvalue::Closure(id, captures) => {
+                let closure = &self.program.closures[*id];
+                let params: Vec<Type> = (closure.params.iter())
+                    .map(|p| closure.locals[*p].ty.clone())
+                    .collect();
+                let code = format!("@rho.closure.{}", id);
+                let (name, ret) = (closure.name.clone(), closure.return_type().clone());
+                let cells = (captures.iter())
+                    .map(|place| self.cell(*place).expect("A closure should capture cells"))
+                    .collect();
+                self.closure(&code, &name, &params, &ret, cells)
+            }
+            Rvalue::Function(name) => {
+                let Type::Function(params, ret) = t else {
+                    unreachable!("a function value has a function type")
+                };
+                self.values.insert(name.clone());
+                let code = format!("@rho.value.{}", llvm_name(name));
+                self.closure(&code, name, params, ret, vec![])
+            }
+            value => unreachable!("only the VM's MIR has `{}`", value),
+        }
+    }
+
+    /* A closure's record: the function `code`, its name for stack traces and the cells it shares
+     * with the function that made it, which it takes a reference to
+     */
+    fn closure(
+        &mut self,
+        code: &str,
+        name: &str,
+        params: &[Type],
+        ret: &Type,
+        cells: Vec<String>,
+    ) -> String {
+        self.declare("declare i8* @rho_record_new(i64, i64)");
+        self.declare("declare void @rho_record_set(i8*, i64, i64)");
+        self.declare("declare void @rho_retain(i8*)");
+        let objects = ((1u64 << cells.len()) - 1) << 2;
+        let record = self.assign(&format!(
+            "call i8* @rho_record_new(i64 {}, i64 {})",
+            cells.len() + 2,
+            objects
+        ));
+        let code = self.assign(&format!(
+            "ptrtoint {} {} to i64",
+            closure_type(params, ret),
+            code
+        ));
+        let name = self.string_constant(name);
+        let name = self.assign(&format!("ptrtoint i8* {} to i64", name));
+        let mut fields = vec![code, name];
+        for cell in cells {
+            self.emit(&format!("call void @rho_retain(i8* {})", cell));
+            fields.push(self.assign(&format!("ptrtoint i8* {} to i64", cell)));
+        }
+        for (i, bits) in fields.iter().enumerate() {
+            self.emit(&format!(
+                "call void @rho_record_set(i8* {}, i64 {}, i64 {})",
+                record, i, bits
+            ));
+        }
+        record
+    }
+
+    fn binary(&mut self, op: BinOp, a: &Operand, b: &Operand, span: Span) -> String {
+        let t = self.operand_type(a);
+        let (x, y) = (self.operand(a), self.operand(b));
+        let (a, b) = (x.as_str(), y.as_str());
+        match op {
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Gt | BinOp::Le | BinOp::Ge => {
+                self.compare(op, &t, a, b)
+            }
+            BinOp::Concat => {
+                self.declare("declare i8* @rho_str_concat(i8*, i8*)");
+                self.assign(&format!("call i8* @rho_str_concat(i8* {}, i8* {})", a, b))
+            }
+            BinOp::AddOverflows => {
+                self.declare("declare { i64, i1 } @llvm.sadd.with.overflow.i64(i64, i64)");
+                let pair = self.assign(&format!(
+                    "call {{ i64, i1 }} @llvm.sadd.with.overflow.i64(i64 {}, i64 {})",
+                    a, b
+                ));
+                self.assign(&format!("extractvalue {{ i64, i1 }} {}, 1", pair))
+            }
+            BinOp::WrappingAdd => self.assign(&format!("add i64 {}, {}", a, b)),
+            BinOp::Append => {
+                self.declare("declare i8* @rho_list_concat(i8*, i8*)");
+                self.assign(&format!("call i8* @rho_list_concat(i8* {}, i8* {})", a, b))
+            }
+            _ if t == Type::float() => match op {
+                BinOp::Add => self.assign(&format!("fadd double {}, {}", a, b)),
+                BinOp::Sub => self.assign(&format!("fsub double {}, {}", a, b)),
+                BinOp::Mul => self.assign(&format!("fmul double {}, {}", a, b)),
+                BinOp::Div => self.assign(&format!("fdiv double {}, {}", a, b)),
+                BinOp::Rem => self.assign(&format!("frem double {}, {}", a, b)),
+                _ => {
+                    self.declare("declare double @llvm.pow.f64(double, double)");
+                    self.assign(&format!(
+                        "call double @llvm.pow.f64(double {}, double {})",
+                        a, b
+                    ))
+                }
+            },
+            BinOp::Add => self.checked("sadd", a, b, span),
+            BinOp::Sub => self.checked("ssub", a, b, span),
+            BinOp::Mul => self.checked("smul", a, b, span),
+            BinOp::Div | BinOp::Rem => {
+                let zero = self.assign(&format!("icmp eq i64 {}, 0", b));
+                self.fail_if(&zero, "division by zero", span);
+                // the one quotient that doesn't fit
+                let min = self.assign(&format!("icmp eq i64 {}, {}", a, i64::MIN));
+                let minus_one = self.assign(&format!("icmp eq i64 {}, -1", b));
+                let both = self.assign(&format!("and i1 {}, {}", min, minus_one));
+                self.fail_if(&both, "integer overflow", span);
+                let instruction = match op {
+                    BinOp::Div => "sdiv",
+                    _ => "srem",
+                };
+                self.assign(&format!("{} i64 {}, {}", instruction, a, b))
+            }
+            BinOp::Pow => {
+                self.helpers.insert("ipow");
+                self.assign(&format!(
+                    "call i64 @rho.ipow(i64 {}, i64 {}, i64 {}, i64 {})",
+                    a, b, span.line, span.col
+                ))
+            }
+            BinOp::Shl | BinOp::Shr => {
+                // negative amounts are huge unsigned ones
+                let too_far = self.assign(&format!("icmp uge i64 {}, 64", b));
+                self.fail_if(&too_far, "integer overflow", span);
+                let instruction = match op {
+                    BinOp::Shl => "shl",
+                    _ => "ashr",
+                };
+                self.assign(&format!("{} i64 {}, {}", instruction, a, b))
+            }
+        }
+    }
+
+    // An i64 `sadd`, `ssub` or `smul` that fails on overflow
+    fn checked(&mut self, operation: &str, a: &str, b: &str, span: Span) -> String {
+        let declaration = match operation {
+            "sadd" => "declare { i64, i1 } @llvm.sadd.with.overflow.i64(i64, i64)",
+            "ssub" => "declare { i64, i1 } @llvm.ssub.with.overflow.i64(i64, i64)",
+            _ => "declare { i64, i1 } @llvm.smul.with.overflow.i64(i64, i64)",
+        };
+        self.declare(declaration);
+        let pair = self.assign(&format!(
+            "call {{ i64, i1 }} @llvm.{}.with.overflow.i64(i64 {}, i64 {})",
+            operation, a, b
+        ));
+        let overflow = self.assign(&format!("extractvalue {{ i64, i1 }} {}, 1", pair));
+        self.fail_if(&overflow, "integer overflow", span);
+        self.assign(&format!("extractvalue {{ i64, i1 }} {}, 0", pair))
+    }
+
+    fn compare(&mut self, op: BinOp, t: &Type, a: &str, b: &str) -> String {
+        let (signed, unsigned, float) = match op {
+            BinOp::Eq => ("eq", "eq", "oeq"),
+            // NaN is unequal to everything
+            BinOp::Ne => ("ne", "ne", "une"),
+            BinOp::Lt => ("slt", "ult", "olt"),
+            BinOp::Gt => ("sgt", "ugt", "ogt"),
+            BinOp::Le => ("sle", "ule", "ole"),
+            _ => ("sge", "uge", "oge"),
+        };
+        let instruction = match t {
+            t if *t == Type::float() => format!("fcmp {} double", float),
+            t if *t == Type::char() => format!("icmp {} i32", unsigned),
+            t if *t == Type::string() => {
+                self.declare("declare i32 @rho_str_compare(i8*, i8*)");
+                let order =
+                    self.assign(&format!("call i32 @rho_str_compare(i8* {}, i8* {})", a, b));
+                return self.assign(&format!("icmp {} i32 {}, 0", signed, order));
+            }
+            t => format!("icmp {} {}", signed, llvm_type(t)),
+        };
+        self.assign(&format!("{} {}, {}", instruction, a, b))
+    }
+
+    fn call(&mut self, result: Option<Place>, callee: &Callee, args: &[Operand], span: Span) {
+        let name = match callee {
+            Callee::Function(name) => name,
+            Callee::Builtin(name) => return self.builtin(result, name, args, span),
+            Callee::Show => return self.show(result, &args[0]),
+            Callee::Enter(name) => return self.enter(name, span),
+            Callee::Leave => return self.leave(),
+            Callee::Value => return self.call_closure(result, args, span),
+            Callee::Method(_) => unreachable!("only the VM's MIR has `{}`", callee),
+            _ => {
+                let v = self.operand(&args[0]);
+                let t = self.operand_type(&args[0]);
+                return self.print(&v, &t, callee);
+            }
+        };
+        let ret = (self.program.functions.iter())
+            .find(|f| f.name == *name)
+            .map(|f| f.return_type().clone())
+            .unwrap_or(Type::Unit);
+        let mut values = vec![];
+        for a in args.iter() {
+            let t = self.operand_type(a);
+            let v = self.consume(a);
+            values.push(format!("{} {}", llvm_type(&t), v));
+        }
+        let call = format!(
+            "call {} @rho.fn.{}({})",
+            llvm_type(&ret),
+            llvm_name(name),
+            values.join(", ")
+        );
+        self.enter(name, span);
+        match result {
+            Some(place) => {
+                let register = self.assign(&call);
+                self.store(place, register);
+            }
+            None => self.emit(&call),
+        }
+        self.leave();
+    }
+
+    /* A call of the closure that is the first argument, through the function its record has. It's
+     * given the record, and takes over the other arguments.
+     */
+    fn call_closure(&mut self, result: Option<Place>, args: &[Operand], span: Span) {
+        let Type::Function(params, ret) = self.operand_type(&args[0]) else {
+            unreachable!("the MIR should only call functions")
+        };
+        self.declare("declare i64 @rho_record_get(i8*, i64)");
+        let closure = self.operand(&args[0]);
+        let code = self.assign(&format!("call i64 @rho_record_get(i8* {}, i64 0)", closure));
+        let code = self.assign(&format!(
+            "inttoptr i64 {} to {}",
+            code,
+            closure_type(&params, &ret)
+        ));
+        let name = self.assign(&format!("call i64 @rho_record_get(i8* {}, i64 1)", closure));
+        let name = self.assign(&format!("inttoptr i64 {} to i8*", name));
+        let mut values = vec![format!("i8* {}", closure)];
+        for a in args[1..].iter() {
+            let t = self.operand_type(a);
+            let v = self.consume(a);
+            values.push(format!("{} {}", llvm_type(&t), v));
+        }
+        let call = format!("call {} {}({})", llvm_type(&ret), code, values.join(", "));
+        self.enter_frame(&name, span);
+        match result {
+            Some(place) => {
+                let register = self.assign(&call);
+                self.store(place, register);
+            }
+            None => self.emit(&call),
+        }
+        self.leave();
+    }
+
+    // The frame of a call to `function` at `span`, for the runtime's stack traces
+    fn enter(&mut self, function: &str, span: Span) {
+        let name = self.string_constant(function);
+        self.enter_frame(&name, span);
+    }
+
+    // `enter` for the function whose name is the str `name`
+    fn enter_frame(&mut self, name: &str, span: Span) {
+        self.declare("declare void @rho_enter(i8*, i8*, i64, i64)");
+        let file = self.string_constant(&self.file.clone());
+        self.emit(&format!(
+            "call void @rho_enter(i8* {}, i8* {}, i64 {}, i64 {})",
+            name, file, span.line, span.col
+        ));
+    }
+
+    fn leave(&mut self) {
+        self.declare("declare void @rho_leave()");
+        self.emit("call void @rho_leave()");
+    }
+
+    // `IO.inspect`'s text of a value as a new str, ints and floats are written like `str` does
+    fn show(&mut self, result: Option<Place>, v: &Operand) {
+        let t = self.operand_type(v);
+        if mir::owns(&t) && t != Type::string() {
+            self.declare("declare i8* @rho_str_show(i64, i8*, i1 zeroext)");
+            let value = self.operand(v);
+            let bits = self.bits_of(&value, &t);
+            let descriptor = self.descriptor(&t);
+            let call = format!(
+                "call i8* @rho_str_show(i64 {}, i8* {}, i1 zeroext true)",
+                bits, descriptor
+            );
+            match result {
+                Some(place) => {
+                    let register = self.assign(&call);
+                    self.store(place, register);
+                }
+                None => self.emit(&call),
+            }
+            return;
+        }
+        let (function, parameter) = match t {
+            t if t == Type::int() => ("rho_str_from_int", "i64"),
+            t if t == Type::float() => ("rho_str_from_float", "double"),
+            t if t == Type::bool() => ("rho_str_inspect_bool", "i1 zeroext"),
+            t if t == Type::char() => ("rho_str_inspect_char", "i32"),
+            t if t == Type::atom() => ("rho_str_inspect_atom", "i32"),
+            _ => ("rho_str_inspect_str", "i8*"),
+        };
+        self.declare(&format!("declare i8* @{}({})", function, parameter));
+        let v = self.operand(v);
+        let call = format!("call i8* @{}({} {})", function, parameter, v);
+        match result {
+            Some(place) => {
+                let register = self.assign(&call);
+                self.store(place, register);
+            }
+            None => self.emit(&call),
+        }
+    }
+
+    // A call to the runtime's `rho_string_upcase` for `String.upcase`, which only borrows its arguments
+    fn builtin(&mut self, result: Option<Place>, name: &str, args: &[Operand], span: Span) {
+        if name.starts_with("Stream.") {
+            return self.stream_builtin(result, name, args, span);
+        }
+        if let ("atom_to_string" | "string_to_atom" | "str", [a]) = (name, args) {
+            let v = match name {
+                "str" => self.str(a),
+                _ => self.atom_builtin(name, a),
+            };
+            if let Some(place) = result {
+                self.store(place, v);
+            }
+            return;
+        }
+        if let ("Map.get" | "Map.has_key" | "Map.put" | "Map.delete", Some(place)) = (name, result)
+        {
+            let v = self.map_builtin(name, args, &self.place_type(place));
+            self.store(place, v);
+            return;
+        }
+        let function = match name {
+            "IO.read_line" => "rho_read_line".to_string(),
+            "List.count" | "Enum.count" => "rho_list_len".to_string(),
+            "Map.size" => "rho_map_len".to_string(),
+            _ => format!("rho_{}", name.replace('.', "_").to_lowercase()),
+        };
+        let fails = mir::RUNTIME_BUILTINS
+            .iter()
+            .any(|(b, fails)| *b == name && *fails);
+        let mut params = vec![];
+        let mut values = vec![];
+        for a in args.iter() {
+            let t = llvm_type(&self.operand_type(a));
+            let v = self.operand(a);
+            params.push(t.to_string());
+            values.push(format!("{} {}", t, v));
+        }
+        if fails {
+            let file = self.string_constant(&self.file.clone());
+            params.extend(["i8*", "i64", "i64"].map(String::from));
+            values.push(format!("i8* {}", file));
+            values.push(format!("i64 {}", span.line));
+            values.push(format!("i64 {}", span.col));
+        }
+        let ret = match result {
+            Some(place) => self.place_type(place),
+            None => rho_core::builtin(name).map_or(Type::Unit, |b| b.return_type),
+        };
+        let ret = match llvm_type(&ret) {
+            // a Rust `bool` is a byte that's 0 or 1
+            "i1" => "zeroext i1",
+            t => t,
+        };
+        self.declare(&format!(
+            "declare {} @{}({})",
+            ret,
+            function,
+            params.join(", ")
+        ));
+        let call = format!("call {} @{}({})", ret, function, values.join(", "));
+        match result {
+            Some(place) => {
+                let register = self.assign(&call);
+                self.store(place, register);
+            }
+            None => self.emit(&call),
+        }
+    }
+
+    /* A `Stream` builtin, `Stream.take_while` is `rho_stream_take_while`, and the calls of a `for`
+     * loop's cursor. The runtime keeps what a stream is made from, a closure along with the adapter
+     * it calls it through, and is told whether the elements it pulls are objects. The ones that
+     * pull, or can fail, are given the location they're at.
+     */
+    fn stream_builtin(&mut self, result: Option<Place>, name: &str, args: &[Operand], span: Span) {
+        let mut arguments = vec![];
+        for (i, a) in args.iter().enumerate() {
+            let t = self.operand_type(a);
+            match (name, i, &t) {
+                // the runtime keeps a new reference to the first element, the accumulator is its
+                ("Stream.iterate", 0, _) | ("Stream.reduce", 1, _) => {
+                    let v = match name {
+                        "Stream.reduce" => self.consume(a),
+                        _ => self.operand(a),
+                    };
+                    let bits = self.bits_of(&v, &t);
+                    arguments.push(("i64".to_string(), bits));
+                    if name == "Stream.iterate" {
+                        arguments.push(("i1 zeroext".to_string(), mir::owns(&t).to_string()));
+                    }
+                }
+                (_, _, Type::Function(..)) => {
+                    let v = self.operand(a);
+                    let id = match self.adapters.iter().position(|adapter| *adapter == t) {
+                        Some(id) => id,
+                        None => {
+                            self.adapters.push(t.clone());
+                            self.adapters.len() - 1
+                        }
+                    };
+                    let adapter =
+                        format!("bitcast (i64 (i8*, i64, i64)* @rho.adapter.{} to i8*)", id);
+                    arguments.push(("i8*".to_string(), v));
+                    arguments.push(("i8*".to_string(), adapter));
+                }
+                _ => {
+                    let v = self.operand(a);
+                    arguments.push((llvm_type(&t).to_string(), v));
+                }
+            }
+        }
+        let objects = matches!(
+            name,
+            "Stream.map"
+                | "Stream.filter"
+                | "Stream.take_while"
+                | "Stream.flat_map"
+                | "Stream.take"
+                | "Stream.chunk"
+                | "Stream.to_list"
+                | "Stream.count"
+                | "Stream.cursor"
+        );
+        if objects {
+            let element = self.operand_type(&args[0]).element_type();
+            let owns = element.is_some_and(|t| mir::owns(&t));
+            arguments.push(("i1 zeroext".to_string(), owns.to_string()));
+        }
+        let located = matches!(
+            name,
+            "Stream.take"
+                | "Stream.chunk"
+                | "Stream.to_list"
+                | "Stream.count"
+                | "Stream.reduce"
+                | "Stream.each"
+                | "Stream.cursor"
+        );
+        if located {
+            let file = self.string_constant(&self.file.clone());
+            arguments.push(("i8*".to_string(), file));
+            arguments.push(("i64".to_string(), span.line.to_string()));
+            arguments.push(("i64".to_string(), span.col.to_string()));
+        }
+        let t = result.map_or(Type::Unit, |place| self.place_type(place));
+        let ret = match name {
+            "Stream.reduce" | "Stream.current" => "i64",
+            "Stream.next" => "zeroext i1",
+            _ => llvm_type(&t),
+        };
+        let function = format!("rho_{}", name.replace('.', "_").to_lowercase());
+        let params: Vec<&str> = arguments.iter().map(|(t, _)| t.as_str()).collect();
+        self.declare(&format!(
+            "declare {} @{}({})",
+            ret,
+            function,
+            params.join(", ")
+        ));
+        let values: Vec<String> = (arguments.iter())
+            .map(|(t, v)| format!("{} {}", t.trim_end_matches(" zeroext"), v))
+            .collect();
+        let call = format!("call {} @{}({})", ret, function, values.join(", "));
+        let Some(place) = result else {
+            return self.emit(&call);
+        };
+        let mut v = self.assign(&call);
+        if ret == "i64" && llvm_type(&t) != "i64" {
+            v = self.value_of(&v, &t);
+        }
+        self.store(place, v);
+    }
+
+    /* `Map.get` and `Map.has_key` look the key up, `Map.put` and `Map.delete` change the map they
+     * were given when nothing else has it and a copy of it when something does. The result, of
+     * type `t`, is a new reference.
+     */
+    fn map_builtin(&mut self, name: &str, args: &[Operand], t: &Type) -> String {
+        let kt = self.operand_type(&args[1]);
+        let (map, k) = (self.operand(&args[0]), self.operand(&args[1]));
+        match name {
+            "Map.get" | "Map.has_key" => {
+                self.declare("declare zeroext i1 @rho_map_get(i8*, i64, i64*)");
+                let out = self.temp();
+                self.f.allocas += &format!("  {} = alloca i64\n", out);
+                let k = self.bits_of(&k, &kt);
+                let found = self.assign(&format!(
+                    "call zeroext i1 @rho_map_get(i8* {}, i64 {}, i64* {})",
+                    map, k, out
+                ));
+                if name == "Map.has_key" {
+                    return found;
+                }
+                let bits = self.assign(&format!("load i64, i64* {}", out));
+                let default = self.operand(&args[2]);
+                let default = self.bits_of(&default, t);
+                let bits = self.assign(&format!(
+                    "select i1 {}, i64 {}, i64 {}",
+                    found, bits, default
+                ));
+                let v = self.value_of(&bits, t);
+                self.retain(&v, t);
+                v
+            }
+            _ => {
+                self.declare("declare i8* @rho_unique(i8*)");
+                self.retain(&map, t);
+                let map = self.assign(&format!("call i8* @rho_unique(i8* {})", map));
+                let k_bits = self.bits_of(&k, &kt);
+                if name == "Map.delete" {
+                    self.declare("declare zeroext i1 @rho_map_remove(i8*, i64)");
+                    self.emit(&format!(
+                        "call zeroext i1 @rho_map_remove(i8* {}, i64 {})",
+                        map, k_bits
+                    ));
+                    return map;
+                }
+                self.declare("declare void @rho_map_put(i8*, i64, i64)");
+                self.retain(&k, &kt);
+                let vt = self.operand_type(&args[2]);
+                let v = self.operand(&args[2]);
+                self.retain(&v, &vt);
+                let v = self.bits_of(&v, &vt);
+                self.emit(&format!(
+                    "call void @rho_map_put(i8* {}, i64 {}, i64 {})",
+                    map, k_bits, v
+                ));
+                map
+            }
+        }
+    }
+
+    // `str(v)` as a new str, a str is itself and anything else is written by the runtime
+    fn str(&mut self, a: &Operand) -> String {
+        let t = self.operand_type(a);
+        let v = self.operand(a);
+        let (function, parameter) = match t {
+            t if t == Type::string() => {
+                self.retain(&v, &t);
+                return v;
+            }
+            t if t == Type::int() => ("rho_str_from_int", "i64"),
+            t if t == Type::float() => ("rho_str_from_float", "double"),
+            t => {
+                self.declare("declare i8* @rho_str_show(i64, i8*, i1 zeroext)");
+                let bits = self.bits_of(&v, &t);
+                let descriptor = self.descriptor(&t);
+                return self.assign(&format!(
+                    "call i8* @rho_str_show(i64 {}, i8* {}, i1 zeroext false)",
+                    bits, descriptor
+                ));
+            }
+        };
+        self.declare(&format!("declare i8* @{}({})", function, parameter));
+        self.assign(&format!("call i8* @{}({} {})", function, parameter, v))
+    }
+
+    /* `string_to_atom` interns its str in the runtime's atom table and `atom_to_string` copies
+     * the table's name for an atom into a new str, the name isn't an object of its own.
+     */
+    fn atom_builtin(&mut self, name: &str, a: &Operand) -> String {
+        let v = self.operand(a);
+        if name == "string_to_atom" {
+            self.declare("declare i32 @rho_atom_intern(i8*)");
+            return self.assign(&format!("call i32 @rho_atom_intern(i8* {})", v));
+        }
+        self.declare("declare i8* @rho_atom_name(i32)");
+        self.declare("declare i64 @strlen(i8*)");
+        self.declare("declare i8* @rho_str_new(i8*, i64)");
+        let bytes = self.assign(&format!("call i8* @rho_atom_name(i32 {})", v));
+        let len = self.assign(&format!("call i64 @strlen(i8* {})", bytes));
+        self.assign(&format!(
+            "call i8* @rho_str_new(i8* {}, i64 {})",
+            bytes, len
+        ))
+    }
+
+    // `IO.print`, `IO.puts` and `IO.inspect` through the runtime's `rho_print_*` functions
+    fn print(&mut self, v: &str, t: &Type, callee: &Callee) {
+        let inspect = *callee == Callee::Inspect;
+        let (function, parameter) = match t {
+            t if inspect && *t == Type::string() => ("rho_inspect_str", "i8*"),
+            t if inspect && *t == Type::char() => ("rho_inspect_char", "i32"),
+            t if *t == Type::int() => ("rho_print_int", "i64"),
+            t if *t == Type::float() => ("rho_print_float", "double"),
+            t if *t == Type::bool() => ("rho_print_bool", "i1 zeroext"),
+            t if *t == Type::char() => ("rho_print_char", "i32"),
+            t if *t == Type::atom() => ("rho_print_atom", "i32"),
+            t if *t == Type::string() => ("rho_print_str", "i8*"),
+            // a list, map or record, which `descriptor` gives the type of
+            _ => ("rho_print_value", "i64, i8*, i1 zeroext"),
+        };
+        let arguments = match function {
+            "rho_print_value" => {
+                let bits = self.bits_of(v, t);
+                let descriptor = self.descriptor(t);
+                format!("i64 {}, i8* {}, i1 zeroext {}", bits, descriptor, inspect)
+            }
+            _ => format!("{} {}", parameter, v),
+        };
+        self.declare(&format!("declare void @{}({})", function, parameter));
+        self.emit(&format!("call void @{}({})", function, arguments));
+        if *callee != Callee::Print {
+            self.declare("declare void @rho_print_newline()");
+            self.emit("call void @rho_print_newline()");
+        }
+    }
+
+    fn terminator(&mut self, terminator: &Terminator, top_level: bool) {
+        match terminator {
+            Terminator::Goto(block) => self.terminate(&format!("br label %bb{}", block)),
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let c = self.operand(condition);
+                let (then, otherwise) = (format!("bb{}", then), format!("bb{}", otherwise));
+                self.branch_if(&c, &then, &otherwise);
+            }
+            // the C `main` exits with the code, what was printed without a newline is still buffered
+            Terminator::Return if top_level => {
+                let code = self.load(Place::Local(0));
+                let code = self.assign(&format!("trunc i64 {} to i32", code));
+                self.declare("declare void @rho_finish()");
+                self.emit("call void @rho_finish()");
+                self.terminate(&format!("ret i32 {}", code));
+            }
+            Terminator::Return => match self.f.locals[0].clone() {
+                Type::Unit => self.terminate("ret void"),
+                t => {
+                    let v = self.load(Place::Local(0));
+                    self.terminate(&format!("ret {} {}", llvm_type(&t), v));
+                }
+            },
+            Terminator::Fail {
+                prefix,
+                message,
+                span,
+                traced,
+            } => {
+                let message = self.operand(message);
+                self.fail_with(prefix, &message, *span, *traced);
+            }
+            Terminator::Unreachable => self.terminate("unreachable"),
+            Terminator::Next { .. } => unreachable!("only the VM's MIR iterates"),
+        }
+    }
+
+    // The functions the generated code calls for the work too long to write out each time
+    fn helper(&mut self, name: &str) -> String {
+        match name {
+            "ipow" => {
+                self.declare("declare void @rho_fail(i8*, i8*, i8*, i64, i64) noreturn");
+                self.declare("declare { i64, i1 } @llvm.smul.with.overflow.i64(i64, i64)");
+                let file = self.string_constant(&self.file.clone());
+                let empty = self.string_constant("");
+                let negative = self.string_constant("an int can't be raised to a negative power");
+                let overflow = self.string_constant("integer overflow");
+                format!(
+                    "; `base ^ exp` by squaring, failing like `i64::checked_pow` does
+define internal i64 @rho.ipow(i64 %base, i64 %exp, i64 %line, i64 %col) {{
+entry:
+  %negative = icmp slt i64 %exp, 0
+  br i1 %negative, label %fail.negative, label %loop
+loop:
+  %acc = phi i64 [ 1, %entry ], [ %acc.next, %square ]
+  %b = phi i64 [ %base, %entry ], [ %b.next, %square ]
+  %e = phi i64 [ %exp, %entry ], [ %e.next, %square ]
+  %finished = icmp eq i64 %e, 0
+  br i1 %finished, label %exit, label %multiply
+multiply:
+  %bit = and i64 %e, 1
+  %odd = icmp ne i64 %bit, 0
+  %product = call {{ i64, i1 }} @llvm.smul.with.overflow.i64(i64 %acc, i64 %b)
+  %product.value = extractvalue {{ i64, i1 }} %product, 0
+  %product.overflow = extractvalue {{ i64, i1 }} %product, 1
+  %acc.next = select i1 %odd, i64 %product.value, i64 %acc
+  %product.failed = and i1 %odd, %product.overflow
+  br i1 %product.failed, label %fail.overflow, label %shift
+shift:
+  %e.next = lshr i64 %e, 1
+  %last = icmp eq i64 %e.next, 0
+  br i1 %last, label %done, label %square
+square:
+  %squared = call {{ i64, i1 }} @llvm.smul.with.overflow.i64(i64 %b, i64 %b)
+  %b.next = extractvalue {{ i64, i1 }} %squared, 0
+  %squared.overflow = extractvalue {{ i64, i1 }} %squared, 1
+  br i1 %squared.overflow, label %fail.overflow, label %loop
+done:
+  ret i64 %acc.next
+exit:
+  ret i64 %acc
+fail.negative:
+  call void @rho_fail(i8* {0}, i8* {1}, i8* {3}, i64 %line, i64 %col)
+  unreachable
+fail.overflow:
+  call void @rho_fail(i8* {0}, i8* {2}, i8* {3}, i64 %line, i64 %col)
+  unreachable
+}}
+",
+                    empty, negative, overflow, file
+                )
+            }
+            _ => unreachable!("there's no helper `{}`", name),
+        }
+    }
+}
+
+// Generates the IR of a program, panicking when it doesn't compile
+#[cfg(test)]
+fn generate_source(source: &str, file: &str) -> Result<String, Vec<Diagnostic>> {
+    let (compiled, diagnostics) = crate::compile(source);
+    let Some((mut program, symbols)) = compiled else {
+        panic!("{} doesn't compile: {:?}", file, diagnostics);
+    };
+    crate::testing::strip_tests(&mut program);
+    let mir = mir::lower(&program, &symbols)?;
+    Ok(generate(&mir, file))
+}
+
+// `lli` with the runtime loaded, when LLVM is installed, the tests only run the IR when it is
+#[cfg(test)]
+pub fn lli() -> Option<std::process::Command> {
+    let lli = crate::native::find_tool("lli")?;
+    let runtime = crate::native::runtime_library("so").ok()?;
+    let mut command = std::process::Command::new(lli);
+    command.arg(format!("-load={}", runtime.display()));
+    Some(command)
+}
+
+/* Runs a program compiled by the native backend on `stdin`, with the leak check on, for its exit
+ * code, stdout and stderr. None when LLVM isn't installed.
+ */
+#[cfg(test)]
+pub fn run_native(source: &str, stdin: &str) -> Option<(Option<i32>, String, String)> {
+    use std::io::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let mut lli = lli()?;
+    let ir = generate_source(source, "a.rho").unwrap_or_else(|e| panic!("{:?}", e));
+    let run = RUNS.fetch_add(1, Ordering::Relaxed);
+    let path = std::env::temp_dir().join(format!("rho_native_{}_{}.ll", std::process::id(), run));
+    std::fs::write(&path, ir).unwrap();
+    let mut child = lli
+        .env("RHO_LEAK_CHECK", "1")
+        .arg(&path)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    let ran = child.wait_with_output().unwrap();
+    let _ = std::fs::remove_file(&path);
+    Some((
+        ran.status.code(),
+        String::from_utf8_lossy(&ran.stdout).into_owned(),
+        String::from_utf8_lossy(&ran.stderr).into_owned(),
+    ))
+}
+
+/* Each program in `llvm_testfiles/golden` has to generate its `.ll`, `RHO_BLESS=1 cargo test` writes
+ * them after a change to the generated code. With LLVM installed the IR also has to assemble, and
+ * run by `lli` to what the interpreter prints, without leaking an object.
+ */
+#[test]
+fn test_codegen_golden() {
+    let bless = std::env::var_os("RHO_BLESS").is_some();
+    let mut paths: Vec<_> = std::fs::read_dir("./llvm_testfiles/golden")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "rho"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+    for path in paths {
+        let file = path.to_str().unwrap();
+        let source = std::fs::read_to_string(&path).unwrap();
+        let ir = generate_source(&source, file).unwrap();
+        let golden = path.with_extension("ll");
+        if bless {
+            std::fs::write(&golden, &ir).unwrap();
+        }
+        let expected = std::fs::read_to_string(&golden).unwrap_or_default();
+        assert!(ir == expected, "{} doesn't generate {:?}", file, golden);
+
+        if let Some(llvm_as) = crate::native::find_tool("llvm-as") {
+            let assembled = std::process::Command::new(llvm_as)
+                .args(["-o", "/dev/null"])
+                .arg(&golden)
+                .output()
+                .unwrap();
+            let stderr = String::from_utf8_lossy(&assembled.stderr);
+            assert!(assembled.status.success(), "{}", stderr);
+        }
+        if let Some(mut lli) = lli() {
+            let ran = lli
+                .env("RHO_LEAK_CHECK", "1")
+                .arg(&golden)
+                .output()
+                .unwrap();
+            let (result, output) = crate::interpreter::run_source(&source, "");
+            assert_eq!(String::from_utf8_lossy(&ran.stdout), output, "{}", file);
+            assert_eq!(ran.status.code(), Some(result.unwrap_or(1)), "{}", file);
+            let stderr = String::from_utf8_lossy(&ran.stderr);
+            assert!(!stderr.contains("still live"), "{}: {}", file, stderr);
+        }
+    }
+}
+
+#[test]
+fn test_codegen_unsupported() {
+    let source = "xs = 0..2\nf = match 1 { _ -> 2 }\nfor c in \"ab\" {\n}\n";
+    let errors = generate_source(source, "a.rho").unwrap_err();
+    let messages: Vec<(&str, usize)> = errors
+        .iter()
+        .map(|d| (d.message.as_str(), d.span.line))
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            (
+                "the native backend doesn't support ranges outside of `for` loops yet",
+                1
+            ),
+            ("the native backend doesn't support `match` yet", 2),
+            (
+                "the native backend doesn't support `for` loops over a `str` yet",
+                3
+            )
+        ]
+    );
+}
+
+#[test]
+fn test_codegen_runtime_errors() {
+    if lli().is_none() {
+        return;
+    }
+    let programs = [
+        "x = 9223372036854775807\nIO.puts(x + 1)\n",
+        "var d = 0\nIO.puts(10 % d)\n",
+        "n = -1\nIO.puts(2 ^ n)\n",
+        "IO.puts(3 ^ 40)\n",
+        "IO.puts(1 << 64)\n",
+        "var s = 0\nfor i in 0..10..s {\n}\n",
+        "big = 2 > 1\nassert !big\n",
+        // the operands of a comparison, and the calls it failed in
+        "func g(int a) -> int {\n    assert a == 2, \"two\"\n    return a\n}\nfunc f() -> int {\n    return g(3)\n}\nf()\n",
+        "func main() -> int {\n    s = \"a\\n\"\n    assert s < \"a\"\n    return 0\n}\n",
+        "assert 2.0 >= 2.5\n",
+        "assert :ok != :ok\n",
+        "func f(int n) -> int {\n    return n * n\n}\nIO.puts(float(f(4)))\nIO.puts(f(1 << 40))\n",
+        "func f(str s) -> int {\n    panic s\n}\nf(\"x\")\n",
+        "x = 0.0 / 0.0\nIO.puts(int(x))\n",
+        "x = 2.5\nIO.puts(int(x))\nIO.puts(int(x * 10000000000000000000.0))\n",
+        "func f(int n) -> int {\n    return f(n + 1)\n}\nf(0)\n",
+    ];
+    let path = std::env::temp_dir().join(format!("rho_codegen_{}.ll", std::process::id()));
+    let run = |ir: String| {
+        std::fs::write(&path, ir).unwrap();
+        lli().unwrap().arg(&path).output().unwrap()
+    };
+    for source in programs {
+        let ran = run(generate_source(source, "a.rho").unwrap());
+        let interpreted = source.to_owned();
+        let (result, output) = std::thread::Builder::new()
+            .stack_size(crate::interpreter::STACK_SIZE)
+            .spawn(move || crate::interpreter::run_source(&interpreted, ""))
+            .unwrap()
+            .join()
+            .unwrap();
+        // what the interpreter writes without the source lines
+        let expected = result.unwrap_err().render("a.rho", "");
+        assert_eq!(String::from_utf8_lossy(&ran.stdout), output, "{}", source);
+        assert_eq!(String::from_utf8_lossy(&ran.stderr), expected, "{}", source);
+        assert_eq!(ran.status.code(), Some(1));
+    }
+
+    /* The resolver doesn't let a function see a global defined after its first call, so the
+     * call is moved before the definition after the program was checked.
+     */
+    let source = "func show() -> int {\n    return later\n}\nvar later = 2\nIO.puts(show())\n";
+    let (compiled, _) = crate::compile(source);
+    let (mut program, symbols) = compiled.unwrap();
+    program.swap(1, 2);
+    let ir = generate(&mir::lower(&program, &symbols).unwrap(), "a.rho");
+    let ran = run(ir);
+    assert_eq!(
+        String::from_utf8_lossy(&ran.stderr),
+        "error: `later` is used before it has a value\n  --> a.rho:2:12\nstack trace:\n    at show (a.rho:2)\n    at the top level (a.rho:5)\n"
+    );
+    let _ = std::fs::remove_file(&path);
+}
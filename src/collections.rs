@@ -0,0 +1,467 @@
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+use crate::maps::Map;
+use crate::rho_core::{
+    apply, arguments, int, run, test, Builtin, CallResult, Context, NativeFn, Outcome,
+};
+use crate::streams::{collect, Cursor};
+use crate::types::Type;
+use crate::value::Value;
+
+/* The `List` module, also available as `Enum`. Every function takes any iterable builtin where it
+ * declares a list: a range goes through its ints and a map through its `{key, value}` entries,
+ * ie. `0..5 |> List.map(fn x -> x * 2) |> List.sum()`.
+ */
+pub fn builtins() -> Vec<Builtin> {
+    let t = || Type::Param("T".to_string());
+    let u = || Type::Param("U".to_string());
+    let list = || Type::list(t());
+    let predicate = || Type::function(vec![t()], Type::bool());
+    vec![
+        both(
+            ["List.map", "Enum.map"],
+            &["T", "U"],
+            vec![list(), Type::function(vec![t()], u())],
+            Type::list(u()),
+            map,
+        ),
+        both(
+            ["List.filter", "Enum.filter"],
+            &["T"],
+            vec![list(), predicate()],
+            list(),
+            filter,
+        ),
+        // the function gets the accumulator first, ie. `List.reduce(xs, 0, fn acc, x -> acc + x)`
+        both(
+            ["List.reduce", "Enum.reduce"],
+            &["T", "U"],
+            vec![list(), u(), Type::function(vec![u(), t()], u())],
+            u(),
+            reduce,
+        ),
+        both(
+            ["List.zip", "Enum.zip"],
+            &["T", "U"],
+            vec![list(), Type::list(u())],
+            Type::list(Type::tuple(vec![t(), u()])),
+            zip,
+        ),
+        both(
+            ["List.sort", "Enum.sort"],
+            &["T"],
+            vec![list()],
+            list(),
+            sort,
+        ),
+        both(
+            ["List.sort_by", "Enum.sort_by"],
+            &["T", "U"],
+            vec![list(), Type::function(vec![t()], u())],
+            list(),
+            sort_by,
+        ),
+        both(
+            ["List.group_by", "Enum.group_by"],
+            &["T", "U"],
+            vec![list(), Type::function(vec![t()], u())],
+            Type::map(u(), list()),
+            group_by,
+        ),
+        both(
+            ["List.take", "Enum.take"],
+            &["T"],
+            vec![list(), Type::int()],
+            list(),
+            take,
+        ),
+        both(
+            ["List.drop", "Enum.drop"],
+            &["T"],
+            vec![list(), Type::int()],
+            list(),
+            drop,
+        ),
+        both(
+            ["List.any", "Enum.any"],
+            &["T"],
+            vec![list(), predicate()],
+            Type::bool(),
+            any,
+        ),
+        both(
+            ["List.all", "Enum.all"],
+            &["T"],
+            vec![list(), predicate()],
+            Type::bool(),
+            all,
+        ),
+        both(["List.sum", "Enum.sum"], &["T"], vec![list()], t(), sum),
+        both(
+            ["List.count", "Enum.count"],
+            &["T"],
+            vec![list()],
+            Type::int(),
+            count,
+        ),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+// The same function under `List` and `Enum`
+fn both(
+    names: [&'static str; 2],
+    type_params: &[&'static str],
+    params: Vec<Type>,
+    return_type: Type,
+    function: NativeFn,
+) -> [Builtin; 2] {
+    names.map(|name| {
+        Builtin::new(
+            name,
+            type_params,
+            params.clone(),
+            return_type.clone(),
+            function,
+        )
+    })
+}
+
+fn cursor(xs: &Value) -> Outcome<Cursor> {
+    Cursor::of(xs)
+        .ok_or_else(|| CallResult::Err(format!("can't iterate over `{}`", xs.type_name())))
+}
+
+// A stable sort that fails rather than guess when two elements can't be compared
+fn sort_pairs(pairs: &mut [(Value, Value)]) -> Outcome<()> {
+    let mut incomparable = None;
+    pairs.sort_by(|(a, _), (b, _)| {
+        a.compare(b).unwrap_or_else(|| {
+            incomparable.get_or_insert((a.type_name(), b.type_name()));
+            Ordering::Equal
+        })
+    });
+    match incomparable {
+        Some((a, b)) => Err(CallResult::Err(format!(
+            "can't compare `{}` and `{}`",
+            a, b
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn map(context: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [xs, f] = arguments(args)?;
+        let mapped = collect(context, &xs)?
+            .into_iter()
+            .map(|x| apply(context, &f, vec![x]))
+            .collect::<Outcome<Vec<Value>>>()?;
+        Ok(Value::list(mapped))
+    })
+}
+
+fn filter(context: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [xs, predicate] = arguments(args)?;
+        let mut kept = vec![];
+        for x in collect(context, &xs)? {
+            if test(context, &predicate, x.clone())? {
+                kept.push(x);
+            }
+        }
+        Ok(Value::list(kept))
+    })
+}
+
+fn reduce(context: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [xs, mut acc, f] = arguments(args)?;
+        for x in collect(context, &xs)? {
+            acc = apply(context, &f, vec![acc, x])?;
+        }
+        Ok(acc)
+    })
+}
+
+fn zip(context: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [xs, ys] = arguments(args)?;
+        let pairs = collect(context, &xs)?
+            .into_iter()
+            .zip(collect(context, &ys)?)
+            .map(|(x, y)| Value::tuple(vec![x, y]))
+            .collect();
+        Ok(Value::list(pairs))
+    })
+}
+
+fn sort(context: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [xs] = arguments(args)?;
+        let mut pairs: Vec<(Value, Value)> = collect(context, &xs)?
+            .into_iter()
+            .map(|x| (x, Value::Unit))
+            .collect();
+        sort_pairs(&mut pairs)?;
+        Ok(Value::list(pairs.into_iter().map(|(x, _)| x).collect()))
+    })
+}
+
+// Calls the function once per element and sorts by what it returned
+fn sort_by(context: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [xs, f] = arguments(args)?;
+        let mut pairs = vec![];
+        for x in collect(context, &xs)? {
+            pairs.push((apply(context, &f, vec![x.clone()])?, x));
+        }
+        sort_pairs(&mut pairs)?;
+        Ok(Value::list(pairs.into_iter().map(|(_, x)| x).collect()))
+    })
+}
+
+// The groups are in the order their first element was seen
+fn group_by(context: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [xs, f] = arguments(args)?;
+        let mut groups = Map::new();
+        for x in collect(context, &xs)? {
+            let key = apply(context, &f, vec![x.clone()])?;
+            let group = groups.get_or_insert(key).map_err(CallResult::Err)?;
+            match group {
+                Value::List(elements) => Rc::make_mut(elements).push(x),
+                _ => *group = Value::list(vec![x]),
+            }
+        }
+        Ok(Value::Map(Rc::new(groups)))
+    })
+}
+
+// A negative count takes from the end, ie. `List.take(xs, -2)` is the last two elements
+fn take(context: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [xs, n] = arguments(args)?;
+        let n = int(&n)?;
+        if n >= 0 {
+            // only pulls what it takes, so it ends an infinite stream
+            let mut cursor = cursor(&xs)?;
+            let mut taken = vec![];
+            while taken.len() < n as usize {
+                match cursor.next(context)? {
+                    Some(x) => taken.push(x),
+                    None => break,
+                }
+            }
+            return Ok(Value::list(taken));
+        }
+        let xs = collect(context, &xs)?;
+        Ok(Value::list(xs[split_at(xs.len(), n)..].to_vec()))
+    })
+}
+
+// A negative count drops from the end
+fn drop(context: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [xs, n] = arguments(args)?;
+        let xs = collect(context, &xs)?;
+        let split = split_at(xs.len(), int(&n)?);
+        Ok(Value::list(if int(&n)? < 0 {
+            xs[..split].to_vec()
+        } else {
+            xs[split..].to_vec()
+        }))
+    })
+}
+
+// The index `n` elements from the start, or from the end when `n` is negative
+fn split_at(len: usize, n: i64) -> usize {
+    let k = n.unsigned_abs().min(len as u64) as usize;
+    if n < 0 {
+        len - k
+    } else {
+        k
+    }
+}
+
+fn any(context: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [xs, predicate] = arguments(args)?;
+        let mut cursor = cursor(&xs)?;
+        while let Some(x) = cursor.next(context)? {
+            if test(context, &predicate, x)? {
+                return Ok(Value::Bool(true));
+            }
+        }
+        Ok(Value::Bool(false))
+    })
+}
+
+fn all(context: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [xs, predicate] = arguments(args)?;
+        let mut cursor = cursor(&xs)?;
+        while let Some(x) = cursor.next(context)? {
+            if !test(context, &predicate, x)? {
+                return Ok(Value::Bool(false));
+            }
+        }
+        Ok(Value::Bool(true))
+    })
+}
+
+// The sum of no elements is the int 0
+fn sum(context: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [xs] = arguments(args)?;
+        let mut total = Value::Int(0);
+        for (i, x) in collect(context, &xs)?.into_iter().enumerate() {
+            total = match (&total, &x) {
+                (Value::Int(a), Value::Int(b)) => match a.checked_add(*b) {
+                    Some(sum) => Value::Int(sum),
+                    None => return Err(CallResult::Err("integer overflow".to_string())),
+                },
+                (Value::Int(_), Value::Float(b)) if i == 0 => Value::Float(*b),
+                (Value::Float(a), Value::Float(b)) => Value::Float(a + b),
+                _ => {
+                    return Err(CallResult::Err(format!(
+                        "can't add `{}` to a sum of `{}`",
+                        x.type_name(),
+                        total.type_name()
+                    )))
+                }
+            };
+        }
+        Ok(total)
+    })
+}
+
+fn count(context: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [xs] = arguments(args)?;
+        Ok(Value::Int(collect(context, &xs)?.len() as i64))
+    })
+}
+
+#[test]
+fn test_list_module() {
+    let (result, output) = crate::interpreter::run_source(
+        "doubled = [1, 2, 3] |> List.map(fn x -> x * 2) |> List.filter(fn x -> x > 2)
+IO.inspect(doubled)
+IO.inspect(List.reduce(doubled, 0, fn acc, x -> acc + x))
+IO.inspect(0..10 |> List.filter(fn x -> x % 3 == 0) |> Enum.sum())
+IO.inspect(List.zip([:a, :b, :c], 1..3))
+IO.inspect(List.sort([3, 1, 2]))
+IO.inspect(List.sort_by([\"ccc\", \"a\", \"bb\"], fn s -> String.length(s)))
+IO.inspect(List.group_by(1..7, fn x -> x % 3))
+IO.inspect({List.take(1..6, 2), List.drop(1..6, 4), List.take(1..6, -1)})
+IO.inspect({List.any([1, 2], fn x -> x > 1), List.all([1, 2], fn x -> x > 1)})
+IO.inspect(List.count({\"a\": 1, \"b\": 2}))
+IO.inspect(List.sum([0.5, 0.25]))
+",
+        "",
+    );
+    assert_eq!(result, Ok(0));
+    assert_eq!(
+        output,
+        "[4, 6]
+10
+18
+[{:a, 1}, {:b, 2}]
+[1, 2, 3]
+[\"a\", \"bb\", \"ccc\"]
+{1: [1, 4], 2: [2, 5], 0: [3, 6]}
+{[1, 2], [5], [5]}
+{true, false}
+2
+0.75
+"
+    );
+}
+
+#[test]
+fn test_list_errors() {
+    let (result, _) = crate::interpreter::run_source("[1, 0] |> List.map(fn x -> 10 / x)\n", "");
+    assert_eq!(
+        result.map_err(|e| e.to_string()),
+        Err("error: division by zero at 1:31".to_string())
+    );
+    let mut program = crate::parsers::parse("List.map(true, fn x -> x)\n").unwrap();
+    let (mut symbols, _) = crate::resolver::resolve(&mut program);
+    let diagnostics: Vec<String> = crate::checker::check(&program, &mut symbols)
+        .iter()
+        .map(|d| d.to_string())
+        .collect();
+    assert_eq!(
+        diagnostics[0],
+        "error: mismatched types: expected `list[_]`, found `bool` at 1:10"
+    );
+}
+
+#[test]
+fn test_collections_native() {
+    let source = "struct Pair[A, B] {
+    A first
+    B second
+}
+struct Line {
+    Pair[int, int] from
+    list[str] tags
+}
+func bump(list[int] xs) -> list[int] {
+    var ys = xs
+    ys[0] = ys[0] + 1
+    return ys
+}
+xs = [1, 2, 3]
+var ys = bump(xs)
+ys[2] = 30
+IO.inspect(xs)
+IO.puts(ys)
+var l = Line{from: Pair{first: 1, second: 2}, tags: [\"a\"]}
+var copy = l
+copy.from.first = 9
+copy.tags[0] = \"b\"
+IO.inspect(l)
+IO.inspect(copy)
+IO.puts(copy.tags[0] <> l.tags[0])
+var grid = [[1.5], []]
+grid[1] = grid[1] ++ [2.5]
+IO.inspect(grid)
+var total = 0
+for x in xs ++ ys {
+    total = total + x
+}
+IO.inspect({total, List.count(grid), 'c', :ok})
+IO.puts(IO.read_line() <> \"!\")
+IO.puts(xs[3])
+";
+    let Some((code, stdout, stderr)) = crate::codegen::run_native(source, "hi\n") else {
+        return;
+    };
+    let (result, output) = crate::interpreter::run_source(source, "hi\n");
+    assert_eq!(stdout, output);
+    assert_eq!(
+        output,
+        "[1, 2, 3]
+[2, 2, 30]
+Line{from: Pair{first: 1, second: 2}, tags: [\"a\"]}
+Line{from: Pair{first: 9, second: 2}, tags: [\"b\"]}
+ba
+[[1.5], [2.5]]
+{40, 2, 'c', :ok}
+hi!
+"
+    );
+    assert_eq!(code, Some(1));
+    assert!(result.is_err());
+    assert!(
+        stderr.contains("index 3 is out of bounds for a length of 3"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("still live"), "{}", stderr);
+}
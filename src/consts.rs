@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+
+use crate::diagnostics::Diagnostic;
+use crate::parsers::*;
+use crate::resolver::SymbolTable;
+use crate::tokens::*;
+
+struct ConstEvaluator {
+    values: HashMap<SymbolId, TokenValue>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/* Evaluates the value of every `const` binding at compile time and replaces it with the
+ * resulting literal. A const can be built from literals, operators and earlier consts, anything
+ * else (a call, a `var`, ..) is reported as not known at compile time. Overflow and division by
+ * zero are reported here rather than when the program runs.
+ */
+pub fn evaluate_consts(program: &mut [Expression], symbols: &SymbolTable) -> Vec<Diagnostic> {
+    let mut evaluator = ConstEvaluator {
+        values: HashMap::new(),
+        diagnostics: vec![],
+    };
+    // top level consts are visible to every function, in the same order the resolver uses
+    for e in program.iter_mut() {
+        if !matches!(e, Expression::Function(_)) {
+            evaluator.visit(e, symbols);
+        }
+    }
+    for e in program.iter_mut() {
+        if matches!(e, Expression::Function(_)) {
+            evaluator.visit(e, symbols);
+        }
+    }
+    evaluator.diagnostics
+}
+
+impl ConstEvaluator {
+    fn visit(&mut self, expression: &mut Expression, symbols: &SymbolTable) {
+        if let Expression::Definition {
+            mutability: Mutability::Const,
+            identifier,
+            value,
+            ..
+        } = expression
+        {
+            match self.evaluate(value) {
+                Ok(result) => {
+                    let span = value.span();
+                    **value = Expression::Literal {
+                        value: result.clone(),
+                        span,
+                    };
+                    if let Some(id) = identifier.symbol.filter(|_| symbols.declares(identifier)) {
+                        self.values.insert(id, result);
+                    }
+                }
+                Err(d) => self.diagnostics.push(d.with_note(
+                    format!(
+                        "`{}` is a const, its value must be known at compile time",
+                        identifier.name
+                    ),
+                    identifier.span,
+                )),
+            }
+            return;
+        }
+        for child in expression.children_mut() {
+            self.visit(child, symbols);
+        }
+    }
+
+    fn evaluate(&self, expression: &Expression) -> Result<TokenValue, Diagnostic> {
+        match expression {
+            Expression::Literal { value, .. } => Ok(value.clone()),
+            Expression::Identifier(ident) => ident
+                .symbol
+                .and_then(|id| self.values.get(&id))
+                .cloned()
+                .ok_or_else(|| {
+                    Diagnostic::error(
+                        format!("`{}` is not known at compile time", ident.name),
+                        ident.span,
+                    )
+                }),
+            Expression::Unary {
+                operator, operand, ..
+            } => match (operator, self.evaluate(operand)?) {
+                (Operators::Subtract, TokenValue::Int(i)) => i
+                    .checked_neg()
+                    .map(TokenValue::Int)
+                    .ok_or_else(|| overflow(expression)),
+                (Operators::Subtract, TokenValue::Float(f)) => Ok(TokenValue::Float(-f)),
+                (Operators::Not, TokenValue::Bool(b)) => Ok(TokenValue::Bool(!b)),
+                _ => Err(unknown(expression)),
+            },
+            Expression::Calculation {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                let l = self.evaluate(left)?;
+                let r = self.evaluate(right)?;
+                calculate(*operator, &l, &r, expression)
+            }
+            _ => Err(unknown(expression)),
+        }
+    }
+}
+
+fn unknown(expression: &Expression) -> Diagnostic {
+    Diagnostic::error("this value is not known at compile time", expression.span())
+}
+
+fn overflow(expression: &Expression) -> Diagnostic {
+    Diagnostic::error("this arithmetic operation overflows", expression.span())
+}
+
+fn calculate(
+    operator: Operators,
+    l: &TokenValue,
+    r: &TokenValue,
+    expression: &Expression,
+) -> Result<TokenValue, Diagnostic> {
+    use TokenValue::*;
+    let value = match (l, r) {
+        (Int(a), Int(b)) => {
+            let (a, b) = (*a, *b);
+            if matches!(operator, Operators::Div | Operators::Modulo) && b == 0 {
+                return Err(Diagnostic::error(
+                    "division by zero in a const",
+                    expression.span(),
+                ));
+            }
+            let result = match operator {
+                Operators::Add => a.checked_add(b),
+                Operators::Subtract => a.checked_sub(b),
+                Operators::Mult => a.checked_mul(b),
+                Operators::Div => a.checked_div(b),
+                Operators::Modulo => a.checked_rem(b),
+                Operators::Exp => u32::try_from(b).ok().and_then(|b| a.checked_pow(b)),
+                Operators::Lshift => u32::try_from(b).ok().and_then(|b| a.checked_shl(b)),
+                Operators::Rshift => u32::try_from(b).ok().and_then(|b| a.checked_shr(b)),
+                _ => return compare(operator, a.cmp(&b), expression),
+            };
+            Int(result.ok_or_else(|| overflow(expression))?)
+        }
+        (Float(a), Float(b)) => match operator {
+            Operators::Add => Float(a + b),
+            Operators::Subtract => Float(a - b),
+            Operators::Mult => Float(a * b),
+            Operators::Div => Float(a / b),
+            Operators::Modulo => Float(a % b),
+            Operators::Exp => Float(a.powf(*b)),
+            _ => match a.partial_cmp(b) {
+                Some(ordering) => return compare(operator, ordering, expression),
+                None => Bool(operator == Operators::BNEq),
+            },
+        },
+        (String(a), String(b)) if operator == Operators::Concat => String(a.to_owned() + b),
+        (String(a), String(b)) => return compare(operator, a.cmp(b), expression),
+        (Char(a), Char(b)) => return compare(operator, a.cmp(b), expression),
+        (Bool(a), Bool(b)) => match operator {
+            Operators::And => Bool(*a && *b),
+            Operators::Or => Bool(*a || *b),
+            _ => return compare(operator, a.cmp(b), expression),
+        },
+        (Atom(a), Atom(b)) => return compare(operator, a.cmp(b), expression),
+        _ => return Err(unknown(expression)),
+    };
+    Ok(value)
+}
+
+fn compare(
+    operator: Operators,
+    ordering: std::cmp::Ordering,
+    expression: &Expression,
+) -> Result<TokenValue, Diagnostic> {
+    use std::cmp::Ordering::*;
+    let result = match operator {
+        Operators::BEq => ordering == Equal,
+        Operators::BNEq => ordering != Equal,
+        Operators::LessThan => ordering == Less,
+        Operators::GreaterThan => ordering == Greater,
+        Operators::LEq => ordering != Greater,
+        Operators::GEq => ordering != Less,
+        _ => return Err(unknown(expression)),
+    };
+    Ok(TokenValue::Bool(result))
+}
+
+#[cfg(test)]
+fn evaluate_source(source: &str) -> (Vec<Expression>, Vec<String>) {
+    let mut program = parse(source).unwrap();
+    let (symbols, _) = crate::resolver::resolve(&mut program);
+    let diagnostics = evaluate_consts(&mut program, &symbols)
+        .iter()
+        .map(|d| d.to_string())
+        .collect();
+    (program, diagnostics)
+}
+
+#[cfg(test)]
+fn const_value(e: &Expression) -> TokenValue {
+    match e {
+        Expression::Definition { value, .. } => match value.as_ref() {
+            Expression::Literal { value, .. } => value.clone(),
+            other => panic!("not evaluated: {:?}", other),
+        },
+        other => panic!("not a definition: {:?}", other),
+    }
+}
+
+#[test]
+fn test_evaluate_consts() {
+    let (program, diagnostics) = evaluate_source(
+        "const SIZE = 4 * 1024
+const int MASK = SIZE - 1
+const GREETING = \"hello\" <> \", world\"
+const BIG = 2 ^ 10 > SIZE / 8
+const HALF = -1.0 / 2.0
+",
+    );
+    assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+    assert_eq!(const_value(&program[0]), TokenValue::Int(4096));
+    assert_eq!(const_value(&program[1]), TokenValue::Int(4095));
+    assert_eq!(
+        const_value(&program[2]),
+        TokenValue::String("hello, world".to_string())
+    );
+    assert_eq!(const_value(&program[3]), TokenValue::Bool(true));
+    assert_eq!(const_value(&program[4]), TokenValue::Float(-0.5));
+}
+
+#[test]
+fn test_evaluate_const_errors() {
+    let (_, diagnostics) = evaluate_source(
+        "x = 2
+const A = x + 1
+const B = 1 / 0
+const C = 9223372036854775807 + 1
+const D = int(\"1\")
+",
+    );
+    assert_eq!(
+        diagnostics,
+        vec![
+            "error: `x` is not known at compile time at 2:11",
+            "error: division by zero in a const at 3:13",
+            "error: this arithmetic operation overflows at 4:31",
+            "error: this value is not known at compile time at 5:11",
+        ]
+    );
+}
@@ -0,0 +1,170 @@
+use core::fmt;
+
+use crate::tokens::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+// An error or warning found in a rho source file, `notes` point at related locations
+// ie. where a duplicate name was first defined.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    pub notes: Vec<(String, Span)>,
+    pub trace: Vec<(String, Span)>, // for a runtime error, the functions it happened in, innermost first
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            span,
+            notes: vec![],
+            trace: vec![],
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            message: message.into(),
+            span,
+            notes: vec![],
+            trace: vec![],
+        }
+    }
+
+    pub fn with_note(mut self, message: impl Into<String>, span: Span) -> Diagnostic {
+        self.notes.push((message.into(), span));
+        self
+    }
+
+    pub fn with_trace(mut self, trace: Vec<(String, Span)>) -> Diagnostic {
+        self.trace = trace;
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /* Renders the diagnostic against its source, ie.
+    error: undefined name `Print`
+     --> scratch.rho:23:6
+       |
+    23 | s |> Print()
+       |      ^^^^^
+    */
+    pub fn render(&self, file_path: &str, source: &str) -> String {
+        self.render_in(&|span| (file_path, source, span))
+    }
+
+    /* `render` for a diagnostic whose spans can be in more than one source, `locate` gives the
+     * file path and source a span is in and the span within that source.
+     */
+    pub fn render_in<'a>(&self, locate: &dyn Fn(Span) -> (&'a str, &'a str, Span)) -> String {
+        let label = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let mut out = format!("{}: {}\n", label, self.message);
+        let (file_path, source, span) = locate(self.span);
+        out += &render_location(file_path, source, span);
+        for (message, span) in self.notes.iter() {
+            out += &format!("note: {}\n", message);
+            let (file_path, source, span) = locate(*span);
+            out += &render_location(file_path, source, span);
+        }
+        if !self.trace.is_empty() {
+            out += "stack trace:\n";
+        }
+        for (function, span) in self.trace.iter() {
+            match span.line {
+                // the frames left out of a deep trace
+                0 => out += &format!("    ... {}\n", function),
+                _ => {
+                    let (file_path, _, span) = locate(*span);
+                    out += &format!("    at {} ({}:{})\n", function, file_path, span.line)
+                }
+            }
+        }
+        out
+    }
+}
+
+fn render_location(file_path: &str, source: &str, span: Span) -> String {
+    let line_number = span.line.to_string();
+    let gutter = " ".repeat(line_number.len());
+    let mut out = format!("{} --> {}:{}\n", gutter, file_path, span);
+    if let Some(line) = source.lines().nth(span.line.saturating_sub(1)) {
+        let padding = " ".repeat(span.col.saturating_sub(1));
+        let carets = "^".repeat(span.len.max(1));
+        out += &format!("{} |\n", gutter);
+        out += &format!("{} | {}\n", line_number, line);
+        out += &format!("{} | {}{}\n", gutter, padding, carets);
+    }
+    out
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {} at {}", label, self.message, self.span)
+    }
+}
+
+#[test]
+fn test_render_diagnostic() {
+    let source = "str s = \"a\"\ns |> Print()\n";
+    let d = Diagnostic::error(
+        "undefined name `Print`",
+        Span {
+            line: 2,
+            col: 6,
+            len: 5,
+        },
+    )
+    .with_note(
+        "did you mean `IO.print`?",
+        Span {
+            line: 1,
+            col: 1,
+            len: 3,
+        },
+    );
+    assert_eq!(
+        d.render("scratch.rho", source),
+        "error: undefined name `Print`
+  --> scratch.rho:2:6
+  |
+2 | s |> Print()
+  |      ^^^^^
+note: did you mean `IO.print`?
+  --> scratch.rho:1:1
+  |
+1 | str s = \"a\"
+  | ^^^
+"
+    );
+    let at = |line| Span {
+        line,
+        col: 5,
+        len: 5,
+    };
+    let d = Diagnostic::error("panic: no", at(2)).with_trace(vec![
+        ("inner".to_string(), at(2)),
+        ("main".to_string(), at(5)),
+    ]);
+    assert!(d
+        .render("panic.rho", "\n    panic \"no\"\n")
+        .ends_with("stack trace:\n    at inner (panic.rho:2)\n    at main (panic.rho:5)\n"));
+}
@@ -0,0 +1,296 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+use crate::streams::Stream;
+use crate::value::*;
+
+/* Memory management in the interpreter. Values are reference counted by their `Rc`s, and since
+ * collections are copied on write a list can never end up holding itself. Closures are how a
+ * cycle forms, a closure holds the scope it was defined in and that scope, or one below it, can
+ * hold the closure:
+ *
+ *     func counter() -> fn() -> int { var n = 0; return fn -> { n = n + 1; return n } }
+ *     add = fn a, b -> a + b   // the globals hold `add`, which holds the globals
+ *
+ * Every scope is tracked here and `collect` frees the ones only such cycles keep alive, by trial
+ * deletion: an object whose strong count is larger than the references the scopes' values make to
+ * it is held from outside, by the interpreter's own stack. Whatever those objects reach is live,
+ * the other scopes are garbage and are emptied, which breaks their cycles so the `Rc`s free them.
+ */
+
+thread_local! {
+    static SCOPES: RefCell<Vec<Weak<RefCell<Scope>>>> = const { RefCell::new(vec![]) };
+    // the tracked scopes after the last collection, the next one runs when that many more exist
+    static COLLECTED_AT: Cell<usize> = const { Cell::new(0) };
+}
+
+// Collections start once there are this many scopes, so short programs never pay for one
+const FIRST_COLLECTION: usize = 4096;
+
+// Called by `Scope::new`, it can start a collection
+pub fn track(scope: &Env) {
+    let count = SCOPES.with(|scopes| {
+        let mut scopes = scopes.borrow_mut();
+        scopes.push(Rc::downgrade(scope));
+        scopes.len()
+    });
+    if count >= FIRST_COLLECTION.max(2 * COLLECTED_AT.with(|c| c.get())) {
+        collect();
+    }
+}
+
+// The scopes still alive, ie. 0 after a program ended and `collect` ran
+pub fn live_scopes() -> usize {
+    SCOPES.with(|scopes| {
+        let mut scopes = scopes.borrow_mut();
+        scopes.retain(|s| s.strong_count() > 0);
+        scopes.len()
+    })
+}
+
+// An object of the value graph, `strong` is its `Rc::strong_count`
+struct Node {
+    strong: usize,
+    internal: usize, // the references to it from other nodes
+    children: Vec<usize>,
+}
+
+// The graph of everything the tracked scopes reach, the nodes by their addresses
+#[derive(Default)]
+struct Graph {
+    nodes: HashMap<usize, Node>,
+}
+
+impl Graph {
+    // Adds the edge `from -> rc`, the first time `rc` is seen its contents are added too
+    fn reference<T: ?Sized>(
+        &mut self,
+        from: usize,
+        rc: &Rc<T>,
+        contents: impl FnOnce(&mut Graph, usize),
+    ) {
+        let address = Rc::as_ptr(rc) as *const () as usize;
+        self.nodes
+            .get_mut(&from)
+            .expect("The referring node should already be in the graph")
+            .children
+            .push(address);
+        if let Some(node) = self.nodes.get_mut(&address) {
+            node.internal += 1;
+            return;
+        }
+        self.nodes.insert(
+            address,
+            Node {
+                strong: Rc::strong_count(rc),
+                internal: 1,
+                children: vec![],
+            },
+        );
+        contents(self, address);
+    }
+
+    fn scope(&mut self, from: usize, env: &Env) {
+        self.reference(from, env, |graph, address| {
+            let Ok(scope) = env.try_borrow() else {
+                // being changed, so the interpreter is using it, it can't be garbage
+                graph.nodes.get_mut(&address).unwrap().strong = usize::MAX;
+                return;
+            };
+            if let Some(parent) = scope.parent() {
+                graph.scope(address, parent);
+            }
+            for value in scope.values() {
+                graph.value(address, value);
+            }
+        });
+    }
+
+    fn value(&mut self, from: usize, value: &Value) {
+        match value {
+            Value::List(values) | Value::Tuple(values) => self.values(from, values),
+            Value::Variant(_, _, values) => self.values(from, values),
+            Value::Struct(_, fields) => self.reference(from, fields, |graph, address| {
+                for (_, value) in fields.iter() {
+                    graph.value(address, value);
+                }
+            }),
+            Value::Map(map) => self.reference(from, map, |graph, address| {
+                for (key, value) in map.iter() {
+                    graph.value(address, key);
+                    graph.value(address, value);
+                }
+            }),
+            Value::Closure(closure) => self.reference(from, closure, |graph, address| {
+                graph.scope(address, &closure.env)
+            }),
+            Value::Compiled(closure) => self.reference(from, closure, |graph, address| {
+                for upvalue in closure.upvalues.iter() {
+                    graph.scope(address, upvalue);
+                }
+            }),
+            Value::Stream(stream) => self.stream(from, stream),
+            _ => {}
+        }
+    }
+
+    fn values(&mut self, from: usize, values: &Rc<Vec<Value>>) {
+        self.reference(from, values, |graph, address| {
+            for value in values.iter() {
+                graph.value(address, value);
+            }
+        });
+    }
+
+    fn stream(&mut self, from: usize, stream: &Rc<Stream>) {
+        self.reference(from, stream, |graph, address| match stream.as_ref() {
+            Stream::Values(values) => graph.values(address, values),
+            Stream::Iterate(first, next) => {
+                graph.value(address, first);
+                graph.value(address, next);
+            }
+            Stream::Map(s, f)
+            | Stream::Filter(s, f)
+            | Stream::TakeWhile(s, f)
+            | Stream::FlatMap(s, f) => {
+                graph.stream(address, s);
+                graph.value(address, f);
+            }
+            Stream::Take(s, _) | Stream::Chunk(s, _) => graph.stream(address, s),
+            Stream::Range(..) | Stream::Lines(_) => {}
+        });
+    }
+}
+
+// Frees the scopes that only cycles keep alive, comes back with how many there were
+pub fn collect() -> usize {
+    let scopes: Vec<Env> = SCOPES.with(|scopes| {
+        let mut scopes = scopes.borrow_mut();
+        scopes.retain(|s| s.strong_count() > 0);
+        scopes.iter().filter_map(|s| s.upgrade()).collect()
+    });
+
+    // a root node refers to every scope, like the interpreter's stack might
+    let mut graph = Graph::default();
+    let root = 0;
+    graph.nodes.insert(
+        root,
+        Node {
+            strong: usize::MAX,
+            internal: 0,
+            children: vec![],
+        },
+    );
+    for scope in scopes.iter() {
+        graph.scope(root, scope);
+    }
+    // the root's references aren't real, and neither are the ones `scopes` holds
+    for scope in scopes.iter() {
+        let node = graph
+            .nodes
+            .get_mut(&(Rc::as_ptr(scope) as *const () as usize))
+            .unwrap();
+        node.internal -= 1;
+        node.strong -= 1;
+    }
+
+    let mut live: HashSet<usize> = HashSet::new();
+    let mut pending: Vec<usize> = graph
+        .nodes
+        .iter()
+        .filter(|(address, node)| **address != root && node.strong > node.internal)
+        .map(|(address, _)| *address)
+        .collect();
+    while let Some(address) = pending.pop() {
+        if live.insert(address) {
+            pending.extend(graph.nodes[&address].children.iter().copied());
+        }
+    }
+
+    let garbage: Vec<Env> = scopes
+        .into_iter()
+        .filter(|s| !live.contains(&(Rc::as_ptr(s) as *const () as usize)))
+        .collect();
+    let count = garbage.len();
+    for scope in garbage.iter() {
+        // dropped after the borrow ends, dropping them can free other garbage scopes
+        let contents = scope.borrow_mut().clear();
+        drop(contents);
+    }
+    drop(garbage);
+    COLLECTED_AT.with(|c| c.set(live_scopes()));
+    count
+}
+
+#[test]
+fn test_collect_cycles() {
+    let globals = Scope::new(None);
+    let before = live_scopes();
+    // `f` defined in a block, the block's scope holds it and it holds the block's scope
+    let block = Scope::new(Some(globals.clone()));
+    let closure = Value::Closure(Rc::new(Closure {
+        name: "f".to_string(),
+        params: vec![],
+        body: Rc::new(crate::parsers::Expression::Break {
+            span: crate::tokens::Span::default(),
+        }),
+        env: block.clone(),
+    }));
+    define(&block, 1, Value::list(vec![closure.clone()]));
+    define(&globals, 0, Value::Int(1));
+    assert_eq!(live_scopes(), before + 1);
+
+    // still held by this function
+    assert_eq!(collect(), 0);
+    drop(block);
+    assert_eq!(collect(), 0);
+    drop(closure);
+    assert_eq!(live_scopes(), before + 1);
+    assert_eq!(collect(), 1);
+    assert_eq!(live_scopes(), before);
+    assert_eq!(lookup(&globals, 0), Some(Value::Int(1)));
+}
+
+#[test]
+fn test_collect_cycles_native() {
+    // each call leaves `walk` in a cycle with its cell, enough of them to collect as it runs
+    let source = "func paths(int depth) -> int {
+  func walk(int d) -> int {
+    if d == 0 {
+      return 1
+    }
+    return walk(d - 1) + walk(d - 1)
+  }
+  return walk(depth)
+}
+
+func counter() -> fn() -> int {
+  var n = 0
+  return fn -> {
+    n = n + 1
+    return n
+  }
+}
+
+var total = 0
+for i in 0..5000 {
+  total = total + paths(2)
+}
+IO.puts(total)
+next = counter()
+next()
+IO.puts(next())
+// a closure reads a global the top level defined before it
+more = fn n -> total + n
+IO.puts(more(1))
+";
+    let Some((code, stdout, stderr)) = crate::codegen::run_native(source, "") else {
+        return;
+    };
+    let (result, output) = crate::interpreter::run_source(source, "");
+    assert_eq!(stdout, output);
+    assert_eq!(output, "20000\n2\n20001\n");
+    assert_eq!(code, Some(result.unwrap()), "{}", stderr);
+    assert!(!stderr.contains("still live"), "{}", stderr);
+}
@@ -0,0 +1,1512 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::diagnostics::Diagnostic;
+use crate::parsers::*;
+use crate::tokens::*;
+use crate::types::Type;
+
+// Deep enough for any sane program, stops polymorphic recursion ie. `f[T]` calling `f[list[T]]`
+const MAX_INSTANCES: usize = 256;
+
+/* Matches a generic type against a concrete one, recording what each type parameter stands for
+ * ie. matching `list[T]` against `list[int]` records T = int
+ */
+pub fn match_type_pattern(
+    pattern: &Type,
+    actual: &Type,
+    subst: &mut HashMap<String, Type>,
+) -> Result<(), String> {
+    match (pattern, actual) {
+        (Type::Param(name), _) => match subst.get(name) {
+            Some(bound) if bound != actual => {
+                Err(format!("`{}` is both `{}` and `{}`", name, bound, actual))
+            }
+            Some(_) => Ok(()),
+            None => {
+                subst.insert(name.to_owned(), actual.clone());
+                Ok(())
+            }
+        },
+        (Type::BuiltIn(a, a_args), Type::BuiltIn(b, b_args)) if a == b => {
+            // an empty list literal is `list` with no element type, it matches any list
+            if b_args.is_empty() {
+                return Ok(());
+            }
+            match_type_args(a_args, b_args, subst)
+        }
+        (Type::Named(a, a_args), Type::Named(b, b_args)) if a == b => {
+            match_type_args(a_args, b_args, subst)
+        }
+        (Type::Function(a_params, a_ret), Type::Function(b_params, b_ret))
+            if a_params.len() == b_params.len() =>
+        {
+            match_type_args(a_params, b_params, subst)?;
+            match_type_pattern(a_ret, b_ret, subst)
+        }
+        _ if pattern == actual => Ok(()),
+        _ => Err(format!("expected `{}`, found `{}`", pattern, actual)),
+    }
+}
+
+fn match_type_args(
+    patterns: &[Type],
+    actuals: &[Type],
+    subst: &mut HashMap<String, Type>,
+) -> Result<(), String> {
+    if patterns.len() != actuals.len() {
+        return Err("mismatched number of type arguments".to_string());
+    }
+    for (p, a) in patterns.iter().zip(actuals.iter()) {
+        match_type_pattern(p, a, subst)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_match_type_pattern() {
+    let t = Type::Param("T".to_string());
+    let u = Type::Param("U".to_string());
+    let mut subst = HashMap::new();
+    match_type_pattern(&Type::list(t.clone()), &Type::list(Type::int()), &mut subst).unwrap();
+    match_type_pattern(
+        &Type::function(vec![t.clone()], u.clone()),
+        &Type::function(vec![Type::int()], Type::string()),
+        &mut subst,
+    )
+    .unwrap();
+    assert_eq!(subst.get("T"), Some(&Type::int()));
+    assert_eq!(subst.get("U"), Some(&Type::string()));
+    assert!(match_type_pattern(&t, &Type::float(), &mut subst).is_err());
+}
+
+// The name of a generic item instantiated with `args`, ie. `map[int, str]`
+pub fn instance_name(name: &str, args: &[Type]) -> String {
+    Type::Named(name.to_string(), args.to_vec()).to_string()
+}
+
+// The name a generic item's instance shows with, ie. `Pair` for `Pair[int, str]`
+pub fn generic_name(instance: &str) -> &str {
+    instance.split('[').next().unwrap_or(instance)
+}
+
+struct Monomorphiser {
+    generic_functions: HashMap<String, FunctionDecl>,
+    functions: HashMap<String, FunctionDecl>,
+    structs: HashMap<String, StructDecl>,
+    enums: HashMap<String, EnumDecl>,
+    interfaces: HashMap<String, InterfaceDecl>,
+    function_instances: HashMap<String, FunctionName>,
+    pending: Vec<(FunctionDecl, HashMap<String, Type>, FunctionName)>,
+    requested_types: RefCell<Vec<(String, Vec<Type>)>>,
+    scopes: Vec<HashMap<String, Type>>,
+    return_types: Vec<Option<Type>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/* Replaces generic functions, structs and enums with a copy per set of type arguments they are
+ * used with, ie. `map(xs, fn x -> x * 2)` with `xs: list[int]` calls a new `map[int, int]`.
+ * Type arguments missing at call sites are inferred from the argument types, and interface bounds
+ * on type parameters are checked against the `<Type>.<method>` functions of the type argument.
+ */
+pub fn monomorphise(program: Vec<Expression>) -> Result<Vec<Expression>, Vec<Diagnostic>> {
+    let mut m = Monomorphiser {
+        generic_functions: HashMap::new(),
+        functions: HashMap::new(),
+        structs: HashMap::new(),
+        enums: HashMap::new(),
+        interfaces: HashMap::new(),
+        function_instances: HashMap::new(),
+        pending: vec![],
+        requested_types: RefCell::new(vec![]),
+        scopes: vec![HashMap::new()],
+        return_types: vec![],
+        diagnostics: vec![],
+    };
+    for e in program.iter() {
+        match e {
+            Expression::Function(decl) if !decl.type_params.is_empty() => {
+                m.generic_functions.insert(decl.name.path(), decl.clone());
+            }
+            Expression::Function(decl) => {
+                m.functions.insert(decl.name.path(), decl.clone());
+            }
+            Expression::Struct(decl) => {
+                m.structs.insert(decl.name.name.to_owned(), decl.clone());
+            }
+            Expression::Enum(decl) => {
+                m.enums.insert(decl.name.name.to_owned(), decl.clone());
+            }
+            Expression::Interface(decl) => {
+                m.interfaces.insert(decl.name.name.to_owned(), decl.clone());
+            }
+            _ => {}
+        }
+    }
+
+    let mut output = vec![];
+    for e in program.into_iter() {
+        match e {
+            Expression::Function(decl) if !decl.type_params.is_empty() => {}
+            Expression::Struct(ref decl) if !decl.type_params.is_empty() => {}
+            Expression::Enum(ref decl) if !decl.type_params.is_empty() => {}
+            Expression::Function(decl) => {
+                let decl = m.rewrite_function(decl, &HashMap::new());
+                output.push(Expression::Function(decl));
+            }
+            other => {
+                let e = m.rewrite(other, None, &HashMap::new());
+                output.push(e);
+            }
+        }
+    }
+
+    let mut instantiated = 0;
+    while let Some((decl, subst, name)) = m.pending.pop() {
+        instantiated += 1;
+        if instantiated > MAX_INSTANCES {
+            m.diagnostics.push(Diagnostic::error(
+                format!(
+                    "too many instances of generic functions, stopped at `{}`",
+                    name.path()
+                ),
+                decl.span,
+            ));
+            break;
+        }
+        let decl = FunctionDecl {
+            name,
+            type_params: vec![],
+            params: decl
+                .params
+                .into_iter()
+                .map(|p| Param {
+                    param_type: p.param_type.map(|t| t.substitute(&subst)),
+                    name: p.name,
+                })
+                .collect(),
+            return_type: decl.return_type.map(|t| t.substitute(&subst)),
+            body: Box::new(map_types(*decl.body, &|t| t.substitute(&subst))),
+            span: decl.span,
+        };
+        let decl = m.rewrite_function(decl, &subst);
+        output.push(Expression::Function(decl));
+    }
+
+    let mut output: Vec<Expression> = output.into_iter().map(|e| m.mangle(e)).collect();
+    output.extend(m.instantiate_types());
+
+    if m.diagnostics.is_empty() {
+        Ok(output)
+    } else {
+        Err(m.diagnostics)
+    }
+}
+
+impl Monomorphiser {
+    fn lookup(&self, name: &str) -> Option<Type> {
+        self.scopes.iter().rev().find_map(|s| s.get(name).cloned())
+    }
+
+    fn declare(&mut self, name: &str, t: Type) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), t);
+        }
+    }
+
+    fn is_generic_type(&self, name: &str) -> bool {
+        self.structs
+            .get(name)
+            .is_some_and(|s| !s.type_params.is_empty())
+            || self
+                .enums
+                .get(name)
+                .is_some_and(|e| !e.type_params.is_empty())
+    }
+
+    fn rewrite_function(
+        &mut self,
+        decl: FunctionDecl,
+        subst: &HashMap<String, Type>,
+    ) -> FunctionDecl {
+        self.scopes.push(HashMap::new());
+        for p in decl.params.iter() {
+            if let Some(t) = &p.param_type {
+                self.declare(&p.name.name, t.clone());
+            }
+        }
+        self.return_types.push(decl.return_type.clone());
+        let body = self.rewrite(*decl.body, None, subst);
+        self.return_types.pop();
+        self.scopes.pop();
+        FunctionDecl {
+            body: Box::new(body),
+            ..decl
+        }
+    }
+
+    // Rewrites calls to generic functions into calls to their instances, `expected` is the type
+    // the context wants ie. from a definition's annotation
+    fn rewrite(
+        &mut self,
+        expression: Expression,
+        expected: Option<&Type>,
+        subst: &HashMap<String, Type>,
+    ) -> Expression {
+        match expression {
+            Expression::Definition {
+                mutability,
+                definition_type,
+                identifier,
+                value,
+                span,
+            } => {
+                let value = self.rewrite(*value, definition_type.as_ref(), subst);
+                let t = definition_type.clone().or_else(|| self.type_of(&value));
+                if let Some(t) = t {
+                    self.declare(&identifier.name, t);
+                }
+                Expression::Definition {
+                    mutability,
+                    definition_type,
+                    identifier,
+                    value: Box::new(value),
+                    span,
+                }
+            }
+            Expression::FunctionCall {
+                function_name,
+                type_args,
+                parameters,
+                span,
+            } => self.rewrite_call(function_name, type_args, parameters, span, expected, subst),
+            // a generic function passed as a value takes its type arguments from the context
+            Expression::Identifier(ident) if self.generic_functions.contains_key(&ident.name) => {
+                let decl = self.generic_functions[&ident.name].clone();
+                let mut s = HashMap::new();
+                if let Some(Type::Function(params, ret)) = expected {
+                    for (p, a) in decl.params.iter().zip(params.iter()) {
+                        if let Some(pattern) = &p.param_type {
+                            let _ = match_type_pattern(pattern, a, &mut s);
+                        }
+                    }
+                    if let Some(pattern) = &decl.return_type {
+                        let _ = match_type_pattern(pattern, ret, &mut s);
+                    }
+                }
+                let args = self.ordered_args(&decl.type_params, &s, &ident.name, ident.span);
+                self.check_bounds(&decl.type_params, &args, ident.span);
+                let instance = self.request_function_instance(&decl, args);
+                Expression::Identifier(Ident::new(&instance.path(), ident.span))
+            }
+            Expression::FieldAccess {
+                object,
+                field,
+                span,
+            } => {
+                // `Option.None` where Option is generic takes its type arguments from the context
+                if let Expression::Identifier(ident) = object.as_ref() {
+                    if self.enums.contains_key(&ident.name) && self.is_generic_type(&ident.name) {
+                        let args = match expected {
+                            Some(Type::Named(n, args)) if *n == ident.name => args.clone(),
+                            _ => {
+                                self.diagnostics.push(Diagnostic::error(
+                                    format!(
+                                        "cannot infer the type arguments of `{}.{}`, add a type annotation",
+                                        ident.name, field.name
+                                    ),
+                                    span,
+                                ));
+                                vec![]
+                            }
+                        };
+                        let name = self.instance_type_name(&ident.name, &args);
+                        return Expression::FieldAccess {
+                            object: Box::new(Expression::Identifier(Ident::new(&name, ident.span))),
+                            field,
+                            span,
+                        };
+                    }
+                }
+                Expression::FieldAccess {
+                    object: Box::new(self.rewrite(*object, None, subst)),
+                    field,
+                    span,
+                }
+            }
+            Expression::StructLiteral {
+                name,
+                type_args,
+                fields,
+                span,
+            } => {
+                let fields: Vec<(Ident, Expression)> = fields
+                    .into_iter()
+                    .map(|(n, v)| (n, self.rewrite(v, None, subst)))
+                    .collect();
+                let Some(decl) = self.structs.get(&name.name).cloned() else {
+                    return Expression::StructLiteral {
+                        name,
+                        type_args,
+                        fields,
+                        span,
+                    };
+                };
+                if decl.type_params.is_empty() {
+                    return Expression::StructLiteral {
+                        name,
+                        type_args,
+                        fields,
+                        span,
+                    };
+                }
+                let mut type_args = type_args;
+                if type_args.is_empty() {
+                    let mut s = HashMap::new();
+                    if let Some(Type::Named(n, args)) = expected {
+                        if *n == name.name {
+                            let pattern = Type::Named(
+                                n.to_owned(),
+                                decl.type_params
+                                    .iter()
+                                    .map(|p| Type::Param(p.name.to_owned()))
+                                    .collect(),
+                            );
+                            let _ = match_type_pattern(
+                                &pattern,
+                                &Type::Named(n.to_owned(), args.clone()),
+                                &mut s,
+                            );
+                        }
+                    }
+                    for (field_name, value) in fields.iter() {
+                        let declared = decl.fields.iter().find(|(_, f)| f.name == field_name.name);
+                        if let (Some((pattern, _)), Some(actual)) = (declared, self.type_of(value))
+                        {
+                            if let Err(e) = match_type_pattern(pattern, &actual, &mut s) {
+                                self.diagnostics.push(Diagnostic::error(
+                                    format!("field `{}`: {}", field_name.name, e),
+                                    value.span(),
+                                ));
+                            }
+                        }
+                    }
+                    type_args = self.ordered_args(&decl.type_params, &s, &name.name, span);
+                }
+                self.check_bounds(&decl.type_params, &type_args, span);
+                Expression::StructLiteral {
+                    name: Ident::new(&self.instance_type_name(&name.name, &type_args), name.span),
+                    type_args: vec![],
+                    fields,
+                    span,
+                }
+            }
+            Expression::Block { expressions, span } => {
+                self.scopes.push(HashMap::new());
+                let expressions = expressions
+                    .into_iter()
+                    .map(|e| self.rewrite(e, None, subst))
+                    .collect();
+                self.scopes.pop();
+                Expression::Block { expressions, span }
+            }
+            Expression::Return { value, span } => {
+                let expected = self.return_types.last().cloned().flatten();
+                Expression::Return {
+                    value: value.map(|v| Box::new(self.rewrite(*v, expected.as_ref(), subst))),
+                    span,
+                }
+            }
+            Expression::Lambda { params, body, span } => {
+                self.scopes.push(HashMap::new());
+                let expected_params = match expected {
+                    Some(Type::Function(ps, _)) => ps.clone(),
+                    _ => vec![],
+                };
+                for (i, p) in params.iter().enumerate() {
+                    if let Some(t) = p.param_type.clone().or(expected_params.get(i).cloned()) {
+                        self.declare(&p.name.name, t);
+                    }
+                }
+                self.return_types.push(None);
+                let body = self.rewrite(*body, None, subst);
+                self.return_types.pop();
+                self.scopes.pop();
+                Expression::Lambda {
+                    params,
+                    body: Box::new(body),
+                    span,
+                }
+            }
+            Expression::For {
+                variable,
+                iterable,
+                body,
+                span,
+            } => {
+                let iterable = self.rewrite(*iterable, None, subst);
+                self.scopes.push(HashMap::new());
+                match self.type_of(&iterable) {
+                    Some(Type::BuiltIn(BuiltinType::Range, _)) => {
+                        self.declare(&variable.name, Type::int())
+                    }
+                    Some(Type::BuiltIn(_, args)) if args.len() == 1 => {
+                        self.declare(&variable.name, args[0].clone())
+                    }
+                    _ => {}
+                }
+                let body = self.rewrite(*body, None, subst);
+                self.scopes.pop();
+                Expression::For {
+                    variable,
+                    iterable: Box::new(iterable),
+                    body: Box::new(body),
+                    span,
+                }
+            }
+            Expression::Match {
+                subject,
+                arms,
+                span,
+            } => {
+                let subject = self.rewrite(*subject, None, subst);
+                let subject_type = self.type_of(&subject);
+                let arms = arms
+                    .into_iter()
+                    .map(|arm| {
+                        self.scopes.push(HashMap::new());
+                        let pattern = self.rewrite_pattern(arm.pattern, subject_type.as_ref());
+                        let guard = arm.guard.map(|g| self.rewrite(g, None, subst));
+                        let body = self.rewrite(arm.body, None, subst);
+                        self.scopes.pop();
+                        MatchArm {
+                            pattern,
+                            guard,
+                            body,
+                        }
+                    })
+                    .collect();
+                Expression::Match {
+                    subject: Box::new(subject),
+                    arms,
+                    span,
+                }
+            }
+            other => self.rewrite_children(other, subst),
+        }
+    }
+
+    // Rewrites the sub-expressions of expressions that don't bind names or call anything
+    fn rewrite_children(
+        &mut self,
+        expression: Expression,
+        subst: &HashMap<String, Type>,
+    ) -> Expression {
+        let mut r = |e: Expression| self.rewrite(e, None, subst);
+        match expression {
+            Expression::Assignment {
+                target,
+                value,
+                span,
+            } => Expression::Assignment {
+                target: Box::new(r(*target)),
+                value: Box::new(r(*value)),
+                span,
+            },
+            Expression::Calculation {
+                left,
+                operator,
+                right,
+                span,
+            } => Expression::Calculation {
+                left: Box::new(r(*left)),
+                operator,
+                right: Box::new(r(*right)),
+                span,
+            },
+            Expression::Unary {
+                operator,
+                operand,
+                span,
+            } => Expression::Unary {
+                operator,
+                operand: Box::new(r(*operand)),
+                span,
+            },
+            Expression::Index {
+                object,
+                index,
+                span,
+            } => Expression::Index {
+                object: Box::new(r(*object)),
+                index: Box::new(r(*index)),
+                span,
+            },
+            Expression::List { elements, span } => Expression::List {
+                elements: elements.into_iter().map(r).collect(),
+                span,
+            },
+            Expression::Tuple { elements, span } => Expression::Tuple {
+                elements: elements.into_iter().map(r).collect(),
+                span,
+            },
+            Expression::Map { entries, span } => Expression::Map {
+                entries: entries.into_iter().map(|(k, v)| (r(k), r(v))).collect(),
+                span,
+            },
+            Expression::Range {
+                start,
+                end,
+                step,
+                span,
+            } => Expression::Range {
+                start: Box::new(r(*start)),
+                end: Box::new(r(*end)),
+                step: step.map(|s| Box::new(r(*s))),
+                span,
+            },
+            Expression::If {
+                branches,
+                else_branch,
+                span,
+            } => Expression::If {
+                branches: branches.into_iter().map(|(c, b)| (r(c), r(b))).collect(),
+                else_branch: else_branch.map(|e| Box::new(r(*e))),
+                span,
+            },
+            Expression::While {
+                condition,
+                body,
+                span,
+            } => Expression::While {
+                condition: Box::new(r(*condition)),
+                body: Box::new(r(*body)),
+                span,
+            },
+            Expression::Cond { arms, span } => Expression::Cond {
+                arms: arms.into_iter().map(|(c, b)| (r(c), r(b))).collect(),
+                span,
+            },
+            other => other,
+        }
+    }
+
+    fn rewrite_pattern(&mut self, pattern: Pattern, subject: Option<&Type>) -> Pattern {
+        match pattern {
+            Pattern::Binding(ident) => {
+                if let Some(t) = subject {
+                    self.declare(&ident.name, t.clone());
+                }
+                Pattern::Binding(ident)
+            }
+            Pattern::Tuple(elements, span) => {
+                let element_types = subject
+                    .and_then(|t| crate::checker::tuple_pattern_types(t, &elements))
+                    .unwrap_or_default();
+                Pattern::Tuple(
+                    elements
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, p)| {
+                            let t = element_types.get(i).and_then(|t| t.as_ref());
+                            self.rewrite_pattern(p, t)
+                        })
+                        .collect(),
+                    span,
+                )
+            }
+            Pattern::List(elements, span) => {
+                let element_type = match subject {
+                    Some(Type::BuiltIn(BuiltinType::List, args)) => args.first().cloned(),
+                    _ => None,
+                };
+                Pattern::List(
+                    elements
+                        .into_iter()
+                        .map(|p| self.rewrite_pattern(p, element_type.as_ref()))
+                        .collect(),
+                    span,
+                )
+            }
+            Pattern::Variant(mut name, fields, span) => {
+                let enum_name = name.idents[0].name.to_owned();
+                let mut field_types = vec![];
+                if let Some(decl) = self.enums.get(&enum_name).cloned() {
+                    let args = match subject {
+                        Some(Type::Named(n, args)) if *n == enum_name => args.clone(),
+                        _ => vec![],
+                    };
+                    let s: HashMap<String, Type> = decl
+                        .type_params
+                        .iter()
+                        .map(|p| p.name.to_owned())
+                        .zip(args.iter().cloned())
+                        .collect();
+                    if let Some(variant) = name
+                        .idents
+                        .get(1)
+                        .and_then(|v| decl.variants.iter().find(|d| d.name.name == v.name))
+                    {
+                        field_types = variant.fields.iter().map(|t| t.substitute(&s)).collect();
+                    }
+                    if !decl.type_params.is_empty() {
+                        name.idents[0].name = self.instance_type_name(&enum_name, &args);
+                    }
+                }
+                Pattern::Variant(
+                    name,
+                    fields
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, p)| self.rewrite_pattern(p, field_types.get(i)))
+                        .collect(),
+                    span,
+                )
+            }
+            other => other,
+        }
+    }
+
+    fn rewrite_call(
+        &mut self,
+        mut function_name: FunctionName,
+        type_args: Vec<Type>,
+        parameters: Vec<Expression>,
+        span: Span,
+        expected: Option<&Type>,
+        subst: &HashMap<String, Type>,
+    ) -> Expression {
+        // `T.show(x)` inside a generic body calls the method of whatever T was instantiated with
+        if let Some(concrete) = subst.get(&function_name.idents[0].name) {
+            let span = function_name.idents[0].span;
+            function_name.idents[0] = Ident::new(&concrete.base_name(), span);
+        }
+        let path = function_name.path();
+
+        let generic = self.generic_functions.get(&path).cloned();
+        let explicit: HashMap<String, Type> = match &generic {
+            Some(decl) => decl
+                .type_params
+                .iter()
+                .map(|p| p.name.to_owned())
+                .zip(type_args.iter().cloned())
+                .collect(),
+            None => HashMap::new(),
+        };
+        let param_types: Vec<Option<Type>> = match (&generic, self.functions.get(&path)) {
+            (Some(decl), _) | (None, Some(decl)) => decl
+                .params
+                .iter()
+                .map(|p| p.param_type.as_ref().map(|t| t.substitute(&explicit)))
+                .collect(),
+            _ => vec![],
+        };
+
+        // lambdas are rewritten once the types of their parameters are known
+        let parameters: Vec<Expression> = parameters
+            .into_iter()
+            .enumerate()
+            .map(|(i, p)| match p {
+                Expression::Lambda { .. } => p,
+                p => {
+                    let expected = param_types
+                        .get(i)
+                        .cloned()
+                        .flatten()
+                        .filter(|t| !t.contains_params());
+                    self.rewrite(p, expected.as_ref(), subst)
+                }
+            })
+            .collect();
+
+        // enum variant constructors, ie. `Option.Some(1)`
+        if function_name.idents.len() == 2 && self.is_generic_type(&function_name.idents[0].name) {
+            let enum_name = function_name.idents[0].name.to_owned();
+            if let Some(decl) = self.enums.get(&enum_name).cloned() {
+                let mut s = HashMap::new();
+                if let Some(Type::Named(n, args)) = expected {
+                    if *n == enum_name {
+                        for (p, a) in decl.type_params.iter().zip(args.iter()) {
+                            s.insert(p.name.to_owned(), a.clone());
+                        }
+                    }
+                }
+                if let Some(variant) = decl
+                    .variants
+                    .iter()
+                    .find(|v| v.name.name == function_name.idents[1].name)
+                {
+                    for (pattern, value) in variant.fields.iter().zip(parameters.iter()) {
+                        if let Some(actual) = self.type_of(value) {
+                            if let Err(e) = match_type_pattern(pattern, &actual, &mut s) {
+                                self.diagnostics.push(Diagnostic::error(e, value.span()));
+                            }
+                        }
+                    }
+                }
+                let args = if type_args.is_empty() {
+                    self.ordered_args(&decl.type_params, &s, &path, span)
+                } else {
+                    type_args
+                };
+                self.check_bounds(&decl.type_params, &args, span);
+                function_name.idents[0].name = self.instance_type_name(&enum_name, &args);
+                return Expression::FunctionCall {
+                    function_name,
+                    type_args: vec![],
+                    parameters: parameters
+                        .into_iter()
+                        .map(|p| self.rewrite(p, None, subst))
+                        .collect(),
+                    span,
+                };
+            }
+        }
+
+        let Some(decl) = generic else {
+            let parameters = parameters
+                .into_iter()
+                .enumerate()
+                .map(|(i, p)| match p {
+                    Expression::Lambda { .. } => {
+                        let expected = param_types.get(i).cloned().flatten();
+                        self.rewrite(p, expected.as_ref(), subst)
+                    }
+                    p => p,
+                })
+                .collect();
+            return Expression::FunctionCall {
+                function_name,
+                type_args,
+                parameters,
+                span,
+            };
+        };
+
+        if parameters.len() != decl.params.len() {
+            self.diagnostics.push(Diagnostic::error(
+                format!(
+                    "`{}` takes {} arguments but {} were given",
+                    path,
+                    decl.params.len(),
+                    parameters.len()
+                ),
+                span,
+            ));
+            return Expression::FunctionCall {
+                function_name,
+                type_args,
+                parameters,
+                span,
+            };
+        }
+
+        let args = if !type_args.is_empty() {
+            if type_args.len() != decl.type_params.len() {
+                self.diagnostics.push(Diagnostic::error(
+                    format!(
+                        "`{}` takes {} type arguments but {} were given",
+                        path,
+                        decl.type_params.len(),
+                        type_args.len()
+                    ),
+                    span,
+                ));
+                return Expression::FunctionCall {
+                    function_name,
+                    type_args,
+                    parameters,
+                    span,
+                };
+            }
+            type_args
+        } else {
+            let mut s = HashMap::new();
+            if let (Some(Type::Named(_, _)) | Some(Type::BuiltIn(_, _)), Some(ret)) =
+                (expected, &decl.return_type)
+            {
+                let _ = match_type_pattern(ret, expected.unwrap(), &mut s);
+            }
+            // everything but lambdas first, then lambdas with what is known about their params
+            for (p, a) in decl.params.iter().zip(parameters.iter()) {
+                if matches!(a, Expression::Lambda { .. }) {
+                    continue;
+                }
+                if let (Some(pattern), Some(actual)) = (&p.param_type, self.type_of(a)) {
+                    if let Err(e) = match_type_pattern(pattern, &actual, &mut s) {
+                        self.diagnostics.push(Diagnostic::error(
+                            format!("argument `{}` of `{}`: {}", p.name.name, path, e),
+                            a.span(),
+                        ));
+                    }
+                }
+            }
+            for (p, lambda) in decl.params.iter().zip(parameters.iter()) {
+                if !matches!(lambda, Expression::Lambda { .. }) {
+                    continue;
+                }
+                let Some(pattern) = p.param_type.clone() else {
+                    continue;
+                };
+                let expected = pattern.substitute(&s);
+                self.scopes.push(HashMap::new());
+                let actual = self.type_of_lambda(lambda, &expected);
+                self.scopes.pop();
+                if let Some(actual) = actual {
+                    if let Err(e) = match_type_pattern(&pattern, &actual, &mut s) {
+                        self.diagnostics.push(Diagnostic::error(
+                            format!("argument `{}` of `{}`: {}", p.name.name, path, e),
+                            lambda.span(),
+                        ));
+                    }
+                }
+            }
+            self.ordered_args(&decl.type_params, &s, &path, span)
+        };
+
+        self.check_bounds(&decl.type_params, &args, span);
+        let s: HashMap<String, Type> = decl
+            .type_params
+            .iter()
+            .map(|p| p.name.to_owned())
+            .zip(args.iter().cloned())
+            .collect();
+        let parameters = parameters
+            .into_iter()
+            .enumerate()
+            .map(|(i, p)| match p {
+                Expression::Lambda { .. } => {
+                    let expected = decl.params[i].param_type.as_ref().map(|t| t.substitute(&s));
+                    self.rewrite(p, expected.as_ref(), subst)
+                }
+                p => p,
+            })
+            .collect();
+        let instance = self.request_function_instance(&decl, args);
+        Expression::FunctionCall {
+            function_name: FunctionName {
+                idents: instance
+                    .idents
+                    .iter()
+                    .map(|i| Ident::new(&i.name, function_name.span()))
+                    .collect(),
+            },
+            type_args: vec![],
+            parameters,
+            span,
+        }
+    }
+
+    // Type arguments in declaration order, reporting the ones that couldn't be inferred
+    fn ordered_args(
+        &mut self,
+        type_params: &[TypeParam],
+        subst: &HashMap<String, Type>,
+        path: &str,
+        span: Span,
+    ) -> Vec<Type> {
+        type_params
+            .iter()
+            .map(|p| match subst.get(&p.name) {
+                Some(t) => t.clone(),
+                None => {
+                    self.diagnostics.push(Diagnostic::error(
+                        format!(
+                            "cannot infer type parameter `{}` of `{}`, pass it explicitly ie. `{}[..](..)`",
+                            p.name, path, path
+                        ),
+                        span,
+                    ));
+                    Type::Unit
+                }
+            })
+            .collect()
+    }
+
+    fn request_function_instance(&mut self, decl: &FunctionDecl, args: Vec<Type>) -> FunctionName {
+        let key = instance_name(&decl.name.path(), &args);
+        if let Some(name) = self.function_instances.get(&key) {
+            return name.clone();
+        }
+        let mut name = decl.name.clone();
+        if let Some(last) = name.idents.last_mut() {
+            last.name = instance_name(&last.name, &args);
+        }
+        let subst = decl
+            .type_params
+            .iter()
+            .map(|p| p.name.to_owned())
+            .zip(args)
+            .collect();
+        self.function_instances.insert(key, name.clone());
+        self.pending.push((decl.clone(), subst, name.clone()));
+        name
+    }
+
+    fn instance_type_name(&self, name: &str, args: &[Type]) -> String {
+        self.requested_types
+            .borrow_mut()
+            .push((name.to_string(), args.to_vec()));
+        instance_name(name, args)
+    }
+
+    // Checks every `T: Interface` bound holds for the type argument given for T
+    fn check_bounds(&mut self, type_params: &[TypeParam], args: &[Type], span: Span) {
+        for (param, arg) in type_params.iter().zip(args.iter()) {
+            for bound in param.bounds.iter() {
+                let Type::Named(interface_name, interface_args) = bound else {
+                    continue;
+                };
+                let Some(interface) = self.interfaces.get(interface_name).cloned() else {
+                    self.diagnostics.push(Diagnostic::error(
+                        format!("`{}` is not an interface", interface_name),
+                        span,
+                    ));
+                    continue;
+                };
+                let mut s: HashMap<String, Type> = interface
+                    .type_params
+                    .iter()
+                    .map(|p| p.name.to_owned())
+                    .zip(interface_args.iter().cloned())
+                    .collect();
+                s.insert("Self".to_string(), arg.clone());
+                for method in interface.methods.iter() {
+                    if let Err(e) = self.check_method(arg, method, &s) {
+                        self.diagnostics.push(
+                            Diagnostic::error(
+                                format!("`{}` does not implement `{}`: {}", arg, bound, e),
+                                span,
+                            )
+                            .with_note(
+                                format!("`{}` is required by `{}`", interface_name, param.name),
+                                interface.span,
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_method(
+        &self,
+        implementor: &Type,
+        method: &MethodSignature,
+        subst: &HashMap<String, Type>,
+    ) -> Result<(), String> {
+        let self_to_param = |t: &Type| match t {
+            Type::Named(n, args) if n == "Self" && args.is_empty() => {
+                Type::Param("Self".to_string())
+            }
+            t => t.bind_params(&["Self".to_string()]),
+        };
+        let path = format!("{}.{}", implementor.base_name(), method.name.name);
+        let wanted: Vec<Type> = method
+            .params
+            .iter()
+            .filter_map(|p| p.param_type.as_ref())
+            .map(|t| self_to_param(t).substitute(subst))
+            .collect();
+        let wanted_return = method
+            .return_type
+            .as_ref()
+            .map(|t| self_to_param(t).substitute(subst))
+            .unwrap_or(Type::Unit);
+        let Some(decl) = self
+            .functions
+            .get(&path)
+            .or_else(|| self.generic_functions.get(&path))
+        else {
+            return Err(format!("missing `func {}`", path));
+        };
+        let actual: Vec<Type> = decl
+            .params
+            .iter()
+            .filter_map(|p| p.param_type.clone())
+            .collect();
+        let actual_return = decl.return_type.clone().unwrap_or(Type::Unit);
+        if actual.len() != wanted.len() {
+            return Err(format!(
+                "`{}` takes {} arguments, the interface requires {}",
+                path,
+                actual.len(),
+                wanted.len()
+            ));
+        }
+        let mut s = HashMap::new();
+        for (a, w) in actual
+            .iter()
+            .zip(wanted.iter())
+            .chain([(&actual_return, &wanted_return)])
+        {
+            match_type_pattern(a, w, &mut s).map_err(|e| format!("`{}` {}", path, e))?;
+        }
+        Ok(())
+    }
+
+    // Rewrites generic struct and enum types into their instances, ie. `Pair[int, str]` is the
+    // struct named "Pair[int, str]" with no type arguments
+    fn mangle_type(&self, t: &Type) -> Type {
+        match t {
+            Type::Named(name, args) if !args.is_empty() && self.is_generic_type(name) => {
+                Type::Named(self.instance_type_name(name, args), vec![])
+            }
+            Type::Named(name, args) => Type::Named(
+                name.to_owned(),
+                args.iter().map(|a| self.mangle_type(a)).collect(),
+            ),
+            Type::BuiltIn(b, args) => {
+                Type::BuiltIn(*b, args.iter().map(|a| self.mangle_type(a)).collect())
+            }
+            Type::Function(params, ret) => Type::Function(
+                params.iter().map(|p| self.mangle_type(p)).collect(),
+                Box::new(self.mangle_type(ret)),
+            ),
+            _ => t.clone(),
+        }
+    }
+
+    fn mangle(&self, expression: Expression) -> Expression {
+        match expression {
+            Expression::Struct(decl) => Expression::Struct(StructDecl {
+                fields: decl
+                    .fields
+                    .into_iter()
+                    .map(|(t, f)| (self.mangle_type(&t), f))
+                    .collect(),
+                ..decl
+            }),
+            Expression::Enum(decl) => Expression::Enum(EnumDecl {
+                variants: decl
+                    .variants
+                    .into_iter()
+                    .map(|v| EnumVariant {
+                        name: v.name,
+                        fields: v.fields.iter().map(|t| self.mangle_type(t)).collect(),
+                    })
+                    .collect(),
+                ..decl
+            }),
+            other => map_types(other, &|t| self.mangle_type(t)),
+        }
+    }
+
+    // Creates the struct and enum declarations for every generic type instance used
+    fn instantiate_types(&mut self) -> Vec<Expression> {
+        let mut done: Vec<String> = vec![];
+        let mut output = vec![];
+        loop {
+            let requested: Vec<(String, Vec<Type>)> =
+                self.requested_types.borrow_mut().drain(..).collect();
+            if requested.is_empty() {
+                break;
+            }
+            for (name, args) in requested {
+                let instance = instance_name(&name, &args);
+                if done.contains(&instance) {
+                    continue;
+                }
+                done.push(instance.clone());
+                if let Some(decl) = self.structs.get(&name).cloned() {
+                    let s = decl
+                        .type_params
+                        .iter()
+                        .map(|p| p.name.to_owned())
+                        .zip(args)
+                        .collect();
+                    let decl = StructDecl {
+                        name: Ident::new(&instance, decl.name.span),
+                        type_params: vec![],
+                        fields: decl
+                            .fields
+                            .iter()
+                            .map(|(t, f)| (t.substitute(&s), f.clone()))
+                            .collect(),
+                        span: decl.span,
+                    };
+                    output.push(self.mangle(Expression::Struct(decl)));
+                } else if let Some(decl) = self.enums.get(&name).cloned() {
+                    let s = decl
+                        .type_params
+                        .iter()
+                        .map(|p| p.name.to_owned())
+                        .zip(args)
+                        .collect();
+                    let decl = EnumDecl {
+                        name: Ident::new(&instance, decl.name.span),
+                        type_params: vec![],
+                        variants: decl
+                            .variants
+                            .iter()
+                            .map(|v| EnumVariant {
+                                name: v.name.clone(),
+                                fields: v.fields.iter().map(|t| t.substitute(&s)).collect(),
+                            })
+                            .collect(),
+                        span: decl.span,
+                    };
+                    output.push(self.mangle(Expression::Enum(decl)));
+                }
+            }
+        }
+        output
+    }
+
+    fn type_of_lambda(&mut self, lambda: &Expression, expected: &Type) -> Option<Type> {
+        let Expression::Lambda { params, body, .. } = lambda else {
+            return None;
+        };
+        let expected_params = match expected {
+            Type::Function(ps, _) => ps.clone(),
+            _ => vec![],
+        };
+        let mut param_types = vec![];
+        for (i, p) in params.iter().enumerate() {
+            let t = p
+                .param_type
+                .clone()
+                .or(expected_params.get(i).cloned())
+                .filter(|t| !t.contains_params())?;
+            self.declare(&p.name.name, t.clone());
+            param_types.push(t);
+        }
+        let ret = self.type_of(body)?;
+        Some(Type::function(param_types, ret))
+    }
+
+    // A best effort type of an expression, enough to infer type arguments from
+    fn type_of(&mut self, expression: &Expression) -> Option<Type> {
+        match expression {
+            Expression::Literal { value, .. } => Some(match value {
+                TokenValue::Int(_) => Type::int(),
+                TokenValue::Float(_) => Type::float(),
+                TokenValue::Bool(_) => Type::bool(),
+                TokenValue::Char(_) => Type::char(),
+                TokenValue::Atom(_) => Type::atom(),
+                TokenValue::String(_) => Type::string(),
+            }),
+            Expression::Identifier(ident) => self.lookup(&ident.name),
+            Expression::List { elements, .. } => match elements.first() {
+                Some(e) => Some(Type::list(self.type_of(e)?)),
+                None => Some(Type::BuiltIn(BuiltinType::List, vec![])),
+            },
+            Expression::Tuple { elements, .. } => {
+                let types: Option<Vec<Type>> = elements.iter().map(|e| self.type_of(e)).collect();
+                Some(Type::tuple(types?))
+            }
+            Expression::Map { entries, .. } => match entries.first() {
+                Some((k, v)) => Some(Type::map(self.type_of(k)?, self.type_of(v)?)),
+                None => Some(Type::BuiltIn(BuiltinType::Map, vec![])),
+            },
+            Expression::Range { .. } => Some(Type::range()),
+            Expression::Calculation {
+                left,
+                operator,
+                right,
+                ..
+            } => match operator {
+                Operators::BEq
+                | Operators::BNEq
+                | Operators::LessThan
+                | Operators::GreaterThan
+                | Operators::LEq
+                | Operators::GEq
+                | Operators::And
+                | Operators::Or => Some(Type::bool()),
+                Operators::Concat => Some(Type::string()),
+                _ => self.type_of(left).or_else(|| self.type_of(right)),
+            },
+            Expression::Unary {
+                operator: Operators::Not,
+                ..
+            } => Some(Type::bool()),
+            Expression::Unary { operand, .. } => self.type_of(operand),
+            Expression::StructLiteral { name, .. } => {
+                Some(Type::Named(name.name.to_owned(), vec![]))
+            }
+            Expression::FieldAccess { object, field, .. } => {
+                let Type::Named(name, args) = self.type_of(object)? else {
+                    return None;
+                };
+                let decl = self.structs.get(&name)?;
+                let s = decl
+                    .type_params
+                    .iter()
+                    .map(|p| p.name.to_owned())
+                    .zip(args)
+                    .collect();
+                decl.fields
+                    .iter()
+                    .find(|(_, f)| f.name == field.name)
+                    .map(|(t, _)| t.substitute(&s))
+            }
+            Expression::Index { object, .. } => match self.type_of(object)? {
+                Type::BuiltIn(BuiltinType::List, args) => args.first().cloned(),
+                Type::BuiltIn(BuiltinType::Map, args) => args.get(1).cloned(),
+                Type::BuiltIn(BuiltinType::String, _) => Some(Type::string()),
+                _ => None,
+            },
+            Expression::FunctionCall {
+                function_name,
+                type_args,
+                parameters,
+                ..
+            } => {
+                let path = function_name.path();
+                if let Some(decl) = self.functions.get(&path) {
+                    return Some(decl.return_type.clone().unwrap_or(Type::Unit));
+                }
+                if let Some(decl) = self.generic_functions.get(&path).cloned() {
+                    let mut s: HashMap<String, Type> = decl
+                        .type_params
+                        .iter()
+                        .map(|p| p.name.to_owned())
+                        .zip(type_args.iter().cloned())
+                        .collect();
+                    for (p, a) in decl.params.iter().zip(parameters.iter()) {
+                        if let (Some(pattern), Some(actual)) = (&p.param_type, self.type_of(a)) {
+                            let _ = match_type_pattern(pattern, &actual, &mut s);
+                        }
+                    }
+                    return Some(
+                        decl.return_type
+                            .clone()
+                            .unwrap_or(Type::Unit)
+                            .substitute(&s),
+                    );
+                }
+                // a call to an instance `name[..]` that was already rewritten
+                let instance = self
+                    .function_instances
+                    .iter()
+                    .find(|(_, n)| n.path() == path)
+                    .map(|(k, _)| k.to_owned())?;
+                let base = instance.split('[').next()?.to_string();
+                let decl = self.generic_functions.get(&base).cloned()?;
+                let mut s = HashMap::new();
+                for (p, a) in decl.params.iter().zip(parameters.iter()) {
+                    if let (Some(pattern), Some(actual)) = (&p.param_type, self.type_of(a)) {
+                        let _ = match_type_pattern(pattern, &actual, &mut s);
+                    }
+                }
+                Some(
+                    decl.return_type
+                        .clone()
+                        .unwrap_or(Type::Unit)
+                        .substitute(&s),
+                )
+            }
+            Expression::Lambda { params, body, .. } => {
+                let types: Option<Vec<Type>> =
+                    params.iter().map(|p| p.param_type.clone()).collect();
+                let types = types?;
+                self.scopes.push(HashMap::new());
+                for (p, t) in params.iter().zip(types.iter()) {
+                    self.declare(&p.name.name, t.clone());
+                }
+                let ret = self.type_of(body);
+                self.scopes.pop();
+                Some(Type::function(types, ret?))
+            }
+            Expression::Block { expressions, .. } => {
+                self.scopes.push(HashMap::new());
+                let mut t = Some(Type::Unit);
+                for e in expressions.iter() {
+                    t = self.type_of(e);
+                    if let Expression::Definition {
+                        definition_type,
+                        identifier,
+                        value,
+                        ..
+                    } = e
+                    {
+                        if let Some(dt) = definition_type.clone().or_else(|| self.type_of(value)) {
+                            self.declare(&identifier.name, dt);
+                        }
+                    }
+                    if let Expression::Return { value: Some(v), .. } = e {
+                        t = self.type_of(v);
+                        break;
+                    }
+                }
+                self.scopes.pop();
+                t
+            }
+            Expression::If { branches, .. } => branches.first().and_then(|(_, b)| self.type_of(b)),
+            Expression::Return { value: Some(v), .. } => self.type_of(v),
+            Expression::Definition { .. } => Some(Type::Unit),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+fn function_names(program: &[Expression]) -> Vec<String> {
+    program
+        .iter()
+        .filter_map(|e| match e {
+            Expression::Function(decl) => Some(decl.name.path()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_monomorphise_infers_type_args() {
+    let program = parse(
+        "func map[T, U](list[T] xs, fn(T) -> U f) -> list[U] {
+    return []
+}
+list[int] xs = [1, 2, 3]
+ys = map(xs, fn x -> x <> \"!\")
+zs = map(xs, fn x -> x * 2)
+",
+    )
+    .unwrap();
+    let program = monomorphise(program).unwrap();
+    let mut names = function_names(&program);
+    names.sort();
+    assert_eq!(names, vec!["map[int, int]", "map[int, str]"]);
+}
+
+#[test]
+fn test_monomorphise_checks_bounds() {
+    let source = "interface Show {
+    func show(Self x) -> str
+}
+struct Point { int x, int y }
+func Point.show(Point p) -> str {
+    return \"point\"
+}
+func describe[T: Show](T x) -> str {
+    return T.show(x)
+}
+a = describe(Point{x: 1, y: 2})
+b = describe(1)
+";
+    let errors = monomorphise(parse(source).unwrap()).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0]
+        .message
+        .contains("`int` does not implement `Show`"));
+
+    let program =
+        monomorphise(parse(source.replace("b = describe(1)\n", "").as_str()).unwrap()).unwrap();
+    let instance = program
+        .iter()
+        .find_map(|e| match e {
+            Expression::Function(decl) if decl.name.path() == "describe[Point]" => Some(decl),
+            _ => None,
+        })
+        .unwrap();
+    // the `T.show` in the body now calls `Point.show`
+    let Expression::Block { expressions, .. } = instance.body.as_ref() else {
+        panic!("expected a block");
+    };
+    let Expression::Return {
+        value: Some(value), ..
+    } = &expressions[0]
+    else {
+        panic!("expected a return");
+    };
+    assert!(matches!(
+        value.as_ref(),
+        Expression::FunctionCall { function_name, .. } if function_name.path() == "Point.show"
+    ));
+}
+
+#[test]
+fn test_monomorphise_generic_types() {
+    let program = parse(
+        "struct Pair[A, B] { A first, B second }
+enum Option[T] { Some(T), None }
+p = Pair{first: 1, second: \"one\"}
+Option[float] o = Option.Some(1.5)
+",
+    )
+    .unwrap();
+    let program = monomorphise(program).unwrap();
+    let types: Vec<String> = program
+        .iter()
+        .filter_map(|e| match e {
+            Expression::Struct(decl) => Some(decl.name.name.to_owned()),
+            Expression::Enum(decl) => Some(decl.name.name.to_owned()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(types, vec!["Pair[int, str]", "Option[float]"]);
+    assert!(matches!(
+        &program[1],
+        Expression::Definition { definition_type: Some(Type::Named(name, args)), .. }
+            if name == "Option[float]" && args.is_empty()
+    ));
+}
+
+#[test]
+fn test_monomorphise_testfile() {
+    let file_path = "./rho_testfiles/generics.rho";
+    let contents = std::fs::read_to_string(file_path).unwrap();
+    let program = monomorphise(parse(&contents).unwrap()).unwrap_or_else(|errors| {
+        panic!(
+            "{}",
+            errors
+                .iter()
+                .map(|e| e.render(file_path, &contents))
+                .collect::<String>()
+        )
+    });
+    let mut names = function_names(&program);
+    names.sort();
+    assert_eq!(
+        names,
+        vec![
+            "describe[int]",
+            "first[int]",
+            "int.show",
+            "map[int, int]",
+            "map[int, str]"
+        ]
+    );
+}
+
+#[test]
+fn test_generics_native() {
+    // each instance is a function of its own, compiled for the types it was called with
+    let source = "func id[T](T x) -> T {
+    return x
+}
+func map[T, U](list[T] xs, fn(T) -> U f) -> list[U] {
+    var list[U] out = []
+    for x in xs {
+        out = out ++ [f(x)]
+    }
+    return out
+}
+struct Pair[A, B] {
+    A first
+    B second
+}
+IO.inspect(id(1) + 2)
+IO.puts(id(\"a\") <> \"b\")
+IO.inspect(map([1, 2], fn x -> x * 2))
+IO.inspect(map([1, 2], fn x -> x > 1))
+IO.inspect(Pair{first: id(1.5), second: [id(\"one\")]})
+";
+    let Some((code, stdout, stderr)) = crate::codegen::run_native(source, "") else {
+        return;
+    };
+    let (result, output) = crate::interpreter::run_source(source, "");
+    assert_eq!(stdout, output);
+    assert_eq!(
+        output,
+        "3
+ab
+[2, 4]
+[false, true]
+Pair{first: 1.5, second: [\"one\"]}
+"
+    );
+    assert_eq!(code, Some(result.unwrap()), "{}", stderr);
+    assert!(!stderr.contains("still live"), "{}", stderr);
+}
@@ -1,2 +1,1389 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+use crate::atoms;
+use crate::diagnostics::Diagnostic;
+use crate::maps::Map;
+use crate::parsers::*;
+use crate::resolver::{SymbolKind, SymbolTable};
+use crate::rho_core::{self, CallResult, Context};
+use crate::streams::Cursor;
 use crate::tokens::*;
+use crate::value::*;
+
+// Deep enough for any sane recursion, the interpreter runs on a thread with a stack this large
+pub use rho_runtime::MAX_CALL_DEPTH;
+// The frames a stack trace shows, compiled code shows as many
+use rho_runtime::TRACE_FRAMES;
+pub const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+// Why evaluation stopped before reaching the end of an expression
+enum Unwind {
+    Return(Value),
+    Break,
+    Continue,
+    Error(Diagnostic),
+}
+
+impl From<Diagnostic> for Unwind {
+    fn from(d: Diagnostic) -> Unwind {
+        Unwind::Error(d)
+    }
+}
+
+type Evaluated = Result<Value, Unwind>;
+
+// One step of the way from a binding to the part of its value being assigned, ie. `p.x` or `xs[0]`
+pub enum Place {
+    Field(String, Span),
+    Index(Value, Span),
+}
+
+pub struct Interpreter<'a> {
+    symbols: &'a SymbolTable,
+    globals: Env,
+    functions: HashMap<String, Value>,
+    struct_fields: HashMap<String, Vec<String>>,
+    // lambda bodies are copied out of the program once, not every time the lambda is evaluated
+    bodies: HashMap<Span, Rc<Expression>>,
+    frames: Vec<(String, Span)>, // the rho functions being called and where they were called
+    native_call: Span, // the call of the builtin running now, functions it calls report errors here
+    input: &'a mut dyn BufRead,
+    output: &'a mut dyn Write,
+}
+
+/* Runs a resolved and checked program. The top level statements run in order, then `main` is
+ * called when the program defines one. The exit code is what `main` or a top level `return`
+ * returns, an int as it is and `:ok`/`:error` as 0 and 1, otherwise 0. A runtime error stops the
+ * program and is returned as a diagnostic pointing at the expression that failed.
+ */
+pub fn run(program: &[Expression], symbols: &SymbolTable) -> Result<i32, Diagnostic> {
+    let stdin = std::io::stdin();
+    let mut input = stdin.lock();
+    let mut output = std::io::stdout();
+    run_with(program, symbols, &mut input, &mut output)
+}
+
+pub fn run_with(
+    program: &[Expression],
+    symbols: &SymbolTable,
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+) -> Result<i32, Diagnostic> {
+    atoms::intern_program(program);
+    let mut interpreter = Interpreter::new(symbols, input, output);
+    interpreter.declare_items(program);
+    let result = interpreter.run_statements(program);
+    let _ = interpreter.output.flush();
+    result
+}
+
+/* Runs one `test` block of a program on an interpreter of its own, so no test sees what another
+ * one did. The top level statements run first as setup, for the bindings a test might use, and
+ * what they print is dropped so `output` only has the test's own. `main` doesn't run. The body
+ * runs in a frame named after the test, for stack traces to show which test failed.
+ */
+pub fn run_test(
+    program: &[Expression],
+    symbols: &SymbolTable,
+    test: &Expression,
+    output: &mut dyn Write,
+) -> Result<(), Diagnostic> {
+    atoms::intern_program(program);
+    let mut input = std::io::empty();
+    let mut setup = std::io::sink();
+    let mut interpreter = Interpreter::new(symbols, &mut input, &mut setup);
+    interpreter.declare_items(program);
+    let globals = interpreter.globals.clone();
+    for e in program.iter() {
+        if matches!(e, Expression::Function(_) | Expression::Test { .. }) {
+            continue;
+        }
+        match interpreter.eval(e, &globals) {
+            Err(Unwind::Error(d)) => return Err(d),
+            // a top level `return` ends the statements, the test still runs
+            Err(Unwind::Return(_)) => break,
+            _ => {}
+        }
+    }
+    interpreter.output = output;
+    let (name, body) = match test {
+        Expression::Test { name, body, .. } => (name.as_str(), body.as_ref()),
+        e => ("", e),
+    };
+    // the test is called by the runner, like `main`, so there's no top level below it
+    interpreter
+        .frames
+        .push((format!("test \"{}\"", name), Span::default()));
+    let result = interpreter.eval(body, &globals);
+    let _ = interpreter.output.flush();
+    match result {
+        Err(Unwind::Error(d)) if d.trace.is_empty() => {
+            let trace = interpreter.stack_trace(d.span);
+            Err(d.with_trace(trace))
+        }
+        Err(Unwind::Error(d)) => Err(d),
+        _ => Ok(()),
+    }
+}
+
+/* Runs the statements a REPL input added to the program, the ones from `start` on, with the
+ * bindings of earlier inputs in `globals`. Functions and structs are declared again since the
+ * input may add some. Returns the value of the last statement, for the REPL to show.
+ */
+pub fn run_input(
+    program: &[Expression],
+    symbols: &SymbolTable,
+    start: usize,
+    globals: &Env,
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+) -> Result<Value, Diagnostic> {
+    atoms::intern_program(program);
+    let mut interpreter = Interpreter::new(symbols, input, output);
+    interpreter.globals = globals.clone();
+    interpreter.declare_items(program);
+    let mut last = Value::Unit;
+    for e in program[start..].iter() {
+        if matches!(e, Expression::Function(_)) {
+            continue;
+        }
+        match interpreter.eval(e, globals) {
+            Ok(v) => last = v,
+            Err(Unwind::Return(v)) => {
+                last = v;
+                break;
+            }
+            Err(Unwind::Error(d)) => {
+                let _ = interpreter.output.flush();
+                return Err(d);
+            }
+            Err(Unwind::Break) | Err(Unwind::Continue) => {}
+        }
+    }
+    let _ = interpreter.output.flush();
+    Ok(last)
+}
+
+impl Context for Interpreter<'_> {
+    fn input(&mut self) -> &mut dyn BufRead {
+        &mut *self.input
+    }
+
+    fn output(&mut self) -> &mut dyn Write {
+        &mut *self.output
+    }
+
+    fn call(&mut self, function: &Value, args: Vec<Value>) -> Result<Value, Diagnostic> {
+        match Interpreter::call(self, function, args, self.native_call) {
+            Ok(v) => Ok(v),
+            Err(Unwind::Error(d)) => Err(d),
+            Err(_) => Ok(Value::Unit),
+        }
+    }
+}
+
+// The exit code a value returned from `main` stands for
+pub fn exit_code(value: &Value) -> i32 {
+    match value {
+        Value::Int(i) => *i as i32,
+        Value::Atom(atoms::ERROR) => 1,
+        Value::Tuple(elements) if elements.first() == Some(&Value::Atom(atoms::ERROR)) => 1,
+        _ => 0,
+    }
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(
+        symbols: &'a SymbolTable,
+        input: &'a mut dyn BufRead,
+        output: &'a mut dyn Write,
+    ) -> Interpreter<'a> {
+        Interpreter {
+            symbols,
+            globals: Scope::new(None),
+            functions: HashMap::new(),
+            struct_fields: HashMap::new(),
+            bodies: HashMap::new(),
+            frames: vec![],
+            native_call: Span::default(),
+            input,
+            output,
+        }
+    }
+
+    // Functions and structs are visible from anywhere in the program
+    pub fn declare_items(&mut self, program: &[Expression]) {
+        for e in program.iter() {
+            match e {
+                Expression::Function(decl) => {
+                    let f = self.closure(decl, self.globals.clone());
+                    self.functions.insert(decl.name.path(), f);
+                }
+                Expression::Struct(decl) => {
+                    let fields = decl.fields.iter().map(|(_, f)| f.name.to_owned()).collect();
+                    self.struct_fields.insert(decl.name.name.to_owned(), fields);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn run_statements(&mut self, program: &[Expression]) -> Result<i32, Diagnostic> {
+        let globals = self.globals.clone();
+        for e in program.iter() {
+            if matches!(e, Expression::Function(_)) {
+                continue;
+            }
+            match self.eval(e, &globals) {
+                Ok(_) => {}
+                // a `return` at the top level ends the program
+                Err(Unwind::Return(v)) => return Ok(exit_code(&v)),
+                Err(Unwind::Error(d)) => return Err(d),
+                Err(Unwind::Break) | Err(Unwind::Continue) => {}
+            }
+        }
+        let Some(main) = self.functions.get("main").cloned() else {
+            return Ok(0);
+        };
+        match self.call(&main, vec![], Span::default()) {
+            Ok(v) => Ok(exit_code(&v)),
+            Err(Unwind::Error(d)) => Err(d),
+            Err(_) => Ok(0),
+        }
+    }
+
+    fn closure(&self, decl: &FunctionDecl, env: Env) -> Value {
+        Value::Closure(Rc::new(Closure {
+            name: decl.name.path(),
+            params: decl.params.iter().map(|p| p.name.clone()).collect(),
+            body: Rc::new(decl.body.as_ref().clone()),
+            env,
+        }))
+    }
+
+    fn kind(&self, ident: &Ident) -> Option<SymbolKind> {
+        ident.symbol.map(|id| self.symbols.get(id).kind)
+    }
+
+    // Most expressions are evaluated by their own method, which keeps the stack frame of this
+    // recursive function small and lets deeply recursive programs run.
+    fn eval(&mut self, expression: &Expression, env: &Env) -> Evaluated {
+        match expression {
+            Expression::Literal { value, .. } => Ok(Value::from_literal(value)),
+            Expression::Definition {
+                identifier, value, ..
+            } => self.eval_definition(identifier, value, env),
+            Expression::Assignment { target, value, .. } => {
+                self.eval_assignment(target, value, env)
+            }
+            Expression::Identifier(ident) => self.identifier(ident, env),
+            Expression::Calculation {
+                left,
+                operator,
+                right,
+                span,
+            } => self.eval_calculation(left, *operator, right, *span, env),
+            Expression::Unary {
+                operator,
+                operand,
+                span,
+            } => self.eval_unary(*operator, operand, *span, env),
+            Expression::FunctionCall {
+                function_name,
+                parameters,
+                span,
+                ..
+            } => self.eval_call(function_name, parameters, *span, env),
+            Expression::FieldAccess { object, field, .. } => self.eval_field(object, field, env),
+            Expression::Index {
+                object,
+                index,
+                span,
+            } => self.eval_index(object, index, *span, env),
+            Expression::List { elements, .. } => self.eval_all(elements, env).map(Value::list),
+            Expression::Tuple { elements, .. } => self.eval_all(elements, env).map(Value::tuple),
+            Expression::Map { entries, .. } => self.eval_map(entries, env),
+            Expression::Range {
+                start,
+                end,
+                step,
+                span,
+            } => self.eval_range(start, end, step.as_deref(), *span, env),
+            Expression::StructLiteral { name, fields, .. } => self.eval_struct(name, fields, env),
+            Expression::Lambda { params, body, span } => self.eval_lambda(params, body, *span, env),
+            Expression::Block { expressions, .. } => {
+                let scope = Scope::new(Some(env.clone()));
+                self.eval_block(expressions, &scope)
+            }
+            Expression::If {
+                branches,
+                else_branch,
+                ..
+            } => self.eval_if(branches, else_branch.as_deref(), env),
+            Expression::While {
+                condition, body, ..
+            } => self.eval_while(condition, body, env),
+            Expression::For {
+                variable,
+                iterable,
+                body,
+                span,
+            } => self.eval_for(variable, iterable, body, *span, env),
+            Expression::Match {
+                subject,
+                arms,
+                span,
+            } => self.eval_match(subject, arms, *span, env),
+            Expression::Cond { arms, span } => self.eval_cond(arms, *span, env),
+            Expression::Return { value: Some(v), .. } => match self.eval(v, env) {
+                Ok(v) => Err(Unwind::Return(v)),
+                Err(e) => Err(e),
+            },
+            Expression::Return { value: None, .. } => Err(Unwind::Return(Value::Unit)),
+            Expression::Try { value, span } => self.eval_try(value, *span, env),
+            Expression::Panic { message, span } => {
+                let message = self.eval(message, env)?;
+                let d = error(&format!("panic: {}", message), *span);
+                Err(Unwind::Error(d.with_trace(self.stack_trace(*span))))
+            }
+            Expression::Assert {
+                condition,
+                message,
+                span,
+            } => self.eval_assert(condition, message.as_deref(), *span, env),
+            // tests are run one at a time by `run_test`, not as part of the program
+            Expression::Test { .. } => Ok(Value::Unit),
+            Expression::Break { .. } => Err(Unwind::Break),
+            Expression::Continue { .. } => Err(Unwind::Continue),
+            // nested functions are defined when their block is entered
+            Expression::Function(_)
+            | Expression::Import { .. }
+            | Expression::Struct(_)
+            | Expression::Enum(_)
+            | Expression::Interface(_) => Ok(Value::Unit),
+        }
+    }
+
+    fn eval_assignment(&mut self, target: &Expression, value: &Expression, env: &Env) -> Evaluated {
+        let v = self.eval(value, env)?;
+        self.assign_to(target, v, env)?;
+        Ok(Value::Unit)
+    }
+
+    fn eval_index(
+        &mut self,
+        object: &Expression,
+        index: &Expression,
+        span: Span,
+        env: &Env,
+    ) -> Evaluated {
+        let v = self.eval(object, env)?;
+        let i = self.eval(index, env)?;
+        Ok(get_index(&v, &i, span)?)
+    }
+
+    fn eval_definition(&mut self, identifier: &Ident, value: &Expression, env: &Env) -> Evaluated {
+        let v = self.eval(value, env)?;
+        if let Some(id) = identifier.symbol {
+            if self.symbols.declares(identifier) || !assign(env, id, v.clone()) {
+                define(env, id, v);
+            }
+        }
+        Ok(Value::Unit)
+    }
+
+    fn eval_calculation(
+        &mut self,
+        left: &Expression,
+        operator: Operators,
+        right: &Expression,
+        span: Span,
+        env: &Env,
+    ) -> Evaluated {
+        let l = self.eval(left, env)?;
+        // `&&` and `||` only evaluate their right side when they need it
+        match (operator, &l) {
+            (Operators::And, Value::Bool(false)) => return Ok(l),
+            (Operators::Or, Value::Bool(true)) => return Ok(l),
+            _ => {}
+        }
+        let r = self.eval(right, env)?;
+        Ok(binary(operator, l, r, span)?)
+    }
+
+    fn eval_unary(
+        &mut self,
+        operator: Operators,
+        operand: &Expression,
+        span: Span,
+        env: &Env,
+    ) -> Evaluated {
+        match (operator, self.eval(operand, env)?) {
+            (Operators::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
+            (Operators::Subtract, Value::Int(i)) => match i.checked_neg() {
+                Some(i) => Ok(Value::Int(i)),
+                None => Err(fail("integer overflow", span)),
+            },
+            (Operators::Subtract, Value::Float(f)) => Ok(Value::Float(-f)),
+            (op, v) => Err(fail(
+                &format!(
+                    "`{}` can't be applied to `{}`",
+                    operator_to_string(op),
+                    v.type_name()
+                ),
+                span,
+            )),
+        }
+    }
+
+    fn eval_field(&mut self, object: &Expression, field: &Ident, env: &Env) -> Evaluated {
+        if let Expression::Identifier(ident) = object {
+            if self.kind(ident) == Some(SymbolKind::Enum) {
+                return Ok(Value::Variant(
+                    Rc::from(ident.name.as_str()),
+                    Rc::from(field.name.as_str()),
+                    Rc::new(vec![]),
+                ));
+            }
+        }
+        let v = self.eval(object, env)?;
+        Ok(get_field(&v, &field.name, field.span)?)
+    }
+
+    // An error is returned from the function as it is, so its caller gets the same result
+    fn eval_try(&mut self, value: &Expression, span: Span, env: &Env) -> Evaluated {
+        let v = self.eval(value, env)?;
+        if let Value::Tuple(elements) = &v {
+            match elements.as_slice() {
+                [Value::Atom(atoms::OK), ok] => return Ok(ok.clone()),
+                [Value::Atom(atoms::ERROR), _] => return Err(Unwind::Return(v)),
+                _ => {}
+            }
+        }
+        Err(fail(
+            &format!(
+                "`?` needs an `{{:ok, value}}` or an `{{:error, reason}}`, found `{}`",
+                v
+            ),
+            span,
+        ))
+    }
+
+    fn eval_map(&mut self, entries: &[(Expression, Expression)], env: &Env) -> Evaluated {
+        let mut map = Map::new();
+        for (key, v) in entries.iter() {
+            let k = self.eval(key, env)?;
+            let v = self.eval(v, env)?;
+            if let Err(message) = map.insert(k, v) {
+                return Err(fail(&message, key.span()));
+            }
+        }
+        Ok(Value::Map(Rc::new(map)))
+    }
+
+    fn eval_range(
+        &mut self,
+        start: &Expression,
+        end: &Expression,
+        step: Option<&Expression>,
+        span: Span,
+        env: &Env,
+    ) -> Evaluated {
+        let start = self.eval_int(start, env)?;
+        let end = self.eval_int(end, env)?;
+        let step = match step {
+            Some(s) => self.eval_int(s, env)?,
+            None => 1,
+        };
+        if step == 0 {
+            return Err(fail("a range can't have a step of 0", span));
+        }
+        Ok(Value::Range(start, end, step))
+    }
+
+    fn eval_struct(
+        &mut self,
+        name: &Ident,
+        fields: &[(Ident, Expression)],
+        env: &Env,
+    ) -> Evaluated {
+        let mut values = HashMap::new();
+        for (field, value) in fields.iter() {
+            values.insert(field.name.to_owned(), self.eval(value, env)?);
+        }
+        let order = self
+            .struct_fields
+            .get(&name.name)
+            .cloned()
+            .unwrap_or_default();
+        let fields = order
+            .into_iter()
+            .filter_map(|f| values.remove(&f).map(|v| (f, v)))
+            .collect();
+        Ok(Value::Struct(Rc::from(name.name.as_str()), Rc::new(fields)))
+    }
+
+    fn eval_lambda(
+        &mut self,
+        params: &[Param],
+        body: &Expression,
+        span: Span,
+        env: &Env,
+    ) -> Evaluated {
+        let body = self
+            .bodies
+            .entry(span)
+            .or_insert_with(|| Rc::new(body.clone()))
+            .clone();
+        Ok(Value::Closure(Rc::new(Closure {
+            name: "fn".to_string(),
+            params: params.iter().map(|p| p.name.clone()).collect(),
+            body,
+            env: env.clone(),
+        })))
+    }
+
+    fn eval_if(
+        &mut self,
+        branches: &[(Expression, Expression)],
+        else_branch: Option<&Expression>,
+        env: &Env,
+    ) -> Evaluated {
+        for (condition, body) in branches.iter() {
+            if self.eval(condition, env)?.is_truthy() {
+                return self.eval(body, env);
+            }
+        }
+        match else_branch {
+            Some(e) => self.eval(e, env),
+            None => Ok(Value::Unit),
+        }
+    }
+
+    fn eval_while(&mut self, condition: &Expression, body: &Expression, env: &Env) -> Evaluated {
+        while self.eval(condition, env)?.is_truthy() {
+            match self.eval(body, env) {
+                Ok(_) | Err(Unwind::Continue) => {}
+                Err(Unwind::Break) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Value::Unit)
+    }
+
+    fn eval_match(
+        &mut self,
+        subject: &Expression,
+        arms: &[MatchArm],
+        span: Span,
+        env: &Env,
+    ) -> Evaluated {
+        let subject = self.eval(subject, env)?;
+        for arm in arms.iter() {
+            let scope = Scope::new(Some(env.clone()));
+            if !match_pattern(&arm.pattern, &subject, &scope) {
+                continue;
+            }
+            if let Some(guard) = &arm.guard {
+                if !self.eval(guard, &scope)?.is_truthy() {
+                    continue;
+                }
+            }
+            return self.eval(&arm.body, &scope);
+        }
+        Err(fail(&format!("no match arm matches `{}`", subject), span))
+    }
+
+    fn eval_cond(&mut self, arms: &[(Expression, Expression)], span: Span, env: &Env) -> Evaluated {
+        for (condition, body) in arms.iter() {
+            if self.eval(condition, env)?.is_truthy() {
+                return self.eval(body, env);
+            }
+        }
+        Err(fail("no `cond` condition is true", span))
+    }
+
+    fn eval_all(&mut self, expressions: &[Expression], env: &Env) -> Result<Vec<Value>, Unwind> {
+        expressions.iter().map(|e| self.eval(e, env)).collect()
+    }
+
+    fn eval_int(&mut self, expression: &Expression, env: &Env) -> Result<i64, Unwind> {
+        match self.eval(expression, env)? {
+            Value::Int(i) => Ok(i),
+            v => Err(fail(
+                &format!("expected an int, found `{}`", v.type_name()),
+                expression.span(),
+            )),
+        }
+    }
+
+    fn eval_block(&mut self, expressions: &[Expression], scope: &Env) -> Evaluated {
+        for e in expressions.iter() {
+            if let Expression::Function(decl) = e {
+                if let Some(id) = decl.name.idents[0].symbol {
+                    define(scope, id, self.closure(decl, scope.clone()));
+                }
+            }
+        }
+        let mut last = Value::Unit;
+        for e in expressions.iter() {
+            last = self.eval(e, scope)?;
+        }
+        Ok(last)
+    }
+
+    fn eval_for(
+        &mut self,
+        variable: &Ident,
+        iterable: &Expression,
+        body: &Expression,
+        span: Span,
+        env: &Env,
+    ) -> Evaluated {
+        let iterable = self.eval(iterable, env)?;
+        // a stream is pulled one element per pass through the body
+        let Some(mut cursor) = Cursor::of(&iterable) else {
+            return Err(fail(
+                &format!("can't iterate over `{}`", iterable.type_name()),
+                span,
+            ));
+        };
+        loop {
+            let outer = std::mem::replace(&mut self.native_call, span);
+            let pulled = cursor.next(self);
+            self.native_call = outer;
+            let element = match pulled {
+                Ok(Some(element)) => element,
+                Ok(None) => break,
+                Err(CallResult::Err(message)) => return Err(fail(&message, span)),
+                Err(CallResult::Raised(d)) => return Err(Unwind::Error(d)),
+                Err(CallResult::Ok(_)) => break,
+            };
+            let scope = Scope::new(Some(env.clone()));
+            if let Some(id) = variable.symbol {
+                define(&scope, id, element);
+            }
+            match self.eval(body, &scope) {
+                Ok(_) | Err(Unwind::Continue) => {}
+                Err(Unwind::Break) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Value::Unit)
+    }
+
+    fn identifier(&mut self, ident: &Ident, env: &Env) -> Evaluated {
+        let Some(id) = ident.symbol else {
+            return Err(fail(
+                &format!("undefined name `{}`", ident.name),
+                ident.span,
+            ));
+        };
+        if let Some(v) = lookup(env, id) {
+            return Ok(v);
+        }
+        let symbol = self.symbols.get(id);
+        match symbol.kind {
+            SymbolKind::Function => match self.functions.get(&symbol.name) {
+                Some(f) => Ok(f.clone()),
+                None => Err(fail(
+                    &format!("undefined function `{}`", ident.name),
+                    ident.span,
+                )),
+            },
+            SymbolKind::Builtin => Ok(builtin(&symbol.name)
+                .map(Value::Builtin)
+                .ok_or_else(|| error(&format!("unknown builtin `{}`", symbol.name), ident.span))?),
+            _ => Err(fail(
+                &format!("`{}` is used before it has a value", ident.name),
+                ident.span,
+            )),
+        }
+    }
+
+    fn eval_call(
+        &mut self,
+        function_name: &FunctionName,
+        parameters: &[Expression],
+        span: Span,
+        env: &Env,
+    ) -> Evaluated {
+        let path = function_name.path();
+        let first = &function_name.idents[0];
+        let args = self.eval_all(parameters, env)?;
+        match self.kind(first) {
+            // a closure held by a variable or a nested function, or by a field of a variable
+            Some(SymbolKind::Variable)
+            | Some(SymbolKind::Parameter)
+            | Some(SymbolKind::Function)
+                if first.symbol.and_then(|id| lookup(env, id)).is_some() =>
+            {
+                let mut f = lookup(env, first.symbol.unwrap()).unwrap();
+                for field in function_name.idents[1..].iter() {
+                    f = get_field(&f, &field.name, field.span)?;
+                }
+                self.call(&f, args, span)
+            }
+            // `T.show(x)` calls the `show` of the type `x` has when the program runs
+            Some(SymbolKind::TypeParam) => {
+                let method = function_name.idents[1..]
+                    .iter()
+                    .map(|i| i.name.to_owned())
+                    .collect::<Vec<String>>()
+                    .join(".");
+                let owner = args.first().map(|a| a.type_name()).unwrap_or_default();
+                let name = format!("{}.{}", owner, method);
+                match self.functions.get(&name).cloned() {
+                    Some(f) => self.call(&f, args, span),
+                    None => Err(fail(&format!("undefined function `{}`", name), span)),
+                }
+            }
+            Some(SymbolKind::Enum) => Ok(Value::Variant(
+                Rc::from(first.name.as_str()),
+                Rc::from(function_name.idents[1].name.as_str()),
+                Rc::new(args),
+            )),
+            _ => {
+                if let Some(f) = self.functions.get(&path).cloned() {
+                    return self.call(&f, args, span);
+                }
+                match builtin(&path) {
+                    Some(name) => Ok(self.call_builtin(name, args, span)?),
+                    None => Err(fail(&format!("undefined function `{}`", path), span)),
+                }
+            }
+        }
+    }
+
+    fn call(&mut self, f: &Value, args: Vec<Value>, span: Span) -> Evaluated {
+        let closure = match f {
+            Value::Closure(c) => c.clone(),
+            Value::Builtin(name) => return Ok(self.call_builtin(name, args, span)?),
+            v => {
+                return Err(fail(
+                    &format!("`{}` is not a function", v.type_name()),
+                    span,
+                ))
+            }
+        };
+        if args.len() != closure.params.len() {
+            return Err(fail(
+                &format!(
+                    "`{}` takes {} arguments but {} were given",
+                    closure.name,
+                    closure.params.len(),
+                    args.len()
+                ),
+                span,
+            ));
+        }
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return Err(fail(
+                &format!("stack overflow, more than {} nested calls", MAX_CALL_DEPTH),
+                span,
+            ));
+        }
+        let scope = Scope::new(Some(closure.env.clone()));
+        for (param, arg) in closure.params.iter().zip(args) {
+            if let Some(id) = param.symbol {
+                define(&scope, id, arg);
+            }
+        }
+        self.frames.push((closure.name.clone(), span));
+        let result = self.eval(&closure.body, &scope);
+        let result = match result {
+            Ok(v) | Err(Unwind::Return(v)) => Ok(v),
+            Err(Unwind::Break) | Err(Unwind::Continue) => Ok(Value::Unit),
+            // the innermost call an error passes through knows the whole stack
+            Err(Unwind::Error(d)) if d.trace.is_empty() => {
+                let trace = self.stack_trace(d.span);
+                Err(Unwind::Error(d.with_trace(trace)))
+            }
+            Err(e) => Err(e),
+        };
+        self.frames.pop();
+        result
+    }
+
+    fn stack_trace(&self, at: Span) -> Vec<(String, Span)> {
+        stack_trace(self.frames.iter().map(|(f, call)| (f.as_str(), *call)), at)
+    }
+
+    // A failed `assert a == b` shows both sides, ie. "assertion failed: `[1, 2] == [2, 1]`"
+    fn eval_assert(
+        &mut self,
+        condition: &Expression,
+        message: Option<&Expression>,
+        span: Span,
+        env: &Env,
+    ) -> Evaluated {
+        let (holds, compared) = match condition {
+            Expression::Calculation {
+                left,
+                operator:
+                    operator @ (Operators::BEq
+                    | Operators::BNEq
+                    | Operators::LessThan
+                    | Operators::GreaterThan
+                    | Operators::LEq
+                    | Operators::GEq),
+                right,
+                span,
+            } => {
+                let l = self.eval(left, env)?;
+                let r = self.eval(right, env)?;
+                let compared = format!(
+                    "`{} {} {}`",
+                    rho_core::inspect(&l),
+                    operator_to_string(*operator),
+                    rho_core::inspect(&r)
+                );
+                (binary(*operator, l, r, *span)?, Some(compared))
+            }
+            _ => (self.eval(condition, env)?, None),
+        };
+        if holds.is_truthy() {
+            return Ok(Value::Unit);
+        }
+        let mut text = "assertion failed".to_string();
+        if let Some(m) = message {
+            text += &format!(": {}", self.eval(m, env)?);
+        }
+        match compared {
+            Some(c) if message.is_some() => text += &format!(", {}", c),
+            Some(c) => text += &format!(": {}", c),
+            None => {}
+        }
+        Err(Unwind::Error(
+            error(&text, span).with_trace(self.stack_trace(span)),
+        ))
+    }
+
+    fn call_builtin(
+        &mut self,
+        name: &str,
+        args: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, Diagnostic> {
+        let Some(builtin) = rho_core::builtin(name) else {
+            return Err(error(&format!("unknown builtin `{}`", name), span));
+        };
+        let outer = std::mem::replace(&mut self.native_call, span);
+        let result = (builtin.function)(self, args);
+        self.native_call = outer;
+        match result {
+            CallResult::Ok(v) => Ok(v),
+            CallResult::Err(message) => Err(error(&message, span)),
+            CallResult::Raised(d) => Err(d),
+        }
+    }
+
+    // `p.x = v` and `xs[i] = v` replace part of the value bound to `p` or `xs`
+    fn assign_to(&mut self, target: &Expression, value: Value, env: &Env) -> Result<(), Unwind> {
+        let mut path = vec![];
+        let mut root = target;
+        loop {
+            match root {
+                Expression::FieldAccess { object, field, .. } => {
+                    path.push(Place::Field(field.name.to_owned(), field.span));
+                    root = object;
+                }
+                Expression::Index {
+                    object,
+                    index,
+                    span,
+                } => {
+                    path.push(Place::Index(self.eval(index, env)?, *span));
+                    root = object;
+                }
+                _ => break,
+            }
+        }
+        path.reverse();
+        let Expression::Identifier(ident) = root else {
+            return Err(fail("can't assign to a temporary value", target.span()));
+        };
+        let Some(id) = ident.symbol else {
+            return Err(fail(
+                &format!("undefined name `{}`", ident.name),
+                ident.span,
+            ));
+        };
+        // the binding is left empty while it is changed, so its collections aren't copied
+        let Some(mut current) = take(env, id) else {
+            return Err(fail(
+                &format!("`{}` is used before it has a value", ident.name),
+                ident.span,
+            ));
+        };
+        let result = set_in(&mut current, &path, value);
+        assign(env, id, current);
+        Ok(result?)
+    }
+}
+
+// The functions being called, innermost first, with the line each one is at. `frames` are the
+// calls being made, outermost first, with where each one was called.
+pub fn stack_trace<'a>(
+    frames: impl DoubleEndedIterator<Item = (&'a str, Span)>,
+    at: Span,
+) -> Vec<(String, Span)> {
+    let mut trace = vec![];
+    let mut line = at;
+    for (function, call) in frames.rev() {
+        trace.push((function.to_owned(), line));
+        line = call;
+    }
+    // `main` is called by the interpreter, everything else from somewhere in the program
+    if line != Span::default() {
+        trace.push(("the top level".to_string(), line));
+    }
+    if trace.len() > TRACE_FRAMES {
+        let hidden = trace.len() - TRACE_FRAMES;
+        trace.truncate(TRACE_FRAMES);
+        trace.push((format!("{} more calls", hidden), Span::default()));
+    }
+    trace
+}
+
+fn error(message: &str, span: Span) -> Diagnostic {
+    Diagnostic::error(message, span)
+}
+
+fn fail(message: &str, span: Span) -> Unwind {
+    Unwind::Error(error(message, span))
+}
+
+fn builtin(name: &str) -> Option<&'static str> {
+    rho_core::builtin(name).map(|b| b.name)
+}
+
+pub fn get_field(v: &Value, field: &str, span: Span) -> Result<Value, Diagnostic> {
+    if let Value::Struct(_, fields) = v {
+        if let Some((_, value)) = fields.iter().find(|(f, _)| f == field) {
+            return Ok(value.clone());
+        }
+    }
+    Err(error(
+        &format!("`{}` has no field `{}`", v.type_name(), field),
+        span,
+    ))
+}
+
+fn list_index(i: &Value, len: usize, span: Span) -> Result<usize, Diagnostic> {
+    match i {
+        Value::Int(i) if *i >= 0 && (*i as usize) < len => Ok(*i as usize),
+        Value::Int(i) => Err(error(
+            &format!("index {} is out of bounds for a length of {}", i, len),
+            span,
+        )),
+        v => Err(error(
+            &format!("an index must be an int, found `{}`", v.type_name()),
+            span,
+        )),
+    }
+}
+
+pub fn get_index(v: &Value, i: &Value, span: Span) -> Result<Value, Diagnostic> {
+    match v {
+        Value::List(elements) => Ok(elements[list_index(i, elements.len(), span)?].clone()),
+        Value::String(s) => {
+            let chars: Vec<char> = s.chars().collect();
+            Ok(Value::Char(chars[list_index(i, chars.len(), span)?]))
+        }
+        Value::Map(map) => match map.get(i) {
+            Some(value) => Ok(value.clone()),
+            None => Err(error(&format!("key `{}` is not in the map", i), span)),
+        },
+        v => Err(error(
+            &format!("`{}` can't be indexed", v.type_name()),
+            span,
+        )),
+    }
+}
+
+pub fn set_in(target: &mut Value, path: &[Place], value: Value) -> Result<(), Diagnostic> {
+    let Some((place, rest)) = path.split_first() else {
+        *target = value;
+        return Ok(());
+    };
+    let type_name = target.type_name();
+    let slot = match (target, place) {
+        (Value::Struct(_, fields), Place::Field(name, span)) => {
+            match Rc::make_mut(fields).iter_mut().find(|(f, _)| f == name) {
+                Some((_, v)) => v,
+                None => {
+                    return Err(error(
+                        &format!("`{}` has no field `{}`", type_name, name),
+                        *span,
+                    ))
+                }
+            }
+        }
+        (Value::List(elements), Place::Index(i, span)) => {
+            let i = list_index(i, elements.len(), *span)?;
+            &mut Rc::make_mut(elements)[i]
+        }
+        (Value::Map(map), Place::Index(key, span)) => {
+            match Rc::make_mut(map).get_or_insert(key.clone()) {
+                Ok(v) => v,
+                Err(message) => return Err(error(&message, *span)),
+            }
+        }
+        (_, Place::Field(name, span)) => {
+            return Err(error(
+                &format!("`{}` has no field `{}`", type_name, name),
+                *span,
+            ))
+        }
+        (_, Place::Index(_, span)) => {
+            return Err(error(
+                &format!("`{}` can't be assigned by index", type_name),
+                *span,
+            ))
+        }
+    };
+    set_in(slot, rest, value)
+}
+
+pub fn binary(operator: Operators, l: Value, r: Value, span: Span) -> Result<Value, Diagnostic> {
+    use Value::*;
+    let overflow = || error("integer overflow", span);
+    let value = match (operator, &l, &r) {
+        (Operators::Div | Operators::Modulo, Int(_), Int(0)) => {
+            return Err(error("division by zero", span))
+        }
+        (Operators::Add, Int(a), Int(b)) => Int(a.checked_add(*b).ok_or_else(overflow)?),
+        (Operators::Subtract, Int(a), Int(b)) => Int(a.checked_sub(*b).ok_or_else(overflow)?),
+        (Operators::Mult, Int(a), Int(b)) => Int(a.checked_mul(*b).ok_or_else(overflow)?),
+        (Operators::Div, Int(a), Int(b)) => Int(a.checked_div(*b).ok_or_else(overflow)?),
+        (Operators::Modulo, Int(a), Int(b)) => Int(a.checked_rem(*b).ok_or_else(overflow)?),
+        (Operators::Exp, Int(a), Int(b)) => match u32::try_from(*b) {
+            Ok(b) => Int(a.checked_pow(b).ok_or_else(overflow)?),
+            Err(_) => return Err(error("an int can't be raised to a negative power", span)),
+        },
+        (Operators::Lshift, Int(a), Int(b)) => Int(u32::try_from(*b)
+            .ok()
+            .and_then(|b| a.checked_shl(b))
+            .ok_or_else(overflow)?),
+        (Operators::Rshift, Int(a), Int(b)) => Int(u32::try_from(*b)
+            .ok()
+            .and_then(|b| a.checked_shr(b))
+            .ok_or_else(overflow)?),
+        (Operators::Add, Float(a), Float(b)) => Float(a + b),
+        (Operators::Subtract, Float(a), Float(b)) => Float(a - b),
+        (Operators::Mult, Float(a), Float(b)) => Float(a * b),
+        (Operators::Div, Float(a), Float(b)) => Float(a / b),
+        (Operators::Modulo, Float(a), Float(b)) => Float(a % b),
+        (Operators::Exp, Float(a), Float(b)) => Float(a.powf(*b)),
+        (Operators::Concat, String(a), String(b)) => Value::string(&format!("{}{}", a, b)),
+        (Operators::EnumConcat, List(a), List(b)) => {
+            let mut elements = (**a).clone();
+            elements.extend(b.iter().cloned());
+            Value::list(elements)
+        }
+        (Operators::BEq, _, _) => Bool(l == r),
+        (Operators::BNEq, _, _) => Bool(l != r),
+        (Operators::And, Bool(a), Bool(b)) => Bool(*a && *b),
+        (Operators::Or, Bool(a), Bool(b)) => Bool(*a || *b),
+        (Operators::LessThan | Operators::GreaterThan | Operators::LEq | Operators::GEq, _, _) => {
+            let ordering = l.compare(&r);
+            let Some(ordering) = ordering else {
+                return Err(error(
+                    &format!(
+                        "`{}` can't compare `{}` and `{}`",
+                        operator_to_string(operator),
+                        l.type_name(),
+                        r.type_name()
+                    ),
+                    span,
+                ));
+            };
+            Bool(match operator {
+                Operators::LessThan => ordering.is_lt(),
+                Operators::GreaterThan => ordering.is_gt(),
+                Operators::LEq => ordering.is_le(),
+                _ => ordering.is_ge(),
+            })
+        }
+        _ => {
+            return Err(error(
+                &format!(
+                    "`{}` can't be applied to `{}` and `{}`",
+                    operator_to_string(operator),
+                    l.type_name(),
+                    r.type_name()
+                ),
+                span,
+            ))
+        }
+    };
+    Ok(value)
+}
+
+// Binds the names in `pattern` into `scope` when `value` matches it
+fn match_pattern(pattern: &Pattern, value: &Value, scope: &Env) -> bool {
+    match (pattern, value) {
+        (Pattern::Wildcard(_), _) => true,
+        (Pattern::Binding(ident), _) => {
+            if let Some(id) = ident.symbol {
+                define(scope, id, value.clone());
+            }
+            true
+        }
+        (Pattern::Literal(literal, _), _) => Value::from_literal(literal) == *value,
+        (Pattern::Tuple(patterns, _), Value::Tuple(values))
+        | (Pattern::List(patterns, _), Value::List(values)) => {
+            patterns.len() == values.len()
+                && patterns
+                    .iter()
+                    .zip(values.iter())
+                    .all(|(p, v)| match_pattern(p, v, scope))
+        }
+        (Pattern::Variant(name, patterns, _), Value::Variant(enum_name, variant, values)) => {
+            name.idents[0].name == **enum_name
+                && name.idents.get(1).is_some_and(|v| v.name == **variant)
+                && patterns.len() == values.len()
+                && patterns
+                    .iter()
+                    .zip(values.iter())
+                    .all(|(p, v)| match_pattern(p, v, scope))
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+pub fn run_source(source: &str, input: &str) -> (Result<i32, Diagnostic>, String) {
+    let mut program = parse(source).unwrap();
+    let (mut symbols, diagnostics) = crate::resolver::resolve(&mut program);
+    let diagnostics: Vec<Diagnostic> = diagnostics
+        .into_iter()
+        .chain(crate::checker::check(&program, &mut symbols))
+        .filter(|d| d.is_error())
+        .collect();
+    assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+    let mut input = input.as_bytes();
+    let mut output = vec![];
+    let result = run_with(&program, &symbols, &mut input, &mut output);
+    (result, String::from_utf8(output).unwrap())
+}
+
+/* hello.rho and scratch.rho predate the checker and don't compile anymore, see
+ * `test_resolve_testfiles`. hello_var.rho and scratch_typed.rho are them as rho is written now.
+ */
+#[test]
+fn test_run_testfiles() {
+    let source = std::fs::read_to_string("./rho_testfiles/hello_var.rho").unwrap();
+    assert_eq!(run_source(&source, ""), (Ok(0), "rho\n".to_string()));
+
+    let source = std::fs::read_to_string("./rho_testfiles/scratch_typed.rho").unwrap();
+    assert_eq!(run_source(&source, ""), (Ok(0), "rho_is_cool".to_string()));
+
+    let source = std::fs::read_to_string("./rho_testfiles/generics.rho").unwrap();
+    assert_eq!(run_source(&source, "").0, Ok(0));
+}
+
+#[test]
+fn test_run_functions_and_closures() {
+    let (result, output) = run_source(
+        "func fib(int n) -> int {
+    if n < 2 {
+        return n
+    }
+    return fib(n - 1) + fib(n - 2)
+}
+func counter() -> fn() -> int {
+    var int count = 0
+    return fn () -> {
+        count = count + 1
+        return count
+    }
+}
+next = counter()
+next()
+IO.puts(next())
+IO.puts(fib(15))
+add = fn a, b -> a + b
+IO.puts([1, 2, 3] |> map(fn x -> add(x, 10)))
+func map(list[int] xs, fn(int) -> int f) -> list[int] {
+    var out = []
+    for x in xs {
+        out = out ++ [f(x)]
+    }
+    return out
+}
+func main() -> int {
+    return 3
+}
+",
+        "",
+    );
+    assert_eq!(result, Ok(3));
+    assert_eq!(output, "2\n610\n[11, 12, 13]\n");
+}
+
+// Closures make cycles through the scopes they were defined in, `gc::collect` has to free them all
+#[test]
+fn test_run_leaks() {
+    let before = crate::gc::live_scopes();
+    let (result, output) = run_source(
+        "func counter() -> fn() -> int {
+    var int count = 0
+    next = fn () -> {
+        count = count + 1
+        return count
+    }
+    return next
+}
+add = fn a, b -> a + b
+var total = 0
+for i in range 0..5000 {
+    next = counter()
+    next()
+    total = add(total, next())
+}
+IO.puts(total)
+",
+        "",
+    );
+    assert_eq!(result, Ok(0));
+    assert_eq!(output, "10000\n");
+    crate::gc::collect();
+    assert_eq!(crate::gc::live_scopes(), before);
+}
+
+#[test]
+fn test_run_control_flow() {
+    let (result, output) = run_source(
+        "enum Shape {
+    Circle(float),
+    Square(float)
+}
+struct Point {
+    int x
+    int y
+}
+var total = 0
+for i in 0..10 {
+    if i == 7 {
+        break
+    } elif i % 2 == 0 {
+        continue
+    }
+    total = total + i
+}
+IO.puts(total)
+var Point p = Point{y: 2, x: 1}
+p.x = 5
+var grid = [[0, 0], [0, 0]]
+grid[1][0] = p.x
+IO.puts(grid)
+shapes = [Shape.Circle(1.0), Shape.Square(2.0)]
+for s in shapes {
+    area = match s {
+        Shape.Circle(r) -> 3.0 * r * r
+        Shape.Square(side) when side > 1.0 -> side * side
+        _ -> 0.0
+    }
+    IO.puts(area)
+}
+name = IO.read_line()
+IO.puts(\"hi \" <> name)
+return int(4.5)
+",
+        "rho\n",
+    );
+    assert_eq!(result, Ok(4));
+    assert_eq!(output, "9\n[[0, 0], [5, 0]]\n3.0\n4.0\nhi rho\n");
+}
+
+#[test]
+fn test_run_errors() {
+    let (result, _) = run_source("xs = [1, 2]\nx = xs[2]\n", "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "error: index 2 is out of bounds for a length of 2 at 2:5"
+    );
+    let (result, _) = run_source("x = 1 / (1 - 1)\n", "");
+    assert_eq!(result.unwrap_err().message, "division by zero");
+    // as deep as the interpreter goes, on a stack as large as the one `main` gives it
+    let deep = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(|| run_source("func f(int n) -> int {\n    return f(n + 1)\n}\nf(0)\n", "").0)
+        .unwrap();
+    let result = deep.join().unwrap();
+    assert!(result.unwrap_err().message.starts_with("stack overflow"));
+}
+
+#[test]
+fn test_run_results() {
+    let source = "func add(str a, str b) -> result[int, str] {
+    x = String.to_int(a)?
+    y = String.to_int(b)?
+    return {:ok, x + y}
+}
+func describe(str a, str b) -> str {
+    match add(a, b) {
+        {:ok, n} -> return \"sum \" <> str(n)
+        {:error, reason} -> return reason
+    }
+}
+IO.puts(describe(\"1\", \"2\"))
+IO.puts(describe(\"1\", \"x\"))
+n = add(\"4\", \"y\")?
+IO.puts(\"not reached\")
+";
+    let (result, output) = run_source(source, "");
+    assert_eq!(result, Ok(1));
+    assert_eq!(output, "sum 3\n`x` is not an int\n");
+}
+
+#[test]
+fn test_run_panic_and_assert() {
+    let source = "func inner(int n) -> int {
+    if n > 1 {
+        panic \"too deep: \" <> str(n)
+    }
+    return inner(n + 1)
+}
+assert 1 + 1 == 2
+inner(0)
+";
+    let d = run_source(source, "").0.unwrap_err();
+    assert_eq!(d.to_string(), "error: panic: too deep: 2 at 3:9");
+    let trace: Vec<(&str, usize)> = d.trace.iter().map(|(f, s)| (f.as_str(), s.line)).collect();
+    assert_eq!(
+        trace,
+        [
+            ("inner", 3),
+            ("inner", 5),
+            ("inner", 5),
+            ("the top level", 8)
+        ]
+    );
+
+    let (result, _) = run_source("xs = [1, 2]\nassert xs == [2, 1]\n", "");
+    assert_eq!(
+        result.unwrap_err().message,
+        "assertion failed: `[1, 2] == [2, 1]`"
+    );
+    let (result, _) = run_source("s = \"a\"\nassert s != \"a\", \"not a\"\n", "");
+    assert_eq!(
+        result.unwrap_err().message,
+        "assertion failed: not a, `\"a\" != \"a\"`"
+    );
+    let (result, _) = run_source("assert List.any([1], fn x -> x > 1)\n", "");
+    assert_eq!(result.unwrap_err().message, "assertion failed");
 
+    // a deep recursion shows its innermost calls
+    let deep = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(|| run_source("func f(int n) -> int {\n    return f(n + 1)\n}\nf(0)\n", "").0)
+        .unwrap();
+    let d = deep.join().unwrap().unwrap_err();
+    assert_eq!(d.trace.len(), TRACE_FRAMES + 1);
+    assert_eq!(d.trace[TRACE_FRAMES].0, "9981 more calls");
+}
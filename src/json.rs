@@ -0,0 +1,510 @@
+use core::fmt;
+
+use crate::parsers::*;
+use crate::tokens::*;
+use crate::types::Type;
+
+/* A JSON value, for the output of the CLI that other tools read. The crate has no serde, tokens
+ * and the syntax tree are written out by `token` and `expression` below, to the schema there.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(String), // as written, so large ints stay exact
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>), // in insertion order
+}
+
+impl Json {
+    pub fn string(s: &str) -> Json {
+        Json::String(s.to_string())
+    }
+
+    pub fn number(n: impl fmt::Display) -> Json {
+        Json::Number(n.to_string())
+    }
+
+    pub fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(
+            fields
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+        )
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(s) => write_string(f, s),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (i, v) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, k)?;
+                    write!(f, ":{}", v)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/* The schema of `rho tokens --json` and `rho ast --json`:
+ *
+ * - a span is flattened into the object it locates, as "line", "col" and "len"
+ * - a token is {"type", "text", line, col, len}, "type" is one of Identifier, Comment, NewLine,
+ *   EoF, Keyword, Operator, Delimiter, Type or Literal and the last five say which in "kind",
+ *   ie. {"type":"Keyword","kind":"Func","text":"func","line":1,"col":1,"len":4}
+ * - a node of the syntax tree has its variant of `Expression` in "node", its fields under the
+ *   parser's names for them, and its span. A missing optional node is null
+ * - an identifier is {"name", line, col, len}, a function's dotted name is a string
+ * - a type is written the way rho writes it, ie. "list[int]", and null where it's inferred
+ * - an operator is its name in `Operators`, ie. "Add", and a definition's mutability its name in
+ *   `Mutability`, ie. "Const"
+ * - a literal's "type" is int, float, bool, char, str or atom and "value" holds it, floats JSON
+ *   has no number for (inf and NaN) are strings
+ * - a pattern has its variant of `Pattern` in "pattern", like a node
+ */
+pub fn span(span: Span) -> Vec<(&'static str, Json)> {
+    vec![
+        ("line", Json::number(span.line)),
+        ("col", Json::number(span.col)),
+        ("len", Json::number(span.len)),
+    ]
+}
+
+fn located(mut fields: Vec<(&str, Json)>, at: Span) -> Json {
+    fields.extend(span(at));
+    Json::object(fields)
+}
+
+// The name of a variant of an enum without fields, ie. "Func"
+fn variant(value: impl fmt::Debug) -> Json {
+    Json::string(&format!("{:?}", value))
+}
+
+pub fn token(token: &Token) -> Json {
+    let (name, kind) = match token.token_type {
+        TokenType::Type(Types::Primitive(t)) => ("Type", Some(variant(t))),
+        TokenType::Type(Types::BuiltIn(t)) => ("Type", Some(variant(t))),
+        TokenType::Literal(Literals::Primitive(t)) => ("Literal", Some(variant(t))),
+        TokenType::Literal(Literals::BuiltIn(t)) => ("Literal", Some(variant(t))),
+        TokenType::Keyword(k) => ("Keyword", Some(variant(k))),
+        TokenType::Operator(o) => ("Operator", Some(variant(o))),
+        TokenType::Delimiter(d) => ("Delimiter", Some(variant(d))),
+        TokenType::EoF => ("EoF", None),
+        TokenType::Identifier => ("Identifier", None),
+        TokenType::Comment => ("Comment", None),
+        TokenType::NewLine => ("NewLine", None),
+    };
+    let mut fields = vec![("type", Json::string(name))];
+    fields.extend(kind.map(|k| ("kind", k)));
+    fields.push(("text", Json::string(token.string)));
+    located(fields, token.span)
+}
+
+fn node(name: &str, fields: Vec<(&str, Json)>, at: Span) -> Json {
+    let mut all = vec![("node", Json::string(name))];
+    all.extend(fields);
+    located(all, at)
+}
+
+fn expressions<'e>(es: impl IntoIterator<Item = &'e Expression>) -> Json {
+    Json::Array(es.into_iter().map(expression).collect())
+}
+
+fn optional(e: Option<&Expression>) -> Json {
+    e.map(expression).unwrap_or(Json::Null)
+}
+
+// Pairs of nodes, ie. the conditions and bodies of an `if`
+fn pairs(ps: &[(Expression, Expression)], first: &str, second: &str) -> Json {
+    let ps = ps
+        .iter()
+        .map(|(a, b)| Json::object(vec![(first, expression(a)), (second, expression(b))]));
+    Json::Array(ps.collect())
+}
+
+fn ident(i: &Ident) -> Json {
+    located(vec![("name", Json::string(&i.name))], i.span)
+}
+
+fn type_of(t: Option<&Type>) -> Json {
+    t.map(|t| Json::string(&t.to_string()))
+        .unwrap_or(Json::Null)
+}
+
+fn types(ts: &[Type]) -> Json {
+    Json::Array(ts.iter().map(|t| type_of(Some(t))).collect())
+}
+
+fn params(ps: &[Param]) -> Json {
+    let ps = ps.iter().map(|p| {
+        Json::object(vec![
+            ("name", ident(&p.name)),
+            ("type", type_of(p.param_type.as_ref())),
+        ])
+    });
+    Json::Array(ps.collect())
+}
+
+fn type_params(ps: &[TypeParam]) -> Json {
+    let ps = ps.iter().map(|p| {
+        Json::object(vec![
+            ("name", Json::string(&p.name)),
+            ("bounds", types(&p.bounds)),
+        ])
+    });
+    Json::Array(ps.collect())
+}
+
+fn literal(value: &TokenValue) -> Vec<(&'static str, Json)> {
+    let (name, value) = match value {
+        TokenValue::Int(n) => ("int", Json::number(n)),
+        TokenValue::Float(n) if n.is_finite() => ("float", Json::number(format!("{:?}", n))),
+        TokenValue::Float(n) => ("float", Json::string(&n.to_string())),
+        TokenValue::Bool(b) => ("bool", Json::Bool(*b)),
+        TokenValue::Char(c) => ("char", Json::string(&c.to_string())),
+        TokenValue::String(s) => ("str", Json::string(s)),
+        TokenValue::Atom(a) => ("atom", Json::string(a)),
+    };
+    vec![("type", Json::string(name)), ("value", value)]
+}
+
+fn pattern(p: &Pattern) -> Json {
+    let (name, fields) = match p {
+        Pattern::Wildcard(_) => ("Wildcard", vec![]),
+        Pattern::Binding(i) => ("Binding", vec![("name", Json::string(&i.name))]),
+        Pattern::Literal(value, _) => ("Literal", literal(value)),
+        Pattern::Tuple(ps, _) => ("Tuple", vec![("elements", patterns(ps))]),
+        Pattern::List(ps, _) => ("List", vec![("elements", patterns(ps))]),
+        Pattern::Variant(name, ps, _) => (
+            "Variant",
+            vec![
+                ("name", Json::string(&name.path())),
+                ("fields", patterns(ps)),
+            ],
+        ),
+    };
+    let mut all = vec![("pattern", Json::string(name))];
+    all.extend(fields);
+    located(all, p.span())
+}
+
+fn patterns(ps: &[Pattern]) -> Json {
+    Json::Array(ps.iter().map(pattern).collect())
+}
+
+fn function(decl: &FunctionDecl) -> Json {
+    let fields = vec![
+        ("name", Json::string(&decl.name.path())),
+        ("type_params", type_params(&decl.type_params)),
+        ("params", params(&decl.params)),
+        ("return_type", type_of(decl.return_type.as_ref())),
+        ("body", expression(&decl.body)),
+    ];
+    node("Function", fields, decl.span)
+}
+
+pub fn expression(e: &Expression) -> Json {
+    let (name, fields) = match e {
+        Expression::Definition {
+            mutability,
+            definition_type,
+            identifier,
+            value,
+            ..
+        } => (
+            "Definition",
+            vec![
+                ("mutability", variant(mutability)),
+                ("definition_type", type_of(definition_type.as_ref())),
+                ("identifier", ident(identifier)),
+                ("value", expression(value)),
+            ],
+        ),
+        Expression::Assignment { target, value, .. } => (
+            "Assignment",
+            vec![("target", expression(target)), ("value", expression(value))],
+        ),
+        Expression::Calculation {
+            left,
+            operator,
+            right,
+            ..
+        } => (
+            "Calculation",
+            vec![
+                ("left", expression(left)),
+                ("operator", variant(operator)),
+                ("right", expression(right)),
+            ],
+        ),
+        Expression::Unary {
+            operator, operand, ..
+        } => (
+            "Unary",
+            vec![
+                ("operator", variant(operator)),
+                ("operand", expression(operand)),
+            ],
+        ),
+        Expression::FunctionCall {
+            function_name,
+            type_args,
+            parameters,
+            ..
+        } => (
+            "FunctionCall",
+            vec![
+                ("function_name", Json::string(&function_name.path())),
+                ("type_args", types(type_args)),
+                ("parameters", expressions(parameters)),
+            ],
+        ),
+        Expression::Literal { value, .. } => ("Literal", literal(value)),
+        Expression::Identifier(i) => ("Identifier", vec![("name", Json::string(&i.name))]),
+        Expression::FieldAccess { object, field, .. } => (
+            "FieldAccess",
+            vec![("object", expression(object)), ("field", ident(field))],
+        ),
+        Expression::Index { object, index, .. } => (
+            "Index",
+            vec![("object", expression(object)), ("index", expression(index))],
+        ),
+        Expression::List { elements, .. } => ("List", vec![("elements", expressions(elements))]),
+        Expression::Tuple { elements, .. } => ("Tuple", vec![("elements", expressions(elements))]),
+        Expression::Map { entries, .. } => {
+            ("Map", vec![("entries", pairs(entries, "key", "value"))])
+        }
+        Expression::Range {
+            start, end, step, ..
+        } => (
+            "Range",
+            vec![
+                ("start", expression(start)),
+                ("end", expression(end)),
+                ("step", optional(step.as_deref())),
+            ],
+        ),
+        Expression::StructLiteral {
+            name,
+            type_args,
+            fields,
+            ..
+        } => {
+            let fields = fields.iter().map(|(field, value)| {
+                Json::object(vec![("name", ident(field)), ("value", expression(value))])
+            });
+            (
+                "StructLiteral",
+                vec![
+                    ("name", ident(name)),
+                    ("type_args", types(type_args)),
+                    ("fields", Json::Array(fields.collect())),
+                ],
+            )
+        }
+        Expression::Lambda {
+            params: ps, body, ..
+        } => (
+            "Lambda",
+            vec![("params", params(ps)), ("body", expression(body))],
+        ),
+        Expression::Block {
+            expressions: es, ..
+        } => ("Block", vec![("expressions", expressions(es))]),
+        Expression::If {
+            branches,
+            else_branch,
+            ..
+        } => (
+            "If",
+            vec![
+                ("branches", pairs(branches, "condition", "body")),
+                ("else_branch", optional(else_branch.as_deref())),
+            ],
+        ),
+        Expression::While {
+            condition, body, ..
+        } => (
+            "While",
+            vec![
+                ("condition", expression(condition)),
+                ("body", expression(body)),
+            ],
+        ),
+        Expression::For {
+            variable,
+            iterable,
+            body,
+            ..
+        } => (
+            "For",
+            vec![
+                ("variable", ident(variable)),
+                ("iterable", expression(iterable)),
+                ("body", expression(body)),
+            ],
+        ),
+        Expression::Match { subject, arms, .. } => {
+            let arms = arms.iter().map(|arm| {
+                Json::object(vec![
+                    ("pattern", pattern(&arm.pattern)),
+                    ("guard", optional(arm.guard.as_ref())),
+                    ("body", expression(&arm.body)),
+                ])
+            });
+            (
+                "Match",
+                vec![
+                    ("subject", expression(subject)),
+                    ("arms", Json::Array(arms.collect())),
+                ],
+            )
+        }
+        Expression::Cond { arms, .. } => ("Cond", vec![("arms", pairs(arms, "condition", "body"))]),
+        Expression::Return { value, .. } => ("Return", vec![("value", optional(value.as_deref()))]),
+        Expression::Try { value, .. } => ("Try", vec![("value", expression(value))]),
+        Expression::Panic { message, .. } => ("Panic", vec![("message", expression(message))]),
+        Expression::Assert {
+            condition, message, ..
+        } => (
+            "Assert",
+            vec![
+                ("condition", expression(condition)),
+                ("message", optional(message.as_deref())),
+            ],
+        ),
+        Expression::Test { name, body, .. } => (
+            "Test",
+            vec![("name", Json::string(name)), ("body", expression(body))],
+        ),
+        Expression::Break { .. } => ("Break", vec![]),
+        Expression::Continue { .. } => ("Continue", vec![]),
+        Expression::Import { path, .. } => ("Import", vec![("path", Json::string(path))]),
+        Expression::Function(decl) => return function(decl),
+        Expression::Struct(decl) => {
+            let fields = decl.fields.iter().map(|(t, name)| {
+                Json::object(vec![("name", ident(name)), ("type", type_of(Some(t)))])
+            });
+            (
+                "Struct",
+                vec![
+                    ("name", ident(&decl.name)),
+                    ("type_params", type_params(&decl.type_params)),
+                    ("fields", Json::Array(fields.collect())),
+                ],
+            )
+        }
+        Expression::Enum(decl) => {
+            let variants = decl.variants.iter().map(|v| {
+                Json::object(vec![("name", ident(&v.name)), ("fields", types(&v.fields))])
+            });
+            (
+                "Enum",
+                vec![
+                    ("name", ident(&decl.name)),
+                    ("type_params", type_params(&decl.type_params)),
+                    ("variants", Json::Array(variants.collect())),
+                ],
+            )
+        }
+        Expression::Interface(decl) => {
+            let methods = decl.methods.iter().map(|m| {
+                Json::object(vec![
+                    ("name", ident(&m.name)),
+                    ("params", params(&m.params)),
+                    ("return_type", type_of(m.return_type.as_ref())),
+                ])
+            });
+            (
+                "Interface",
+                vec![
+                    ("name", ident(&decl.name)),
+                    ("type_params", type_params(&decl.type_params)),
+                    ("methods", Json::Array(methods.collect())),
+                ],
+            )
+        }
+    };
+    node(name, fields, e.span())
+}
+
+#[test]
+fn test_json_display() {
+    let json = Json::object(vec![
+        ("name", Json::string("a \"b\"\n")),
+        (
+            "values",
+            Json::Array(vec![Json::number(1), Json::Null, Json::Bool(true)]),
+        ),
+    ]);
+    assert_eq!(
+        json.to_string(),
+        r#"{"name":"a \"b\"\n","values":[1,null,true]}"#
+    );
+}
+
+#[test]
+fn test_syntax_tree_json() {
+    let tokens =
+        crate::tokens::tokenize("func f(int x) -> int {\n    return x + 1.5\n}\n").unwrap();
+    assert_eq!(
+        token(&tokens[0]).to_string(),
+        r#"{"type":"Keyword","kind":"Func","text":"func","line":1,"col":1,"len":4}"#
+    );
+    let expressions = crate::parsers::expressionize(&tokens).unwrap();
+    assert_eq!(
+        expression(&expressions[0]).to_string(),
+        concat!(
+            r#"{"node":"Function","name":"f","type_params":[],"#,
+            r#""params":[{"name":{"name":"x","line":1,"col":12,"len":1},"type":"int"}],"#,
+            r#""return_type":"int","body":{"node":"Block","expressions":[{"node":"Return","#,
+            r#""value":{"node":"Calculation","left":{"node":"Identifier","name":"x","#,
+            r#""line":2,"col":12,"len":1},"operator":"Add","right":{"node":"Literal","#,
+            r#""type":"float","value":1.5,"line":2,"col":16,"len":3},"line":2,"col":14,"len":1},"#,
+            r#""line":2,"col":5,"len":6}],"line":1,"col":22,"len":1},"line":1,"col":1,"len":4}"#
+        )
+    );
+    let atom = crate::tokens::tokenize("match :ok { :ok -> 1 }\n").unwrap();
+    let expressions = crate::parsers::expressionize(&atom).unwrap();
+    assert!(expression(&expressions[0])
+        .to_string()
+        .contains(r#""pattern":{"pattern":"Literal","type":"atom","value":"ok""#));
+}
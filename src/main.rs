@@ -11,110 +11,81 @@ use std::fmt::Debug;
 use std::fs;
 use std::string;
 
+mod atoms;
+mod bytecode;
+mod checker;
+mod cli;
+mod codegen;
+mod collections;
+mod consts;
+mod diagnostics;
+mod gc;
+mod generics;
+mod interpreter;
+mod json;
+mod maps;
+mod mir;
+mod mutability;
+mod native;
 mod parsers;
+mod passes;
+mod repl;
+mod resolver;
 mod rho_core;
-mod sim;
+mod streams;
+mod strings;
+mod testing;
 mod tokens;
+mod types;
+mod value;
+mod vm;
+use crate::diagnostics::Diagnostic;
 use crate::parsers::*;
-use crate::sim::*;
+use crate::resolver::SymbolTable;
 use crate::tokens::*;
 
-fn main() {
-    // let args: Vec<String> = env::args().collect();
-
-    // let file_path = &args[1];
-
-    // println!("In file {}", file_path);
-
-    // let file_path = "./rho_testfiles/scratch.rho";
-    let file_path = "./rho_testfiles/hello.rho";
-    let contents = fs::read_to_string(file_path).expect("Should have been able to read the file");
-    // let contents: &str = &fs::read_to_string(file_path).expect("Should have been able to read the file");fs::read_to_string(file_path).expect("Should have been able to read the file");
-    // println!("{:?}", "abc".split_at(10));
-
-    // let contents = "str s = \"rho_is_cool\"\n";
-    //     let contents = "func main() {
-
-    //     return 0
-    //     // alternatively: return :ok
-    // }
-
-    // l = fn x -> {
-    //     return x + 1
-    // }
-
-    // 1..1
-    // 10..1.
-    // 1.0 1.0
-    // 1.0.0
-    // 2345 asdf";
-
-    // // println!("{:?}", contents.split_once(|c: char| c.is_whitespace()));
-    // println!("{:?}", contents);
-
-    // -------------
-    let tokens = tokenize(contents.as_str());
-    println!(
-        "tokenize output: {}",
-        tokens
-            .iter()
-            .map(|t| { format!("{:?}", t) })
-            .reduce(|acc, tos| acc + "\n" + &tos)
-            .unwrap()
-    );
-
-    let tokens2: Vec<&Token<'_>> = tokens.iter().collect();
-
-    let expressions = expressionize(tokens2);
-    // println!("expressions: {:?}", expressions);
-    println!(
-        "\n--\nexpressions: {}",
-        expressions
-            .iter()
-            .map(|t| { format!("{}", t) })
-            .reduce(|acc, tos| acc + "\n" + &tos)
-            .unwrap()
-    );
-
-    //  -------------------
-
-    // let s = "\"asdfklsdjfa\\\"\" asdf";
-    // println!("{:?}", parse_literal(s))
-    // while true {
-    //     s.find("\n")
-    // }
-
-    // println!("{:?}: {:?}\n", "1..1", parse_literal("1..1"));
-    // println!("{:?}: {:?}\n", "10..1.", parse_literal("10..1."));
-    // println!("{:?}: {:?}\n", "-1.0 1.0", parse_literal("-1.0 1.0"));
-    // println!("{:?}: {:?}\n", "1 ", parse_literal("1 "));
-    // println!("{:?}: {:?}\n", "1", parse_literal("-1 "));
-    // println!("{:?}: {:?}\n", "-1", parse_literal("-1 "));
-    // println!("{:?}: {:?}\n", "-2.14", parse_literal("-2.14"));
-
-    // println!("{:?}: {:?}\n", ":my0Atom:another ", parse_literal(":my0Atom:another "));
-
-    // println!("{:?}: {:?}\n", "true false", tokenize("true asdf"));
-    // println!("{:?}: {:?}\n", "false", parse_literal("false"));
-    // println!("{:?}: {:?}\n", "falsetrue", parse_literal("falsetrue"));
-    // let tokens = tokenize("true false");
-    // println!("{:?}\n", tokens);
-
-    // println!("{:?}", parsers::parse_keyword("func"))
-    // println!(
-    //     "{:?}",
-    //     matches!(
-    //         tokens.first().unwrap().token_type,
-    //         TokenType::Type(Types::Primitive(_))
-    //     )
-    // )
-
-    // let (mut t, mut ros) = tokenize(contents);
-    // while !ros.is_empty() {
-    //     println!("{t}, {ros}");
-    //     (t, ros) = tokenize(ros);
-    // }
-    // println!("{:?}", tokenize(contents));
+/* Everything before running a program: parsing, name resolution, the checks and
+ * monomorphisation. The program comes back unless there were errors, the diagnostics are the
+ * warnings and errors found on the way. It's the monomorphised one, resolved and checked again so
+ * each instance of a generic function is a function of its own with the types it was called with.
+ */
+pub fn compile(contents: &str) -> (Option<(Vec<Expression>, SymbolTable)>, Vec<Diagnostic>) {
+    let mut expressions = match tokenize(contents).and_then(|tokens| expressionize(&tokens)) {
+        Ok(expressions) => expressions,
+        Err(e) => return (None, vec![e]),
+    };
+    let (mut symbols, mut diagnostics) = resolver::resolve(&mut expressions);
+    if !diagnostics.iter().any(|d| d.is_error()) {
+        diagnostics.extend(checker::check(&expressions, &mut symbols));
+        diagnostics.extend(mutability::check_mutability(&expressions, &symbols));
+        diagnostics.extend(consts::evaluate_consts(&mut expressions, &symbols));
+    }
+    if diagnostics.iter().any(|d| d.is_error()) {
+        return (None, diagnostics);
+    }
+    checker::annotate(&mut expressions, &symbols);
+    let mut expressions = match generics::monomorphise(expressions) {
+        Ok(expressions) => expressions,
+        Err(errors) => {
+            diagnostics.extend(errors);
+            return (None, diagnostics);
+        }
+    };
+    // the instances get symbols of their own, the warnings were all found the first time
+    let (mut symbols, mut errors) = resolver::resolve(&mut expressions);
+    if !errors.iter().any(|d| d.is_error()) {
+        errors.extend(checker::check(&expressions, &mut symbols));
+    }
+    errors.retain(|d| d.is_error());
+    if !errors.is_empty() {
+        diagnostics.extend(errors);
+        return (None, diagnostics);
+    }
+    checker::annotate(&mut expressions, &symbols);
+    (Some((expressions, symbols)), diagnostics)
+}
 
-    // println!("{:?}", format!("@{0} = constant [{1} x i8] c\"Hellow, World!\0A\"", "name", 16))
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    std::process::exit(cli::main(&args));
 }
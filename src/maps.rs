@@ -0,0 +1,497 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::rc::Rc;
+
+use crate::rho_core::{arguments, run, Builtin, CallResult, Context, Outcome};
+use crate::streams::collect;
+use crate::types::Type;
+use crate::value::Value;
+
+/* The value of a rho map, a hash table that keeps its keys in the order they were first put in.
+ * Iterating, printing and `Map.keys` all follow that order, so a program's output never depends
+ * on the hashes. Putting a key that is already there keeps its place, deleting one leaves a hole
+ * in `entries` that is compacted away once there are more holes than entries.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Map {
+    entries: Vec<Option<(Value, Value)>>, // in insertion order, None where a key was deleted
+    index: HashMap<u64, Vec<usize>>,      // the positions in `entries` of the keys with a hash
+    len: usize,
+}
+
+impl Map {
+    pub fn new() -> Map {
+        Map::default()
+    }
+
+    // Later entries win, ie. `Map.from_list([{1, "a"}, {1, "b"}])` is `{1: "b"}`
+    pub fn from_entries(entries: impl IntoIterator<Item = (Value, Value)>) -> Result<Map, String> {
+        let mut map = Map::new();
+        for (k, v) in entries {
+            map.insert(k, v)?;
+        }
+        Ok(map)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn position(&self, key: &Value) -> Option<usize> {
+        // an unhashable key can't have been put in, so it's never there
+        let positions = self.index.get(&hash(key).ok()?)?;
+        positions
+            .iter()
+            .copied()
+            .find(|&i| matches!(&self.entries[i], Some((k, _)) if k == key))
+    }
+
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        let i = self.position(key)?;
+        self.entries[i].as_ref().map(|(_, v)| v)
+    }
+
+    pub fn contains_key(&self, key: &Value) -> bool {
+        self.position(key).is_some()
+    }
+
+    pub fn insert(&mut self, key: Value, value: Value) -> Result<(), String> {
+        *self.get_or_insert(key)? = value;
+        Ok(())
+    }
+
+    // The value of a key, a new key is put at the end with a unit value for the caller to set
+    pub fn get_or_insert(&mut self, key: Value) -> Result<&mut Value, String> {
+        let h = hash(&key)?;
+        let i = match self.position(&key) {
+            Some(i) => i,
+            None => {
+                self.index.entry(h).or_default().push(self.entries.len());
+                self.entries.push(Some((key, Value::Unit)));
+                self.len += 1;
+                self.entries.len() - 1
+            }
+        };
+        Ok(&mut self.entries[i].as_mut().unwrap().1)
+    }
+
+    pub fn remove(&mut self, key: &Value) -> Option<Value> {
+        let i = self.position(key)?;
+        let (_, value) = self.entries[i].take()?;
+        self.len -= 1;
+        if self.entries.len() > 2 * self.len + 8 {
+            self.compact();
+        } else if let Some(positions) = self.index.get_mut(&hash(key).ok()?) {
+            positions.retain(|&p| p != i);
+        }
+        Some(value)
+    }
+
+    fn compact(&mut self) {
+        let entries = mem::take(&mut self.entries);
+        self.index.clear();
+        for (i, (k, v)) in entries.into_iter().flatten().enumerate() {
+            // the keys were hashed when they were put in
+            self.index.entry(hash(&k).unwrap()).or_default().push(i);
+            self.entries.push(Some((k, v)));
+        }
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&Value, &Value)> {
+        self.entries.iter().flatten().map(|(k, v)| (k, v))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Value> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.iter().map(|(_, v)| v)
+    }
+}
+
+// Two maps are equal when they have the same entries, whatever order they were put in
+impl PartialEq for Map {
+    fn eq(&self, other: &Map) -> bool {
+        self.len == other.len && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+/* The hash of a map key. Only values with an equality that a hash can follow can be keys: ints,
+ * strs, chars, bools, atoms and the tuples, lists, structs and variants made of them. Floats
+ * can't, `0.0 / 0.0` isn't equal to itself, nor can functions, streams or other maps.
+ * `DefaultHasher::new` always starts from the same keys, so the hashes are the same every run.
+ */
+pub fn hash(key: &Value) -> Result<u64, String> {
+    let mut hasher = DefaultHasher::new();
+    hash_into(key, &mut hasher)?;
+    Ok(hasher.finish())
+}
+
+fn hash_into(value: &Value, hasher: &mut DefaultHasher) -> Result<(), String> {
+    mem::discriminant(value).hash(hasher);
+    match value {
+        Value::Unit => {}
+        Value::Int(i) => i.hash(hasher),
+        Value::Bool(b) => b.hash(hasher),
+        Value::Char(c) => c.hash(hasher),
+        Value::Atom(a) => a.0.hash(hasher),
+        Value::String(s) => s.hash(hasher),
+        Value::Range(start, end, step) => (start, end, step).hash(hasher),
+        Value::List(elements) | Value::Tuple(elements) => {
+            elements.len().hash(hasher);
+            for e in elements.iter() {
+                hash_into(e, hasher)?;
+            }
+        }
+        Value::Struct(name, fields) => {
+            name.hash(hasher);
+            for (_, v) in fields.iter() {
+                hash_into(v, hasher)?;
+            }
+        }
+        Value::Variant(name, variant, values) => {
+            (name, variant).hash(hasher);
+            for v in values.iter() {
+                hash_into(v, hasher)?;
+            }
+        }
+        Value::Float(_)
+        | Value::Map(_)
+        | Value::Closure(_)
+        | Value::Compiled(_)
+        | Value::Builtin(_)
+        | Value::Stream(_) => {
+            return Err(format!("can't use a `{}` as a map key", value.type_name()))
+        }
+    }
+    Ok(())
+}
+
+/* The `Map` module. Maps are values like lists, so `put`, `delete` and `merge` return a new map
+ * and leave the one they were given as it was.
+ */
+pub fn builtins() -> Vec<Builtin> {
+    let k = || Type::Param("K".to_string());
+    let v = || Type::Param("V".to_string());
+    let map = || Type::map(k(), v());
+    vec![
+        // the value of a key, or the default when it isn't there
+        Builtin::new("Map.get", &["K", "V"], vec![map(), k(), v()], v(), get),
+        Builtin::new(
+            "Map.has_key",
+            &["K", "V"],
+            vec![map(), k()],
+            Type::bool(),
+            has_key,
+        ),
+        Builtin::new("Map.size", &["K", "V"], vec![map()], Type::int(), size),
+        Builtin::new("Map.put", &["K", "V"], vec![map(), k(), v()], map(), put),
+        Builtin::new("Map.delete", &["K", "V"], vec![map(), k()], map(), delete),
+        // the second map's values win, its new keys go after the first map's
+        Builtin::new("Map.merge", &["K", "V"], vec![map(), map()], map(), merge),
+        Builtin::new("Map.keys", &["K", "V"], vec![map()], Type::list(k()), keys),
+        Builtin::new(
+            "Map.values",
+            &["K", "V"],
+            vec![map()],
+            Type::list(v()),
+            values,
+        ),
+        Builtin::new(
+            "Map.to_list",
+            &["K", "V"],
+            vec![map()],
+            Type::list(Type::tuple(vec![k(), v()])),
+            to_list,
+        ),
+        Builtin::new(
+            "Map.from_list",
+            &["K", "V"],
+            vec![Type::list(Type::tuple(vec![k(), v()]))],
+            map(),
+            from_list,
+        ),
+    ]
+}
+
+fn map_arg(v: Value) -> Outcome<Rc<Map>> {
+    match v {
+        Value::Map(map) => Ok(map),
+        v => Err(CallResult::Err(format!("expected a map, found `{}`", v))),
+    }
+}
+
+fn key_error(message: String) -> CallResult {
+    CallResult::Err(message)
+}
+
+fn get(_: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [map, key, default] = arguments(args)?;
+        Ok(map_arg(map)?.get(&key).cloned().unwrap_or(default))
+    })
+}
+
+fn has_key(_: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [map, key] = arguments(args)?;
+        Ok(Value::Bool(map_arg(map)?.contains_key(&key)))
+    })
+}
+
+fn size(_: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [map] = arguments(args)?;
+        Ok(Value::Int(map_arg(map)?.len() as i64))
+    })
+}
+
+fn put(_: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [map, key, value] = arguments(args)?;
+        let mut map = map_arg(map)?;
+        Rc::make_mut(&mut map)
+            .insert(key, value)
+            .map_err(key_error)?;
+        Ok(Value::Map(map))
+    })
+}
+
+fn delete(_: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [map, key] = arguments(args)?;
+        let mut map = map_arg(map)?;
+        if map.contains_key(&key) {
+            Rc::make_mut(&mut map).remove(&key);
+        }
+        Ok(Value::Map(map))
+    })
+}
+
+fn merge(_: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [left, right] = arguments(args)?;
+        let (mut left, right) = (map_arg(left)?, map_arg(right)?);
+        if !right.is_empty() {
+            let merged = Rc::make_mut(&mut left);
+            for (k, v) in right.iter() {
+                merged.insert(k.clone(), v.clone()).map_err(key_error)?;
+            }
+        }
+        Ok(Value::Map(left))
+    })
+}
+
+fn keys(_: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [map] = arguments(args)?;
+        Ok(Value::list(map_arg(map)?.keys().cloned().collect()))
+    })
+}
+
+fn values(_: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [map] = arguments(args)?;
+        Ok(Value::list(map_arg(map)?.values().cloned().collect()))
+    })
+}
+
+fn to_list(_: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [map] = arguments(args)?;
+        let entries = map_arg(map)?
+            .iter()
+            .map(|(k, v)| Value::tuple(vec![k.clone(), v.clone()]))
+            .collect();
+        Ok(Value::list(entries))
+    })
+}
+
+fn from_list(context: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    run(|| {
+        let [entries] = arguments(args)?;
+        let mut map = Map::new();
+        for entry in collect(context, &entries)? {
+            match &entry {
+                Value::Tuple(pair) if pair.len() == 2 => {
+                    map.insert(pair[0].clone(), pair[1].clone())
+                        .map_err(key_error)?;
+                }
+                _ => {
+                    return Err(CallResult::Err(format!(
+                        "expected a `{{key, value}}` tuple, found `{}`",
+                        entry
+                    )))
+                }
+            }
+        }
+        Ok(Value::Map(Rc::new(map)))
+    })
+}
+
+#[test]
+fn test_map_order() {
+    let int = Value::Int;
+    let mut map = Map::new();
+    for i in [3, 1, 2] {
+        map.insert(int(i), int(i * 10)).unwrap();
+    }
+    map.insert(int(1), int(0)).unwrap();
+    assert_eq!(map.get(&int(1)), Some(&int(0)));
+    assert_eq!(map.remove(&int(3)), Some(int(30)));
+    assert_eq!(map.remove(&int(3)), None);
+    map.insert(int(3), int(30)).unwrap();
+    let keys: Vec<&Value> = map.keys().collect();
+    assert_eq!(keys, vec![&int(1), &int(2), &int(3)]);
+    assert_eq!(map.len(), 3);
+
+    // compacting keeps the order and the index
+    for i in 100..200 {
+        map.insert(int(i), Value::Unit).unwrap();
+    }
+    for i in 100..190 {
+        map.remove(&int(i));
+    }
+    let keys: Vec<i64> = map
+        .keys()
+        .map(|k| match k {
+            Value::Int(i) => *i,
+            _ => unreachable!(),
+        })
+        .collect();
+    assert_eq!(
+        keys,
+        [1, 2, 3, 190, 191, 192, 193, 194, 195, 196, 197, 198, 199]
+    );
+    assert!(map.entries.len() < 30);
+    assert_eq!(map.get(&int(195)), Some(&Value::Unit));
+
+    let reordered = Map::from_entries(map.iter().rev().map(|(k, v)| (k.clone(), v.clone())));
+    assert_eq!(reordered.unwrap(), map);
+}
+
+#[test]
+fn test_map_keys() {
+    let key = Value::tuple(vec![Value::Atom(crate::atoms::OK), Value::string("a")]);
+    let mut map = Map::new();
+    map.insert(key.clone(), Value::Int(1)).unwrap();
+    assert_eq!(
+        map.get(&Value::tuple(vec![
+            Value::Atom(crate::atoms::OK),
+            Value::string("a")
+        ])),
+        Some(&Value::Int(1))
+    );
+    assert_eq!(map.get(&Value::Int(1)), None);
+    assert_eq!(
+        map.insert(Value::Float(1.0), Value::Unit),
+        Err("can't use a `float` as a map key".to_string())
+    );
+    assert_eq!(
+        map.insert(
+            Value::tuple(vec![Value::Int(1), Value::Float(1.0)]),
+            Value::Unit
+        ),
+        Err("can't use a `float` as a map key".to_string())
+    );
+    assert_eq!(map.get(&Value::Float(1.0)), None);
+    assert_eq!(hash(&key), hash(&key.clone()));
+    assert_ne!(hash(&Value::Int(1)), hash(&Value::Char('\u{1}')));
+}
+
+#[test]
+fn test_map_module() {
+    let (result, output) = crate::interpreter::run_source(
+        "scores = {\"ann\": 3, \"bob\": 5}
+more = Map.put(scores, \"cy\", 1) |> Map.put(\"ann\", 4)
+IO.inspect(more)
+IO.inspect(scores)
+IO.inspect(Map.get(more, \"dee\", 0) + Map.get(more, \"ann\", 0))
+IO.inspect(Map.delete(more, \"bob\") |> Map.keys())
+IO.inspect(Map.merge(scores, {\"cy\": 2, \"ann\": 0}) |> Map.values())
+IO.inspect(Map.to_list({\"a\": 1}))
+pairs = Map.from_list([{{0, 0}, :origin}, {{1, 2}, :point}])
+IO.inspect({Map.size(pairs), Map.has_key(pairs, {1, 2}), pairs[{0, 0}]})
+for entry in more {
+    IO.inspect(entry)
+}
+",
+        "",
+    );
+    assert_eq!(result, Ok(0));
+    assert_eq!(
+        output,
+        "{\"ann\": 4, \"bob\": 5, \"cy\": 1}
+{\"ann\": 3, \"bob\": 5}
+4
+[\"ann\", \"cy\"]
+[0, 5, 2]
+[{\"a\", 1}]
+{2, true, :origin}
+{\"ann\", 4}
+{\"bob\", 5}
+{\"cy\", 1}
+"
+    );
+}
+
+#[test]
+fn test_map_native() {
+    let source = "scores = {\"ann\": 3, \"bob\": 5}
+more = Map.put(scores, \"cy\", 1) |> Map.put(\"ann\", 4)
+IO.inspect(more)
+IO.inspect(scores)
+IO.inspect(Map.get(more, \"dee\", 0) + Map.get(more, \"ann\", 0))
+IO.inspect(Map.delete(more, \"bob\") |> Map.keys())
+IO.inspect(Map.merge(scores, {\"cy\": 2, \"ann\": 0}) |> Map.values())
+IO.inspect(Map.to_list({\"a\": 1}))
+var lists = {1: [:a], 2: []}
+lists[2] = lists[1] ++ [:b]
+lists[1][0] = :c
+IO.inspect({Map.size(lists), Map.has_key(lists, 3), lists[2]})
+IO.puts(lists)
+for entry in more {
+    IO.inspect(entry)
+}
+";
+    let Some((code, stdout, stderr)) = crate::codegen::run_native(source, "") else {
+        return;
+    };
+    let (result, output) = crate::interpreter::run_source(source, "");
+    assert_eq!(stdout, output);
+    assert_eq!(
+        output,
+        "{\"ann\": 4, \"bob\": 5, \"cy\": 1}
+{\"ann\": 3, \"bob\": 5}
+4
+[\"ann\", \"cy\"]
+[0, 5, 2]
+[{\"a\", 1}]
+{2, false, [:a, :b]}
+{1: [:c], 2: [:a, :b]}
+{\"ann\", 4}
+{\"bob\", 5}
+{\"cy\", 1}
+"
+    );
+    assert_eq!(code, Some(result.unwrap()), "{}", stderr);
+    assert!(!stderr.contains("still live"), "{}", stderr);
+
+    let source = "var m = {\"a\": {\"b\": 1}}\nm[\"x\"][\"b\"] = 2\n";
+    let Some((code, _, stderr)) = crate::codegen::run_native(source, "") else {
+        return;
+    };
+    assert_eq!(code, Some(1));
+    assert!(
+        stderr.contains("`unit` can't be assigned by index"),
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,3768 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::atoms;
+use crate::checker;
+use crate::diagnostics::Diagnostic;
+use crate::parsers::*;
+use crate::resolver::{SymbolKind, SymbolTable};
+use crate::rho_core;
+use crate::tokens::*;
+use crate::types::Type;
+use rho_runtime::text::escape;
+
+/* The mid-level IR the backends compile, lowered from a checked program so none of them has to
+ * know the syntax tree. A function is a list of basic blocks, each a list of statements ending in a
+ * terminator that says where control goes next. Every intermediate value gets a temporary, a local
+ * like the bindings are, and every local and global has a type, so the IR can be read on its own:
+ *
+ *     fn twice(_1: str) -> str {
+ *         let _0: str
+ *         let _1: str  // s
+ *         let _2: str
+ *         let _3: str
+ *         let _4: str
+ *
+ *         bb0:
+ *             _2 = _1
+ *             _3 = _1
+ *             _4 = Concat(_2, _3) @ 2:14
+ *             drop _2
+ *             drop _3
+ *             _0 = move _4
+ *             goto bb1
+ *
+ *         bb1:
+ *             drop _1
+ *             return
+ *     }
+ *
+ * `_0` is the return place and the parameters come next. Objects, strs, lists, maps, tuples,
+ * structs, closures and streams, are reference counted and the IR says where their references
+ * end: reading a place copies it, which is a new reference, a `move` hands the one the place has
+ * over, and `drop` releases it and leaves the place empty. A local holding an object starts empty, and dropping an empty place does nothing.
+ * A function drops its bindings where it returns, the top level its globals too.
+ *
+ * int arithmetic fails like the interpreter's does, on overflow or a division by zero, at the
+ * span its statement has. The top level statements are a function of their own, `<top level>`,
+ * which calls the program's `main` and returns the exit code. Only the core of the language is
+ * lowered so far, anything else, ie. enums or `match`, is reported as an error where it's used.
+ * Lambdas and nested functions are closures of their own, a local one shares lives in a cell
+ * both hold a reference to, which `fresh` makes and `drop` lets go of. The `Stream` builtins are
+ * the runtime's, which calls the closures of a stream when something pulls from it.
+ * Setting an element, `p.x = v` or `xs[i] = v`, keeps its path, ie. `_1.x[_2] = _3`, and changes
+ * each object on it only the place has, or a copy of one something else has too.
+ */
+pub fn lower(program: &[Expression], symbols: &SymbolTable) -> Result<Program, Vec<Diagnostic>> {
+    Lowering::new(program, symbols, false).program(program)
+}
+
+/* The whole language, for the VM, whose values know their types when it runs. The types here only
+ * say what the checker knew, `_` where it's a type parameter or wasn't known, and nothing is
+ * converted or dropped. A nested function or lambda is a closure of its own, `#0` and on, and the
+ * locals it shares with the function making it are `captured`, which it reaches as its upvalues:
+ *
+ *     #0 fn fn() -> _ {
+ *         let _0: _
+ *         let ^0: int  // n
+ *
+ * The top level returns what `main` does, or unit, the VM makes an exit code of it like the
+ * interpreter does.
+ */
+pub fn lower_for_vm(
+    program: &[Expression],
+    symbols: &SymbolTable,
+) -> Result<Program, Vec<Diagnostic>> {
+    atoms::intern_program(program);
+    Lowering::new(program, symbols, true).program(program)
+}
+
+pub type LocalId = usize;
+pub type BlockId = usize;
+pub type GlobalId = usize;
+pub type UpvalueId = usize;
+pub type ClosureId = usize;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub structs: Vec<Struct>,
+    pub globals: Vec<Global>,
+    pub functions: Vec<Function>,
+    pub closures: Vec<Function>, // the nested functions and lambdas, a `Closure` makes one
+    pub entry: Function,         // `<top level>`
+}
+
+// A struct's fields in the order it declares them, which is the order its records have them in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Struct {
+    pub name: String,
+    pub type_params: Vec<String>,
+    pub fields: Vec<(String, Type)>,
+}
+
+// A top level binding, every function can see those
+#[derive(Debug, Clone, PartialEq)]
+pub struct Global {
+    pub name: String,
+    pub ty: Type,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String, // dotted, ie. `Shapes.area`
+    pub params: Vec<LocalId>,
+    pub locals: Vec<Local>, // `_0` is the return place, its type the return type
+    pub upvalues: Vec<Local>, // what a closure captured, `^0` and on
+    pub blocks: Vec<Block>, // it starts at `bb0`
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Local {
+    pub name: Option<String>, // None for temporaries
+    pub ty: Type,
+    pub captured: bool, // a closure shares it, so it lives in a cell of its own
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub statements: Vec<Statement>,
+    pub terminator: Terminator,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Place {
+    Local(LocalId),
+    Global(GlobalId),
+    Upvalue(UpvalueId),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Copy(Place),
+    Move(Place),
+    Constant(Constant),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    Atom(String),
+    Str(String),
+    Unit,
+}
+
+// The operands of a `Binary` have the same type, int or float for the arithmetic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Concat,
+    Append,       // `++` of two lists
+    AddOverflows, // whether an int `Add` would fail, for the end of a range
+    WrappingAdd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rvalue {
+    Use(Operand),
+    Binary(BinOp, Operand, Operand),
+    Unary(UnOp, Operand),
+    Cast(Operand, Type), // an int to a float, a bool or char to an int, or a float in range to one
+    Select(Operand, Operand, Operand),
+    List(Vec<Operand>),
+    Tuple(Vec<Operand>),
+    Map(Vec<(Operand, Operand)>),
+    Struct(String, Vec<(String, Operand)>), // the fields in the order the struct declares them
+    Variant(String, String, Vec<Operand>),  // the enum, the variant and its values
+    Range(Operand, Operand, Option<Operand>),
+    Field(Operand, String),
+    Index(Operand, Operand),
+    Closure(ClosureId, Vec<Place>), // the places of the function making it its upvalues share
+    Function(String),               // a top level function as a value
+    Builtin(String),                // a builtin as a value
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Callee {
+    Function(String), // takes over its arguments' references
+    Print,            // `IO.print` and the others only look at their argument
+    Puts,
+    Inspect,
+    Builtin(String), // the native backend has `RUNTIME_BUILTINS`, which only look at their arguments too
+    Show,            // the str `IO.inspect` prints for its argument, ie. `"a"` for a str
+    // the frame of a call for stack traces, `Function` has one of its own but an inlined one not
+    Enter(String),
+    Leave,
+    Value,          // the closure or builtin that is the first argument, which it only looks at
+    Method(String), // `T.show(x)`, the `show` of the type its first argument has when it runs
+}
+
+/* The builtins compiled code calls the runtime for, `String.upcase` is `rho_string_upcase`, the
+ * atom ones go through the runtime's atom table, the `Map` ones that make a new map change a copy
+ * and `str` writes any value like `IO.puts` does. The ones that can fail, true here, take the
+ * location to fail at after their arguments.
+ */
+pub const RUNTIME_BUILTINS: [(&str, bool); 35] = [
+    ("String.length", false),
+    ("String.char_count", false),
+    ("String.byte_size", false),
+    ("String.graphemes", false),
+    ("String.slice", false),
+    ("String.split", false),
+    ("String.join", false),
+    ("String.trim", false),
+    ("String.trim_leading", false),
+    ("String.trim_trailing", false),
+    ("String.replace", false),
+    ("String.contains", false),
+    ("String.starts_with", false),
+    ("String.ends_with", false),
+    ("String.upcase", false),
+    ("String.downcase", false),
+    ("String.pad_leading", true),
+    ("String.pad_trailing", true),
+    ("String.to_int", false),
+    ("String.to_float", false),
+    ("str", false),
+    ("atom_to_string", false),
+    ("string_to_atom", false),
+    ("IO.read_line", false),
+    ("List.count", false),
+    ("Enum.count", false),
+    ("Map.get", false),
+    ("Map.has_key", false),
+    ("Map.size", false),
+    ("Map.put", false),
+    ("Map.delete", false),
+    ("Map.merge", false),
+    ("Map.keys", false),
+    ("Map.values", false),
+    ("Map.to_list", false),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Assign {
+        place: Place,
+        value: Rvalue,
+        span: Span,
+    },
+    Call {
+        result: Option<Place>,
+        callee: Callee,
+        args: Vec<Operand>,
+        span: Span,
+    },
+    Drop(Place),
+    // whether the subject matches the pattern, which binds its places when it does
+    Match {
+        result: Place,
+        subject: Operand,
+        pattern: Pattern,
+        span: Span,
+    },
+    // `p.x = v` and `xs[i][j] = v`, the path goes from the place in
+    SetPath {
+        place: Place,
+        path: Vec<Step>,
+        value: Operand,
+        span: Span,
+    },
+    Fresh(Place), // a new cell for a captured local, ie. for each pass through a loop declaring it
+    // a `for` loop starts a pass over its iterable, which `next` goes on with
+    Iterate {
+        iterable: Operand,
+        span: Span,
+    },
+    EndIterate, // a `break` leaves the innermost pass
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Wildcard,
+    Bind(Place),
+    Literal(Constant),
+    Tuple(Vec<Pattern>),
+    List(Vec<Pattern>),
+    Variant(String, Option<String>, Vec<Pattern>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Field(String),
+    Index(Operand),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Goto(BlockId),
+    Branch {
+        condition: Operand,
+        then: BlockId,
+        otherwise: BlockId,
+    },
+    Return,
+    /* Ends the program with `prefix` and the str `message`, ie. "panic: " and "too big". The
+     * stack trace is shown when it's inside a call, or `traced` for a `panic` or `assert`.
+     */
+    Fail {
+        prefix: String,
+        message: Operand,
+        span: Span,
+        traced: bool,
+    },
+    Unreachable,
+    // the next element of the innermost pass goes to `place` and on to `body`, or it ended
+    Next {
+        place: Option<Place>,
+        body: BlockId,
+        done: BlockId,
+        span: Span,
+    },
+}
+
+impl Constant {
+    pub fn ty(&self) -> Type {
+        match self {
+            Constant::Int(_) => Type::int(),
+            Constant::Float(_) => Type::float(),
+            Constant::Bool(_) => Type::bool(),
+            Constant::Char(_) => Type::char(),
+            Constant::Atom(_) => Type::atom(),
+            Constant::Str(_) => Type::string(),
+            Constant::Unit => Type::Unit,
+        }
+    }
+}
+
+impl Program {
+    pub fn fields(&self, t: &Type) -> Option<Vec<(String, Type)>> {
+        fields(&self.structs, t)
+    }
+}
+
+// The fields of a struct type with the types its type arguments give them, in declaration order
+fn fields(structs: &[Struct], t: &Type) -> Option<Vec<(String, Type)>> {
+    let Type::Named(name, args) = t else {
+        return None;
+    };
+    let decl = structs.iter().find(|s| s.name == *name)?;
+    let s: HashMap<String, Type> = (decl.type_params.iter().cloned())
+        .zip(args.iter().cloned())
+        .collect();
+    let fields = decl.fields.iter();
+    Some(fields.map(|(f, t)| (f.clone(), t.substitute(&s))).collect())
+}
+
+impl Function {
+    pub fn place_type(&self, place: Place, globals: &[Global]) -> Type {
+        match place {
+            Place::Local(id) => self.locals[id].ty.clone(),
+            Place::Global(id) => globals[id].ty.clone(),
+            Place::Upvalue(id) => self.upvalues[id].ty.clone(),
+        }
+    }
+
+    pub fn operand_type(&self, operand: &Operand, globals: &[Global]) -> Type {
+        match operand {
+            Operand::Copy(place) | Operand::Move(place) => self.place_type(*place, globals),
+            Operand::Constant(c) => c.ty(),
+        }
+    }
+
+    pub fn return_type(&self) -> &Type {
+        &self.locals[0].ty
+    }
+
+    // Whether a closure shares the place, which a call can change then
+    pub fn captured(&self, place: Place) -> bool {
+        match place {
+            Place::Local(id) => self.locals[id].captured,
+            Place::Upvalue(_) => true,
+            Place::Global(_) => false,
+        }
+    }
+}
+
+impl Operand {
+    pub fn place(&self) -> Option<Place> {
+        match self {
+            Operand::Copy(place) | Operand::Move(place) => Some(*place),
+            Operand::Constant(_) => None,
+        }
+    }
+}
+
+impl Rvalue {
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Rvalue::Use(a) | Rvalue::Unary(_, a) | Rvalue::Cast(a, _) | Rvalue::Field(a, _) => {
+                vec![a]
+            }
+            Rvalue::Binary(_, a, b) | Rvalue::Index(a, b) => vec![a, b],
+            Rvalue::Select(c, a, b) => vec![c, a, b],
+            Rvalue::List(values) | Rvalue::Tuple(values) | Rvalue::Variant(_, _, values) => {
+                values.iter().collect()
+            }
+            Rvalue::Map(entries) => entries.iter().flat_map(|(k, v)| [k, v]).collect(),
+            Rvalue::Struct(_, fields) => fields.iter().map(|(_, v)| v).collect(),
+            Rvalue::Range(a, b, step) => [a, b].into_iter().chain(step.iter()).collect(),
+            Rvalue::Closure(..) | Rvalue::Function(_) | Rvalue::Builtin(_) => vec![],
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Rvalue::Use(a) | Rvalue::Unary(_, a) | Rvalue::Cast(a, _) | Rvalue::Field(a, _) => {
+                vec![a]
+            }
+            Rvalue::Binary(_, a, b) | Rvalue::Index(a, b) => vec![a, b],
+            Rvalue::Select(c, a, b) => vec![c, a, b],
+            Rvalue::List(values) | Rvalue::Tuple(values) | Rvalue::Variant(_, _, values) => {
+                values.iter_mut().collect()
+            }
+            Rvalue::Map(entries) => entries.iter_mut().flat_map(|(k, v)| [k, v]).collect(),
+            Rvalue::Struct(_, fields) => fields.iter_mut().map(|(_, v)| v).collect(),
+            Rvalue::Range(a, b, step) => [a, b].into_iter().chain(step.iter_mut()).collect(),
+            Rvalue::Closure(..) | Rvalue::Function(_) | Rvalue::Builtin(_) => vec![],
+        }
+    }
+
+    // The places it names, a closure's captures are places rather than operands it reads
+    fn places_mut(&mut self) -> Vec<&mut Place> {
+        match self {
+            Rvalue::Closure(_, captures) => captures.iter_mut().collect(),
+            value => value
+                .operands_mut()
+                .into_iter()
+                .filter_map(place_mut)
+                .collect(),
+        }
+    }
+}
+
+impl Callee {
+    // Whether the call can run rho code, which can change any global or captured local
+    pub fn runs_code(&self) -> bool {
+        match self {
+            Callee::Function(_) | Callee::Value | Callee::Method(_) => true,
+            // a builtin can call a function it's given, or pull a stream that does
+            Callee::Builtin(name) => !RUNTIME_BUILTINS.iter().any(|(b, _)| b == name),
+            _ => false,
+        }
+    }
+}
+
+impl Pattern {
+    // The places it binds
+    pub fn places_mut(&mut self) -> Vec<&mut Place> {
+        match self {
+            Pattern::Bind(place) => vec![place],
+            Pattern::Tuple(patterns)
+            | Pattern::List(patterns)
+            | Pattern::Variant(_, _, patterns) => {
+                patterns.iter_mut().flat_map(|p| p.places_mut()).collect()
+            }
+            Pattern::Wildcard | Pattern::Literal(_) => vec![],
+        }
+    }
+
+    pub fn places(&self) -> Vec<Place> {
+        self.clone().places_mut().into_iter().map(|p| *p).collect()
+    }
+}
+
+impl Statement {
+    // The operands it reads, a `drop` has none
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Statement::Assign { value, .. } => value.operands(),
+            Statement::Call { args, .. } => args.iter().collect(),
+            Statement::Match { subject, .. } => vec![subject],
+            Statement::SetPath { path, value, .. } => {
+                let indexes = path.iter().filter_map(|step| match step {
+                    Step::Index(i) => Some(i),
+                    Step::Field(_) => None,
+                });
+                std::iter::once(value).chain(indexes).collect()
+            }
+            Statement::Iterate { iterable, .. } => vec![iterable],
+            Statement::Drop(_) | Statement::Fresh(_) | Statement::EndIterate => vec![],
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Statement::Assign { value, .. } => value.operands_mut(),
+            Statement::Call { args, .. } => args.iter_mut().collect(),
+            Statement::Match { subject, .. } => vec![subject],
+            Statement::SetPath { path, value, .. } => {
+                let indexes = path.iter_mut().filter_map(|step| match step {
+                    Step::Index(i) => Some(i),
+                    Step::Field(_) => None,
+                });
+                std::iter::once(value).chain(indexes).collect()
+            }
+            Statement::Iterate { iterable, .. } => vec![iterable],
+            Statement::Drop(_) | Statement::Fresh(_) | Statement::EndIterate => vec![],
+        }
+    }
+
+    // The place it sets, or empties for a `drop` and a `fresh`
+    pub fn written(&self) -> Option<Place> {
+        match self {
+            Statement::Assign { place, .. }
+            | Statement::Drop(place)
+            | Statement::Fresh(place)
+            | Statement::Match { result: place, .. } => Some(*place),
+            Statement::Call { result, .. } => *result,
+            Statement::SetPath { .. } | Statement::Iterate { .. } | Statement::EndIterate => None,
+        }
+    }
+
+    // The other places it changes, the bindings of a `match` and the place a path goes into
+    pub fn changed(&self) -> Vec<Place> {
+        match self {
+            Statement::Match { pattern, .. } => pattern.places(),
+            Statement::SetPath { place, .. } => vec![*place],
+            _ => vec![],
+        }
+    }
+
+    // Every place it names, the one it writes and the ones its operands read
+    pub fn places_mut(&mut self) -> Vec<&mut Place> {
+        match self {
+            Statement::Assign { place, value, .. } => {
+                let mut places = vec![place];
+                places.extend(value.places_mut());
+                places
+            }
+            Statement::Call { result, args, .. } => {
+                let mut places: Vec<&mut Place> = result.iter_mut().collect();
+                places.extend(args.iter_mut().filter_map(place_mut));
+                places
+            }
+            Statement::Match {
+                result,
+                subject,
+                pattern,
+                ..
+            } => {
+                let mut places = vec![result];
+                places.extend(place_mut(subject));
+                places.extend(pattern.places_mut());
+                places
+            }
+            Statement::SetPath {
+                place, path, value, ..
+            } => {
+                let mut places = vec![place];
+                places.extend(place_mut(value));
+                for step in path.iter_mut() {
+                    if let Step::Index(i) = step {
+                        places.extend(place_mut(i));
+                    }
+                }
+                places
+            }
+            Statement::Iterate { iterable, .. } => place_mut(iterable).into_iter().collect(),
+            Statement::Drop(place) | Statement::Fresh(place) => vec![place],
+            Statement::EndIterate => vec![],
+        }
+    }
+}
+
+fn place_mut(operand: &mut Operand) -> Option<&mut Place> {
+    match operand {
+        Operand::Copy(place) | Operand::Move(place) => Some(place),
+        Operand::Constant(_) => None,
+    }
+}
+
+impl Terminator {
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Terminator::Branch { condition, .. } => vec![condition],
+            Terminator::Fail { message, .. } => vec![message],
+            _ => vec![],
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Terminator::Branch { condition, .. } => vec![condition],
+            Terminator::Fail { message, .. } => vec![message],
+            _ => vec![],
+        }
+    }
+
+    // The places it names, the ones its operands read and the one `next` sets
+    pub fn places_mut(&mut self) -> Vec<&mut Place> {
+        match self {
+            Terminator::Next { place, .. } => place.iter_mut().collect(),
+            terminator => (terminator.operands_mut().into_iter())
+                .filter_map(place_mut)
+                .collect(),
+        }
+    }
+
+    // The blocks it can go to
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Goto(block) => vec![*block],
+            Terminator::Branch {
+                then, otherwise, ..
+            } => vec![*then, *otherwise],
+            Terminator::Next { body, done, .. } => vec![*body, *done],
+            _ => vec![],
+        }
+    }
+
+    pub fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Terminator::Goto(block) => vec![block],
+            Terminator::Branch {
+                then, otherwise, ..
+            } => vec![then, otherwise],
+            Terminator::Next { body, done, .. } => vec![body, done],
+            _ => vec![],
+        }
+    }
+}
+
+// Whether values of type `t` are reference counted objects, the native backend's structs are
+pub fn owns(t: &Type) -> bool {
+    matches!(
+        t,
+        Type::BuiltIn(
+            BuiltinType::String
+                | BuiltinType::List
+                | BuiltinType::Map
+                | BuiltinType::Tuple
+                | BuiltinType::Result
+                | BuiltinType::Stream,
+            _
+        ) | Type::Named(..)
+            | Type::Function(..)
+    )
+}
+
+// Whether `t` is one of the types of plain values, which the operators work on
+pub fn compiled(t: &Type) -> bool {
+    *t == Type::int()
+        || *t == Type::float()
+        || *t == Type::bool()
+        || *t == Type::char()
+        || *t == Type::atom()
+        || *t == Type::string()
+}
+
+// The type of a value the VM's lowering knows nothing about, shown as `_`
+fn unknown() -> Type {
+    Type::Var(0)
+}
+
+// The function being lowered
+struct Builder {
+    locals: Vec<Local>,
+    params: Vec<LocalId>,
+    upvalues: Vec<Local>,
+    captures: Vec<Place>, // where the function making the closure has each upvalue
+    statements: Vec<Vec<Statement>>, // of each block
+    terminators: Vec<Option<Terminator>>,
+    block: BlockId,
+    slots: HashMap<SymbolId, LocalId>,
+    upvalue_slots: HashMap<SymbolId, UpvalueId>,
+    fresh: HashSet<SymbolId>, // captured locals that got their cell before their declaration runs
+    loops: Vec<(BlockId, BlockId, bool)>, // where `continue` and `break` go, and if it iterates
+    return_block: BlockId,
+    top_level: bool,
+}
+
+impl Builder {
+    fn new(return_type: Type, top_level: bool) -> Builder {
+        Builder {
+            locals: vec![Local {
+                name: None,
+                ty: return_type,
+                captured: false,
+            }],
+            params: vec![],
+            upvalues: vec![],
+            captures: vec![],
+            statements: vec![vec![], vec![]],
+            terminators: vec![None, None],
+            block: 0,
+            slots: HashMap::new(),
+            upvalue_slots: HashMap::new(),
+            fresh: HashSet::new(),
+            loops: vec![],
+            return_block: 1,
+            top_level,
+        }
+    }
+}
+
+struct Lowering<'a> {
+    symbols: &'a SymbolTable,
+    vm: bool, // the whole language for the VM, rather than what the native backend compiles
+    globals: Vec<Global>,
+    global_ids: HashMap<SymbolId, GlobalId>,
+    set_flags: HashMap<GlobalId, GlobalId>, // the bool globals saying a function reads a global set
+    defined: HashSet<GlobalId>,             // the globals whose definition the top level ran so far
+    functions: HashMap<String, (Vec<Type>, Type)>, // by their dotted name
+    structs: Vec<Struct>,
+    captured: HashSet<SymbolId>,
+    closures: Vec<Function>,
+    enclosing: Vec<Builder>, // the functions around the closure being lowered, outermost first
+    diagnostics: Vec<Diagnostic>,
+    f: Builder,
+}
+
+impl Lowering<'_> {
+    // Finds the globals, the functions and the structs every function can use
+    fn new<'a>(program: &[Expression], symbols: &'a SymbolTable, vm: bool) -> Lowering<'a> {
+        let mut l = Lowering {
+            symbols,
+            vm,
+            globals: vec![],
+            global_ids: HashMap::new(),
+            set_flags: HashMap::new(),
+            defined: HashSet::new(),
+            functions: HashMap::new(),
+            structs: vec![],
+            captured: captured(program, symbols),
+            closures: vec![],
+            enclosing: vec![],
+            diagnostics: vec![],
+            f: Builder::new(Type::Unit, false),
+        };
+        for e in program.iter() {
+            if let Expression::Struct(decl) = e {
+                let names: Vec<String> = decl.type_params.iter().map(|p| p.name.clone()).collect();
+                let fields = (decl.fields.iter())
+                    .map(|(t, f)| (f.name.clone(), t.bind_params(&names)))
+                    .collect();
+                l.structs.push(Struct {
+                    name: decl.name.name.clone(),
+                    type_params: names,
+                    fields,
+                });
+            }
+        }
+        for e in program.iter() {
+            match e {
+                Expression::Definition { identifier, .. } => {
+                    let Some(id) = identifier.symbol else {
+                        continue;
+                    };
+                    let symbol = symbols.get(id);
+                    let t = match &symbol.symbol_type {
+                        Some(t) if vm || l.compiled(t) => t.clone(),
+                        None if vm => unknown(),
+                        _ => continue,
+                    };
+                    if !l.global_ids.contains_key(&id) {
+                        l.global_ids.insert(id, l.globals.len());
+                        l.globals.push(Global {
+                            name: symbol.name.clone(),
+                            ty: t,
+                        });
+                    }
+                }
+                Expression::Function(decl) => {
+                    let id = decl.name.idents.last().and_then(|i| i.symbol);
+                    let t = id.and_then(|id| symbols.get(id).symbol_type.clone());
+                    match t {
+                        Some(Type::Function(params, ret)) => {
+                            l.functions.insert(decl.name.path(), (params, *ret));
+                        }
+                        _ if vm => {
+                            let params = vec![unknown(); decl.params.len()];
+                            l.functions.insert(decl.name.path(), (params, unknown()));
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        l
+    }
+
+    fn program(mut self, program: &[Expression]) -> Result<Program, Vec<Diagnostic>> {
+        let mut functions = vec![];
+        for e in program.iter() {
+            if let Expression::Function(decl) = e {
+                if let Some(f) = self.function(decl) {
+                    functions.push(f);
+                }
+            }
+        }
+        let entry = self.entry(program);
+        if !self.diagnostics.is_empty() {
+            self.diagnostics.sort_by_key(|d| (d.span.line, d.span.col));
+            self.diagnostics.dedup();
+            return Err(self.diagnostics);
+        }
+        Ok(Program {
+            structs: self.structs,
+            globals: self.globals,
+            functions,
+            closures: self.closures,
+            entry,
+        })
+    }
+
+    fn unsupported(&mut self, what: &str, span: Span) {
+        let backend = if self.vm {
+            "the VM"
+        } else {
+            "the native backend"
+        };
+        self.diagnostics.push(Diagnostic::error(
+            format!("{} doesn't support {} yet", backend, what),
+            span,
+        ));
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.f.statements.push(vec![]);
+        self.f.terminators.push(None);
+        self.f.statements.len() - 1
+    }
+
+    // Whether the block ended, the code after a `return` or `break` goes in one nothing reaches
+    fn terminated(&self) -> bool {
+        self.f.terminators[self.f.block].is_some()
+    }
+
+    fn push(&mut self, statement: Statement) {
+        if self.terminated() {
+            self.f.block = self.new_block();
+        }
+        self.f.statements[self.f.block].push(statement);
+    }
+
+    fn terminate(&mut self, terminator: Terminator) {
+        if self.terminated() {
+            self.f.block = self.new_block();
+        }
+        self.f.terminators[self.f.block] = Some(terminator);
+    }
+
+    fn goto(&mut self, block: BlockId) {
+        self.terminate(Terminator::Goto(block));
+    }
+
+    fn branch(&mut self, condition: Operand, then: BlockId, otherwise: BlockId) {
+        self.terminate(Terminator::Branch {
+            condition,
+            then,
+            otherwise,
+        });
+    }
+
+    // Continues in `block`, the block before goes to it unless it already ended
+    fn start(&mut self, block: BlockId) {
+        if !self.terminated() {
+            self.goto(block);
+        }
+        self.f.block = block;
+    }
+
+    fn temp(&mut self, ty: Type) -> LocalId {
+        self.f.locals.push(Local {
+            name: None,
+            ty,
+            captured: false,
+        });
+        self.f.locals.len() - 1
+    }
+
+    // A temporary holding `value`, which the code using it takes over
+    fn assign(&mut self, ty: Type, value: Rvalue, span: Span) -> Operand {
+        let place = Place::Local(self.temp(ty));
+        self.push(Statement::Assign { place, value, span });
+        Operand::Move(place)
+    }
+
+    fn type_of(&self, operand: &Operand) -> Type {
+        match operand {
+            Operand::Copy(place) | Operand::Move(place) => match place {
+                Place::Local(id) => self.f.locals[*id].ty.clone(),
+                Place::Global(id) => self.globals[*id].ty.clone(),
+                Place::Upvalue(id) => self.f.upvalues[*id].ty.clone(),
+            },
+            Operand::Constant(c) => c.ty(),
+        }
+    }
+
+    // The VM counts the references its values have itself
+    fn owns(&self, t: &Type) -> bool {
+        !self.vm && owns(t)
+    }
+
+    /* Whether values of type `t` can be lowered yet, the plain ones and strs, and lists, maps,
+     * tuples, structs, closures and streams of those. A map's keys are compared by their bits or as strs, and a
+     * record has at most 64 fields. The elements of an empty literal have no type until it's used.
+     */
+    fn compiled(&self, t: &Type) -> bool {
+        self.compiled_in(t, &mut vec![])
+    }
+
+    // `compiled`, a struct inside itself is when the rest of it is
+    fn compiled_in(&self, t: &Type, structs: &mut Vec<String>) -> bool {
+        let mut element = |t: &Type| matches!(t, Type::Var(_)) || self.compiled_in(t, structs);
+        match t {
+            Type::BuiltIn(BuiltinType::List | BuiltinType::Stream, args) => {
+                args.iter().all(element)
+            }
+            Type::BuiltIn(BuiltinType::Tuple, args) => args.len() <= 64 && args.iter().all(element),
+            // only the runtime makes them, a tag and the value or reason
+            Type::BuiltIn(BuiltinType::Result, args) => args.iter().all(element),
+            Type::BuiltIn(BuiltinType::Map, args) => match args.as_slice() {
+                [k, v] => {
+                    (*k == Type::string() || (compiled(k) && *k != Type::float())) && element(v)
+                }
+                _ => false,
+            },
+            // a closure's record
+            Type::Function(params, ret) => {
+                params.iter().all(|t| self.compiled_in(t, structs))
+                    && (**ret == Type::Unit || self.compiled_in(ret, structs))
+            }
+            Type::Named(name, _) if structs.contains(name) => true,
+            Type::Named(name, _) => {
+                let Some(fields) = fields(&self.structs, t) else {
+                    return false;
+                };
+                structs.push(name.clone());
+                let all = fields.iter().all(|(_, t)| self.compiled_in(t, structs));
+                structs.pop();
+                all && fields.len() <= 64
+            }
+            t => compiled(t),
+        }
+    }
+
+    // For a use that only looks at a value, the temporary still has to be dropped after it
+    fn borrow(operand: &Operand) -> Operand {
+        match operand {
+            Operand::Move(place) => Operand::Copy(*place),
+            operand => operand.clone(),
+        }
+    }
+
+    // A value nothing uses, ie. of an expression statement or after it was borrowed
+    fn discard(&mut self, operand: Option<Operand>) {
+        if let Some(Operand::Move(place)) = operand {
+            if self.owns(&self.type_of(&Operand::Move(place))) && !self.terminated() {
+                self.push(Statement::Drop(place));
+            }
+        }
+    }
+
+    fn fail(&mut self, prefix: &str, message: Operand, span: Span) {
+        self.terminate(Terminator::Fail {
+            prefix: prefix.to_string(),
+            message,
+            span,
+            traced: false,
+        });
+    }
+
+    // A failure of `panic` or `assert`
+    fn panic(&mut self, prefix: &str, message: Operand, span: Span) {
+        self.terminate(Terminator::Fail {
+            prefix: prefix.to_string(),
+            message,
+            span,
+            traced: true,
+        });
+    }
+
+    // Fails with `message` when the bool `condition` holds, the code after runs when it doesn't
+    fn fail_if(&mut self, condition: Operand, message: &str, span: Span) {
+        let failed = self.new_block();
+        let ok = self.new_block();
+        self.branch(condition, failed, ok);
+        self.f.block = failed;
+        self.fail(
+            "",
+            Operand::Constant(Constant::Str(message.to_string())),
+            span,
+        );
+        self.f.block = ok;
+    }
+
+    /* Fails when a function reads a global before its definition ran. The resolver only lets a
+     * function see the globals defined before it's first called, so this is for what it missed.
+     * The top level reads its globals in order, and the functions are lowered before it, so the
+     * definitions know which globals have a flag to set. A closure made at the top level after a
+     * global's definition always sees it set.
+     */
+    fn check_set(&mut self, global: GlobalId, span: Span) {
+        if self.f.top_level || self.defined.contains(&global) {
+            return;
+        }
+        let flag = match self.set_flags.get(&global) {
+            Some(flag) => *flag,
+            None => {
+                self.globals.push(Global {
+                    name: format!("{}.set", self.globals[global].name),
+                    ty: Type::bool(),
+                });
+                self.set_flags.insert(global, self.globals.len() - 1);
+                self.globals.len() - 1
+            }
+        };
+        let set = self.assign(
+            Type::bool(),
+            Rvalue::Use(Operand::Copy(Place::Global(flag))),
+            span,
+        );
+        let unset = self.assign(Type::bool(), Rvalue::Unary(UnOp::Not, set), span);
+        let name = &self.globals[global].name;
+        self.fail_if(
+            unset,
+            &format!("`{}` is used before it has a value", name),
+            span,
+        );
+    }
+
+    // The flag a global's definition sets, when a function checks it
+    fn set_flag(&self, place: Place) -> Option<GlobalId> {
+        match place {
+            Place::Global(global) => self.set_flags.get(&global).copied(),
+            _ => None,
+        }
+    }
+
+    // Where a binding is stored, its local is made the first time it's needed
+    fn slot(&mut self, ident: &Ident) -> Result<(Place, Type), ()> {
+        let Some(id) = ident.symbol else {
+            self.unsupported("this binding", ident.span);
+            return Err(());
+        };
+        let symbol = self.symbols.get(id);
+        let t = match symbol.symbol_type.clone() {
+            Some(t) => t,
+            None if self.vm => unknown(),
+            None => {
+                self.unsupported(&format!("`{}` here", ident.name), ident.span);
+                return Err(());
+            }
+        };
+        if !self.vm && !self.compiled(&t) {
+            self.unsupported(&format!("`{}` values", t), ident.span);
+            return Err(());
+        }
+        if let Some(place) = self.lookup(ident) {
+            return Ok((place, t));
+        }
+        // a nested function's local is made when its block is entered
+        let local = matches!(
+            symbol.kind,
+            SymbolKind::Variable | SymbolKind::Parameter | SymbolKind::Function
+        );
+        if !self.vm && !local {
+            self.unsupported("functions as values", ident.span);
+            return Err(());
+        }
+        self.f.locals.push(Local {
+            name: Some(symbol.name.clone()),
+            ty: t.clone(),
+            captured: self.captured.contains(&id),
+        });
+        let local = self.f.locals.len() - 1;
+        self.f.slots.insert(id, local);
+        Ok((Place::Local(local), t))
+    }
+
+    // The function at `level`, the one being lowered is the innermost
+    fn builder(&mut self, level: usize) -> &mut Builder {
+        match self.enclosing.get_mut(level) {
+            Some(f) => f,
+            None => &mut self.f,
+        }
+    }
+
+    // Where a binding that has a place already is, without making one
+    fn lookup(&mut self, ident: &Ident) -> Option<Place> {
+        let level = self.enclosing.len();
+        ident.symbol.and_then(|id| self.resolve(level, id))
+    }
+
+    // Where the function at `level` finds a binding, capturing it from the functions around it
+    fn resolve(&mut self, level: usize, id: SymbolId) -> Option<Place> {
+        if let Some(global) = self.global_ids.get(&id) {
+            return Some(Place::Global(*global));
+        }
+        let f = self.builder(level);
+        if let Some(local) = f.slots.get(&id) {
+            return Some(Place::Local(*local));
+        }
+        if let Some(upvalue) = f.upvalue_slots.get(&id) {
+            return Some(Place::Upvalue(*upvalue));
+        }
+        if level == 0 {
+            return None;
+        }
+        let capture = match self.resolve(level - 1, id)? {
+            // `captured` missed a use, which would leave the binding unset
+            Place::Local(local) if !self.builder(level - 1).locals[local].captured => return None,
+            place => place,
+        };
+        let symbol = self.symbols.get(id);
+        let upvalue = Local {
+            name: Some(symbol.name.clone()),
+            ty: symbol.symbol_type.clone().unwrap_or_else(unknown),
+            captured: false,
+        };
+        let f = self.builder(level);
+        f.captures.push(capture);
+        f.upvalues.push(upvalue);
+        f.upvalue_slots.insert(id, f.upvalues.len() - 1);
+        Some(Place::Upvalue(f.upvalues.len() - 1))
+    }
+
+    // Whether the place lives in a cell, a local a closure shares or the closure's upvalue
+    fn in_cell(&self, place: Place) -> bool {
+        match place {
+            Place::Local(id) => self.f.locals[id].captured,
+            Place::Upvalue(_) => true,
+            Place::Global(_) => false,
+        }
+    }
+
+    // Binds a value in the VM's lowering, a definition of a binding with a place sets that place
+    fn bind(&mut self, ident: &Ident, v: Operand, span: Span) {
+        if ident.symbol.is_none() {
+            return;
+        }
+        if !self.symbols.declares(ident) {
+            if let Some(place) = self.lookup(ident) {
+                let value = Rvalue::Use(v);
+                return self.push(Statement::Assign { place, value, span });
+            }
+        }
+        self.declare(ident, v, span);
+    }
+
+    // A captured local gets a new cell every time its declaration runs
+    fn declare(&mut self, ident: &Ident, v: Operand, span: Span) {
+        let Ok((place, _)) = self.slot(ident) else {
+            return;
+        };
+        self.fresh(ident, place);
+        let value = Rvalue::Use(v);
+        self.push(Statement::Assign { place, value, span });
+    }
+
+    // The new cell of a captured local being declared, unless its block made it already
+    fn fresh(&mut self, ident: &Ident, place: Place) {
+        let fresh = ident.symbol.is_some_and(|id| self.f.fresh.remove(&id));
+        if matches!(place, Place::Local(_)) && self.in_cell(place) && !fresh {
+            self.push(Statement::Fresh(place));
+        }
+    }
+
+    // Moves a value into a binding, an object it held before is dropped, a cell drops its own
+    fn store(&mut self, place: Place, value: Operand, t: &Type, span: Span) {
+        if self.owns(t) && !self.in_cell(place) {
+            self.push(Statement::Drop(place));
+        }
+        self.push(Statement::Assign {
+            place,
+            value: Rvalue::Use(value),
+            span,
+        });
+    }
+
+    /* An int where a float is expected is converted, like a declared `float x = 1`, and an empty
+     * list or map literal gets the type it's used as
+     */
+    fn coerce(&mut self, value: Operand, t: &Type, span: Span) -> Operand {
+        if self.type_of(&value) == Type::int() && *t == Type::float() {
+            return self.assign(Type::float(), Rvalue::Cast(value, Type::float()), span);
+        }
+        if let (Operand::Move(Place::Local(id)), false) = (&value, self.vm) {
+            if self.f.locals[*id].ty.contains_vars() {
+                self.f.locals[*id].ty = t.clone();
+            }
+        }
+        value
+    }
+
+    // The type of a literal's elements, `[1, 2.5]` is a list of floats
+    fn common_type(&self, values: &[Operand]) -> Type {
+        let types: Vec<Type> = values.iter().map(|v| self.type_of(v)).collect();
+        match types.iter().find(|t| !t.contains_vars()) {
+            Some(t) if *t == Type::int() && types.contains(&Type::float()) => Type::float(),
+            Some(t) => t.clone(),
+            None => types.first().cloned().unwrap_or_else(unknown),
+        }
+    }
+
+    // An expression whose value is needed
+    fn value(&mut self, expression: &Expression) -> Result<Operand, ()> {
+        match self.expression(expression)? {
+            Some(v) => Ok(v),
+            // what has no value is unit to the VM, ie. a definition or a loop
+            None if self.vm => Ok(Operand::Constant(Constant::Unit)),
+            None => {
+                self.unsupported("using this as a value", expression.span());
+                Err(())
+            }
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Result<Option<Operand>, ()> {
+        match expression {
+            Expression::Literal { value, .. } => Ok(Some(Operand::Constant(constant(value)))),
+            Expression::Definition {
+                identifier,
+                value,
+                span,
+                ..
+            } => {
+                let v = self.value(value)?;
+                if self.vm {
+                    self.bind(identifier, v, *span);
+                    return Ok(None);
+                }
+                if identifier.name == "_" {
+                    self.discard(Some(v));
+                    return Ok(None);
+                }
+                let (place, t) = self.slot(identifier)?;
+                let v = self.coerce(v, &t, *span);
+                if self.symbols.declares(identifier) {
+                    self.fresh(identifier, place);
+                }
+                self.store(place, v, &t, *span);
+                if let Place::Global(global) = place {
+                    self.defined.insert(global);
+                }
+                let set_flag = self.set_flag(place);
+                if let Some(flag) = set_flag.filter(|_| self.symbols.declares(identifier)) {
+                    self.push(Statement::Assign {
+                        place: Place::Global(flag),
+                        value: Rvalue::Use(Operand::Constant(Constant::Bool(true))),
+                        span: *span,
+                    });
+                }
+                Ok(None)
+            }
+            Expression::Assignment {
+                target,
+                value,
+                span,
+            } if self.vm => self.set_path(target, value, *span).map(|_| None),
+            Expression::Assignment {
+                target,
+                value,
+                span,
+            } => {
+                let Expression::Identifier(ident) = target.as_ref() else {
+                    return self.assign_path(target, value, *span).map(|_| None);
+                };
+                let v = self.value(value)?;
+                let (place, t) = self.slot(ident)?;
+                let v = self.coerce(v, &t, *span);
+                self.store(place, v, &t, *span);
+                Ok(None)
+            }
+            Expression::Identifier(ident) if self.vm => Ok(Some(self.identifier(ident))),
+            Expression::Identifier(ident) => {
+                let kind = ident.symbol.map(|id| self.symbols.get(id).kind);
+                if kind == Some(SymbolKind::Function) && self.lookup(ident).is_none() {
+                    return self.function_value(ident).map(Some);
+                }
+                let (place, t) = self.slot(ident)?;
+                if let Place::Global(global) = place {
+                    self.check_set(global, ident.span);
+                }
+                Ok(Some(self.assign(
+                    t,
+                    Rvalue::Use(Operand::Copy(place)),
+                    ident.span,
+                )))
+            }
+            Expression::Calculation {
+                left,
+                operator,
+                right,
+                span,
+            } => self.calculation(left, *operator, right, *span).map(Some),
+            Expression::Unary {
+                operator,
+                operand,
+                span,
+            } => self.unary(*operator, operand, *span).map(Some),
+            Expression::FunctionCall {
+                function_name,
+                parameters,
+                span,
+                ..
+            } => self.call(function_name, parameters, *span),
+            Expression::Block { expressions, .. } => {
+                self.nested_functions(expressions);
+                let mut last = None;
+                for e in expressions.iter() {
+                    if let Expression::Function(_) = e {
+                        // made when the block was entered, and it's a statement
+                        self.discard(last.take());
+                        continue;
+                    }
+                    let v = self.expression(e)?;
+                    let before = std::mem::replace(&mut last, v);
+                    self.discard(before);
+                }
+                Ok(last)
+            }
+            Expression::If {
+                branches,
+                else_branch,
+                ..
+            } => self.branches(branches, else_branch.as_deref(), None),
+            Expression::Cond { arms, span } => self.branches(arms, None, Some(*span)),
+            Expression::While {
+                condition, body, ..
+            } => {
+                let check = self.new_block();
+                let run = self.new_block();
+                let end = self.new_block();
+                self.start(check);
+                let c = self.value(condition)?;
+                self.branch(c, run, end);
+                self.f.block = run;
+                self.f.loops.push((check, end, false));
+                let generated = self.expression(body);
+                self.f.loops.pop();
+                self.discard(generated?);
+                self.goto(check);
+                self.f.block = end;
+                Ok(None)
+            }
+            Expression::For {
+                variable,
+                iterable,
+                body,
+                span,
+            } if self.vm => self.for_each(variable, iterable, body, *span),
+            Expression::For {
+                variable,
+                iterable,
+                body,
+                span,
+            } => self.for_range(variable, iterable, body, *span),
+            Expression::Break { span } | Expression::Continue { span } => {
+                let Some((next, end, iterates)) = self.f.loops.last().copied() else {
+                    // the interpreter's escapes the function, whose value is unit then
+                    if self.vm {
+                        return Ok(None);
+                    }
+                    self.unsupported("this outside a loop", *span);
+                    return Err(());
+                };
+                let target = match expression {
+                    Expression::Break { .. } => {
+                        if iterates {
+                            self.push(Statement::EndIterate);
+                        }
+                        end
+                    }
+                    _ => next,
+                };
+                self.goto(target);
+                Ok(None)
+            }
+            Expression::Return { value, span } => {
+                let value = match value {
+                    Some(v) => self.expression(v)?,
+                    None => None,
+                };
+                self.ret(value, *span);
+                Ok(None)
+            }
+            Expression::Panic { message, span } => {
+                let m = self.value(message)?;
+                if !self.vm && self.type_of(&m) != Type::string() {
+                    self.unsupported("panicking with anything but a `str`", message.span());
+                    return Err(());
+                }
+                self.panic("panic: ", m, *span);
+                Ok(None)
+            }
+            Expression::Assert {
+                condition,
+                message,
+                span,
+            } => self
+                .assert(condition, message.as_deref(), *span)
+                .map(|_| None),
+            // `rho test` runs these, and declarations have no code of their own
+            Expression::Test { .. }
+            | Expression::Import { .. }
+            | Expression::Struct(_)
+            | Expression::Enum(_)
+            | Expression::Interface(_) => Ok(None),
+            // a nested one is made when its block is entered, a top level one isn't a statement
+            Expression::Function(_) if self.vm => Ok(None),
+            Expression::Function(decl) => {
+                self.unsupported("functions inside blocks", decl.span);
+                Err(())
+            }
+            e if self.vm => self.dynamic(e).map(Some),
+            e => {
+                let what = match e {
+                    Expression::List { .. }
+                    | Expression::Tuple { .. }
+                    | Expression::Map { .. }
+                    | Expression::StructLiteral { .. } => return self.literal(e).map(Some),
+                    Expression::FieldAccess { .. } | Expression::Index { .. } => {
+                        return self.element(e).map(Some)
+                    }
+                    Expression::Lambda { params, body, span } => {
+                        let name = "fn".to_string();
+                        return self.closure(name, params, body, unknown(), *span).map(Some);
+                    }
+                    Expression::Range { .. } => "ranges outside of `for` loops",
+                    Expression::Match { .. } => "`match`",
+                    Expression::Try { .. } => "`?`",
+                    _ => "this expression",
+                };
+                self.unsupported(what, e.span());
+                Err(())
+            }
+        }
+    }
+
+    /* A list, tuple, map or struct literal, a new object that takes over its elements. A struct's
+     * fields are in the order it declares them, its type arguments are the ones its values have
+     * when it isn't given them.
+     */
+    fn literal(&mut self, e: &Expression) -> Result<Operand, ()> {
+        let span = e.span();
+        let (t, value) = match e {
+            Expression::List { elements, .. } => {
+                let values = self.values(elements)?;
+                let element = self.common_type(&values);
+                let values = (values.into_iter())
+                    .map(|v| self.coerce(v, &element, span))
+                    .collect();
+                (Type::list(element), Rvalue::List(values))
+            }
+            Expression::Tuple { elements, .. } => {
+                let values = self.values(elements)?;
+                let types = values.iter().map(|v| self.type_of(v)).collect();
+                (Type::tuple(types), Rvalue::Tuple(values))
+            }
+            Expression::Map { entries, .. } => {
+                let mut keys = vec![];
+                let mut values = vec![];
+                for (key, value) in entries.iter() {
+                    keys.push(self.value(key)?);
+                    values.push(self.value(value)?);
+                }
+                let (k, v) = (self.common_type(&keys), self.common_type(&values));
+                let entries = (keys.into_iter().zip(values))
+                    .map(|(key, value)| (key, self.coerce(value, &v, span)))
+                    .collect();
+                (Type::map(k, v), Rvalue::Map(entries))
+            }
+            Expression::StructLiteral {
+                name,
+                type_args,
+                fields,
+                ..
+            } => {
+                let mut given = HashMap::new();
+                for (field, value) in fields.iter() {
+                    let v = self.value(value)?;
+                    given.insert(field.name.clone(), v);
+                }
+                let Some(decl) = self.structs.iter().find(|s| s.name == name.name).cloned() else {
+                    self.unsupported("this struct", name.span);
+                    return Err(());
+                };
+                let mut s: HashMap<String, Type> = (decl.type_params.iter().cloned())
+                    .zip(type_args.iter().cloned())
+                    .collect();
+                for (field, t) in decl.fields.iter() {
+                    if let Some(v) = given.get(field) {
+                        bind(t, &self.type_of(v), &mut s);
+                    }
+                }
+                let args = decl.type_params.iter();
+                let args = args.map(|p| s.get(p).cloned().unwrap_or_else(unknown));
+                let t = Type::Named(name.name.clone(), args.collect());
+                let mut values = vec![];
+                for (field, field_type) in decl.fields.iter() {
+                    if let Some(v) = given.remove(field) {
+                        let v = self.coerce(v, &field_type.substitute(&s), span);
+                        values.push((field.clone(), v));
+                    }
+                }
+                (t, Rvalue::Struct(name.name.clone(), values))
+            }
+            _ => unreachable!("only literals of objects are lowered here"),
+        };
+        if !self.compiled(&t) {
+            self.unsupported(&format!("`{}` values", t), span);
+            return Err(());
+        }
+        Ok(self.assign(t, value, span))
+    }
+
+    // `object.field` and `object[index]`, a new reference to the element
+    fn element(&mut self, e: &Expression) -> Result<Operand, ()> {
+        let (o, value, t, span) = match e {
+            Expression::FieldAccess {
+                object,
+                field,
+                span,
+            } => {
+                if let Expression::Identifier(ident) = object.as_ref() {
+                    let kind = ident.symbol.map(|id| self.symbols.get(id).kind);
+                    if kind == Some(SymbolKind::Enum) {
+                        self.unsupported("enums", *span);
+                        return Err(());
+                    }
+                }
+                let o = self.value(object)?;
+                let t = self.type_of(&o);
+                let Some((_, element)) = fields(&self.structs, &t)
+                    .and_then(|fields| fields.into_iter().find(|(f, _)| *f == field.name))
+                else {
+                    self.unsupported(&format!("the fields of `{}`", t), *span);
+                    return Err(());
+                };
+                let value = Rvalue::Field(Self::borrow(&o), field.name.clone());
+                (o, value, element, *span)
+            }
+            Expression::Index {
+                object,
+                index,
+                span,
+            } => {
+                let o = self.value(object)?;
+                let i = self.value(index)?;
+                let t = self.type_of(&o);
+                let element = match &t {
+                    Type::BuiltIn(BuiltinType::List | BuiltinType::Map, args) => args.last(),
+                    _ => None,
+                };
+                let Some(element) = element.cloned() else {
+                    self.unsupported(&format!("indexing a `{}`", t), *span);
+                    return Err(());
+                };
+                let value = Rvalue::Index(Self::borrow(&o), Self::borrow(&i));
+                let v = self.assign(element, value, *span);
+                self.discard(Some(o));
+                self.discard(Some(i));
+                return Ok(v);
+            }
+            _ => unreachable!("only fields and elements are lowered here"),
+        };
+        let v = self.assign(t, value, span);
+        self.discard(Some(o));
+        Ok(v)
+    }
+
+    /* `p.x = v` and `xs[i][j] = v`, the value first and then the indexes like the VM's
+     * `set_path`. The objects on the path are made unshared as it goes, so the change is only
+     * seen through the binding.
+     */
+    fn assign_path(
+        &mut self,
+        target: &Expression,
+        value: &Expression,
+        span: Span,
+    ) -> Result<(), ()> {
+        let v = self.value(value)?;
+        let mut path = vec![];
+        let mut root = target;
+        loop {
+            match root {
+                Expression::FieldAccess { object, field, .. } => {
+                    path.push(Step::Field(field.name.clone()));
+                    root = object;
+                }
+                Expression::Index { object, index, .. } => {
+                    path.push(Step::Index(self.value(index)?));
+                    root = object;
+                }
+                _ => break,
+            }
+        }
+        path.reverse();
+        let Expression::Identifier(ident) = root else {
+            self.unsupported("assigning to a temporary value", root.span());
+            return Err(());
+        };
+        let (place, mut t) = self.slot(ident)?;
+        if let Place::Global(global) = place {
+            self.check_set(global, ident.span);
+        }
+        for step in path.iter() {
+            let element = match (step, &t) {
+                (Step::Field(field), _) => fields(&self.structs, &t)
+                    .and_then(|fields| fields.into_iter().find(|(f, _)| f == field))
+                    .map(|(_, t)| t),
+                (Step::Index(_), Type::BuiltIn(BuiltinType::List | BuiltinType::Map, args)) => {
+                    args.last().cloned()
+                }
+                _ => None,
+            };
+            let Some(element) = element else {
+                self.unsupported(&format!("assigning inside a `{}`", t), target.span());
+                return Err(());
+            };
+            t = element;
+        }
+        let v = self.coerce(v, &t, span);
+        let indexes: Vec<Operand> = (path.iter_mut())
+            .filter_map(|step| match step {
+                Step::Index(i) => Some(std::mem::replace(i, Self::borrow(i))),
+                Step::Field(_) => None,
+            })
+            .collect();
+        self.push(Statement::SetPath {
+            place,
+            path,
+            value: v,
+            span,
+        });
+        for i in indexes {
+            self.discard(Some(i));
+        }
+        Ok(())
+    }
+
+    fn calculation(
+        &mut self,
+        left: &Expression,
+        operator: Operators,
+        right: &Expression,
+        span: Span,
+    ) -> Result<Operand, ()> {
+        if matches!(operator, Operators::And | Operators::Or) {
+            return self.short_circuit(left, operator, right, span);
+        }
+        let l = self.value(left)?;
+        let r = self.value(right)?;
+        let (l, r) = match self.vm {
+            true => (l, r),
+            false => self.promote(l, r, span),
+        };
+        let v = self.binary(operator, Self::borrow(&l), Self::borrow(&r), span)?;
+        self.discard(Some(l));
+        self.discard(Some(r));
+        Ok(v)
+    }
+
+    // `1 + 2.5` is a float calculation, and `[] ++ xs` one of the lists `xs` is
+    fn promote(&mut self, l: Operand, r: Operand, span: Span) -> (Operand, Operand) {
+        let (lt, rt) = (self.type_of(&l), self.type_of(&r));
+        if lt == Type::float() || rt == Type::float() {
+            let l = self.coerce(l, &Type::float(), span);
+            (l, self.coerce(r, &Type::float(), span))
+        } else if lt.contains_vars() {
+            (self.coerce(l, &rt, span), r)
+        } else {
+            let r = self.coerce(r, &lt, span);
+            (l, r)
+        }
+    }
+
+    // The value of `l operator r`, which only looks at its operands
+    fn binary(
+        &mut self,
+        operator: Operators,
+        l: Operand,
+        r: Operand,
+        span: Span,
+    ) -> Result<Operand, ()> {
+        if self.vm {
+            return Ok(self.dynamic_binary(operator, l, r, span));
+        }
+        let t = self.type_of(&l);
+        let ordered = !matches!(operator, Operators::BEq | Operators::BNEq);
+        let (op, result) = match operator {
+            Operators::BEq
+            | Operators::BNEq
+            | Operators::LessThan
+            | Operators::GreaterThan
+            | Operators::LEq
+            | Operators::GEq => {
+                let comparable = t == Type::int()
+                    || t == Type::float()
+                    || t == Type::char()
+                    || t == Type::string()
+                    || ((t == Type::bool() || t == Type::atom()) && !ordered);
+                let op = match operator {
+                    Operators::BEq => BinOp::Eq,
+                    Operators::BNEq => BinOp::Ne,
+                    Operators::LessThan => BinOp::Lt,
+                    Operators::GreaterThan => BinOp::Gt,
+                    Operators::LEq => BinOp::Le,
+                    _ => BinOp::Ge,
+                };
+                (comparable.then_some(op), Type::bool())
+            }
+            Operators::Concat if t == Type::string() => (Some(BinOp::Concat), t.clone()),
+            Operators::EnumConcat if matches!(t, Type::BuiltIn(BuiltinType::List, _)) => {
+                (Some(BinOp::Append), t.clone())
+            }
+            _ if t == Type::int() || t == Type::float() => {
+                let op = match operator {
+                    Operators::Add => Some(BinOp::Add),
+                    Operators::Subtract => Some(BinOp::Sub),
+                    Operators::Mult => Some(BinOp::Mul),
+                    Operators::Div => Some(BinOp::Div),
+                    Operators::Modulo => Some(BinOp::Rem),
+                    Operators::Exp => Some(BinOp::Pow),
+                    Operators::Lshift if t == Type::int() => Some(BinOp::Shl),
+                    Operators::Rshift if t == Type::int() => Some(BinOp::Shr),
+                    _ => None,
+                };
+                (op, t.clone())
+            }
+            _ => (None, t.clone()),
+        };
+        let Some(op) = op else {
+            return self.unsupported_operator(operator, &t, span);
+        };
+        Ok(self.assign(result, Rvalue::Binary(op, l, r), span))
+    }
+
+    // `l operator r` in the VM's lowering, which fails like the interpreter's on the wrong types
+    fn dynamic_binary(
+        &mut self,
+        operator: Operators,
+        l: Operand,
+        r: Operand,
+        span: Span,
+    ) -> Operand {
+        let op = match operator {
+            Operators::Add => BinOp::Add,
+            Operators::Subtract => BinOp::Sub,
+            Operators::Mult => BinOp::Mul,
+            Operators::Div => BinOp::Div,
+            Operators::Modulo => BinOp::Rem,
+            Operators::Exp => BinOp::Pow,
+            Operators::Lshift => BinOp::Shl,
+            Operators::Rshift => BinOp::Shr,
+            Operators::Concat => BinOp::Concat,
+            Operators::EnumConcat => BinOp::Append,
+            Operators::BEq => BinOp::Eq,
+            Operators::BNEq => BinOp::Ne,
+            Operators::LessThan => BinOp::Lt,
+            Operators::GreaterThan => BinOp::Gt,
+            Operators::LEq => BinOp::Le,
+            Operators::GEq => BinOp::Ge,
+            _ => {
+                let message = format!(
+                    "`{}` can't be applied to these values",
+                    operator_to_string(operator)
+                );
+                return self.unreachable_fail(&message, span);
+            }
+        };
+        let (lt, rt) = (self.type_of(&l), self.type_of(&r));
+        let t = match op {
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Gt | BinOp::Le | BinOp::Ge => Type::bool(),
+            _ if lt == rt => lt,
+            _ => unknown(),
+        };
+        self.assign(t, Rvalue::Binary(op, l, r), span)
+    }
+
+    // A runtime error the checker lets through in the VM's lowering, the code after has unit
+    fn unreachable_fail(&mut self, message: &str, span: Span) -> Operand {
+        let message = Operand::Constant(Constant::Str(message.to_string()));
+        self.fail("", message, span);
+        Operand::Constant(Constant::Unit)
+    }
+
+    /* `assert`, a failed comparison shows its operands like the interpreter's do, after the
+     * message when there is one:
+     *
+     *     assertion failed: too many, `3 == 2`
+     */
+    fn assert(
+        &mut self,
+        condition: &Expression,
+        message: Option<&Expression>,
+        span: Span,
+    ) -> Result<(), ()> {
+        let compared = match condition {
+            Expression::Calculation {
+                left,
+                operator:
+                    operator @ (Operators::BEq
+                    | Operators::BNEq
+                    | Operators::LessThan
+                    | Operators::GreaterThan
+                    | Operators::LEq
+                    | Operators::GEq),
+                right,
+                span,
+            } => {
+                let l = self.value(left)?;
+                let r = self.value(right)?;
+                let c = self.binary(*operator, Self::borrow(&l), Self::borrow(&r), *span)?;
+                Some((c, l, *operator, r))
+            }
+            _ => None,
+        };
+        let c = match &compared {
+            Some((c, ..)) => c.clone(),
+            None => self.value(condition)?,
+        };
+        let failed = self.new_block();
+        let ok = self.new_block();
+        self.branch(c, ok, failed);
+        self.f.block = failed;
+        let mut text = match message {
+            Some(message) => Some(self.value(message)?),
+            None => None,
+        };
+        if let Some((_, l, operator, r)) = &compared {
+            let shown = self.show(Self::borrow(l), span);
+            let op = format!(" {} ", operator_to_string(*operator));
+            let shown = self.concat(
+                Operand::Constant(Constant::Str("`".to_string())),
+                shown,
+                span,
+            );
+            let shown = self.concat(shown, Operand::Constant(Constant::Str(op)), span);
+            let r = self.show(Self::borrow(r), span);
+            let shown = self.concat(shown, r, span);
+            let shown = self.concat(
+                shown,
+                Operand::Constant(Constant::Str("`".to_string())),
+                span,
+            );
+            text = Some(match text {
+                Some(m) => {
+                    let m =
+                        self.concat(m, Operand::Constant(Constant::Str(", ".to_string())), span);
+                    self.concat(m, shown, span)
+                }
+                None => shown,
+            });
+        }
+        match text {
+            Some(m) => self.panic("assertion failed: ", m, span),
+            None => {
+                let m = Operand::Constant(Constant::Str("assertion failed".to_string()));
+                self.panic("", m, span);
+            }
+        }
+        self.f.block = ok;
+        if let Some((_, l, _, r)) = compared {
+            self.discard(Some(l));
+            self.discard(Some(r));
+        }
+        Ok(())
+    }
+
+    // `IO.inspect`'s text of a value, a new str
+    fn show(&mut self, v: Operand, span: Span) -> Operand {
+        let result = Place::Local(self.temp(Type::string()));
+        self.push(Statement::Call {
+            result: Some(result),
+            callee: Callee::Show,
+            args: vec![v],
+            span,
+        });
+        Operand::Move(result)
+    }
+
+    // `a <> b` of two strs the code is done with
+    fn concat(&mut self, a: Operand, b: Operand, span: Span) -> Operand {
+        let value = Rvalue::Binary(BinOp::Concat, Self::borrow(&a), Self::borrow(&b));
+        let v = self.assign(Type::string(), value, span);
+        self.discard(Some(a));
+        self.discard(Some(b));
+        v
+    }
+
+    fn unsupported_operator(
+        &mut self,
+        operator: Operators,
+        t: &Type,
+        span: Span,
+    ) -> Result<Operand, ()> {
+        self.unsupported(
+            &format!("`{}` on `{}`", operator_to_string(operator), t),
+            span,
+        );
+        Err(())
+    }
+
+    // `&&` and `||` only evaluate their right side when they need it
+    fn short_circuit(
+        &mut self,
+        left: &Expression,
+        operator: Operators,
+        right: &Expression,
+        span: Span,
+    ) -> Result<Operand, ()> {
+        let result = Place::Local(self.temp(Type::bool()));
+        let l = self.value(left)?;
+        self.push(Statement::Assign {
+            place: result,
+            value: Rvalue::Use(l.clone()),
+            span,
+        });
+        let rest = self.new_block();
+        let end = self.new_block();
+        match operator {
+            Operators::And => self.branch(l, rest, end),
+            _ => self.branch(l, end, rest),
+        }
+        self.f.block = rest;
+        let r = self.value(right)?;
+        self.push(Statement::Assign {
+            place: result,
+            value: Rvalue::Use(r),
+            span,
+        });
+        self.start(end);
+        Ok(Operand::Move(result))
+    }
+
+    fn unary(
+        &mut self,
+        operator: Operators,
+        operand: &Expression,
+        span: Span,
+    ) -> Result<Operand, ()> {
+        let v = self.value(operand)?;
+        let t = self.type_of(&v);
+        if self.vm {
+            let op = match operator {
+                Operators::Not => UnOp::Not,
+                _ => UnOp::Neg,
+            };
+            return Ok(self.assign(t, Rvalue::Unary(op, v), span));
+        }
+        let op = match operator {
+            Operators::Not if t == Type::bool() => UnOp::Not,
+            Operators::Subtract if t == Type::int() || t == Type::float() => UnOp::Neg,
+            _ => return self.unsupported_operator(operator, &t, span),
+        };
+        Ok(self.assign(t, Rvalue::Unary(op, v), span))
+    }
+
+    /* `if` and `cond`, each branch moves its value into a temporary that is the value of them all
+     * when every branch has one. A `cond` without a true condition fails at `no_match`.
+     */
+    fn branches(
+        &mut self,
+        branches: &[(Expression, Expression)],
+        else_branch: Option<&Expression>,
+        no_match: Option<Span>,
+    ) -> Result<Option<Operand>, ()> {
+        let end = self.new_block();
+        let mut result: Option<Place> = None;
+        let mut every_branch_has_value = true;
+        for (condition, body) in branches.iter() {
+            let c = self.value(condition)?;
+            let then = self.new_block();
+            let next = self.new_block();
+            self.branch(c, then, next);
+            self.f.block = then;
+            let v = self.expression(body)?;
+            every_branch_has_value &= self.store_branch(v, &mut result, body.span());
+            self.goto(end);
+            self.f.block = next;
+        }
+        match (else_branch, no_match) {
+            (Some(e), _) => {
+                let v = self.expression(e)?;
+                every_branch_has_value &= self.store_branch(v, &mut result, e.span());
+            }
+            (None, Some(span)) => {
+                let m = Operand::Constant(Constant::Str("no `cond` condition is true".to_string()));
+                self.fail("", m, span);
+            }
+            // unit for the VM when no condition holds
+            (None, None) if self.vm => {
+                self.store_branch(None, &mut result, Span::default());
+            }
+            (None, None) => every_branch_has_value = false,
+        }
+        self.start(end);
+        match result {
+            Some(place) if every_branch_has_value => Ok(Some(Operand::Move(place))),
+            // the branches that had a value stored it, and nothing will use it
+            Some(place) => {
+                self.discard(Some(Operand::Move(place)));
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Moves a branch's value into the result of its `if`, false when it has none
+    fn store_branch(
+        &mut self,
+        value: Option<Operand>,
+        result: &mut Option<Place>,
+        span: Span,
+    ) -> bool {
+        if self.terminated() {
+            // it returned or broke out, there's nothing to store
+            return true;
+        }
+        let v = match value {
+            Some(v) => v,
+            None if self.vm => Operand::Constant(Constant::Unit),
+            None => return false,
+        };
+        let t = self.type_of(&v);
+        if result.is_none() {
+            *result = Some(Place::Local(self.temp(t.clone())));
+        }
+        let place = result.unwrap();
+        if t != self.type_of(&Operand::Move(place)) {
+            // the VM's values are whatever the branch gives
+            if let (true, Place::Local(id)) = (self.vm, place) {
+                self.f.locals[id].ty = unknown();
+            } else {
+                self.discard(Some(v));
+                return false;
+            }
+        }
+        self.push(Statement::Assign {
+            place,
+            value: Rvalue::Use(v),
+            span,
+        });
+        true
+    }
+
+    // `for i in start..end..step`, like `value::range` the end isn't included
+    fn for_range(
+        &mut self,
+        variable: &Ident,
+        iterable: &Expression,
+        body: &Expression,
+        span: Span,
+    ) -> Result<Option<Operand>, ()> {
+        let Expression::Range {
+            start, end, step, ..
+        } = iterable
+        else {
+            return self.for_elements(variable, iterable, body, span);
+        };
+        let first = self.value(start)?;
+        let last = self.value(end)?;
+        let step = match step {
+            Some(s) => {
+                let step = self.value(s)?;
+                let zero = Rvalue::Binary(
+                    BinOp::Eq,
+                    Self::borrow(&step),
+                    Operand::Constant(Constant::Int(0)),
+                );
+                let zero = self.assign(Type::bool(), zero, iterable.span());
+                self.fail_if(zero, "a range can't have a step of 0", iterable.span());
+                step
+            }
+            None => Operand::Constant(Constant::Int(1)),
+        };
+        let counter = Place::Local(self.temp(Type::int()));
+        self.push(Statement::Assign {
+            place: counter,
+            value: Rvalue::Use(first),
+            span,
+        });
+        let check = self.new_block();
+        let run = self.new_block();
+        let advance = self.new_block();
+        let done = self.new_block();
+        self.start(check);
+        let current = Operand::Copy(counter);
+        let step_copy = Self::borrow(&step);
+        let last_copy = Self::borrow(&last);
+        let in_range = match step_copy {
+            // the usual `start..end`
+            Operand::Constant(Constant::Int(1)) => {
+                let below = Rvalue::Binary(BinOp::Lt, current.clone(), last_copy);
+                self.assign(Type::bool(), below, span)
+            }
+            _ => {
+                let zero = Operand::Constant(Constant::Int(0));
+                let up = Rvalue::Binary(BinOp::Gt, step_copy.clone(), zero);
+                let up = self.assign(Type::bool(), up, span);
+                let below = Rvalue::Binary(BinOp::Lt, current.clone(), last_copy.clone());
+                let below = self.assign(Type::bool(), below, span);
+                let above = Rvalue::Binary(BinOp::Gt, current.clone(), last_copy);
+                let above = self.assign(Type::bool(), above, span);
+                self.assign(Type::bool(), Rvalue::Select(up, below, above), span)
+            }
+        };
+        self.branch(in_range, run, done);
+        self.f.block = run;
+        if variable.name != "_" {
+            let (place, _) = self.slot(variable)?;
+            // a closure made in the body shares the value of its own pass
+            self.fresh(variable, place);
+            self.push(Statement::Assign {
+                place,
+                value: Rvalue::Use(current.clone()),
+                span: variable.span,
+            });
+        }
+        self.f.loops.push((advance, done, false));
+        let generated = self.expression(body);
+        self.f.loops.pop();
+        self.discard(generated?);
+        self.start(advance);
+        // the range ends at the last value before an overflow
+        let overflow = Rvalue::Binary(BinOp::AddOverflows, current.clone(), step_copy.clone());
+        let overflow = self.assign(Type::bool(), overflow, span);
+        self.push(Statement::Assign {
+            place: counter,
+            value: Rvalue::Binary(BinOp::WrappingAdd, current, step_copy),
+            span,
+        });
+        self.branch(overflow, done, check);
+        self.f.block = done;
+        Ok(None)
+    }
+
+    /* `for x in xs` over a list, or over a map's `{key, value}` entries as `Map.to_list` has them.
+     * A binding of its own holds the list while the loop runs, so a `return` inside drops it. A
+     * stream is pulled from by `for_stream` instead.
+     */
+    fn for_elements(
+        &mut self,
+        variable: &Ident,
+        iterable: &Expression,
+        body: &Expression,
+        span: Span,
+    ) -> Result<Option<Operand>, ()> {
+        let v = self.value(iterable)?;
+        let v = match self.type_of(&v) {
+            Type::BuiltIn(BuiltinType::List, _) => v,
+            Type::BuiltIn(BuiltinType::Stream, _) => {
+                return self.for_stream(variable, v, body, span)
+            }
+            Type::BuiltIn(BuiltinType::Map, _) => {
+                self.runtime_call("Map.to_list".to_string(), vec![v], span)?
+            }
+            t => {
+                self.unsupported(&format!("`for` loops over a `{}`", t), iterable.span());
+                return Err(());
+            }
+        };
+        let t = self.type_of(&v);
+        let element = t.element_type().unwrap_or_else(unknown);
+        self.f.locals.push(Local {
+            name: Some("for".to_string()),
+            ty: t.clone(),
+            captured: false,
+        });
+        let list = Place::Local(self.f.locals.len() - 1);
+        self.store(list, v, &t, span);
+        let count = vec![Operand::Copy(list)];
+        let len = self.runtime_call("List.count".to_string(), count, span)?;
+        let counter = Place::Local(self.temp(Type::int()));
+        self.push(Statement::Assign {
+            place: counter,
+            value: Rvalue::Use(Operand::Constant(Constant::Int(0))),
+            span,
+        });
+        let check = self.new_block();
+        let run = self.new_block();
+        let advance = self.new_block();
+        let done = self.new_block();
+        self.start(check);
+        let below = Rvalue::Binary(BinOp::Lt, Operand::Copy(counter), len);
+        let below = self.assign(Type::bool(), below, span);
+        self.branch(below, run, done);
+        self.f.block = run;
+        if variable.name != "_" {
+            let (place, t) = self.slot(variable)?;
+            self.fresh(variable, place);
+            let value = Rvalue::Index(Operand::Copy(list), Operand::Copy(counter));
+            let v = self.assign(element, value, variable.span);
+            self.store(place, v, &t, variable.span);
+        }
+        self.f.loops.push((advance, done, false));
+        let generated = self.expression(body);
+        self.f.loops.pop();
+        self.discard(generated?);
+        self.start(advance);
+        let next = Rvalue::Binary(
+            BinOp::WrappingAdd,
+            Operand::Copy(counter),
+            Operand::Constant(Constant::Int(1)),
+        );
+        self.push(Statement::Assign {
+            place: counter,
+            value: next,
+            span,
+        });
+        self.goto(check);
+        self.f.block = done;
+        self.push(Statement::Drop(list));
+        Ok(None)
+    }
+
+    /* `for x in s` over a stream, which pulls one element for each pass through the body. The
+     * runtime's cursor for it is held by a binding of its own like `for_elements`' list is.
+     */
+    fn for_stream(
+        &mut self,
+        variable: &Ident,
+        stream: Operand,
+        body: &Expression,
+        span: Span,
+    ) -> Result<Option<Operand>, ()> {
+        let t = self.type_of(&stream);
+        let element = t.element_type().unwrap_or_else(unknown);
+        self.f.locals.push(Local {
+            name: Some("for".to_string()),
+            ty: t.clone(),
+            captured: false,
+        });
+        let cursor = Place::Local(self.f.locals.len() - 1);
+        let args = vec![Self::borrow(&stream)];
+        let pass = self.call_value(
+            t.clone(),
+            Callee::Builtin("Stream.cursor".to_string()),
+            args,
+            span,
+        );
+        self.discard(Some(stream));
+        self.store(cursor, pass, &t, span);
+        let check = self.new_block();
+        let run = self.new_block();
+        let done = self.new_block();
+        self.start(check);
+        let next = Callee::Builtin("Stream.next".to_string());
+        let more = self.call_value(Type::bool(), next, vec![Operand::Copy(cursor)], span);
+        self.branch(more, run, done);
+        self.f.block = run;
+        if variable.name != "_" {
+            let (place, t) = self.slot(variable)?;
+            self.fresh(variable, place);
+            let current = Callee::Builtin("Stream.current".to_string());
+            let v = self.call_value(element, current, vec![Operand::Copy(cursor)], variable.span);
+            self.store(place, v, &t, variable.span);
+        }
+        self.f.loops.push((check, done, false));
+        let generated = self.expression(body);
+        self.f.loops.pop();
+        self.discard(generated?);
+        self.goto(check);
+        self.f.block = done;
+        self.push(Statement::Drop(cursor));
+        Ok(None)
+    }
+
+    fn call(
+        &mut self,
+        name: &FunctionName,
+        parameters: &[Expression],
+        span: Span,
+    ) -> Result<Option<Operand>, ()> {
+        if self.vm {
+            return self.dynamic_call(name, parameters, span).map(Some);
+        }
+        let path = name.path();
+        let first = &name.idents[0];
+        let kind = first.symbol.map(|id| self.symbols.get(id).kind);
+        // a closure held by a binding or a nested function
+        let held = match kind {
+            Some(SymbolKind::Variable | SymbolKind::Parameter) => Some(self.slot(first)?.0),
+            Some(SymbolKind::Function) => self.lookup(first),
+            _ => None,
+        };
+        if let Some(place) = held {
+            if name.idents.len() > 1 {
+                self.unsupported("calling a function held by a field", span);
+                return Err(());
+            }
+            return self.call_closure(place, parameters, span);
+        }
+        if path.starts_with("Stream.") {
+            return self.stream_call(path, parameters, span);
+        }
+        let mut args = vec![];
+        for p in parameters.iter() {
+            args.push(self.value(p)?);
+        }
+        if let Some((params, ret)) = self.functions.get(&path).cloned() {
+            let args: Vec<Operand> = args
+                .into_iter()
+                .zip(params.iter())
+                .map(|(a, t)| self.coerce(a, t, span))
+                .collect();
+            let result = (ret != Type::Unit).then(|| Place::Local(self.temp(ret)));
+            self.push(Statement::Call {
+                result,
+                callee: Callee::Function(path),
+                args,
+                span,
+            });
+            return Ok(result.map(Operand::Move));
+        }
+        if RUNTIME_BUILTINS.iter().any(|(name, _)| *name == path) {
+            return self.runtime_call(path, args, span).map(Some);
+        }
+        if let ("int" | "float", [_]) = (path.as_str(), args.as_slice()) {
+            return self.convert(&path, args.pop().unwrap(), span).map(Some);
+        }
+        let callee = match (path.as_str(), args.as_slice()) {
+            ("IO.puts", [_]) => Callee::Puts,
+            ("IO.print", [_]) => Callee::Print,
+            ("IO.inspect", [_]) => Callee::Inspect,
+            _ => {
+                self.unsupported(&format!("calling `{}`", path), span);
+                return Err(());
+            }
+        };
+        let v = args.pop().unwrap();
+        let t = self.type_of(&v);
+        if !self.compiled(&t) {
+            let what = match callee {
+                Callee::Inspect => "inspecting",
+                _ => "printing",
+            };
+            self.unsupported(&format!("{} a `{}`", what, t), span);
+            return Err(());
+        }
+        self.push(Statement::Call {
+            result: None,
+            callee: callee.clone(),
+            args: vec![Self::borrow(&v)],
+            span,
+        });
+        // `IO.inspect` comes back with its argument
+        if callee == Callee::Inspect {
+            return Ok(Some(v));
+        }
+        self.discard(Some(v));
+        Ok(None)
+    }
+
+    /* `int(x)` and `float(x)` of the types they work on, which fail like `rho_core::convert` does.
+     * A float becomes an int when it's a number that truncates to one.
+     */
+    fn convert(&mut self, to: &str, v: Operand, span: Span) -> Result<Operand, ()> {
+        let from = self.type_of(&v);
+        let t = match to {
+            "int" => Type::int(),
+            _ => Type::float(),
+        };
+        if from == t {
+            return Ok(v);
+        }
+        let convertible = match to {
+            "int" => from == Type::float() || from == Type::char() || from == Type::bool(),
+            _ => from == Type::int(),
+        };
+        if !convertible {
+            self.unsupported(&format!("converting a `{}` to `{}`", from, to), span);
+            return Err(());
+        }
+        if from == Type::float() {
+            let f = || Self::borrow(&v);
+            let nan = self.assign(Type::bool(), Rvalue::Binary(BinOp::Ne, f(), f()), span);
+            self.fail_if(nan, rho_core::float_to_int(f64::NAN).unwrap_err(), span);
+            // -2^63 and 2^63, the ints are the floats from one up to the other
+            let min = Operand::Constant(Constant::Float(i64::MIN as f64));
+            let below = self.assign(Type::bool(), Rvalue::Binary(BinOp::Lt, f(), min), span);
+            let message = rho_core::float_to_int(f64::INFINITY).unwrap_err();
+            self.fail_if(below, message, span);
+            let max = Operand::Constant(Constant::Float(-(i64::MIN as f64)));
+            let above = self.assign(Type::bool(), Rvalue::Binary(BinOp::Ge, f(), max), span);
+            self.fail_if(above, message, span);
+        }
+        Ok(self.assign(t.clone(), Rvalue::Cast(v, t), span))
+    }
+
+    /* A call to one of `RUNTIME_BUILTINS`, its arguments are the types the checker checked. A
+     * generic one's type parameters are the types its arguments have.
+     */
+    fn runtime_call(
+        &mut self,
+        name: String,
+        args: Vec<Operand>,
+        span: Span,
+    ) -> Result<Operand, ()> {
+        let result = self.builtin_call(name, args, span)?;
+        Ok(result.expect("A runtime builtin should give a value"))
+    }
+
+    // `runtime_call` of any builtin the runtime has, a stream's can give unit
+    fn builtin_call(
+        &mut self,
+        name: String,
+        args: Vec<Operand>,
+        span: Span,
+    ) -> Result<Option<Operand>, ()> {
+        let Some(builtin) = rho_core::builtin(&name) else {
+            self.unsupported(&format!("calling `{}`", name), span);
+            return Err(());
+        };
+        let mut s = HashMap::new();
+        for (param, a) in builtin.params.iter().zip(args.iter()) {
+            bind(param, &self.type_of(a), &mut s);
+        }
+        let mut args: Vec<Operand> = (args.into_iter().zip(builtin.params.iter()))
+            .map(|(a, param)| self.coerce(a, &param.substitute(&s), span))
+            .collect();
+        // a stream given where a list is expected is pulled into one
+        for (a, param) in args.iter_mut().zip(builtin.params.iter()) {
+            let t = self.type_of(a);
+            if let (Type::BuiltIn(BuiltinType::Stream, _), Type::BuiltIn(BuiltinType::List, _)) =
+                (&t, param)
+            {
+                let stream = std::mem::replace(a, Operand::Constant(Constant::Unit));
+                *a = self.runtime_call("Stream.to_list".to_string(), vec![stream], span)?;
+            }
+        }
+        let ret = builtin.return_type.substitute(&s);
+        let result = (ret != Type::Unit).then(|| Place::Local(self.temp(ret)));
+        self.push(Statement::Call {
+            result,
+            callee: Callee::Builtin(name),
+            args: args.iter().map(Self::borrow).collect(),
+            span,
+        });
+        for a in args {
+            self.discard(Some(a));
+        }
+        Ok(result.map(Operand::Move))
+    }
+
+    /* A call to one of the `Stream` builtins, which the runtime runs, calling the closures it's
+     * given through adapters the code generator makes. A stream is one of its records, which
+     * holds what it's made from, and nothing runs until a terminal operation or a `for` loop
+     * pulls from it. Ranges aren't values natively, so a `Stream.from` of one is its own call.
+     */
+    fn stream_call(
+        &mut self,
+        path: String,
+        parameters: &[Expression],
+        span: Span,
+    ) -> Result<Option<Operand>, ()> {
+        if let (
+            "Stream.from",
+            [Expression::Range {
+                start, end, step, ..
+            }],
+        ) = (path.as_str(), parameters)
+        {
+            let mut args = vec![self.value(start)?, self.value(end)?];
+            args.push(match step {
+                Some(step) => {
+                    let step = self.value(step)?;
+                    let zero = Operand::Constant(Constant::Int(0));
+                    let zero = Rvalue::Binary(BinOp::Eq, Self::borrow(&step), zero);
+                    let zero = self.assign(Type::bool(), zero, span);
+                    self.fail_if(zero, "a range can't have a step of 0", span);
+                    step
+                }
+                None => Operand::Constant(Constant::Int(1)),
+            });
+            let t = Type::BuiltIn(BuiltinType::Stream, vec![Type::int()]);
+            let stream =
+                self.call_value(t, Callee::Builtin("Stream.range".to_string()), args, span);
+            return Ok(Some(stream));
+        }
+        let mut args = self.values(parameters)?;
+        if path == "Stream.from" {
+            match self.type_of(&args[0]) {
+                Type::BuiltIn(BuiltinType::Stream, _) => return Ok(args.pop()),
+                Type::BuiltIn(BuiltinType::List, _) => {}
+                Type::BuiltIn(BuiltinType::Map, _) => {
+                    let map = args.pop().unwrap();
+                    args.push(self.runtime_call("Map.to_list".to_string(), vec![map], span)?);
+                }
+                t => {
+                    self.unsupported(&format!("streams of a `{}`", t), span);
+                    return Err(());
+                }
+            }
+        }
+        self.builtin_call(path, args, span)
+    }
+
+    /* `p.x = v` and `xs[i][j] = v` in the VM's lowering, the value first and then the indexes,
+     * outermost first as the interpreter evaluates them. The path goes from the binding in.
+     */
+    fn set_path(&mut self, target: &Expression, value: &Expression, span: Span) -> Result<(), ()> {
+        let v = self.value(value)?;
+        if let Expression::Identifier(ident) = target {
+            self.bind(ident, v, span);
+            return Ok(());
+        }
+        let mut path = vec![];
+        let mut root = target;
+        loop {
+            match root {
+                Expression::FieldAccess { object, field, .. } => {
+                    path.push(Step::Field(field.name.clone()));
+                    root = object;
+                }
+                Expression::Index { object, index, .. } => {
+                    path.push(Step::Index(self.value(index)?));
+                    root = object;
+                }
+                _ => break,
+            }
+        }
+        path.reverse();
+        let place = match root {
+            Expression::Identifier(ident) => self
+                .lookup(ident)
+                .ok_or_else(|| format!("`{}` is used before it has a value", ident.name)),
+            _ => Err("can't assign to a temporary value".to_string()),
+        };
+        match place {
+            Ok(place) => self.push(Statement::SetPath {
+                place,
+                path,
+                value: v,
+                span,
+            }),
+            Err(message) => {
+                self.unreachable_fail(&message, span);
+            }
+        }
+        Ok(())
+    }
+
+    // A top level function as a value, which the native backend makes a closure of
+    fn function_value(&mut self, ident: &Ident) -> Result<Operand, ()> {
+        let symbols = self.symbols;
+        let Some(symbol) = ident.symbol.map(|id| symbols.get(id)) else {
+            self.unsupported(&format!("`{}` here", ident.name), ident.span);
+            return Err(());
+        };
+        let t = symbol.symbol_type.clone().unwrap_or_else(unknown);
+        if !self.functions.contains_key(&symbol.name) {
+            self.unsupported(&format!("`{}` as a value", ident.name), ident.span);
+            return Err(());
+        }
+        if !self.compiled(&t) {
+            self.unsupported(&format!("`{}` values", t), ident.span);
+            return Err(());
+        }
+        let value = Rvalue::Function(symbol.name.clone());
+        Ok(self.assign(t, value, ident.span))
+    }
+
+    // A name's value in the VM's lowering, a binding's or the function or builtin it names
+    fn identifier(&mut self, ident: &Ident) -> Operand {
+        if let Some(place) = self.lookup(ident) {
+            let t = self.type_of(&Operand::Copy(place));
+            return self.assign(t, Rvalue::Use(Operand::Copy(place)), ident.span);
+        }
+        let symbols = self.symbols;
+        let Some(symbol) = ident.symbol.map(|id| symbols.get(id)) else {
+            let message = format!("undefined name `{}`", ident.name);
+            return self.unreachable_fail(&message, ident.span);
+        };
+        let t = symbol.symbol_type.clone().unwrap_or_else(unknown);
+        let message = match symbol.kind {
+            SymbolKind::Function if self.functions.contains_key(&symbol.name) => {
+                let value = Rvalue::Function(symbol.name.clone());
+                return self.assign(t, value, ident.span);
+            }
+            SymbolKind::Function => format!("undefined function `{}`", ident.name),
+            SymbolKind::Builtin => match rho_core::builtin(&symbol.name) {
+                Some(builtin) => {
+                    let value = Rvalue::Builtin(builtin.name.to_string());
+                    return self.assign(t, value, ident.span);
+                }
+                None => format!("unknown builtin `{}`", symbol.name),
+            },
+            _ => format!("`{}` is used before it has a value", ident.name),
+        };
+        self.unreachable_fail(&message, ident.span)
+    }
+
+    /* A block's nested functions are made when it's entered, so they can call each other. They
+     * can use the block's locals declared after them, so those get their cells first, which the
+     * declarations fill in rather than replace.
+     */
+    fn nested_functions(&mut self, expressions: &[Expression]) {
+        let nested: Vec<&FunctionDecl> = expressions
+            .iter()
+            .filter_map(|e| match e {
+                Expression::Function(decl) => Some(decl),
+                _ => None,
+            })
+            .collect();
+        if nested.is_empty() {
+            return;
+        }
+        for e in expressions.iter() {
+            let Expression::Definition { identifier, .. } = e else {
+                continue;
+            };
+            let Some(id) = identifier.symbol else {
+                continue;
+            };
+            if !self.symbols.declares(identifier) || !self.captured.contains(&id) {
+                continue;
+            }
+            if let Ok((place, _)) = self.slot(identifier) {
+                if self.in_cell(place) {
+                    self.push(Statement::Fresh(place));
+                    self.f.fresh.insert(id);
+                }
+            }
+        }
+        let mut places = vec![];
+        for decl in nested.iter() {
+            let ident = &decl.name.idents[0];
+            let place = match ident.symbol {
+                Some(_) => self.slot(ident).ok().map(|(place, _)| place),
+                None => None,
+            };
+            if let Some(place) = place.filter(|place| self.in_cell(*place)) {
+                self.push(Statement::Fresh(place));
+            }
+            places.push(place);
+        }
+        for (decl, place) in nested.iter().zip(places) {
+            let ret = match place.map(|place| self.type_of(&Operand::Copy(place))) {
+                Some(Type::Function(_, ret)) => *ret,
+                _ => unknown(),
+            };
+            let Ok(v) = self.closure(decl.name.path(), &decl.params, &decl.body, ret, decl.span)
+            else {
+                continue;
+            };
+            if let Some(place) = place {
+                let t = self.type_of(&v);
+                self.store(place, v, &t, decl.span);
+            }
+        }
+    }
+
+    /* A nested function or lambda, lowered as a closure of its own, and the value making it. A
+     * lambda's return type is the one its body gives when the native backend lowers it.
+     */
+    fn closure(
+        &mut self,
+        name: String,
+        params: &[Param],
+        body: &Expression,
+        ret: Type,
+        span: Span,
+    ) -> Result<Operand, ()> {
+        let outer = std::mem::replace(&mut self.f, Builder::new(ret, false));
+        self.enclosing.push(outer);
+        self.body(params, body, span);
+        let captures = std::mem::take(&mut self.f.captures);
+        let function = self.finish(name, span);
+        self.f = self.enclosing.pop().unwrap();
+        let params = (function.params.iter())
+            .map(|id| function.locals[*id].ty.clone())
+            .collect();
+        let t = Type::function(params, function.return_type().clone());
+        if !self.vm && !self.compiled(&t) {
+            self.unsupported(&format!("`{}` values", t), span);
+            return Err(());
+        }
+        // a record has a field for the function and its name before the cells
+        if !self.vm && captures.len() > 62 {
+            self.unsupported("closures capturing more than 62 bindings", span);
+            return Err(());
+        }
+        self.closures.push(function);
+        let value = Rvalue::Closure(self.closures.len() - 1, captures);
+        Ok(self.assign(t, value, span))
+    }
+
+    // The parameters and body of a function in the VM's lowering, it returns what the body gives
+    fn body(&mut self, params: &[Param], body: &Expression, span: Span) {
+        for p in params.iter() {
+            let local = match p.name.symbol.map(|_| self.slot(&p.name)) {
+                Some(Ok((Place::Local(local), _))) if !self.f.params.contains(&local) => local,
+                _ => self.temp(unknown()),
+            };
+            self.f.params.push(local);
+        }
+        let v = self.expression(body).ok().flatten();
+        self.ret(v, span);
+    }
+
+    /* A call of the closure in `place`, which the call only looks at. It's copied first, so it
+     * stays alive when the call sets the binding to another.
+     */
+    fn call_closure(
+        &mut self,
+        place: Place,
+        parameters: &[Expression],
+        span: Span,
+    ) -> Result<Option<Operand>, ()> {
+        if let Place::Global(global) = place {
+            self.check_set(global, span);
+        }
+        let t = self.type_of(&Operand::Copy(place));
+        let Type::Function(params, ret) = t.clone() else {
+            self.unsupported(&format!("calling a `{}`", t), span);
+            return Err(());
+        };
+        let f = self.assign(t, Rvalue::Use(Operand::Copy(place)), span);
+        let mut args = vec![Self::borrow(&f)];
+        for (p, t) in parameters.iter().zip(params.iter()) {
+            let v = self.value(p)?;
+            args.push(self.coerce(v, t, span));
+        }
+        let result = (*ret != Type::Unit).then(|| Place::Local(self.temp(*ret)));
+        self.push(Statement::Call {
+            result,
+            callee: Callee::Value,
+            args,
+            span,
+        });
+        self.discard(Some(f));
+        Ok(result.map(Operand::Move))
+    }
+
+    // A call whose result goes to a new temporary, unit when it has none
+    fn call_value(&mut self, t: Type, callee: Callee, args: Vec<Operand>, span: Span) -> Operand {
+        let result = Place::Local(self.temp(t));
+        self.push(Statement::Call {
+            result: Some(result),
+            callee,
+            args,
+            span,
+        });
+        Operand::Move(result)
+    }
+
+    fn values(&mut self, expressions: &[Expression]) -> Result<Vec<Operand>, ()> {
+        expressions.iter().map(|e| self.value(e)).collect()
+    }
+
+    // A call in the VM's lowering, the same cases as `Interpreter::eval_call`
+    fn dynamic_call(
+        &mut self,
+        name: &FunctionName,
+        parameters: &[Expression],
+        span: Span,
+    ) -> Result<Operand, ()> {
+        let path = name.path();
+        let first = &name.idents[0];
+        let symbols = self.symbols;
+        let kind = first.symbol.map(|id| symbols.get(id).kind);
+        // a closure held by a variable or a nested function, or by a field of a variable
+        if let Some(SymbolKind::Variable | SymbolKind::Parameter | SymbolKind::Function) = kind {
+            if let Some(place) = self.lookup(first) {
+                let t = self.type_of(&Operand::Copy(place));
+                let mut callee = self.assign(t, Rvalue::Use(Operand::Copy(place)), span);
+                for field in name.idents[1..].iter() {
+                    let value = Rvalue::Field(callee, field.name.clone());
+                    callee = self.assign(unknown(), value, field.span);
+                }
+                let ret = match self.type_of(&callee) {
+                    Type::Function(_, ret) => *ret,
+                    _ => unknown(),
+                };
+                let mut args = vec![callee];
+                args.extend(self.values(parameters)?);
+                return Ok(self.call_value(ret, Callee::Value, args, span));
+            }
+        }
+        let args = self.values(parameters)?;
+        match kind {
+            // `T.show(x)` calls the `show` of the type `x` has when the program runs
+            Some(SymbolKind::TypeParam) => {
+                let method: Vec<&str> = name.idents[1..].iter().map(|i| i.name.as_str()).collect();
+                let callee = Callee::Method(method.join("."));
+                Ok(self.call_value(unknown(), callee, args, span))
+            }
+            Some(SymbolKind::Enum) => {
+                let t = Type::Named(first.name.clone(), vec![]);
+                let variant = name.idents[1].name.clone();
+                let value = Rvalue::Variant(first.name.clone(), variant, args);
+                Ok(self.assign(t, value, span))
+            }
+            _ => {
+                if let Some((_, ret)) = self.functions.get(&path).cloned() {
+                    return Ok(self.call_value(ret, Callee::Function(path), args, span));
+                }
+                let Some(builtin) = rho_core::builtin(&path) else {
+                    let message = format!("undefined function `{}`", path);
+                    return Ok(self.unreachable_fail(&message, span));
+                };
+                let callee = Callee::Builtin(builtin.name.to_string());
+                Ok(self.call_value(builtin.return_type, callee, args, span))
+            }
+        }
+    }
+
+    // `for x in xs` in the VM's lowering, over anything it iterates, ie. a list or a stream
+    fn for_each(
+        &mut self,
+        variable: &Ident,
+        iterable: &Expression,
+        body: &Expression,
+        span: Span,
+    ) -> Result<Option<Operand>, ()> {
+        let iterable = self.value(iterable)?;
+        self.push(Statement::Iterate { iterable, span });
+        let check = self.new_block();
+        let run = self.new_block();
+        let done = self.new_block();
+        self.start(check);
+        let place = match variable.symbol {
+            Some(_) => self.slot(variable).ok().map(|(place, _)| place),
+            None => None,
+        };
+        // a closure made in the body shares the element of its own pass
+        if let Some(place) = place.filter(|place| self.in_cell(*place)) {
+            self.push(Statement::Fresh(place));
+        }
+        self.terminate(Terminator::Next {
+            place,
+            body: run,
+            done,
+            span,
+        });
+        self.f.block = run;
+        self.f.loops.push((check, done, true));
+        let generated = self.expression(body);
+        self.f.loops.pop();
+        self.discard(generated?);
+        self.goto(check);
+        self.f.block = done;
+        Ok(None)
+    }
+
+    // The expressions only the VM's lowering has, whose values it builds or takes apart
+    fn dynamic(&mut self, e: &Expression) -> Result<Operand, ()> {
+        let symbols = self.symbols;
+        let value = match e {
+            Expression::FieldAccess { object, field, .. } => match object.as_ref() {
+                Expression::Identifier(ident)
+                    if ident.symbol.map(|id| symbols.get(id).kind) == Some(SymbolKind::Enum) =>
+                {
+                    let t = Type::Named(ident.name.clone(), vec![]);
+                    let value = Rvalue::Variant(ident.name.clone(), field.name.clone(), vec![]);
+                    return Ok(self.assign(t, value, e.span()));
+                }
+                _ => {
+                    let o = self.value(object)?;
+                    let value = Rvalue::Field(o, field.name.clone());
+                    return Ok(self.assign(unknown(), value, field.span));
+                }
+            },
+            Expression::Index { object, index, .. } => {
+                let o = self.value(object)?;
+                Rvalue::Index(o, self.value(index)?)
+            }
+            Expression::List { elements, .. } => Rvalue::List(self.values(elements)?),
+            Expression::Tuple { elements, .. } => Rvalue::Tuple(self.values(elements)?),
+            Expression::Map { entries, .. } => {
+                let mut values = vec![];
+                for (key, value) in entries.iter() {
+                    let k = self.value(key)?;
+                    values.push((k, self.value(value)?));
+                }
+                Rvalue::Map(values)
+            }
+            Expression::Range {
+                start, end, step, ..
+            } => {
+                let start = self.value(start)?;
+                let end = self.value(end)?;
+                let step = match step {
+                    Some(step) => Some(self.value(step)?),
+                    None => None,
+                };
+                Rvalue::Range(start, end, step)
+            }
+            // the values in the order they are given, the fields in the order they're declared
+            Expression::StructLiteral { name, fields, .. } => {
+                let mut given = HashMap::new();
+                for (field, value) in fields.iter() {
+                    let v = self.value(value)?;
+                    given.insert(field.name.clone(), v);
+                }
+                let order = self.structs.iter().find(|s| s.name == name.name);
+                let fields = (order
+                    .map(|s| s.fields.clone())
+                    .unwrap_or_default()
+                    .into_iter())
+                .filter_map(|(f, _)| given.remove(&f).map(|v| (f, v)))
+                .collect();
+                let t = Type::Named(name.name.clone(), vec![]);
+                return Ok(self.assign(t, Rvalue::Struct(name.name.clone(), fields), e.span()));
+            }
+            Expression::Lambda { params, body, .. } => {
+                let name = "fn".to_string();
+                return self.closure(name, params, body, unknown(), e.span());
+            }
+            Expression::Match {
+                subject,
+                arms,
+                span,
+            } => return self.match_arms(subject, arms, *span),
+            Expression::Try { value, span } => return self.try_value(value, *span),
+            e => {
+                self.unsupported("this expression", e.span());
+                return Err(());
+            }
+        };
+        Ok(self.assign(unknown(), value, e.span()))
+    }
+
+    /* `match`, each arm tries its pattern on the subject, which binds the pattern's places when it
+     * matches, and then its guard. When no arm matches it fails like the interpreter's.
+     */
+    fn match_arms(
+        &mut self,
+        subject: &Expression,
+        arms: &[MatchArm],
+        span: Span,
+    ) -> Result<Operand, ()> {
+        let s = self.value(subject)?;
+        let end = self.new_block();
+        let mut result = None;
+        for arm in arms.iter() {
+            let pattern = self.pattern(&arm.pattern);
+            let matched = Place::Local(self.temp(Type::bool()));
+            self.push(Statement::Match {
+                result: matched,
+                subject: Self::borrow(&s),
+                pattern,
+                span: arm.pattern.span(),
+            });
+            let then = self.new_block();
+            let next = self.new_block();
+            self.branch(Operand::Move(matched), then, next);
+            self.f.block = then;
+            if let Some(guard) = &arm.guard {
+                let g = self.value(guard)?;
+                let body = self.new_block();
+                self.branch(g, body, next);
+                self.f.block = body;
+            }
+            let v = self.expression(&arm.body)?;
+            self.store_branch(v, &mut result, arm.body.span());
+            self.goto(end);
+            self.f.block = next;
+        }
+        let shown = self.shown(Self::borrow(&s), span);
+        self.fail("no match arm matches `", shown, span);
+        self.start(end);
+        Ok(match result {
+            Some(place) => Operand::Move(place),
+            None => Operand::Constant(Constant::Unit),
+        })
+    }
+
+    // `str(v)` and a closing backtick, for the errors that show a value
+    fn shown(&mut self, v: Operand, span: Span) -> Operand {
+        let text = self.call_value(
+            Type::string(),
+            Callee::Builtin("str".to_string()),
+            vec![v],
+            span,
+        );
+        self.concat(
+            text,
+            Operand::Constant(Constant::Str("`".to_string())),
+            span,
+        )
+    }
+
+    fn pattern(&mut self, pattern: &crate::parsers::Pattern) -> Pattern {
+        use crate::parsers::Pattern as Syntax;
+        let all = |l: &mut Self, patterns: &[Syntax]| -> Vec<Pattern> {
+            patterns.iter().map(|p| l.pattern(p)).collect()
+        };
+        match pattern {
+            Syntax::Wildcard(_) => Pattern::Wildcard,
+            Syntax::Binding(ident) => match ident.symbol.map(|_| self.slot(ident)) {
+                Some(Ok((place, _))) => Pattern::Bind(place),
+                _ => Pattern::Wildcard,
+            },
+            Syntax::Literal(literal, _) => Pattern::Literal(constant(literal)),
+            Syntax::Tuple(patterns, _) => Pattern::Tuple(all(self, patterns)),
+            Syntax::List(patterns, _) => Pattern::List(all(self, patterns)),
+            Syntax::Variant(name, patterns, _) => Pattern::Variant(
+                name.idents[0].name.clone(),
+                name.idents.get(1).map(|v| v.name.clone()),
+                all(self, patterns),
+            ),
+        }
+    }
+
+    // `v?`, the value of an `{:ok, value}`, and an `{:error, reason}` is returned as it is
+    fn try_value(&mut self, value: &Expression, span: Span) -> Result<Operand, ()> {
+        let v = self.value(value)?;
+        let atom = |name: &str| Pattern::Literal(Constant::Atom(name.to_string()));
+        let ok = Place::Local(self.temp(unknown()));
+        let patterns = [
+            Pattern::Tuple(vec![atom("ok"), Pattern::Bind(ok)]),
+            Pattern::Tuple(vec![atom("error"), Pattern::Wildcard]),
+        ];
+        let done = self.new_block();
+        for (i, pattern) in patterns.into_iter().enumerate() {
+            let matched = Place::Local(self.temp(Type::bool()));
+            self.push(Statement::Match {
+                result: matched,
+                subject: Self::borrow(&v),
+                pattern,
+                span,
+            });
+            let then = self.new_block();
+            let next = self.new_block();
+            self.branch(Operand::Move(matched), then, next);
+            self.f.block = then;
+            match i {
+                0 => self.goto(done),
+                _ => self.ret(Some(Self::borrow(&v)), span),
+            }
+            self.f.block = next;
+        }
+        let shown = self.shown(Self::borrow(&v), span);
+        let prefix = "`?` needs an `{:ok, value}` or an `{:error, reason}`, found `";
+        self.fail(prefix, shown, span);
+        self.f.block = done;
+        Ok(Operand::Move(ok))
+    }
+
+    // Moves the value into `_0` and goes to the block that drops the bindings and returns
+    fn ret(&mut self, value: Option<Operand>, span: Span) {
+        if self.terminated() {
+            return;
+        }
+        let result = Place::Local(0);
+        let mut t = self.f.locals[0].ty.clone();
+        // a function gives the VM its body's value, whatever its type, and it makes the exit code
+        if self.vm {
+            let value = Rvalue::Use(value.unwrap_or(Operand::Constant(Constant::Unit)));
+            self.push(Statement::Assign {
+                place: result,
+                value,
+                span,
+            });
+            let block = self.f.return_block;
+            return self.goto(block);
+        }
+        // a lambda returns what its body, or its first `return`, gives
+        if t.contains_vars() {
+            t = value.as_ref().map_or(Type::Unit, |v| self.type_of(v));
+            self.f.locals[0].ty = t.clone();
+        }
+        let value = match value {
+            // the top level returns the exit code, see `interpreter::exit_code`
+            Some(v) if self.f.top_level => match self.type_of(&v) {
+                vt if vt == Type::int() => Some(Rvalue::Use(v)),
+                vt if vt == Type::atom() => {
+                    let error = Operand::Constant(Constant::Atom("error".to_string()));
+                    let failed =
+                        self.assign(Type::bool(), Rvalue::Binary(BinOp::Eq, v, error), span);
+                    Some(Rvalue::Cast(failed, Type::int()))
+                }
+                _ => {
+                    self.discard(Some(v));
+                    Some(Rvalue::Use(Operand::Constant(Constant::Int(0))))
+                }
+            },
+            None if self.f.top_level => Some(Rvalue::Use(Operand::Constant(Constant::Int(0)))),
+            v if t == Type::Unit => {
+                self.discard(v);
+                None
+            }
+            Some(v) => Some(Rvalue::Use(self.coerce(v, &t, span))),
+            None => return self.terminate(Terminator::Unreachable),
+        };
+        if let Some(value) = value {
+            self.push(Statement::Assign {
+                place: result,
+                value,
+                span,
+            });
+        }
+        let block = self.f.return_block;
+        self.goto(block);
+    }
+
+    // The return block, which drops the bindings, and for the top level the globals too
+    fn finish(&mut self, name: String, span: Span) -> Function {
+        self.f.block = self.f.return_block;
+        let bindings: Vec<LocalId> = (1..self.f.locals.len())
+            .filter(|id| {
+                let local = &self.f.locals[*id];
+                local.name.is_some() && (self.owns(&local.ty) || (local.captured && !self.vm))
+            })
+            .collect();
+        for id in bindings {
+            self.push(Statement::Drop(Place::Local(id)));
+        }
+        if self.f.top_level {
+            let globals: Vec<GlobalId> = (0..self.globals.len())
+                .filter(|id| self.owns(&self.globals[*id].ty))
+                .collect();
+            for id in globals {
+                self.push(Statement::Drop(Place::Global(id)));
+            }
+        }
+        self.terminate(Terminator::Return);
+
+        let f = std::mem::replace(&mut self.f, Builder::new(Type::Unit, false));
+        let blocks = f
+            .statements
+            .into_iter()
+            .zip(f.terminators)
+            .map(|(statements, terminator)| Block {
+                statements,
+                terminator: terminator.unwrap_or(Terminator::Unreachable),
+            })
+            .collect();
+        Function {
+            name,
+            params: f.params,
+            locals: f.locals,
+            upvalues: f.upvalues,
+            blocks,
+            span,
+        }
+    }
+
+    fn function(&mut self, decl: &FunctionDecl) -> Option<Function> {
+        let path = decl.name.path();
+        let (params, ret) = self.functions.get(&path).cloned()?;
+        if self.vm {
+            self.f = Builder::new(ret, false);
+            self.body(&decl.params, &decl.body, decl.span);
+            return Some(self.finish(path, decl.span));
+        }
+        if ret != Type::Unit && !self.compiled(&ret) {
+            self.unsupported(&format!("returning `{}`", ret), decl.span);
+            return None;
+        }
+        self.f = Builder::new(ret.clone(), false);
+        for (param, t) in decl.params.iter().zip(params.iter()) {
+            if !self.compiled(t) {
+                self.unsupported(&format!("`{}` parameters", t), param.name.span);
+                return None;
+            }
+            let (Place::Local(local), _) = self.slot(&param.name).ok()? else {
+                return None;
+            };
+            self.f.params.push(local);
+        }
+        if let Ok(v) = self.expression(&decl.body) {
+            self.discard(v);
+        }
+        if !self.terminated() {
+            if ret == Type::Unit {
+                self.ret(None, decl.span);
+            } else {
+                let message = format!("`{}` ended without returning a value", path);
+                self.fail("", Operand::Constant(Constant::Str(message)), decl.span);
+            }
+        }
+        Some(self.finish(path, decl.span))
+    }
+
+    // The top level statements, and then the program's `main` when it has one
+    fn entry(&mut self, program: &[Expression]) -> Function {
+        let t = if self.vm { unknown() } else { Type::int() };
+        self.f = Builder::new(t, true);
+        for e in program.iter() {
+            if matches!(e, Expression::Function(_)) {
+                continue;
+            }
+            if let Ok(v) = self.expression(e) {
+                self.discard(v);
+            }
+        }
+        let span = program.last().map(|e| e.span()).unwrap_or_default();
+        if !self.terminated() {
+            let value = match self.functions.get("main").cloned() {
+                // the VM calls it whatever it takes, like the interpreter
+                Some((params, ret)) if params.is_empty() || self.vm => {
+                    let result =
+                        (self.vm || ret != Type::Unit).then(|| Place::Local(self.temp(ret)));
+                    // from no line, like the interpreter calls it, a stack trace ends at `main`
+                    self.push(Statement::Call {
+                        result,
+                        callee: Callee::Function("main".to_string()),
+                        args: vec![],
+                        span: Span::default(),
+                    });
+                    result.map(Operand::Move)
+                }
+                _ => None,
+            };
+            self.ret(value, span);
+        }
+        self.finish("<top level>".to_string(), Span::default())
+    }
+}
+
+// Binds the type parameters in `param` to the types they are in `t`, ie. `T` to int for `list[T]`
+fn bind(param: &Type, t: &Type, s: &mut HashMap<String, Type>) {
+    match (param, t) {
+        (Type::Param(name), t) if s.get(name).is_none_or(|bound| bound.contains_vars()) => {
+            s.insert(name.clone(), t.clone());
+        }
+        (
+            Type::BuiltIn(_, params) | Type::Named(_, params),
+            Type::BuiltIn(_, ts) | Type::Named(_, ts),
+        ) => {
+            for (param, t) in params.iter().zip(ts.iter()) {
+                bind(param, t, s);
+            }
+        }
+        (Type::Function(params, ret), Type::Function(ts, t)) => {
+            for (param, t) in params.iter().zip(ts.iter()) {
+                bind(param, t, s);
+            }
+            bind(ret, t, s);
+        }
+        _ => {}
+    }
+}
+
+fn constant(value: &TokenValue) -> Constant {
+    match value {
+        TokenValue::Int(i) => Constant::Int(*i),
+        TokenValue::Float(f) => Constant::Float(*f),
+        TokenValue::Bool(b) => Constant::Bool(*b),
+        TokenValue::Char(c) => Constant::Char(*c),
+        TokenValue::String(s) => Constant::Str(s.clone()),
+        TokenValue::Atom(name) => Constant::Atom(name.clone()),
+    }
+}
+
+// The locals some nested function or lambda uses, which have to live in cells
+fn captured(program: &[Expression], symbols: &SymbolTable) -> HashSet<SymbolId> {
+    use crate::parsers::Pattern as Syntax;
+
+    struct Scan<'a> {
+        symbols: &'a SymbolTable,
+        owners: HashMap<SymbolId, usize>, // the function declaring each local
+        uses: Vec<(SymbolId, usize)>,
+        functions: usize,
+    }
+
+    impl Scan<'_> {
+        fn declare(&mut self, ident: &Ident, function: usize) {
+            if let Some(id) = ident.symbol {
+                self.owners.insert(id, function);
+            }
+        }
+
+        fn pattern(&mut self, pattern: &Syntax, function: usize) {
+            match pattern {
+                Syntax::Binding(ident) => self.declare(ident, function),
+                Syntax::Tuple(patterns, _)
+                | Syntax::List(patterns, _)
+                | Syntax::Variant(_, patterns, _) => {
+                    for p in patterns.iter() {
+                        self.pattern(p, function);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        fn function(&mut self, params: &[Param], body: &Expression) {
+            self.functions += 1;
+            let function = self.functions;
+            for p in params.iter() {
+                self.declare(&p.name, function);
+            }
+            self.expression(body, function);
+        }
+
+        fn expression(&mut self, e: &Expression, function: usize) {
+            match e {
+                Expression::Definition { identifier, .. } if self.symbols.declares(identifier) => {
+                    self.declare(identifier, function)
+                }
+                Expression::Definition { identifier, .. } | Expression::Identifier(identifier) => {
+                    if let Some(id) = identifier.symbol {
+                        self.uses.push((id, function));
+                    }
+                }
+                Expression::FunctionCall { function_name, .. } => {
+                    if let Some(id) = function_name.idents[0].symbol {
+                        self.uses.push((id, function));
+                    }
+                }
+                Expression::For { variable, .. } => self.declare(variable, function),
+                Expression::Match { arms, .. } => {
+                    for arm in arms.iter() {
+                        self.pattern(&arm.pattern, function);
+                    }
+                }
+                Expression::Lambda { params, body, .. } => return self.function(params, body),
+                Expression::Function(decl) => {
+                    self.declare(&decl.name.idents[0], function);
+                    return self.function(&decl.params, &decl.body);
+                }
+                _ => {}
+            }
+            for child in e.children() {
+                self.expression(child, function);
+            }
+        }
+    }
+
+    let mut scan = Scan {
+        symbols,
+        owners: HashMap::new(),
+        uses: vec![],
+        functions: 0,
+    };
+    for e in program.iter() {
+        scan.expression(e, 0);
+    }
+    scan.uses
+        .iter()
+        .filter(|(id, function)| scan.owners.get(id).is_some_and(|owner| owner != function))
+        .map(|(id, _)| *id)
+        .collect()
+}
+
+impl fmt::Display for Place {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Place::Local(id) => write!(f, "_{}", id),
+            Place::Global(id) => write!(f, "@{}", id),
+            Place::Upvalue(id) => write!(f, "^{}", id),
+        }
+    }
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::Int(i) => write!(f, "{}", i),
+            Constant::Float(x) => write!(f, "{:?}", x),
+            Constant::Bool(b) => write!(f, "{}", b),
+            Constant::Char(c) => write!(f, "'{}'", escape(&c.to_string(), '\'')),
+            Constant::Atom(name) => write!(f, ":{}", name),
+            Constant::Str(s) => write!(f, "\"{}\"", escape(s, '"')),
+            Constant::Unit => write!(f, "()"),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Copy(place) => write!(f, "{}", place),
+            Operand::Move(place) => write!(f, "move {}", place),
+            Operand::Constant(c) => write!(f, "const {}", c),
+        }
+    }
+}
+
+impl fmt::Display for Rvalue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rvalue::Use(operand) => write!(f, "{}", operand),
+            Rvalue::Binary(op, a, b) => write!(f, "{:?}({}, {})", op, a, b),
+            Rvalue::Unary(op, a) => write!(f, "{:?}({})", op, a),
+            Rvalue::Cast(a, t) => write!(f, "{} as {}", a, t),
+            Rvalue::Select(c, a, b) => write!(f, "Select({}, {}, {})", c, a, b),
+            Rvalue::List(values) => write!(f, "List({})", list(values)),
+            Rvalue::Tuple(values) => write!(f, "Tuple({})", list(values)),
+            Rvalue::Map(entries) => {
+                let entries: Vec<String> = (entries.iter())
+                    .map(|(k, v)| format!("{}: {}", k, v))
+                    .collect();
+                write!(f, "Map({})", entries.join(", "))
+            }
+            Rvalue::Struct(name, fields) => {
+                let fields: Vec<String> = (fields.iter())
+                    .map(|(field, v)| format!("{}: {}", field, v))
+                    .collect();
+                write!(f, "{}{{{}}}", name, fields.join(", "))
+            }
+            Rvalue::Variant(name, variant, values) => {
+                write!(f, "{}.{}({})", name, variant, list(values))
+            }
+            Rvalue::Range(start, end, None) => write!(f, "Range({}, {})", start, end),
+            Rvalue::Range(start, end, Some(step)) => {
+                write!(f, "Range({}, {}, {})", start, end, step)
+            }
+            Rvalue::Field(a, name) => write!(f, "{}.{}", a, name),
+            Rvalue::Index(a, i) => write!(f, "{}[{}]", a, i),
+            Rvalue::Closure(id, captures) => write!(f, "Closure(#{}, {})", id, list(captures)),
+            Rvalue::Function(name) => write!(f, "fn {}", name),
+            Rvalue::Builtin(name) => write!(f, "builtin {}", name),
+        }
+    }
+}
+
+impl fmt::Display for Callee {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Callee::Function(name) => write!(f, "{}", name),
+            Callee::Print => write!(f, "IO.print"),
+            Callee::Puts => write!(f, "IO.puts"),
+            Callee::Inspect => write!(f, "IO.inspect"),
+            Callee::Builtin(name) => write!(f, "{}", name),
+            Callee::Show => write!(f, "show"),
+            Callee::Enter(name) => write!(f, "enter {}", name),
+            Callee::Leave => write!(f, "leave"),
+            Callee::Value => write!(f, "call"),
+            Callee::Method(method) => write!(f, "method {}", method),
+        }
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Assign { place, value, span } => {
+                write!(f, "{} = {}", place, value)?;
+                // where it fails, a copy can't
+                match value {
+                    Rvalue::Binary(..)
+                    | Rvalue::Unary(..)
+                    | Rvalue::Map(_)
+                    | Rvalue::Range(..)
+                    | Rvalue::Field(..)
+                    | Rvalue::Index(..) => {
+                        write!(f, " @ {}:{}", span.line, span.col)
+                    }
+                    _ => Ok(()),
+                }
+            }
+            Statement::Call {
+                result,
+                callee,
+                args,
+                span,
+            } => {
+                if let Some(place) = result {
+                    write!(f, "{} = ", place)?;
+                }
+                let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+                write!(
+                    f,
+                    "{}({}) @ {}:{}",
+                    callee,
+                    args.join(", "),
+                    span.line,
+                    span.col
+                )
+            }
+            Statement::Drop(place) => write!(f, "drop {}", place),
+            Statement::Match {
+                result,
+                subject,
+                pattern,
+                span,
+            } => write!(
+                f,
+                "{} = match {}, {} @ {}:{}",
+                result, subject, pattern, span.line, span.col
+            ),
+            Statement::SetPath {
+                place,
+                path,
+                value,
+                span,
+            } => {
+                write!(f, "{}", place)?;
+                for step in path.iter() {
+                    match step {
+                        Step::Field(name) => write!(f, ".{}", name)?,
+                        Step::Index(i) => write!(f, "[{}]", i)?,
+                    }
+                }
+                write!(f, " = {} @ {}:{}", value, span.line, span.col)
+            }
+            Statement::Fresh(place) => write!(f, "fresh {}", place),
+            Statement::Iterate { iterable, span } => {
+                write!(f, "iterate {} @ {}:{}", iterable, span.line, span.col)
+            }
+            Statement::EndIterate => write!(f, "end iterate"),
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Terminator::Goto(block) => write!(f, "goto bb{}", block),
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => write!(f, "branch {}, bb{}, bb{}", condition, then, otherwise),
+            Terminator::Return => write!(f, "return"),
+            Terminator::Fail {
+                prefix,
+                message,
+                span,
+                traced,
+            } => write!(
+                f,
+                "{} \"{}\", {} @ {}:{}",
+                if *traced { "panic" } else { "fail" },
+                escape(prefix, '"'),
+                message,
+                span.line,
+                span.col
+            ),
+            Terminator::Unreachable => write!(f, "unreachable"),
+            Terminator::Next {
+                place,
+                body,
+                done,
+                span,
+            } => {
+                match place {
+                    Some(place) => write!(f, "next {}", place)?,
+                    None => write!(f, "next _")?,
+                }
+                write!(f, ", bb{}, bb{} @ {}:{}", body, done, span.line, span.col)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Bind(place) => write!(f, "{}", place),
+            Pattern::Literal(c) => write!(f, "{}", c),
+            Pattern::Tuple(patterns) => write!(f, "{{{}}}", list(patterns)),
+            Pattern::List(patterns) => write!(f, "[{}]", list(patterns)),
+            Pattern::Variant(name, variant, patterns) => {
+                write!(f, "{}.{}", name, variant.as_deref().unwrap_or("?"))?;
+                if !patterns.is_empty() {
+                    write!(f, "({})", list(patterns))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// `a, b, c`
+fn list<T: fmt::Display>(items: &[T]) -> String {
+    let items: Vec<String> = items.iter().map(|i| i.to_string()).collect();
+    items.join(", ")
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let params: Vec<String> = (self.params.iter())
+            .map(|id| format!("_{}: {}", id, self.locals[*id].ty))
+            .collect();
+        writeln!(
+            f,
+            "fn {}({}) -> {} {{",
+            self.name,
+            params.join(", "),
+            self.return_type()
+        )?;
+        for (id, local) in self.locals.iter().enumerate() {
+            match &local.name {
+                Some(name) => writeln!(f, "    let _{}: {}  // {}", id, local.ty, name)?,
+                None => writeln!(f, "    let _{}: {}", id, local.ty)?,
+            }
+        }
+        for (id, upvalue) in self.upvalues.iter().enumerate() {
+            let name = upvalue.name.as_deref().unwrap_or_default();
+            writeln!(f, "    let ^{}: {}  // {}", id, upvalue.ty, name)?;
+        }
+        for (id, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "\n    bb{}:", id)?;
+            for statement in block.statements.iter() {
+                writeln!(f, "        {}", statement)?;
+            }
+            writeln!(f, "        {}", block.terminator)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for s in self.structs.iter() {
+            let fields: Vec<String> = (s.fields.iter())
+                .map(|(name, t)| format!("{}: {}", name, t))
+                .collect();
+            let params = match s.type_params.is_empty() {
+                true => String::new(),
+                false => format!("[{}]", s.type_params.join(", ")),
+            };
+            writeln!(f, "struct {}{} {{ {} }}", s.name, params, fields.join(", "))?;
+        }
+        for (id, global) in self.globals.iter().enumerate() {
+            writeln!(f, "global @{}: {}  // {}", id, global.ty, global.name)?;
+        }
+        for function in self.functions.iter().chain([&self.entry]) {
+            writeln!(f)?;
+            write!(f, "{}", function)?;
+        }
+        for (id, closure) in self.closures.iter().enumerate() {
+            write!(f, "\n#{} {}", id, closure)?;
+        }
+        Ok(())
+    }
+}
+
+// The MIR of a program, panicking on its errors
+#[cfg(test)]
+pub fn lower_source(source: &str) -> Result<Program, Vec<Diagnostic>> {
+    let (compiled, diagnostics) = crate::compile(source);
+    let Some((mut program, symbols)) = compiled else {
+        panic!("doesn't compile: {:?}", diagnostics);
+    };
+    crate::testing::strip_tests(&mut program);
+    lower(&program, &symbols)
+}
+
+#[test]
+fn test_lower() {
+    let mir =
+        lower_source("func twice(str s) -> str {\n    return s <> s\n}\nIO.puts(twice(\"a\"))\n")
+            .unwrap();
+    assert_eq!(
+        mir.to_string(),
+        "
+fn twice(_1: str) -> str {
+    let _0: str
+    let _1: str  // s
+    let _2: str
+    let _3: str
+    let _4: str
+
+    bb0:
+        _2 = _1
+        _3 = _1
+        _4 = Concat(_2, _3) @ 2:14
+        drop _2
+        drop _3
+        _0 = move _4
+        goto bb1
+
+    bb1:
+        drop _1
+        return
+}
+
+fn <top level>() -> int {
+    let _0: int
+    let _1: str
+
+    bb0:
+        _1 = twice(const \"a\") @ 4:9
+        IO.puts(_1) @ 4:1
+        drop _1
+        _0 = const 0
+        goto bb1
+
+    bb1:
+        return
+}
+"
+    );
+}
+
+#[test]
+fn test_lower_control_flow() {
+    let source = "var int total = 0
+for i in range 0..10..2 {
+    if i == 4 {
+        continue
+    }
+    total = total + i
+}
+func main() -> atom {
+    return if total > 10 { :error } else { :ok }
+}
+";
+    let mir = lower_source(source).unwrap();
+    let entry = mir.entry.to_string();
+    // the step isn't 1 so the direction is checked, and a step of 0 fails
+    assert!(entry.contains("Select("), "{}", entry);
+    assert!(
+        entry.contains("fail \"\", const \"a range can't have a step of 0\" @ 2:17"),
+        "{}",
+        entry
+    );
+    assert!(entry.contains("AddOverflows("), "{}", entry);
+    // `main`'s atom is turned into the exit code
+    assert!(entry.contains("Eq(move _"), "{}", entry);
+    assert!(entry.contains("as int"), "{}", entry);
+    let main = mir.functions[0].to_string();
+    assert!(main.starts_with("fn main() -> atom {"), "{}", main);
+    // `main` reads `total`, whose definition says it's set
+    assert_eq!(
+        mir.globals,
+        [
+            Global {
+                name: "total".to_string(),
+                ty: Type::int()
+            },
+            Global {
+                name: "total.set".to_string(),
+                ty: Type::bool()
+            }
+        ]
+    );
+    assert_eq!(entry.matches("@1 = const true").count(), 1, "{}", entry);
+    assert!(
+        main.contains("fail \"\", const \"`total` is used before it has a value\" @ 9:15"),
+        "{}",
+        main
+    );
+
+    let errors = lower_source("xs = 1..3\nIO.puts(match 1 { _ -> 2 })\n").unwrap_err();
+    let messages: Vec<&str> = errors.iter().map(|d| d.message.as_str()).collect();
+    assert_eq!(
+        messages,
+        [
+            "the native backend doesn't support ranges outside of `for` loops yet",
+            "the native backend doesn't support `match` yet"
+        ]
+    );
+}
@@ -0,0 +1,161 @@
+use crate::diagnostics::Diagnostic;
+use crate::parsers::*;
+use crate::resolver::{SymbolKind, SymbolTable};
+use crate::tokens::*;
+
+/* Bindings are immutable unless they are declared with `var`. Reports assignments to immutable
+ * bindings, parameters and consts, and writes to the fields or elements of a value whose binding
+ * is immutable ie. `p.x = 1` where `p` was declared with `Point p = ..`.
+ */
+pub fn check_mutability(program: &[Expression], symbols: &SymbolTable) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    for e in program.iter() {
+        check_expression(e, symbols, &mut diagnostics);
+    }
+    diagnostics.sort_by_key(|d| (d.span.line, d.span.col));
+    diagnostics
+}
+
+fn check_expression(expression: &Expression, symbols: &SymbolTable, out: &mut Vec<Diagnostic>) {
+    match expression {
+        Expression::Definition { identifier, .. } if !symbols.declares(identifier) => {
+            if let Some(d) = check_reassignment(identifier, symbols) {
+                out.push(d);
+            }
+        }
+        Expression::Assignment { target, .. } => {
+            if let Some(d) = check_write(target, symbols) {
+                out.push(d);
+            }
+        }
+        _ => {}
+    }
+    for child in expression.children() {
+        check_expression(child, symbols, out);
+    }
+}
+
+// `x = 2` where `x` is already bound
+fn check_reassignment(identifier: &Ident, symbols: &SymbolTable) -> Option<Diagnostic> {
+    let symbol = symbols.get(identifier.symbol?);
+    let name = &identifier.name;
+    let (message, note) = match (symbol.kind, symbol.mutability) {
+        (_, Mutability::Mutable) => return None,
+        (_, Mutability::Const) => (
+            format!("cannot assign to const `{}`", name),
+            format!("`{}` is defined here", name),
+        ),
+        (SymbolKind::Parameter, _) => (
+            format!("cannot assign to parameter `{}`", name),
+            format!("`{}` is defined here", name),
+        ),
+        _ => (
+            format!("cannot assign twice to immutable binding `{}`", name),
+            format!(
+                "`{}` is first assigned here, declare it with `var` to make it mutable",
+                name
+            ),
+        ),
+    };
+    Some(Diagnostic::error(message, identifier.span).with_note(note, symbol.span))
+}
+
+// `p.x = ..` or `xs[0] = ..` writes into the value bound to `p` or `xs`
+fn check_write(target: &Expression, symbols: &SymbolTable) -> Option<Diagnostic> {
+    let what = match target {
+        Expression::Index { .. } => "an element",
+        _ => "a field",
+    };
+    let mut root = target;
+    while let Expression::FieldAccess { object, .. } | Expression::Index { object, .. } = root {
+        root = object;
+    }
+    let Expression::Identifier(ident) = root else {
+        return Some(Diagnostic::error(
+            format!("cannot assign to {} of a temporary value", what),
+            target.span(),
+        ));
+    };
+    let symbol = symbols.get(ident.symbol?);
+    if symbol.mutability == Mutability::Mutable {
+        return None;
+    }
+    let message = match (symbol.kind, symbol.mutability) {
+        (_, Mutability::Const) => format!("cannot assign to {} of const `{}`", what, ident.name),
+        (SymbolKind::Parameter, _) => {
+            format!("cannot assign to {} of parameter `{}`", what, ident.name)
+        }
+        _ => format!(
+            "cannot assign to {} of immutable binding `{}`",
+            what, ident.name
+        ),
+    };
+    let mut note = format!("`{}` is defined here", ident.name);
+    if symbol.kind == SymbolKind::Variable && symbol.mutability == Mutability::Immutable {
+        note += ", declare it with `var` to make it mutable";
+    }
+    Some(Diagnostic::error(message, target.span()).with_note(note, symbol.span))
+}
+
+#[cfg(test)]
+fn mutability_source(source: &str) -> Vec<String> {
+    let mut program = parse(source).unwrap();
+    let (symbols, diagnostics) = crate::resolver::resolve(&mut program);
+    assert!(
+        diagnostics.iter().all(|d| !d.is_error()),
+        "{:?}",
+        diagnostics
+    );
+    check_mutability(&program, &symbols)
+        .iter()
+        .map(|d| format!("{} (note at {})", d, d.notes[0].1))
+        .collect()
+}
+
+#[test]
+fn test_check_reassignment() {
+    assert_eq!(
+        mutability_source(
+            "int x = 1
+x = 2
+var int y = 1
+y = 2
+var z = 1
+z = z + 1
+const LIMIT = 10
+LIMIT = 11
+func f(int a) {
+    a = 2
+}
+"
+        ),
+        vec![
+            "error: cannot assign twice to immutable binding `x` at 2:1 (note at 1:5)",
+            "error: cannot assign to const `LIMIT` at 8:1 (note at 7:7)",
+            "error: cannot assign to parameter `a` at 10:5 (note at 9:12)",
+        ]
+    );
+}
+
+#[test]
+fn test_check_field_writes() {
+    assert_eq!(
+        mutability_source(
+            "struct Point {
+    int x
+    int y
+}
+Point p = Point{x: 1, y: 2}
+p.x = 3
+var Point q = Point{x: 1, y: 2}
+q.y = 4
+list[int] xs = [1, 2]
+xs[0] = 5
+"
+        ),
+        vec![
+            "error: cannot assign to a field of immutable binding `p` at 6:1 (note at 5:7)",
+            "error: cannot assign to an element of immutable binding `xs` at 10:1 (note at 9:11)",
+        ]
+    );
+}
@@ -0,0 +1,433 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::passes::Pass;
+
+// What `rho build` stops at and writes, `--emit=ll|bc|obj|exe|mir`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+    Ll,
+    Bc,
+    Obj,
+    Exe,
+    Mir, // the mid-level IR, written before there's any LLVM IR
+}
+
+impl Emit {
+    pub fn parse(s: &str) -> Option<Emit> {
+        match s {
+            "ll" => Some(Emit::Ll),
+            "bc" => Some(Emit::Bc),
+            "obj" => Some(Emit::Obj),
+            "exe" => Some(Emit::Exe),
+            "mir" => Some(Emit::Mir),
+            _ => None,
+        }
+    }
+
+    // The extension of its file, the executable has none
+    fn extension(self) -> &'static str {
+        match self {
+            Emit::Ll => "ll",
+            Emit::Bc => "bc",
+            Emit::Obj => "o",
+            Emit::Exe => "",
+            Emit::Mir => "mir",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildOptions {
+    pub emit: Emit,
+    pub output: Option<String>,
+    pub target: Option<String>, // a target triple, the host when None
+    pub opt_level: u8,          // 0 to 3, like `-O2`
+    pub passes: Vec<Pass>,      // the MIR passes, all of them unless `--passes` says otherwise
+}
+
+impl Default for BuildOptions {
+    fn default() -> BuildOptions {
+        BuildOptions {
+            emit: Emit::Exe,
+            output: None,
+            target: None,
+            opt_level: 0,
+            passes: Pass::ALL.to_vec(),
+        }
+    }
+}
+
+/* Turns the IR `codegen::generate` made of `file` into what `options` asks for, running the LLVM
+ * tools one after the other like this:
+ *
+ *     .ll --llvm-as (or opt with -O1 and up)--> .bc --llc--> .o --cc, with the runtime--> executable
+ *
+ * The files before the last are written to a directory of their own in the temp dir, which is
+ * removed afterwards whether the build worked or not. Comes back with the path it wrote, or a
+ * message saying which step failed.
+ */
+pub fn build(file: &str, ir: &str, options: &BuildOptions) -> Result<PathBuf, String> {
+    let output = match &options.output {
+        Some(output) => PathBuf::from(output),
+        None => default_output(file, options.emit),
+    };
+    // the tools are looked for first, a missing one shouldn't leave half a build behind
+    let mut tools = vec![];
+    if options.emit != Emit::Ll || options.opt_level > 0 {
+        tools.push(if options.opt_level > 0 {
+            "opt"
+        } else {
+            "llvm-as"
+        });
+    }
+    if matches!(options.emit, Emit::Obj | Emit::Exe) {
+        tools.push("llc");
+    }
+    let mut found = vec![];
+    for tool in tools {
+        let Some(path) = find_tool(tool) else {
+            return Err(format!(
+                "`{}` isn't installed, `--emit={}` needs it, install LLVM or use `--emit=ll`",
+                tool,
+                emit_name(options.emit)
+            ));
+        };
+        found.push(path);
+    }
+    let linker = match options.emit {
+        Emit::Exe => Some((find_linker(options.target.as_deref())?, find_runtime()?)),
+        _ => None,
+    };
+
+    let dir = Intermediates::new()?;
+    let result = run_pipeline(&dir.path, ir, options, &output, &found, linker);
+    drop(dir);
+    result.map(|_| output)
+}
+
+/* `build` for `-o -`, which writes the LLVM IR to stdout instead of a file. It's built to a file
+ * in the temp dir like any other and comes back as text, only `--emit=ll` has any.
+ */
+pub fn build_text(file: &str, ir: &str, options: &BuildOptions) -> Result<String, String> {
+    let dir = Intermediates::new()?;
+    let output = dir.path.join(format!("main.{}", options.emit.extension()));
+    let options = BuildOptions {
+        output: Some(output.to_string_lossy().to_string()),
+        ..options.clone()
+    };
+    let output = build(file, ir, &options)?;
+    fs::read_to_string(&output).map_err(|e| format!("can't read {}: {}", output.display(), e))
+}
+
+fn run_pipeline(
+    dir: &Path,
+    ir: &str,
+    options: &BuildOptions,
+    output: &Path,
+    tools: &[PathBuf],
+    linker: Option<((PathBuf, bool), PathBuf)>,
+) -> Result<(), String> {
+    let optimize = format!("-O{}", options.opt_level);
+    let last = |emit: Emit| -> PathBuf {
+        match emit == options.emit {
+            true => output.to_path_buf(),
+            false => dir.join(format!("main.{}", emit.extension())),
+        }
+    };
+
+    let ll = match options.emit == Emit::Ll && options.opt_level == 0 {
+        true => output.to_path_buf(),
+        false => dir.join("main.ll"),
+    };
+    fs::write(&ll, ir).map_err(|e| format!("can't write {}: {}", ll.display(), e))?;
+    if options.emit == Emit::Ll && options.opt_level == 0 {
+        return Ok(());
+    }
+
+    let mut tools = tools.iter();
+    let bc = last(Emit::Bc);
+    let mut assemble = Command::new(tools.next().unwrap());
+    if options.opt_level > 0 {
+        assemble.arg(&optimize);
+        if options.emit == Emit::Ll {
+            assemble.arg("-S");
+        }
+    }
+    let assembled = match options.emit {
+        Emit::Ll => output.to_path_buf(),
+        _ => bc,
+    };
+    assemble.arg(&ll).arg("-o").arg(&assembled);
+    run(&mut assemble)?;
+    if matches!(options.emit, Emit::Ll | Emit::Bc) {
+        return Ok(());
+    }
+
+    let obj = last(Emit::Obj);
+    let mut compile = Command::new(tools.next().unwrap());
+    compile
+        .arg("-filetype=obj")
+        .arg(&optimize)
+        .arg("--relocation-model=pic");
+    if let Some(target) = &options.target {
+        compile.arg(format!("-mtriple={}", target));
+    }
+    compile.arg(&assembled).arg("-o").arg(&obj);
+    run(&mut compile)?;
+
+    let Some(((linker, takes_target), runtime)) = linker else {
+        return Ok(());
+    };
+    let mut link = Command::new(linker);
+    if let (Some(target), true) = (&options.target, takes_target) {
+        link.arg(format!("--target={}", target));
+    }
+    link.arg(&obj).arg(runtime).arg("-o").arg(output);
+    // what the runtime's Rust standard library needs, `pow` is in libm
+    link.args(RUNTIME_LIBRARIES.split(' '));
+    run(&mut link)
+}
+
+// `rustc --print native-static-libs` for the runtime on Linux
+const RUNTIME_LIBRARIES: &str = "-lgcc_s -lutil -lrt -lpthread -lm -ldl -lc";
+
+/* The runtime's static library, `RHO_RUNTIME` or else the one cargo built next to the `rho`
+ * executable, or next to the tests in `target/debug/deps`. The shared library `lli` loads is
+ * looked for beside it.
+ */
+pub fn find_runtime() -> Result<PathBuf, String> {
+    runtime_library("a")
+}
+
+// The runtime built as `extension`, "a" for the static library and "so" for the shared one
+pub fn runtime_library(extension: &str) -> Result<PathBuf, String> {
+    let name = format!("librho_runtime.{}", extension);
+    if let Some(path) = std::env::var_os("RHO_RUNTIME") {
+        let path = PathBuf::from(path).with_extension(extension);
+        if path.is_file() {
+            return Ok(path);
+        }
+    }
+    let exe =
+        std::env::current_exe().map_err(|e| format!("can't find the rho executable: {}", e))?;
+    for dir in exe.ancestors().skip(1).take(2) {
+        let path = dir.join(&name);
+        if path.is_file() {
+            return Ok(path);
+        }
+    }
+    Err(format!(
+        "the runtime library `{}` isn't next to rho, build it with `cargo build --workspace` or point `RHO_RUNTIME` at it",
+        name
+    ))
+}
+
+// Runs a step of the build, failing with what the tool printed
+fn run(command: &mut Command) -> Result<(), String> {
+    let name = Path::new(command.get_program())
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let ran = command
+        .output()
+        .map_err(|e| format!("can't run `{}`: {}", name, e))?;
+    if ran.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&ran.stderr);
+    Err(format!("`{}` failed\n{}", name, stderr.trim_end()))
+}
+
+fn emit_name(emit: Emit) -> &'static str {
+    match emit {
+        Emit::Ll => "ll",
+        Emit::Bc => "bc",
+        Emit::Obj => "obj",
+        Emit::Exe => "exe",
+        Emit::Mir => "mir",
+    }
+}
+
+// `dir/hello.rho` builds `hello`, or `hello.ll` and so on, in the current directory like `rustc`
+pub fn default_output(file: &str, emit: Emit) -> PathBuf {
+    let stem = Path::new(file)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "main".to_string());
+    PathBuf::from(stem).with_extension(emit.extension())
+}
+
+/* A tool on the PATH, or one of its versioned names that distributions install LLVM with, ie.
+ * `llc-14`. `RHO_LLVM_DIR` points at the LLVM tools when they aren't on the PATH.
+ */
+pub fn find_tool(name: &str) -> Option<PathBuf> {
+    let mut dirs: Vec<PathBuf> = std::env::var_os("RHO_LLVM_DIR")
+        .map(PathBuf::from)
+        .into_iter()
+        .collect();
+    if let Some(path) = std::env::var_os("PATH") {
+        dirs.extend(std::env::split_paths(&path));
+    }
+    let mut names = vec![name.to_string()];
+    names.extend(
+        (14..=20)
+            .rev()
+            .map(|version| format!("{}-{}", name, version)),
+    );
+    for name in names.iter() {
+        for dir in dirs.iter() {
+            let path = dir.join(name);
+            if path.is_file() {
+                return Some(path);
+            }
+        }
+    }
+    None
+}
+
+/* The C compiler that links the executable, `$CC` or else the first of `cc`, `clang` and `gcc`.
+ * Comes with whether it takes `--target`, only clang can link for another target.
+ */
+fn find_linker(target: Option<&str>) -> Result<(PathBuf, bool), String> {
+    let from_env = std::env::var("CC").ok().and_then(|cc| find_tool(&cc));
+    let Some(linker) =
+        from_env.or_else(|| ["cc", "clang", "gcc"].iter().find_map(|t| find_tool(t)))
+    else {
+        return Err(
+            "no C compiler to link with, install one or set `CC`, or use `--emit=obj`".to_string(),
+        );
+    };
+    let is_clang = fs::canonicalize(&linker)
+        .unwrap_or_else(|_| linker.clone())
+        .file_name()
+        .is_some_and(|n| n.to_string_lossy().starts_with("clang"));
+    if target.is_some() && !is_clang {
+        let clang = find_tool("clang").ok_or(
+            "linking for another `--target` needs clang, use `--emit=obj` and link it yourself",
+        )?;
+        return Ok((clang, true));
+    }
+    Ok((linker, is_clang))
+}
+
+// The directory of a build's intermediate files, removed when it's dropped
+struct Intermediates {
+    path: PathBuf,
+}
+
+impl Intermediates {
+    fn new() -> Result<Intermediates, String> {
+        // builds can run at the same time, ie. in the tests
+        static BUILDS: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "rho-build-{}-{}",
+            std::process::id(),
+            BUILDS.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = std::env::temp_dir().join(name);
+        fs::create_dir_all(&path).map_err(|e| format!("can't create {}: {}", path.display(), e))?;
+        Ok(Intermediates { path })
+    }
+}
+
+impl Drop for Intermediates {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+fn build_source(source: &str, options: &BuildOptions) -> Result<PathBuf, String> {
+    let (compiled, _) = crate::compile(source);
+    let (program, symbols) = compiled.unwrap();
+    let mut mir = crate::mir::lower(&program, &symbols).unwrap();
+    crate::passes::optimize(&mut mir, &options.passes);
+    let ir = crate::codegen::generate(&mir, "a.rho");
+    build("a.rho", &ir, options)
+}
+
+#[test]
+fn test_default_output() {
+    assert_eq!(
+        default_output("src/hello.rho", Emit::Exe),
+        PathBuf::from("hello")
+    );
+    assert_eq!(
+        default_output("hello.rho", Emit::Ll),
+        PathBuf::from("hello.ll")
+    );
+    assert_eq!(default_output("a/b.rho", Emit::Obj), PathBuf::from("b.o"));
+    assert_eq!(Emit::parse("bc"), Some(Emit::Bc));
+    assert_eq!(default_output("a.rho", Emit::Mir), PathBuf::from("a.mir"));
+    assert_eq!(Emit::parse("asm"), None);
+    assert!(find_tool("rho-no-such-tool").is_none());
+}
+
+#[test]
+fn test_build_pipeline() {
+    let tools = ["llvm-as", "opt", "llc"];
+    if tools.iter().any(|t| find_tool(t).is_none()) || find_linker(None).is_err() {
+        return;
+    }
+    let dir = Intermediates::new().unwrap();
+    let source = "func main() -> int {\n    IO.puts(6 * 7)\n    return 3\n}\n";
+    for (emit, opt_level, name) in [
+        (Emit::Ll, 0, "a.ll"),
+        (Emit::Ll, 2, "a.opt.ll"),
+        (Emit::Bc, 1, "a.bc"),
+        (Emit::Obj, 0, "a.o"),
+        (Emit::Exe, 2, "a"),
+    ] {
+        let output = dir.path.join(name);
+        let options = BuildOptions {
+            emit,
+            output: Some(output.to_string_lossy().to_string()),
+            target: None,
+            opt_level,
+            ..BuildOptions::default()
+        };
+        assert_eq!(build_source(source, &options), Ok(output.clone()));
+        assert!(output.is_file(), "{}", name);
+    }
+    let ran = Command::new(dir.path.join("a")).output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&ran.stdout), "42\n");
+    assert_eq!(ran.status.code(), Some(3));
+    // nothing but the outputs, the intermediates went with their directory
+    assert_eq!(fs::read_dir(&dir.path).unwrap().count(), 5);
+    let optimized = fs::read_to_string(dir.path.join("a.opt.ll")).unwrap();
+    assert!(!optimized.contains("alloca"));
+
+    // `-o -`, the IR comes back instead of being written
+    let (compiled, _) = crate::compile(source);
+    let (program, symbols) = compiled.unwrap();
+    let mir = crate::mir::lower(&program, &symbols).unwrap();
+    let ir = crate::codegen::generate(&mir, "a.rho");
+    let options = BuildOptions {
+        emit: Emit::Ll,
+        output: Some("-".to_string()),
+        ..BuildOptions::default()
+    };
+    assert_eq!(build_text("a.rho", &ir, &options), Ok(ir.clone()));
+    let options = BuildOptions {
+        opt_level: 2,
+        ..options
+    };
+    assert_eq!(
+        build_text("a.rho", &ir, &options).map(|t| t.contains("alloca")),
+        Ok(false)
+    );
+    assert!(!Path::new("-").exists());
+
+    let options = BuildOptions {
+        emit: Emit::Obj,
+        output: Some(dir.path.join("b.o").to_string_lossy().to_string()),
+        target: Some("no-such-target".to_string()),
+        ..BuildOptions::default()
+    };
+    let error = build_source(source, &options).unwrap_err();
+    assert!(error.starts_with("`llc` failed\n"), "{}", error);
+    assert!(!dir.path.join("b.o").exists());
+}
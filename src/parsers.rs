@@ -1,347 +1,2166 @@
 use core::fmt;
 use std::fmt::write;
 
+use crate::diagnostics::Diagnostic;
 use crate::tokens::{self, *};
+use crate::types::Type;
 
-// #[derive(Debug, Clone, PartialEq)]
-// pub struct Expression {
-//     tokens: Vec<Token>,
-// }
-trait HasLen {
-    fn len(&self) -> usize;
+// Filled in by the resolver, indexes into its symbol table
+pub type SymbolId = usize;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+    pub symbol: Option<SymbolId>,
+}
+
+impl Ident {
+    pub fn new(name: &str, span: Span) -> Ident {
+        Ident {
+            name: name.to_string(),
+            span,
+            symbol: None,
+        }
+    }
+}
+
+// Bindings are immutable unless declared with `var`, a `const` is evaluated at compile time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mutability {
+    Immutable,
+    Mutable,
+    Const,
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum Expression<'a> {
+pub enum Expression {
     Definition {
-        definition_type: &'a Token<'a>,
-        identifier: &'a Token<'a>,
-        value: Box<Expression<'a>>,
-    }, // <type> <ident> = <expression>
-    Calculation, // <expression> <operator> <expression>
+        mutability: Mutability,
+        definition_type: Option<Type>,
+        identifier: Ident,
+        value: Box<Expression>,
+        span: Span,
+    }, // [var || const] <type> <ident> = <expression> || [var || const] <ident> = <expression>
+    Assignment {
+        target: Box<Expression>,
+        value: Box<Expression>,
+        span: Span,
+    }, // <ident>.<field> = <expression> || <ident>[<expression>] = <expression>
+    Calculation {
+        left: Box<Expression>,
+        operator: Operators,
+        right: Box<Expression>,
+        span: Span,
+    }, // <expression> <operator> <expression>
+    Unary {
+        operator: Operators,
+        operand: Box<Expression>,
+        span: Span,
+    }, // -<expression> || !<expression>
     FunctionCall {
-        function_name: FunctionName<'a>,
-        parameters: ParameterList<'a>,
+        function_name: FunctionName,
+        type_args: Vec<Type>,
+        parameters: Vec<Expression>,
+        span: Span,
     }, // <functionName>(<parameterList>) && functionName : <ident>.<ident>.<ident>...
-    // TODO: Change literalType to take literals
     Literal {
-        literal_type: TokenType,
-        token: &'a Token<'a>,
+        value: TokenValue,
+        span: Span,
     }, // <a literal>
-    Identifier, // <ident>
-    Empty {
-        tokens: Vec<&'a Token<'a>>,
+    Identifier(Ident), // <ident>
+    FieldAccess {
+        object: Box<Expression>,
+        field: Ident,
+        span: Span,
+    }, // <expression>.<ident>
+    Index {
+        object: Box<Expression>,
+        index: Box<Expression>,
+        span: Span,
+    }, // <expression>[<expression>]
+    List {
+        elements: Vec<Expression>,
+        span: Span,
+    }, // [thing, another, thing]
+    Tuple {
+        elements: Vec<Expression>,
+        span: Span,
+    }, // {thing, thing, thing}
+    Map {
+        entries: Vec<(Expression, Expression)>,
+        span: Span,
+    }, // {key: value, name: item}
+    Range {
+        start: Box<Expression>,
+        end: Box<Expression>,
+        step: Option<Box<Expression>>,
+        span: Span,
+    }, // 1..5 || -1..-10..-1
+    StructLiteral {
+        name: Ident,
+        type_args: Vec<Type>,
+        fields: Vec<(Ident, Expression)>,
+        span: Span,
+    }, // Point{x: 1, y: 2}
+    Lambda {
+        params: Vec<Param>,
+        body: Box<Expression>,
+        span: Span,
+    }, // fn x, y -> <expression>
+    Block {
+        expressions: Vec<Expression>,
+        span: Span,
+    }, // { <expression> ... }
+    If {
+        branches: Vec<(Expression, Expression)>,
+        else_branch: Option<Box<Expression>>,
+        span: Span,
+    }, // if <cond> {} elif <cond> {} else {}
+    While {
+        condition: Box<Expression>,
+        body: Box<Expression>,
+        span: Span,
+    },
+    For {
+        variable: Ident,
+        iterable: Box<Expression>,
+        body: Box<Expression>,
+        span: Span,
+    }, // for <ident> in [range] <expression> {}
+    Match {
+        subject: Box<Expression>,
+        arms: Vec<MatchArm>,
+        span: Span,
+    }, // match <expression> { <pattern> [when <guard>] -> <expression> ... }
+    Cond {
+        arms: Vec<(Expression, Expression)>,
+        span: Span,
+    }, // cond { <condition> -> <expression> ... }
+    Return {
+        value: Option<Box<Expression>>,
+        span: Span,
+    },
+    Try {
+        value: Box<Expression>,
+        span: Span,
+    }, // <expression>? returns an `{:error, reason}` and unwraps an `{:ok, value}`
+    Panic {
+        message: Box<Expression>,
+        span: Span,
+    }, // panic <expression>
+    Assert {
+        condition: Box<Expression>,
+        message: Option<Box<Expression>>,
+        span: Span,
+    }, // assert <condition> [, <message>]
+    Test {
+        name: String,
+        body: Box<Expression>,
+        span: Span,
+    }, // test "name" { .. }, only `rho test` runs these
+    Break {
+        span: Span,
+    },
+    Continue {
+        span: Span,
     },
+    Import {
+        path: String,
+        span: Span,
+    }, // import "strings"
+    Function(FunctionDecl),
+    Struct(StructDecl),
+    Enum(EnumDecl),
+    Interface(InterfaceDecl),
 }
 
-impl fmt::Display for Expression<'_> {
+impl Expression {
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Definition { span, .. }
+            | Expression::Assignment { span, .. }
+            | Expression::Calculation { span, .. }
+            | Expression::Unary { span, .. }
+            | Expression::FunctionCall { span, .. }
+            | Expression::Literal { span, .. }
+            | Expression::FieldAccess { span, .. }
+            | Expression::Index { span, .. }
+            | Expression::List { span, .. }
+            | Expression::Tuple { span, .. }
+            | Expression::Map { span, .. }
+            | Expression::Range { span, .. }
+            | Expression::StructLiteral { span, .. }
+            | Expression::Lambda { span, .. }
+            | Expression::Block { span, .. }
+            | Expression::If { span, .. }
+            | Expression::While { span, .. }
+            | Expression::For { span, .. }
+            | Expression::Match { span, .. }
+            | Expression::Cond { span, .. }
+            | Expression::Return { span, .. }
+            | Expression::Try { span, .. }
+            | Expression::Panic { span, .. }
+            | Expression::Assert { span, .. }
+            | Expression::Test { span, .. }
+            | Expression::Break { span }
+            | Expression::Continue { span }
+            | Expression::Import { span, .. } => *span,
+            Expression::Identifier(ident) => ident.span,
+            Expression::Function(decl) => decl.span,
+            Expression::Struct(decl) => decl.span,
+            Expression::Enum(decl) => decl.span,
+            Expression::Interface(decl) => decl.span,
+        }
+    }
+
+    // Every direct sub-expression, for the passes that walk the whole tree
+    pub fn children(&self) -> Vec<&Expression> {
+        match self {
+            Expression::Definition { value, .. } => vec![value],
+            Expression::Assignment { target, value, .. } => vec![target, value],
+            Expression::Calculation { left, right, .. } => vec![left, right],
+            Expression::Unary { operand, .. } => vec![operand],
+            Expression::FunctionCall { parameters, .. } => parameters.iter().collect(),
+            Expression::FieldAccess { object, .. } => vec![object],
+            Expression::Index { object, index, .. } => vec![object, index],
+            Expression::List { elements, .. } | Expression::Tuple { elements, .. } => {
+                elements.iter().collect()
+            }
+            Expression::Map { entries, .. } => entries.iter().flat_map(|(k, v)| [k, v]).collect(),
+            Expression::Range {
+                start, end, step, ..
+            } => {
+                let mut v: Vec<&Expression> = vec![start, end];
+                if let Some(s) = step {
+                    v.push(s);
+                }
+                v
+            }
+            Expression::StructLiteral { fields, .. } => fields.iter().map(|(_, v)| v).collect(),
+            Expression::Lambda { body, .. } => vec![body],
+            Expression::Block { expressions, .. } => expressions.iter().collect(),
+            Expression::If {
+                branches,
+                else_branch,
+                ..
+            } => {
+                let mut v: Vec<&Expression> = branches.iter().flat_map(|(c, b)| [c, b]).collect();
+                if let Some(e) = else_branch {
+                    v.push(e);
+                }
+                v
+            }
+            Expression::While {
+                condition, body, ..
+            } => vec![condition, body],
+            Expression::For { iterable, body, .. } => vec![iterable, body],
+            Expression::Match { subject, arms, .. } => {
+                let mut v: Vec<&Expression> = vec![subject];
+                for arm in arms.iter() {
+                    if let Some(g) = &arm.guard {
+                        v.push(g);
+                    }
+                    v.push(&arm.body);
+                }
+                v
+            }
+            Expression::Cond { arms, .. } => arms.iter().flat_map(|(c, b)| [c, b]).collect(),
+            Expression::Return { value: Some(v), .. } => vec![v],
+            Expression::Try { value, .. } => vec![value],
+            Expression::Panic { message, .. } => vec![message],
+            Expression::Test { body, .. } => vec![body],
+            Expression::Assert {
+                condition, message, ..
+            } => {
+                let mut v: Vec<&Expression> = vec![condition];
+                if let Some(m) = message {
+                    v.push(m);
+                }
+                v
+            }
+            Expression::Function(decl) => vec![&decl.body],
+            _ => vec![],
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Vec<&mut Expression> {
+        match self {
+            Expression::Definition { value, .. } => vec![value],
+            Expression::Assignment { target, value, .. } => vec![target, value],
+            Expression::Calculation { left, right, .. } => vec![left, right],
+            Expression::Unary { operand, .. } => vec![operand],
+            Expression::FunctionCall { parameters, .. } => parameters.iter_mut().collect(),
+            Expression::FieldAccess { object, .. } => vec![object],
+            Expression::Index { object, index, .. } => vec![object, index],
+            Expression::List { elements, .. } | Expression::Tuple { elements, .. } => {
+                elements.iter_mut().collect()
+            }
+            Expression::Map { entries, .. } => {
+                entries.iter_mut().flat_map(|(k, v)| [k, v]).collect()
+            }
+            Expression::Range {
+                start, end, step, ..
+            } => {
+                let mut v: Vec<&mut Expression> = vec![start, end];
+                if let Some(s) = step {
+                    v.push(s);
+                }
+                v
+            }
+            Expression::StructLiteral { fields, .. } => fields.iter_mut().map(|(_, v)| v).collect(),
+            Expression::Lambda { body, .. } => vec![body],
+            Expression::Block { expressions, .. } => expressions.iter_mut().collect(),
+            Expression::If {
+                branches,
+                else_branch,
+                ..
+            } => {
+                let mut v: Vec<&mut Expression> =
+                    branches.iter_mut().flat_map(|(c, b)| [c, b]).collect();
+                if let Some(e) = else_branch {
+                    v.push(e);
+                }
+                v
+            }
+            Expression::While {
+                condition, body, ..
+            } => vec![condition, body],
+            Expression::For { iterable, body, .. } => vec![iterable, body],
+            Expression::Match { subject, arms, .. } => {
+                let mut v: Vec<&mut Expression> = vec![subject];
+                for arm in arms.iter_mut() {
+                    if let Some(g) = &mut arm.guard {
+                        v.push(g);
+                    }
+                    v.push(&mut arm.body);
+                }
+                v
+            }
+            Expression::Cond { arms, .. } => arms.iter_mut().flat_map(|(c, b)| [c, b]).collect(),
+            Expression::Return { value: Some(v), .. } => vec![v],
+            Expression::Try { value, .. } => vec![value],
+            Expression::Test { body, .. } => vec![body],
+            Expression::Panic { message, .. } => vec![message],
+            Expression::Assert {
+                condition, message, ..
+            } => {
+                let mut v: Vec<&mut Expression> = vec![condition];
+                if let Some(m) = message {
+                    v.push(m);
+                }
+                v
+            }
+            Expression::Function(decl) => vec![&mut decl.body],
+            _ => vec![],
+        }
+    }
+}
+
+impl fmt::Display for Expression {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Expression::Definition {
                 definition_type,
                 identifier,
                 value,
+                ..
             } => write!(
                 f,
-                "Def[t: {:?} | ident: {:?} | val: {:?}]",
-                definition_type.token_type, identifier.string, value
+                "Def[t: {} | ident: {:?} | val: {:?}]",
+                definition_type
+                    .as_ref()
+                    .map(|t| t.to_string())
+                    .unwrap_or("_".to_string()),
+                identifier.name,
+                value
             ),
-            // Expression::Calculation => todo!(),
-            // Expression::FunctionCall => todo!(),
-            // Expression::Literal { literal_type, token } => todo!(),
-            // Expression::Identifier => todo!(),
-            // Expression::Empty { tokens } => todo!(),
             _ => write!(f, "{:?}", self),
         }
     }
 }
 
-impl HasLen for Expression<'_> {
-    fn len(&self) -> usize {
-        match &self {
-            Expression::Calculation => todo!(),
-            Expression::FunctionCall {
-                function_name,
-                parameters,
-            } => function_name.idents.len() + parameters.params.len(),
-            // Expression::Literal(Literal) => 1,
-            Expression::Identifier => todo!(),
-            Expression::Definition {
-                definition_type,
-                identifier,
-                value,
-            } => 3 + value.len(),
-            Expression::Literal {
-                literal_type,
-                token,
-            } => 1,
-            Expression::Empty { tokens } => tokens.len(),
-            // Expression::Empty(se) => se.tokens.len(),
-        }
-    }
-}
-
-// pub struct Expression {
-//     expression_type: ExpressionType,
-// }
-
-// #[derive(Debug, Clone, PartialEq)]
-// pub struct Empty {
-//     tokens: Vec<Token>,
-// }
-
-// #[derive(Debug, Clone, PartialEq)]
-// pub struct Definition {
-//     definition_type: Token,
-//     identifier: Token,
-//     value: Expression,
-// }
-
-// impl fmt::Display for Definition<'static> {
-//     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-//         match self.definition_type.token_type {
-//             TokenType::Type(Types::Primitive(PrimitiveType::Int)) => {
-//                 write!(
-//                     f,
-//                     "
-//     %{1} = alloca i32
-//     %computed_value_{1} = {0:?}
-
-//     store i32 %computed_value_{1}, i32* %{1}
-//                 ",
-//                     self.value,
-//                     // self.identifier.string,
-//                     self.identifier.string
-//                 )
-//             }
-//             TokenType::Type(_) => todo!(),
-//             _ => todo!(),
-//         }
-//     }
-// }
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionName {
+    pub idents: Vec<Ident>,
+}
+
+impl FunctionName {
+    // The dotted name, ie. "IO.print"
+    pub fn path(&self) -> String {
+        self.idents
+            .iter()
+            .map(|i| i.name.to_owned())
+            .collect::<Vec<String>>()
+            .join(".")
+    }
+
+    pub fn span(&self) -> Span {
+        self.idents.first().map(|i| i.span).unwrap_or_default()
+    }
+}
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct Calculation<'a> {
-    left: Token<'a>,
-    operator: Token<'a>,
-    right: Token<'a>,
+pub struct Param {
+    pub param_type: Option<Type>,
+    pub name: Ident,
 }
 
+// A generic type parameter, ie. the `T: Show` in `func show_all[T: Show](list[T] xs)`
 #[derive(Debug, Clone, PartialEq)]
-pub struct FunctionCall<'a> {
-    function_name: Token<'a>,
-    parameter_list: Vec<Token<'a>>,
+pub struct TypeParam {
+    pub name: String,
+    pub bounds: Vec<Type>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct FunctionName<'a> {
-    idents: Vec<Token<'a>>,
+pub struct FunctionDecl {
+    pub name: FunctionName,
+    pub type_params: Vec<TypeParam>,
+    pub params: Vec<Param>,
+    pub return_type: Option<Type>,
+    pub body: Box<Expression>,
+    pub span: Span,
 }
+
 #[derive(Debug, Clone, PartialEq)]
-pub struct ParameterList<'a> {
-    params: Vec<Token<'a>>,
-}
-
-// #[derive(Debug, Clone, PartialEq)]
-// pub struct Literal {
-//     token: Token,
-// }
-
-// fn create_expression_literal<'a>(token: &'a Token) -> Expression<'a> {
-//     // let tok = token.to_owned();
-//     // let expression =
-//     Expression::Literal {
-//         literal_type: token.token_type,
-//         token: token,
-//     }
-// }
-
-fn match_empty<'a>(tokens: Vec<&'a Token<'a>>) -> Result<Expression<'a>, &'a str> {
-    let empty_len: usize = tokens
-        .iter()
-        .take_while(|t| {
-            matches!(t.token_type, TokenType::NewLine) || matches!(t.token_type, TokenType::Comment)
-        })
-        .count();
-    if empty_len > 0 {
-        return Ok(Expression::Empty {
-            tokens: tokens.get(0..empty_len).unwrap().to_vec(),
-        });
+pub struct StructDecl {
+    pub name: Ident,
+    pub type_params: Vec<TypeParam>,
+    pub fields: Vec<(Type, Ident)>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumVariant {
+    pub name: Ident,
+    pub fields: Vec<Type>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumDecl {
+    pub name: Ident,
+    pub type_params: Vec<TypeParam>,
+    pub variants: Vec<EnumVariant>,
+    pub span: Span,
+}
+
+// A method an interface requires, `Self` stands for the implementing type
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethodSignature {
+    pub name: Ident,
+    pub params: Vec<Param>,
+    pub return_type: Option<Type>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterfaceDecl {
+    pub name: Ident,
+    pub type_params: Vec<TypeParam>,
+    pub methods: Vec<MethodSignature>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expression>,
+    pub body: Expression,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Wildcard(Span),                            // _
+    Binding(Ident),                            // x
+    Literal(TokenValue, Span),                 // 1 || "a" || :ok
+    Tuple(Vec<Pattern>, Span),                 // {:ok, value}
+    List(Vec<Pattern>, Span),                  // [a, b]
+    Variant(FunctionName, Vec<Pattern>, Span), // Shape.Circle(r)
+}
+
+impl Pattern {
+    pub fn span(&self) -> Span {
+        match self {
+            Pattern::Wildcard(span)
+            | Pattern::Literal(_, span)
+            | Pattern::Tuple(_, span)
+            | Pattern::List(_, span)
+            | Pattern::Variant(_, _, span) => *span,
+            Pattern::Binding(ident) => ident.span,
+        }
+    }
+}
+
+type Tokens<'t> = &'t [Token<'t>];
+type Parsed<'t, T> = Result<(T, Tokens<'t>), Diagnostic>;
+
+fn peek(tokens: Tokens) -> TokenType {
+    tokens
+        .first()
+        .map(|t| t.token_type)
+        .unwrap_or(TokenType::EoF)
+}
+
+fn peek_at(tokens: Tokens, n: usize) -> TokenType {
+    tokens
+        .get(n)
+        .map(|t| t.token_type)
+        .unwrap_or(TokenType::EoF)
+}
+
+fn span_of(tokens: Tokens) -> Span {
+    tokens.first().map(|t| t.span).unwrap_or_default()
+}
+
+fn is_delimiter(tokens: Tokens, delimiter: Delimiters) -> bool {
+    peek(tokens) == TokenType::Delimiter(delimiter)
+}
+
+fn is_operator(tokens: Tokens, operator: Operators) -> bool {
+    peek(tokens) == TokenType::Operator(operator)
+}
+
+fn is_keyword(tokens: Tokens, keyword: Keywords) -> bool {
+    peek(tokens) == TokenType::Keyword(keyword)
+}
+
+fn skip_newlines(mut tokens: Tokens) -> Tokens {
+    while peek(tokens) == TokenType::NewLine {
+        tokens = &tokens[1..];
+    }
+    tokens
+}
+
+fn describe(tokens: Tokens) -> String {
+    match tokens.first() {
+        Some(t) if t.token_type == TokenType::NewLine => "end of line".to_string(),
+        Some(t) if t.token_type == TokenType::EoF => "end of file".to_string(),
+        Some(t) => format!("`{}`", t.string),
+        None => "end of file".to_string(),
     }
-    Err("Failed to match empty")
 }
 
-fn match_definition<'a>(tokens: Vec<&'a Token<'a>>) -> Result<Expression<'a>, &'a str> {
-    if matches!(tokens.get(0).unwrap().token_type, TokenType::Type(_))
-        && matches!(tokens.get(1).unwrap().token_type, TokenType::Identifier)
+fn unexpected(tokens: Tokens, expected: &str) -> Diagnostic {
+    Diagnostic::error(
+        format!("expected {}, found {}", expected, describe(tokens)),
+        span_of(tokens),
+    )
+}
+
+fn expect<'t>(tokens: Tokens<'t>, token_type: TokenType, expected: &str) -> Parsed<'t, Span> {
+    if peek(tokens) == token_type {
+        return Ok((span_of(tokens), &tokens[1..]));
+    }
+    Err(unexpected(tokens, expected))
+}
+
+// Names may also be type keywords, ie. `func map[T, U](..)` or `List.map(..)`
+fn match_name(tokens: Tokens) -> Parsed<Ident> {
+    match peek(tokens) {
+        TokenType::Identifier | TokenType::Type(_) => {
+            Ok((Ident::new(tokens[0].string, tokens[0].span), &tokens[1..]))
+        }
+        _ => Err(unexpected(tokens, "a name")),
+    }
+}
+
+fn match_identifier(tokens: Tokens) -> Parsed<Ident> {
+    match peek(tokens) {
+        TokenType::Identifier => Ok((Ident::new(tokens[0].string, tokens[0].span), &tokens[1..])),
+        _ => Err(unexpected(tokens, "an identifier")),
+    }
+}
+
+// Parses a comma separated list of `item`s up to the `close` delimiter, newlines are ignored.
+fn match_list_of<'t, T>(
+    mut tokens: Tokens<'t>,
+    close: Delimiters,
+    item: impl Fn(Tokens<'t>) -> Parsed<'t, T>,
+) -> Parsed<'t, Vec<T>> {
+    let mut items = vec![];
+    loop {
+        tokens = skip_newlines(tokens);
+        if is_delimiter(tokens, close) {
+            return Ok((items, &tokens[1..]));
+        }
+        let (i, ros) = item(tokens)?;
+        items.push(i);
+        tokens = skip_newlines(ros);
+        if is_delimiter(tokens, Delimiters::Comma) {
+            tokens = &tokens[1..];
+        } else if !is_delimiter(tokens, close) {
+            return Err(unexpected(
+                tokens,
+                &format!("`,` or `{}`", delimiter_str(close)),
+            ));
+        }
+    }
+}
+
+fn delimiter_str(delimiter: Delimiters) -> &'static str {
+    match delimiter {
+        Delimiters::ParClose => ")",
+        Delimiters::BracketClose => "]",
+        Delimiters::BraceClose => "}",
+        _ => "delimiter",
+    }
+}
+
+/* Types
+ * int | str | list[T] | map[K, V] | fn(int, int) -> int | Pair[int, str] | T
+ */
+pub fn match_type(tokens: Tokens) -> Parsed<Type> {
+    match peek(tokens) {
+        TokenType::Type(types) => {
+            let base = Type::from_token_type(types);
+            if is_delimiter(&tokens[1..], Delimiters::BracketOpen) {
+                let (args, ros) =
+                    match_list_of(&tokens[2..], Delimiters::BracketClose, match_type)?;
+                if let Type::BuiltIn(b, _) = base {
+                    return Ok((Type::BuiltIn(b, args), ros));
+                }
+                return Err(Diagnostic::error(
+                    format!("`{}` does not take type arguments", tokens[0].string),
+                    tokens[0].span,
+                ));
+            }
+            Ok((base, &tokens[1..]))
+        }
+        TokenType::Keyword(Keywords::Fn) => {
+            let (_, ros) = expect(
+                &tokens[1..],
+                TokenType::Delimiter(Delimiters::ParOpen),
+                "`(`",
+            )?;
+            let (params, ros) = match_list_of(ros, Delimiters::ParClose, match_type)?;
+            if is_operator(ros, Operators::Into) {
+                let (ret, ros) = match_type(&ros[1..])?;
+                return Ok((Type::function(params, ret), ros));
+            }
+            Ok((Type::function(params, Type::Unit), ros))
+        }
+        TokenType::Identifier => {
+            let name = tokens[0].string.to_string();
+            if is_delimiter(&tokens[1..], Delimiters::BracketOpen) {
+                let (args, ros) =
+                    match_list_of(&tokens[2..], Delimiters::BracketClose, match_type)?;
+                return Ok((Type::Named(name, args), ros));
+            }
+            Ok((Type::Named(name, vec![]), &tokens[1..]))
+        }
+        _ => Err(unexpected(tokens, "a type")),
+    }
+}
+
+// [T, U: Show + Eq]
+fn match_type_params(tokens: Tokens) -> Parsed<Vec<TypeParam>> {
+    if !is_delimiter(tokens, Delimiters::BracketOpen) {
+        return Ok((vec![], tokens));
+    }
+    match_list_of(&tokens[1..], Delimiters::BracketClose, |tokens| {
+        let (name, mut ros) = match_identifier(tokens)?;
+        let mut bounds = vec![];
+        if is_delimiter(ros, Delimiters::Colon) {
+            let (bound, r) = match_type(&ros[1..])?;
+            bounds.push(bound);
+            ros = r;
+            while is_operator(ros, Operators::Add) {
+                let (bound, r) = match_type(&ros[1..])?;
+                bounds.push(bound);
+                ros = r;
+            }
+        }
+        Ok((
+            TypeParam {
+                name: name.name,
+                bounds,
+            },
+            ros,
+        ))
+    })
+}
+
+fn type_param_names(type_params: &[TypeParam]) -> Vec<String> {
+    type_params.iter().map(|p| p.name.to_owned()).collect()
+}
+
+// A typed parameter, `<type> <ident>`
+fn match_param(tokens: Tokens) -> Parsed<Param> {
+    let (param_type, ros) = match_type(tokens)?;
+    let (name, ros) = match_identifier(ros)?;
+    Ok((
+        Param {
+            param_type: Some(param_type),
+            name,
+        },
+        ros,
+    ))
+}
+
+// A lambda parameter, the type is optional, `x` || `int x`
+fn match_lambda_param(tokens: Tokens) -> Parsed<Param> {
+    if peek(tokens) == TokenType::Identifier
         && matches!(
-            tokens.get(2).unwrap().token_type,
-            TokenType::Operator(Operators::Equal)
+            peek_at(tokens, 1),
+            TokenType::Delimiter(Delimiters::Comma)
+                | TokenType::Delimiter(Delimiters::ParClose)
+                | TokenType::Operator(Operators::Into)
         )
-        && matches!(tokens.get(3).unwrap().token_type, TokenType::Literal(_))
     {
-        let dt = tokens.get(0).unwrap();
-        let literal_token = tokens.get(3).unwrap().to_owned();
-        let v: Expression<'a> = Expression::Literal {
-            literal_type: tokens.get(3).unwrap().token_type,
-            token: tokens.get(3).unwrap(),
-        };
-        let expression: Result<Expression<'a>, &str> = Ok(Expression::Definition {
-            definition_type: dt,
-            identifier: tokens.get(1).unwrap(),
-            value: Box::new(v),
-        });
+        let (name, ros) = match_identifier(tokens)?;
+        return Ok((
+            Param {
+                param_type: None,
+                name,
+            },
+            ros,
+        ));
+    }
+    match_param(tokens)
+}
+
+fn match_block(tokens: Tokens) -> Parsed<Expression> {
+    let (span, mut tokens) = expect(tokens, TokenType::Delimiter(Delimiters::BraceOpen), "`{`")?;
+    let mut expressions = vec![];
+    loop {
+        tokens = skip_separators(tokens);
+        if is_delimiter(tokens, Delimiters::BraceClose) {
+            return Ok((Expression::Block { expressions, span }, &tokens[1..]));
+        }
+        if peek(tokens) == TokenType::EoF {
+            return Err(Diagnostic::error("block never closed, expected `}`", span));
+        }
+        let (e, ros) = match_statement(tokens)?;
+        expressions.push(e);
+        tokens = expect_statement_end(ros)?;
+    }
+}
+
+fn skip_separators(mut tokens: Tokens) -> Tokens {
+    while matches!(
+        peek(tokens),
+        TokenType::NewLine | TokenType::Delimiter(Delimiters::Semicolon)
+    ) {
+        tokens = &tokens[1..];
+    }
+    tokens
+}
+
+fn expect_statement_end(tokens: Tokens) -> Result<Tokens, Diagnostic> {
+    match peek(tokens) {
+        TokenType::NewLine | TokenType::Delimiter(Delimiters::Semicolon) => Ok(&tokens[1..]),
+        TokenType::Delimiter(Delimiters::BraceClose) | TokenType::EoF => Ok(tokens),
+        _ => Err(unexpected(tokens, "end of statement")),
+    }
+}
+
+// func <name>[<type params>](<params>) -> <type> { <body> }
+fn match_function(tokens: Tokens) -> Parsed<Expression> {
+    let span = span_of(tokens);
+    let (first, mut ros) = match_name(&tokens[1..])?;
+    let mut idents = vec![first];
+    while is_delimiter(ros, Delimiters::Period) {
+        let (ident, r) = match_name(&ros[1..])?;
+        idents.push(ident);
+        ros = r;
+    }
+    let (type_params, ros) = match_type_params(ros)?;
+    let (_, ros) = expect(ros, TokenType::Delimiter(Delimiters::ParOpen), "`(`")?;
+    let (params, mut ros) = match_list_of(ros, Delimiters::ParClose, match_param)?;
+    let mut return_type = None;
+    if is_operator(ros, Operators::Into) {
+        let (t, r) = match_type(&ros[1..])?;
+        return_type = Some(t);
+        ros = r;
+    }
+    let (body, ros) = match_block(ros)?;
+
+    let names = type_param_names(&type_params);
+    let decl = FunctionDecl {
+        name: FunctionName { idents },
+        params: params
+            .into_iter()
+            .map(|p| Param {
+                param_type: p.param_type.map(|t| t.bind_params(&names)),
+                name: p.name,
+            })
+            .collect(),
+        return_type: return_type.map(|t| t.bind_params(&names)),
+        body: Box::new(bind_body_params(body, &names)),
+        type_params: bind_bound_params(type_params),
+        span,
+    };
+    Ok((Expression::Function(decl), ros))
+}
+
+fn bind_bound_params(type_params: Vec<TypeParam>) -> Vec<TypeParam> {
+    let names = type_param_names(&type_params);
+    type_params
+        .into_iter()
+        .map(|p| TypeParam {
+            name: p.name,
+            bounds: p.bounds.iter().map(|b| b.bind_params(&names)).collect(),
+        })
+        .collect()
+}
+
+// Type annotations inside a generic function's body may refer to its type parameters
+fn bind_body_params(expression: Expression, names: &[String]) -> Expression {
+    if names.is_empty() {
         return expression;
     }
-    Err("Could not match defintion")
-}
-
-fn match_function_name<'a>(tokens: Vec<&'a Token<'a>>) -> Result<FunctionName<'a>, &'a str> {
-    let iter = tokens.iter();
-    let mut fn_name: Vec<Token> = vec![];
-    // let x = iter.clone().next().unwrap();
-    'keep_matching: for t in iter {
-        if matches!(t.token_type, TokenType::Identifier)
-            && matches!(
-                fn_name
-                    .last()
-                    .unwrap_or(&Token {
-                        string: "",
-                        token_type: TokenType::Delimiter(Delimiters::Period)
-                    })
-                    .token_type,
-                TokenType::Delimiter(Delimiters::Period)
-            )
-        {
-            fn_name.push(**t);
-            continue 'keep_matching;
-        }
-        if matches!(t.token_type, TokenType::Delimiter(Delimiters::Period))
-            && matches!(
-                fn_name
-                    .last()
-                    .unwrap_or(&Token {
-                        string: "",
-                        token_type: TokenType::EoF
+    map_types(expression, &|t| t.bind_params(names))
+}
+
+// Rewrites every type annotation in an expression tree
+pub fn map_types(expression: Expression, f: &dyn Fn(&Type) -> Type) -> Expression {
+    let m = |e: Expression| map_types(e, f);
+    let mb = |e: Box<Expression>| Box::new(map_types(*e, f));
+    match expression {
+        Expression::Definition {
+            mutability,
+            definition_type,
+            identifier,
+            value,
+            span,
+        } => Expression::Definition {
+            mutability,
+            definition_type: definition_type.map(|t| f(&t)),
+            identifier,
+            value: mb(value),
+            span,
+        },
+        Expression::Assignment {
+            target,
+            value,
+            span,
+        } => Expression::Assignment {
+            target: mb(target),
+            value: mb(value),
+            span,
+        },
+        Expression::Calculation {
+            left,
+            operator,
+            right,
+            span,
+        } => Expression::Calculation {
+            left: mb(left),
+            operator,
+            right: mb(right),
+            span,
+        },
+        Expression::Unary {
+            operator,
+            operand,
+            span,
+        } => Expression::Unary {
+            operator,
+            operand: mb(operand),
+            span,
+        },
+        Expression::FunctionCall {
+            function_name,
+            type_args,
+            parameters,
+            span,
+        } => Expression::FunctionCall {
+            function_name,
+            type_args: type_args.iter().map(f).collect(),
+            parameters: parameters.into_iter().map(m).collect(),
+            span,
+        },
+        Expression::FieldAccess {
+            object,
+            field,
+            span,
+        } => Expression::FieldAccess {
+            object: mb(object),
+            field,
+            span,
+        },
+        Expression::Index {
+            object,
+            index,
+            span,
+        } => Expression::Index {
+            object: mb(object),
+            index: mb(index),
+            span,
+        },
+        Expression::List { elements, span } => Expression::List {
+            elements: elements.into_iter().map(m).collect(),
+            span,
+        },
+        Expression::Tuple { elements, span } => Expression::Tuple {
+            elements: elements.into_iter().map(m).collect(),
+            span,
+        },
+        Expression::Map { entries, span } => Expression::Map {
+            entries: entries.into_iter().map(|(k, v)| (m(k), m(v))).collect(),
+            span,
+        },
+        Expression::Range {
+            start,
+            end,
+            step,
+            span,
+        } => Expression::Range {
+            start: mb(start),
+            end: mb(end),
+            step: step.map(mb),
+            span,
+        },
+        Expression::StructLiteral {
+            name,
+            type_args,
+            fields,
+            span,
+        } => Expression::StructLiteral {
+            name,
+            type_args: type_args.iter().map(f).collect(),
+            fields: fields.into_iter().map(|(n, v)| (n, m(v))).collect(),
+            span,
+        },
+        Expression::Lambda { params, body, span } => Expression::Lambda {
+            params: params
+                .into_iter()
+                .map(|p| Param {
+                    param_type: p.param_type.map(|t| f(&t)),
+                    name: p.name,
+                })
+                .collect(),
+            body: mb(body),
+            span,
+        },
+        Expression::Block { expressions, span } => Expression::Block {
+            expressions: expressions.into_iter().map(m).collect(),
+            span,
+        },
+        Expression::If {
+            branches,
+            else_branch,
+            span,
+        } => Expression::If {
+            branches: branches.into_iter().map(|(c, b)| (m(c), m(b))).collect(),
+            else_branch: else_branch.map(mb),
+            span,
+        },
+        Expression::While {
+            condition,
+            body,
+            span,
+        } => Expression::While {
+            condition: mb(condition),
+            body: mb(body),
+            span,
+        },
+        Expression::For {
+            variable,
+            iterable,
+            body,
+            span,
+        } => Expression::For {
+            variable,
+            iterable: mb(iterable),
+            body: mb(body),
+            span,
+        },
+        Expression::Match {
+            subject,
+            arms,
+            span,
+        } => Expression::Match {
+            subject: mb(subject),
+            arms: arms
+                .into_iter()
+                .map(|arm| MatchArm {
+                    pattern: arm.pattern,
+                    guard: arm.guard.map(m),
+                    body: m(arm.body),
+                })
+                .collect(),
+            span,
+        },
+        Expression::Cond { arms, span } => Expression::Cond {
+            arms: arms.into_iter().map(|(c, b)| (m(c), m(b))).collect(),
+            span,
+        },
+        Expression::Return { value, span } => Expression::Return {
+            value: value.map(mb),
+            span,
+        },
+        Expression::Try { value, span } => Expression::Try {
+            value: mb(value),
+            span,
+        },
+        Expression::Panic { message, span } => Expression::Panic {
+            message: mb(message),
+            span,
+        },
+        Expression::Test { name, body, span } => Expression::Test {
+            name,
+            body: mb(body),
+            span,
+        },
+        Expression::Assert {
+            condition,
+            message,
+            span,
+        } => Expression::Assert {
+            condition: mb(condition),
+            message: message.map(mb),
+            span,
+        },
+        Expression::Function(decl) => Expression::Function(FunctionDecl {
+            params: decl
+                .params
+                .into_iter()
+                .map(|p| Param {
+                    param_type: p.param_type.map(|t| f(&t)),
+                    name: p.name,
+                })
+                .collect(),
+            return_type: decl.return_type.map(|t| f(&t)),
+            body: mb(decl.body),
+            ..decl
+        }),
+        other => other,
+    }
+}
+
+// struct <name>[<type params>] { <type> <ident>, ... }
+fn match_struct(tokens: Tokens) -> Parsed<Expression> {
+    let span = span_of(tokens);
+    let (name, ros) = match_identifier(&tokens[1..])?;
+    let (type_params, ros) = match_type_params(ros)?;
+    let (_, ros) = expect(ros, TokenType::Delimiter(Delimiters::BraceOpen), "`{`")?;
+    let (fields, ros) = match_members(ros, |tokens| {
+        let (field_type, ros) = match_type(tokens)?;
+        let (field, ros) = match_identifier(ros)?;
+        Ok(((field_type, field), ros))
+    })?;
+    let names = type_param_names(&type_params);
+    let decl = StructDecl {
+        name,
+        fields: fields
+            .into_iter()
+            .map(|(t, f)| (t.bind_params(&names), f))
+            .collect(),
+        type_params: bind_bound_params(type_params),
+        span,
+    };
+    Ok((Expression::Struct(decl), ros))
+}
+
+// Members of a struct, enum or interface body, separated by commas or newlines up to a `}`
+fn match_members<'t, T>(
+    mut tokens: Tokens<'t>,
+    item: impl Fn(Tokens<'t>) -> Parsed<'t, T>,
+) -> Parsed<'t, Vec<T>> {
+    let mut items = vec![];
+    loop {
+        while matches!(
+            peek(tokens),
+            TokenType::NewLine | TokenType::Delimiter(Delimiters::Comma)
+        ) {
+            tokens = &tokens[1..];
+        }
+        if is_delimiter(tokens, Delimiters::BraceClose) {
+            return Ok((items, &tokens[1..]));
+        }
+        let (i, ros) = item(tokens)?;
+        items.push(i);
+        if !matches!(
+            peek(ros),
+            TokenType::NewLine
+                | TokenType::Delimiter(Delimiters::Comma)
+                | TokenType::Delimiter(Delimiters::BraceClose)
+        ) {
+            return Err(unexpected(ros, "`,`, a new line or `}`"));
+        }
+        tokens = ros;
+    }
+}
+
+// enum <name>[<type params>] { Thing, AThing(int), TheThing(str, T) }
+fn match_enum(tokens: Tokens) -> Parsed<Expression> {
+    let span = span_of(tokens);
+    let (name, ros) = match_identifier(&tokens[1..])?;
+    let (type_params, ros) = match_type_params(ros)?;
+    let (_, ros) = expect(ros, TokenType::Delimiter(Delimiters::BraceOpen), "`{`")?;
+    let (variants, ros) = match_members(ros, |tokens| {
+        let (name, ros) = match_identifier(tokens)?;
+        if is_delimiter(ros, Delimiters::ParOpen) {
+            let (fields, ros) = match_list_of(&ros[1..], Delimiters::ParClose, match_type)?;
+            return Ok((EnumVariant { name, fields }, ros));
+        }
+        Ok((
+            EnumVariant {
+                name,
+                fields: vec![],
+            },
+            ros,
+        ))
+    })?;
+    let names = type_param_names(&type_params);
+    let decl = EnumDecl {
+        name,
+        variants: variants
+            .into_iter()
+            .map(|v| EnumVariant {
+                name: v.name,
+                fields: v.fields.iter().map(|t| t.bind_params(&names)).collect(),
+            })
+            .collect(),
+        type_params: bind_bound_params(type_params),
+        span,
+    };
+    Ok((Expression::Enum(decl), ros))
+}
+
+// interface <name>[<type params>] { func <name>(<params>) -> <type> ... }
+fn match_interface(tokens: Tokens) -> Parsed<Expression> {
+    let span = span_of(tokens);
+    let (name, ros) = match_identifier(&tokens[1..])?;
+    let (type_params, ros) = match_type_params(ros)?;
+    let (_, ros) = expect(ros, TokenType::Delimiter(Delimiters::BraceOpen), "`{`")?;
+    let (methods, ros) = match_members(ros, |tokens| {
+        let (_, ros) = expect(tokens, TokenType::Keyword(Keywords::Func), "`func`")?;
+        let (name, ros) = match_name(ros)?;
+        let (_, ros) = expect(ros, TokenType::Delimiter(Delimiters::ParOpen), "`(`")?;
+        let (params, mut ros) = match_list_of(ros, Delimiters::ParClose, match_param)?;
+        let mut return_type = None;
+        if is_operator(ros, Operators::Into) {
+            let (t, r) = match_type(&ros[1..])?;
+            return_type = Some(t);
+            ros = r;
+        }
+        Ok((
+            MethodSignature {
+                name,
+                params,
+                return_type,
+            },
+            ros,
+        ))
+    })?;
+    let names = type_param_names(&type_params);
+    let decl = InterfaceDecl {
+        name,
+        methods: methods
+            .into_iter()
+            .map(|m| MethodSignature {
+                name: m.name,
+                params: m
+                    .params
+                    .into_iter()
+                    .map(|p| Param {
+                        param_type: p.param_type.map(|t| t.bind_params(&names)),
+                        name: p.name,
                     })
-                    .token_type,
-                TokenType::Identifier
-            )
+                    .collect(),
+                return_type: m.return_type.map(|t| t.bind_params(&names)),
+            })
+            .collect(),
+        type_params: bind_bound_params(type_params),
+        span,
+    };
+    Ok((Expression::Interface(decl), ros))
+}
+
+// <type> <ident> = <expression>, returns None if the tokens aren't shaped like a typed definition
+fn try_match_typed_definition(tokens: Tokens) -> Option<Result<(Expression, Tokens), Diagnostic>> {
+    let (definition_type, ros) = match_type(tokens).ok()?;
+    if peek(ros) != TokenType::Identifier
+        || peek_at(ros, 1) != TokenType::Operator(Operators::Equal)
+    {
+        return None;
+    }
+    let identifier = Ident::new(ros[0].string, ros[0].span);
+    Some(match_expression(&ros[2..]).map(|(value, ros)| {
+        (
+            Expression::Definition {
+                mutability: Mutability::Immutable,
+                definition_type: Some(definition_type),
+                identifier,
+                value: Box::new(value),
+                span: span_of(tokens),
+            },
+            ros,
+        )
+    }))
+}
+
+// var [<type>] <ident> = <expression> || const [<type>] <ident> = <expression>
+fn match_binding(tokens: Tokens, mutability: Mutability) -> Parsed<Expression> {
+    let span = span_of(tokens);
+    let ros = &tokens[1..];
+    let (definition_type, ros) = match match_type(ros) {
+        Ok((t, rest)) if peek(rest) == TokenType::Identifier => (Some(t), rest),
+        _ => (None, ros),
+    };
+    let (identifier, ros) = match_name(ros)?;
+    let (_, ros) = expect(ros, TokenType::Operator(Operators::Equal), "`=`")?;
+    let (value, ros) = match_expression(ros)?;
+    Ok((
+        Expression::Definition {
+            mutability,
+            definition_type,
+            identifier,
+            value: Box::new(value),
+            span,
+        },
+        ros,
+    ))
+}
+
+fn match_statement(tokens: Tokens) -> Parsed<Expression> {
+    match peek(tokens) {
+        TokenType::Keyword(Keywords::Import) => {
+            let span = span_of(tokens);
+            let ros = &tokens[1..];
+            match peek(ros) {
+                TokenType::Literal(Literals::BuiltIn(BuiltinType::String)) => {
+                    let path = match token_value(&ros[0]) {
+                        Ok(TokenValue::String(s)) => s,
+                        _ => String::new(),
+                    };
+                    Ok((Expression::Import { path, span }, &ros[1..]))
+                }
+                _ => Err(unexpected(ros, "a string path")),
+            }
+        }
+        TokenType::Keyword(Keywords::Func) => match_function(tokens),
+        // `fn main() {..}` is accepted as a function definition too
+        TokenType::Keyword(Keywords::Fn)
+            if peek_at(tokens, 1) == TokenType::Identifier
+                && peek_at(tokens, 2) == TokenType::Delimiter(Delimiters::ParOpen) =>
         {
-            fn_name.push(**t);
-            continue 'keep_matching;
+            match_function(tokens)
+        }
+        TokenType::Keyword(Keywords::Var) => match_binding(tokens, Mutability::Mutable),
+        TokenType::Keyword(Keywords::Const) => match_binding(tokens, Mutability::Const),
+        TokenType::Keyword(Keywords::Struct) => match_struct(tokens),
+        TokenType::Type(Types::BuiltIn(BuiltinType::Enum)) => match_enum(tokens),
+        TokenType::Keyword(Keywords::Interface) => match_interface(tokens),
+        TokenType::Keyword(Keywords::Test) => {
+            let span = span_of(tokens);
+            let ros = &tokens[1..];
+            let name = match (peek(ros), token_value(&ros[0])) {
+                (
+                    TokenType::Literal(Literals::BuiltIn(BuiltinType::String)),
+                    Ok(TokenValue::String(name)),
+                ) => name,
+                _ => return Err(unexpected(ros, "the test's name")),
+            };
+            let (body, ros) = match_block(&ros[1..])?;
+            Ok((
+                Expression::Test {
+                    name,
+                    body: Box::new(body),
+                    span,
+                },
+                ros,
+            ))
+        }
+        _ => {
+            if let Some(definition) = try_match_typed_definition(tokens) {
+                return definition;
+            }
+            let (e, ros) = match_expression(tokens)?;
+            if !is_operator(ros, Operators::Equal) {
+                return Ok((e, ros));
+            }
+            let (value, ros) = match_expression(&ros[1..])?;
+            let span = e.span();
+            match e {
+                Expression::Identifier(identifier) => Ok((
+                    Expression::Definition {
+                        mutability: Mutability::Immutable,
+                        definition_type: None,
+                        identifier,
+                        value: Box::new(value),
+                        span,
+                    },
+                    ros,
+                )),
+                Expression::FieldAccess { .. } | Expression::Index { .. } => Ok((
+                    Expression::Assignment {
+                        target: Box::new(e),
+                        value: Box::new(value),
+                        span,
+                    },
+                    ros,
+                )),
+                _ => Err(Diagnostic::error("invalid assignment target", span)),
+            }
         }
-        break;
     }
-    if matches!(
-        fn_name
-            .last()
-            .unwrap_or(&Token {
-                string: "",
-                token_type: TokenType::EoF
+}
+
+// Binding power of the binary operators, higher binds tighter
+fn binary_precedence(operator: Operators) -> Option<u8> {
+    match operator {
+        Operators::Or => Some(1),
+        Operators::And => Some(2),
+        Operators::BEq | Operators::BNEq => Some(3),
+        Operators::LessThan | Operators::GreaterThan | Operators::LEq | Operators::GEq => Some(4),
+        Operators::Pipe => Some(5),
+        Operators::DoubleDot => Some(6),
+        Operators::Concat | Operators::EnumConcat => Some(7),
+        Operators::Lshift | Operators::Rshift => Some(8),
+        Operators::Add | Operators::Subtract => Some(9),
+        Operators::Mult | Operators::Div | Operators::Modulo => Some(10),
+        Operators::Exp => Some(11),
+        _ => None,
+    }
+}
+
+pub fn match_expression(tokens: Tokens) -> Parsed<Expression> {
+    match_binary(tokens, 0)
+}
+
+fn match_binary(tokens: Tokens, min_precedence: u8) -> Parsed<Expression> {
+    let (mut left, mut tokens) = match_unary(tokens)?;
+    loop {
+        // a pipeline may continue on the next line
+        let next = skip_newlines(tokens);
+        if is_operator(next, Operators::Pipe) {
+            tokens = next;
+        }
+        let (operator, span) = match tokens.first() {
+            Some(Token {
+                token_type: TokenType::Operator(op),
+                span,
+                ..
+            }) => (*op, *span),
+            _ => break,
+        };
+        let precedence = match binary_precedence(operator) {
+            Some(p) if p >= min_precedence.max(1) => p,
+            _ => break,
+        };
+        // ^ is right associative
+        let next_precedence = if operator == Operators::Exp {
+            precedence
+        } else {
+            precedence + 1
+        };
+        let (right, ros) = match_binary(&tokens[1..], next_precedence)?;
+        tokens = ros;
+        left = match operator {
+            Operators::DoubleDot => {
+                let mut step = None;
+                if is_operator(tokens, Operators::DoubleDot) {
+                    let (s, ros) = match_binary(&tokens[1..], next_precedence)?;
+                    step = Some(Box::new(s));
+                    tokens = ros;
+                }
+                Expression::Range {
+                    start: Box::new(left),
+                    end: Box::new(right),
+                    step,
+                    span,
+                }
+            }
+            Operators::Pipe => pipe_into(left, right)?,
+            _ => Expression::Calculation {
+                left: Box::new(left),
+                operator,
+                right: Box::new(right),
+                span,
+            },
+        };
+    }
+    Ok((left, tokens))
+}
+
+// `a |> f(b)` is `f(a, b)`, and `a |> f` calls the function value f with a
+fn pipe_into(left: Expression, right: Expression) -> Result<Expression, Diagnostic> {
+    match right {
+        Expression::FunctionCall {
+            function_name,
+            type_args,
+            mut parameters,
+            span,
+        } => {
+            parameters.insert(0, left);
+            Ok(Expression::FunctionCall {
+                function_name,
+                type_args,
+                parameters,
+                span,
             })
-            .token_type,
-        TokenType::Identifier
-    ) {
-        return Ok(FunctionName { idents: fn_name });
+        }
+        Expression::Identifier(ident) => Ok(Expression::FunctionCall {
+            span: ident.span,
+            function_name: FunctionName {
+                idents: vec![ident],
+            },
+            type_args: vec![],
+            parameters: vec![left],
+        }),
+        other => Err(Diagnostic::error(
+            "the right side of `|>` must be a function call",
+            other.span(),
+        )),
     }
-    println!("fn name: {:?}", fn_name);
+}
 
-    Err("")
+fn match_unary(tokens: Tokens) -> Parsed<Expression> {
+    match peek(tokens) {
+        TokenType::Operator(operator @ (Operators::Subtract | Operators::Not)) => {
+            let (operand, ros) = match_unary(&tokens[1..])?;
+            Ok((
+                Expression::Unary {
+                    operator,
+                    operand: Box::new(operand),
+                    span: span_of(tokens),
+                },
+                ros,
+            ))
+        }
+        _ => match_postfix(tokens),
+    }
 }
 
-fn match_parameter_list<'a>(tokens: Vec<&'a Token<'a>>) -> Result<ParameterList<'a>, &'a str> {
-    if matches!(
-        tokens.first().unwrap().token_type,
-        TokenType::Delimiter(Delimiters::ParOpen)
-    ) {
-        let mut params: Vec<Token> = tokens
-            .clone()
-            .into_iter()
-            .take_while(|t| !matches!(t.token_type, TokenType::Delimiter(Delimiters::ParClose)))
-            .collect();
-        let t = tokens.get(params.len()).unwrap();
-        if !(params.is_empty()) {
-            if matches!(t.token_type, TokenType::Delimiter(Delimiters::ParClose)) {
-                params.push(**t)
+// The dotted name an expression spells, if it is one ie. `IO.print`
+fn as_function_name(expression: &Expression) -> Option<FunctionName> {
+    match expression {
+        Expression::Identifier(ident) => Some(FunctionName {
+            idents: vec![ident.clone()],
+        }),
+        Expression::FieldAccess { object, field, .. } => {
+            let mut name = as_function_name(object)?;
+            name.idents.push(field.clone());
+            Some(name)
+        }
+        _ => None,
+    }
+}
+
+fn match_call_args(tokens: Tokens) -> Parsed<Vec<Expression>> {
+    let (_, ros) = expect(tokens, TokenType::Delimiter(Delimiters::ParOpen), "`(`")?;
+    match_list_of(ros, Delimiters::ParClose, match_expression)
+}
+
+// Name { (newlines) field :
+fn is_struct_literal_start(tokens: Tokens) -> bool {
+    if !is_delimiter(tokens, Delimiters::BraceOpen) {
+        return false;
+    }
+    let ros = skip_newlines(&tokens[1..]);
+    peek(ros) == TokenType::Identifier && peek_at(ros, 1) == TokenType::Delimiter(Delimiters::Colon)
+}
+
+fn match_postfix(tokens: Tokens) -> Parsed<Expression> {
+    let (mut expression, mut tokens) = match_primary(tokens)?;
+    loop {
+        match peek(tokens) {
+            TokenType::Delimiter(Delimiters::Period) => {
+                let (field, ros) = match_name(&tokens[1..])?;
+                expression = Expression::FieldAccess {
+                    span: expression.span(),
+                    object: Box::new(expression),
+                    field,
+                };
+                tokens = ros;
+            }
+            TokenType::Delimiter(Delimiters::ParOpen) => {
+                let Some(function_name) = as_function_name(&expression) else {
+                    return Err(Diagnostic::error(
+                        "only named functions can be called",
+                        expression.span(),
+                    ));
+                };
+                let (parameters, ros) = match_call_args(tokens)?;
+                expression = Expression::FunctionCall {
+                    span: expression.span(),
+                    function_name,
+                    type_args: vec![],
+                    parameters,
+                };
+                tokens = ros;
             }
-            return Ok(ParameterList { params });
+            TokenType::Delimiter(Delimiters::BracketOpen) => {
+                // explicit type arguments `name[int, str](..)`, otherwise an index `xs[0]`
+                if let Some(function_name) = as_function_name(&expression) {
+                    if let Ok((type_args, ros)) =
+                        match_list_of(&tokens[1..], Delimiters::BracketClose, match_type)
+                    {
+                        if is_delimiter(ros, Delimiters::ParOpen) {
+                            let (parameters, ros) = match_call_args(ros)?;
+                            expression = Expression::FunctionCall {
+                                span: expression.span(),
+                                function_name,
+                                type_args,
+                                parameters,
+                            };
+                            tokens = ros;
+                            continue;
+                        }
+                        if let Expression::Identifier(name) = &expression {
+                            if is_struct_literal_start(ros) {
+                                let (fields, ros) = match_struct_fields(ros)?;
+                                expression = Expression::StructLiteral {
+                                    span: name.span,
+                                    name: name.clone(),
+                                    type_args,
+                                    fields,
+                                };
+                                tokens = ros;
+                                continue;
+                            }
+                        }
+                    }
+                }
+                let (index, ros) = match_expression(skip_newlines(&tokens[1..]))?;
+                let (_, ros) = expect(
+                    skip_newlines(ros),
+                    TokenType::Delimiter(Delimiters::BracketClose),
+                    "`]`",
+                )?;
+                expression = Expression::Index {
+                    span: expression.span(),
+                    object: Box::new(expression),
+                    index: Box::new(index),
+                };
+                tokens = ros;
+            }
+            TokenType::Operator(Operators::Try) => {
+                expression = Expression::Try {
+                    span: expression.span(),
+                    value: Box::new(expression),
+                };
+                tokens = &tokens[1..];
+            }
+            TokenType::Delimiter(Delimiters::BraceOpen) if is_struct_literal_start(tokens) => {
+                let Expression::Identifier(name) = &expression else {
+                    break;
+                };
+                let (fields, ros) = match_struct_fields(tokens)?;
+                expression = Expression::StructLiteral {
+                    span: name.span,
+                    name: name.clone(),
+                    type_args: vec![],
+                    fields,
+                };
+                tokens = ros;
+            }
+            _ => break,
         }
-        // if params.collect()
     }
-    Err("Couldn't match param list")
+    Ok((expression, tokens))
+}
+
+// { <ident>: <expression>, ... }
+fn match_struct_fields(tokens: Tokens) -> Parsed<Vec<(Ident, Expression)>> {
+    match_list_of(&tokens[1..], Delimiters::BraceClose, |tokens| {
+        let (name, ros) = match_identifier(tokens)?;
+        let (_, ros) = expect(ros, TokenType::Delimiter(Delimiters::Colon), "`:`")?;
+        let (value, ros) = match_expression(skip_newlines(ros))?;
+        Ok(((name, value), ros))
+    })
 }
 
-fn match_function_call<'a>(tokens: Vec<&'a Token<'a>>) -> Result<Expression<'a>, &'a str> {
-    let iter = tokens.clone().into_iter();
-    let fn_name: FunctionName<'_>;
-    match match_function_name(tokens.clone()) {
-        Ok(e) => fn_name = e,
-        Err(_) => {
-            return Err("Could not match function name");
+fn match_primary(tokens: Tokens) -> Parsed<Expression> {
+    let span = span_of(tokens);
+    match peek(tokens) {
+        TokenType::Literal(_) => match token_value(&tokens[0]) {
+            Ok(value) => Ok((Expression::Literal { value, span }, &tokens[1..])),
+            Err(e) => Err(Diagnostic::error(e, span)),
+        },
+        TokenType::Identifier => Ok((
+            Expression::Identifier(Ident::new(tokens[0].string, span)),
+            &tokens[1..],
+        )),
+        // type names can be called as functions, ie. `float(i)` or `map[int, str](xs, f)`
+        TokenType::Type(_)
+            if matches!(
+                peek_at(tokens, 1),
+                TokenType::Delimiter(Delimiters::ParOpen)
+                    | TokenType::Delimiter(Delimiters::BracketOpen)
+                    | TokenType::Delimiter(Delimiters::Period)
+            ) =>
+        {
+            Ok((
+                Expression::Identifier(Ident::new(tokens[0].string, span)),
+                &tokens[1..],
+            ))
         }
+        TokenType::Delimiter(Delimiters::ParOpen) => {
+            let (e, ros) = match_expression(skip_newlines(&tokens[1..]))?;
+            let (_, ros) = expect(
+                skip_newlines(ros),
+                TokenType::Delimiter(Delimiters::ParClose),
+                "`)`",
+            )?;
+            Ok((e, ros))
+        }
+        TokenType::Delimiter(Delimiters::BracketOpen) => {
+            let (elements, ros) =
+                match_list_of(&tokens[1..], Delimiters::BracketClose, match_expression)?;
+            Ok((Expression::List { elements, span }, ros))
+        }
+        TokenType::Delimiter(Delimiters::BraceOpen) => match_tuple_or_map(tokens),
+        TokenType::Keyword(Keywords::Fn) => match_lambda(tokens),
+        TokenType::Keyword(Keywords::If) => match_if(tokens),
+        TokenType::Keyword(Keywords::While) => {
+            let (condition, ros) = match_expression(&tokens[1..])?;
+            let (body, ros) = match_block(ros)?;
+            Ok((
+                Expression::While {
+                    condition: Box::new(condition),
+                    body: Box::new(body),
+                    span,
+                },
+                ros,
+            ))
+        }
+        TokenType::Keyword(Keywords::For) => {
+            let (variable, ros) = match_identifier(&tokens[1..])?;
+            let (_, mut ros) = expect(ros, TokenType::Keyword(Keywords::In), "`in`")?;
+            // `for i in range 0..10` reads nicely, the `range` is optional
+            if peek(ros) == TokenType::Type(Types::BuiltIn(BuiltinType::Range)) {
+                ros = &ros[1..];
+            }
+            let (iterable, ros) = match_expression(ros)?;
+            let (body, ros) = match_block(ros)?;
+            Ok((
+                Expression::For {
+                    variable,
+                    iterable: Box::new(iterable),
+                    body: Box::new(body),
+                    span,
+                },
+                ros,
+            ))
+        }
+        TokenType::Keyword(Keywords::Match) => match_match(tokens),
+        TokenType::Keyword(Keywords::Cond) => {
+            let (_, ros) = expect(
+                &tokens[1..],
+                TokenType::Delimiter(Delimiters::BraceOpen),
+                "`{`",
+            )?;
+            let (arms, ros) = match_arms(ros, |tokens| {
+                let (condition, ros) = match_expression(tokens)?;
+                let (_, ros) = expect(ros, TokenType::Operator(Operators::Into), "`->`")?;
+                let (body, ros) = match_arm_body(ros)?;
+                Ok(((condition, body), ros))
+            })?;
+            Ok((Expression::Cond { arms, span }, ros))
+        }
+        TokenType::Keyword(Keywords::Return) => {
+            let ros = &tokens[1..];
+            if matches!(
+                peek(ros),
+                TokenType::NewLine
+                    | TokenType::EoF
+                    | TokenType::Delimiter(Delimiters::BraceClose)
+                    | TokenType::Delimiter(Delimiters::Semicolon)
+            ) {
+                return Ok((Expression::Return { value: None, span }, ros));
+            }
+            let (value, ros) = match_expression(ros)?;
+            Ok((
+                Expression::Return {
+                    value: Some(Box::new(value)),
+                    span,
+                },
+                ros,
+            ))
+        }
+        TokenType::Keyword(Keywords::Panic) => {
+            let (message, ros) = match_expression(&tokens[1..])?;
+            Ok((
+                Expression::Panic {
+                    message: Box::new(message),
+                    span,
+                },
+                ros,
+            ))
+        }
+        TokenType::Keyword(Keywords::Assert) => {
+            let (condition, mut ros) = match_expression(&tokens[1..])?;
+            let mut message = None;
+            if is_delimiter(ros, Delimiters::Comma) {
+                let (m, r) = match_expression(&ros[1..])?;
+                message = Some(Box::new(m));
+                ros = r;
+            }
+            Ok((
+                Expression::Assert {
+                    condition: Box::new(condition),
+                    message,
+                    span,
+                },
+                ros,
+            ))
+        }
+        TokenType::Keyword(Keywords::Break) => Ok((Expression::Break { span }, &tokens[1..])),
+        TokenType::Keyword(Keywords::Continue) => Ok((Expression::Continue { span }, &tokens[1..])),
+        _ => Err(unexpected(tokens, "an expression")),
+    }
+}
+
+// {} is an empty map, {a, b} a tuple and {a: b} a map
+fn match_tuple_or_map(tokens: Tokens) -> Parsed<Expression> {
+    let span = span_of(tokens);
+    let ros = skip_newlines(&tokens[1..]);
+    if is_delimiter(ros, Delimiters::BraceClose) {
+        return Ok((
+            Expression::Map {
+                entries: vec![],
+                span,
+            },
+            &ros[1..],
+        ));
+    }
+    let (first, after_first) = match_expression(ros)?;
+    if is_delimiter(after_first, Delimiters::Colon) {
+        let (entries, ros) = match_list_of(&tokens[1..], Delimiters::BraceClose, |tokens| {
+            let (key, ros) = match_expression(tokens)?;
+            let (_, ros) = expect(ros, TokenType::Delimiter(Delimiters::Colon), "`:`")?;
+            let (value, ros) = match_expression(skip_newlines(ros))?;
+            Ok(((key, value), ros))
+        })?;
+        return Ok((Expression::Map { entries, span }, ros));
+    }
+    let (elements, ros) = match_list_of(&tokens[1..], Delimiters::BraceClose, match_expression)?;
+    Ok((Expression::Tuple { elements, span }, ros))
+}
+
+// fn x, y -> <expression> || fn (int x) -> { <block> }
+fn match_lambda(tokens: Tokens) -> Parsed<Expression> {
+    let span = span_of(tokens);
+    let mut ros = &tokens[1..];
+    let params;
+    if is_delimiter(ros, Delimiters::ParOpen) {
+        let (p, r) = match_list_of(&ros[1..], Delimiters::ParClose, match_lambda_param)?;
+        params = p;
+        ros = r;
+    } else {
+        let mut p = vec![];
+        while !is_operator(ros, Operators::Into) {
+            let (param, r) = match_lambda_param(ros)?;
+            p.push(param);
+            ros = r;
+            if is_delimiter(ros, Delimiters::Comma) {
+                ros = &ros[1..];
+            } else if !is_operator(ros, Operators::Into) {
+                return Err(unexpected(ros, "`,` or `->`"));
+            }
+        }
+        params = p;
+    }
+    let (_, ros) = expect(ros, TokenType::Operator(Operators::Into), "`->`")?;
+    let (body, ros) = if is_delimiter(ros, Delimiters::BraceOpen) {
+        match_block(ros)?
+    } else {
+        match_expression(ros)?
     };
-    println!("{:?}", fn_name);
-    let params: ParameterList<'_>;
-    let toks: Vec<&Token> = iter.skip(fn_name.idents.len()).collect();
-    match match_parameter_list(toks) {
-        Ok(e) => params = e,
-        Err(_) => {
-            return Err("Could not match function name");
-        }
-    }
-    println!("params {:?}", params);
-    let e = Expression::FunctionCall {
-        function_name: fn_name,
-        parameters: params,
-    };
-    return Ok(e);
-    // need to match against a parameter list now.
+    Ok((
+        Expression::Lambda {
+            params,
+            body: Box::new(body),
+            span,
+        },
+        ros,
+    ))
+}
+
+// if <cond> {} elif <cond> {} else {}
+fn match_if(tokens: Tokens) -> Parsed<Expression> {
+    let span = span_of(tokens);
+    let (condition, ros) = match_expression(&tokens[1..])?;
+    let (body, mut ros) = match_block(ros)?;
+    let mut branches = vec![(condition, body)];
+    let mut else_branch = None;
+    loop {
+        let next = skip_newlines(ros);
+        if is_keyword(next, Keywords::Elif) {
+            let (condition, r) = match_expression(&next[1..])?;
+            let (body, r) = match_block(r)?;
+            branches.push((condition, body));
+            ros = r;
+        } else if is_keyword(next, Keywords::Else) {
+            let (body, r) = if is_keyword(&next[1..], Keywords::If) {
+                match_if(&next[1..])?
+            } else {
+                match_block(&next[1..])?
+            };
+            else_branch = Some(Box::new(body));
+            ros = r;
+            break;
+        } else {
+            break;
+        }
+    }
+    Ok((
+        Expression::If {
+            branches,
+            else_branch,
+            span,
+        },
+        ros,
+    ))
 }
 
-pub fn match_expression<'a>(tokens: Vec<&'a Token<'a>>) -> Result<Expression<'a>, &'a str> {
-    match match_empty(tokens.clone()) {
-        Ok(e) => {
-            return Ok(e);
+// Arms of a match or cond, one per line up to the closing `}`
+fn match_arms<'t, T>(
+    mut tokens: Tokens<'t>,
+    arm: impl Fn(Tokens<'t>) -> Parsed<'t, T>,
+) -> Parsed<'t, Vec<T>> {
+    let mut arms = vec![];
+    loop {
+        tokens = skip_separators(tokens);
+        if is_delimiter(tokens, Delimiters::BraceClose) {
+            return Ok((arms, &tokens[1..]));
         }
-        Err(_) => {}
+        let (a, ros) = arm(tokens)?;
+        arms.push(a);
+        tokens = expect_statement_end(ros)?;
+    }
+}
+
+fn match_arm_body(tokens: Tokens) -> Parsed<Expression> {
+    if is_delimiter(tokens, Delimiters::BraceOpen) && !is_struct_literal_start(tokens) {
+        return match_block(tokens);
     }
-    match match_definition(tokens.clone()) {
-        Ok(e) => {
-            return Ok(e);
+    match_statement(tokens)
+}
+
+// match <expression> { <pattern> [when <guard>] -> <expression> ... }
+fn match_match(tokens: Tokens) -> Parsed<Expression> {
+    let span = span_of(tokens);
+    let (subject, ros) = match_expression(&tokens[1..])?;
+    let (_, ros) = expect(ros, TokenType::Delimiter(Delimiters::BraceOpen), "`{`")?;
+    let (arms, ros) = match_arms(ros, |tokens| {
+        let (pattern, mut ros) = match_pattern(tokens)?;
+        let mut guard = None;
+        if is_keyword(ros, Keywords::When) {
+            let (g, r) = match_expression(&ros[1..])?;
+            guard = Some(g);
+            ros = r;
+        }
+        let (_, ros) = expect(ros, TokenType::Operator(Operators::Into), "`->`")?;
+        let (body, ros) = match_arm_body(ros)?;
+        Ok((
+            MatchArm {
+                pattern,
+                guard,
+                body,
+            },
+            ros,
+        ))
+    })?;
+    Ok((
+        Expression::Match {
+            subject: Box::new(subject),
+            arms,
+            span,
+        },
+        ros,
+    ))
+}
+
+pub fn match_pattern(tokens: Tokens) -> Parsed<Pattern> {
+    let span = span_of(tokens);
+    match peek(tokens) {
+        TokenType::Literal(_) => match token_value(&tokens[0]) {
+            Ok(value) => Ok((Pattern::Literal(value, span), &tokens[1..])),
+            Err(e) => Err(Diagnostic::error(e, span)),
+        },
+        TokenType::Identifier if tokens[0].string == "_" => {
+            Ok((Pattern::Wildcard(span), &tokens[1..]))
+        }
+        TokenType::Identifier => {
+            let (first, mut ros) = match_identifier(tokens)?;
+            let mut idents = vec![first];
+            while is_delimiter(ros, Delimiters::Period) {
+                let (ident, r) = match_name(&ros[1..])?;
+                idents.push(ident);
+                ros = r;
+            }
+            if is_delimiter(ros, Delimiters::ParOpen) {
+                let (fields, ros) = match_list_of(&ros[1..], Delimiters::ParClose, match_pattern)?;
+                return Ok((Pattern::Variant(FunctionName { idents }, fields, span), ros));
+            }
+            if idents.len() > 1 {
+                return Ok((Pattern::Variant(FunctionName { idents }, vec![], span), ros));
+            }
+            Ok((Pattern::Binding(idents.remove(0)), ros))
+        }
+        TokenType::Delimiter(Delimiters::BraceOpen) => {
+            let (elements, ros) =
+                match_list_of(&tokens[1..], Delimiters::BraceClose, match_pattern)?;
+            Ok((Pattern::Tuple(elements, span), ros))
         }
-        Err(_) => {}
+        TokenType::Delimiter(Delimiters::BracketOpen) => {
+            let (elements, ros) =
+                match_list_of(&tokens[1..], Delimiters::BracketClose, match_pattern)?;
+            Ok((Pattern::List(elements, span), ros))
+        }
+        _ => Err(unexpected(tokens, "a pattern")),
     }
+}
 
-    match match_function_call(tokens.clone()) {
-        Ok(e) => {
-            return Ok(e);
+// `x-1` lexes as `x` `-1`, split the literal back into an operator when it follows an operand
+fn split_negative_literals<'a>(tokens: &[Token<'a>]) -> Vec<Token<'a>> {
+    let mut out: Vec<Token<'a>> = vec![];
+    for t in tokens.iter() {
+        let follows_operand = matches!(
+            out.last().map(|l| l.token_type),
+            Some(TokenType::Identifier)
+                | Some(TokenType::Literal(_))
+                | Some(TokenType::Delimiter(Delimiters::ParClose))
+                | Some(TokenType::Delimiter(Delimiters::BracketClose))
+        );
+        let is_numeric = matches!(
+            t.token_type,
+            TokenType::Literal(Literals::Primitive(PrimitiveType::Int))
+                | TokenType::Literal(Literals::Primitive(PrimitiveType::Float))
+        );
+        if follows_operand && is_numeric && t.string.starts_with('-') {
+            out.push(Token {
+                string: &t.string[..1],
+                token_type: TokenType::Operator(Operators::Subtract),
+                span: Span { len: 1, ..t.span },
+            });
+            out.push(Token {
+                string: &t.string[1..],
+                token_type: t.token_type,
+                span: Span {
+                    col: t.span.col + 1,
+                    len: t.span.len - 1,
+                    ..t.span
+                },
+            });
+            continue;
         }
-        Err(_) => {}
+        out.push(*t);
     }
-    // match def
+    out
+}
 
-    Err("Not implemented")
+/* An expression goes on over the lines inside an open `(` or `[`, ie. `x = (1 +` and then `2)`, so
+ * their newlines are dropped. A `{` inside them has statements, whose newlines separate them.
+ */
+fn join_bracketed_lines<'a>(tokens: &[Token<'a>]) -> Vec<Token<'a>> {
+    let mut open = vec![];
+    let mut out = vec![];
+    for t in tokens.iter() {
+        match t.token_type {
+            TokenType::Delimiter(
+                d @ (Delimiters::ParOpen | Delimiters::BracketOpen | Delimiters::BraceOpen),
+            ) => open.push(d),
+            TokenType::Delimiter(
+                Delimiters::ParClose | Delimiters::BracketClose | Delimiters::BraceClose,
+            ) => {
+                open.pop();
+            }
+            TokenType::NewLine
+                if matches!(
+                    open.last(),
+                    Some(Delimiters::ParOpen | Delimiters::BracketOpen)
+                ) =>
+            {
+                continue
+            }
+            _ => {}
+        }
+        out.push(*t);
+    }
+    out
 }
 
-pub fn expressionize<'a>(mut tokens: Vec<&'a Token<'a>>) -> Vec<Expression<'a>> {
+// Parses a whole file's tokens into its top level expressions
+pub fn expressionize(tokens: &[Token]) -> Result<Vec<Expression>, Diagnostic> {
+    let mut toks: Vec<Token> = split_negative_literals(&join_bracketed_lines(
+        &tokens
+            .iter()
+            .filter(|t| t.token_type != TokenType::Comment)
+            .cloned()
+            .collect::<Vec<Token>>(),
+    ));
+    let end = toks.last().map(|t| t.span).unwrap_or_default();
+    toks.push(Token {
+        string: "",
+        token_type: TokenType::EoF,
+        span: Span {
+            col: end.col + end.len,
+            len: 0,
+            ..end
+        },
+    });
+
     let mut v: Vec<Expression> = vec![];
+    let mut tokens: Tokens = &toks;
+    'keep_matching: loop {
+        tokens = skip_separators(tokens);
+        if peek(tokens) == TokenType::EoF {
+            break 'keep_matching;
+        }
+        let (e, ros) = match_statement(tokens)?;
+        v.push(e);
+        tokens = expect_statement_end(ros)?;
+        if is_delimiter(tokens, Delimiters::BraceClose) {
+            return Err(Diagnostic::error("unexpected `}`", span_of(tokens)));
+        }
+    }
 
-    'keep_matching: while !tokens.is_empty() {
-        match match_expression(tokens.clone()) {
-            Ok(e) => {
-                let l = e.len();
-                v.push(e.to_owned());
-                tokens = tokens.drain(l..).collect();
-                // v.iter().skip(e.len());
-                continue 'keep_matching;
-            }
-            Err(e) => {}
+    Ok(v)
+}
+
+// Tokenizes and parses a source string
+pub fn parse(source: &str) -> Result<Vec<Expression>, Diagnostic> {
+    let tokens = tokenize(source)?;
+    expressionize(&tokens)
+}
+
+#[test]
+fn test_parse_definitions() {
+    let expressions = parse("str s = \"rho\"\nint i = 1 + 2 * 3\nl = fn x -> x - 1\n").unwrap();
+    assert_eq!(expressions.len(), 3);
+    match &expressions[1] {
+        Expression::Definition {
+            definition_type: Some(t),
+            identifier,
+            value,
+            ..
+        } => {
+            assert_eq!(*t, Type::int());
+            assert_eq!(identifier.name, "i");
+            assert!(matches!(
+                **value,
+                Expression::Calculation {
+                    operator: Operators::Add,
+                    ..
+                }
+            ));
+        }
+        e => panic!("expected a definition, got {:?}", e),
+    }
+    assert!(matches!(
+        &expressions[2],
+        Expression::Definition {
+            definition_type: None,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn test_parse_bindings() {
+    let expressions = parse("var int i = 1\nconst N = 2 * 8\nvar xs = []\n").unwrap();
+    let mutability: Vec<(Mutability, bool)> = expressions
+        .iter()
+        .map(|e| match e {
+            Expression::Definition {
+                mutability,
+                definition_type,
+                ..
+            } => (*mutability, definition_type.is_some()),
+            e => panic!("expected a definition, got {:?}", e),
+        })
+        .collect();
+    assert_eq!(
+        mutability,
+        vec![
+            (Mutability::Mutable, true),
+            (Mutability::Const, false),
+            (Mutability::Mutable, false)
+        ]
+    );
+    assert!(parse("var 1 = 2").is_err());
+}
+
+#[test]
+fn test_parse_pipe() {
+    let expressions = parse("s |> IO.print()").unwrap();
+    match &expressions[0] {
+        Expression::FunctionCall {
+            function_name,
+            parameters,
+            ..
+        } => {
+            assert_eq!(function_name.path(), "IO.print");
+            assert_eq!(parameters.len(), 1);
         }
-        println!("end_tokens: {:?}", tokens);
-        break;
+        e => panic!("expected a call, got {:?}", e),
     }
+}
+
+#[test]
+fn test_parse_generic_function() {
+    let expressions =
+        parse("func map[T, U](list[T] xs, fn(T) -> U f) -> list[U] {\n  return []\n}").unwrap();
+    let Expression::Function(decl) = &expressions[0] else {
+        panic!("expected a function");
+    };
+    assert_eq!(decl.name.path(), "map");
+    assert_eq!(decl.type_params.len(), 2);
+    assert_eq!(
+        decl.params[1].param_type,
+        Some(Type::function(
+            vec![Type::Param("T".to_string())],
+            Type::Param("U".to_string())
+        ))
+    );
+    assert_eq!(
+        decl.return_type,
+        Some(Type::list(Type::Param("U".to_string())))
+    );
+}
+
+#[test]
+fn test_parse_error_span() {
+    let err = parse("int i = 1\nint j = )").unwrap_err();
+    assert_eq!(
+        err.span,
+        Span {
+            line: 2,
+            col: 9,
+            len: 1
+        }
+    );
+}
+
+#[test]
+fn test_parse_bracketed_lines() {
+    let expressions =
+        parse("x = (1 +\n    2)\nxs = [1,\n 2\n]\nf(x,\n  fn y -> {\n    y\n    x\n})\n");
+    let expressions = expressions.unwrap();
+    assert_eq!(expressions.len(), 3);
+    assert!(matches!(
+        &expressions[0],
+        Expression::Definition { value, .. } if matches!(**value, Expression::Calculation { .. })
+    ));
+    // the lambda's block still has two statements
+    let Expression::FunctionCall { parameters, .. } = &expressions[2] else {
+        panic!("expected a call, found {:?}", expressions[2]);
+    };
+    let Expression::Lambda { body, .. } = &parameters[1] else {
+        panic!("expected a lambda, found {:?}", parameters[1]);
+    };
+    assert!(matches!(&**body, Expression::Block { expressions, .. } if expressions.len() == 2));
+    assert!(parse("x = (1 +\n").is_err());
+}
 
-    // v.reverse();
-    v
+#[test]
+fn test_parse_testfiles() {
+    let files = [
+        "hello.rho",
+        "scratch.rho",
+        "hello_var.rho",
+        "scratch_typed.rho",
+    ];
+    for file_path in files.map(|f| format!("./rho_testfiles/{}", f)) {
+        let contents = std::fs::read_to_string(&file_path).unwrap();
+        if let Err(e) = parse(&contents) {
+            panic!("{}", e.render(&file_path, &contents));
+        }
+    }
 }
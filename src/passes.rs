@@ -0,0 +1,1354 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use crate::mir::*;
+use crate::rho_core::float_to_int;
+use crate::types::Type;
+
+/* The optimisations over the MIR, which every backend gets before it compiles a program. Each
+ * pass can be turned off on its own, `rho build --passes=fold,dce` only runs those two:
+ *
+ *     inline  calls to small functions that call nothing themselves are replaced by their body
+ *     fold    constants are propagated through the blocks and what's computed from them folded,
+ *             ie. `x = 1 + 2` stores 3 and a `branch` on a constant is a `goto`
+ *     copies  a temporary that copies a place is replaced by the place, one that's only moved
+ *             somewhere is assigned there to begin with
+ *     dce     unused assignments, unreachable blocks, empty ones that only `goto` and functions
+ *             nothing calls are removed, and a block only reached from one `goto` is merged
+ *
+ * What one pass leaves is what the next one works on, an inlined body has constant arguments to
+ * fold and folding leaves dead code, so they run in that order until a round changes nothing.
+ * Nothing that can fail at runtime is folded away, `1 / 0` still fails where it did.
+ */
+pub fn optimize(program: &mut Program, passes: &[Pass]) {
+    for _ in 0..ROUNDS {
+        let before = program.to_string();
+        for pass in Pass::ALL {
+            if !passes.contains(&pass) {
+                continue;
+            }
+            match pass {
+                Pass::Inline => inline(program),
+                Pass::Fold => functions(program).for_each(fold),
+                Pass::Copies => functions(program).for_each(propagate_copies),
+                Pass::DeadCode => remove_dead_code(program),
+            }
+        }
+        if program.to_string() == before {
+            break;
+        }
+    }
+}
+
+// Enough for the passes to settle on the programs seen so far, the last rounds seldom do anything
+const ROUNDS: usize = 8;
+
+// The most statements a function can have to be inlined
+const INLINE_LIMIT: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+    Inline,
+    Fold,
+    Copies,
+    DeadCode,
+}
+
+impl Pass {
+    // In the order they run
+    pub const ALL: [Pass; 4] = [Pass::Inline, Pass::Fold, Pass::Copies, Pass::DeadCode];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::Inline => "inline",
+            Pass::Fold => "fold",
+            Pass::Copies => "copies",
+            Pass::DeadCode => "dce",
+        }
+    }
+
+    // A comma separated list of names, or `none` or `all`
+    pub fn parse_list(s: &str) -> Option<Vec<Pass>> {
+        match s {
+            "none" => return Some(vec![]),
+            "all" => return Some(Pass::ALL.to_vec()),
+            _ => {}
+        }
+        s.split(',')
+            .map(|name| Pass::ALL.into_iter().find(|p| p.name() == name))
+            .collect()
+    }
+}
+
+fn functions(program: &mut Program) -> impl Iterator<Item = &mut Function> {
+    (program.functions.iter_mut())
+        .chain(program.closures.iter_mut())
+        .chain(std::iter::once(&mut program.entry))
+}
+
+// The blocks reachable from `bb0`, in reverse postorder
+fn reachable(function: &Function) -> Vec<BlockId> {
+    let mut visited = vec![false; function.blocks.len()];
+    let mut order = vec![];
+    let mut stack = vec![(0, function.blocks[0].terminator.successors())];
+    visited[0] = true;
+    while let Some((block, pending)) = stack.last_mut() {
+        match pending.pop() {
+            Some(next) if !visited[next] => {
+                visited[next] = true;
+                stack.push((next, function.blocks[next].terminator.successors()));
+            }
+            Some(_) => {}
+            None => {
+                order.push(*block);
+                stack.pop();
+            }
+        }
+    }
+    order.reverse();
+    order
+}
+
+/* Inlining */
+
+/* The functions small enough to inline, none of them calls a function so inlining them ends. One
+ * that can fail keeps its frame where it's inlined, for the stack trace. A `for` loop or a closure
+ * belongs to the frame of the function it's in, so a function with one stays a call.
+ */
+fn inline(program: &mut Program) {
+    let globals = program.globals.clone();
+    let leaves: HashMap<String, (Function, bool)> = (program.functions.iter())
+        .filter(|f| {
+            let statements: Vec<&Statement> =
+                f.blocks.iter().flat_map(|b| b.statements.iter()).collect();
+            let needs_frame = statements.iter().any(|s| {
+                matches!(
+                    s,
+                    Statement::Call {
+                        callee: Callee::Function(_),
+                        ..
+                    } | Statement::Iterate { .. }
+                        | Statement::Assign {
+                            value: Rvalue::Closure(..),
+                            ..
+                        }
+                )
+            });
+            statements.len() <= INLINE_LIMIT && !needs_frame
+        })
+        .map(|f| (f.name.clone(), (f.clone(), can_fail(f, &globals))))
+        .collect();
+    for function in functions(program) {
+        while let Some((block, index, (callee, frame))) = next_call(function, &leaves) {
+            inline_call(function, block, index, callee, *frame);
+        }
+    }
+}
+
+fn can_fail(function: &Function, globals: &[Global]) -> bool {
+    function.blocks.iter().any(|b| {
+        let failing = (b.statements.iter()).any(|s| statement_fails(s, function, globals));
+        failing
+            || matches!(
+                b.terminator,
+                Terminator::Fail { .. } | Terminator::Next { .. }
+            )
+    })
+}
+
+// Whether a statement can fail, a call to a function or into its frame can too
+fn statement_fails(statement: &Statement, function: &Function, globals: &[Global]) -> bool {
+    match statement {
+        Statement::Assign { value, .. } => fails(value, function, globals),
+        Statement::Call { callee, .. } => match callee {
+            Callee::Function(_) | Callee::Enter(_) | Callee::Value | Callee::Method(_) => true,
+            // the VM's builtins are all the others, which can fail
+            Callee::Builtin(name) => !RUNTIME_BUILTINS.contains(&(name.as_str(), false)),
+            _ => false,
+        },
+        Statement::SetPath { .. } | Statement::Iterate { .. } => true,
+        Statement::Drop(_)
+        | Statement::Match { .. }
+        | Statement::Fresh(_)
+        | Statement::EndIterate => false,
+    }
+}
+
+fn next_call<'a>(
+    function: &Function,
+    leaves: &'a HashMap<String, (Function, bool)>,
+) -> Option<(BlockId, usize, &'a (Function, bool))> {
+    for (b, block) in function.blocks.iter().enumerate() {
+        for (i, statement) in block.statements.iter().enumerate() {
+            if let Statement::Call {
+                callee: Callee::Function(name),
+                args,
+                ..
+            } = statement
+            {
+                // a call with the wrong arguments fails as a call, on the VM
+                match leaves.get(name) {
+                    Some(callee) if callee.0.params.len() == args.len() => {
+                        return Some((b, i, callee))
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    None
+}
+
+/* Replaces the call at `index` in `block` with the callee's blocks. Its locals are added to the
+ * function's, the arguments are moved into its parameters like a call does, and its `return`
+ * moves `_0` into the call's result and goes to a block with the statements after the call. With
+ * `frame` the body is between an `enter` and a `leave` of the callee's frame.
+ */
+fn inline_call(
+    function: &mut Function,
+    block: BlockId,
+    index: usize,
+    callee: &Function,
+    frame: bool,
+) {
+    let locals = function.locals.len();
+    let first = function.blocks.len();
+    let after = first + callee.blocks.len();
+    let rest = function.blocks[block].statements.split_off(index + 1);
+    let Some(Statement::Call {
+        result, args, span, ..
+    }) = function.blocks[block].statements.pop()
+    else {
+        unreachable!("next_call finds calls")
+    };
+    let shift = |place: &mut Place| {
+        if let Place::Local(id) = place {
+            *id += locals;
+        }
+    };
+    // a unit `_0` has no value in compiled code, the VM's has
+    let returns = *callee.return_type() != Type::Unit
+        || (callee.blocks.iter().flat_map(|b| b.statements.iter()))
+            .any(|s| s.written() == Some(Place::Local(0)));
+    let frame_call = |callee: Callee| Statement::Call {
+        result: None,
+        callee,
+        args: vec![],
+        span,
+    };
+    if frame {
+        let enter = frame_call(Callee::Enter(callee.name.clone()));
+        function.blocks[block].statements.push(enter);
+    }
+    for (param, arg) in callee.params.iter().zip(args) {
+        function.blocks[block].statements.push(Statement::Assign {
+            place: Place::Local(param + locals),
+            value: Rvalue::Use(arg),
+            span,
+        });
+    }
+    let terminator = std::mem::replace(
+        &mut function.blocks[block].terminator,
+        Terminator::Goto(first),
+    );
+    function.locals.extend(callee.locals.iter().cloned());
+    for b in callee.blocks.iter() {
+        let mut b = b.clone();
+        for statement in b.statements.iter_mut() {
+            statement.places_mut().into_iter().for_each(shift);
+        }
+        b.terminator.places_mut().into_iter().for_each(shift);
+        for target in b.terminator.successors_mut() {
+            *target += first;
+        }
+        if b.terminator == Terminator::Return {
+            if frame {
+                b.statements.push(frame_call(Callee::Leave));
+            }
+            if let (Some(place), true) = (result, returns) {
+                b.statements.push(Statement::Assign {
+                    place,
+                    value: Rvalue::Use(Operand::Move(Place::Local(locals))),
+                    span,
+                });
+            }
+            b.terminator = Terminator::Goto(after);
+        }
+        function.blocks.push(b);
+    }
+    function.blocks.push(Block {
+        statements: rest,
+        terminator,
+    });
+}
+
+/* Constant folding and propagation */
+
+// The places known to hold a constant
+type Known = HashMap<Place, Constant>;
+
+// Floats are the same when their bits are, a NaN is still the NaN it was
+fn same(a: &Constant, b: &Constant) -> bool {
+    match (a, b) {
+        (Constant::Float(a), Constant::Float(b)) => a.to_bits() == b.to_bits(),
+        (a, b) => a == b,
+    }
+}
+
+fn same_known(a: &Known, b: &Known) -> bool {
+    a.len() == b.len() && a.iter().all(|(p, c)| b.get(p).is_some_and(|d| same(c, d)))
+}
+
+// What's known where two paths meet, the constants both of them agree on
+fn meet(a: &Known, b: &Known) -> Known {
+    (a.iter())
+        .filter(|(p, c)| b.get(p).is_some_and(|d| same(c, d)))
+        .map(|(p, c)| (*p, c.clone()))
+        .collect()
+}
+
+/* Finds what's known at the start of each block, going over the blocks until that stops
+ * changing, and then rewrites each block from there. Running rho code can change any global, so
+ * nothing is known about them after a call or the `next` of a stream, and a local a closure shares
+ * is never known.
+ */
+fn fold(function: &mut Function) {
+    let order = reachable(function);
+    let mut entries: Vec<Option<Known>> = vec![None; function.blocks.len()];
+    entries[0] = Some(Known::new());
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in order.iter() {
+            let Some(mut known) = entries[*b].clone() else {
+                continue;
+            };
+            let block = &function.blocks[*b];
+            for statement in block.statements.iter() {
+                if let Some(s) = fold_statement(statement, &known) {
+                    learn(&s, &mut known, function);
+                }
+            }
+            let terminator = fold_terminator(&block.terminator, &known);
+            if let Terminator::Next { place, .. } = terminator {
+                forget_shared(&mut known, function);
+                if let Some(place) = place {
+                    known.remove(&place);
+                }
+            }
+            for next in terminator.successors() {
+                let entry = match &entries[next] {
+                    Some(entry) => meet(entry, &known),
+                    None => known.clone(),
+                };
+                if !entries[next]
+                    .as_ref()
+                    .is_some_and(|e| same_known(e, &entry))
+                {
+                    entries[next] = Some(entry);
+                    changed = true;
+                }
+            }
+        }
+    }
+    for b in order {
+        let mut known = entries[b].clone().unwrap_or_default();
+        let mut statements = vec![];
+        for statement in function.blocks[b].statements.iter() {
+            if let Some(s) = fold_statement(statement, &known) {
+                learn(&s, &mut known, function);
+                statements.push(s);
+            }
+        }
+        let terminator = fold_terminator(&function.blocks[b].terminator, &known);
+        function.blocks[b].statements = statements;
+        function.blocks[b].terminator = terminator;
+    }
+}
+
+// Forgets the places rho code can change, after something that runs it
+fn forget_shared(known: &mut Known, function: &Function) {
+    known.retain(|place, _| !matches!(place, Place::Global(_)) && !function.captured(*place));
+}
+
+fn learn(statement: &Statement, known: &mut Known, function: &Function) {
+    match statement {
+        Statement::Assign {
+            place,
+            value: Rvalue::Use(Operand::Constant(c)),
+            ..
+        } if !function.captured(*place) => {
+            known.insert(*place, c.clone());
+        }
+        Statement::Assign { place, .. } => {
+            known.remove(place);
+        }
+        Statement::Call { result, callee, .. } => {
+            if callee.runs_code() {
+                forget_shared(known, function);
+            }
+            if let Some(place) = result {
+                known.remove(place);
+            }
+        }
+        // `fold_statement` removes the drops of constants, the others have no constant to forget
+        Statement::Drop(_) => {}
+        statement => {
+            for place in statement.written().into_iter().chain(statement.changed()) {
+                known.remove(&place);
+            }
+        }
+    }
+}
+
+fn substitute(operand: &mut Operand, known: &Known) {
+    if let Some(c) = operand.place().and_then(|p| known.get(&p)) {
+        *operand = Operand::Constant(c.clone());
+    }
+}
+
+// The statement with the constants it reads put in, and folded, None when it does nothing
+fn fold_statement(statement: &Statement, known: &Known) -> Option<Statement> {
+    if let Statement::Drop(place) = statement {
+        // a constant str is never freed
+        return match known.contains_key(place) {
+            true => None,
+            false => Some(statement.clone()),
+        };
+    }
+    let mut statement = statement.clone();
+    for operand in statement.operands_mut() {
+        substitute(operand, known);
+    }
+    if let Statement::Assign { value, .. } = &mut statement {
+        if let Some(folded) = fold_rvalue(value) {
+            *value = folded;
+        }
+    }
+    Some(statement)
+}
+
+fn fold_terminator(terminator: &Terminator, known: &Known) -> Terminator {
+    let mut terminator = terminator.clone();
+    for operand in terminator.operands_mut() {
+        substitute(operand, known);
+    }
+    match terminator {
+        Terminator::Branch {
+            condition: Operand::Constant(Constant::Bool(c)),
+            then,
+            otherwise,
+        } => Terminator::Goto(if c { then } else { otherwise }),
+        terminator => terminator,
+    }
+}
+
+// A simpler rvalue that comes to the same value, None when it can't be simplified
+fn fold_rvalue(value: &Rvalue) -> Option<Rvalue> {
+    use Operand::Constant as C;
+    let c = match value {
+        Rvalue::Binary(op, C(a), C(b)) => binary(*op, a, b)?,
+        Rvalue::Unary(op, C(a)) => match (op, a) {
+            (UnOp::Neg, Constant::Int(i)) => Constant::Int(i.checked_neg()?),
+            (UnOp::Neg, Constant::Float(x)) => Constant::Float(-x),
+            (UnOp::Not, Constant::Bool(b)) => Constant::Bool(!b),
+            _ => return None,
+        },
+        Rvalue::Cast(C(a), t) => match a {
+            Constant::Int(i) if *t == Type::float() => Constant::Float(*i as f64),
+            Constant::Bool(b) if *t == Type::int() => Constant::Int(*b as i64),
+            Constant::Char(c) if *t == Type::int() => Constant::Int(*c as i64),
+            // the MIR checked the float first, what's left of a failed check is never reached
+            Constant::Float(x) if *t == Type::int() => Constant::Int(float_to_int(*x).ok()?),
+            _ => return None,
+        },
+        Rvalue::Select(C(Constant::Bool(c)), a, b) => {
+            return Some(Rvalue::Use(if *c { a.clone() } else { b.clone() }))
+        }
+        _ => return None,
+    };
+    Some(Rvalue::Use(C(c)))
+}
+
+// The value of `a op b`, None when it fails at runtime or isn't a constant
+fn binary(op: BinOp, a: &Constant, b: &Constant) -> Option<Constant> {
+    use Constant::*;
+    let ordering = match (a, b) {
+        (Int(a), Int(b)) => a.partial_cmp(b),
+        (Float(a), Float(b)) => a.partial_cmp(b),
+        (Char(a), Char(b)) => a.partial_cmp(b),
+        (Str(a), Str(b)) => a.partial_cmp(b),
+        (Bool(a), Bool(b)) => a.partial_cmp(b),
+        (Atom(a), Atom(b)) if matches!(op, BinOp::Eq | BinOp::Ne) => a.partial_cmp(b),
+        _ => return None,
+    };
+    // NaN is unordered, so only `!=` holds for it
+    let compared = match op {
+        BinOp::Eq => Some(ordering == Some(Ordering::Equal)),
+        BinOp::Ne => Some(ordering != Some(Ordering::Equal)),
+        BinOp::Lt => Some(ordering == Some(Ordering::Less)),
+        BinOp::Gt => Some(ordering == Some(Ordering::Greater)),
+        BinOp::Le => Some(matches!(ordering, Some(Ordering::Less | Ordering::Equal))),
+        BinOp::Ge => Some(matches!(
+            ordering,
+            Some(Ordering::Greater | Ordering::Equal)
+        )),
+        _ => None,
+    };
+    if let Some(b) = compared {
+        return Some(Bool(b));
+    }
+    let c = match (a, b) {
+        (Int(a), Int(b)) => match op {
+            BinOp::Add => Int(a.checked_add(*b)?),
+            BinOp::Sub => Int(a.checked_sub(*b)?),
+            BinOp::Mul => Int(a.checked_mul(*b)?),
+            BinOp::Div => Int(a.checked_div(*b)?),
+            BinOp::Rem => Int(a.checked_rem(*b)?),
+            BinOp::Pow => Int(a.checked_pow(u32::try_from(*b).ok()?)?),
+            BinOp::Shl if (0..64).contains(b) => Int(a << b),
+            BinOp::Shr if (0..64).contains(b) => Int(a >> b),
+            BinOp::AddOverflows => Bool(a.checked_add(*b).is_none()),
+            BinOp::WrappingAdd => Int(a.wrapping_add(*b)),
+            _ => return None,
+        },
+        (Float(a), Float(b)) => match op {
+            BinOp::Add => Float(a + b),
+            BinOp::Sub => Float(a - b),
+            BinOp::Mul => Float(a * b),
+            BinOp::Div => Float(a / b),
+            BinOp::Rem => Float(a % b),
+            BinOp::Pow => Float(a.powf(*b)),
+            _ => return None,
+        },
+        (Str(a), Str(b)) if op == BinOp::Concat => Str(format!("{}{}", a, b)),
+        _ => return None,
+    };
+    Some(c)
+}
+
+/* Copy propagation */
+
+// The temporaries, the locals that aren't bindings or `_0`
+fn temporaries(function: &Function) -> Vec<bool> {
+    (function.locals.iter().enumerate())
+        .map(|(id, local)| id != 0 && local.name.is_none())
+        .collect()
+}
+
+// How many times each local is assigned, by an `Assign`, a call's result or a `next`
+fn assignments(function: &Function) -> Vec<usize> {
+    let mut counts = vec![0; function.locals.len()];
+    for block in function.blocks.iter() {
+        for statement in block.statements.iter() {
+            if let (Some(Place::Local(id)), false) =
+                (statement.written(), matches!(statement, Statement::Drop(_)))
+            {
+                counts[id] += 1;
+            }
+        }
+        if let Terminator::Next {
+            place: Some(Place::Local(id)),
+            ..
+        } = block.terminator
+        {
+            counts[id] += 1;
+        }
+    }
+    counts
+}
+
+// Whether `statement` names `place`, as an operand, what it writes or drops, or a closure it makes
+fn mentions(statement: &Statement, place: Place, function: &Function) -> bool {
+    let made = match statement {
+        Statement::Assign {
+            value: Rvalue::Closure(_, captures),
+            ..
+        } => captures.contains(&place),
+        _ => false,
+    };
+    made || changes(statement, place, function)
+        || statement
+            .operands()
+            .iter()
+            .any(|o| o.place() == Some(place))
+}
+
+// Whether `statement` changes `place`, running rho code can change any global or shared local
+fn changes(statement: &Statement, place: Place, function: &Function) -> bool {
+    let runs_code = matches!(statement, Statement::Call { callee, .. } if callee.runs_code());
+    let shared = matches!(place, Place::Global(_)) || function.captured(place);
+    statement.written() == Some(place)
+        || statement.changed().contains(&place)
+        || (runs_code && shared)
+}
+
+/* Two rewrites of a temporary whose uses are all in the block that assigns it:
+ *
+ *     _2 = _1                     _4 = Concat(_1, _1)
+ *     _3 = _1                     _0 = move _4
+ *     _4 = Concat(_2, _3)   -->
+ *     drop _2                     ... becomes _0 = Concat(_1, _1)
+ *     drop _3
+ *
+ * A copy is replaced by what it copies while that doesn't change, a moved copy of an object
+ * becomes a copy of the original and its drops go with it. A temporary only moved into another
+ * place is assigned to that place instead, when nothing in between looks at it.
+ */
+fn propagate_copies(function: &mut Function) {
+    let temporaries = temporaries(function);
+    let counts = assignments(function);
+    let mut used_in = vec![HashSet::new(); function.locals.len()];
+    for (b, block) in function.blocks.iter().enumerate() {
+        let statements = block.statements.iter().flat_map(|s| s.operands());
+        for operand in statements.chain(block.terminator.operands()) {
+            if let Some(Place::Local(id)) = operand.place() {
+                used_in[id].insert(b);
+            }
+        }
+        for statement in block.statements.iter() {
+            if let Statement::Drop(Place::Local(id)) = statement {
+                used_in[*id].insert(b);
+            }
+        }
+    }
+    for b in 0..function.blocks.len() {
+        let mut i = 0;
+        while i < function.blocks[b].statements.len() {
+            let Some(Place::Local(t)) = function.blocks[b].statements[i].written() else {
+                i += 1;
+                continue;
+            };
+            let local = temporaries[t] && counts[t] == 1 && used_in[t].iter().all(|u| *u == b);
+            if !(local && (copy(function, b, i, t) || forward(function, b, i, t))) {
+                i += 1;
+            }
+        }
+    }
+}
+
+// Replaces the temporary `t` that statement `i` of block `b` copies a place into with that place
+fn copy(function: &mut Function, b: BlockId, i: usize, t: LocalId) -> bool {
+    let block = &function.blocks[b];
+    let Statement::Assign {
+        value: Rvalue::Use(Operand::Copy(source)),
+        ..
+    } = block.statements[i]
+    else {
+        return false;
+    };
+    // reading a global can fail, where it's read
+    if let Place::Global(_) = source {
+        return false;
+    }
+    let temporary = Place::Local(t);
+    let rest = &block.statements[i + 1..];
+    // until its last use the place it copies has to stay the same
+    let last = match block
+        .terminator
+        .operands()
+        .iter()
+        .any(|o| o.place() == Some(temporary))
+    {
+        true => rest.len(),
+        false => match rest.iter().rposition(|s| mentions(s, temporary, function)) {
+            Some(last) => last + 1,
+            None => 0,
+        },
+    };
+    if rest[..last.min(rest.len())]
+        .iter()
+        .any(|s| changes(s, source, function))
+    {
+        return false;
+    }
+    let block = &mut function.blocks[b];
+    block.statements.remove(i);
+    block
+        .statements
+        .retain(|s| *s != Statement::Drop(temporary));
+    let statements = block.statements[i..]
+        .iter_mut()
+        .flat_map(|s| s.operands_mut());
+    for operand in statements.chain(block.terminator.operands_mut()) {
+        if operand.place() == Some(temporary) {
+            *operand = Operand::Copy(source);
+        }
+    }
+    true
+}
+
+// Assigns the temporary `t` statement `i` of block `b` sets to where it's moved to instead
+fn forward(function: &mut Function, b: BlockId, i: usize, t: LocalId) -> bool {
+    let temporary = Place::Local(t);
+    let block = &function.blocks[b];
+    if block
+        .terminator
+        .operands()
+        .iter()
+        .any(|o| o.place() == Some(temporary))
+    {
+        return false;
+    }
+    let uses: Vec<usize> = (i + 1..block.statements.len())
+        .filter(|j| mentions(&block.statements[*j], temporary, function))
+        .collect();
+    let [j] = uses[..] else {
+        return false;
+    };
+    let Statement::Assign {
+        place: target,
+        value: Rvalue::Use(Operand::Move(moved)),
+        ..
+    } = block.statements[j]
+    else {
+        return false;
+    };
+    let between = &block.statements[i + 1..j];
+    if moved != temporary || between.iter().any(|s| mentions(s, target, function)) {
+        return false;
+    }
+    let block = &mut function.blocks[b];
+    block.statements.remove(j);
+    match &mut block.statements[i] {
+        Statement::Assign { place, .. } => *place = target,
+        Statement::Call { result, .. } => *result = Some(target),
+        Statement::Match { result, .. } => *result = target,
+        _ => unreachable!("a temporary is assigned before it's dropped"),
+    }
+    true
+}
+
+/* Dead code elimination */
+
+fn remove_dead_code(program: &mut Program) {
+    /* the functions the top level and the closures use, and the ones those use. A function value
+     * can be called and `T.show(x)` calls the `show` of any type
+     */
+    let mut called = HashSet::new();
+    let mut pending: Vec<&Function> = program.closures.iter().collect();
+    pending.push(&program.entry);
+    while let Some(function) = pending.pop() {
+        for statement in function.blocks.iter().flat_map(|b| b.statements.iter()) {
+            let used: Vec<&Function> = match statement {
+                Statement::Call {
+                    callee: Callee::Function(name),
+                    ..
+                }
+                | Statement::Assign {
+                    value: Rvalue::Function(name),
+                    ..
+                } => program
+                    .functions
+                    .iter()
+                    .filter(|f| f.name == *name)
+                    .collect(),
+                Statement::Call {
+                    callee: Callee::Method(method),
+                    ..
+                } => (program.functions.iter())
+                    .filter(|f| f.name.ends_with(&format!(".{}", method)))
+                    .collect(),
+                _ => vec![],
+            };
+            for f in used {
+                if called.insert(f.name.clone()) {
+                    pending.push(f);
+                }
+            }
+        }
+    }
+    program.functions.retain(|f| called.contains(&f.name));
+
+    let globals = program.globals.clone();
+    let mut read = vec![false; globals.len()];
+    for function in functions(program) {
+        simplify_blocks(function);
+        for place in all_read(function) {
+            if let Place::Global(id) = place {
+                read[id] = true;
+            }
+        }
+    }
+    for function in functions(program) {
+        let mut dead: Vec<Place> = (0..globals.len())
+            .filter(|id| !read[*id])
+            .map(Place::Global)
+            .collect();
+        let mut read = vec![false; function.locals.len()];
+        for place in all_read(function) {
+            if let Place::Local(id) = place {
+                read[id] = true;
+            }
+        }
+        read[0] = true; // by `return`
+        for param in function.params.iter() {
+            read[*param] = true;
+        }
+        // by the closures sharing them
+        for (id, local) in function.locals.iter().enumerate() {
+            read[id] |= local.captured;
+        }
+        dead.extend((0..read.len()).filter(|id| !read[*id]).map(Place::Local));
+        for place in dead {
+            remove_unread(function, place, &globals);
+        }
+        remove_empty_frames(function, &globals);
+        remove_unused_locals(function);
+    }
+}
+
+// The places the function reads, a path is set inside the value its place has
+fn all_read(function: &Function) -> impl Iterator<Item = Place> + '_ {
+    function.blocks.iter().flat_map(|b| {
+        let statements = b.statements.iter().flat_map(|s| {
+            let operands = s.operands().into_iter().filter_map(|o| o.place());
+            let path = match s {
+                Statement::SetPath { place, .. } => Some(*place),
+                _ => None,
+            };
+            operands.chain(path)
+        });
+        let terminator = b.terminator.operands().into_iter();
+        statements.chain(terminator.filter_map(|o| o.place()))
+    })
+}
+
+// Whether an assignment of `value` only sets its place, it can't fail or take over an object
+fn pure(value: &Rvalue, function: &Function, globals: &[Global]) -> bool {
+    let moved =
+        |o: &&Operand| matches!(o, Operand::Move(p) if owns(&function.place_type(*p, globals)));
+    match value {
+        Rvalue::Use(Operand::Move(place)) => !owns(&function.place_type(*place, globals)),
+        // a new list or record takes over what's moved into it
+        Rvalue::List(_) | Rvalue::Tuple(_) | Rvalue::Map(_) | Rvalue::Struct(..) => {
+            !value.operands().iter().any(moved) && !fails(value, function, globals)
+        }
+        value => !fails(value, function, globals),
+    }
+}
+
+/* Whether computing `value` can fail, int arithmetic can. So can the operators on values whose
+ * types the checker didn't know, which the VM finds out when it runs, and looking inside a value.
+ */
+fn fails(value: &Rvalue, function: &Function, globals: &[Global]) -> bool {
+    let int = |a: &Operand| function.operand_type(a, globals) == Type::int();
+    let unknown = |a: &Operand| !compiled(&function.operand_type(a, globals));
+    match value {
+        Rvalue::Binary(op, a, b) => match op {
+            _ if unknown(a) || unknown(b) => true,
+            BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Rem | BinOp::Pow => int(a),
+            BinOp::Shl | BinOp::Shr => true,
+            _ => false,
+        },
+        Rvalue::Unary(_, a) if unknown(a) => true,
+        Rvalue::Unary(UnOp::Neg, a) => int(a),
+        // a global a function reads before the top level sets it
+        Rvalue::Use(a) => matches!(a.place(), Some(Place::Global(_))),
+        Rvalue::Map(_) | Rvalue::Range(..) | Rvalue::Field(..) | Rvalue::Index(..) => true,
+        _ => false,
+    }
+}
+
+/* Removes the assignments to a place nothing reads, and its drops. When one of them has to stay
+ * because it can fail the drops stay too, a call's result is kept when it's an object to drop.
+ */
+fn remove_unread(function: &mut Function, place: Place, globals: &[Global]) {
+    let statements = function.blocks.iter().flat_map(|b| b.statements.iter());
+    let removable = statements.clone().all(|s| match s {
+        Statement::Assign {
+            place: p, value, ..
+        } if *p == place => pure(value, function, globals),
+        Statement::Call { result, .. } if *result == Some(place) => false,
+        _ => true,
+    });
+    let owned = owns(&function.place_type(place, globals));
+    for block in function.blocks.iter_mut() {
+        block.statements.retain(|s| match s {
+            Statement::Assign { place: p, .. } | Statement::Drop(p) => !(removable && *p == place),
+            _ => true,
+        });
+        for statement in block.statements.iter_mut() {
+            if let Statement::Call { result, .. } = statement {
+                if *result == Some(place) && !owned {
+                    *result = None;
+                }
+            }
+        }
+    }
+}
+
+// Removes the frames of inlined calls that have nothing left in them that can fail
+fn remove_empty_frames(function: &mut Function, globals: &[Global]) {
+    for b in 0..function.blocks.len() {
+        let statements = &function.blocks[b].statements;
+        let mut empty = vec![];
+        let mut entered = None;
+        for (i, statement) in statements.iter().enumerate() {
+            match statement {
+                Statement::Call {
+                    callee: Callee::Enter(_),
+                    ..
+                } => entered = Some(i),
+                Statement::Call {
+                    callee: Callee::Leave,
+                    ..
+                } => {
+                    if let Some(enter) = entered.take() {
+                        empty.extend([enter, i]);
+                    }
+                }
+                s if statement_fails(s, function, globals) => entered = None,
+                _ => {}
+            }
+        }
+        let mut i = 0;
+        function.blocks[b].statements.retain(|_| {
+            i += 1;
+            !empty.contains(&(i - 1))
+        });
+    }
+}
+
+// Drops the locals no statement names anymore, and numbers the others again
+fn remove_unused_locals(function: &mut Function) {
+    let mut used = vec![false; function.locals.len()];
+    used[0] = true;
+    for param in function.params.iter() {
+        used[*param] = true;
+    }
+    for block in function.blocks.iter_mut() {
+        for statement in block.statements.iter_mut() {
+            for place in statement.places_mut() {
+                if let Place::Local(id) = place {
+                    used[*id] = true;
+                }
+            }
+        }
+        for place in block.terminator.places_mut() {
+            if let Place::Local(id) = place {
+                used[*id] = true;
+            }
+        }
+    }
+    let mut numbers = vec![0; used.len()];
+    let mut locals = vec![];
+    for (id, local) in function.locals.iter().enumerate() {
+        if used[id] {
+            numbers[id] = locals.len();
+            locals.push(local.clone());
+        }
+    }
+    function.locals = locals;
+    let renumber = |place: &mut Place| {
+        if let Place::Local(id) = place {
+            *id = numbers[*id];
+        }
+    };
+    for param in function.params.iter_mut() {
+        *param = numbers[*param];
+    }
+    for block in function.blocks.iter_mut() {
+        for statement in block.statements.iter_mut() {
+            statement.places_mut().into_iter().for_each(renumber);
+        }
+        block.terminator.places_mut().into_iter().for_each(renumber);
+    }
+}
+
+/* Goes around the blocks with nothing but a `goto`, merges a block into the one before when
+ * that's the only way to it, and removes the blocks nothing reaches, numbering the others again.
+ */
+fn simplify_blocks(function: &mut Function) {
+    let empty_goto = |blocks: &[Block], b: BlockId| match blocks[b].terminator {
+        Terminator::Goto(next) if blocks[b].statements.is_empty() && next != b => Some(next),
+        _ => None,
+    };
+    for b in 0..function.blocks.len() {
+        let mut terminator = function.blocks[b].terminator.clone();
+        for target in terminator.successors_mut() {
+            // a loop of empty blocks goes nowhere, so it's only followed as far as its length
+            for _ in 0..function.blocks.len() {
+                match empty_goto(&function.blocks, *target) {
+                    Some(next) => *target = next,
+                    None => break,
+                }
+            }
+        }
+        if let Terminator::Branch {
+            then, otherwise, ..
+        } = terminator
+        {
+            if then == otherwise {
+                terminator = Terminator::Goto(then);
+            }
+        }
+        function.blocks[b].terminator = terminator;
+    }
+
+    let order = reachable(function);
+    let mut predecessors = vec![0; function.blocks.len()];
+    for b in order.iter() {
+        for next in function.blocks[*b].terminator.successors() {
+            predecessors[next] += 1;
+        }
+    }
+    for b in order.iter() {
+        while let Terminator::Goto(next) = function.blocks[*b].terminator {
+            if next == *b || next == 0 || predecessors[next] != 1 {
+                break;
+            }
+            let merged = std::mem::replace(
+                &mut function.blocks[next],
+                Block {
+                    statements: vec![],
+                    terminator: Terminator::Unreachable,
+                },
+            );
+            // it was only reached from here, which now goes where it went
+            predecessors[next] = 0;
+            let block = &mut function.blocks[*b];
+            block.statements.extend(merged.statements);
+            block.terminator = merged.terminator;
+        }
+    }
+
+    let mut reached = reachable(function);
+    reached.sort();
+    let mut numbers = vec![0; function.blocks.len()];
+    for (number, b) in reached.iter().enumerate() {
+        numbers[*b] = number;
+    }
+    let mut blocks = std::mem::take(&mut function.blocks);
+    for b in reached {
+        let mut block = std::mem::replace(
+            &mut blocks[b],
+            Block {
+                statements: vec![],
+                terminator: Terminator::Unreachable,
+            },
+        );
+        for target in block.terminator.successors_mut() {
+            *target = numbers[*target];
+        }
+        function.blocks.push(block);
+    }
+}
+
+// The MIR of a program before and after `passes`
+#[cfg(test)]
+fn optimized(source: &str, passes: &[Pass]) -> (Program, Program) {
+    let before = crate::mir::lower_source(source).unwrap();
+    let mut after = before.clone();
+    optimize(&mut after, passes);
+    (before, after)
+}
+
+#[test]
+fn test_fold() {
+    let source = "x = 1 + 2\nIO.puts(x * 10)\nIO.puts(9223372036854775807 + 1)\n";
+    let (before, after) = optimized(source, &[Pass::Fold]);
+    assert_eq!(
+        before.entry.to_string(),
+        "fn <top level>() -> int {
+    let _0: int
+    let _1: int
+    let _2: int
+    let _3: int
+    let _4: int
+
+    bb0:
+        _1 = Add(const 1, const 2) @ 1:7
+        @0 = move _1
+        _2 = @0
+        _3 = Mul(_2, const 10) @ 2:11
+        IO.puts(_3) @ 2:1
+        _4 = Add(const 9223372036854775807, const 1) @ 3:29
+        IO.puts(_4) @ 3:1
+        _0 = const 0
+        goto bb1
+
+    bb1:
+        return
+}
+"
+    );
+    // the overflow still fails when it runs
+    assert_eq!(
+        after.entry.to_string(),
+        "fn <top level>() -> int {
+    let _0: int
+    let _1: int
+    let _2: int
+    let _3: int
+    let _4: int
+
+    bb0:
+        _1 = const 3
+        @0 = const 3
+        _2 = const 3
+        _3 = const 30
+        IO.puts(const 30) @ 2:1
+        _4 = Add(const 9223372036854775807, const 1) @ 3:29
+        IO.puts(_4) @ 3:1
+        _0 = const 0
+        goto bb1
+
+    bb1:
+        return
+}
+"
+    );
+}
+
+#[test]
+fn test_dead_code() {
+    let source = "func unused() -> int {
+    return 1
+}
+var n = 0
+if 2 > 3 {
+    n = 1
+}
+IO.puts(n)
+";
+    let (before, after) = optimized(source, &[Pass::DeadCode]);
+    assert_eq!(before.functions.len(), 1);
+    assert_eq!(
+        before.entry.to_string(),
+        "fn <top level>() -> int {
+    let _0: int
+    let _1: bool
+    let _2: int
+
+    bb0:
+        @0 = const 0
+        _1 = Gt(const 2, const 3) @ 5:6
+        branch move _1, bb3, bb4
+
+    bb1:
+        return
+
+    bb2:
+        _2 = @0
+        IO.puts(_2) @ 8:1
+        _0 = const 0
+        goto bb1
+
+    bb3:
+        @0 = const 1
+        goto bb2
+
+    bb4:
+        goto bb2
+}
+"
+    );
+    // `unused` is gone, the empty `bb4` is gone around and `bb1` merged into `bb2`
+    assert_eq!(
+        after.to_string(),
+        "global @0: int  // n
+
+fn <top level>() -> int {
+    let _0: int
+    let _1: bool
+    let _2: int
+
+    bb0:
+        @0 = const 0
+        _1 = Gt(const 2, const 3) @ 5:6
+        branch move _1, bb2, bb1
+
+    bb1:
+        _2 = @0
+        IO.puts(_2) @ 8:1
+        _0 = const 0
+        return
+
+    bb2:
+        @0 = const 1
+        goto bb1
+}
+"
+    );
+    // with the condition folded the `if` is unreachable, and then `n` is never read
+    let (_, after) = optimized(source, &[Pass::Fold, Pass::DeadCode]);
+    assert_eq!(
+        after.entry.to_string(),
+        "fn <top level>() -> int {
+    let _0: int
+
+    bb0:
+        IO.puts(const 0) @ 8:1
+        _0 = const 0
+        return
+}
+"
+    );
+}
+
+#[test]
+fn test_copy_propagation() {
+    let source = "func twice(str s) -> str {\n    return s <> s\n}\nIO.puts(twice(\"a\"))\n";
+    let (before, after) = optimized(source, &[Pass::Copies]);
+    assert!(before.functions[0].to_string().contains("_2 = _1\n"));
+    // the copies of `s` and their drops are gone, and the result is made in `_0`
+    assert_eq!(
+        after.functions[0].to_string(),
+        "fn twice(_1: str) -> str {
+    let _0: str
+    let _1: str  // s
+    let _2: str
+    let _3: str
+    let _4: str
+
+    bb0:
+        _0 = Concat(_1, _1) @ 2:14
+        goto bb1
+
+    bb1:
+        drop _1
+        return
+}
+"
+    );
+
+    // `bump` changes `x` after it's read
+    let source = "var x = 1
+func bump() -> int {
+    x = x + 10
+    return 0
+}
+IO.puts(x + bump())
+";
+    let (_, after) = optimized(source, &[Pass::Copies]);
+    let entry = after.entry.to_string();
+    assert!(entry.contains("_1 = @0\n"), "{}", entry);
+    assert!(entry.contains("Add(_1, _2)"), "{}", entry);
+}
+
+#[test]
+fn test_inline() {
+    let source = "func double(int n) -> int {\n    return n * 2\n}\nIO.puts(double(21))\n";
+    let (before, after) = optimized(source, &[Pass::Inline, Pass::DeadCode]);
+    assert!(before
+        .entry
+        .to_string()
+        .contains("_1 = double(const 21) @ 4:9"));
+    /* the argument is moved into `n`, and `_0` of `double` into the call's result. `n * 2` can
+     * fail, so the body is in the frame of `double`
+     */
+    assert_eq!(
+        after.to_string(),
+        "
+fn <top level>() -> int {
+    let _0: int
+    let _1: int
+    let _2: int
+    let _3: int  // n
+    let _4: int
+    let _5: int
+
+    bb0:
+        enter double() @ 4:9
+        _3 = const 21
+        _4 = _3
+        _5 = Mul(_4, const 2) @ 2:14
+        _2 = move _5
+        leave() @ 4:9
+        _1 = move _2
+        IO.puts(_1) @ 4:1
+        _0 = const 0
+        return
+}
+"
+    );
+    let (_, after) = optimized(source, &Pass::ALL);
+    let entry = after.entry.to_string();
+    // `21 * 2` was folded, there's nothing left to fail in the frame
+    assert!(entry.contains("IO.puts(const 42) @ 4:1"), "{}", entry);
+    assert!(!entry.contains("enter"), "{}", entry);
+
+    // `fib` calls itself, so it isn't inlined
+    let source = "func fib(int n) -> int {\n    return if n < 2 { n } else { fib(n - 1) + fib(n - 2) }\n}\nIO.puts(fib(10))\n";
+    let (_, after) = optimized(source, &Pass::ALL);
+    assert!(after.entry.to_string().contains("fib(const 10)"));
+}
+
+/* The golden programs of the LLVM backend, and some that fail, run the same after each pass on its
+ * own and after all of them, when LLVM is installed.
+ */
+#[test]
+fn test_optimized_programs() {
+    let Some(_) = crate::codegen::lli() else {
+        return;
+    };
+    let mut sources: Vec<String> = std::fs::read_dir("./llvm_testfiles/golden")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "rho"))
+        .map(|path| std::fs::read_to_string(path).unwrap())
+        .collect();
+    sources.extend(
+        [
+            "x = 9223372036854775806 + 1\nIO.puts(x + 1)\n",
+            "func half(int n) -> int {\n    return n / 2\n}\nIO.puts(half(1) / half(1))\n",
+            "var s = \"a\"\nfor i in range 0..3 {\n    s = s <> \"b\"\n}\nIO.puts(s)\n",
+            "func f(str a) -> str {
+    var s = a
+    var t = s
+    s = \"x\"
+    return t <> s
+}
+IO.puts(f(\"y\"))
+",
+            "var g = \"a\"
+func change() -> str {
+    g = g <> \"b\"
+    return \"c\"
+}
+IO.puts(g <> change())
+IO.puts(g)
+",
+            "func greet(str name) -> str {
+    return \"hi \" <> name
+}
+var s = \"a\"
+while s != \"aaaa\" {
+    IO.puts(greet(s))
+    s = s <> \"a\"
+}
+",
+        ]
+        .map(String::from),
+    );
+    let path = std::env::temp_dir().join(format!("rho_passes_{}.ll", std::process::id()));
+    let mut configurations: Vec<Vec<Pass>> = Pass::ALL.iter().map(|p| vec![*p]).collect();
+    configurations.push(Pass::ALL.to_vec());
+    for source in sources.iter() {
+        let (result, output) = crate::interpreter::run_source(source, "");
+        for passes in configurations.iter() {
+            let (_, mir) = optimized(source, passes);
+            std::fs::write(&path, crate::codegen::generate(&mir, "a.rho")).unwrap();
+            let ran = crate::codegen::lli()
+                .unwrap()
+                .env("RHO_LEAK_CHECK", "1")
+                .arg(&path)
+                .output()
+                .unwrap();
+            let stderr = String::from_utf8_lossy(&ran.stderr);
+            let context = format!("{:?} on\n{}{}", passes, source, stderr);
+            assert_eq!(String::from_utf8_lossy(&ran.stdout), output, "{}", context);
+            match &result {
+                Ok(code) => assert_eq!(ran.status.code(), Some(*code), "{}", context),
+                Err(e) => {
+                    let at = format!("a.rho:{}:{}", e.span.line, e.span.col);
+                    assert!(stderr.contains(&e.message), "{}", context);
+                    assert!(stderr.contains(&at), "{}", context);
+                }
+            }
+            assert!(!stderr.contains("still live"), "{}", context);
+        }
+    }
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,413 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+use crate::cli;
+use crate::diagnostics::Diagnostic;
+use crate::interpreter;
+use crate::parsers::{expressionize, Expression, SymbolId};
+use crate::resolver::SymbolTable;
+use crate::rho_core;
+use crate::tokens::*;
+use crate::value::*;
+
+const HELP: &str = "Enter rho statements and expressions, the value of an expression is shown.
+Input with an unclosed `{`, `(`, `[`, string or comment continues on the next line.
+
+    :type <expr>     shows the type of an expression without running it
+    :tokens <code>   shows the tokens of some code
+    :ast <code>      shows the syntax tree of some code
+    :load <file>     runs a file in this session, its `main` isn't called
+    :history         shows the last inputs, kept in ~/.rho_history
+    :help            shows this message
+    :quit            leaves, so does ctrl-d
+";
+
+// The binding `:type` checks its expression as
+const TYPE_BINDING: &str = "repl_type";
+
+/* `rho repl`, an interactive session on the interpreter. Every input is compiled together with
+ * the inputs before it, so it can use their functions, structs and bindings and is checked
+ * against them, but only its own statements run. The values of top level bindings carry over
+ * from input to input in `globals`.
+ */
+pub fn main() -> i32 {
+    let mut repl = Repl::new();
+    let history = history_path();
+    let stdin = std::io::stdin();
+    let mut stdin = stdin.lock();
+    let mut stdout = std::io::stdout();
+    println!("rho {}, :help for help", env!("CARGO_PKG_VERSION"));
+    loop {
+        let Some(input) = read_input(&mut stdin, &mut stdout) else {
+            println!();
+            return 0;
+        };
+        if input.trim().is_empty() {
+            continue;
+        }
+        if let Some(path) = &history {
+            append_history(path, &input);
+        }
+        if matches!(input.trim(), ":quit" | ":q") {
+            return 0;
+        }
+        repl.input(&input, &mut stdin, &mut stdout);
+    }
+}
+
+// Reads lines until the input is complete, None at the end of stdin
+fn read_input(stdin: &mut dyn BufRead, stdout: &mut dyn Write) -> Option<String> {
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() { "rho> " } else { "...> " };
+        let _ = write!(stdout, "{}", prompt);
+        let _ = stdout.flush();
+        let mut line = String::new();
+        match stdin.read_line(&mut line) {
+            Ok(0) | Err(_) if input.is_empty() => return None,
+            // a cut off input is run as it is and reports what's missing
+            Ok(0) | Err(_) => return Some(input),
+            Ok(_) => input += &line,
+        }
+        if input.trim_start().starts_with(':') || is_complete(&input) {
+            return Some(input);
+        }
+    }
+}
+
+// Whether an input has closed every bracket, string and block comment it opened
+pub fn is_complete(input: &str) -> bool {
+    let tokens = match tokenize(input) {
+        Ok(tokens) => tokens,
+        // the only error that more input can fix
+        Err(e) => return e.message != "string literal never closed",
+    };
+    let mut depth = 0;
+    for token in tokens.iter() {
+        match token.token_type {
+            TokenType::Delimiter(Delimiters::ParOpen)
+            | TokenType::Delimiter(Delimiters::BracketOpen)
+            | TokenType::Delimiter(Delimiters::BraceOpen) => depth += 1,
+            TokenType::Delimiter(Delimiters::ParClose)
+            | TokenType::Delimiter(Delimiters::BracketClose)
+            | TokenType::Delimiter(Delimiters::BraceClose) => depth -= 1,
+            TokenType::Comment
+                if token.string.starts_with("/*")
+                    && (token.string.len() < 4 || !token.string.ends_with("*/")) =>
+            {
+                return false
+            }
+            _ => {}
+        }
+    }
+    depth <= 0
+}
+
+// A session that compiled with an input added, see `Repl::compile`
+type Compiled = (Vec<Expression>, SymbolTable, String, Vec<Diagnostic>);
+
+/* Where the diagnostics about an input point. A typed input's are at its lines in the session,
+ * a loaded file's at its own path and lines, and `:type`'s at the expression rather than the
+ * binding it's checked as.
+ */
+struct Origin<'a> {
+    file: Option<&'a str>, // None for a typed input
+    code: &'a str,
+    first_line: usize, // the session line `code` starts on
+    prefix: usize,     // the columns added before `code` on its first line
+}
+
+impl Origin<'_> {
+    fn render(&self, d: &Diagnostic, session: &str) -> String {
+        d.render_in(&|span| match self.file {
+            Some(file) if span.line >= self.first_line => {
+                let line = span.line - self.first_line + 1;
+                let col = match line {
+                    1 => span.col.saturating_sub(self.prefix).max(1),
+                    _ => span.col,
+                };
+                (file, self.code, Span { line, col, ..span })
+            }
+            _ => ("repl", session, span),
+        })
+    }
+}
+
+pub struct Repl {
+    source: String,    // every input that compiled, one after the other
+    statements: usize, // the top level expressions of `source`
+    symbols: SymbolTable,
+    globals: Env,
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl {
+            source: String::new(),
+            statements: 0,
+            symbols: SymbolTable::default(),
+            globals: Scope::new(None),
+        }
+    }
+
+    // Handles an input, a meta-command or code, writing what it shows to `output`
+    pub fn input(&mut self, input: &str, stdin: &mut dyn BufRead, output: &mut dyn Write) {
+        let trimmed = input.trim();
+        let (command, argument) = trimmed.split_once(' ').unwrap_or((trimmed, ""));
+        let argument = argument.trim();
+        let shown = match command {
+            ":help" | ":h" => Ok(HELP.to_string()),
+            ":type" | ":t" => self.type_of(argument),
+            ":tokens" => tokenize(argument)
+                .map(|tokens| cli::format_tokens(&tokens))
+                .map_err(|e| e.render("repl", argument)),
+            ":ast" => tokenize(argument)
+                .and_then(|tokens| expressionize(&tokens))
+                .map(|expressions| expressions.iter().map(|e| format!("{:#?}\n", e)).collect())
+                .map_err(|e| e.render("repl", argument)),
+            ":load" | ":l" => match fs::read_to_string(argument) {
+                Ok(source) => self.run(&source, Some(argument), stdin, output),
+                Err(e) => Err(format!("error: can't read {}: {}\n", argument, e)),
+            },
+            ":history" => Ok(read_history().join("")),
+            command if command.starts_with(':') => Err(format!(
+                "error: unknown command `{}`, :help lists them\n",
+                command
+            )),
+            _ => self.run(input, None, stdin, output),
+        };
+        let _ = match shown {
+            Ok(text) => write!(output, "{}", text),
+            Err(text) => write!(output, "{}", text),
+        };
+        let _ = output.flush();
+    }
+
+    // Where the diagnostics about `code` point when it's added to the session, see `Origin`
+    fn origin<'a>(&self, file: Option<&'a str>, code: &'a str, prefix: usize) -> Origin<'a> {
+        Origin {
+            file,
+            code,
+            first_line: self.source.lines().count() + 1,
+            prefix,
+        }
+    }
+
+    /* Compiles the session with `code` added to it. Comes back with the program, its symbols, its
+     * source and the warnings about `code`, or the errors rendered as `origin` says.
+     */
+    fn compile(&self, code: &str, origin: &Origin) -> Result<Compiled, String> {
+        let mut source = self.source.clone();
+        source += code;
+        if !source.ends_with('\n') {
+            source.push('\n');
+        }
+        let (compiled, diagnostics) = crate::compile(&source);
+        let Some((program, symbols)) = compiled else {
+            let errors = diagnostics.iter().filter(|d| d.is_error());
+            return Err(errors.map(|d| origin.render(d, &source)).collect());
+        };
+        // earlier inputs were already warned about
+        let warnings = diagnostics
+            .into_iter()
+            .filter(|d| d.span.line >= origin.first_line)
+            .collect();
+        Ok((program, symbols, source, warnings))
+    }
+
+    // Runs `code`, typed in or loaded from `file`
+    fn run(
+        &mut self,
+        code: &str,
+        file: Option<&str>,
+        stdin: &mut dyn BufRead,
+        output: &mut dyn Write,
+    ) -> Result<String, String> {
+        let origin = self.origin(file, code, 0);
+        let (program, symbols, source, warnings) = self.compile(code, &origin)?;
+        for d in warnings.iter().filter(|d| !is_shown_result(&program, d)) {
+            let _ = write!(output, "{}", origin.render(d, &source));
+        }
+
+        let globals = self.carry_over(&symbols);
+        let result =
+            interpreter::run_input(&program, &symbols, self.statements, &globals, stdin, output);
+        // the statements before a runtime error did run, the input stays in the session
+        self.source = source;
+        self.statements = program.len();
+        self.symbols = symbols;
+        self.globals = globals;
+        match result {
+            Ok(Value::Unit) => Ok(String::new()),
+            Ok(value) => Ok(format!("{}\n", rho_core::inspect(&value))),
+            Err(e) => Err(origin.render(&e, &self.source)),
+        }
+    }
+
+    /* The top level bindings with their values so far, by their ids in the newly compiled
+     * session. Ids can change from one compile to the next, a binding is the same one when it is
+     * declared at the same place.
+     */
+    fn carry_over(&self, symbols: &SymbolTable) -> Env {
+        let globals = Scope::new(None);
+        let old: HashMap<(&str, Span), SymbolId> = self
+            .symbols
+            .symbols
+            .iter()
+            .enumerate()
+            .map(|(id, s)| ((s.name.as_str(), s.span), id))
+            .collect();
+        for (id, symbol) in symbols.symbols.iter().enumerate() {
+            let Some(old_id) = old.get(&(symbol.name.as_str(), symbol.span)) else {
+                continue;
+            };
+            if let Some(value) = lookup(&self.globals, *old_id) {
+                define(&globals, id, value);
+            }
+        }
+        globals
+    }
+
+    fn type_of(&self, expression: &str) -> Result<String, String> {
+        if expression.is_empty() {
+            return Err("error: `:type` needs an expression\n".to_string());
+        }
+        let code = format!("{} = {}\n", TYPE_BINDING, expression);
+        let prefix = TYPE_BINDING.len() + " = ".len();
+        let origin = self.origin(Some("repl"), expression, prefix);
+        let unit = format!(
+            "error: `{}` can't be bound to a value of type `unit`",
+            TYPE_BINDING
+        );
+        let (_, symbols, source, _) = match self.compile(&code, &origin) {
+            // an expression without a value, the only error about the binding itself
+            Err(errors) if errors.starts_with(&unit) && !errors.contains("\nerror: ") => {
+                return Ok("unit\n".to_string())
+            }
+            compiled => compiled?,
+        };
+        let line = source.lines().count();
+        let symbol = symbols
+            .symbols
+            .iter()
+            .find(|s| s.name == TYPE_BINDING && s.span.line == line);
+        match symbol.and_then(|s| s.symbol_type.as_ref()) {
+            Some(t) => Ok(format!("{}\n", t)),
+            None => Ok("unknown\n".to_string()),
+        }
+    }
+}
+
+// The value of an input's last expression is shown, ie. a `result` isn't left unused
+fn is_shown_result(program: &[Expression], d: &Diagnostic) -> bool {
+    !d.is_error()
+        && d.message.starts_with("unused `")
+        && program.last().is_some_and(|e| e.span() == d.span)
+}
+
+fn history_path() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(PathBuf::from(home).join(".rho_history"))
+}
+
+fn append_history(path: &PathBuf, input: &str) {
+    let file = fs::OpenOptions::new().create(true).append(true).open(path);
+    if let Ok(mut file) = file {
+        let _ = write!(file, "{}", input);
+        if !input.ends_with('\n') {
+            let _ = writeln!(file);
+        }
+    }
+}
+
+// The last inputs, numbered
+fn read_history() -> Vec<String> {
+    let Some(contents) = history_path().and_then(|path| fs::read_to_string(path).ok()) else {
+        return vec![];
+    };
+    let lines: Vec<&str> = contents.lines().collect();
+    let start = lines.len().saturating_sub(20);
+    lines[start..]
+        .iter()
+        .enumerate()
+        .map(|(i, line)| format!("{:>5}  {}\n", start + i + 1, line))
+        .collect()
+}
+
+#[cfg(test)]
+fn session(inputs: &[&str]) -> Vec<String> {
+    let mut repl = Repl::new();
+    let mut stdin = std::io::empty();
+    inputs
+        .iter()
+        .map(|input| {
+            let mut output = vec![];
+            repl.input(input, &mut stdin, &mut output);
+            String::from_utf8(output).unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn test_repl_session() {
+    let outputs = session(&[
+        "x = 40",
+        "func add(int a, int b) -> int {\n    return a + b\n}\n",
+        "add(x, 2)",
+        "var names = [\"a\"]",
+        "names = names ++ [\"b\"]",
+        "IO.puts(\"hi\")",
+        "names",
+        ":type add(x, 1) > 2",
+        "y",
+        "x + 1",
+    ]);
+    assert_eq!(outputs[0..3], ["", "", "42\n"]);
+    assert_eq!(outputs[5..8], ["hi\n", "[\"a\", \"b\"]\n", "bool\n"]);
+    assert!(outputs[8].starts_with("error: undefined name `y`"));
+    assert_eq!(outputs[9], "41\n");
+}
+
+#[test]
+fn test_repl_errors() {
+    let outputs = session(&["int n = 1", "n / 0", "n + 1", ":nope", ":tokens n + 1"]);
+    assert!(outputs[1].starts_with("error: "));
+    assert_eq!(outputs[2], "2\n");
+    assert_eq!(
+        outputs[3],
+        "error: unknown command `:nope`, :help lists them\n"
+    );
+    assert!(outputs[4].starts_with("1:1\tIdentifier\t\"n\"\n"));
+}
+
+#[test]
+fn test_is_complete() {
+    assert!(is_complete("x = 1"));
+    assert!(is_complete("f(1)\n"));
+    assert!(!is_complete("func f() {"));
+    assert!(!is_complete("xs = [1,\n 2"));
+    assert!(!is_complete("s = \"abc"));
+    assert!(!is_complete("/* a comment"));
+    assert!(is_complete("func f() {\n}\n"));
+    // what goes on over the lines inside brackets parses too
+    assert!(!is_complete("x = (1 +"));
+    let outputs = session(&["x = (1 +\n    2)", "x * [3,\n 4][1]"]);
+    assert_eq!(outputs, ["", "12\n"]);
+}
+
+#[test]
+fn test_repl_error_locations() {
+    let path = std::env::temp_dir().join(format!("rho_repl_load_{}.rho", std::process::id()));
+    fs::write(&path, "IO.puts(\"loaded\")\nz = 1 + \"a\"\n").unwrap();
+    let load = format!(":load {}", path.display());
+    let outputs = session(&["x = 1", ":type x + \"a\"", ":type IO.puts(\"a\")", &load]);
+    fs::remove_file(&path).unwrap();
+    // at the expression given, not the binding it's checked as
+    assert!(outputs[1].contains("--> repl:1:3\n  |\n1 | x + \"a\"\n  |   ^\n"));
+    assert_eq!(outputs[2], "unit\n");
+    // at the loaded file's own lines
+    let at = format!("--> {}:2:7\n  |\n2 | z = 1 + \"a\"\n", path.display());
+    assert!(outputs[3].contains(&at), "{}", outputs[3]);
+}
@@ -0,0 +1,937 @@
+use std::collections::HashMap;
+
+use crate::diagnostics::Diagnostic;
+use crate::parsers::*;
+use crate::rho_core;
+use crate::tokens::*;
+use crate::types::Type;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Variable,
+    Parameter,
+    Function,
+    Struct,
+    Enum,
+    Interface,
+    TypeParam,
+    Builtin,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String, // the dotted name for functions, ie. "Point.show"
+    pub kind: SymbolKind,
+    pub span: Span, // where it is declared, builtins have the default span
+    pub symbol_type: Option<Type>, // as declared, the checker fills it in for untyped bindings
+    pub mutability: Mutability, // only `var` bindings can be assigned to after they are defined
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    pub symbols: Vec<Symbol>,
+    // functions, by their dotted name, and types, by name
+    pub globals: HashMap<String, SymbolId>,
+}
+
+impl SymbolTable {
+    pub fn get(&self, id: SymbolId) -> &Symbol {
+        &self.symbols[id]
+    }
+
+    fn add(&mut self, symbol: Symbol) -> SymbolId {
+        self.symbols.push(symbol);
+        self.symbols.len() - 1
+    }
+
+    // Whether `ident` declares its symbol, rather than assigning to an existing one ie. `x = 2`
+    pub fn declares(&self, ident: &Ident) -> bool {
+        ident
+            .symbol
+            .is_some_and(|id| self.symbols[id].span == ident.span)
+    }
+
+    pub fn global(&self, name: &str) -> Option<&Symbol> {
+        self.globals.get(name).map(|id| &self.symbols[*id])
+    }
+}
+
+struct Resolver {
+    table: SymbolTable,
+    scopes: Vec<HashMap<String, SymbolId>>,
+    // the first scope of the function being resolved, shadowing is only reported within a function
+    function_scope: usize,
+    // the top level statement being resolved, and the one each top level binding is defined in
+    statement: usize,
+    defined_at: HashMap<SymbolId, usize>,
+    // top level bindings defined from this statement on aren't seen, see `first_calls`
+    visible_before: usize,
+    loop_depth: usize,
+    struct_fields: HashMap<String, Vec<String>>,
+    enum_variants: HashMap<String, Vec<String>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/* Builds the scopes of a program, resolves every identifier and dotted name to its declaration
+ * and records the declaration's id in the `symbol` of the identifier. Reports undefined names,
+ * names defined twice in the same scope, and bindings that shadow another in the same function.
+ */
+pub fn resolve(program: &mut [Expression]) -> (SymbolTable, Vec<Diagnostic>) {
+    let mut r = Resolver {
+        table: SymbolTable::default(),
+        scopes: vec![HashMap::new()],
+        function_scope: 0,
+        statement: 0,
+        defined_at: HashMap::new(),
+        visible_before: usize::MAX,
+        loop_depth: 0,
+        struct_fields: HashMap::new(),
+        enum_variants: HashMap::new(),
+        diagnostics: vec![],
+    };
+    for name in rho_core::builtin_names() {
+        let id = r.table.add(Symbol {
+            name: name.to_string(),
+            kind: SymbolKind::Builtin,
+            span: Span::default(),
+            symbol_type: None,
+            mutability: Mutability::Immutable,
+        });
+        r.table.globals.insert(name.to_string(), id);
+    }
+
+    // declarations are visible from anywhere in the file
+    for e in program.iter() {
+        r.declare_item(e);
+    }
+    for e in program.iter_mut() {
+        if let Expression::Function(decl) = e {
+            r.check_method_owner(decl);
+            let id = r.table.globals.get(&decl.name.path()).cloned();
+            decl.name.idents.last_mut().unwrap().symbol = id;
+        }
+    }
+    for (i, e) in program.iter_mut().enumerate() {
+        if !matches!(e, Expression::Function(_)) {
+            r.statement = i;
+            r.resolve(e);
+        }
+    }
+    // function bodies see the top level bindings defined before they are first called
+    let first_calls = first_calls(program);
+    for e in program.iter_mut() {
+        if let Expression::Function(decl) = e {
+            r.visible_before = first_calls[&decl.name.path()];
+            r.resolve_function(decl);
+        }
+    }
+
+    r.diagnostics
+        .sort_by_key(|d| (d.span.line, d.span.col, d.is_error()));
+    (r.table, r.diagnostics)
+}
+
+/* The top level statement each top level function is first called in, ie. the index of the first
+ * statement that calls it, or that calls a function calling it. Any call or mention of a
+ * function's name counts, and `T.show(x)` counts for every `show` method. Functions only `main`
+ * calls, or that nothing calls, are called after every statement.
+ */
+fn first_calls(program: &[Expression]) -> HashMap<String, usize> {
+    let functions: Vec<(String, &FunctionDecl)> = program
+        .iter()
+        .filter_map(|e| match e {
+            Expression::Function(decl) => Some((decl.name.path(), decl)),
+            _ => None,
+        })
+        .collect();
+    let callees = |e: &Expression| -> Vec<String> {
+        let mut names = vec![];
+        called_names(e, &mut names);
+        let calls = |name: &String, path: &String| match name.split_once('.') {
+            Some((_, method)) => path.split_once('.').is_some_and(|(_, m)| m == method),
+            None => name == path,
+        };
+        functions
+            .iter()
+            .filter(|(path, _)| names.iter().any(|name| calls(name, path)))
+            .map(|(path, _)| path.to_owned())
+            .collect()
+    };
+
+    let mut first: HashMap<String, usize> = functions
+        .iter()
+        .map(|(path, _)| (path.to_owned(), program.len()))
+        .collect();
+    for (i, e) in program.iter().enumerate() {
+        if !matches!(e, Expression::Function(_)) {
+            for callee in callees(e) {
+                first.insert(callee.to_owned(), first[&callee].min(i));
+            }
+        }
+    }
+    // a function is first called as early as the first of its callers is
+    let calls: Vec<(String, Vec<String>)> = functions
+        .iter()
+        .map(|(path, decl)| (path.to_owned(), callees(&decl.body)))
+        .collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (caller, callees) in calls.iter() {
+            for callee in callees.iter() {
+                if first[callee] > first[caller] {
+                    first.insert(callee.to_owned(), first[caller]);
+                    changed = true;
+                }
+            }
+        }
+    }
+    first
+}
+
+// The names `e` calls or mentions, that can be functions
+fn called_names(e: &Expression, names: &mut Vec<String>) {
+    match e {
+        Expression::FunctionCall { function_name, .. } => names.push(function_name.path()),
+        Expression::Identifier(ident) => names.push(ident.name.to_owned()),
+        _ => {}
+    }
+    for child in e.children() {
+        called_names(child, names);
+    }
+}
+
+// Levenshtein distance, for suggesting a name when one is misspelt
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for j in 0..b.len() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == b[j] {
+                previous
+            } else {
+                1 + previous.min(row[j]).min(row[j + 1])
+            };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+#[test]
+fn test_edit_distance() {
+    assert_eq!(edit_distance("print", "print"), 0);
+    assert_eq!(edit_distance("prnt", "print"), 1);
+    assert_eq!(edit_distance("Print", "IO.print"), 4);
+}
+
+impl Resolver {
+    fn declare_global(&mut self, name: &str, kind: SymbolKind, span: Span) {
+        if let Some(existing) = self.table.globals.get(name) {
+            let first = self.table.get(*existing).span;
+            let mut d = Diagnostic::error(format!("`{}` is defined more than once", name), span);
+            if first != Span::default() {
+                d = d.with_note(format!("`{}` is first defined here", name), first);
+            }
+            self.diagnostics.push(d);
+            return;
+        }
+        let id = self.table.add(Symbol {
+            name: name.to_string(),
+            kind,
+            span,
+            symbol_type: None,
+            mutability: Mutability::Immutable,
+        });
+        self.table.globals.insert(name.to_string(), id);
+    }
+
+    fn declare_item(&mut self, e: &Expression) {
+        match e {
+            Expression::Function(decl) => {
+                let span = decl.name.idents.last().map(|i| i.span).unwrap_or(decl.span);
+                self.declare_global(&decl.name.path(), SymbolKind::Function, span);
+            }
+            Expression::Struct(decl) => {
+                self.declare_global(&decl.name.name, SymbolKind::Struct, decl.name.span);
+                self.struct_fields.insert(
+                    decl.name.name.to_owned(),
+                    decl.fields.iter().map(|(_, f)| f.name.to_owned()).collect(),
+                );
+            }
+            Expression::Enum(decl) => {
+                self.declare_global(&decl.name.name, SymbolKind::Enum, decl.name.span);
+                self.enum_variants.insert(
+                    decl.name.name.to_owned(),
+                    decl.variants
+                        .iter()
+                        .map(|v| v.name.name.to_owned())
+                        .collect(),
+                );
+            }
+            Expression::Interface(decl) => {
+                self.declare_global(&decl.name.name, SymbolKind::Interface, decl.name.span);
+            }
+            _ => {}
+        }
+    }
+
+    // `func Point.show(..)` needs a type named Point
+    fn check_method_owner(&mut self, decl: &FunctionDecl) {
+        if decl.name.idents.len() < 2 {
+            return;
+        }
+        let owner = &decl.name.idents[0];
+        let is_type = matches!(
+            self.table.global(&owner.name).map(|s| s.kind),
+            Some(SymbolKind::Struct) | Some(SymbolKind::Enum)
+        ) || tokenize_type(&owner.name).is_ok_and(|(s, _)| s == owner.name);
+        if !is_type {
+            self.diagnostics.push(Diagnostic::error(
+                format!("undefined type `{}`", owner.name),
+                owner.span,
+            ));
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<SymbolId> {
+        self.lookup_binding(name)
+            .or_else(|| self.table.globals.get(name).cloned())
+    }
+
+    // The binding `name` is, leaving out the top level ones that aren't defined yet
+    fn lookup_binding(&self, name: &str) -> Option<SymbolId> {
+        self.scopes
+            .iter()
+            .rev()
+            .filter_map(|s| s.get(name).cloned())
+            .find(|id| {
+                self.defined_at
+                    .get(id)
+                    .is_none_or(|at| *at < self.visible_before)
+            })
+    }
+
+    // A top level binding named `name`, when there's one that isn't defined yet
+    fn defined_later(&self, name: &str) -> Option<SymbolId> {
+        match self.scopes[0].get(name) {
+            Some(id) if self.lookup_binding(name).is_none() => Some(*id),
+            _ => None,
+        }
+    }
+
+    fn declare(&mut self, ident: &mut Ident, kind: SymbolKind, symbol_type: Option<Type>) {
+        if ident.name == "_" {
+            return;
+        }
+        let scope = self.scopes.last().unwrap();
+        if let Some(existing) = scope.get(&ident.name) {
+            let first = self.table.get(*existing).span;
+            self.diagnostics.push(
+                Diagnostic::error(
+                    format!("`{}` is defined more than once in this scope", ident.name),
+                    ident.span,
+                )
+                .with_note(format!("`{}` is first defined here", ident.name), first),
+            );
+        } else if let Some(outer) = self.scopes[self.function_scope..]
+            .iter()
+            .rev()
+            .find_map(|s| s.get(&ident.name))
+        {
+            let first = self.table.get(*outer).span;
+            self.diagnostics.push(
+                Diagnostic::warning(
+                    format!("`{}` shadows an earlier binding", ident.name),
+                    ident.span,
+                )
+                .with_note(
+                    format!("the shadowed `{}` is defined here", ident.name),
+                    first,
+                ),
+            );
+        }
+        let id = self.table.add(Symbol {
+            name: ident.name.to_owned(),
+            kind,
+            span: ident.span,
+            symbol_type,
+            mutability: Mutability::Immutable,
+        });
+        if self.scopes.len() == 1 {
+            self.defined_at.insert(id, self.statement);
+        }
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(ident.name.to_owned(), id);
+        ident.symbol = Some(id);
+    }
+
+    fn undefined(&mut self, what: &str, name: &str, span: Span) {
+        let mut candidates: Vec<&String> = self.table.globals.keys().collect();
+        for s in self.scopes.iter() {
+            candidates.extend(s.keys());
+        }
+        let best = candidates
+            .into_iter()
+            .map(|c| (edit_distance(name, c), c))
+            .filter(|(d, c)| *d <= (name.len() / 3).max(1) && *d < name.len() && *c != name)
+            .min();
+        let mut message = format!("undefined {} `{}`", what, name);
+        if let Some((_, suggestion)) = best {
+            message += &format!(", did you mean `{}`?", suggestion);
+        }
+        self.diagnostics.push(Diagnostic::error(message, span));
+    }
+
+    fn resolve_type(&mut self, t: &Type, span: Span) {
+        match t {
+            Type::Named(name, args) => {
+                let known = name == "Self"
+                    || matches!(
+                        self.lookup(name).map(|id| self.table.get(id).kind),
+                        Some(SymbolKind::Struct)
+                            | Some(SymbolKind::Enum)
+                            | Some(SymbolKind::Interface)
+                            | Some(SymbolKind::TypeParam)
+                    );
+                if !known {
+                    self.undefined("type", name, span);
+                }
+                for a in args.iter() {
+                    self.resolve_type(a, span);
+                }
+            }
+            Type::BuiltIn(_, args) => {
+                for a in args.iter() {
+                    self.resolve_type(a, span);
+                }
+            }
+            Type::Function(params, ret) => {
+                for p in params.iter() {
+                    self.resolve_type(p, span);
+                }
+                self.resolve_type(ret, span);
+            }
+            _ => {}
+        }
+    }
+
+    fn resolve_function(&mut self, decl: &mut FunctionDecl) {
+        let outer_function_scope = self.function_scope;
+        let outer_loop_depth = self.loop_depth;
+        self.scopes.push(HashMap::new());
+        self.function_scope = self.scopes.len() - 1;
+        self.loop_depth = 0;
+        for p in decl.type_params.iter() {
+            let mut ident = Ident::new(&p.name, decl.span);
+            self.declare(&mut ident, SymbolKind::TypeParam, None);
+        }
+        for p in decl.type_params.iter() {
+            for b in p.bounds.iter() {
+                self.resolve_type(b, decl.span);
+            }
+        }
+        for p in decl.params.iter_mut() {
+            if let Some(t) = &p.param_type {
+                self.resolve_type(t, p.name.span);
+            }
+            self.declare(&mut p.name, SymbolKind::Parameter, p.param_type.clone());
+        }
+        if let Some(t) = &decl.return_type {
+            self.resolve_type(t, decl.span);
+        }
+        self.resolve(&mut decl.body);
+        self.scopes.pop();
+        self.function_scope = outer_function_scope;
+        self.loop_depth = outer_loop_depth;
+    }
+
+    fn resolve_generic_decl(&mut self, type_params: &[TypeParam], span: Span, types: Vec<Type>) {
+        self.scopes.push(HashMap::new());
+        for p in type_params.iter() {
+            let mut ident = Ident::new(&p.name, span);
+            self.declare(&mut ident, SymbolKind::TypeParam, None);
+        }
+        for t in types.iter() {
+            self.resolve_type(t, span);
+        }
+        self.scopes.pop();
+    }
+
+    fn resolve_call(&mut self, function_name: &mut FunctionName) {
+        let path = function_name.path();
+        let first = function_name.idents[0].name.to_owned();
+
+        // calling a closure held by a variable, ie. `l(1)`
+        if let Some(id) = self.lookup_binding(&first) {
+            function_name.idents[0].symbol = Some(id);
+            return;
+        }
+        if let Some(id) = self.table.globals.get(&path) {
+            function_name.idents.last_mut().unwrap().symbol = Some(*id);
+            return;
+        }
+        if function_name.idents.len() >= 2 {
+            if let Some(id) = self.table.globals.get(&first).cloned() {
+                function_name.idents[0].symbol = Some(id);
+                if self.table.get(id).kind == SymbolKind::Enum {
+                    let variant = function_name.idents[1].clone();
+                    self.check_variant(&first, &variant);
+                    return;
+                }
+                self.undefined("function", &path, function_name.idents[1].span);
+                return;
+            }
+            let is_module = rho_core::builtin_names()
+                .iter()
+                .any(|b| b.starts_with(&format!("{}.", first)));
+            if is_module {
+                self.undefined("function", &path, function_name.idents[1].span);
+                return;
+            }
+        }
+        self.undefined("function", &path, function_name.span());
+    }
+
+    fn check_variant(&mut self, enum_name: &str, variant: &Ident) {
+        let known = self
+            .enum_variants
+            .get(enum_name)
+            .is_some_and(|vs| vs.contains(&variant.name));
+        if !known {
+            self.diagnostics.push(Diagnostic::error(
+                format!("enum `{}` has no variant `{}`", enum_name, variant.name),
+                variant.span,
+            ));
+        }
+    }
+
+    fn resolve_pattern(&mut self, pattern: &mut Pattern) {
+        match pattern {
+            Pattern::Binding(ident) => self.declare(ident, SymbolKind::Variable, None),
+            Pattern::Tuple(elements, _) | Pattern::List(elements, _) => {
+                for p in elements.iter_mut() {
+                    self.resolve_pattern(p);
+                }
+            }
+            Pattern::Variant(name, fields, _) => {
+                let enum_name = name.idents[0].name.to_owned();
+                match self.table.globals.get(&enum_name).cloned() {
+                    Some(id) if self.table.get(id).kind == SymbolKind::Enum => {
+                        name.idents[0].symbol = Some(id);
+                        if let Some(variant) = name.idents.get(1).cloned() {
+                            self.check_variant(&enum_name, &variant);
+                        }
+                    }
+                    _ => self.undefined("enum", &enum_name, name.idents[0].span),
+                }
+                for p in fields.iter_mut() {
+                    self.resolve_pattern(p);
+                }
+            }
+            Pattern::Wildcard(_) | Pattern::Literal(_, _) => {}
+        }
+    }
+
+    fn resolve_block(&mut self, expressions: &mut [Expression]) {
+        self.scopes.push(HashMap::new());
+        // nested functions can be called before they are defined in their block
+        for e in expressions.iter_mut() {
+            if let Expression::Function(decl) = e {
+                if decl.name.idents.len() == 1 {
+                    let mut ident = decl.name.idents[0].clone();
+                    self.declare(&mut ident, SymbolKind::Function, None);
+                    decl.name.idents[0].symbol = ident.symbol;
+                }
+            }
+        }
+        for e in expressions.iter_mut() {
+            self.resolve(e);
+        }
+        self.scopes.pop();
+    }
+
+    fn resolve(&mut self, expression: &mut Expression) {
+        match expression {
+            Expression::Definition {
+                mutability,
+                definition_type,
+                identifier,
+                value,
+                ..
+            } => {
+                self.resolve(value);
+                if definition_type.is_some() || *mutability != Mutability::Immutable {
+                    if let Some(t) = definition_type {
+                        self.resolve_type(t, identifier.span);
+                    }
+                    self.declare(identifier, SymbolKind::Variable, definition_type.clone());
+                    if let Some(id) = identifier.symbol {
+                        self.table.symbols[id].mutability = *mutability;
+                    }
+                    return;
+                }
+                // `x = ..` assigns to an existing binding, or declares a new one
+                match self.lookup(&identifier.name) {
+                    Some(id)
+                        if matches!(
+                            self.table.get(id).kind,
+                            SymbolKind::Variable | SymbolKind::Parameter
+                        ) =>
+                    {
+                        identifier.symbol = Some(id);
+                    }
+                    Some(id)
+                        if self.scopes.last().unwrap().get(&identifier.name).is_none()
+                            && self.table.get(id).kind != SymbolKind::Builtin =>
+                    {
+                        let first = self.table.get(id).span;
+                        self.diagnostics.push(
+                            Diagnostic::error(
+                                format!(
+                                    "cannot assign to `{}`, it is not a variable",
+                                    identifier.name
+                                ),
+                                identifier.span,
+                            )
+                            .with_note(format!("`{}` is defined here", identifier.name), first),
+                        );
+                    }
+                    _ => self.declare(identifier, SymbolKind::Variable, None),
+                }
+            }
+            Expression::Identifier(ident) => match self.lookup(&ident.name) {
+                Some(id) => ident.symbol = Some(id),
+                None => match self.defined_later(&ident.name) {
+                    Some(id) => {
+                        let defined = self.table.get(id).span;
+                        self.diagnostics.push(
+                            Diagnostic::error(
+                                format!("`{}` is used before it is defined", ident.name),
+                                ident.span,
+                            )
+                            .with_note(format!("`{}` is defined here", ident.name), defined),
+                        );
+                    }
+                    None => self.undefined("name", &ident.name.clone(), ident.span),
+                },
+            },
+            Expression::FunctionCall {
+                function_name,
+                type_args,
+                parameters,
+                span,
+            } => {
+                for t in type_args.iter() {
+                    self.resolve_type(t, *span);
+                }
+                self.resolve_call(function_name);
+                for p in parameters.iter_mut() {
+                    self.resolve(p);
+                }
+            }
+            Expression::FieldAccess { object, field, .. } => {
+                if let Expression::Identifier(ident) = object.as_mut() {
+                    if let Some(id) = self.lookup(&ident.name) {
+                        if self.table.get(id).kind == SymbolKind::Enum {
+                            ident.symbol = Some(id);
+                            let enum_name = ident.name.to_owned();
+                            self.check_variant(&enum_name, field);
+                            return;
+                        }
+                    }
+                }
+                self.resolve(object);
+            }
+            Expression::StructLiteral {
+                name,
+                type_args,
+                fields,
+                span,
+            } => {
+                for t in type_args.iter() {
+                    self.resolve_type(t, *span);
+                }
+                match self.table.globals.get(&name.name).cloned() {
+                    Some(id) if self.table.get(id).kind == SymbolKind::Struct => {
+                        name.symbol = Some(id);
+                        let declared = self
+                            .struct_fields
+                            .get(&name.name)
+                            .cloned()
+                            .unwrap_or_default();
+                        for (field, _) in fields.iter() {
+                            if !declared.contains(&field.name) {
+                                self.diagnostics.push(Diagnostic::error(
+                                    format!("struct `{}` has no field `{}`", name.name, field.name),
+                                    field.span,
+                                ));
+                            }
+                        }
+                        for d in declared.iter() {
+                            if !fields.iter().any(|(f, _)| f.name == *d) {
+                                self.diagnostics.push(Diagnostic::error(
+                                    format!("missing field `{}` in `{}`", d, name.name),
+                                    name.span,
+                                ));
+                            }
+                        }
+                    }
+                    _ => self.undefined("struct", &name.name.clone(), name.span),
+                }
+                for (_, value) in fields.iter_mut() {
+                    self.resolve(value);
+                }
+            }
+            Expression::Lambda { params, body, .. } => {
+                let outer_loop_depth = self.loop_depth;
+                self.loop_depth = 0;
+                self.scopes.push(HashMap::new());
+                for p in params.iter_mut() {
+                    if let Some(t) = &p.param_type {
+                        self.resolve_type(t, p.name.span);
+                    }
+                    self.declare(&mut p.name, SymbolKind::Parameter, p.param_type.clone());
+                }
+                self.resolve(body);
+                self.scopes.pop();
+                self.loop_depth = outer_loop_depth;
+            }
+            Expression::Block { expressions, .. } => self.resolve_block(expressions),
+            Expression::While {
+                condition, body, ..
+            } => {
+                self.resolve(condition);
+                self.loop_depth += 1;
+                self.resolve(body);
+                self.loop_depth -= 1;
+            }
+            Expression::For {
+                variable,
+                iterable,
+                body,
+                ..
+            } => {
+                self.resolve(iterable);
+                self.scopes.push(HashMap::new());
+                self.declare(variable, SymbolKind::Variable, None);
+                self.loop_depth += 1;
+                self.resolve(body);
+                self.loop_depth -= 1;
+                self.scopes.pop();
+            }
+            Expression::Match { subject, arms, .. } => {
+                self.resolve(subject);
+                for arm in arms.iter_mut() {
+                    self.scopes.push(HashMap::new());
+                    self.resolve_pattern(&mut arm.pattern);
+                    if let Some(g) = &mut arm.guard {
+                        self.resolve(g);
+                    }
+                    self.resolve(&mut arm.body);
+                    self.scopes.pop();
+                }
+            }
+            Expression::Break { span } | Expression::Continue { span } => {
+                if self.loop_depth == 0 {
+                    self.diagnostics.push(Diagnostic::error(
+                        "`break` and `continue` can only be used inside a loop",
+                        *span,
+                    ));
+                }
+            }
+            Expression::Function(decl) => {
+                // a nested function, it was declared when its block was entered
+                self.resolve_function(decl);
+            }
+            Expression::Struct(decl) => {
+                let types = decl.fields.iter().map(|(t, _)| t.clone()).collect();
+                self.resolve_generic_decl(&decl.type_params.clone(), decl.span, types);
+            }
+            Expression::Enum(decl) => {
+                let types = decl
+                    .variants
+                    .iter()
+                    .flat_map(|v| v.fields.iter().cloned())
+                    .collect();
+                self.resolve_generic_decl(&decl.type_params.clone(), decl.span, types);
+            }
+            Expression::Interface(decl) => {
+                let types = decl
+                    .methods
+                    .iter()
+                    .flat_map(|m| {
+                        m.params
+                            .iter()
+                            .filter_map(|p| p.param_type.clone())
+                            .chain(m.return_type.clone())
+                    })
+                    .collect();
+                self.resolve_generic_decl(&decl.type_params.clone(), decl.span, types);
+            }
+            Expression::Import { .. } | Expression::Literal { .. } => {}
+            other => {
+                for child in other.children_mut() {
+                    self.resolve(child);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn resolve_source(source: &str) -> (Vec<Expression>, SymbolTable, Vec<Diagnostic>) {
+    let mut program = parse(source).unwrap();
+    let (table, diagnostics) = resolve(&mut program);
+    (program, table, diagnostics)
+}
+
+#[test]
+fn test_resolve_hello() {
+    let source = std::fs::read_to_string("./rho_testfiles/hello_var.rho").unwrap();
+    let (program, table, diagnostics) = resolve_source(&source);
+    assert!(diagnostics.is_empty());
+    // `s = "you"` assigns to the `var str s` above it
+    let Expression::Definition { identifier, .. } = &program[1] else {
+        panic!("expected a definition");
+    };
+    assert!(!table.declares(identifier));
+    // IO.puts resolves to the builtin
+    let Expression::FunctionCall { function_name, .. } = &program[3] else {
+        panic!("expected a call");
+    };
+    let id = function_name.idents[1].symbol.unwrap();
+    assert_eq!(table.get(id).kind, SymbolKind::Builtin);
+
+    // a typed definition always declares, so `str s` can't be defined twice in one scope
+    let (_, _, diagnostics) = resolve_source("str s = \"a\"\nstr s = \"b\"\n");
+    assert_eq!(diagnostics.len(), 1);
+    assert!(diagnostics[0]
+        .message
+        .contains("`s` is defined more than once"));
+    assert_eq!(diagnostics[0].notes[0].1.line, 1);
+}
+
+#[test]
+fn test_resolve_undefined_and_shadowing() {
+    let (_, _, diagnostics) = resolve_source(
+        "int x = 1
+func f(int y) -> int {
+    if y > 0 {
+        int y = 2
+        return y + z
+    }
+    return IO.prnt(y)
+}
+",
+    );
+    let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "warning: `y` shadows an earlier binding at 4:13",
+            "error: undefined name `z` at 5:20",
+            "error: undefined function `IO.prnt`, did you mean `IO.print`? at 7:15",
+        ]
+    );
+}
+
+#[test]
+fn test_resolve_symbols() {
+    let (program, table, diagnostics) = resolve_source(
+        "var int x = 1
+x = x + 1
+l = fn a -> a + x
+b = l(2)
+",
+    );
+    assert!(diagnostics.is_empty());
+    let Expression::Definition { identifier, .. } = &program[1] else {
+        panic!("expected a definition");
+    };
+    // `x = x + 1` assigns to the x declared on the first line
+    assert!(!table.declares(identifier));
+    assert_eq!(table.get(identifier.symbol.unwrap()).span.line, 1);
+}
+
+#[test]
+fn test_resolve_globals_in_order() {
+    let messages = |source: &str| -> Vec<String> {
+        let diagnostics = resolve_source(source).2;
+        diagnostics.iter().map(|d| d.to_string()).collect()
+    };
+    // a function sees the top level bindings defined before it's first called
+    assert_eq!(
+        messages(
+            "func show() -> int {\n    return later\n}\nfunc outer() -> int {\n    return show()\n}\nouter()\nvar later = 2\nshow()\n"
+        ),
+        ["error: `later` is used before it is defined at 2:12"]
+    );
+    assert!(
+        messages("func show() -> int {\n    return later\n}\nvar later = 2\nshow()\n").is_empty()
+    );
+    assert!(messages("func main() -> int {\n    return later\n}\nlater = 2\n").is_empty());
+    assert!(messages("func f() -> int {\n    return T.g()\n}\nf()\nx = 1\nfunc int.g() -> int {\n    return x\n}\n")
+        .contains(&"error: `x` is used before it is defined at 7:12".to_string()));
+
+    // assigning to one that isn't defined yet makes a local, rather than assigning to it
+    let (program, table, diagnostics) =
+        resolve_source("func f() -> int {\n    x = 1\n    return x\n}\nf()\nx = 2\n");
+    assert!(diagnostics.is_empty());
+    let Expression::Function(decl) = &program[0] else {
+        panic!("expected a function");
+    };
+    let Expression::Block { expressions, .. } = decl.body.as_ref() else {
+        panic!("expected a block");
+    };
+    let Expression::Definition { identifier, .. } = &expressions[0] else {
+        panic!("expected a definition");
+    };
+    assert!(table.declares(identifier));
+}
+
+#[test]
+fn test_resolve_testfiles() {
+    let source = std::fs::read_to_string("./rho_testfiles/generics.rho").unwrap();
+    assert!(resolve_source(&source).2.is_empty());
+
+    let source = std::fs::read_to_string("./rho_testfiles/scratch_typed.rho").unwrap();
+    let messages: Vec<String> = resolve_source(&source)
+        .2
+        .iter()
+        .map(|d| d.to_string())
+        .collect();
+    assert_eq!(
+        messages,
+        vec!["warning: `i` shadows an earlier binding at 31:5"]
+    );
+
+    /* The files from before there was a resolver don't compile anymore: hello.rho defines the
+     * immutable `str s` three times in one scope, and scratch.rho pipes into a `Print` there never
+     * was, after which its `while true` would never end.
+     */
+    let messages = |file: &str| -> Vec<String> {
+        let source = std::fs::read_to_string(file).unwrap();
+        let diagnostics = resolve_source(&source).2;
+        diagnostics.iter().map(|d| d.to_string()).collect()
+    };
+    assert_eq!(
+        messages("./rho_testfiles/hello.rho"),
+        [
+            "error: `s` is defined more than once in this scope at 3:5",
+            "error: `s` is defined more than once in this scope at 4:5"
+        ]
+    );
+    assert_eq!(
+        messages("./rho_testfiles/scratch.rho"),
+        [
+            "error: undefined function `Print` at 19:6",
+            "warning: `i` shadows an earlier binding at 31:5"
+        ]
+    );
+}
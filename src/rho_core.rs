@@ -1,26 +1,442 @@
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+use std::sync::{OnceLock, RwLock};
+
+use crate::atoms;
+use crate::collections;
+use crate::diagnostics::Diagnostic;
+use crate::generics::generic_name;
+use crate::maps;
+use crate::streams;
+use crate::strings;
 use crate::tokens::*;
+use crate::types::Type;
+use crate::value::Value;
+use rho_runtime::text::{escape, inspect_float};
+
+/* What a native function can use besides its arguments, the program's stdin and stdout and a way
+ * to call back into rho, ie. `List.map` calling the function it was given.
+ */
+pub trait Context {
+    fn input(&mut self) -> &mut dyn BufRead;
+    fn output(&mut self) -> &mut dyn Write;
+    fn call(&mut self, function: &Value, args: Vec<Value>) -> Result<Value, Diagnostic>;
+}
+
+// The outcome of a native call, the interpreter reports an `Err` as a runtime error at the call
+#[derive(Debug, PartialEq)]
+pub enum CallResult {
+    Ok(Value),
+    Err(String),
+    Raised(Diagnostic), // an error in a rho function the native called, reported where it happened
+}
+
+pub type NativeFn = fn(&mut dyn Context, Vec<Value>) -> CallResult;
+
+/* A native function rho programs can call by its dotted name, ie. `IO.puts`. The resolver
+ * declares it, the checker checks calls against its signature and the interpreter calls
+ * `function` with the evaluated arguments, which always match the signature.
+ */
+#[derive(Clone)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub type_params: Vec<&'static str>,
+    pub params: Vec<Type>,
+    pub return_type: Type,
+    pub function: NativeFn,
+}
+
+impl Builtin {
+    pub fn new(
+        name: &'static str,
+        type_params: &[&'static str],
+        params: Vec<Type>,
+        return_type: Type,
+        function: NativeFn,
+    ) -> Builtin {
+        Builtin {
+            name,
+            type_params: type_params.to_vec(),
+            params,
+            return_type,
+            function,
+        }
+    }
+}
+
+// The native functions every rho program can call, in the order the resolver declares them
+fn core_builtins() -> Vec<Builtin> {
+    let t = || Type::Param("T".to_string());
+    vec![
+        Builtin::new("IO.print", &["T"], vec![t()], Type::Unit, io_print),
+        Builtin::new("IO.puts", &["T"], vec![t()], Type::Unit, io_puts),
+        Builtin::new("IO.inspect", &["T"], vec![t()], t(), io_inspect),
+        Builtin::new("IO.read_line", &[], vec![], Type::string(), io_read_line),
+        Builtin::new(
+            "atom_to_string",
+            &[],
+            vec![Type::atom()],
+            Type::string(),
+            atom_to_string,
+        ),
+        Builtin::new(
+            "string_to_atom",
+            &[],
+            vec![Type::string()],
+            Type::atom(),
+            string_to_atom,
+        ),
+        // conversions, ie. `float(i)`, the checker only allows the ones `convert` can do
+        Builtin::new("int", &["T"], vec![t()], Type::int(), |_, args| {
+            convert("int", args)
+        }),
+        Builtin::new("float", &["T"], vec![t()], Type::float(), |_, args| {
+            convert("float", args)
+        }),
+        Builtin::new("str", &["T"], vec![t()], Type::string(), |_, args| {
+            convert("str", args)
+        }),
+        Builtin::new("bool", &["T"], vec![t()], Type::bool(), |_, args| {
+            convert("bool", args)
+        }),
+        Builtin::new("char", &["T"], vec![t()], Type::char(), |_, args| {
+            convert("char", args)
+        }),
+    ]
+    .into_iter()
+    .chain(strings::builtins())
+    .chain(collections::builtins())
+    .chain(maps::builtins())
+    .chain(streams::builtins())
+    .collect()
+}
+
+// A failed native call, already in the form the interpreter reports
+pub type Outcome<T> = Result<T, CallResult>;
+
+// Runs the body of a native written with `?`
+pub fn run(f: impl FnOnce() -> Outcome<Value>) -> CallResult {
+    match f() {
+        Ok(v) => CallResult::Ok(v),
+        Err(e) => e,
+    }
+}
 
+pub fn arguments<const N: usize>(args: Vec<Value>) -> Outcome<[Value; N]> {
+    let given = args.len();
+    args.try_into()
+        .map_err(|_| CallResult::Err(format!("expected {} arguments but {} were given", N, given)))
+}
 
-enum CallResult {
-    Ok(Vec<Types>), // <- returns the function's return types
-    Err
+// Calls a rho function from a native, its errors are passed on as they are
+pub fn apply(context: &mut dyn Context, f: &Value, args: Vec<Value>) -> Outcome<Value> {
+    context.call(f, args).map_err(CallResult::Raised)
+}
+
+pub fn test(context: &mut dyn Context, predicate: &Value, v: Value) -> Outcome<bool> {
+    match apply(context, predicate, vec![v])? {
+        Value::Bool(b) => Ok(b),
+        v => Err(CallResult::Err(format!(
+            "expected the function to return a bool, found `{}`",
+            v
+        ))),
+    }
+}
+
+pub fn int(v: &Value) -> Outcome<i64> {
+    match v {
+        Value::Int(i) => Ok(*i),
+        v => Err(CallResult::Err(format!("expected an int, found `{}`", v))),
+    }
+}
+
+fn registry() -> &'static RwLock<Vec<Builtin>> {
+    static REGISTRY: OnceLock<RwLock<Vec<Builtin>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(core_builtins()))
+}
+
+/* Makes a native function callable from rho, replacing any builtin with the same name. Programs
+ * resolved after this call can use it, ie.
+ *
+ *   rho_core::register(Builtin::new("Math.double", &[], vec![Type::int()], Type::int(), double));
+ */
+pub fn register(builtin: Builtin) {
+    let mut builtins = registry().write().unwrap_or_else(|e| e.into_inner());
+    match builtins.iter_mut().find(|b| b.name == builtin.name) {
+        Some(existing) => *existing = builtin,
+        None => builtins.push(builtin),
+    }
+}
+
+pub fn builtin(name: &str) -> Option<Builtin> {
+    let builtins = registry().read().unwrap_or_else(|e| e.into_inner());
+    builtins.iter().find(|b| b.name == name).cloned()
+}
+
+pub fn builtin_names() -> Vec<&'static str> {
+    let builtins = registry().read().unwrap_or_else(|e| e.into_inner());
+    builtins.iter().map(|b| b.name).collect()
+}
+
+fn first(args: Vec<Value>) -> Value {
+    args.into_iter().next().unwrap_or(Value::Unit)
+}
+
+fn written(result: std::io::Result<()>) -> CallResult {
+    match result {
+        Ok(()) => CallResult::Ok(Value::Unit),
+        Err(e) => CallResult::Err(e.to_string()),
+    }
 }
 
 // mod IO {
-fn inspect(token: Token) -> Result<String, String> {
-    match token.token_type {
-        TokenType::Type(Types::Primitive(PrimitiveType::Int)) => todo!(),
-        TokenType::Type(_) => todo!(),
-        TokenType::Literal(_) => todo!(),
-        TokenType::Keyword(_) => todo!(),
-        TokenType::Operator(_) => todo!(),
-        TokenType::Delimiter(_) => todo!(),
-        TokenType::EoF => todo!(),
-        TokenType::Identifier => todo!(),
-        TokenType::Comment => todo!(),
-        TokenType::NewLine => todo!(),
-    }
-
-    // Err("Not implemented".to_string())
+fn io_print(context: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    written(write!(context.output(), "{}", first(args)))
+}
+
+fn io_puts(context: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    written(writeln!(context.output(), "{}", first(args)))
+}
+
+// Writes the value as rho syntax and returns it, so it can be put in the middle of a pipeline
+fn io_inspect(context: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    let value = first(args);
+    match written(writeln!(context.output(), "{}", inspect(&value))) {
+        CallResult::Ok(_) => CallResult::Ok(value),
+        err => err,
+    }
+}
+
+// A line of stdin without its line ending, prompts written with `IO.print` are flushed first
+fn io_read_line(context: &mut dyn Context, _: Vec<Value>) -> CallResult {
+    let _ = context.output().flush();
+    let mut line = String::new();
+    if let Err(e) = context.input().read_line(&mut line) {
+        return CallResult::Err(e.to_string());
+    }
+    let line = line.strip_suffix('\n').unwrap_or(&line);
+    CallResult::Ok(Value::string(line.strip_suffix('\r').unwrap_or(line)))
+}
+
+/* Renders a value in rho syntax, reading the rendering back gives an equal value ie. a string is
+ * quoted and escaped and a float always has a decimal point. Functions and streams can't be
+ * written as literals, they are rendered as `#fn<name/arity>` and `#stream`.
+ */
+pub fn inspect(value: &Value) -> String {
+    match value {
+        Value::Unit => "unit".to_string(),
+        Value::Int(i) => i.to_string(),
+        Value::Float(f) => inspect_float(*f),
+        Value::Bool(b) => b.to_string(),
+        Value::Char(c) => format!("'{}'", escape(&c.to_string(), '\'')),
+        Value::Atom(a) => format!(":{}", a),
+        Value::String(s) => format!("\"{}\"", escape(s, '"')),
+        Value::List(elements) => format!("[{}]", inspect_all(elements)),
+        Value::Tuple(elements) => format!("{{{}}}", inspect_all(elements)),
+        Value::Map(map) => {
+            let entries: Vec<String> = map
+                .iter()
+                .map(|(k, v)| format!("{}: {}", inspect(k), inspect(v)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Value::Range(start, end, 1) => format!("{}..{}", start, end),
+        Value::Range(start, end, step) => format!("{}..{}..{}", start, end, step),
+        Value::Struct(name, fields) => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(k, v)| format!("{}: {}", k, inspect(v)))
+                .collect();
+            format!("{}{{{}}}", generic_name(name), fields.join(", "))
+        }
+        Value::Variant(name, variant, values) if values.is_empty() => {
+            format!("{}.{}", generic_name(name), variant)
+        }
+        Value::Variant(name, variant, values) => {
+            format!(
+                "{}.{}({})",
+                generic_name(name),
+                variant,
+                inspect_all(values)
+            )
+        }
+        Value::Closure(c) => format!("#fn<{}/{}>", c.name, c.params.len()),
+        Value::Compiled(c) => format!("#fn<{}/{}>", c.function.name, c.function.arity),
+        Value::Builtin(name) => format!("#fn<{}>", name),
+        Value::Stream(_) => "#stream".to_string(),
+    }
 }
 // }
+
+fn inspect_all(values: &[Value]) -> String {
+    values
+        .iter()
+        .map(inspect)
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn atom_to_string(_: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    match first(args) {
+        Value::Atom(a) => CallResult::Ok(Value::string(&atoms::atom_to_string(a))),
+        v => CallResult::Err(format!("`atom_to_string` takes an atom, not `{}`", v)),
+    }
+}
+
+fn string_to_atom(_: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    match first(args) {
+        Value::String(s) => CallResult::Ok(Value::Atom(atoms::string_to_atom(&s))),
+        v => CallResult::Err(format!("`string_to_atom` takes a str, not `{}`", v)),
+    }
+}
+
+fn convert(to: &str, args: Vec<Value>) -> CallResult {
+    let v = first(args);
+    let converted = match (to, &v) {
+        ("str", _) => Some(Value::string(&v.to_string())),
+        ("int", Value::Int(i)) => Some(Value::Int(*i)),
+        ("int", Value::Float(f)) => match float_to_int(*f) {
+            Ok(i) => Some(Value::Int(i)),
+            Err(message) => return CallResult::Err(message.to_string()),
+        },
+        ("int", Value::Char(c)) => Some(Value::Int(*c as i64)),
+        ("int", Value::Bool(b)) => Some(Value::Int(*b as i64)),
+        ("float", Value::Int(i)) => Some(Value::Float(*i as f64)),
+        ("float", Value::Float(f)) => Some(Value::Float(*f)),
+        ("bool", Value::Bool(b)) => Some(Value::Bool(*b)),
+        ("char", Value::Char(c)) => Some(Value::Char(*c)),
+        ("char", Value::Int(i)) => u32::try_from(*i)
+            .ok()
+            .and_then(char::from_u32)
+            .map(Value::Char),
+        _ => None,
+    };
+    match converted {
+        Some(v) => CallResult::Ok(v),
+        None => CallResult::Err(format!("`{}` can't be converted to `{}`", v, to)),
+    }
+}
+
+/* A float truncated towards zero, which fails like int arithmetic does when there's no int for it
+ * rather than saturating. The native backend checks for the same two cases.
+ */
+pub fn float_to_int(f: f64) -> Result<i64, &'static str> {
+    if f.is_nan() {
+        return Err("NaN can't be converted to `int`");
+    }
+    // -2^63 is i64::MIN, and 2^63 the first float past i64::MAX
+    let t = f.trunc();
+    if !(-9223372036854775808.0..9223372036854775808.0).contains(&t) {
+        return Err("float out of range for `int`");
+    }
+    Ok(t as i64)
+}
+
+#[test]
+fn test_inspect() {
+    let point = Value::Struct(
+        Rc::from("Point"),
+        Rc::new(vec![
+            ("x".to_string(), Value::Int(1)),
+            ("y".to_string(), Value::Int(-2)),
+        ]),
+    );
+    assert_eq!(inspect(&point), "Point{x: 1, y: -2}");
+    assert_eq!(inspect(&Value::string("a \"b\"\n")), "\"a \\\"b\\\"\\n\"");
+    assert_eq!(inspect(&Value::Char('\'')), "'\\''");
+    assert_eq!(inspect(&Value::Float(1e20)), "100000000000000000000.0");
+    assert_eq!(inspect(&Value::Float(-0.5)), "-0.5");
+    assert_eq!(inspect(&Value::Range(10, 0, -2)), "10..0..-2");
+    let some = Value::Variant(Rc::from("Option"), Rc::from("Some"), Rc::new(vec![point]));
+    assert_eq!(inspect(&some), "Option.Some(Point{x: 1, y: -2})");
+}
+
+#[test]
+fn test_inspect_round_trips() {
+    // every rendering is read back by a program that inspects it again
+    let source = "enum Option {
+    Some(int),
+    None
+}
+struct Point {
+    int x
+    int y
+}
+IO.inspect({1, -2.5, 'q', '\\n', :ok, \"tab\\there \\\"quoted\\\" \\\\\"})
+IO.inspect({[1.0, 0.1, 1000000.0], {\"a\": [Option.Some(3), Option.None]}})
+IO.inspect({Point{y: 2, x: 1}, 0..10, -1..-10..-3, true, {}})
+";
+    let (result, output) = crate::interpreter::run_source(source, "");
+    assert_eq!(result, Ok(0));
+    let lines: Vec<&str> = output.lines().collect();
+    let reread = lines
+        .iter()
+        .map(|l| format!("IO.inspect({})\n", l))
+        .collect::<String>();
+    let (result, output_again) = crate::interpreter::run_source(
+        &(source.split("IO").next().unwrap().to_string() + &reread),
+        "",
+    );
+    assert_eq!(result, Ok(0));
+    assert_eq!(output, output_again);
+    assert_eq!(
+        lines[2],
+        "{Point{x: 1, y: 2}, 0..10, -1..-10..-3, true, {}}"
+    );
+}
+
+#[cfg(test)]
+fn halve(_: &mut dyn Context, args: Vec<Value>) -> CallResult {
+    match first(args) {
+        Value::Int(i) if i % 2 == 0 => CallResult::Ok(Value::Int(i / 2)),
+        v => CallResult::Err(format!("`{}` is odd", v)),
+    }
+}
+
+#[test]
+fn test_register_builtin() {
+    register(Builtin::new(
+        "Test.halve",
+        &[],
+        vec![Type::int()],
+        Type::int(),
+        halve,
+    ));
+    let (result, output) =
+        crate::interpreter::run_source("IO.puts(Test.halve(20) |> Test.halve())\n", "");
+    assert_eq!(result, Ok(0));
+    assert_eq!(output, "5\n");
+    let (result, _) = crate::interpreter::run_source("IO.puts(Test.halve(3))\n", "");
+    assert_eq!(
+        result.map_err(|e| e.to_string()),
+        Err("error: `3` is odd at 1:9".to_string())
+    );
+
+    // the checker holds calls to the signature it was registered with
+    let mut program = crate::parsers::parse("Test.halve(\"ten\")\n").unwrap();
+    let (mut symbols, _) = crate::resolver::resolve(&mut program);
+    let diagnostics = crate::checker::check(&program, &mut symbols);
+    assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+}
+
+#[test]
+fn test_float_to_int() {
+    assert_eq!(float_to_int(-2.9), Ok(-2));
+    assert_eq!(float_to_int(-9223372036854775808.0), Ok(i64::MIN));
+    assert_eq!(
+        float_to_int(9223372036854775807.0),
+        Err("float out of range for `int`")
+    );
+    assert_eq!(
+        float_to_int(f64::NEG_INFINITY),
+        Err("float out of range for `int`")
+    );
+    assert_eq!(
+        float_to_int(f64::NAN),
+        Err("NaN can't be converted to `int`")
+    );
+    assert_eq!(
+        convert("int", vec![Value::Float(1.0 / 0.0)]),
+        CallResult::Err("float out of range for `int`".to_string())
+    );
+}
@@ -1,7 +0,0 @@
-use std::io;
-
-use crate::tokens;
-
-pub fn simulate() {
-    
-}
\ No newline at end of file
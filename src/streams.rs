@@ -0,0 +1,550 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::rc::Rc;
+
+use crate::rho_core::{apply, arguments, int, run, test, Builtin, CallResult, Context, Outcome};
+use crate::types::Type;
+use crate::value::Value;
+
+/* A lazy, pull based sequence. A stream value only describes where its elements come from and
+ * what happens to them on the way, nothing runs until a terminal operation (`Stream.to_list`, a
+ * `for` loop, any `List` function, ..) pulls from it through a `Cursor`, one element at a time.
+ * Pulling never builds the intermediate lists, so a stream can be infinite as long as something
+ * like `Stream.take` ends it. A stream can be gone through more than once, each pass starts over,
+ * which for `Stream.lines` means reading the file again. The interpreter and the VM run streams,
+ * natively they're the runtime's `rho_runtime::streams`, which calls their closures back.
+ */
+#[derive(Debug, PartialEq)]
+pub enum Stream {
+    Range(i64, i64, i64),
+    Values(Rc<Vec<Value>>),
+    Iterate(Value, Value), // the first element and the function giving the next one from the last
+    Lines(Rc<str>),        // the lines of the file at a path
+    Map(Rc<Stream>, Value),
+    Filter(Rc<Stream>, Value),
+    Take(Rc<Stream>, usize),
+    TakeWhile(Rc<Stream>, Value),
+    Chunk(Rc<Stream>, usize),
+    FlatMap(Rc<Stream>, Value),
+}
+
+// Where one pass over a stream is
+pub enum Cursor {
+    Range(Box<dyn Iterator<Item = i64>>),
+    Values(Rc<Vec<Value>>, usize),
+    Iterate {
+        last: Option<Value>,
+        first: Value,
+        next: Value,
+    },
+    Lines(Option<BufReader<File>>, Rc<str>), // the file is opened by the first pull
+    Map(Box<Cursor>, Value),
+    Filter(Box<Cursor>, Value),
+    Take(Box<Cursor>, usize), // how many elements are left
+    TakeWhile(Box<Cursor>, Value, bool),
+    Chunk(Box<Cursor>, usize),
+    FlatMap(Box<Cursor>, Value, Option<Box<Cursor>>), // the elements of the last result come first
+}
+
+impl Cursor {
+    pub fn new(stream: &Stream) -> Cursor {
+        let inner = |s: &Rc<Stream>| Box::new(Cursor::new(s));
+        match stream {
+            Stream::Range(start, end, step) => {
+                Cursor::Range(Box::new(crate::value::range(*start, *end, *step)))
+            }
+            Stream::Values(values) => Cursor::Values(values.clone(), 0),
+            Stream::Iterate(first, next) => Cursor::Iterate {
+                last: None,
+                first: first.clone(),
+                next: next.clone(),
+            },
+            Stream::Lines(path) => Cursor::Lines(None, path.clone()),
+            Stream::Map(s, f) => Cursor::Map(inner(s), f.clone()),
+            Stream::Filter(s, f) => Cursor::Filter(inner(s), f.clone()),
+            Stream::Take(s, n) => Cursor::Take(inner(s), *n),
+            Stream::TakeWhile(s, f) => Cursor::TakeWhile(inner(s), f.clone(), false),
+            Stream::Chunk(s, n) => Cursor::Chunk(inner(s), *n),
+            Stream::FlatMap(s, f) => Cursor::FlatMap(inner(s), f.clone(), None),
+        }
+    }
+
+    // A pass over any iterable value, None for a value that isn't one
+    pub fn of(value: &Value) -> Option<Cursor> {
+        match value {
+            Value::Stream(s) => Some(Cursor::new(s)),
+            Value::Range(start, end, step) => {
+                Some(Cursor::new(&Stream::Range(*start, *end, *step)))
+            }
+            Value::List(values) => Some(Cursor::Values(values.clone(), 0)),
+            v => Some(Cursor::Values(Rc::new(v.elements()?.collect()), 0)),
+        }
+    }
+
+    pub fn next(&mut self, context: &mut dyn Context) -> Outcome<Option<Value>> {
+        match self {
+            Cursor::Range(range) => Ok(range.next().map(Value::Int)),
+            Cursor::Values(values, i) => {
+                let value = values.get(*i).cloned();
+                *i += 1;
+                Ok(value)
+            }
+            Cursor::Iterate { last, first, next } => {
+                let value = match last.take() {
+                    None => first.clone(),
+                    Some(last) => apply(context, next, vec![last])?,
+                };
+                *last = Some(value.clone());
+                Ok(Some(value))
+            }
+            Cursor::Lines(reader, path) => {
+                if reader.is_none() {
+                    let file = File::open(&**path)
+                        .map_err(|e| CallResult::Err(format!("can't read `{}`: {}", path, e)))?;
+                    *reader = Some(BufReader::new(file));
+                }
+                let mut line = String::new();
+                let read = reader.as_mut().unwrap().read_line(&mut line);
+                match read {
+                    Ok(0) => Ok(None),
+                    Ok(_) => {
+                        let line = line.strip_suffix('\n').unwrap_or(&line);
+                        Ok(Some(Value::string(line.strip_suffix('\r').unwrap_or(line))))
+                    }
+                    Err(e) => Err(CallResult::Err(format!("can't read `{}`: {}", path, e))),
+                }
+            }
+            Cursor::Map(inner, f) => match inner.next(context)? {
+                Some(v) => Ok(Some(apply(context, f, vec![v])?)),
+                None => Ok(None),
+            },
+            Cursor::Filter(inner, predicate) => {
+                while let Some(v) = inner.next(context)? {
+                    if test(context, predicate, v.clone())? {
+                        return Ok(Some(v));
+                    }
+                }
+                Ok(None)
+            }
+            Cursor::Take(inner, left) => {
+                if *left == 0 {
+                    return Ok(None);
+                }
+                *left -= 1;
+                inner.next(context)
+            }
+            Cursor::TakeWhile(inner, predicate, done) => {
+                if *done {
+                    return Ok(None);
+                }
+                match inner.next(context)? {
+                    Some(v) if test(context, predicate, v.clone())? => Ok(Some(v)),
+                    _ => {
+                        *done = true;
+                        Ok(None)
+                    }
+                }
+            }
+            Cursor::Chunk(inner, size) => {
+                let mut chunk = vec![];
+                while chunk.len() < *size {
+                    match inner.next(context)? {
+                        Some(v) => chunk.push(v),
+                        None => break,
+                    }
+                }
+                Ok(if chunk.is_empty() {
+                    None
+                } else {
+                    Some(Value::list(chunk))
+                })
+            }
+            Cursor::FlatMap(inner, f, current) => loop {
+                if let Some(cursor) = current {
+                    if let Some(v) = cursor.next(context)? {
+                        return Ok(Some(v));
+                    }
+                }
+                let Some(v) = inner.next(context)? else {
+                    return Ok(None);
+                };
+                let result = apply(context, f, vec![v])?;
+                match Cursor::of(&result) {
+                    Some(cursor) => *current = Some(Box::new(cursor)),
+                    None => return Err(not_iterable(&result)),
+                }
+            },
+        }
+    }
+}
+
+fn not_iterable(v: &Value) -> CallResult {
+    CallResult::Err(format!("can't iterate over `{}`", v.type_name()))
+}
+
+// Every element of an iterable value, pulling a stream to its end
+pub fn collect(context: &mut dyn Context, value: &Value) -> Outcome<Vec<Value>> {
+    let mut cursor = Cursor::of(value).ok_or_else(|| not_iterable(value))?;
+    let mut elements = vec![];
+    while let Some(v) = cursor.next(context)? {
+        elements.push(v);
+    }
+    Ok(elements)
+}
+
+fn stream(s: Stream) -> Value {
+    Value::Stream(Rc::new(s))
+}
+
+// The stream a value is, an iterable that isn't a stream yet becomes one
+fn source(value: Value) -> Outcome<Rc<Stream>> {
+    Ok(match value {
+        Value::Stream(s) => s,
+        Value::Range(start, end, step) => Rc::new(Stream::Range(start, end, step)),
+        Value::List(values) => Rc::new(Stream::Values(values)),
+        v => match v.elements() {
+            Some(elements) => Rc::new(Stream::Values(Rc::new(elements.collect()))),
+            None => return Err(not_iterable(&v)),
+        },
+    })
+}
+
+fn count_arg(v: &Value) -> Outcome<usize> {
+    usize::try_from(int(v)?)
+        .map_err(|_| CallResult::Err(format!("expected a count, found `{}`", v)))
+}
+
+pub fn builtins() -> Vec<Builtin> {
+    let t = || Type::Param("T".to_string());
+    let u = || Type::Param("U".to_string());
+    let of = |t: Type| Type::BuiltIn(crate::tokens::BuiltinType::Stream, vec![t]);
+    let predicate = || Type::function(vec![t()], Type::bool());
+    vec![
+        // constructors
+        Builtin::new(
+            "Stream.from",
+            &["T"],
+            vec![Type::list(t())],
+            of(t()),
+            |_, args| {
+                run(|| {
+                    let [xs] = arguments(args)?;
+                    Ok(Value::Stream(source(xs)?))
+                })
+            },
+        ),
+        Builtin::new(
+            "Stream.iterate",
+            &["T"],
+            vec![t(), Type::function(vec![t()], t())],
+            of(t()),
+            |_, args| {
+                run(|| {
+                    let [first, next] = arguments(args)?;
+                    Ok(stream(Stream::Iterate(first, next)))
+                })
+            },
+        ),
+        Builtin::new(
+            "Stream.lines",
+            &[],
+            vec![Type::string()],
+            of(Type::string()),
+            |_, args| {
+                run(|| match arguments(args)? {
+                    [Value::String(path)] => Ok(stream(Stream::Lines(path))),
+                    [v] => Err(CallResult::Err(format!("expected a path, found `{}`", v))),
+                })
+            },
+        ),
+        // lazy combinators
+        Builtin::new(
+            "Stream.map",
+            &["T", "U"],
+            vec![of(t()), Type::function(vec![t()], u())],
+            of(u()),
+            |_, args| {
+                run(|| {
+                    let [s, f] = arguments(args)?;
+                    Ok(stream(Stream::Map(source(s)?, f)))
+                })
+            },
+        ),
+        Builtin::new(
+            "Stream.filter",
+            &["T"],
+            vec![of(t()), predicate()],
+            of(t()),
+            |_, args| {
+                run(|| {
+                    let [s, f] = arguments(args)?;
+                    Ok(stream(Stream::Filter(source(s)?, f)))
+                })
+            },
+        ),
+        Builtin::new(
+            "Stream.take",
+            &["T"],
+            vec![of(t()), Type::int()],
+            of(t()),
+            |_, args| {
+                run(|| {
+                    let [s, n] = arguments(args)?;
+                    Ok(stream(Stream::Take(source(s)?, count_arg(&n)?)))
+                })
+            },
+        ),
+        Builtin::new(
+            "Stream.take_while",
+            &["T"],
+            vec![of(t()), predicate()],
+            of(t()),
+            |_, args| {
+                run(|| {
+                    let [s, f] = arguments(args)?;
+                    Ok(stream(Stream::TakeWhile(source(s)?, f)))
+                })
+            },
+        ),
+        // lists of `size` elements, the last one can be shorter
+        Builtin::new(
+            "Stream.chunk",
+            &["T"],
+            vec![of(t()), Type::int()],
+            of(Type::list(t())),
+            |_, args| {
+                run(|| {
+                    let [s, size] = arguments(args)?;
+                    match count_arg(&size)? {
+                        0 => Err(CallResult::Err("a chunk can't be empty".to_string())),
+                        size => Ok(stream(Stream::Chunk(source(s)?, size))),
+                    }
+                })
+            },
+        ),
+        Builtin::new(
+            "Stream.flat_map",
+            &["T", "U"],
+            vec![of(t()), Type::function(vec![t()], Type::list(u()))],
+            of(u()),
+            |_, args| {
+                run(|| {
+                    let [s, f] = arguments(args)?;
+                    Ok(stream(Stream::FlatMap(source(s)?, f)))
+                })
+            },
+        ),
+        // terminal operations, these pull the stream
+        Builtin::new(
+            "Stream.to_list",
+            &["T"],
+            vec![of(t())],
+            Type::list(t()),
+            |context, args| {
+                run(|| {
+                    let [s] = arguments(args)?;
+                    Ok(Value::list(collect(context, &s)?))
+                })
+            },
+        ),
+        Builtin::new(
+            "Stream.reduce",
+            &["T", "U"],
+            vec![of(t()), u(), Type::function(vec![u(), t()], u())],
+            u(),
+            |context, args| {
+                run(|| {
+                    let [s, mut acc, f] = arguments(args)?;
+                    let mut cursor = Cursor::of(&s).ok_or_else(|| not_iterable(&s))?;
+                    while let Some(v) = cursor.next(context)? {
+                        acc = apply(context, &f, vec![acc, v])?;
+                    }
+                    Ok(acc)
+                })
+            },
+        ),
+        Builtin::new(
+            "Stream.each",
+            &["T"],
+            vec![of(t()), Type::function(vec![t()], Type::Unit)],
+            Type::Unit,
+            |context, args| {
+                run(|| {
+                    let [s, f] = arguments(args)?;
+                    let mut cursor = Cursor::of(&s).ok_or_else(|| not_iterable(&s))?;
+                    while let Some(v) = cursor.next(context)? {
+                        apply(context, &f, vec![v])?;
+                    }
+                    Ok(Value::Unit)
+                })
+            },
+        ),
+        Builtin::new(
+            "Stream.count",
+            &["T"],
+            vec![of(t())],
+            Type::int(),
+            |context, args| {
+                run(|| {
+                    let [s] = arguments(args)?;
+                    let mut cursor = Cursor::of(&s).ok_or_else(|| not_iterable(&s))?;
+                    let mut count = 0;
+                    while cursor.next(context)?.is_some() {
+                        count += 1;
+                    }
+                    Ok(Value::Int(count))
+                })
+            },
+        ),
+    ]
+}
+
+#[test]
+fn test_stream_module() {
+    let path = std::env::temp_dir().join(format!("rho_stream_{}.txt", std::process::id()));
+    std::fs::write(&path, "first\r\nsecond\nthird\n").unwrap();
+    let (result, output) = crate::interpreter::run_source(
+        &format!(
+            "powers = Stream.iterate(1, fn x -> x * 2)
+IO.inspect(powers |> Stream.map(fn x -> x + 1) |> Stream.take(5) |> Stream.to_list())
+IO.inspect(powers |> Stream.take_while(fn x -> x < 100) |> Stream.chunk(3) |> Stream.to_list())
+evens = Stream.from(1..4) |> Stream.flat_map(fn x -> [x, x * 10]) |> Stream.filter(fn x -> x % 2 == 0)
+for x in evens {{
+    IO.print(x)
+}}
+IO.puts(\"\")
+IO.inspect(Stream.lines({:?}) |> Stream.map(fn l -> String.length(l)) |> List.sum())
+IO.inspect({{Stream.count(evens), Stream.reduce(evens, 0, fn acc, x -> acc + x)}})
+IO.inspect(List.take(powers, 3))
+",
+            path.to_string_lossy()
+        ),
+        "",
+    );
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(result, Ok(0));
+    assert_eq!(
+        output,
+        "[2, 3, 5, 9, 17]
+[[1, 2, 4], [8, 16, 32], [64]]
+1022030
+16
+{4, 62}
+[1, 2, 4]
+"
+    );
+}
+
+#[test]
+fn test_streams_are_lazy() {
+    // the division by zero is never reached, only as much of the stream runs as is pulled
+    let (result, output) = crate::interpreter::run_source(
+        "s = Stream.from([1, 2, 0]) |> Stream.map(fn x -> 10 / x)
+IO.inspect(s |> Stream.take(2) |> Stream.to_list())
+IO.inspect(Stream.to_list(s))
+",
+        "",
+    );
+    assert_eq!(output, "[10, 5]\n");
+    assert_eq!(
+        result.map_err(|e| e.to_string()),
+        Err("error: division by zero at 1:53".to_string())
+    );
+}
+
+#[test]
+fn test_streams_native() {
+    let path = std::env::temp_dir().join(format!("rho_stream_native_{}.txt", std::process::id()));
+    std::fs::write(&path, "first\r\nsecond\nthird\n").unwrap();
+    let source = format!(
+        "powers = Stream.iterate(1, fn x -> x * 2)
+IO.inspect(powers |> Stream.map(fn x -> x + 1) |> Stream.take(5) |> Stream.to_list())
+IO.inspect(powers |> Stream.take_while(fn x -> x < 100) |> Stream.chunk(3) |> Stream.to_list())
+evens = Stream.from(1..4) |> Stream.flat_map(fn x -> [x, x * 10]) |> Stream.filter(fn x -> x % 2 == 0)
+for x in evens {{
+    IO.print(x)
+}}
+IO.puts(\"\")
+IO.inspect(Stream.lines({:?}) |> Stream.map(fn l -> String.length(l)) |> Stream.reduce(0, fn a, b -> a + b))
+IO.inspect({{Stream.count(evens), Stream.reduce(evens, 0, fn acc, x -> acc + x)}})
+IO.inspect(List.count(powers |> Stream.take(3)))
+words = Stream.from([\"a\", \"bb\", \"ccc\"]) |> Stream.map(fn w -> w <> \"!\")
+for w in words {{
+    if w == \"bb!\" {{
+        break
+    }}
+    IO.puts(w)
+}}
+Stream.each(words, fn w -> IO.puts(w))
+IO.inspect(Stream.from({{\"a\": 1}}) |> Stream.to_list())
+IO.inspect(Stream.from([1.5, 2.0]) |> Stream.filter(fn x -> x > 1.6) |> Stream.to_list())
+func first_big(stream[int] s) -> int {{
+    for x in s {{
+        for y in Stream.from([1, 2]) {{
+            if x * y > 5 {{
+                return x
+            }}
+        }}
+    }}
+    return -1
+}}
+IO.inspect(first_big(Stream.iterate(1, fn x -> x + 1)))
+
+// each call leaves `s` in a cycle with the closure that shows it
+func cycle(int n) -> int {{
+    var s = Stream.from([1, 2])
+    show = fn x -> {{
+        IO.print(s)
+        return x
+    }}
+    s = Stream.map(s, fn x -> x + n) |> Stream.map(show)
+    return Stream.reduce(s, 0, fn a, b -> a + b)
+}}
+var sum = 0
+for i in 0..3000 {{
+    sum = sum + cycle(i)
+}}
+IO.puts(\"\")
+IO.inspect(sum)
+",
+        path.to_string_lossy()
+    );
+    let native = crate::codegen::run_native(&source, "");
+    let (result, output) = crate::interpreter::run_source(&source, "");
+    std::fs::remove_file(&path).unwrap();
+    let Some((code, stdout, stderr)) = native else {
+        return;
+    };
+    assert_eq!(stdout, output);
+    assert!(output.starts_with(
+        "[2, 3, 5, 9, 17]
+[[1, 2, 4], [8, 16, 32], [64]]
+1022030
+16
+{4, 62}
+3
+a!
+a!
+bb!
+ccc!
+[{\"a\", 1}]
+[2.0]
+3
+#stream#stream"
+    ));
+    assert!(output.ends_with("\n9006000\n"));
+    assert_eq!(code, Some(result.unwrap()), "{}", stderr);
+    assert!(!stderr.contains("still live"), "{}", stderr);
+
+    // only as much of the stream runs as is pulled, then the division fails in the closure
+    let source = "s = Stream.from([1, 2, 0]) |> Stream.map(fn x -> 10 / x)
+IO.inspect(s |> Stream.take(2) |> Stream.to_list())
+IO.inspect(Stream.to_list(s))
+";
+    let Some((code, stdout, stderr)) = crate::codegen::run_native(source, "") else {
+        return;
+    };
+    assert_eq!(stdout, "[10, 5]\n");
+    assert_eq!(code, Some(1));
+    assert!(stderr.contains("division by zero"), "{}", stderr);
+}
@@ -0,0 +1,319 @@
+use crate::rho_core::{Builtin, CallResult};
+use crate::types::Type;
+use crate::value::Value;
+pub use rho_runtime::text::{graphemes, pad, parse_float, parse_int, replace, slice, split};
+
+/* The `String` module. The functions on `&str` are what both backends build on, the interpreter
+ * calls them through the natives registered in `builtins` and compiled code through the runtime.
+ * Lengths, indices and widths count graphemes, what a reader sees as one character, so
+ * `String.length("e\u{301}")` is 1 even though it is two chars and three bytes.
+ */
+pub fn builtins() -> Vec<Builtin> {
+    let s = Type::string;
+    vec![
+        Builtin::new("String.length", &[], vec![s()], Type::int(), |_, args| {
+            call(args, |a| {
+                Ok(Value::Int(graphemes(string(&a[0])?).len() as i64))
+            })
+        }),
+        Builtin::new(
+            "String.char_count",
+            &[],
+            vec![s()],
+            Type::int(),
+            |_, args| {
+                call(args, |a| {
+                    Ok(Value::Int(string(&a[0])?.chars().count() as i64))
+                })
+            },
+        ),
+        Builtin::new(
+            "String.byte_size",
+            &[],
+            vec![s()],
+            Type::int(),
+            |_, args| call(args, |a| Ok(Value::Int(string(&a[0])?.len() as i64))),
+        ),
+        Builtin::new(
+            "String.graphemes",
+            &[],
+            vec![s()],
+            Type::list(s()),
+            |_, args| call(args, |a| Ok(strings(graphemes(string(&a[0])?)))),
+        ),
+        Builtin::new(
+            "String.slice",
+            &[],
+            vec![s(), Type::int(), Type::int()],
+            s(),
+            |_, args| {
+                call(args, |a| {
+                    let sliced = slice(string(&a[0])?, int(&a[1])?, int(&a[2])?);
+                    Ok(Value::string(&sliced))
+                })
+            },
+        ),
+        Builtin::new(
+            "String.split",
+            &[],
+            vec![s(), s()],
+            Type::list(s()),
+            |_, args| call(args, |a| Ok(strings(split(string(&a[0])?, string(&a[1])?)))),
+        ),
+        Builtin::new(
+            "String.join",
+            &[],
+            vec![Type::list(s()), s()],
+            s(),
+            |_, args| {
+                call(args, |a| {
+                    let Value::List(parts) = &a[0] else {
+                        return Err(format!("`String.join` takes a list, not `{}`", a[0]));
+                    };
+                    let parts = parts.iter().map(string).collect::<Result<Vec<_>, _>>()?;
+                    Ok(Value::string(&parts.join(string(&a[1])?)))
+                })
+            },
+        ),
+        Builtin::new("String.trim", &[], vec![s()], s(), |_, args| {
+            call(args, |a| Ok(Value::string(string(&a[0])?.trim())))
+        }),
+        Builtin::new("String.trim_leading", &[], vec![s()], s(), |_, args| {
+            call(args, |a| Ok(Value::string(string(&a[0])?.trim_start())))
+        }),
+        Builtin::new("String.trim_trailing", &[], vec![s()], s(), |_, args| {
+            call(args, |a| Ok(Value::string(string(&a[0])?.trim_end())))
+        }),
+        Builtin::new(
+            "String.replace",
+            &[],
+            vec![s(), s(), s()],
+            s(),
+            |_, args| {
+                call(args, |a| {
+                    let replaced = replace(string(&a[0])?, string(&a[1])?, string(&a[2])?);
+                    Ok(Value::string(&replaced))
+                })
+            },
+        ),
+        Builtin::new(
+            "String.contains",
+            &[],
+            vec![s(), s()],
+            Type::bool(),
+            |_, args| {
+                call(args, |a| {
+                    Ok(Value::Bool(string(&a[0])?.contains(string(&a[1])?)))
+                })
+            },
+        ),
+        Builtin::new(
+            "String.starts_with",
+            &[],
+            vec![s(), s()],
+            Type::bool(),
+            |_, args| {
+                call(args, |a| {
+                    Ok(Value::Bool(string(&a[0])?.starts_with(string(&a[1])?)))
+                })
+            },
+        ),
+        Builtin::new(
+            "String.ends_with",
+            &[],
+            vec![s(), s()],
+            Type::bool(),
+            |_, args| {
+                call(args, |a| {
+                    Ok(Value::Bool(string(&a[0])?.ends_with(string(&a[1])?)))
+                })
+            },
+        ),
+        Builtin::new("String.upcase", &[], vec![s()], s(), |_, args| {
+            call(args, |a| Ok(Value::string(&string(&a[0])?.to_uppercase())))
+        }),
+        Builtin::new("String.downcase", &[], vec![s()], s(), |_, args| {
+            call(args, |a| Ok(Value::string(&string(&a[0])?.to_lowercase())))
+        }),
+        Builtin::new(
+            "String.to_int",
+            &[],
+            vec![s()],
+            Type::result(Type::int(), s()),
+            |_, args| {
+                call(args, |a| {
+                    Ok(match parse_int(string(&a[0])?) {
+                        Ok(i) => Value::ok(Value::Int(i)),
+                        Err(reason) => Value::error(Value::string(&reason)),
+                    })
+                })
+            },
+        ),
+        Builtin::new(
+            "String.to_float",
+            &[],
+            vec![s()],
+            Type::result(Type::float(), s()),
+            |_, args| {
+                call(args, |a| {
+                    Ok(match parse_float(string(&a[0])?) {
+                        Ok(f) => Value::ok(Value::Float(f)),
+                        Err(reason) => Value::error(Value::string(&reason)),
+                    })
+                })
+            },
+        ),
+        Builtin::new(
+            "String.pad_leading",
+            &[],
+            vec![s(), Type::int(), s()],
+            s(),
+            |_, args| {
+                call(args, |a| {
+                    let padded = pad(string(&a[0])?, int(&a[1])?, string(&a[2])?, true)?;
+                    Ok(Value::string(&padded))
+                })
+            },
+        ),
+        Builtin::new(
+            "String.pad_trailing",
+            &[],
+            vec![s(), Type::int(), s()],
+            s(),
+            |_, args| {
+                call(args, |a| {
+                    let padded = pad(string(&a[0])?, int(&a[1])?, string(&a[2])?, false)?;
+                    Ok(Value::string(&padded))
+                })
+            },
+        ),
+    ]
+}
+
+fn call(args: Vec<Value>, f: impl FnOnce(&[Value]) -> Result<Value, String>) -> CallResult {
+    match f(&args) {
+        Ok(v) => CallResult::Ok(v),
+        Err(message) => CallResult::Err(message),
+    }
+}
+
+fn string(v: &Value) -> Result<&str, String> {
+    match v {
+        Value::String(s) => Ok(s),
+        v => Err(format!("expected a str, found `{}`", v)),
+    }
+}
+
+fn int(v: &Value) -> Result<i64, String> {
+    match v {
+        Value::Int(i) => Ok(*i),
+        v => Err(format!("expected an int, found `{}`", v)),
+    }
+}
+
+fn strings(parts: Vec<&str>) -> Value {
+    Value::list(parts.into_iter().map(Value::string).collect())
+}
+
+#[test]
+fn test_graphemes() {
+    assert_eq!(graphemes("e\u{301}a"), ["e\u{301}", "a"]);
+    assert_eq!(graphemes("a\r\nb"), ["a", "\r\n", "b"]);
+    // a family emoji, a thumbs up with a skin tone and two flags
+    let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+    let thumbs = "\u{1F44D}\u{1F3FD}";
+    let flags = "\u{1F1EF}\u{1F1F5}\u{1F1EB}\u{1F1F7}";
+    assert_eq!(
+        graphemes(&format!("{}{}{}", family, thumbs, flags)),
+        [family, thumbs, "\u{1F1EF}\u{1F1F5}", "\u{1F1EB}\u{1F1F7}"]
+    );
+    // a Hangul syllable of jamo, a Devanagari spacing mark and an emoji presentation sequence
+    let han = "\u{1112}\u{1161}\u{11AB}";
+    let ki = "\u{915}\u{93F}";
+    let heart = "\u{2764}\u{FE0F}";
+    assert_eq!(
+        graphemes(&format!("{}{}{}", han, ki, heart)),
+        [han, ki, heart]
+    );
+    assert!(graphemes("").is_empty());
+}
+
+#[test]
+fn test_string_functions() {
+    assert_eq!(slice("h\u{e9}llo w\u{f6}rld", -5, 3), "w\u{f6}r");
+    assert_eq!(slice("abc", 1, 10), "bc");
+    assert_eq!(slice("abc", 5, 1), "");
+    assert_eq!(split("a,b,,c", ","), ["a", "b", "", "c"]);
+    assert_eq!(parse_int("-42"), Ok(-42));
+    assert_eq!(parse_int("4x"), Err("`4x` is not an int".to_string()));
+    assert_eq!(parse_float("1e3"), Ok(1000.0));
+    assert!(parse_float("inf").is_err());
+    assert_eq!(pad("7", 3, "0", true), Ok("007".to_string()));
+    assert_eq!(pad("ab", 5, "-=", false), Ok("ab-=-".to_string()));
+    assert!(pad("", 2, "", true).is_err());
+    assert_eq!(
+        pad("a", i64::MAX, "b", true),
+        Err("can't pad to 9223372036854775807 graphemes, the most is 16777216".to_string())
+    );
+    // a string already that wide is left as it is
+    assert_eq!(pad("a", -5, "b", true), Ok("a".to_string()));
+}
+
+#[test]
+fn test_string_module() {
+    let (result, output) = crate::interpreter::run_source(
+        "name = \"  Ren\u{e9}e \"
+trimmed = String.trim(name)
+IO.inspect({String.length(trimmed), String.char_count(\"e\u{301}\"), String.byte_size(trimmed)})
+words = String.split(\"a b c\", \" \")
+IO.puts(String.join(words, \"-\") |> String.upcase())
+IO.puts(String.pad_leading(str(42), 6, \".\") <> \"|\")
+match String.to_int(\"12\") {
+    {:ok, n} -> IO.puts(n + 1)
+    {:error, reason} -> IO.puts(reason)
+}
+match String.to_float(\"x\") {
+    {:ok, f} -> IO.puts(f)
+    {:error, reason} -> IO.puts(\"error: \" <> reason)
+}
+IO.inspect(String.replace(\"aaa\", \"a\", \"b\") |> String.contains(\"bb\"))
+",
+        "",
+    );
+    assert_eq!(result, Ok(0));
+    assert_eq!(
+        output,
+        "{5, 2, 6}\nA-B-C\n....42|\n13\nerror: `x` is not a float\ntrue\n"
+    );
+}
+
+#[test]
+fn test_string_module_native() {
+    let source = "words = String.split(IO.read_line(), \" \")
+IO.inspect(words)
+IO.puts(String.join(words, \"-\") <> str(String.split(\"ab\", \"\")))
+IO.inspect(String.graphemes(\"e\u{301}x\"))
+IO.inspect({String.to_int(\"-12\"), String.to_int(\"1.5\")})
+IO.inspect([String.to_float(\"1.5e3\"), String.to_float(\"inf\")])
+IO.puts(str(1) <> str(2.0) <> str(true) <> str('c') <> str(:a) <> str(\"s\"))
+IO.inspect(str({\"k\": [String.to_int(\"7\")]}))
+";
+    let Some((code, stdout, stderr)) = crate::codegen::run_native(source, "a b c\n") else {
+        return;
+    };
+    let (result, output) = crate::interpreter::run_source(source, "a b c\n");
+    assert_eq!(stdout, output);
+    assert_eq!(
+        output,
+        "[\"a\", \"b\", \"c\"]
+a-b-c[\"a\", \"b\"]
+[\"e\u{301}\", \"x\"]
+{{:ok, -12}, {:error, \"`1.5` is not an int\"}}
+[{:ok, 1500.0}, {:error, \"`inf` is not a float\"}]
+12.0truec:as
+\"{\\\"k\\\": [{:ok, 7}]}\"
+"
+    );
+    assert_eq!(code, Some(result.unwrap()), "{}", stderr);
+    assert!(!stderr.contains("still live"), "{}", stderr);
+}
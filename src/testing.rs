@@ -0,0 +1,432 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::interpreter;
+use crate::parsers::Expression;
+use crate::resolver::SymbolTable;
+
+/* `rho test` (or `rho t`). Every .rho file under the given path, the current directory by
+ * default, is compiled and each of its `test "name" { .. }` blocks is run on an interpreter of its
+ * own. A test passes when it finishes and fails on a runtime error, ie. a failed `assert`.
+ *
+ *     rho test [path] [--filter text] [--junit report.xml]
+ *
+ * `--filter` only runs the tests with `text` in their name, `--junit` also writes the results as
+ * JUnit XML for CI. The exit code is 1 when a test failed or a file didn't compile, 2 for bad
+ * arguments.
+ */
+pub fn main(args: &[String]) -> i32 {
+    let options = match Options::parse(args) {
+        Ok(options) if options.help => {
+            print!("{}", USAGE);
+            return 0;
+        }
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            eprint!("{}", USAGE);
+            return 2;
+        }
+    };
+    let files = match discover(Path::new(&options.path)) {
+        Ok(files) => files,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            return 2;
+        }
+    };
+    let start = Instant::now();
+    let mut cases = vec![];
+    let mut filtered = 0;
+    let mut broken = 0;
+    for path in files.iter() {
+        let file = path.display().to_string();
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("error: can't read {}: {}", file, e);
+                broken += 1;
+                continue;
+            }
+        };
+        let (compiled, diagnostics) = crate::compile(&source);
+        for d in diagnostics.iter() {
+            eprint!("{}", d.render(&file, &source));
+        }
+        let Some((program, symbols)) = compiled else {
+            broken += 1;
+            continue;
+        };
+        let (ran, skipped) = run_tests(
+            &file,
+            &source,
+            &program,
+            &symbols,
+            options.filter.as_deref(),
+        );
+        cases.extend(ran);
+        filtered += skipped;
+    }
+    let time = start.elapsed();
+    print!("{}", report(&cases, filtered, time));
+    if broken > 0 {
+        println!("{} didn't compile", plural(broken, "file"));
+    }
+    if let Some(junit_path) = &options.junit {
+        if let Err(e) = fs::write(junit_path, junit(&cases, time)) {
+            eprintln!("error: can't write {}: {}", junit_path, e);
+            return 1;
+        }
+    }
+    let failed = cases.iter().any(|c| c.failure.is_some());
+    if failed || broken > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+const USAGE: &str = "usage: rho test [path] [--filter text] [--junit report.xml]
+
+runs the `test` blocks of every .rho file under path, the current directory by default
+
+options:
+    --filter <text>         only runs the tests with text in their name
+    --junit <file>          also writes the results to file as JUnit XML
+    -h, --help              prints this message
+";
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    path: String,
+    filter: Option<String>,
+    junit: Option<String>,
+    help: bool,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options {
+            path: ".".to_string(),
+            filter: None,
+            junit: None,
+            help: false,
+        };
+        let mut path = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |flag: &str| {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| format!("`{}` needs a value", flag))
+            };
+            match arg.as_str() {
+                "--filter" => options.filter = Some(value(arg)?),
+                "--junit" => options.junit = Some(value(arg)?),
+                "-h" | "--help" => options.help = true,
+                flag if flag.starts_with("--") => return Err(format!("unknown flag `{}`", flag)),
+                _ if path.is_none() => path = Some(arg.to_owned()),
+                _ => return Err(format!("unexpected argument `{}`", arg)),
+            }
+        }
+        if let Some(path) = path {
+            options.path = path;
+        }
+        Ok(options)
+    }
+}
+
+// The .rho files at `root`, in a stable order, skipping hidden directories and build output
+fn discover(root: &Path) -> Result<Vec<PathBuf>, String> {
+    if root.is_file() {
+        return Ok(vec![root.to_path_buf()]);
+    }
+    let entries =
+        fs::read_dir(root).map_err(|e| format!("can't read {}: {}", root.display(), e))?;
+    let mut paths: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+    paths.sort();
+    let mut files = vec![];
+    for path in paths {
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        if path.is_dir() {
+            if !name.starts_with('.') && name != "target" {
+                files.extend(discover(&path)?);
+            }
+        } else if path.extension().is_some_and(|e| e == "rho") {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+// A test that ran, `failure` is its error rendered against the file
+#[derive(Debug)]
+pub struct TestCase {
+    pub file: String,
+    pub name: String,
+    pub time: Duration,
+    pub output: String,
+    pub failure: Option<String>,
+}
+
+// Runs the tests of a compiled file whose names contain `filter`, and counts the ones it left out
+pub fn run_tests(
+    file: &str,
+    source: &str,
+    program: &[Expression],
+    symbols: &SymbolTable,
+    filter: Option<&str>,
+) -> (Vec<TestCase>, usize) {
+    let mut cases = vec![];
+    let mut filtered = 0;
+    for test in program.iter() {
+        let Expression::Test { name, .. } = test else {
+            continue;
+        };
+        if filter.is_some_and(|f| !name.contains(f)) {
+            filtered += 1;
+            continue;
+        }
+        let mut output = vec![];
+        let start = Instant::now();
+        let result = interpreter::run_test(program, symbols, test, &mut output);
+        cases.push(TestCase {
+            file: file.to_owned(),
+            name: name.to_owned(),
+            time: start.elapsed(),
+            output: String::from_utf8_lossy(&output).into_owned(),
+            failure: result.err().map(|d| d.render(file, source)),
+        });
+    }
+    (cases, filtered)
+}
+
+// A normal run leaves the tests out
+pub fn strip_tests(program: &mut Vec<Expression>) {
+    program.retain(|e| !matches!(e, Expression::Test { .. }));
+}
+
+// `1 test` and `2 tests`
+fn plural(count: usize, noun: &str) -> String {
+    match count {
+        1 => format!("1 {}", noun),
+        n => format!("{} {}s", n, noun),
+    }
+}
+
+fn millis(time: Duration) -> String {
+    format!("{:.2}ms", time.as_secs_f64() * 1000.0)
+}
+
+// What `rho test` prints, in the shape of `cargo test`'s output
+pub fn report(cases: &[TestCase], filtered: usize, time: Duration) -> String {
+    let mut out = format!("running {}\n", plural(cases.len(), "test"));
+    for case in cases.iter() {
+        let status = match case.failure {
+            Some(_) => "FAILED",
+            None => "ok",
+        };
+        out += &format!(
+            "test {}: {} ... {} ({})\n",
+            case.file,
+            case.name,
+            status,
+            millis(case.time)
+        );
+    }
+    let failures: Vec<&TestCase> = cases.iter().filter(|c| c.failure.is_some()).collect();
+    if !failures.is_empty() {
+        out += "\nfailures:\n";
+        for case in failures.iter() {
+            out += &format!("\n---- {}: {} ----\n", case.file, case.name);
+            out += &case.output;
+            out += case.failure.as_deref().unwrap_or("");
+        }
+    }
+    out += &format!(
+        "\ntest result: {}. {} passed; {} failed; {} filtered out; finished in {}\n",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        cases.len() - failures.len(),
+        failures.len(),
+        filtered,
+        millis(time)
+    );
+    out
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// The results as JUnit XML, a test suite per file
+pub fn junit(cases: &[TestCase], time: Duration) -> String {
+    let failures = |cases: &[&TestCase]| cases.iter().filter(|c| c.failure.is_some()).count();
+    let all: Vec<&TestCase> = cases.iter().collect();
+    let mut out = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n".to_string();
+    out += &format!(
+        "<testsuites name=\"rho test\" tests=\"{}\" failures=\"{}\" time=\"{:.6}\">\n",
+        all.len(),
+        failures(&all),
+        time.as_secs_f64()
+    );
+    let mut files: Vec<&str> = cases.iter().map(|c| c.file.as_str()).collect();
+    files.dedup();
+    for file in files {
+        let suite: Vec<&TestCase> = cases.iter().filter(|c| c.file == file).collect();
+        let suite_time: Duration = suite.iter().map(|c| c.time).sum();
+        out += &format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.6}\">\n",
+            xml_escape(file),
+            suite.len(),
+            failures(&suite),
+            suite_time.as_secs_f64()
+        );
+        for case in suite {
+            out += &format!(
+                "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.6}\"",
+                xml_escape(&case.name),
+                xml_escape(file),
+                case.time.as_secs_f64()
+            );
+            match &case.failure {
+                Some(failure) => {
+                    let message = failure.lines().next().unwrap_or("");
+                    out += &format!(
+                        ">\n      <failure message=\"{}\">{}</failure>\n",
+                        xml_escape(message),
+                        xml_escape(failure)
+                    );
+                    if !case.output.is_empty() {
+                        out += &format!(
+                            "      <system-out>{}</system-out>\n",
+                            xml_escape(&case.output)
+                        );
+                    }
+                    out += "    </testcase>\n";
+                }
+                None => out += "/>\n",
+            }
+        }
+        out += "  </testsuite>\n";
+    }
+    out += "</testsuites>\n";
+    out
+}
+
+#[cfg(test)]
+fn run_source_tests(source: &str, filter: Option<&str>) -> (Vec<TestCase>, usize) {
+    let (compiled, diagnostics) = crate::compile(source);
+    let (program, symbols) = compiled.unwrap_or_else(|| panic!("{:?}", diagnostics));
+    run_tests("math.rho", source, &program, &symbols, filter)
+}
+
+#[test]
+fn test_run_tests() {
+    let source = "var calls = 0
+func double(int n) -> int {
+    return n * 2
+}
+test \"double doubles\" {
+    calls = calls + 1
+    assert double(2) == 4
+}
+test \"each test starts over\" {
+    calls = calls + 1
+    IO.puts(calls)
+    assert calls == 2, \"calls\"
+}
+test \"other\" {
+    panic \"unreachable\"
+}
+IO.puts(\"main\")
+";
+    let (cases, filtered) = run_source_tests(source, Some("s"));
+    assert_eq!(filtered, 1);
+    let names: Vec<&str> = cases.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, ["double doubles", "each test starts over"]);
+    assert_eq!(cases[0].failure, None);
+    // the setup's output isn't the test's
+    assert_eq!(cases[0].output, "");
+    assert_eq!(cases[1].output, "1\n");
+    assert!(cases[1]
+        .failure
+        .as_deref()
+        .unwrap()
+        .starts_with("error: assertion failed: calls, `1 == 2`\n   --> math.rho:12:5\n"));
+
+    assert!(cases[1]
+        .failure
+        .as_deref()
+        .unwrap()
+        .ends_with("stack trace:\n    at test \"each test starts over\" (math.rho:12)\n"));
+    let one = report(&cases[..1], 0, Duration::ZERO);
+    assert!(one.starts_with("running 1 test\ntest "), "{}", one);
+    assert!(report(&[], 0, Duration::ZERO).starts_with("running 0 tests\n"));
+    let report = report(&cases, filtered, Duration::ZERO);
+    assert!(report.starts_with("running 2 tests\ntest math.rho: double doubles ... ok ("));
+    assert!(report.contains("\n---- math.rho: each test starts over ----\n1\nerror: "));
+    assert!(report.ends_with(
+        "test result: FAILED. 1 passed; 1 failed; 1 filtered out; finished in 0.00ms\n"
+    ));
+
+    // a normal run leaves the tests out
+    let (compiled, _) = crate::compile(source);
+    let (mut program, symbols) = compiled.unwrap();
+    strip_tests(&mut program);
+    let mut output = vec![];
+    let result = interpreter::run_with(&program, &symbols, &mut std::io::empty(), &mut output);
+    assert_eq!(result, Ok(0));
+    assert_eq!(output, b"main\n");
+}
+
+#[test]
+fn test_junit() {
+    let case = |name: &str, failure: Option<&str>| TestCase {
+        file: "a.rho".to_string(),
+        name: name.to_string(),
+        time: Duration::from_millis(2),
+        output: String::new(),
+        failure: failure.map(|f| f.to_string()),
+    };
+    let cases = [
+        case("adds", None),
+        case("<compares>", Some("error: assertion failed: `1 == 2`\n")),
+    ];
+    assert_eq!(
+        junit(&cases, Duration::from_millis(5)),
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<testsuites name=\"rho test\" tests=\"2\" failures=\"1\" time=\"0.005000\">
+  <testsuite name=\"a.rho\" tests=\"2\" failures=\"1\" time=\"0.004000\">
+    <testcase name=\"adds\" classname=\"a.rho\" time=\"0.002000\"/>
+    <testcase name=\"&lt;compares&gt;\" classname=\"a.rho\" time=\"0.002000\">
+      <failure message=\"error: assertion failed: `1 == 2`\">error: assertion failed: `1 == 2`
+</failure>
+    </testcase>
+  </testsuite>
+</testsuites>
+"
+    );
+}
+
+#[test]
+fn test_options() {
+    let args = |s: &str| s.split_whitespace().map(String::from).collect::<Vec<_>>();
+    assert_eq!(
+        Options::parse(&args("src --filter maps --junit out.xml")),
+        Ok(Options {
+            path: "src".to_string(),
+            filter: Some("maps".to_string()),
+            junit: Some("out.xml".to_string()),
+            help: false,
+        })
+    );
+    assert!(Options::parse(&args("--help")).unwrap().help);
+    assert!(Options::parse(&args("src -h")).unwrap().help);
+    assert_eq!(Options::parse(&args("")).unwrap().path, ".");
+    assert!(Options::parse(&args("--filter")).is_err());
+    assert!(Options::parse(&args("a b")).is_err());
+}
@@ -1,10 +1,38 @@
 use std::fmt;
 use std::vec::Vec;
 
+use crate::diagnostics::Diagnostic;
+
 #[derive(Clone, PartialEq, Copy)]
 pub struct Token<'a> {
     pub string: &'a str,
     pub token_type: TokenType,
+    pub span: Span,
+}
+
+// A location in the source file, lines and columns are 1 indexed.
+#[derive(Debug, Clone, PartialEq, Eq, Copy, Default, Hash)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+    pub len: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+// Checks that `string` starts with the whole word `word`, ie. "int" matches "int x" but not "integer"
+fn starts_with_word(string: &str, word: &str) -> bool {
+    string.starts_with(word)
+        && !string
+            .get(word.len()..)
+            .unwrap_or("")
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_alphanumeric() || c == '_')
 }
 
 // impl<'a> FromIterator<&'a Token<'a>> for Token<'a> {
@@ -14,7 +42,7 @@ pub struct Token<'a> {
 //             v.push(i);
 //         }
 //         v
-        
+
 //         // todo!()
 //     }
 // }
@@ -33,7 +61,6 @@ impl fmt::Debug for Token<'_> {
     }
 }
 
-
 #[derive(Debug, Clone, PartialEq, Copy)]
 /*  The top level token enum */
 pub enum TokenType {
@@ -80,7 +107,7 @@ pub enum Literals {
     BuiltIn(BuiltinType),
 }
 
-#[derive(Debug, Clone, PartialEq, Copy)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Copy)]
 pub enum PrimitiveType {
     Int,
     Float,
@@ -89,7 +116,7 @@ pub enum PrimitiveType {
     Atom,
 }
 
-#[derive(Debug, Clone, PartialEq, Copy)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Copy)]
 pub enum BuiltinType {
     // in order of importance
     String, // "\" my string of things {interpolated expression} "
@@ -98,68 +125,76 @@ pub enum BuiltinType {
     List,   // [thing, another, thing ]
     Range,  // 1..5 || -1..-10..-1 <- not sure about the step...
     Map,    // {key: value, name: item}
-    Stream, // not entirely sure how the stream works...
+    Stream, // a lazy sequence, see `streams::Stream`
+    Result, // {:ok, value} or {:error, reason}, result[T, E]
 }
 
 pub fn tokenize_type(string: &str) -> Result<(&str, TokenType), &str> {
     // Stream,
-    if string.starts_with("stream") {
+    if starts_with_word(string, "stream") {
         return Ok((
             "stream",
             TokenType::Type(Types::BuiltIn(BuiltinType::Stream)),
         ));
     }
     // String,
-    else if string.starts_with("str") {
+    else if starts_with_word(string, "str") {
         return Ok(("str", TokenType::Type(Types::BuiltIn(BuiltinType::String))));
     }
     // Enum,
-    else if string.starts_with("enum") {
+    else if starts_with_word(string, "enum") {
         return Ok(("enum", TokenType::Type(Types::BuiltIn(BuiltinType::Enum))));
     }
     // Tuple,
-    else if string.starts_with("tuple") {
+    else if starts_with_word(string, "tuple") {
         return Ok(("tuple", TokenType::Type(Types::BuiltIn(BuiltinType::Tuple))));
     }
     // List,
-    else if string.starts_with("list") {
+    else if starts_with_word(string, "list") {
         return Ok(("list", TokenType::Type(Types::BuiltIn(BuiltinType::List))));
     }
     // Range,
-    else if string.starts_with("range") {
+    else if starts_with_word(string, "range") {
         return Ok(("range", TokenType::Type(Types::BuiltIn(BuiltinType::Range))));
     }
     // Map,
-    else if string.starts_with("map") {
+    else if starts_with_word(string, "map") {
         return Ok(("map", TokenType::Type(Types::BuiltIn(BuiltinType::Map))));
     }
+    // Result,
+    else if starts_with_word(string, "result") {
+        return Ok((
+            "result",
+            TokenType::Type(Types::BuiltIn(BuiltinType::Result)),
+        ));
+    }
     // Int,
-    else if string.starts_with("int") {
+    else if starts_with_word(string, "int") {
         return Ok(("int", TokenType::Type(Types::Primitive(PrimitiveType::Int))));
     }
     // Float,
-    else if string.starts_with("float") {
+    else if starts_with_word(string, "float") {
         return Ok((
             "float",
             TokenType::Type(Types::Primitive(PrimitiveType::Float)),
         ));
     }
     // Bool,
-    else if string.starts_with("bool") {
+    else if starts_with_word(string, "bool") {
         return Ok((
             "bool",
             TokenType::Type(Types::Primitive(PrimitiveType::Bool)),
         ));
     }
     // Char,
-    else if string.starts_with("char") {
+    else if starts_with_word(string, "char") {
         return Ok((
             "char",
             TokenType::Type(Types::Primitive(PrimitiveType::Char)),
         ));
     }
     // Atom,
-    else if string.starts_with("atom") {
+    else if starts_with_word(string, "atom") {
         return Ok((
             "atom",
             TokenType::Type(Types::Primitive(PrimitiveType::Atom)),
@@ -194,6 +229,13 @@ fn test_tokenize_type() {
         tokenize_type("map"),
         Ok(("map", TokenType::Type(Types::BuiltIn(BuiltinType::Map))))
     );
+    assert_eq!(
+        tokenize_type("result"),
+        Ok((
+            "result",
+            TokenType::Type(Types::BuiltIn(BuiltinType::Result))
+        ))
+    );
     assert_eq!(
         tokenize_type("stream"),
         Ok((
@@ -233,6 +275,10 @@ fn test_tokenize_type() {
             TokenType::Type(Types::Primitive(PrimitiveType::Atom))
         ))
     );
+    // types must be whole words
+    assert!(tokenize_type("string").is_err());
+    assert!(tokenize_type("integer").is_err());
+    assert!(tokenize_type("mapper").is_err());
 }
 
 #[derive(Debug, Clone, PartialEq, Copy)]
@@ -257,32 +303,40 @@ pub enum Operators {
     Equal,       // "="
     DoubleDot,   // ".." (for ranges)
     Pipe,        // "|>"
+    And,         // "&&"
+    Or,          // "||"
+    Not,         // "!"
+    Try,         // "?" (returns an error result, unwraps an ok one)
 }
 
-// pub fn operator_to_string(op: Operators) -> &'static str {
-//     match op {
-//         Operators::Add => "+",
-//         Operators::EnumConcat => "++",
-//         Operators::Subtract => "-",
-//         Operators::Div => "/",
-//         Operators::Mult => "*",
-//         Operators::Modulo => "%",
-//         Operators::Exp => "^",
-//         Operators::Concat => "<>",
-//         Operators::LessThan => "<",
-//         Operators::GreaterThan => ">",
-//         Operators::LEq => "<=",
-//         Operators::GEq => ">=",
-//         Operators::BEq => "==",
-//         Operators::BNEq => "!=",
-//         Operators::Lshift => "<<",
-//         Operators::Rshift => ">>",
-//         Operators::Into => "->",
-//         Operators::Equal => "=",
-//         Operators::DoubleDot => "..",
-//         Operators::Pipe => "|>"
-//     }
-// }
+pub fn operator_to_string(op: Operators) -> &'static str {
+    match op {
+        Operators::Add => "+",
+        Operators::EnumConcat => "++",
+        Operators::Subtract => "-",
+        Operators::Div => "/",
+        Operators::Mult => "*",
+        Operators::Modulo => "%",
+        Operators::Exp => "^",
+        Operators::Concat => "<>",
+        Operators::LessThan => "<",
+        Operators::GreaterThan => ">",
+        Operators::LEq => "<=",
+        Operators::GEq => ">=",
+        Operators::BEq => "==",
+        Operators::BNEq => "!=",
+        Operators::Lshift => "<<",
+        Operators::Rshift => ">>",
+        Operators::Into => "->",
+        Operators::Equal => "=",
+        Operators::DoubleDot => "..",
+        Operators::Pipe => "|>",
+        Operators::And => "&&",
+        Operators::Or => "||",
+        Operators::Not => "!",
+        Operators::Try => "?",
+    }
+}
 
 pub fn tokenize_operator(string: &str) -> Result<(&str, TokenType), &str> {
     match string.chars().take(2).collect::<String>().as_str() {
@@ -297,6 +351,8 @@ pub fn tokenize_operator(string: &str) -> Result<(&str, TokenType), &str> {
         "->" => return Ok(("->", TokenType::Operator(Operators::Into))),
         ".." => return Ok(("..", TokenType::Operator(Operators::DoubleDot))),
         "|>" => return Ok(("|>", TokenType::Operator(Operators::Pipe))),
+        "&&" => return Ok(("&&", TokenType::Operator(Operators::And))),
+        "||" => return Ok(("||", TokenType::Operator(Operators::Or))),
         _ => {}
     }
     match string.chars().take(1).collect::<String>().as_str() {
@@ -309,6 +365,8 @@ pub fn tokenize_operator(string: &str) -> Result<(&str, TokenType), &str> {
         "<" => return Ok(("<", TokenType::Operator(Operators::LessThan))),
         ">" => return Ok((">", TokenType::Operator(Operators::GreaterThan))),
         "=" => return Ok(("=", TokenType::Operator(Operators::Equal))),
+        "!" => return Ok(("!", TokenType::Operator(Operators::Not))),
+        "?" => return Ok(("?", TokenType::Operator(Operators::Try))),
         _ => {}
     };
 
@@ -321,6 +379,10 @@ fn test_tokenize_operator() {
         tokenize_operator("+"),
         Ok(("+", TokenType::Operator(Operators::Add)))
     );
+    assert_eq!(
+        tokenize_operator("?"),
+        Ok(("?", TokenType::Operator(Operators::Try)))
+    );
     assert_eq!(
         tokenize_operator("-"),
         Ok(("-", TokenType::Operator(Operators::Subtract)))
@@ -397,6 +459,18 @@ fn test_tokenize_operator() {
         tokenize_operator("|>"),
         Ok(("|>", TokenType::Operator(Operators::Pipe)))
     );
+    assert_eq!(
+        tokenize_operator("&&"),
+        Ok(("&&", TokenType::Operator(Operators::And)))
+    );
+    assert_eq!(
+        tokenize_operator("||"),
+        Ok(("||", TokenType::Operator(Operators::Or)))
+    );
+    assert_eq!(
+        tokenize_operator("!"),
+        Ok(("!", TokenType::Operator(Operators::Not)))
+    );
 }
 
 /*
@@ -428,48 +502,60 @@ pub enum Keywords {
     Assert, // allows for runtime assertions / testing
     Panic, // nukes everything and panics!
     Test, // allows for testing in modules, which auto tests on `rho test || rho t`
+    While, // a basic c style while loop
+    In,   // for <ident> in <iterable>
+    Var,  // a mutable binding, bindings are immutable by default
+    Const, // a binding evaluated at compile time
     EoF,
 }
 
 pub fn tokenize_keyword(string: &str) -> Result<(&str, TokenType), &str> {
-    if string.starts_with("import") {
+    if starts_with_word(string, "import") {
         return Ok(("import", TokenType::Keyword(Keywords::Import)));
-    } else if string.starts_with("include") {
+    } else if starts_with_word(string, "include") {
         return Ok(("include", TokenType::Keyword(Keywords::Include)));
-    } else if string.starts_with("struct") {
+    } else if starts_with_word(string, "struct") {
         return Ok(("struct", TokenType::Keyword(Keywords::Struct)));
-    } else if string.starts_with("for") {
+    } else if starts_with_word(string, "for") {
         return Ok(("for", TokenType::Keyword(Keywords::For)));
-    } else if string.starts_with("continue") {
+    } else if starts_with_word(string, "continue") {
         return Ok(("continue", TokenType::Keyword(Keywords::Continue)));
-    } else if string.starts_with("break") {
+    } else if starts_with_word(string, "break") {
         return Ok(("break", TokenType::Keyword(Keywords::Break)));
-    } else if string.starts_with("func") {
+    } else if starts_with_word(string, "func") {
         return Ok(("func", TokenType::Keyword(Keywords::Func)));
-    } else if string.starts_with("fn") {
+    } else if starts_with_word(string, "fn") {
         return Ok(("fn", TokenType::Keyword(Keywords::Fn)));
-    } else if string.starts_with("match") {
+    } else if starts_with_word(string, "match") {
         return Ok(("match", TokenType::Keyword(Keywords::Match)));
-    } else if string.starts_with("cond") {
+    } else if starts_with_word(string, "cond") {
         return Ok(("cond", TokenType::Keyword(Keywords::Cond)));
-    } else if string.starts_with("when") {
+    } else if starts_with_word(string, "when") {
         return Ok(("when", TokenType::Keyword(Keywords::When)));
-    } else if string.starts_with("if") {
+    } else if starts_with_word(string, "if") {
         return Ok(("if", TokenType::Keyword(Keywords::If)));
-    } else if string.starts_with("elif") {
+    } else if starts_with_word(string, "elif") {
         return Ok(("elif", TokenType::Keyword(Keywords::Elif)));
-    } else if string.starts_with("else") {
+    } else if starts_with_word(string, "else") {
         return Ok(("else", TokenType::Keyword(Keywords::Else)));
-    } else if string.starts_with("return") {
+    } else if starts_with_word(string, "return") {
         return Ok(("return", TokenType::Keyword(Keywords::Return)));
-    } else if string.starts_with("interface") {
+    } else if starts_with_word(string, "interface") {
         return Ok(("interface", TokenType::Keyword(Keywords::Interface)));
-    } else if string.starts_with("assert") {
+    } else if starts_with_word(string, "assert") {
         return Ok(("assert", TokenType::Keyword(Keywords::Assert)));
-    } else if string.starts_with("panic") {
+    } else if starts_with_word(string, "panic") {
         return Ok(("panic", TokenType::Keyword(Keywords::Panic)));
-    } else if string.starts_with("test") {
+    } else if starts_with_word(string, "test") {
         return Ok(("test", TokenType::Keyword(Keywords::Test)));
+    } else if starts_with_word(string, "while") {
+        return Ok(("while", TokenType::Keyword(Keywords::While)));
+    } else if starts_with_word(string, "in") {
+        return Ok(("in", TokenType::Keyword(Keywords::In)));
+    } else if starts_with_word(string, "var") {
+        return Ok(("var", TokenType::Keyword(Keywords::Var)));
+    } else if starts_with_word(string, "const") {
+        return Ok(("const", TokenType::Keyword(Keywords::Const)));
     } else if string.starts_with("<EOF>") {
         return Ok(("<EOF>", TokenType::Keyword(Keywords::EoF)));
     }
@@ -554,14 +640,37 @@ fn test_tokenize_keyword() {
         tokenize_keyword("test"),
         Ok(("test", TokenType::Keyword(Keywords::Test)))
     );
+    assert_eq!(
+        tokenize_keyword("while"),
+        Ok(("while", TokenType::Keyword(Keywords::While)))
+    );
+    assert_eq!(
+        tokenize_keyword("in"),
+        Ok(("in", TokenType::Keyword(Keywords::In)))
+    );
+    assert_eq!(
+        tokenize_keyword("var"),
+        Ok(("var", TokenType::Keyword(Keywords::Var)))
+    );
+    assert_eq!(
+        tokenize_keyword("const"),
+        Ok(("const", TokenType::Keyword(Keywords::Const)))
+    );
     assert_eq!(
         tokenize_keyword("<EOF>"),
         Ok(("<EOF>", TokenType::Keyword(Keywords::EoF)))
     );
+    // keywords must be whole words
+    assert!(tokenize_keyword("format").is_err());
+    assert!(tokenize_keyword("testing").is_err());
+    assert!(tokenize_keyword("index").is_err());
+    assert!(tokenize_keyword("variable").is_err());
 }
 
 pub fn tokenize_identifier(string: &str) -> Result<(&str, TokenType), &str> {
-    if !string.chars().next().unwrap_or(' ').is_alphabetic() {
+    // `_` on its own is the wildcard pattern
+    let first = string.chars().next().unwrap_or(' ');
+    if !first.is_alphabetic() && first != '_' {
         return Err("Err: could not parse identifer | Ident must start with alphabetic");
     }
     let ident = string
@@ -580,8 +689,14 @@ pub fn tokenize_string_literal(string: &str) -> Result<(&str, TokenType), &str>
     if !string.starts_with('\"') || string.len() < 2 {
         return Err("Not a string literal");
     }
-    for i in 1..(string.len()) {
-        if string.as_bytes()[i] == b'\"' && string.as_bytes()[i - 1] != b'\\' {
+    // an escape is skipped as a pair, so `"\\"` ends at its second quote
+    let mut escaped = false;
+    for (i, b) in string.bytes().enumerate().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if b == b'\\' {
+            escaped = true;
+        } else if b == b'\"' {
             return Ok((
                 string.get(0..i + 1).unwrap(),
                 TokenType::Literal(Literals::BuiltIn(BuiltinType::String)),
@@ -603,6 +718,10 @@ fn tokenize_atom_literal(string: &str) -> Result<(&str, TokenType), &str> {
         .chars()
         .take_while(|c| c.is_alphabetic())
         .collect::<String>();
+    if atom_string.is_empty() {
+        // a lone `:` is a delimiter, ie. `{key: value}`
+        return Err("Not an atom literal");
+    }
 
     Ok((
         string.get(0..atom_string.len() + 1).unwrap_or(""),
@@ -629,6 +748,72 @@ fn test_tokenize_atom() {
             TokenType::Literal(Literals::Primitive(PrimitiveType::Atom))
         ))
     );
+    assert!(tokenize_atom_literal(": value").is_err());
+}
+
+fn tokenize_bool_literal(string: &str) -> Result<(&str, TokenType), &str> {
+    for word in ["true", "false"] {
+        if starts_with_word(string, word) {
+            return Ok((
+                word,
+                TokenType::Literal(Literals::Primitive(PrimitiveType::Bool)),
+            ));
+        }
+    }
+    Err("Not a bool literal")
+}
+
+fn tokenize_char_literal(string: &str) -> Result<(&str, TokenType), &str> {
+    if !string.starts_with('\'') {
+        return Err("Not a char literal");
+    }
+    let mut chars = string.char_indices().skip(1);
+    let end = match chars.next() {
+        Some((_, '\\')) => chars.nth(1),
+        Some(_) => chars.next(),
+        None => None,
+    };
+    match end {
+        Some((i, '\'')) => Ok((
+            string.get(0..i + 1).unwrap(),
+            TokenType::Literal(Literals::Primitive(PrimitiveType::Char)),
+        )),
+        _ => Err("Err: char literal never closed."),
+    }
+}
+
+#[test]
+fn test_tokenize_bool_and_char() {
+    assert_eq!(
+        tokenize_bool_literal("true false"),
+        Ok((
+            "true",
+            TokenType::Literal(Literals::Primitive(PrimitiveType::Bool))
+        ))
+    );
+    assert!(tokenize_bool_literal("trueish").is_err());
+    assert_eq!(
+        tokenize_char_literal("'a' "),
+        Ok((
+            "'a'",
+            TokenType::Literal(Literals::Primitive(PrimitiveType::Char))
+        ))
+    );
+    assert_eq!(
+        tokenize_char_literal("'\\n'"),
+        Ok((
+            "'\\n'",
+            TokenType::Literal(Literals::Primitive(PrimitiveType::Char))
+        ))
+    );
+    assert!(tokenize_char_literal("'ab'").is_err());
+    assert_eq!(
+        tokenize_string_literal("\"a\\\"b\\\\\" c"),
+        Ok((
+            "\"a\\\"b\\\\\"",
+            TokenType::Literal(Literals::BuiltIn(BuiltinType::String))
+        ))
+    );
 }
 
 #[derive(Debug, Clone, PartialEq, Copy)]
@@ -644,6 +829,7 @@ pub enum Delimiters {
     Comma,
     Period,
     Semicolon,
+    Colon,
 }
 
 pub fn tokenize_delimiter(string: &str) -> Result<(&str, TokenType), &str> {
@@ -659,6 +845,7 @@ pub fn tokenize_delimiter(string: &str) -> Result<(&str, TokenType), &str> {
         "," => return Ok((",", TokenType::Delimiter(Delimiters::Comma))),
         ";" => return Ok((";", TokenType::Delimiter(Delimiters::Semicolon))),
         "." => return Ok((".", TokenType::Delimiter(Delimiters::Period))),
+        ":" => return Ok((":", TokenType::Delimiter(Delimiters::Colon))),
         _ => {}
     };
     Err("Err: Could not parse delimiter")
@@ -710,6 +897,10 @@ fn test_tokenize_delim() {
         tokenize_delimiter("."),
         Ok((".", TokenType::Delimiter(Delimiters::Period)))
     );
+    assert_eq!(
+        tokenize_delimiter(":"),
+        Ok((":", TokenType::Delimiter(Delimiters::Colon)))
+    );
 }
 
 fn tokenize_comment(string: &str) -> Result<(&str, TokenType), &str> {
@@ -764,87 +955,150 @@ fn test_tokenize_comment() {
 }
 
 pub fn tokenize_numeric_literal(string: &str) -> Result<(&str, TokenType), &str> {
-    let init_string = string.to_owned();
     let is_negative = string.starts_with('-');
-    // if is_negative {
-    //     // string = string.get(1..).unwrap_or("")
-    // }
     let mut n = "".to_owned();
     if is_negative {
         n.insert(0, '-');
     }
     let l = n.len();
-    let n = n + string
+    let digits = string
         .get(l..)
         .unwrap_or("")
         .chars()
-        .take_while(|c| c.is_numeric())
-        .collect::<String>()
-        .as_str();
-    // string = string.get(n.len()..).unwrap_or("");
-
-    // if is_negative {
-    //     n.insert(0, '-');
-    // }
+        .take_while(|c| c.is_ascii_digit())
+        .collect::<String>();
+    if digits.is_empty() {
+        // a lone `-` is an operator, ie. `->` or `a - b`
+        return Err("Err: could not parse numeric");
+    }
+    let n = n + digits.as_str();
     let ros = string.get(n.len()..).unwrap_or("");
 
-    if !n.is_empty() {
-        if !ros.is_empty() && ros.as_bytes()[0] == b'.' {
-            // either a float literal or an error
-            if ros.len() >= 2 {
-                if ros.as_bytes()[1] != b'.' {
-                    let dec = ros
-                        .get(1..)
-                        .unwrap_or("")
-                        .chars()
-                        .take_while(|c| c.is_numeric())
-                        .collect::<String>();
-                    let token_string = n + "." + &dec;
-                    return Ok((
-                        string.get(0..token_string.len()).unwrap_or(""),
-                        TokenType::Literal(Literals::Primitive(PrimitiveType::Int)),
-                    ));
-                } else {
-                    return Ok((
-                        string.get(0..n.len()).unwrap_or(""),
-                        TokenType::Literal(Literals::Primitive(PrimitiveType::Int)),
-                    ));
-                }
-            }
-        } else {
-            return Ok((
-                string.get(0..n.len()).unwrap_or(""),
-                TokenType::Literal(Literals::Primitive(PrimitiveType::Int)),
-            ));
-        }
+    if ros.len() >= 2 && ros.as_bytes()[0] == b'.' && ros.as_bytes()[1].is_ascii_digit() {
+        // a float literal, `1..2` is a range and `1.` is an int followed by a period
+        let dec = ros
+            .get(1..)
+            .unwrap_or("")
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect::<String>();
+        let token_string = n + "." + &dec;
+        return Ok((
+            string.get(0..token_string.len()).unwrap_or(""),
+            TokenType::Literal(Literals::Primitive(PrimitiveType::Float)),
+        ));
     }
-    Err("Err: could not parse numeric")
+    Ok((
+        string.get(0..n.len()).unwrap_or(""),
+        TokenType::Literal(Literals::Primitive(PrimitiveType::Int)),
+    ))
 }
 
-#[derive(Debug, Clone)]
+#[test]
+fn test_tokenize_numeric() {
+    assert_eq!(
+        tokenize_numeric_literal("12 "),
+        Ok((
+            "12",
+            TokenType::Literal(Literals::Primitive(PrimitiveType::Int))
+        ))
+    );
+    assert_eq!(
+        tokenize_numeric_literal("-1.5"),
+        Ok((
+            "-1.5",
+            TokenType::Literal(Literals::Primitive(PrimitiveType::Float))
+        ))
+    );
+    assert_eq!(
+        tokenize_numeric_literal("1..10"),
+        Ok((
+            "1",
+            TokenType::Literal(Literals::Primitive(PrimitiveType::Int))
+        ))
+    );
+    assert_eq!(
+        tokenize_numeric_literal("1."),
+        Ok((
+            "1",
+            TokenType::Literal(Literals::Primitive(PrimitiveType::Int))
+        ))
+    );
+    assert!(tokenize_numeric_literal("-> {").is_err());
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum TokenValue {
-    Int(i32),
+    Int(i64),
     String(String),
     // Expression(Expression),
-    Float(f32),
+    Float(f64),
     Bool(bool),
+    Char(char),
+    Atom(String),
+}
+
+// Replaces the escape sequences in a string or char literal
+pub fn unescape(string: &str) -> String {
+    let mut out = String::new();
+    let mut chars = string.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('0') => out.push('\0'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
 }
 
-pub fn tokenize(mut input_string: &str) -> Vec<Token> {
+// Gets the value of a literal token, ie. `"a\n"` -> String("a\n")
+pub fn token_value(token: &Token) -> Result<TokenValue, String> {
+    let string = token.string;
+    match token.token_type {
+        TokenType::Literal(Literals::Primitive(PrimitiveType::Int)) => string
+            .parse()
+            .map(TokenValue::Int)
+            .map_err(|_| format!("integer literal `{}` is out of range", string)),
+        TokenType::Literal(Literals::Primitive(PrimitiveType::Float)) => string
+            .parse()
+            .map(TokenValue::Float)
+            .map_err(|_| format!("invalid float literal `{}`", string)),
+        TokenType::Literal(Literals::Primitive(PrimitiveType::Bool)) => {
+            Ok(TokenValue::Bool(string == "true"))
+        }
+        TokenType::Literal(Literals::Primitive(PrimitiveType::Char)) => {
+            let inner = unescape(string.get(1..string.len() - 1).unwrap_or(""));
+            Ok(TokenValue::Char(inner.chars().next().unwrap_or('\0')))
+        }
+        TokenType::Literal(Literals::Primitive(PrimitiveType::Atom)) => {
+            Ok(TokenValue::Atom(string.get(1..).unwrap_or("").to_string()))
+        }
+        TokenType::Literal(Literals::BuiltIn(BuiltinType::String)) => Ok(TokenValue::String(
+            unescape(string.get(1..string.len() - 1).unwrap_or("")),
+        )),
+        _ => Err(format!("`{}` is not a literal", string)),
+    }
+}
+
+pub fn tokenize(mut input_string: &str) -> Result<Vec<Token<'_>>, Diagnostic> {
     let v = &mut vec![];
-    let mut max_run = 3;
-    // if string.len() > 1 {
-    //     let mut ros = tokenize(string.get(1..).unwrap());
-    //     v.append(&mut ros);
-    // }
-    // let mut input: &str = input_string;
     #[allow(clippy::type_complexity)]
     let tokenizers: Vec<fn(&str) -> Result<(&str, TokenType), &str>> = vec![
         // parse_keyword,
         tokenize_comment,
         tokenize_string_literal,
+        tokenize_char_literal,
         tokenize_numeric_literal,
         tokenize_atom_literal,
+        tokenize_bool_literal,
         tokenize_keyword,
         tokenize_type,
         tokenize_operator,
@@ -852,37 +1106,96 @@ pub fn tokenize(mut input_string: &str) -> Vec<Token> {
         tokenize_identifier,
     ];
 
-    println!("tokenize instr: {:?}", input_string);
-    'tokenLoop: while !input_string.is_empty() && max_run > 0 {
+    let source = input_string;
+    let mut line = 1;
+    let mut line_start = 0;
+    'tokenLoop: while !input_string.is_empty() {
+        let offset = source.len() - input_string.len();
+        let col = source.get(line_start..offset).unwrap_or("").chars().count() + 1;
         if input_string.starts_with('\n') {
-            v.push(Token{
+            v.push(Token {
                 string: "\n",
-                token_type: TokenType::NewLine
-            })
+                token_type: TokenType::NewLine,
+                span: Span { line, col, len: 1 },
+            });
+            line += 1;
+            line_start = offset + 1;
+            input_string = input_string.get(1..).unwrap_or("");
+            continue 'tokenLoop;
+        }
+        if input_string.starts_with([' ', '\t', '\r']) {
+            input_string = input_string.get(1..).unwrap_or("");
+            continue 'tokenLoop;
+        }
+        if input_string.starts_with('"') && tokenize_string_literal(input_string).is_err() {
+            return Err(Diagnostic::error(
+                "string literal never closed",
+                Span { line, col, len: 1 },
+            ));
         }
-        input_string = input_string.trim_start();
         for tokenizer in tokenizers.iter() {
-            match tokenizer(input_string) {
-                Ok((s, t_type)) => {
-                    v.push(Token {
-                        string: s,
-                        token_type: t_type,
-                    });
-                    input_string = input_string.get(s.len()..).unwrap_or("");
-                    continue 'tokenLoop;
+            if let Ok((s, t_type)) = tokenizer(input_string) {
+                v.push(Token {
+                    string: s,
+                    token_type: t_type,
+                    span: Span {
+                        line,
+                        col,
+                        len: s.chars().count(),
+                    },
+                });
+                // block comments and strings may span several lines
+                if let Some(last_newline) = s.rfind('\n') {
+                    line += s.matches('\n').count();
+                    line_start = offset + last_newline + 1;
                 }
-                Err(s) => {}
+                input_string = input_string.get(s.len()..).unwrap_or("");
+                continue 'tokenLoop;
             }
         }
 
-        println!("instr: {:?} | max_run: {}", input_string, max_run);
-        max_run -= 1;
+        let c = input_string.chars().next().unwrap_or(' ');
+        return Err(Diagnostic::error(
+            format!("unexpected character `{}`", c),
+            Span { line, col, len: 1 },
+        ));
     }
 
-    v.to_vec()
+    Ok(v.to_vec())
 }
 
-
+#[test]
+fn test_tokenize() {
+    let tokens = tokenize("int i = 1\nfn x -> {\n  x-1 }").unwrap();
+    let types: Vec<TokenType> = tokens.iter().map(|t| t.token_type).collect();
+    assert_eq!(
+        types,
+        vec![
+            TokenType::Type(Types::Primitive(PrimitiveType::Int)),
+            TokenType::Identifier,
+            TokenType::Operator(Operators::Equal),
+            TokenType::Literal(Literals::Primitive(PrimitiveType::Int)),
+            TokenType::NewLine,
+            TokenType::Keyword(Keywords::Fn),
+            TokenType::Identifier,
+            TokenType::Operator(Operators::Into),
+            TokenType::Delimiter(Delimiters::BraceOpen),
+            TokenType::NewLine,
+            TokenType::Identifier,
+            TokenType::Literal(Literals::Primitive(PrimitiveType::Int)),
+            TokenType::Delimiter(Delimiters::BraceClose),
+        ]
+    );
+    assert_eq!(
+        tokens[10].span,
+        Span {
+            line: 3,
+            col: 3,
+            len: 1
+        }
+    );
+    assert!(tokenize("str s = \"never closed").is_err());
+}
 
 //     // parse an atom
 //     if string.starts_with(':') {
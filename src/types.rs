@@ -0,0 +1,249 @@
+use core::fmt;
+use std::collections::HashMap;
+
+use crate::tokens::*;
+
+// The type of a rho value, unlike `tokens::Types` this can carry type arguments ie. `list[int]`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Type {
+    Primitive(PrimitiveType),
+    BuiltIn(BuiltinType, Vec<Type>), // str, list[T], map[K, V], tuple[A, B], range, stream[T]
+    Named(String, Vec<Type>),        // a user defined struct, enum or interface, ie. Pair[int, str]
+    Param(String),                   // a generic type parameter, ie. T in `func id[T](T x) -> T`
+    Function(Vec<Type>, Box<Type>),  // fn(int, int) -> int
+    Unit,                            // no value, ie. a func without a return
+    Var(usize),                      // not known yet, the checker solves these by unification
+}
+
+impl Type {
+    pub fn int() -> Type {
+        Type::Primitive(PrimitiveType::Int)
+    }
+
+    pub fn float() -> Type {
+        Type::Primitive(PrimitiveType::Float)
+    }
+
+    pub fn bool() -> Type {
+        Type::Primitive(PrimitiveType::Bool)
+    }
+
+    pub fn char() -> Type {
+        Type::Primitive(PrimitiveType::Char)
+    }
+
+    pub fn atom() -> Type {
+        Type::Primitive(PrimitiveType::Atom)
+    }
+
+    pub fn string() -> Type {
+        Type::BuiltIn(BuiltinType::String, vec![])
+    }
+
+    pub fn list(element: Type) -> Type {
+        Type::BuiltIn(BuiltinType::List, vec![element])
+    }
+
+    pub fn map(key: Type, value: Type) -> Type {
+        Type::BuiltIn(BuiltinType::Map, vec![key, value])
+    }
+
+    pub fn tuple(elements: Vec<Type>) -> Type {
+        Type::BuiltIn(BuiltinType::Tuple, elements)
+    }
+
+    // `{:ok, value}` or `{:error, reason}`
+    pub fn result(ok: Type, error: Type) -> Type {
+        Type::BuiltIn(BuiltinType::Result, vec![ok, error])
+    }
+
+    pub fn range() -> Type {
+        Type::BuiltIn(BuiltinType::Range, vec![])
+    }
+
+    pub fn function(params: Vec<Type>, ret: Type) -> Type {
+        Type::Function(params, Box::new(ret))
+    }
+
+    // The type a type keyword token names, ie. `int` or `list` (whose element type is filled in later)
+    pub fn from_token_type(types: Types) -> Type {
+        match types {
+            Types::Primitive(p) => Type::Primitive(p),
+            Types::BuiltIn(b) => Type::BuiltIn(b, vec![]),
+        }
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            Type::Primitive(PrimitiveType::Int) | Type::Primitive(PrimitiveType::Float)
+        )
+    }
+
+    // Whether values of this type can be map keys, see `maps::hash`
+    pub fn is_hashable(&self) -> bool {
+        match self {
+            Type::Primitive(PrimitiveType::Float) | Type::Function(..) => false,
+            Type::BuiltIn(BuiltinType::Map | BuiltinType::Stream, _) => false,
+            Type::BuiltIn(_, args) => args.iter().all(Type::is_hashable),
+            _ => true,
+        }
+    }
+
+    // The type of what a `for` loop over this type goes through, a map's entries are tuples
+    pub fn element_type(&self) -> Option<Type> {
+        match self {
+            Type::BuiltIn(BuiltinType::Range, _) => Some(Type::int()),
+            Type::BuiltIn(BuiltinType::String, _) => Some(Type::char()),
+            Type::BuiltIn(BuiltinType::List | BuiltinType::Stream, args) => args.first().cloned(),
+            Type::BuiltIn(BuiltinType::Map, args) if args.len() == 2 => {
+                Some(Type::tuple(args.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    // The name methods of this type are namespaced under, ie. `Point` for `func Point.show(..)`
+    pub fn base_name(&self) -> String {
+        match self {
+            Type::Named(name, _) => name.to_owned(),
+            Type::Param(name) => name.to_owned(),
+            Type::BuiltIn(BuiltinType::String, _) => "str".to_string(),
+            Type::BuiltIn(b, _) => format!("{:?}", b).to_lowercase(),
+            Type::Primitive(p) => format!("{:?}", p).to_lowercase(),
+            Type::Function(_, _) => "fn".to_string(),
+            Type::Unit => "unit".to_string(),
+            Type::Var(_) => "_".to_string(),
+        }
+    }
+
+    // The direct type arguments of this type, ie. [int, str] for `Pair[int, str]`
+    pub fn type_args(&self) -> Vec<Type> {
+        match self {
+            Type::Named(_, args) | Type::BuiltIn(_, args) => args.clone(),
+            Type::Function(params, ret) => {
+                let mut args = params.clone();
+                args.push(*ret.clone());
+                args
+            }
+            _ => vec![],
+        }
+    }
+
+    // Turns the names of in scope type parameters into `Type::Param`s, the parser can't tell
+    // `T` from a struct named `T` until it has seen the type parameter list.
+    pub fn bind_params(&self, names: &[String]) -> Type {
+        match self {
+            Type::Named(name, args) if args.is_empty() && names.contains(name) => {
+                Type::Param(name.to_owned())
+            }
+            Type::Named(name, args) => Type::Named(
+                name.to_owned(),
+                args.iter().map(|a| a.bind_params(names)).collect(),
+            ),
+            Type::BuiltIn(b, args) => {
+                Type::BuiltIn(*b, args.iter().map(|a| a.bind_params(names)).collect())
+            }
+            Type::Function(params, ret) => Type::Function(
+                params.iter().map(|p| p.bind_params(names)).collect(),
+                Box::new(ret.bind_params(names)),
+            ),
+            _ => self.clone(),
+        }
+    }
+
+    pub fn substitute(&self, subst: &HashMap<String, Type>) -> Type {
+        match self {
+            Type::Param(name) => subst.get(name).cloned().unwrap_or(self.clone()),
+            Type::Named(name, args) => Type::Named(
+                name.to_owned(),
+                args.iter().map(|a| a.substitute(subst)).collect(),
+            ),
+            Type::BuiltIn(b, args) => {
+                Type::BuiltIn(*b, args.iter().map(|a| a.substitute(subst)).collect())
+            }
+            Type::Function(params, ret) => Type::Function(
+                params.iter().map(|p| p.substitute(subst)).collect(),
+                Box::new(ret.substitute(subst)),
+            ),
+            _ => self.clone(),
+        }
+    }
+
+    pub fn contains_vars(&self) -> bool {
+        match self {
+            Type::Var(_) => true,
+            Type::Named(_, args) | Type::BuiltIn(_, args) => args.iter().any(|a| a.contains_vars()),
+            Type::Function(params, ret) => {
+                params.iter().any(|p| p.contains_vars()) || ret.contains_vars()
+            }
+            _ => false,
+        }
+    }
+
+    pub fn contains_params(&self) -> bool {
+        match self {
+            Type::Param(_) => true,
+            Type::Named(_, args) | Type::BuiltIn(_, args) => {
+                args.iter().any(|a| a.contains_params())
+            }
+            Type::Function(params, ret) => {
+                params.iter().any(|p| p.contains_params()) || ret.contains_params()
+            }
+            _ => false,
+        }
+    }
+}
+
+fn join_types(types: &[Type]) -> String {
+    types
+        .iter()
+        .map(|t| t.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// Types are displayed in rho syntax, ie. `map[str, list[int]]`
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::BuiltIn(_, args) | Type::Named(_, args) if !args.is_empty() => {
+                write!(f, "{}[{}]", self.base_name(), join_types(args))
+            }
+            Type::Function(params, ret) if **ret == Type::Unit => {
+                write!(f, "fn({})", join_types(params))
+            }
+            Type::Function(params, ret) => write!(f, "fn({}) -> {}", join_types(params), ret),
+            _ => write!(f, "{}", self.base_name()),
+        }
+    }
+}
+
+#[test]
+fn test_type_display() {
+    assert_eq!(
+        Type::map(Type::string(), Type::list(Type::int())).to_string(),
+        "map[str, list[int]]"
+    );
+    assert_eq!(
+        Type::function(
+            vec![Type::Param("T".to_string())],
+            Type::Param("U".to_string())
+        )
+        .to_string(),
+        "fn(T) -> U"
+    );
+    assert_eq!(
+        Type::Named("Pair".to_string(), vec![Type::int(), Type::float()]).to_string(),
+        "Pair[int, float]"
+    );
+}
+
+#[test]
+fn test_type_substitute() {
+    let t = Type::list(Type::Named("T".to_string(), vec![])).bind_params(&["T".to_string()]);
+    assert_eq!(t, Type::list(Type::Param("T".to_string())));
+    let subst = HashMap::from([("T".to_string(), Type::int())]);
+    assert_eq!(t.substitute(&subst), Type::list(Type::int()));
+    assert!(t.contains_params());
+}
@@ -0,0 +1,293 @@
+use core::fmt;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::atoms::{self, Atom};
+use crate::bytecode;
+use crate::gc;
+use crate::maps::Map;
+use crate::parsers::*;
+use crate::streams::Stream;
+use crate::tokens::*;
+
+/* A value of a running rho program. Collections are shared between copies and copied when one of
+ * them is written to (`Rc::make_mut`), so assigning a list to a second binding and changing it
+ * through one binding never changes the other.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Unit,
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    Atom(Atom),
+    String(Rc<str>),
+    List(Rc<Vec<Value>>),
+    Tuple(Rc<Vec<Value>>),
+    Map(Rc<Map>),                              // iterates in insertion order
+    Range(i64, i64, i64),                      // start, end (exclusive) and step
+    Struct(Rc<str>, Rc<Vec<(String, Value)>>), // fields in declaration order
+    Variant(Rc<str>, Rc<str>, Rc<Vec<Value>>), // enum name, variant name, values
+    Closure(Rc<Closure>),
+    Compiled(Rc<CompiledClosure>), // a function of a program running on the VM
+    Builtin(&'static str),         // a native function, by its dotted name
+    Stream(Rc<Stream>),
+}
+
+// A function value, the top level functions, nested functions and lambdas
+#[derive(Debug)]
+pub struct Closure {
+    pub name: String, // "fn" for lambdas
+    pub params: Vec<Ident>,
+    pub body: Rc<Expression>,
+    pub env: Env, // the scope the function was defined in
+}
+
+// Two closures are only equal when they are the same closure
+impl PartialEq for Closure {
+    fn eq(&self, other: &Closure) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/* A function value on the VM, a compiled function and the variables it captured. Each captured
+ * variable lives in a scope of its own, so the closures sharing it see each other's changes and
+ * `gc::collect` finds the cycles a closure makes through them, as it does for `Closure`.
+ */
+#[derive(Debug)]
+pub struct CompiledClosure {
+    pub function: Rc<bytecode::Function>,
+    pub upvalues: Vec<Env>,
+}
+
+impl PartialEq for CompiledClosure {
+    fn eq(&self, other: &CompiledClosure) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+pub type Env = Rc<RefCell<Scope>>;
+
+// The bindings of a block or a call, by the symbol the resolver gave them
+#[derive(Debug, Default)]
+pub struct Scope {
+    values: HashMap<SymbolId, Value>,
+    parent: Option<Env>,
+}
+
+impl Scope {
+    pub fn new(parent: Option<Env>) -> Env {
+        let scope = Rc::new(RefCell::new(Scope {
+            values: HashMap::new(),
+            parent,
+        }));
+        gc::track(&scope);
+        scope
+    }
+
+    pub fn parent(&self) -> Option<&Env> {
+        self.parent.as_ref()
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.values.values()
+    }
+
+    // Empties a scope `gc::collect` found to be garbage, the caller drops what it held
+    pub fn clear(&mut self) -> (HashMap<SymbolId, Value>, Option<Env>) {
+        (std::mem::take(&mut self.values), self.parent.take())
+    }
+}
+
+pub fn lookup(env: &Env, id: SymbolId) -> Option<Value> {
+    let scope = env.borrow();
+    match scope.values.get(&id) {
+        Some(v) => Some(v.clone()),
+        None => scope.parent.as_ref().and_then(|p| lookup(p, id)),
+    }
+}
+
+pub fn define(env: &Env, id: SymbolId, value: Value) {
+    env.borrow_mut().values.insert(id, value);
+}
+
+// Replaces the value of an existing binding, returns false when there is none
+pub fn assign(env: &Env, id: SymbolId, value: Value) -> bool {
+    let mut scope = env.borrow_mut();
+    if let Some(slot) = scope.values.get_mut(&id) {
+        *slot = value;
+        return true;
+    }
+    match scope.parent.clone() {
+        Some(p) => {
+            drop(scope);
+            assign(&p, id, value)
+        }
+        None => false,
+    }
+}
+
+// Moves the value out of a binding, leaving `unit` until it is assigned again
+pub fn take(env: &Env, id: SymbolId) -> Option<Value> {
+    let mut scope = env.borrow_mut();
+    if let Some(slot) = scope.values.get_mut(&id) {
+        return Some(std::mem::replace(slot, Value::Unit));
+    }
+    let parent = scope.parent.clone();
+    drop(scope);
+    parent.and_then(|p| take(&p, id))
+}
+
+impl Value {
+    pub fn string(s: &str) -> Value {
+        Value::String(Rc::from(s))
+    }
+
+    pub fn list(elements: Vec<Value>) -> Value {
+        Value::List(Rc::new(elements))
+    }
+
+    pub fn tuple(elements: Vec<Value>) -> Value {
+        Value::Tuple(Rc::new(elements))
+    }
+
+    // `{:ok, value}`
+    pub fn ok(value: Value) -> Value {
+        Value::tuple(vec![Value::Atom(atoms::OK), value])
+    }
+
+    // `{:error, reason}`
+    pub fn error(reason: Value) -> Value {
+        Value::tuple(vec![Value::Atom(atoms::ERROR), reason])
+    }
+
+    pub fn from_literal(literal: &TokenValue) -> Value {
+        match literal {
+            TokenValue::Int(i) => Value::Int(*i),
+            TokenValue::Float(f) => Value::Float(*f),
+            TokenValue::Bool(b) => Value::Bool(*b),
+            TokenValue::Char(c) => Value::Char(*c),
+            TokenValue::String(s) => Value::string(s),
+            TokenValue::Atom(a) => Value::Atom(atoms::intern(a)),
+        }
+    }
+
+    // The name methods of the value's type are namespaced under, as in `types::Type::base_name`
+    pub fn type_name(&self) -> String {
+        match self {
+            Value::Unit => "unit".to_string(),
+            Value::Int(_) => "int".to_string(),
+            Value::Float(_) => "float".to_string(),
+            Value::Bool(_) => "bool".to_string(),
+            Value::Char(_) => "char".to_string(),
+            Value::Atom(_) => "atom".to_string(),
+            Value::String(_) => "str".to_string(),
+            Value::List(_) => "list".to_string(),
+            Value::Tuple(_) => "tuple".to_string(),
+            Value::Map(_) => "map".to_string(),
+            Value::Range(..) => "range".to_string(),
+            Value::Struct(name, _) | Value::Variant(name, _, _) => name.to_string(),
+            Value::Closure(_) | Value::Compiled(_) | Value::Builtin(_) => "fn".to_string(),
+            Value::Stream(_) => "stream".to_string(),
+        }
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        matches!(self, Value::Bool(true))
+    }
+
+    // What a `for` loop goes through, a map's entries are `{key, value}` tuples
+    pub fn elements(&self) -> Option<Box<dyn Iterator<Item = Value> + '_>> {
+        Some(match self {
+            Value::Range(start, end, step) => Box::new(range(*start, *end, *step).map(Value::Int)),
+            Value::List(elements) => Box::new(elements.iter().cloned()),
+            Value::String(s) => Box::new(s.chars().map(Value::Char)),
+            Value::Map(map) => Box::new(
+                map.iter()
+                    .map(|(k, v)| Value::tuple(vec![k.clone(), v.clone()])),
+            ),
+            _ => return None,
+        })
+    }
+
+    // The order `<` and sorting use, lists and tuples compare element by element
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a.partial_cmp(b),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::String(a), Value::String(b)) => a.partial_cmp(b),
+            (Value::Char(a), Value::Char(b)) => a.partial_cmp(b),
+            (Value::Bool(a), Value::Bool(b)) => a.partial_cmp(b),
+            (Value::Atom(a), Value::Atom(b)) => a.to_string().partial_cmp(&b.to_string()),
+            (Value::List(a), Value::List(b)) | (Value::Tuple(a), Value::Tuple(b)) => {
+                for (x, y) in a.iter().zip(b.iter()) {
+                    match x.compare(y)? {
+                        Ordering::Equal => {}
+                        ordering => return Some(ordering),
+                    }
+                }
+                Some(a.len().cmp(&b.len()))
+            }
+            _ => None,
+        }
+    }
+}
+
+// The values of `start..end..step`, `end` itself is never included
+pub fn range(start: i64, end: i64, step: i64) -> impl Iterator<Item = i64> {
+    let mut next = Some(start);
+    std::iter::from_fn(move || {
+        let current = next?;
+        let in_range = if step > 0 {
+            current < end
+        } else {
+            current > end
+        };
+        if !in_range {
+            return None;
+        }
+        next = current.checked_add(step);
+        Some(current)
+    })
+}
+
+// What `IO.print` writes, strings and chars are written as they are and anything else as
+// `rho_core::inspect` renders it
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Char(c) => write!(f, "{}", c),
+            Value::String(s) => write!(f, "{}", s),
+            _ => write!(f, "{}", crate::rho_core::inspect(self)),
+        }
+    }
+}
+
+#[test]
+fn test_value_display() {
+    let v = Value::list(vec![
+        Value::string("a"),
+        Value::Char('b'),
+        Value::Float(1.0),
+        Value::tuple(vec![Value::Atom(atoms::OK), Value::Int(2)]),
+    ]);
+    assert_eq!(v.to_string(), "[\"a\", 'b', 1.0, {:ok, 2}]");
+    assert_eq!(Value::string("rho").to_string(), "rho");
+    let none = Value::Variant(Rc::from("Option"), Rc::from("None"), Rc::new(vec![]));
+    assert_eq!(none.to_string(), "Option.None");
+}
+
+#[test]
+fn test_scopes() {
+    let globals = Scope::new(None);
+    define(&globals, 0, Value::Int(1));
+    let inner = Scope::new(Some(globals.clone()));
+    define(&inner, 1, Value::Int(2));
+    assert!(assign(&inner, 0, Value::Int(3)));
+    assert_eq!(lookup(&globals, 0), Some(Value::Int(3)));
+    assert_eq!(lookup(&globals, 1), None);
+    assert!(!assign(&inner, 2, Value::Unit));
+}
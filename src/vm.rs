@@ -0,0 +1,1017 @@
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+use crate::bytecode::{Capture, Matcher, Op, Program, Target};
+use crate::diagnostics::Diagnostic;
+use crate::interpreter::{self, exit_code, Place, MAX_CALL_DEPTH};
+use crate::maps::Map;
+use crate::parsers::SymbolId;
+#[cfg(test)]
+use crate::passes::Pass;
+use crate::rho_core::{self, CallResult, Context, NativeFn};
+use crate::streams::Cursor;
+use crate::tokens::*;
+use crate::value::*;
+
+// A cell's scope holds its one binding under this symbol
+const CELL: SymbolId = 0;
+
+// A call being made, the script's own statements are the outermost one
+struct Frame {
+    closure: Rc<CompiledClosure>,
+    ip: usize,
+    base: usize, // where its slots start, the function called is just below them
+    cells: Vec<Option<Env>>,
+    cursors: usize, // the `for` loops that were running when it was called
+    call: Span,
+}
+
+/* Runs `bytecode` programs on a stack of values, with the same results, output and errors as
+ * the tree walking interpreter. A call pushes a frame rather than recursing, so deep recursion
+ * needs no large native stack, only a native function calling a closure runs the dispatch loop
+ * again.
+ */
+pub struct Vm<'a> {
+    program: &'a Program,
+    globals: Vec<Option<Value>>,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    inlined: Vec<(usize, String, Span)>, // the frame each inlined call runs in, its name and call
+    cursors: Vec<Cursor>,
+    native_call: Span, // the call of the builtin running now, functions it calls report errors here
+    input: &'a mut dyn BufRead,
+    output: &'a mut dyn Write,
+}
+
+// Runs a compiled program, the exit code and errors are the ones `interpreter::run` gives
+pub fn run(program: &Program) -> Result<i32, Diagnostic> {
+    let stdin = std::io::stdin();
+    let mut input = stdin.lock();
+    let mut output = std::io::stdout();
+    run_with(program, &mut input, &mut output)
+}
+
+pub fn run_with(
+    program: &Program,
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+) -> Result<i32, Diagnostic> {
+    let mut vm = Vm {
+        program,
+        globals: vec![None; program.globals.len()],
+        stack: vec![],
+        frames: vec![],
+        inlined: vec![],
+        cursors: vec![],
+        native_call: Span::default(),
+        input,
+        output,
+    };
+    let script = Rc::new(CompiledClosure {
+        function: program.script.clone(),
+        upvalues: vec![],
+    });
+    vm.stack.push(Value::Compiled(script.clone()));
+    let result = vm
+        .enter(script, 0, Span::default())
+        .and_then(|_| vm.execute(0));
+    let _ = vm.output.flush();
+    result.map(|v| exit_code(&v))
+}
+
+impl Context for Vm<'_> {
+    fn input(&mut self) -> &mut dyn BufRead {
+        &mut *self.input
+    }
+
+    fn output(&mut self) -> &mut dyn Write {
+        &mut *self.output
+    }
+
+    fn call(&mut self, function: &Value, args: Vec<Value>) -> Result<Value, Diagnostic> {
+        let span = self.native_call;
+        match function {
+            Value::Compiled(closure) => {
+                let floor = self.frames.len();
+                let height = self.stack.len();
+                let count = args.len();
+                self.stack.push(function.clone());
+                self.stack.extend(args);
+                if let Err(d) = self.enter(closure.clone(), count, span) {
+                    self.stack.truncate(height);
+                    return Err(d);
+                }
+                self.execute(floor)
+            }
+            Value::Builtin(name) => self.call_builtin(name, args, span),
+            v => Err(error(
+                &format!("`{}` is not a function", v.type_name()),
+                span,
+            )),
+        }
+    }
+}
+
+impl Vm<'_> {
+    // Pushes the frame of a call, the function and its arguments are on the stack
+    fn enter(
+        &mut self,
+        closure: Rc<CompiledClosure>,
+        count: usize,
+        span: Span,
+    ) -> Result<(), Diagnostic> {
+        let function = &closure.function;
+        if count != function.arity {
+            return Err(error(
+                &format!(
+                    "`{}` takes {} arguments but {} were given",
+                    function.name, function.arity, count
+                ),
+                span,
+            ));
+        }
+        // the script's frame isn't a call
+        if self.frames.len() + self.inlined.len() > MAX_CALL_DEPTH {
+            return Err(error(
+                &format!("stack overflow, more than {} nested calls", MAX_CALL_DEPTH),
+                span,
+            ));
+        }
+        let base = self.stack.len() - count;
+        self.stack
+            .resize(base + function.locals.len().max(count), Value::Unit);
+        self.frames.push(Frame {
+            cells: vec![None; function.cells.len()],
+            closure,
+            ip: 0,
+            base,
+            cursors: self.cursors.len(),
+            call: span,
+        });
+        Ok(())
+    }
+
+    // Calls the value below the `count` arguments on top, true when that pushed a frame
+    fn call_value(&mut self, count: usize, span: Span) -> Result<bool, Diagnostic> {
+        let callee = self.stack[self.stack.len() - 1 - count].clone();
+        match callee {
+            Value::Compiled(closure) => {
+                self.enter(closure, count, span)?;
+                Ok(true)
+            }
+            Value::Builtin(name) => {
+                let args = self.stack.split_off(self.stack.len() - count);
+                self.stack.pop();
+                let result = self.call_builtin(name, args, span)?;
+                self.stack.push(result);
+                Ok(false)
+            }
+            v => Err(error(
+                &format!("`{}` is not a function", v.type_name()),
+                span,
+            )),
+        }
+    }
+
+    fn call_builtin(
+        &mut self,
+        name: &str,
+        args: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, Diagnostic> {
+        match rho_core::builtin(name) {
+            Some(builtin) => self.call_native(builtin.function, args, span),
+            None => Err(error(&format!("unknown builtin `{}`", name), span)),
+        }
+    }
+
+    fn call_native(
+        &mut self,
+        function: NativeFn,
+        args: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, Diagnostic> {
+        let outer = std::mem::replace(&mut self.native_call, span);
+        let result = function(self, args);
+        self.native_call = outer;
+        match result {
+            CallResult::Ok(v) => Ok(v),
+            CallResult::Err(message) => Err(error(&message, span)),
+            CallResult::Raised(d) => Err(d),
+        }
+    }
+
+    // Runs until the frame above `floor` returns, and comes back with what it returned
+    fn execute(&mut self, floor: usize) -> Result<Value, Diagnostic> {
+        match self.dispatch(floor) {
+            Ok(v) => Ok(v),
+            Err(mut d) => {
+                // the innermost call an error passes through knows the whole stack
+                if d.trace.is_empty() && (self.frames.len() > 1 || !self.inlined.is_empty()) {
+                    let trace = self.stack_trace(d.span);
+                    d = d.with_trace(trace);
+                }
+                let frame = &self.frames[floor];
+                self.stack.truncate(frame.base - 1);
+                self.cursors.truncate(frame.cursors);
+                self.frames.truncate(floor);
+                self.inlined.retain(|(frame, _, _)| *frame < floor);
+                Err(d)
+            }
+        }
+    }
+
+    // The calls made, with the inlined ones after the frame they run in
+    fn stack_trace(&self, at: Span) -> Vec<(String, Span)> {
+        let mut frames = vec![];
+        for (i, f) in self.frames.iter().enumerate() {
+            if i > 0 {
+                frames.push((f.closure.function.name.as_str(), f.call));
+            }
+            let inlined = self.inlined.iter().filter(|(frame, _, _)| *frame == i);
+            frames.extend(inlined.map(|(_, name, call)| (name.as_str(), *call)));
+        }
+        interpreter::stack_trace(frames.into_iter(), at)
+    }
+
+    // Pops the innermost frame, the value is for its caller or, at `floor`, the one it returns
+    fn leave(&mut self, value: Value, floor: usize) -> Option<Value> {
+        let frame = self.frames.pop().unwrap();
+        self.stack.truncate(frame.base - 1);
+        self.cursors.truncate(frame.cursors);
+        let depth = self.frames.len();
+        self.inlined.retain(|(frame, _, _)| *frame < depth);
+        if self.frames.len() == floor {
+            return Some(value);
+        }
+        self.stack.push(value);
+        None
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack
+            .pop()
+            .expect("The compiler should balance the stack")
+    }
+
+    fn cell(&mut self, cell: usize) -> Env {
+        self.frames.last_mut().unwrap().cells[cell]
+            .get_or_insert_with(|| Scope::new(None))
+            .clone()
+    }
+
+    // Takes the value out of a binding, leaving unit until it is put back
+    fn take(&mut self, target: Target, base: usize) -> Option<Value> {
+        match target {
+            Target::Local(slot) => Some(std::mem::replace(
+                &mut self.stack[base + slot as usize],
+                Value::Unit,
+            )),
+            Target::Cell(slot) => take(&self.cell(slot as usize), CELL),
+            Target::Upvalue(slot) => {
+                let closure = self.frames.last().unwrap().closure.clone();
+                take(&closure.upvalues[slot as usize], CELL)
+            }
+            Target::Global(slot) => self.globals[slot as usize].take(),
+        }
+    }
+
+    fn put(&mut self, target: Target, base: usize, value: Value) {
+        match target {
+            Target::Local(slot) => self.stack[base + slot as usize] = value,
+            Target::Cell(slot) => define(&self.cell(slot as usize), CELL, value),
+            Target::Upvalue(slot) => {
+                let closure = self.frames.last().unwrap().closure.clone();
+                define(&closure.upvalues[slot as usize], CELL, value);
+            }
+            Target::Global(slot) => self.globals[slot as usize] = Some(value),
+        }
+    }
+
+    // Binds the names in the pattern when `value` matches it, as `interpreter::match_pattern`
+    fn matches(&mut self, matcher: &Matcher, value: &Value, base: usize) -> bool {
+        match (matcher, value) {
+            (Matcher::Wildcard, _) => true,
+            (Matcher::Bind(Target::Cell(slot)), _) => {
+                let cell = Scope::new(None);
+                define(&cell, CELL, value.clone());
+                self.frames.last_mut().unwrap().cells[*slot as usize] = Some(cell);
+                true
+            }
+            (Matcher::Bind(target), _) => {
+                self.put(*target, base, value.clone());
+                true
+            }
+            (Matcher::Literal(literal), _) => literal == value,
+            (Matcher::Tuple(matchers), Value::Tuple(values))
+            | (Matcher::List(matchers), Value::List(values)) => {
+                matchers.len() == values.len()
+                    && matchers
+                        .iter()
+                        .zip(values.iter())
+                        .all(|(m, v)| self.matches(m, v, base))
+            }
+            (Matcher::Variant(name, variant, matchers), Value::Variant(enum_name, v, values)) => {
+                *name == **enum_name
+                    && variant.as_deref() == Some(&**v)
+                    && matchers.len() == values.len()
+                    && matchers
+                        .iter()
+                        .zip(values.iter())
+                        .all(|(m, v)| self.matches(m, v, base))
+            }
+            _ => false,
+        }
+    }
+
+    fn dispatch(&mut self, floor: usize) -> Result<Value, Diagnostic> {
+        // entered again whenever a call or a return changes the running frame
+        'frames: loop {
+            let frame = self.frames.last().unwrap();
+            let closure = frame.closure.clone();
+            let base = frame.base;
+            let mut ip = frame.ip;
+            let function = &closure.function;
+            let chunk = &function.chunk;
+            let code = &chunk.code[..];
+            loop {
+                let offset = ip;
+                let op = Op::decode(code[ip]);
+                ip += 1;
+                let operand = |at: usize| u16::from_le_bytes([code[at], code[at + 1]]) as usize;
+                match op {
+                    Op::Constant => {
+                        self.stack.push(chunk.constants[operand(ip)].clone());
+                        ip += 2;
+                    }
+                    Op::Unit => self.stack.push(Value::Unit),
+                    Op::True => self.stack.push(Value::Bool(true)),
+                    Op::False => self.stack.push(Value::Bool(false)),
+                    Op::Pop => {
+                        self.pop();
+                    }
+                    Op::GetLocal => {
+                        let v = self.stack[base + operand(ip)].clone();
+                        ip += 2;
+                        self.stack.push(v);
+                    }
+                    Op::SetLocal => {
+                        let v = self.pop();
+                        self.stack[base + operand(ip)] = v;
+                        ip += 2;
+                    }
+                    Op::GetCell | Op::GetUpvalue => {
+                        let slot = operand(ip);
+                        ip += 2;
+                        let cell = match op {
+                            Op::GetCell => self.cell(slot),
+                            _ => closure.upvalues[slot].clone(),
+                        };
+                        let v = lookup(&cell, CELL).unwrap_or(Value::Unit);
+                        self.stack.push(v);
+                    }
+                    Op::SetCell | Op::SetUpvalue => {
+                        let slot = operand(ip);
+                        ip += 2;
+                        let cell = match op {
+                            Op::SetCell => self.cell(slot),
+                            _ => closure.upvalues[slot].clone(),
+                        };
+                        let v = self.pop();
+                        define(&cell, CELL, v);
+                    }
+                    Op::FreshCell => {
+                        self.frames.last_mut().unwrap().cells[operand(ip)] = Some(Scope::new(None));
+                        ip += 2;
+                    }
+                    Op::GetGlobal => {
+                        let slot = operand(ip);
+                        ip += 2;
+                        match &self.globals[slot] {
+                            Some(v) => self.stack.push(v.clone()),
+                            None => {
+                                return Err(error(
+                                    &format!(
+                                        "`{}` is used before it has a value",
+                                        self.program.globals[slot]
+                                    ),
+                                    chunk.span(offset),
+                                ))
+                            }
+                        }
+                    }
+                    Op::SetGlobal => {
+                        self.globals[operand(ip)] = Some(self.pop());
+                        ip += 2;
+                    }
+                    Op::Add
+                    | Op::Subtract
+                    | Op::Multiply
+                    | Op::Divide
+                    | Op::Remainder
+                    | Op::Power
+                    | Op::ShiftLeft
+                    | Op::ShiftRight
+                    | Op::Concat
+                    | Op::Append
+                    | Op::Equal
+                    | Op::NotEqual
+                    | Op::Less
+                    | Op::Greater
+                    | Op::LessEqual
+                    | Op::GreaterEqual => {
+                        let r = self.pop();
+                        let l = self.pop();
+                        let v = match ints(op, &l, &r) {
+                            Some(v) => v,
+                            None => interpreter::binary(
+                                op.operator().unwrap(),
+                                l,
+                                r,
+                                chunk.span(offset),
+                            )?,
+                        };
+                        self.stack.push(v);
+                    }
+                    Op::Negate | Op::Not => {
+                        let v = match (op, self.pop()) {
+                            (Op::Not, Value::Bool(b)) => Value::Bool(!b),
+                            (Op::Negate, Value::Int(i)) => match i.checked_neg() {
+                                Some(i) => Value::Int(i),
+                                None => return Err(error("integer overflow", chunk.span(offset))),
+                            },
+                            (Op::Negate, Value::Float(f)) => Value::Float(-f),
+                            (op, v) => {
+                                let operator = if op == Op::Not { "!" } else { "-" };
+                                return Err(error(
+                                    &format!(
+                                        "`{}` can't be applied to `{}`",
+                                        operator,
+                                        v.type_name()
+                                    ),
+                                    chunk.span(offset),
+                                ));
+                            }
+                        };
+                        self.stack.push(v);
+                    }
+                    Op::Jump => ip = operand(ip),
+                    Op::JumpIfFalse | Op::JumpIfTrue => {
+                        let jump = self.pop().is_truthy() == (op == Op::JumpIfTrue);
+                        ip = if jump { operand(ip) } else { ip + 2 };
+                    }
+                    Op::Call => {
+                        let count = code[ip] as usize;
+                        ip += 1;
+                        self.frames.last_mut().unwrap().ip = ip;
+                        if self.call_value(count, chunk.span(offset))? {
+                            continue 'frames;
+                        }
+                    }
+                    Op::CallNative => {
+                        let (native, count) = (operand(ip), code[ip + 2] as usize);
+                        ip += 3;
+                        let args = self.stack.split_off(self.stack.len() - count);
+                        let function = chunk.natives[native].1;
+                        let v = self.call_native(function, args, chunk.span(offset))?;
+                        self.stack.push(v);
+                    }
+                    // `T.show(x)` calls the `show` of the type `x` has
+                    Op::CallMethod => {
+                        let (method, count) = (operand(ip), code[ip + 2] as usize);
+                        ip += 3;
+                        self.frames.last_mut().unwrap().ip = ip;
+                        let first = self.stack.len() - count;
+                        let owner = match count {
+                            0 => String::new(),
+                            _ => self.stack[first].type_name(),
+                        };
+                        let name = format!("{}.{}", owner, chunk.constants[method]);
+                        let span = chunk.span(offset);
+                        let Some(f) = self.program.functions.get(&name) else {
+                            return Err(error(&format!("undefined function `{}`", name), span));
+                        };
+                        let f = self.globals[*f as usize].clone().unwrap_or(Value::Unit);
+                        self.stack.insert(first, f);
+                        if self.call_value(count, span)? {
+                            continue 'frames;
+                        }
+                    }
+                    Op::Return => {
+                        let v = self.pop();
+                        match self.leave(v, floor) {
+                            Some(v) => return Ok(v),
+                            None => continue 'frames,
+                        }
+                    }
+                    Op::Closure => {
+                        let function = chunk.functions[operand(ip)].clone();
+                        ip += 2;
+                        let upvalues = function
+                            .captures
+                            .iter()
+                            .map(|capture| match capture {
+                                Capture::Cell(cell) => self.cell(*cell as usize),
+                                Capture::Upvalue(upvalue) => {
+                                    closure.upvalues[*upvalue as usize].clone()
+                                }
+                            })
+                            .collect();
+                        let closure = CompiledClosure { function, upvalues };
+                        self.stack.push(Value::Compiled(Rc::new(closure)));
+                    }
+                    Op::List | Op::Tuple => {
+                        let elements = self.stack.split_off(self.stack.len() - operand(ip));
+                        ip += 2;
+                        self.stack.push(match op {
+                            Op::List => Value::list(elements),
+                            _ => Value::tuple(elements),
+                        });
+                    }
+                    Op::Map => {
+                        let entries = self.stack.split_off(self.stack.len() - 2 * operand(ip));
+                        ip += 2;
+                        let mut map = Map::new();
+                        let mut entries = entries.into_iter();
+                        while let (Some(k), Some(v)) = (entries.next(), entries.next()) {
+                            if let Err(message) = map.insert(k, v) {
+                                return Err(error(&message, chunk.span(offset)));
+                            }
+                        }
+                        self.stack.push(Value::Map(Rc::new(map)));
+                    }
+                    Op::Struct => {
+                        let Value::Tuple(names) = &chunk.constants[operand(ip)] else {
+                            unreachable!("A struct's constant should be a tuple of names");
+                        };
+                        ip += 2;
+                        let values = self.stack.split_off(self.stack.len() - (names.len() - 1));
+                        let fields = names[1..]
+                            .iter()
+                            .map(|n| n.to_string())
+                            .zip(values)
+                            .collect();
+                        let name = names[0].to_string();
+                        self.stack
+                            .push(Value::Struct(Rc::from(name.as_str()), Rc::new(fields)));
+                    }
+                    Op::Variant => {
+                        let Value::Tuple(names) = &chunk.constants[operand(ip)] else {
+                            unreachable!("A variant's constant should be a tuple of names");
+                        };
+                        let count = code[ip + 2] as usize;
+                        ip += 3;
+                        let values = self.stack.split_off(self.stack.len() - count);
+                        self.stack.push(Value::Variant(
+                            Rc::from(names[0].to_string().as_str()),
+                            Rc::from(names[1].to_string().as_str()),
+                            Rc::new(values),
+                        ));
+                    }
+                    Op::Range | Op::SteppedRange => {
+                        let span = chunk.span(offset);
+                        let step = match op {
+                            Op::SteppedRange => int(self.pop(), span)?,
+                            _ => 1,
+                        };
+                        let end = int(self.pop(), span)?;
+                        let start = int(self.pop(), span)?;
+                        if step == 0 {
+                            return Err(error("a range can't have a step of 0", span));
+                        }
+                        self.stack.push(Value::Range(start, end, step));
+                    }
+                    Op::GetField => {
+                        let Value::String(name) = &chunk.constants[operand(ip)] else {
+                            unreachable!("A field's constant should be its name");
+                        };
+                        ip += 2;
+                        let v = self.pop();
+                        let v = interpreter::get_field(&v, name, chunk.span(offset))?;
+                        self.stack.push(v);
+                    }
+                    Op::GetIndex => {
+                        let i = self.pop();
+                        let v = self.pop();
+                        let v = interpreter::get_index(&v, &i, chunk.span(offset))?;
+                        self.stack.push(v);
+                    }
+                    Op::SetPath => {
+                        let Value::List(steps) = &chunk.constants[operand(ip)] else {
+                            unreachable!("A path's constant should be a list of steps");
+                        };
+                        let target = Target::from_kind(code[ip + 2], operand(ip + 3) as u16);
+                        ip += 5;
+                        let span = chunk.span(offset);
+                        let count = steps.iter().filter(|s| **s == Value::Unit).count();
+                        // in the order the path goes from the binding in
+                        let mut indexes =
+                            self.stack.split_off(self.stack.len() - count).into_iter();
+                        let value = self.pop();
+                        let path: Vec<Place> = steps
+                            .iter()
+                            .map(|step| match step {
+                                Value::String(field) => Place::Field(field.to_string(), span),
+                                _ => Place::Index(indexes.next().unwrap(), span),
+                            })
+                            .collect();
+                        let Some(mut current) = self.take(target, base) else {
+                            let names = match target {
+                                Target::Global(_) => &self.program.globals,
+                                Target::Upvalue(_) => &function.upvalues,
+                                _ => &function.cells,
+                            };
+                            let name = names[target.kind().1 as usize].to_owned();
+                            return Err(error(
+                                &format!("`{}` is used before it has a value", name),
+                                span,
+                            ));
+                        };
+                        let result = interpreter::set_in(&mut current, &path, value);
+                        self.put(target, base, current);
+                        result?;
+                    }
+                    Op::Iterate => {
+                        let iterable = self.pop();
+                        // a stream is pulled one element per pass through the body
+                        let Some(cursor) = Cursor::of(&iterable) else {
+                            return Err(error(
+                                &format!("can't iterate over `{}`", iterable.type_name()),
+                                chunk.span(offset),
+                            ));
+                        };
+                        self.cursors.push(cursor);
+                    }
+                    Op::Next => {
+                        let exit = operand(ip);
+                        ip += 2;
+                        self.frames.last_mut().unwrap().ip = ip;
+                        let mut cursor = self.cursors.pop().unwrap();
+                        let span = chunk.span(offset);
+                        let outer = std::mem::replace(&mut self.native_call, span);
+                        let pulled = cursor.next(self);
+                        self.native_call = outer;
+                        match pulled {
+                            Ok(Some(element)) => {
+                                self.cursors.push(cursor);
+                                self.stack.push(element);
+                            }
+                            Ok(None) | Err(CallResult::Ok(_)) => ip = exit,
+                            Err(CallResult::Err(message)) => return Err(error(&message, span)),
+                            Err(CallResult::Raised(d)) => return Err(d),
+                        }
+                    }
+                    Op::EndIterate => {
+                        self.cursors.pop();
+                    }
+                    Op::Match => {
+                        let subject = self.pop();
+                        let matched = self.matches(&chunk.patterns[operand(ip)], &subject, base);
+                        ip += 2;
+                        self.stack.push(Value::Bool(matched));
+                    }
+                    Op::Show => {
+                        let v = self.pop();
+                        self.stack.push(Value::string(&rho_core::inspect(&v)));
+                    }
+                    Op::Enter => {
+                        let Value::String(name) = &chunk.constants[operand(ip)] else {
+                            unreachable!("A frame's constant should be its function's name");
+                        };
+                        ip += 2;
+                        let span = chunk.span(offset);
+                        if self.frames.len() + self.inlined.len() > MAX_CALL_DEPTH {
+                            return Err(error(
+                                &format!(
+                                    "stack overflow, more than {} nested calls",
+                                    MAX_CALL_DEPTH
+                                ),
+                                span,
+                            ));
+                        }
+                        self.inlined
+                            .push((self.frames.len() - 1, name.to_string(), span));
+                    }
+                    Op::Leave => {
+                        self.inlined.pop();
+                    }
+                    Op::Fail => {
+                        let prefix = &chunk.constants[operand(ip)];
+                        let traced = code[ip + 2] != 0;
+                        let message = self.pop();
+                        let span = chunk.span(offset);
+                        let d = error(&format!("{}{}", prefix, message), span);
+                        return Err(match traced {
+                            true => d.with_trace(self.stack_trace(span)),
+                            false => d,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+// The arithmetic and comparisons of two ints, the common case, without going through `binary`
+fn ints(op: Op, l: &Value, r: &Value) -> Option<Value> {
+    let (Value::Int(a), Value::Int(b)) = (l, r) else {
+        return None;
+    };
+    Some(match op {
+        Op::Add => Value::Int(a.checked_add(*b)?),
+        Op::Subtract => Value::Int(a.checked_sub(*b)?),
+        Op::Multiply => Value::Int(a.checked_mul(*b)?),
+        Op::Equal => Value::Bool(a == b),
+        Op::NotEqual => Value::Bool(a != b),
+        Op::Less => Value::Bool(a < b),
+        Op::Greater => Value::Bool(a > b),
+        Op::LessEqual => Value::Bool(a <= b),
+        Op::GreaterEqual => Value::Bool(a >= b),
+        _ => return None,
+    })
+}
+
+fn int(v: Value, span: Span) -> Result<i64, Diagnostic> {
+    match v {
+        Value::Int(i) => Ok(i),
+        v => Err(error(
+            &format!("expected an int, found `{}`", v.type_name()),
+            span,
+        )),
+    }
+}
+
+fn error(message: &str, span: Span) -> Diagnostic {
+    Diagnostic::error(message, span)
+}
+
+// With every pass, as `rho run --vm` runs it
+#[cfg(test)]
+pub fn run_source(source: &str, input: &str) -> (Result<i32, Diagnostic>, String) {
+    run_after(source, input, &Pass::ALL)
+}
+
+#[cfg(test)]
+fn run_after(source: &str, input: &str, passes: &[Pass]) -> (Result<i32, Diagnostic>, String) {
+    let program = crate::bytecode::compile_source(source, passes);
+    let mut input = input.as_bytes();
+    let mut output = vec![];
+    let result = run_with(&program, &mut input, &mut output);
+    (result, String::from_utf8(output).unwrap())
+}
+
+// The VM has to give what the interpreter gives, errors and their traces included, optimised or not
+#[cfg(test)]
+fn assert_same(source: &str, input: &str) {
+    let expected = interpret(source, input);
+    for passes in [&Pass::ALL[..], &[]] {
+        let result = run_after(source, input, passes);
+        assert_eq!(result, expected, "{} with {:?}", source, passes);
+    }
+}
+
+// On a stack as large as the one `main` gives the interpreter
+#[cfg(test)]
+fn interpret(source: &str, input: &str) -> (Result<i32, Diagnostic>, String) {
+    let (source, input) = (source.to_string(), input.to_string());
+    std::thread::Builder::new()
+        .stack_size(interpreter::STACK_SIZE)
+        .spawn(move || interpreter::run_source(&source, &input))
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+#[test]
+fn test_vm_matches_interpreter() {
+    let mut files = vec![];
+    for dir in ["./rho_testfiles", "./llvm_testfiles/golden"] {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|e| e == "rho") {
+                files.push(path);
+            }
+        }
+    }
+    for path in files.iter() {
+        let source = std::fs::read_to_string(path).unwrap();
+        // hello.rho and scratch.rho predate the checker, see `resolver::test_resolve_testfiles`
+        if crate::compile(&source).0.is_none() {
+            continue;
+        }
+        assert_same(&source, "");
+    }
+
+    assert_same(
+        "interface Show {
+    func show(Self x) -> str
+}
+enum Shape {
+    Circle(float),
+    Square(float)
+}
+struct Point {
+    int x
+    int y
+}
+func Point.show(Point p) -> str {
+    return \"(\" <> str(p.x) <> \", \" <> str(p.y) <> \")\"
+}
+func describe[T: Show](T value) -> str {
+    return T.show(value)
+}
+func is_even(int n) -> bool {
+    func even(int n) -> bool {
+        return n == 0 || odd(n - 1)
+    }
+    func odd(int n) -> bool {
+        return n != 0 && even(n - 1)
+    }
+    return even(n)
+}
+func adders(int base) -> list[fn(int) -> int] {
+    var fs = []
+    for i in 0..3 {
+        fs = fs ++ [fn x -> x + i + base]
+    }
+    return fs
+}
+var total = 0
+for i in 0..10 {
+    if i == 7 {
+        break
+    } elif i % 2 == 0 {
+        continue
+    }
+    total = total + i
+}
+IO.puts(total)
+var Point p = Point{y: 2, x: 1}
+p.x = 5
+var grid = [[0, 0], [0, 0]]
+grid[1][0] = p.x
+IO.puts(grid)
+IO.puts(describe(p))
+var ages = {\"ann\": 30}
+ages[\"bob\"] = 41
+IO.puts(ages)
+shapes = [Shape.Circle(1.0), Shape.Square(2.0), Shape.Square(0.5)]
+for s in shapes {
+    area = match s {
+        Shape.Circle(r) -> 3.0 * r * r
+        Shape.Square(side) when side > 1.0 -> side * side
+        _ -> 0.0
+    }
+    IO.puts(area)
+}
+IO.puts(is_even(10))
+IO.puts(List.map(adders(100), fn f -> f(1)))
+sign = cond {
+    total < 0 -> :negative
+    total == 0 -> :zero
+    true -> :positive
+}
+IO.puts(sign)
+var n = 0
+while true {
+    n = n + 1
+    for m in [1, 2] {
+        if n > 3 {
+            break
+        }
+    }
+    if n > 3 {
+        break
+    }
+}
+IO.puts(n)
+for x in Enum.filter(1..10, fn x -> x % 3 == 0) {
+    IO.print(x)
+}
+IO.puts(\"\")
+assert 1 + 1 == 2
+name = IO.read_line()
+IO.puts(\"hi \" <> name)
+return int(4.5)
+",
+        "rho\n",
+    );
+    assert_same(
+        "func add(str a, str b) -> result[int, str] {
+    x = String.to_int(a)?
+    y = String.to_int(b)?
+    return {:ok, x + y}
+}
+func describe(str a, str b) -> str {
+    match add(a, b) {
+        {:ok, n} -> return \"sum \" <> str(n)
+        {:error, reason} -> return reason
+    }
+}
+IO.puts(describe(\"1\", \"2\"))
+IO.puts(describe(\"1\", \"x\"))
+n = add(\"4\", \"y\")?
+IO.puts(\"not reached\")
+",
+        "",
+    );
+}
+
+#[test]
+fn test_vm_errors() {
+    let sources = [
+        "xs = [1, 2]\nx = xs[2]\n",
+        "x = 1 / (1 - 1)\n",
+        "func inner(int n) -> int {
+    if n > 1 {
+        panic \"too deep: \" <> str(n)
+    }
+    return inner(n + 1)
+}
+assert 1 + 1 == 2
+inner(0)
+",
+        "xs = [1, 2]\nassert xs == [2, 1]\n",
+        "s = \"a\"\nassert s != \"a\", \"not a\"\n",
+        "assert List.any([1], fn x -> x > 1)\n",
+        "func check(int x) -> int {\n    assert x < 0, \"negative\"\n    return x\n}\nIO.puts(List.map([-1, 2], fn x -> check(x)))\n",
+        "func f(int n) -> int {\n    return n * f(n + 1)\n}\nfunc main() -> int {\n    return f(1)\n}\n",
+    ];
+    for source in sources {
+        assert_same(source, "");
+    }
+
+    // as deep as the interpreter goes, which the VM does without a large stack of its own
+    let deep = "func f(int n) -> int {\n    return f(n + 1)\n}\nf(0)\n";
+    let d = run_source(deep, "").0.unwrap_err();
+    assert!(d.message.starts_with("stack overflow"));
+    assert_eq!(d.trace.last().unwrap().0, "9981 more calls");
+    assert_eq!(Err(d), interpret(deep, "").0);
+}
+
+// Closures capturing cells make the same cycles closures make through scopes
+#[test]
+fn test_vm_leaks() {
+    let before = crate::gc::live_scopes();
+    let (result, output) = run_source(
+        "func counter() -> fn() -> int {
+    var int count = 0
+    func next() -> int {
+        count = count + 1
+        return count
+    }
+    return next
+}
+var total = 0
+for i in range 0..5000 {
+    next = counter()
+    next()
+    total = total + next()
+}
+IO.puts(total)
+",
+        "",
+    );
+    assert_eq!(result, Ok(0));
+    assert_eq!(output, "10000\n");
+    crate::gc::collect();
+    assert_eq!(crate::gc::live_scopes(), before);
+}
+
+/* The benchmarks in `benchmarks/`, on the interpreter and on the VM, run with
+ *
+ *     cargo test --release benchmarks -- --ignored --nocapture
+ */
+#[test]
+#[ignore]
+fn test_benchmarks() {
+    use std::time::Instant;
+
+    let mut paths: Vec<_> = std::fs::read_dir("./benchmarks")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "rho"))
+        .collect();
+    paths.sort();
+    println!(
+        "{:<16}{:>14}{:>14}{:>10}",
+        "benchmark", "interpreter", "vm", "speedup"
+    );
+    for path in paths.iter() {
+        let source = std::fs::read_to_string(path).unwrap();
+        let start = Instant::now();
+        let expected = interpret(&source, "");
+        let interpreted = start.elapsed();
+        let start = Instant::now();
+        let result = run_source(&source, "");
+        let compiled = start.elapsed();
+        assert_eq!(result, expected, "{}", path.display());
+        println!(
+            "{:<16}{:>12.1}ms{:>12.1}ms{:>9.1}x",
+            path.file_stem().unwrap().to_string_lossy(),
+            interpreted.as_secs_f64() * 1000.0,
+            compiled.as_secs_f64() * 1000.0,
+            interpreted.as_secs_f64() / compiled.as_secs_f64()
+        );
+    }
+}